@@ -62,6 +62,44 @@ impl InnerBuilder {
         }
     }
 
+    fn descriptors(self, enable: bool) -> Self {
+        match self {
+            InnerBuilder::Thrift(inner) => InnerBuilder::Thrift(inner.descriptors(enable)),
+            // the descriptors of protobuf are in its own reflection
+            inner => inner,
+        }
+    }
+
+    fn file_descriptor_set(self, enable: bool) -> Self {
+        match self {
+            InnerBuilder::Protobuf(inner) => {
+                InnerBuilder::Protobuf(inner.file_descriptor_set(enable))
+            }
+            inner => inner,
+        }
+    }
+
+    fn service_features(self, enable: bool) -> Self {
+        match self {
+            InnerBuilder::Protobuf(inner) => InnerBuilder::Protobuf(inner.service_features(enable)),
+            InnerBuilder::Thrift(inner) => InnerBuilder::Thrift(inner.service_features(enable)),
+        }
+    }
+
+    fn gen_mock(self, enable: bool) -> Self {
+        match self {
+            InnerBuilder::Protobuf(inner) => InnerBuilder::Protobuf(inner.gen_mock(enable)),
+            InnerBuilder::Thrift(inner) => InnerBuilder::Thrift(inner.gen_mock(enable)),
+        }
+    }
+
+    fn serde(self, enable: bool) -> Self {
+        match self {
+            InnerBuilder::Protobuf(inner) => InnerBuilder::Protobuf(inner.serde(enable)),
+            InnerBuilder::Thrift(inner) => InnerBuilder::Thrift(inner.serde(enable)),
+        }
+    }
+
     pub fn add_service<P>(self, path: P) -> Self
     where
         P: AsRef<Path>,
@@ -96,7 +134,12 @@ impl ConfigBuilder {
                 crate::model::IdlProtocol::Thrift => InnerBuilder::thrift(),
                 crate::model::IdlProtocol::Protobuf => InnerBuilder::protobuf(),
             }
-            .filename(entry.filename);
+            .filename(entry.filename)
+            .descriptors(entry.descriptors)
+            .service_features(entry.service_features)
+            .gen_mock(entry.gen_mock)
+            .serde(entry.serde)
+            .file_descriptor_set(entry.file_descriptor_set);
 
             for p in self.plugins.iter() {
                 builder = builder.plugin(p.clone());
@@ -0,0 +1,102 @@
+//! The encoded `FileDescriptorSet` of the protos, such as for the reflection of the servers.
+//!
+//! The set is written next to the generated file, such as `volo_gen.descriptor.bin` of
+//! `volo_gen.rs`, with the files imported by the protos too, and included in the generated module
+//! as `FILE_DESCRIPTOR_SET`:
+//!
+//! ```rust,ignore
+//! ItemServiceServer::new(S)
+//!     .enable_reflection(volo_gen::FILE_DESCRIPTOR_SET)
+//!     .run(addr)
+//!     .await;
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use protobuf::Message as _;
+
+/// Parses the protos and writes the encoded `FileDescriptorSet` of them to `path`.
+pub(crate) fn write(idls: &[PathBuf], include_dirs: &[PathBuf], path: &Path) -> anyhow::Result<()> {
+    // the protos are named relative to the include dirs, and to their own dirs otherwise
+    let mut includes = include_dirs.to_vec();
+    for idl in idls {
+        if let Some(dir) = idl.parent() {
+            if !includes.iter().any(|i| i == dir) {
+                includes.push(dir.to_path_buf());
+            }
+        }
+    }
+    let parsed = protobuf_parse::Parser::new()
+        .pure()
+        .includes(&includes)
+        .inputs(idls)
+        .parse_and_typecheck()
+        .context("failed to parse the protos for the FileDescriptorSet")?;
+
+    let set = protobuf::descriptor::FileDescriptorSet {
+        file: parsed.file_descriptors,
+        ..Default::default()
+    };
+    let bytes = set.write_to_bytes()?;
+    std::fs::write(path, bytes).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// Includes the `FileDescriptorSet` at `path` in the module of the generated file.
+pub(crate) fn include(generated: &Path, path: &Path) -> anyhow::Result<()> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("failed to find {}", path.display()))?;
+    let item = format!(
+        "\npub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!({:?});\n",
+        path.display().to_string()
+    );
+
+    let mut source = std::fs::read_to_string(generated)
+        .with_context(|| format!("failed to read {}", generated.display()))?;
+    // the generated items are in the module of the file stem, such as `volo_gen`, which is
+    // re-exported by the IDL crates
+    let module = generated
+        .file_stem()
+        .map(|stem| format!("pub mod {}", stem.to_string_lossy()));
+    match module
+        .and_then(|module| source.find(&module).map(|at| at + module.len()))
+        .and_then(|at| source[at..].find('{').map(|brace| at + brace + 1))
+    {
+        Some(at) => source.insert_str(at, &item),
+        None => source.push_str(&item),
+    }
+    std::fs::write(generated, source)
+        .with_context(|| format!("failed to write {}", generated.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_include() {
+        let dir = tempfile::tempdir().unwrap();
+        let idl = dir.path().join("item.proto");
+        std::fs::write(
+            &idl,
+            "syntax = \"proto3\";\npackage volo.example;\nmessage Item { int64 id = 1; }\n",
+        )
+        .unwrap();
+        let path = dir.path().join("volo_gen.descriptor.bin");
+        write(&[idl], &[], &path).unwrap();
+        let set = protobuf::descriptor::FileDescriptorSet::parse_from_bytes(
+            &std::fs::read(&path).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(set.file[0].name(), "item.proto");
+
+        let generated = dir.path().join("volo_gen.rs");
+        std::fs::write(&generated, "pub mod volo_gen {\n}\n").unwrap();
+        include(&generated, &path).unwrap();
+        let source = std::fs::read_to_string(&generated).unwrap();
+        assert!(source.starts_with("pub mod volo_gen {\npub const FILE_DESCRIPTOR_SET"));
+    }
+}
@@ -0,0 +1,632 @@
+//! The diagnostics of the IDLs, checked before the codegen.
+//!
+//! The parsers panic from deep inside on the IDLs they fail to resolve, with no clue of which IDL
+//! is at fault, so the includes and the definitions are checked first. The missing includes, the
+//! circular includes and the duplicate definitions are reported with the places of them in the
+//! IDLs, the chains of the includes to them, and the suggestions if any:
+//!
+//! ```text
+//! error: cannot find the include "commn.thrift"
+//!  --> idl/item.thrift:3:9
+//!   |
+//! 3 | include "commn.thrift"
+//!   |         ^^^^^^^^^^^^^^
+//!   = included by: idl/main.thrift -> idl/item.thrift
+//!   = searched in: idl, include
+//!   = help: did you mean "common.thrift"?
+//! ```
+//!
+//! Only the includes and the names of the definitions are checked, the rest of the IDLs are left
+//! to the parsers.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Syntax {
+    Thrift,
+    Protobuf,
+}
+
+impl Syntax {
+    fn of(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("proto") => Syntax::Protobuf,
+            _ => Syntax::Thrift,
+        }
+    }
+}
+
+/// A place in an IDL, with the line of it to be shown in the diagnostics.
+#[derive(Debug, Clone)]
+struct Span {
+    file: PathBuf,
+    line: usize,
+    col: usize,
+    len: usize,
+    text: String,
+}
+
+impl Span {
+    fn location(&self) -> String {
+        format!("{}:{}:{}", self.file.display(), self.line, self.col)
+    }
+}
+
+/// Renders a diagnostic of the span, followed by the notes.
+fn render(title: &str, span: &Span, notes: &[String]) -> String {
+    let line = span.line.to_string();
+    let pad = " ".repeat(line.len());
+    let mut out = format!("error: {}\n{}--> {}\n", title, pad, span.location());
+    let _ = writeln!(out, "{} |", pad);
+    let _ = writeln!(out, "{} | {}", line, span.text.trim_end());
+    let _ = writeln!(
+        out,
+        "{} | {}{}",
+        pad,
+        " ".repeat(span.col - 1),
+        "^".repeat(span.len.max(1))
+    );
+    for note in notes {
+        let _ = writeln!(out, "{} = {}", pad, note);
+    }
+    out
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Punct(char),
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    col: usize,
+}
+
+impl Lexer<'_> {
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+}
+
+/// Tokenizes the IDL, with the line and the column of each token.
+fn tokenize(source: &str, syntax: Syntax) -> Vec<(Token, usize, usize)> {
+    let mut lexer = Lexer {
+        chars: source.chars().peekable(),
+        line: 1,
+        col: 1,
+    };
+    let mut tokens = Vec::new();
+    loop {
+        let (line, col) = (lexer.line, lexer.col);
+        let c = match lexer.bump() {
+            Some(c) => c,
+            None => return tokens,
+        };
+        match c {
+            c if c.is_whitespace() => {}
+            // the line comments, `#` is of thrift only
+            c if (c == '#' && syntax == Syntax::Thrift)
+                || (c == '/' && lexer.chars.peek() == Some(&'/')) =>
+            {
+                while !matches!(lexer.bump(), None | Some('\n')) {}
+            }
+            '/' if lexer.chars.peek() == Some(&'*') => {
+                lexer.bump();
+                let mut prev = ' ';
+                while let Some(c) = lexer.bump() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            '"' | '\'' => {
+                let mut value = String::new();
+                while let Some(next) = lexer.bump() {
+                    match next {
+                        '\\' => value.extend(lexer.bump()),
+                        next if next == c => break,
+                        next => value.push(next),
+                    }
+                }
+                tokens.push((Token::Str(value), line, col));
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '.' => {
+                let mut ident = c.to_string();
+                while let Some(&next) = lexer.chars.peek() {
+                    if !(next.is_alphanumeric() || next == '_' || next == '.') {
+                        break;
+                    }
+                    ident.push(next);
+                    lexer.bump();
+                }
+                tokens.push((Token::Ident(ident), line, col));
+            }
+            c => tokens.push((Token::Punct(c), line, col)),
+        }
+    }
+}
+
+/// An include of an IDL, or a definition in it.
+#[derive(Debug)]
+struct Named {
+    name: String,
+    span: Span,
+}
+
+#[derive(Debug, Default)]
+struct Idl {
+    includes: Vec<Named>,
+    /// By the full names for protobuf, whose names are global in the packages.
+    definitions: Vec<Named>,
+}
+
+struct Parser<'a> {
+    file: &'a Path,
+    lines: Vec<&'a str>,
+    tokens: Vec<(Token, usize, usize)>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _, _)| t)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.pos += 1;
+        self.tokens.get(self.pos - 1).map(|(t, _, _)| t)
+    }
+
+    fn span(&self, pos: usize, len: usize) -> Span {
+        let (_, line, col) = self.tokens[pos];
+        Span {
+            file: self.file.to_path_buf(),
+            line,
+            col,
+            len,
+            text: self.lines.get(line - 1).copied().unwrap_or_default().into(),
+        }
+    }
+
+    /// Returns the ident as a name at the current token if any, and moves past it.
+    fn name(&mut self) -> Option<(String, Span)> {
+        match self.peek()? {
+            Token::Ident(name) => {
+                let name = name.clone();
+                let span = self.span(self.pos, name.chars().count());
+                self.pos += 1;
+                Some((name, span))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the string at the current token if any, and moves past it.
+    fn string(&mut self) -> Option<(String, Span)> {
+        match self.peek()? {
+            Token::Str(value) => {
+                let value = value.clone();
+                // with the quotes
+                let span = self.span(self.pos, value.chars().count() + 2);
+                self.pos += 1;
+                Some((value, span))
+            }
+            _ => None,
+        }
+    }
+
+    /// Skips the group opened by the current token if it's `open`.
+    fn skip_group(&mut self, open: char, close: char) {
+        if self.peek() != Some(&Token::Punct(open)) {
+            return;
+        }
+        let mut depth = 0;
+        while let Some(token) = self.next() {
+            match token {
+                Token::Punct(c) if *c == open => depth += 1,
+                Token::Punct(c) if *c == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Skips a thrift type, such as `map<string, list<i32>>` and its annotations.
+    fn skip_type(&mut self) {
+        self.pos += 1;
+        self.skip_group('<', '>');
+        self.skip_group('(', ')');
+    }
+
+    fn thrift(mut self) -> Idl {
+        let mut idl = Idl::default();
+        let mut depth = 0;
+        while let Some(token) = self.next() {
+            let keyword = match token {
+                Token::Punct('{') => {
+                    depth += 1;
+                    continue;
+                }
+                Token::Punct('}') => {
+                    depth -= 1;
+                    continue;
+                }
+                Token::Ident(keyword) if depth == 0 => keyword.clone(),
+                _ => continue,
+            };
+            match keyword.as_str() {
+                "include" => {
+                    if let Some((name, span)) = self.string() {
+                        idl.includes.push(Named { name, span });
+                    }
+                }
+                "struct" | "union" | "exception" | "enum" | "senum" | "service" => {
+                    if let Some((name, span)) = self.name() {
+                        idl.definitions.push(Named { name, span });
+                    }
+                }
+                "typedef" | "const" => {
+                    self.skip_type();
+                    if let Some((name, span)) = self.name() {
+                        idl.definitions.push(Named { name, span });
+                    }
+                }
+                _ => {}
+            }
+        }
+        idl
+    }
+
+    fn protobuf(mut self) -> Idl {
+        let mut idl = Idl::default();
+        let mut package = String::new();
+        // the names of the enclosing blocks, `None` for the ones of no scope such as options
+        let mut scopes: Vec<Option<String>> = Vec::new();
+        let mut pending = None;
+        while let Some(token) = self.next() {
+            let keyword = match token {
+                Token::Punct('{') => {
+                    scopes.push(pending.take());
+                    continue;
+                }
+                Token::Punct('}') => {
+                    scopes.pop();
+                    continue;
+                }
+                Token::Punct(';') => {
+                    pending = None;
+                    continue;
+                }
+                Token::Ident(keyword) => keyword.clone(),
+                _ => continue,
+            };
+            match keyword.as_str() {
+                "import" if scopes.is_empty() => {
+                    if matches!(self.peek(), Some(Token::Ident(m)) if m == "public" || m == "weak")
+                    {
+                        self.pos += 1;
+                    }
+                    if let Some((name, span)) = self.string() {
+                        idl.includes.push(Named { name, span });
+                    }
+                }
+                "package" if scopes.is_empty() => {
+                    if let Some((name, _)) = self.name() {
+                        package = name;
+                    }
+                }
+                "message" | "enum" | "service" => {
+                    if let Some((name, span)) = self.name() {
+                        let full_name = std::iter::once(&package)
+                            .chain(scopes.iter().flatten())
+                            .chain([&name])
+                            .filter(|s| !s.is_empty())
+                            .map(|s| s.as_str())
+                            .collect::<Vec<_>>()
+                            .join(".");
+                        idl.definitions.push(Named {
+                            name: full_name,
+                            span,
+                        });
+                        // only the messages are the scopes of the nested definitions
+                        if keyword == "message" {
+                            pending = Some(name);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        idl
+    }
+}
+
+fn parse(file: &Path, source: &str) -> Idl {
+    let syntax = Syntax::of(file);
+    let parser = Parser {
+        file,
+        lines: source.lines().collect(),
+        tokens: tokenize(source, syntax),
+        pos: 0,
+    };
+    match syntax {
+        Syntax::Thrift => parser.thrift(),
+        Syntax::Protobuf => parser.protobuf(),
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+struct Checker<'a> {
+    include_dirs: &'a [PathBuf],
+    root_dirs: Vec<PathBuf>,
+    /// The IDLs being visited, each included by the previous one.
+    stack: Vec<PathBuf>,
+    done: HashSet<PathBuf>,
+    /// The protobuf definitions, by the full names.
+    defined: HashMap<String, Span>,
+    errors: Vec<String>,
+}
+
+impl Checker<'_> {
+    /// Returns the dirs to search the include of the file in, in order.
+    fn search_dirs(&self, file: &Path) -> Vec<PathBuf> {
+        let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+        let mut dirs = Vec::new();
+        match Syntax::of(file) {
+            Syntax::Thrift => {
+                dirs.push(dir);
+                dirs.extend(self.include_dirs.iter().cloned());
+            }
+            Syntax::Protobuf => {
+                dirs.extend(self.include_dirs.iter().cloned());
+                dirs.push(dir);
+                dirs.extend(self.root_dirs.iter().cloned());
+                dirs.push(PathBuf::new());
+            }
+        }
+        let mut seen = HashSet::new();
+        dirs.retain(|d| seen.insert(d.clone()));
+        dirs
+    }
+
+    /// Returns the include in the dirs searched which is the most similar to the missing one.
+    fn suggest(&self, dirs: &[PathBuf], include: &str) -> Option<String> {
+        let name = Path::new(include).file_name()?.to_str()?;
+        let extension = Path::new(include).extension();
+        dirs.iter()
+            .flat_map(|dir| {
+                let root = if dir.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    dir
+                };
+                walkdir::WalkDir::new(root)
+                    .max_depth(4)
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .filter(|e| e.file_type().is_file() && e.path().extension() == extension)
+                    .filter_map(move |e| {
+                        let file_name = e.file_name().to_str()?;
+                        let distance = edit_distance(file_name, name);
+                        let path = e.path().strip_prefix(root).ok()?;
+                        Some((distance, path.to_string_lossy().replace('\\', "/")))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .filter(|(distance, path)| *distance <= 2 && path != include)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, path)| path)
+    }
+
+    fn visit(&mut self, file: &Path, via: Option<Span>) {
+        let key = std::fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        if let Some(start) = self
+            .stack
+            .iter()
+            .position(|f| std::fs::canonicalize(f).unwrap_or_else(|_| f.clone()) == key)
+        {
+            let chain = chain(self.stack[start..].iter().chain([&file.to_path_buf()]));
+            let via = via.expect("the roots aren't included");
+            self.errors.push(render(
+                &format!("circular include of {}", file.display()),
+                &via,
+                &[format!("the include chain: {}", chain)],
+            ));
+            return;
+        }
+        if !self.done.insert(key) {
+            return;
+        }
+        let source = match std::fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(e) => {
+                self.errors
+                    .push(format!("error: failed to read {}: {}\n", file.display(), e));
+                return;
+            }
+        };
+        let idl = parse(file, &source);
+
+        let mut defined_here: HashMap<&str, &Span> = HashMap::new();
+        for def in idl.definitions.iter() {
+            let first = match Syntax::of(file) {
+                Syntax::Thrift => defined_here.insert(&def.name, &def.span).cloned(),
+                Syntax::Protobuf => self.defined.insert(def.name.clone(), def.span.clone()),
+            };
+            if let Some(first) = first {
+                self.errors.push(render(
+                    &format!("duplicate definition of `{}`", def.name),
+                    &def.span,
+                    &[format!("first defined at {}", first.location())],
+                ));
+            }
+        }
+
+        self.stack.push(file.to_path_buf());
+        for include in idl.includes {
+            let dirs = self.search_dirs(file);
+            let found = dirs
+                .iter()
+                .map(|dir| dir.join(&include.name))
+                .find(|path| path.is_file());
+            match found {
+                Some(path) => self.visit(&path, Some(include.span)),
+                // embedded in the protobuf parsers
+                None if include.name.starts_with("google/protobuf/") => {}
+                None => {
+                    let mut notes = Vec::new();
+                    if self.stack.len() > 1 {
+                        notes.push(format!("included by: {}", chain(self.stack.iter())));
+                    }
+                    let searched = dirs
+                        .iter()
+                        .map(|d| {
+                            if d.as_os_str().is_empty() {
+                                ".".to_string()
+                            } else {
+                                d.display().to_string()
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    notes.push(format!("searched in: {}", searched));
+                    if let Some(suggestion) = self.suggest(&dirs, &include.name) {
+                        notes.push(format!("help: did you mean \"{}\"?", suggestion));
+                    }
+                    self.errors.push(render(
+                        &format!("cannot find the include \"{}\"", include.name),
+                        &include.span,
+                        &notes,
+                    ));
+                }
+            }
+        }
+        self.stack.pop();
+    }
+}
+
+fn chain<'a>(files: impl Iterator<Item = &'a PathBuf>) -> String {
+    files
+        .map(|f| f.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+/// Checks the includes and the definitions of the IDLs and the ones included by them.
+pub(crate) fn check(idls: &[PathBuf], include_dirs: &[PathBuf]) -> anyhow::Result<()> {
+    let mut checker = Checker {
+        include_dirs,
+        root_dirs: idls
+            .iter()
+            .filter_map(|idl| idl.parent().map(Path::to_path_buf))
+            .collect(),
+        stack: Vec::new(),
+        done: HashSet::new(),
+        defined: HashMap::new(),
+        errors: Vec::new(),
+    };
+    for idl in idls {
+        checker.visit(idl, None);
+    }
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "failed to resolve the IDLs:\n\n{}",
+            checker.errors.join("\n")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, source: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, source).unwrap();
+            path
+        };
+        write("common.thrift", "struct Base {}\n");
+        let item = write(
+            "item.thrift",
+            r#"include "commn.thrift"
+include "main.thrift"
+
+# struct Item {}
+struct Item {}
+typedef map<string, i32> Item
+"#,
+        );
+        let main = write("main.thrift", "include \"item.thrift\"\n");
+        let err = check(&[main.clone()], &[]).unwrap_err().to_string();
+
+        let item = item.display();
+        let main = main.display();
+        assert!(err.contains(&format!(
+            "error: duplicate definition of `Item`\n --> {}:6:26\n",
+            item
+        )));
+        assert!(err.contains(&format!("first defined at {}:5:8", item)));
+        let snippet = [
+            "error: cannot find the include \"commn.thrift\"".to_string(),
+            format!(" --> {}:1:9", item),
+            "  |".to_string(),
+            "1 | include \"commn.thrift\"".to_string(),
+            "  |         ^^^^^^^^^^^^^^".to_string(),
+            format!("  = included by: {} -> {}", main, item),
+        ];
+        assert!(err.contains(&snippet.join("\n")));
+        assert!(err.contains("help: did you mean \"common.thrift\"?"));
+        assert!(err.contains(&format!(
+            "the include chain: {} -> {} -> {}",
+            main, item, main
+        )));
+
+        let a = write("a.proto", "package p;\nmessage A { message B {} }\n");
+        let b = write(
+            "b.proto",
+            "package p;\nimport \"a.proto\";\nmessage B {}\nenum A {}\n",
+        );
+        let err = check(&[a, b], &[]).unwrap_err().to_string();
+        assert!(err.contains("duplicate definition of `p.A`"));
+        assert!(!err.contains("`p.B`"));
+    }
+}
@@ -0,0 +1,160 @@
+//! The cargo features of the generated services.
+//!
+//! With [`Builder::service_features`](crate::Builder::service_features), the items generated for
+//! each service, such as its client, its server and the types of its requests and responses, are
+//! gated by `#[cfg(feature = "...")]` of the snake case name of the service, such as
+//! `item_service` of `ItemService`. The crates depending on a big IDL crate can then enable only
+//! the features of the services they use, and skip compiling the rest of them.
+//!
+//! The features of all the services are written next to the generated file, such as
+//! `volo_gen.features.toml` of `volo_gen.rs`, to be copied into the `[features]` of the IDL
+//! crate:
+//!
+//! ```toml
+//! [features]
+//! item_service = []
+//! user_service = []
+//! ```
+
+use std::{
+    collections::BTreeSet,
+    fmt::Write as _,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use heck::ToSnakeCase;
+use proc_macro2::TokenStream;
+
+/// Returns the feature of the service.
+pub fn feature_name(service: &str) -> String {
+    service.to_snake_case()
+}
+
+/// Gates the items of the services by their features, and collects the features.
+///
+/// Disabled by default, which keeps the items as they are.
+#[derive(Default, Clone)]
+pub struct ServiceFeatures {
+    features: Option<Arc<Mutex<BTreeSet<String>>>>,
+}
+
+impl ServiceFeatures {
+    pub(crate) fn enabled() -> Self {
+        Self {
+            features: Some(Default::default()),
+        }
+    }
+
+    /// Gates every item of the service by its feature.
+    pub fn gate(&self, service: &str, items: TokenStream) -> TokenStream {
+        let features = match &self.features {
+            Some(features) => features,
+            None => return items,
+        };
+        let mut file: syn::File = match syn::parse2(items.clone()) {
+            Ok(file) => file,
+            Err(e) => {
+                println!(
+                    "cargo:warning=failed to gate the items of {} by its feature: {}",
+                    service, e
+                );
+                return items;
+            }
+        };
+        let feature = feature_name(service);
+        let cfg: syn::Attribute = syn::parse_quote!(#[cfg(feature = #feature)]);
+        for item in file.items.iter_mut() {
+            if let Some(attrs) = attrs_mut(item) {
+                attrs.insert(0, cfg.clone());
+            }
+        }
+        features.lock().unwrap().insert(feature);
+        quote::quote!(#file)
+    }
+
+    /// Writes the features collected as the `[features]` of a manifest, if enabled.
+    pub(crate) fn write(&self, path: &Path) -> std::io::Result<()> {
+        let features = match &self.features {
+            Some(features) => features.lock().unwrap().clone(),
+            None => return Ok(()),
+        };
+        let mut manifest = String::from("[features]\n");
+        for feature in features.iter() {
+            let _ = writeln!(manifest, "{} = []", feature);
+        }
+        std::fs::write(path, manifest)
+    }
+}
+
+fn attrs_mut(item: &mut syn::Item) -> Option<&mut Vec<syn::Attribute>> {
+    use syn::Item;
+
+    Some(match item {
+        Item::Const(i) => &mut i.attrs,
+        Item::Enum(i) => &mut i.attrs,
+        Item::ExternCrate(i) => &mut i.attrs,
+        Item::Fn(i) => &mut i.attrs,
+        Item::ForeignMod(i) => &mut i.attrs,
+        Item::Impl(i) => &mut i.attrs,
+        Item::Macro(i) => &mut i.attrs,
+        Item::Macro2(i) => &mut i.attrs,
+        Item::Mod(i) => &mut i.attrs,
+        Item::Static(i) => &mut i.attrs,
+        Item::Struct(i) => &mut i.attrs,
+        Item::Trait(i) => &mut i.attrs,
+        Item::TraitAlias(i) => &mut i.attrs,
+        Item::Type(i) => &mut i.attrs,
+        Item::Union(i) => &mut i.attrs,
+        Item::Use(i) => &mut i.attrs,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+
+    use super::*;
+
+    #[test]
+    fn test_gate() {
+        let items = quote! {
+            pub struct ItemServiceClient;
+            impl ItemServiceClient {
+                pub fn new() -> Self { Self }
+            }
+        };
+        assert_eq!(
+            ServiceFeatures::default()
+                .gate("ItemService", items.clone())
+                .to_string(),
+            items.to_string()
+        );
+
+        let features = ServiceFeatures::enabled();
+        let gated: syn::File = syn::parse2(features.gate("ItemService", items)).unwrap();
+        for item in gated.items.iter() {
+            let attr = match item {
+                syn::Item::Struct(i) => &i.attrs[0],
+                syn::Item::Impl(i) => &i.attrs[0],
+                _ => unreachable!(),
+            };
+            assert_eq!(
+                quote!(#attr).to_string(),
+                quote!(#[cfg(feature = "item_service")]).to_string()
+            );
+        }
+        let items = quote! {
+            pub struct UserServiceClient;
+        };
+        features.gate("UserService", items);
+
+        let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        features.write(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "[features]\nitem_service = []\nuser_service = []\n"
+        );
+    }
+}
@@ -1,5 +1,6 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
+use anyhow::Context as _;
 use itertools::Itertools;
 use pilota_build::{
     db::RirDatabase,
@@ -11,18 +12,65 @@ use pilota_build::{
 use proc_macro2::{Ident, TokenStream};
 use quote::{format_ident, quote};
 
-pub struct MkGrpcBackend;
+use crate::{
+    features::ServiceFeatures,
+    headers::{Headers, ServiceHeaders},
+};
+
+#[derive(Default)]
+pub struct MkGrpcBackend {
+    headers: Headers,
+    features: ServiceFeatures,
+    mock: bool,
+    json: bool,
+}
+
+impl crate::MakeVoloBackend for MkGrpcBackend {
+    fn read_idls(mut self, idls: &[PathBuf]) -> anyhow::Result<Self> {
+        for idl in idls {
+            let source = std::fs::read_to_string(idl)
+                .with_context(|| format!("failed to read {}", idl.display()))?;
+            self.headers.extend(crate::headers::parse(&source));
+        }
+        Ok(self)
+    }
+
+    fn service_features(mut self, features: ServiceFeatures) -> Self {
+        self.features = features;
+        self
+    }
+
+    fn gen_mock(mut self, enable: bool) -> Self {
+        self.mock = enable;
+        self
+    }
+
+    fn serde(mut self, enable: bool) -> Self {
+        self.json = enable;
+        self
+    }
+}
 
 impl pilota_build::MakeBackend for MkGrpcBackend {
     type Target = VoloGrpcBackend;
 
     fn make_backend(self, context: std::sync::Arc<pilota_build::Context>) -> Self::Target {
-        VoloGrpcBackend { cx: context }
+        VoloGrpcBackend {
+            cx: context,
+            headers: self.headers,
+            features: self.features,
+            mock: self.mock,
+            json: self.json,
+        }
     }
 }
 
 pub struct VoloGrpcBackend {
     cx: Arc<Context>,
+    headers: Headers,
+    features: ServiceFeatures,
+    mock: bool,
+    json: bool,
 }
 
 impl VoloGrpcBackend {
@@ -60,12 +108,265 @@ impl VoloGrpcBackend {
         let ret_ty = self.cx.codegen_item_ty(ty.kind);
 
         if streaming {
-            quote!(::std::result::Result<::volo_grpc::Response<impl ::futures::Stream<Item = ::std::result::Result<#ret_ty,::volo_grpc::Status>>>, ::volo_grpc::Status>)
+            // return the concrete `RecvStream` so callers can still read the trailers
+            // after the message stream is drained.
+            quote!(::std::result::Result<::volo_grpc::Response<::volo_grpc::RecvStream<#ret_ty>>, ::volo_grpc::Status>)
         } else {
             quote!(::std::result::Result<::volo_grpc::Response<#ret_ty>, ::volo_grpc::Status>)
         }
     }
 
+    /// Whether the message has a `bool heartbeat` field, the streams of which are unwrapped into
+    /// unary-like calls by the client, see `volo_grpc::keep_alive`.
+    fn has_heartbeat(&self, ty: &pilota_build::ty::Ty) -> bool {
+        use pilota_build::ty::TyKind;
+
+        match &ty.kind {
+            TyKind::Path(p) => match &*self.cx.expect_item(p.did) {
+                rir::Item::Message(m) => m
+                    .fields
+                    .iter()
+                    .any(|f| &**f.name == "heartbeat" && matches!(f.ty.kind, TyKind::Bool)),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Generates the typed keys of the required headers of the service, and the headers required
+    /// by each method for `volo_grpc::layer::required_headers`.
+    fn codegen_headers(
+        &self,
+        service_name: &Ident,
+        package: &str,
+        s: &rir::Service,
+        headers: &ServiceHeaders,
+    ) -> TokenStream {
+        let headers_name = format_ident!("{}Headers", service_name);
+        let key = |h: &str| format_ident!("{}", h.replace(['-', '.'], "_").to_uppercase());
+
+        let accessors = headers
+            .all(s.methods.iter().map(|m| m.name.to_string()))
+            .into_iter()
+            .map(|h| {
+                let key = key(h);
+                let getter = format_ident!("{}", h.replace(['-', '.'], "_"));
+                let setter = format_ident!("set_{}", getter);
+                let get_doc = format!("Returns the value of the `{}` header.", h);
+                let set_doc = format!("Sets the value of the `{}` header.", h);
+                quote! {
+                    pub const #key: &'static str = #h;
+
+                    #[doc = #get_doc]
+                    pub fn #getter(metadata: &::volo_grpc::metadata::MetadataMap) -> ::std::option::Option<&str> {
+                        metadata.get(Self::#key).and_then(|v| v.to_str().ok())
+                    }
+
+                    #[doc = #set_doc]
+                    pub fn #setter(
+                        metadata: &mut ::volo_grpc::metadata::MetadataMap,
+                        value: &str,
+                    ) -> ::std::result::Result<(), ::volo_grpc::metadata::errors::InvalidMetadataValue> {
+                        metadata.insert(Self::#key, value.parse()?);
+                        ::std::result::Result::Ok(())
+                    }
+                }
+            });
+
+        let required = s.methods.iter().map(|method| {
+            let path = format!("/{}.{}/{}", package, s.name, method.name);
+            let keys = headers.of_method(&method.name).into_iter().map(key);
+            quote! {
+                #path => &[#(Self::#keys),*],
+            }
+        });
+
+        quote! {
+            /// The typed keys of the headers required by the service.
+            pub struct #headers_name;
+
+            impl #headers_name {
+                #(#accessors)*
+
+                /// Returns the headers required by the method of the path, such as
+                /// `/volo.example.ItemService/GetItem`.
+                pub fn required(path: &str) -> &'static [&'static str] {
+                    match path {
+                        #(#required)*
+                        _ => &[],
+                    }
+                }
+            }
+        }
+    }
+
+    /// Generates the client trait of the service implemented by its client, and the mock client
+    /// implementing it by the closures programmed for the methods.
+    ///
+    /// The streams of the responses are boxed in the trait, so that the mocks can make them.
+    fn codegen_mock(
+        &self,
+        service_name: &Ident,
+        client_name: &Ident,
+        s: &rir::Service,
+    ) -> TokenStream {
+        let trait_name = format_ident!("{}ClientTrait", service_name);
+        let mock_name = format_ident!("Mock{}", client_name);
+
+        let mut trait_methods = Vec::new();
+        let mut client_impls = Vec::new();
+        let mut mock_fields = Vec::new();
+        let mut mock_setters = Vec::new();
+        let mut mock_impls = Vec::new();
+        for method in s.methods.iter() {
+            let method_name = format_ident!("{}", method.name.to_snake_case());
+            let setter_name = format_ident!("on_{}", method.name.to_snake_case());
+            let req_ty = self.cx.codegen_item_ty(method.args[0].ty.kind.clone());
+            let resp_ty = self.cx.codegen_item_ty(method.ret.kind.clone());
+            let client_streaming = self.cx.node_contains_tag::<ClientStreaming>(method.def_id);
+            let server_streaming = self.cx.node_contains_tag::<ServerStreaming>(method.def_id);
+
+            let req_ty = if client_streaming {
+                quote!(::volo_grpc::Request<::volo_grpc::BoxStream<'static, #req_ty>>)
+            } else {
+                quote!(::volo_grpc::Request<#req_ty>)
+            };
+            let (resp_ty, into_resp) = if server_streaming {
+                (
+                    quote!(::std::result::Result<::volo_grpc::Response<::volo_grpc::BoxStream<'static, ::std::result::Result<#resp_ty, ::volo_grpc::Status>>>, ::volo_grpc::Status>),
+                    quote!(.map(|resp| resp.map(|stream| ::std::boxed::Box::pin(stream) as _))),
+                )
+            } else {
+                (
+                    quote!(::std::result::Result<::volo_grpc::Response<#resp_ty>, ::volo_grpc::Status>),
+                    quote!(),
+                )
+            };
+            let setter_doc = format!("Responds to the calls of `{}` by `f`.", method.name);
+            let unmocked = format!("{} is not mocked", method.name);
+
+            trait_methods.push(quote! {
+                async fn #method_name(&mut self, requests: #req_ty) -> #resp_ty;
+            });
+            client_impls.push(quote! {
+                async fn #method_name(&mut self, requests: #req_ty) -> #resp_ty {
+                    #client_name::#method_name(self, requests).await #into_resp
+                }
+            });
+            mock_fields.push(quote! {
+                #method_name: ::std::option::Option<::std::sync::Arc<dyn Fn(#req_ty) -> #resp_ty + ::core::marker::Send + ::core::marker::Sync>>,
+            });
+            mock_setters.push(quote! {
+                #[doc = #setter_doc]
+                pub fn #setter_name(mut self, f: impl Fn(#req_ty) -> #resp_ty + ::core::marker::Send + ::core::marker::Sync + 'static) -> Self {
+                    self.#method_name = ::std::option::Option::Some(::std::sync::Arc::new(f));
+                    self
+                }
+            });
+            mock_impls.push(quote! {
+                async fn #method_name(&mut self, requests: #req_ty) -> #resp_ty {
+                    match &self.#method_name {
+                        ::std::option::Option::Some(f) => f(requests),
+                        ::std::option::Option::None => ::std::result::Result::Err(::volo_grpc::Status::unimplemented(#unmocked)),
+                    }
+                }
+            });
+        }
+
+        let trait_doc = format!(
+            "The calls of [`{}`], implemented by [`{}`] for the tests.",
+            client_name, mock_name
+        );
+        let mock_doc = format!(
+            "The mock of [`{}`], responding to the calls by the closures of the methods, and with \
+             `UNIMPLEMENTED` to the methods not programmed.",
+            client_name
+        );
+        quote! {
+            #[doc = #trait_doc]
+            #[::async_trait::async_trait]
+            pub trait #trait_name: ::core::marker::Send {
+                #(#trait_methods)*
+            }
+
+            #[::async_trait::async_trait]
+            impl #trait_name for #client_name {
+                #(#client_impls)*
+            }
+
+            #[doc = #mock_doc]
+            #[derive(Clone, Default)]
+            pub struct #mock_name {
+                #(#mock_fields)*
+            }
+
+            impl #mock_name {
+                pub fn new() -> Self {
+                    ::std::default::Default::default()
+                }
+
+                #(#mock_setters)*
+            }
+
+            #[::async_trait::async_trait]
+            impl #trait_name for #mock_name {
+                #(#mock_impls)*
+            }
+        }
+    }
+
+    /// Generates the JSON transcoding of the request and the response enums of the service by the
+    /// paths of the methods, see `volo_grpc::json`.
+    fn codegen_json(
+        &self,
+        req_enum_name: &Ident,
+        resp_enum_name: &Ident,
+        paths: &[String],
+        s: &rir::Service,
+    ) -> TokenStream {
+        let mut decodes = Vec::new();
+        let mut encodes = Vec::new();
+        for (method, path) in s.methods.iter().zip(paths) {
+            let variant_name = format_ident!("{}", method.name.to_upper_camel_case());
+            let req_ty = self.cx.codegen_item_ty(method.args[0].ty.kind.clone());
+            let decode = if self.cx.node_contains_tag::<ClientStreaming>(method.def_id) {
+                format_ident!("decode_stream")
+            } else {
+                format_ident!("decode")
+            };
+            let encode = if self.cx.node_contains_tag::<ServerStreaming>(method.def_id) {
+                format_ident!("encode_stream")
+            } else {
+                format_ident!("encode")
+            };
+
+            decodes.push(quote! {
+                #path => ::std::result::Result::Ok(Self::#variant_name(::volo_grpc::json::#decode::<#req_ty>(json)?)),
+            });
+            encodes.push(quote! {
+                Self::#variant_name(s) => ::std::boxed::Box::pin(::volo_grpc::json::#encode(s)),
+            });
+        }
+
+        quote! {
+            impl ::volo_grpc::json::FromJson for #req_enum_name {
+                fn from_json(method: &str, json: &[u8]) -> ::std::result::Result<Self, ::volo_grpc::Status> {
+                    match method {
+                        #(#decodes)*
+                        _ => ::std::result::Result::Err(::volo_grpc::Status::new(::volo_grpc::Code::Unimplemented, "Method not found.")),
+                    }
+                }
+            }
+
+            impl ::volo_grpc::json::IntoJson for #resp_enum_name {
+                fn into_json(self) -> ::volo_grpc::json::BoxFuture<'static, ::std::result::Result<::volo_grpc::codegen::Bytes, ::volo_grpc::Status>> {
+                    match self {
+                        #(#encodes)*
+                    }
+                }
+            }
+        }
+    }
     fn build_client_req(&self, _ty: pilota_build::ty::Ty, streaming: bool) -> TokenStream {
         if streaming {
             quote!(requests
@@ -85,22 +386,22 @@ impl VoloGrpcBackend {
         _ty: pilota_build::ty::Ty,
         streaming: bool,
     ) -> TokenStream {
-        let resp_stream = quote! {
-            let (mut metadata, extensions, message_stream) = resp.into_parts();
-            let mut message_stream = match message_stream {
-                #resp_enum_name::#variant_name(stream) => stream,
-                _ => return Err(::volo_grpc::Status::new(::volo_grpc::Code::Unimplemented, "Method not found.")),
-            };
-        };
-
         if streaming {
             quote! {
-                #resp_stream
+                let (metadata, extensions, message_stream) = resp.into_parts();
+                let message_stream = match message_stream {
+                    #resp_enum_name::#variant_name(stream) => stream,
+                    _ => return Err(::volo_grpc::Status::new(::volo_grpc::Code::Unimplemented, "Method not found.")),
+                };
                 Ok(::volo_grpc::Response::from_parts(metadata, extensions, message_stream))
             }
         } else {
             quote! {
-                #resp_stream
+                let (mut metadata, extensions, message_stream) = resp.into_parts();
+                let mut message_stream = match message_stream {
+                    #resp_enum_name::#variant_name(stream) => stream,
+                    _ => return Err(::volo_grpc::Status::new(::volo_grpc::Code::Unimplemented, "Method not found.")),
+                };
                 let message = ::volo_grpc::codegen::StreamExt::try_next(&mut message_stream)
                     .await
                     .map_err(|mut status| {
@@ -200,7 +501,9 @@ impl CodegenBackend for VoloGrpcBackend {
         }
     }
 
-    fn codegen_service_impl(&self, def_id: DefId, stream: &mut TokenStream, s: &rir::Service) {
+    fn codegen_service_impl(&self, def_id: DefId, out: &mut TokenStream, s: &rir::Service) {
+        // the items of the service, to be gated by its feature
+        let stream = &mut TokenStream::new();
         let service_name = format_ident!("{}", s.name.to_upper_camel_case());
         let server_name = format_ident!("{}Server", service_name);
         let client_builder_name = format_ident!("{}ClientBuilder", service_name);
@@ -210,6 +513,16 @@ impl CodegenBackend for VoloGrpcBackend {
         let file = self.cx.file(file_id).unwrap();
 
         let package = file.package.iter().join(".");
+        let full_name = if package.is_empty() {
+            s.name.to_string()
+        } else {
+            format!("{}.{}", package, s.name)
+        };
+        let method_names = s
+            .methods
+            .iter()
+            .map(|method| method.name.to_string())
+            .collect::<Vec<_>>();
 
         let req_enum_name_send = format_ident!("{}RequestSend", service_name);
         let resp_enum_name_send = format_ident!("{}ResponseSend", service_name);
@@ -274,6 +587,8 @@ impl CodegenBackend for VoloGrpcBackend {
 
         let client_methods = s.methods.iter().map(|method| {
             let method_name = format_ident!("{}", method.name.to_snake_case());
+            let method_name_with_callopt =
+                format_ident!("{}_with_callopt", method.name.to_snake_case());
 
             let path = format!("/{}.{}/{}", package, s.name, method.name);
             let input_ty = &method.args[0].ty;
@@ -291,6 +606,23 @@ impl CodegenBackend for VoloGrpcBackend {
 
             let resp = self.build_client_resp(&resp_enum_name_recv, &variant_name, output_ty.clone(), server_streaming);
 
+            let unary = if server_streaming && self.has_heartbeat(output_ty) {
+                let method_name_unary = format_ident!("{}_unary", method.name.to_snake_case());
+                let ret_ty = self.cx.codegen_item_ty(output_ty.kind.clone());
+                quote! {
+                    pub async fn #method_name_unary(
+                        &mut self,
+                        requests: #req_ty,
+                    ) -> ::std::result::Result<::volo_grpc::Response<#ret_ty>, ::volo_grpc::Status> {
+                        let (metadata, extensions, stream) = self.#method_name(requests).await?.into_parts();
+                        let message = ::volo_grpc::keep_alive::final_message(stream, |m: &#ret_ty| m.heartbeat).await?;
+                        Ok(::volo_grpc::Response::from_parts(metadata, extensions, message))
+                    }
+                }
+            } else {
+                quote!()
+            };
+
             quote! {
                 pub async fn #method_name(
                     &mut self,
@@ -307,9 +639,38 @@ impl CodegenBackend for VoloGrpcBackend {
 
                     #resp
                 }
+
+                pub async fn #method_name_with_callopt(
+                    &mut self,
+                    requests: #req_ty,
+                    callopt: ::volo_grpc::client::CallOpt,
+                ) -> #resp_ty {
+                    let req = #req.map(|message| #req_enum_name_send::#variant_name(::std::boxed::Box::pin(message) as _));
+
+                    let resp = self
+                        .client
+                        .as_mut()
+                        .unwrap()
+                        .call_with_callopt(#path, req, ::std::option::Option::Some(callopt))
+                        .await?;
+
+                    #resp
+                }
+
+                #unary
             }
         });
 
+        if let Some(headers) = self.headers.get(&full_name) {
+            stream.extend(self.codegen_headers(&service_name, &package, s, headers));
+        }
+        if self.mock {
+            stream.extend(self.codegen_mock(&service_name, &client_name, s));
+        }
+        if self.json {
+            stream.extend(self.codegen_json(&req_enum_name_recv, &resp_enum_name_send, &paths, s));
+        }
+
         stream.extend(quote! {
             pub enum #req_enum_name_send {
                 #(#enum_variant_names(::volo_grpc::BoxStream<'static, ::std::result::Result<#req_tys, ::volo_grpc::Status>>),)*
@@ -330,10 +691,10 @@ impl CodegenBackend for VoloGrpcBackend {
             }
 
             impl ::volo_grpc::RecvEntryMessage for #req_enum_name_recv {
-                fn from_body(method: ::std::option::Option<&str>, body: ::volo_grpc::codegen::hyper::Body, kind: ::volo_grpc::codec::decode::Kind) -> ::std::result::Result<Self, ::volo_grpc::Status> {
+                fn from_body(method: ::std::option::Option<&str>, body: ::volo_grpc::codegen::hyper::Body, kind: ::volo_grpc::codec::decode::Kind, config: ::volo_grpc::codec::decode::DecodeConfig) -> ::std::result::Result<Self, ::volo_grpc::Status> {
                     match method {
                         #(Some(#paths) => {
-                            Ok(Self::#enum_variant_names(::volo_grpc::RecvStream::new(body, kind)))
+                            Ok(Self::#enum_variant_names(::volo_grpc::RecvStream::with_config(body, kind, config)))
                         })*
                         _ => Err(::volo_grpc::Status::new(::volo_grpc::Code::Unimplemented, "Method not found.")),
                     }
@@ -359,13 +720,13 @@ impl CodegenBackend for VoloGrpcBackend {
             }
 
             impl ::volo_grpc::RecvEntryMessage for #resp_enum_name_recv {
-                fn from_body(method: ::std::option::Option<&str>, body: ::volo_grpc::codegen::hyper::Body, kind: ::volo_grpc::codec::decode::Kind) -> ::std::result::Result<Self, ::volo_grpc::Status>
+                fn from_body(method: ::std::option::Option<&str>, body: ::volo_grpc::codegen::hyper::Body, kind: ::volo_grpc::codec::decode::Kind, config: ::volo_grpc::codec::decode::DecodeConfig) -> ::std::result::Result<Self, ::volo_grpc::Status>
                 where
                     Self: ::core::marker::Sized,
                 {
                     match method {
                         #(Some(#paths) => {
-                            Ok(Self::#enum_variant_names(::volo_grpc::RecvStream::new(body, kind)))
+                            Ok(Self::#enum_variant_names(::volo_grpc::RecvStream::with_config(body, kind, config)))
                         })*
                         _ => Err(::volo_grpc::Status::new(::volo_grpc::Code::Unimplemented, "Method not found.")),
                     }
@@ -406,6 +767,12 @@ impl CodegenBackend for VoloGrpcBackend {
                 #(#client_methods)*
             }
 
+            impl ::volo::service_info::ServiceInfo for #client_name {
+                const NAME: &'static str = #full_name;
+                const METHODS: &'static [&'static str] = &[#(#method_names),*];
+                const VERSION: &'static str = ::std::env!("CARGO_PKG_VERSION");
+            }
+
             impl ::volo_grpc::client::SetClient<#req_enum_name_send, #resp_enum_name_recv> for #client_name {
                 fn set_client(
                     mut self,
@@ -421,6 +788,12 @@ impl CodegenBackend for VoloGrpcBackend {
                 inner: ::std::sync::Arc<S>,
             }
 
+            impl<S> ::volo::service_info::ServiceInfo for #server_name<S> {
+                const NAME: &'static str = #full_name;
+                const METHODS: &'static [&'static str] = &[#(#method_names),*];
+                const VERSION: &'static str = ::std::env!("CARGO_PKG_VERSION");
+            }
+
             impl<S> Clone for #server_name<S> {
                 fn clone(&self) -> Self {
                     #server_name {
@@ -436,6 +809,12 @@ impl CodegenBackend for VoloGrpcBackend {
                     };
                     ::volo_grpc::server::Server::new(service)
                 }
+
+                pub fn service(inner: S) -> Self {
+                    Self {
+                        inner: ::std::sync::Arc::new(inner),
+                    }
+                }
             }
 
             impl<S> ::volo::service::Service<::volo_grpc::context::ServerContext, ::volo_grpc::Request<#req_enum_name_recv>> for #server_name<S>
@@ -463,5 +842,6 @@ impl CodegenBackend for VoloGrpcBackend {
                 }
             }
         });
+        out.extend(self.features.gate(&s.name, std::mem::take(stream)));
     }
 }
@@ -0,0 +1,310 @@
+//! The headers required by the gRPC services and methods, annotated in the protobuf IDLs by the
+//! options of the bundled `volo/annotations.proto`:
+//!
+//! ```protobuf
+//! import "volo/annotations.proto";
+//!
+//! service ItemService {
+//!     option (volo.service_headers) = "x-tenant-id";
+//!
+//!     rpc GetItem(GetItemRequest) returns (GetItemResponse) {
+//!         option (volo.method_headers) = "x-user-id";
+//!     }
+//! }
+//! ```
+//!
+//! The options are read from the sources of the IDLs, since the parser doesn't keep the custom
+//! ones, and the backend generates the typed keys of them.
+
+use std::collections::HashMap;
+
+/// The required headers of a service.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ServiceHeaders {
+    /// The headers required by all the methods.
+    pub service: Vec<String>,
+    /// The headers required by each method, by the method names.
+    pub methods: HashMap<String, Vec<String>>,
+}
+
+impl ServiceHeaders {
+    /// Returns all the headers, in the order annotated.
+    pub fn all(&self, methods: impl IntoIterator<Item = impl AsRef<str>>) -> Vec<&str> {
+        let mut all: Vec<&str> = self.service.iter().map(|h| h.as_str()).collect();
+        for method in methods {
+            for h in self.methods.get(method.as_ref()).into_iter().flatten() {
+                if !all.contains(&h.as_str()) {
+                    all.push(h);
+                }
+            }
+        }
+        all
+    }
+
+    /// Returns the headers required by the method, including the ones of the service.
+    pub fn of_method(&self, method: &str) -> Vec<&str> {
+        self.all([method])
+    }
+}
+
+/// The required headers by the full names of the services, such as `volo.example.ItemService`.
+pub type Headers = HashMap<String, ServiceHeaders>;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token<'a> {
+    Ident(&'a str),
+    Str(String),
+    Punct(char),
+}
+
+fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '/' if matches!(chars.peek(), Some((_, '/'))) => {
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some((_, '*'))) => {
+                chars.next();
+                let mut prev = ' ';
+                for (_, c) in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            '"' | '\'' => {
+                let mut value = String::new();
+                while let Some((_, next)) = chars.next() {
+                    match next {
+                        '\\' => {
+                            if let Some((_, escaped)) = chars.next() {
+                                value.push(escaped);
+                            }
+                        }
+                        next if next == c => break,
+                        next => value.push(next),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '.' => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, next)) = chars.peek() {
+                    if !(next.is_alphanumeric() || next == '_' || next == '.') {
+                        break;
+                    }
+                    end = i + next.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token::Ident(&source[start..end]));
+            }
+            c => tokens.push(Token::Punct(c)),
+        }
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&Token<'a>> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.pos)
+    }
+
+    fn ident(&mut self) -> Option<&'a str> {
+        match self.next()? {
+            Token::Ident(ident) => Some(*ident),
+            _ => None,
+        }
+    }
+
+    /// Skips to the end of the statement, across the nested braces.
+    fn skip_statement(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.next() {
+            match token {
+                Token::Punct('{') => depth += 1,
+                // the end of the enclosing block
+                Token::Punct('}') if depth == 0 => {
+                    self.pos -= 1;
+                    return;
+                }
+                Token::Punct('}') => depth -= 1,
+                Token::Punct(';') if depth == 0 => return,
+                _ => {}
+            }
+        }
+    }
+
+    /// Parses an option after `option`, returns its name and its string value if any.
+    fn option(&mut self) -> Option<(&'a str, Option<String>)> {
+        let name = if matches!(self.peek()?, Token::Punct('(')) {
+            self.next();
+            let name = self.ident()?;
+            self.next();
+            name
+        } else {
+            self.ident()?
+        };
+        let assigned = matches!(self.next(), Some(Token::Punct('=')));
+        let value = match self.peek() {
+            Some(Token::Str(value)) if assigned => Some(value.clone()),
+            _ => None,
+        };
+        self.skip_statement();
+        Some((name.trim_start_matches('.'), value))
+    }
+
+    fn service(&mut self, headers: &mut ServiceHeaders) {
+        while let Some(token) = self.next() {
+            match token {
+                Token::Punct('}') => return,
+                Token::Ident("option") => {
+                    if let Some(("volo.service_headers", Some(value))) = self.option() {
+                        push_header(&mut headers.service, value);
+                    }
+                }
+                Token::Ident("rpc") => {
+                    let name = match self.ident() {
+                        Some(name) => name.to_string(),
+                        None => continue,
+                    };
+                    self.method(name, headers);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn method(&mut self, name: String, headers: &mut ServiceHeaders) {
+        // the signature, up to either `;` or the block of the options
+        loop {
+            match self.next() {
+                None | Some(Token::Punct(';')) => return,
+                Some(Token::Punct('{')) => break,
+                _ => {}
+            }
+        }
+        while let Some(token) = self.next() {
+            match token {
+                Token::Punct('}') => return,
+                Token::Ident("option") => {
+                    if let Some(("volo.method_headers", Some(value))) = self.option() {
+                        push_header(headers.methods.entry(name.clone()).or_default(), value);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn push_header(headers: &mut Vec<String>, value: String) {
+    let header = value.to_ascii_lowercase();
+    let valid = !header.is_empty()
+        && !header.starts_with("grpc-")
+        && header
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b));
+    if !valid {
+        println!("cargo:warning=invalid required header: {:?}", value);
+    } else if !headers.contains(&header) {
+        headers.push(header);
+    }
+}
+
+/// Reads the required headers annotated in the source of a protobuf IDL.
+pub(crate) fn parse(source: &str) -> Headers {
+    let mut parser = Parser {
+        tokens: tokenize(source),
+        pos: 0,
+    };
+    let mut package = String::new();
+    let mut all = Headers::new();
+    while let Some(token) = parser.next() {
+        match token {
+            Token::Ident("package") => {
+                if let Some(name) = parser.ident() {
+                    package = name.to_string();
+                }
+            }
+            Token::Ident("service") => {
+                let name = match parser.ident() {
+                    Some(name) => name,
+                    None => continue,
+                };
+                if parser.next() != Some(&Token::Punct('{')) {
+                    continue;
+                }
+                let mut headers = ServiceHeaders::default();
+                parser.service(&mut headers);
+                if headers != ServiceHeaders::default() {
+                    let full_name = if package.is_empty() {
+                        name.to_string()
+                    } else {
+                        format!("{}.{}", package, name)
+                    };
+                    all.insert(full_name, headers);
+                }
+            }
+            _ => {}
+        }
+    }
+    all
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let source = r#"
+            syntax = "proto3";
+            package volo.example;
+
+            import "volo/annotations.proto";
+
+            service ItemService {
+                // option (volo.service_headers) = "x-commented";
+                option (volo.service_headers) = "X-Tenant-Id";
+                option deprecated = true;
+
+                rpc GetItem(GetItemRequest) returns (GetItemResponse) {
+                    option (google.api.http) = { get: "/v1/items/{id}" };
+                    option (volo.method_headers) = "x-user-id";
+                    option (volo.method_headers) = "x-tenant-id";
+                }
+                rpc ListItems(stream ListItemsRequest) returns (stream Item);
+            }
+
+            service Plain {
+                rpc Ping(Empty) returns (Empty) {}
+            }
+        "#;
+        let headers = parse(source);
+        assert_eq!(headers.len(), 1);
+        let item = &headers["volo.example.ItemService"];
+        assert_eq!(item.service, ["x-tenant-id"]);
+        assert_eq!(item.methods["GetItem"], ["x-user-id", "x-tenant-id"]);
+        assert_eq!(item.of_method("GetItem"), ["x-tenant-id", "x-user-id"]);
+        assert_eq!(item.of_method("ListItems"), ["x-tenant-id"]);
+    }
+}
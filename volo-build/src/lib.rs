@@ -12,48 +12,133 @@ use anyhow::anyhow;
 use pilota_build::parser::Parser;
 
 pub mod config_builder;
+mod descriptor_set;
+mod diagnostics;
+pub mod features;
 pub mod grpc_backend;
+pub mod headers;
 pub mod model;
+pub mod serde_plugin;
 pub mod thrift_backend;
 pub mod util;
+mod well_known;
 
 pub use config_builder::ConfigBuilder;
 pub use pilota_build::{
     parser, plugin, rir, BoxClonePlugin, ClonePlugin, Context, DefId, MakeBackend, Plugin,
 };
 
+/// Makes the backends of volo, which can read the IDLs before the codegen.
+pub trait MakeVoloBackend: MakeBackend + Sized {
+    /// Reads from the IDLs what the parser doesn't keep, such as the custom options.
+    fn read_idls(self, _idls: &[PathBuf]) -> anyhow::Result<Self> {
+        Ok(self)
+    }
+
+    /// Gates the items generated for every service by its feature, see [`features`].
+    fn service_features(self, _features: features::ServiceFeatures) -> Self {
+        self
+    }
+
+    /// Generates the client traits and the mock clients of the services.
+    fn gen_mock(self, _enable: bool) -> Self {
+        self
+    }
+
+    /// Generates the JSON transcoding of the services, for the types deriving serde, see
+    /// [`serde_plugin`].
+    fn serde(self, _enable: bool) -> Self {
+        self
+    }
+}
+
 pub struct Builder<MkB, P> {
     pilota_builder: pilota_build::Builder<MkB, P>,
+    mk_backend: MkB,
     idls: Vec<PathBuf>,
     out_dir: Option<PathBuf>,
     filename: PathBuf,
     config_file_path: PathBuf,
+    include_dirs: Vec<PathBuf>,
+    bundled_includes: bool,
+    service_features: bool,
+    gen_mock: bool,
+    serde: bool,
+    file_descriptor_set: bool,
 }
 
 impl Builder<thrift_backend::MkThriftBackend, pilota_build::parser::ThriftParser> {
     pub fn thrift() -> Self {
         Builder {
             pilota_builder: pilota_build::Builder::thrift()
-                .with_backend(thrift_backend::MkThriftBackend),
+                .with_backend(thrift_backend::MkThriftBackend::default()),
+            mk_backend: Default::default(),
             out_dir: Default::default(),
             filename: "volo_gen".into(),
             idls: Default::default(),
             config_file_path: "volo.yml".into(),
+            include_dirs: Default::default(),
+            bundled_includes: false,
+            service_features: false,
+            gen_mock: false,
+            serde: false,
+            file_descriptor_set: false,
         }
     }
+
+    /// Sets whether to generate the runtime descriptors of the structs, enums and services, for
+    /// the generic tools to walk the generated types, see `volo_thrift::descriptor`.
+    ///
+    /// Defaults to false.
+    pub fn descriptors(mut self, enable: bool) -> Self {
+        self.mk_backend = self.mk_backend.descriptors(enable);
+        self
+    }
 }
 
 impl Builder<grpc_backend::MkGrpcBackend, pilota_build::parser::ProtobufParser> {
     pub fn protobuf() -> Self {
         Builder {
             pilota_builder: pilota_build::Builder::protobuf()
-                .with_backend(grpc_backend::MkGrpcBackend),
+                .with_backend(grpc_backend::MkGrpcBackend::default()),
+            mk_backend: Default::default(),
             out_dir: Default::default(),
             filename: "volo_gen".into(),
             idls: Default::default(),
             config_file_path: "volo.yml".into(),
+            include_dirs: Default::default(),
+            bundled_includes: true,
+            service_features: false,
+            gen_mock: false,
+            serde: false,
+            file_descriptor_set: false,
         }
     }
+
+    /// Sets whether to resolve the imports of the bundled IDLs, which are the well-known types,
+    /// `google/api/{annotations,http,field_behavior}.proto`,
+    /// `google/rpc/{status,code,error_details}.proto` and the options of volo in
+    /// `volo/annotations.proto`, see [`headers`].
+    ///
+    /// The include dirs are searched before the bundled ones, so that the projects can keep their
+    /// own copies of the files.
+    ///
+    /// Defaults to true.
+    pub fn bundled_includes(mut self, enable: bool) -> Self {
+        self.bundled_includes = enable;
+        self
+    }
+
+    /// Sets whether to write the encoded `FileDescriptorSet` of the protos next to the generated
+    /// file, such as `volo_gen.descriptor.bin` of `volo_gen.rs`, and include it in the generated
+    /// file as `FILE_DESCRIPTOR_SET`, such as for the reflection of the servers, see
+    /// `volo_grpc::reflection`.
+    ///
+    /// Defaults to false.
+    pub fn file_descriptor_set(mut self, enable: bool) -> Self {
+        self.file_descriptor_set = enable;
+        self
+    }
 }
 
 impl<MkB, Parser> Builder<MkB, Parser> {
@@ -88,6 +173,43 @@ impl<MkB, Parser> Builder<MkB, Parser> {
         self
     }
 
+    /// Adds the dirs to resolve the imports of the IDLs from.
+    pub fn include_dirs(mut self, include_dirs: Vec<PathBuf>) -> Self {
+        self.include_dirs.extend(include_dirs);
+        self
+    }
+
+    /// Sets whether to gate the items generated for every service by the cargo feature of it, and
+    /// write the features next to the generated file, see [`features`].
+    ///
+    /// Defaults to false.
+    pub fn service_features(mut self, enable: bool) -> Self {
+        self.service_features = enable;
+        self
+    }
+
+    /// Sets whether to generate the `{Service}ClientTrait` of every service, implemented by both
+    /// its client and the `Mock{Service}Client` generated along, the responses of which are
+    /// programmed by the closures of the methods, such as `on_get_item`, for the unit tests of the
+    /// code calling the service.
+    ///
+    /// Defaults to false.
+    pub fn gen_mock(mut self, enable: bool) -> Self {
+        self.gen_mock = enable;
+        self
+    }
+
+    /// Sets whether to derive `serde::{Serialize, Deserialize}` on the generated messages, enums
+    /// and newtypes, and for protobuf also to implement `volo_grpc::json::{FromJson, IntoJson}`
+    /// on the request and the response enums of every service, for the JSON transcoding of
+    /// `volo_grpc::json`, which is enabled by its `json` feature, see [`serde_plugin`].
+    ///
+    /// Defaults to false.
+    pub fn serde(mut self, enable: bool) -> Self {
+        self.serde = enable;
+        self
+    }
+
     fn get_out_dir(&self) -> anyhow::Result<PathBuf> {
         self.out_dir
             .clone()
@@ -102,15 +224,10 @@ impl<MkB, Parser> Builder<MkB, Parser> {
 
 impl<MkB, P> Builder<MkB, P>
 where
-    MkB: MakeBackend,
+    MkB: MakeVoloBackend,
     P: Parser,
 {
-    pub fn include_dirs(mut self, include_dirs: Vec<PathBuf>) -> Self {
-        self.pilota_builder = self.pilota_builder.include_dirs(include_dirs);
-        self
-    }
-
-    pub fn write(self) -> anyhow::Result<()> {
+    pub fn write(mut self) -> anyhow::Result<()> {
         let out_dir = self.get_out_dir()?;
 
         if !out_dir.exists() {
@@ -121,8 +238,42 @@ where
             return Ok(());
         }
 
+        if self.bundled_includes {
+            let dir = well_known::write_includes(&out_dir.join("volo_includes"))?;
+            self.include_dirs.push(dir);
+        }
+        // before the parsers panic on the IDLs they fail to resolve
+        diagnostics::check(&self.idls, &self.include_dirs)?;
+        let descriptor_set = if self.file_descriptor_set {
+            let path = out_dir.join(self.filename.with_extension("descriptor.bin"));
+            descriptor_set::write(&self.idls, &self.include_dirs, &path)?;
+            Some(path)
+        } else {
+            None
+        };
+
+        let features = if self.service_features {
+            features::ServiceFeatures::enabled()
+        } else {
+            Default::default()
+        };
+        let mk_backend = self
+            .mk_backend
+            .read_idls(&self.idls)?
+            .service_features(features.clone())
+            .gen_mock(self.gen_mock)
+            .serde(self.serde);
+        if self.serde {
+            self.pilota_builder = self.pilota_builder.plugin(serde_plugin::SerdePlugin);
+        }
         self.pilota_builder
-            .compile(&self.idls, &out_dir.join(self.filename));
+            .with_backend(mk_backend)
+            .include_dirs(self.include_dirs)
+            .compile(&self.idls, &out_dir.join(&self.filename));
+        if let Some(path) = descriptor_set {
+            descriptor_set::include(&out_dir.join(&self.filename), &path)?;
+        }
+        features.write(&out_dir.join(self.filename.with_extension("features.toml")))?;
         Ok(())
     }
 }
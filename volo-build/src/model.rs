@@ -16,6 +16,22 @@ pub struct Entry {
     pub filename: PathBuf,
 
     pub idls: Vec<Idl>,
+    /// Whether to generate the runtime descriptors, only for thrift.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub descriptors: bool,
+    /// Whether to gate the generated services by their cargo features, see `volo_build::features`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub service_features: bool,
+    /// Whether to generate the client traits and the mock clients of the services.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub gen_mock: bool,
+    /// Whether to derive serde on the generated types, and for protobuf to generate the JSON
+    /// transcoding of the services.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub serde: bool,
+    /// Whether to write the encoded `FileDescriptorSet` of the protos, only for protobuf.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub file_descriptor_set: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
@@ -0,0 +1,29 @@
+//! The serde derives of the generated types.
+//!
+//! With [`Builder::serde`](crate::Builder::serde), the messages, the enums and the newtypes
+//! generated derive `serde::{Serialize, Deserialize}`, such as to transcode the messages of the
+//! gRPC services from and to JSON, see `volo_grpc::json`.
+//!
+//! The crate of the generated code depends on `serde` with the `derive` feature, and enables the
+//! `serde` features of the crates of the field types it uses, such as `bytes` for the bytes fields.
+
+use std::sync::Arc;
+
+use pilota_build::{plugin::walk_item, rir, Context, DefId, Plugin};
+
+/// Adds `#[derive(::serde::Serialize, ::serde::Deserialize)]` to the generated types.
+#[derive(Clone, Copy, Default)]
+pub struct SerdePlugin;
+
+impl Plugin for SerdePlugin {
+    fn on_item(&mut self, cx: &mut Context, def_id: DefId, item: Arc<rir::Item>) {
+        if let rir::Item::Message(_) | rir::Item::Enum(_) | rir::Item::NewType(_) = &*item {
+            cx.with_adjust(def_id, |adj| {
+                adj.add_attrs(&[syn::parse_quote!(
+                    #[derive(::serde::Serialize, ::serde::Deserialize)]
+                )])
+            });
+        }
+        walk_item(self, cx, def_id, item)
+    }
+}
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use itertools::Itertools;
 use pilota_build::{
     codegen::thrift::DecodeHelper, db::RirDatabase, rir, rir::Method, Context, DefId, ThriftBackend,
 };
@@ -7,12 +8,167 @@ use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
 use syn::Ident;
 
+use crate::features::ServiceFeatures;
+
 pub struct VoloThriftBackend {
     cx: Arc<Context>,
     inner: ThriftBackend,
+    descriptors: bool,
+    features: ServiceFeatures,
+    mock: bool,
 }
 
 impl VoloThriftBackend {
+    /// Returns the name of the item with the namespace of its IDL, such as `example.Item`.
+    fn full_name(&self, def_id: DefId, name: impl std::fmt::Display) -> String {
+        let file_id = self.cx.node(def_id).unwrap().file_id;
+        let package = self.cx.file(file_id).unwrap().package.iter().join(".");
+        if package.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", package, name)
+        }
+    }
+
+    fn codegen_ty_descriptor(&self, ty: &pilota_build::ty::Ty) -> TokenStream {
+        use pilota_build::ty::TyKind;
+
+        match &ty.kind {
+            TyKind::Bool => quote!(::volo_thrift::descriptor::TypeDescriptor::Bool),
+            TyKind::U8 | TyKind::I8 => quote!(::volo_thrift::descriptor::TypeDescriptor::I8),
+            TyKind::I16 => quote!(::volo_thrift::descriptor::TypeDescriptor::I16),
+            TyKind::I32 => quote!(::volo_thrift::descriptor::TypeDescriptor::I32),
+            TyKind::I64 => quote!(::volo_thrift::descriptor::TypeDescriptor::I64),
+            TyKind::F64 => quote!(::volo_thrift::descriptor::TypeDescriptor::Double),
+            TyKind::String => quote!(::volo_thrift::descriptor::TypeDescriptor::String),
+            TyKind::Bytes => quote!(::volo_thrift::descriptor::TypeDescriptor::Binary),
+            TyKind::Vec(elem) => {
+                let elem = self.codegen_ty_descriptor(elem);
+                quote!(::volo_thrift::descriptor::TypeDescriptor::List(&#elem))
+            }
+            TyKind::Set(elem) => {
+                let elem = self.codegen_ty_descriptor(elem);
+                quote!(::volo_thrift::descriptor::TypeDescriptor::Set(&#elem))
+            }
+            TyKind::Map(key, value) => {
+                let key = self.codegen_ty_descriptor(key);
+                let value = self.codegen_ty_descriptor(value);
+                quote!(::volo_thrift::descriptor::TypeDescriptor::Map(&#key, &#value))
+            }
+            TyKind::Arc(inner) => self.codegen_ty_descriptor(inner),
+            TyKind::Path(p) => {
+                let item = self.cx.expect_item(p.did);
+                let path: syn::Path = self.cx.cur_related_item_path(p.did);
+                match &*item {
+                    // typedefs are described as the types they alias
+                    rir::Item::NewType(t) => self.codegen_ty_descriptor(&t.ty),
+                    rir::Item::Enum(e) if e.repr.is_some() => quote! {
+                        ::volo_thrift::descriptor::TypeDescriptor::Enum(<#path as ::volo_thrift::descriptor::DescribeEnum>::enum_descriptor)
+                    },
+                    _ => quote! {
+                        ::volo_thrift::descriptor::TypeDescriptor::Struct(<#path as ::volo_thrift::descriptor::DescribeStruct>::struct_descriptor)
+                    },
+                }
+            }
+            _ => quote!(::volo_thrift::descriptor::TypeDescriptor::Void),
+        }
+    }
+
+    /// The fields of the union, or of the exceptions of a method.
+    fn codegen_variant_descriptors(&self, e: &rir::Enum) -> Vec<TokenStream> {
+        e.variants
+            .iter()
+            .filter_map(|v| {
+                let ty = v.fields.first()?;
+                let id = v.id.unwrap_or_default() as i16;
+                let name = &**v.name;
+                let ty = self.codegen_ty_descriptor(ty);
+                Some(quote! {
+                    ::volo_thrift::descriptor::FieldDescriptor {
+                        id: #id,
+                        name: #name,
+                        ty: #ty,
+                        required: false,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    fn codegen_struct_descriptor(
+        &self,
+        name: &Ident,
+        full_name: &str,
+        fields: Vec<TokenStream>,
+        is_union: bool,
+    ) -> TokenStream {
+        quote! {
+            impl ::volo_thrift::descriptor::DescribeStruct for #name {
+                fn struct_descriptor() -> &'static ::volo_thrift::descriptor::StructDescriptor {
+                    static DESCRIPTOR: ::volo_thrift::descriptor::StructDescriptor = ::volo_thrift::descriptor::StructDescriptor {
+                        name: #full_name,
+                        fields: &[#(#fields),*],
+                        is_union: #is_union,
+                    };
+                    &DESCRIPTOR
+                }
+            }
+        }
+    }
+
+    fn codegen_service_descriptor(
+        &self,
+        client_name: &Ident,
+        full_name: &str,
+        methods: &[Arc<Method>],
+    ) -> TokenStream {
+        let methods = methods.iter().map(|m| {
+            let name = &**m.name;
+            let oneway = m.oneway;
+            let args = m.args.iter().map(|a| {
+                let id = a.id as i16;
+                let name = &**a.name;
+                let ty = self.codegen_ty_descriptor(&a.ty);
+                quote! {
+                    ::volo_thrift::descriptor::FieldDescriptor {
+                        id: #id,
+                        name: #name,
+                        ty: #ty,
+                        required: false,
+                    }
+                }
+            });
+            let result = self.codegen_ty_descriptor(&m.ret);
+            let exceptions = m
+                .exceptions
+                .iter()
+                .flat_map(|p| match &*self.cx.expect_item(p.did) {
+                    rir::Item::Enum(e) => self.codegen_variant_descriptors(e),
+                    _ => panic!("expected exceptions enum"),
+                });
+            quote! {
+                ::volo_thrift::descriptor::MethodDescriptor {
+                    name: #name,
+                    oneway: #oneway,
+                    args: &[#(#args),*],
+                    result: #result,
+                    exceptions: &[#(#exceptions),*],
+                }
+            }
+        });
+        quote! {
+            impl ::volo_thrift::descriptor::DescribeService for #client_name {
+                fn service_descriptor() -> &'static ::volo_thrift::descriptor::ServiceDescriptor {
+                    static DESCRIPTOR: ::volo_thrift::descriptor::ServiceDescriptor = ::volo_thrift::descriptor::ServiceDescriptor {
+                        name: #full_name,
+                        methods: &[#(#methods),*],
+                    };
+                    &DESCRIPTOR
+                }
+            }
+        }
+    }
+
     fn codegen_service_anonymous_type(&self, stream: &mut TokenStream, def_id: DefId) {
         let service_name = format_ident!("{}", self.cx.symbol_name(def_id).to_upper_camel_case());
         let methods = self.cx.service_methods(def_id);
@@ -164,6 +320,130 @@ impl VoloThriftBackend {
         });
     }
 
+    /// The type of the exceptions of the method returned by the client.
+    fn method_exception_ty(&self, method: &Method) -> TokenStream {
+        if let Some(p) = &method.exceptions {
+            let path = self.cx.cur_related_item_path(p.did);
+            quote! { #path }
+        } else {
+            quote!(std::convert::Infallible)
+        }
+    }
+
+    /// Generates the client trait of the service implemented by its client, and the mock client
+    /// implementing it by the closures programmed for the methods, which are called with the args
+    /// of the methods.
+    fn codegen_mock(
+        &self,
+        service_name: &Ident,
+        client_name: &Ident,
+        methods: &[Arc<Method>],
+    ) -> TokenStream {
+        let trait_name = format_ident!("{}ClientTrait", service_name);
+        let mock_name = format_ident!("Mock{}", client_name);
+
+        let mut trait_methods = Vec::new();
+        let mut client_impls = Vec::new();
+        let mut mock_fields = Vec::new();
+        let mut mock_setters = Vec::new();
+        let mut mock_impls = Vec::new();
+        for m in methods {
+            let name = format_ident!("{}", m.name.to_snake_case());
+            let setter_name = format_ident!("on_{}", m.name.to_snake_case());
+            let resp_type = self.cx.codegen_item_ty(m.ret.kind.clone());
+            let exception = self.method_exception_ty(m);
+            let ret_ty = quote!(::std::result::Result<#resp_type, ::pilota::thrift::ResponseError<#exception>>);
+            let req_fields = m
+                .args
+                .iter()
+                .map(|a| {
+                    let name = format_ident!("{}", a.name);
+                    let ty = self.cx.codegen_item_ty(a.ty.kind.clone());
+                    quote!(#name: #ty)
+                })
+                .collect::<Vec<_>>();
+            let req_field_names = m
+                .args
+                .iter()
+                .map(|a| format_ident!("{}", a.name))
+                .collect::<Vec<_>>();
+            let args_name = self.method_args_path(service_name, m);
+            let setter_doc = format!("Responds to the calls of `{}` by `f`.", m.name);
+            let unmocked = format!("{} is not mocked", m.name);
+
+            trait_methods.push(quote! {
+                async fn #name(&mut self #(, #req_fields)*) -> #ret_ty;
+            });
+            client_impls.push(quote! {
+                async fn #name(&mut self #(, #req_fields)*) -> #ret_ty {
+                    #client_name::#name(self #(, #req_field_names)*).await
+                }
+            });
+            mock_fields.push(quote! {
+                #name: ::std::option::Option<::std::sync::Arc<dyn Fn(#args_name) -> #ret_ty + ::core::marker::Send + ::core::marker::Sync>>,
+            });
+            mock_setters.push(quote! {
+                #[doc = #setter_doc]
+                pub fn #setter_name(mut self, f: impl Fn(#args_name) -> #ret_ty + ::core::marker::Send + ::core::marker::Sync + 'static) -> Self {
+                    self.#name = ::std::option::Option::Some(::std::sync::Arc::new(f));
+                    self
+                }
+            });
+            mock_impls.push(quote! {
+                async fn #name(&mut self #(, #req_fields)*) -> #ret_ty {
+                    match &self.#name {
+                        ::std::option::Option::Some(f) => f(#args_name { #(#req_field_names),* }),
+                        ::std::option::Option::None => ::std::result::Result::Err(::pilota::thrift::new_application_error(
+                            ::pilota::thrift::ApplicationErrorKind::UnknownMethod,
+                            #unmocked,
+                        ).into()),
+                    }
+                }
+            });
+        }
+
+        let trait_doc = format!(
+            "The calls of [`{}`], implemented by [`{}`] for the tests.",
+            client_name, mock_name
+        );
+        let mock_doc = format!(
+            "The mock of [`{}`], responding to the calls by the closures of the methods, and with \
+             `UnknownMethod` to the methods not programmed.",
+            client_name
+        );
+        quote! {
+            #[doc = #trait_doc]
+            #[::async_trait::async_trait]
+            pub trait #trait_name: ::core::marker::Send {
+                #(#trait_methods)*
+            }
+
+            #[::async_trait::async_trait]
+            impl #trait_name for #client_name {
+                #(#client_impls)*
+            }
+
+            #[doc = #mock_doc]
+            #[derive(Clone, Default)]
+            pub struct #mock_name {
+                #(#mock_fields)*
+            }
+
+            impl #mock_name {
+                pub fn new() -> Self {
+                    ::std::default::Default::default()
+                }
+
+                #(#mock_setters)*
+            }
+
+            #[::async_trait::async_trait]
+            impl #trait_name for #mock_name {
+                #(#mock_impls)*
+            }
+        }
+    }
+
     fn method_ty_path(&self, service_name: &Ident, method: &Method, suffix: &str) -> TokenStream {
         match method.source {
             rir::MethodSource::Extend(def_id) => {
@@ -211,15 +491,40 @@ impl pilota_build::CodegenBackend for VoloThriftBackend {
         stream: &mut proc_macro2::TokenStream,
         s: &rir::Message,
     ) {
-        self.inner.codegen_struct_impl(def_id, stream, s)
+        self.inner.codegen_struct_impl(def_id, stream, s);
+        if self.descriptors {
+            let name = format_ident!("{}", s.name.to_upper_camel_case());
+            let fields = s
+                .fields
+                .iter()
+                .map(|f| {
+                    let id = f.id as i16;
+                    let field_name = &**f.name;
+                    let ty = self.codegen_ty_descriptor(&f.ty);
+                    let required = matches!(f.kind, rir::FieldKind::Required);
+                    quote! {
+                        ::volo_thrift::descriptor::FieldDescriptor {
+                            id: #id,
+                            name: #field_name,
+                            ty: #ty,
+                            required: #required,
+                        }
+                    }
+                })
+                .collect();
+            let full_name = self.full_name(def_id, &s.name);
+            stream.extend(self.codegen_struct_descriptor(&name, &full_name, fields, false));
+        }
     }
 
     fn codegen_service_impl(
         &self,
         def_id: DefId,
-        stream: &mut proc_macro2::TokenStream,
+        out: &mut proc_macro2::TokenStream,
         s: &rir::Service,
     ) {
+        // the items of the service, to be gated by its feature
+        let stream = &mut TokenStream::new();
         let service_name = format_ident!("{}", s.name.to_upper_camel_case());
         let server_name = format_ident!("{}Server", service_name);
         let client_name = format_ident!("{}Client", service_name);
@@ -229,6 +534,12 @@ impl pilota_build::CodegenBackend for VoloThriftBackend {
 
         let all_methods = self.cx.service_methods(def_id);
 
+        let full_name = self.full_name(def_id, &s.name);
+        let method_names = all_methods
+            .iter()
+            .map(|m| m.name.to_string())
+            .collect::<Vec<_>>();
+
         let client_methods = all_methods.iter().map(|m| {
             let name = format_ident!("{}", m.name.to_snake_case());
             let resp_type = self.cx.codegen_item_ty(m.ret.kind.clone());
@@ -254,12 +565,7 @@ impl pilota_build::CodegenBackend for VoloThriftBackend {
             };
             let req_field_names = m.args.iter().map(|a| format_ident!("{}", a.name));
             let anonymous_args_name = self.method_args_path(&service_name, m);
-            let exception = if let Some(p) = &m.exceptions {
-                let path = self.cx.cur_related_item_path(p.did);
-                quote!{ #path }
-            } else {
-                quote!(std::convert::Infallible)
-            };
+            let exception = self.method_exception_ty(m);
 
             let convert_exceptions = m.exceptions.iter().map(|p| {
                 self.cx.expect_item(p.did)
@@ -284,8 +590,12 @@ impl pilota_build::CodegenBackend for VoloThriftBackend {
                         Some(resp) => match resp {
                             #res_name::#enum_variant(#result_path::Ok(resp)) => Ok(resp),
                             #(#convert_exceptions,)*
+                            // the peer may send an exception not declared in our IDL
                             #[allow(unreachable_patterns)]
-                            _ => unreachable!(),
+                            _ => Err(::pilota::thrift::new_application_error(
+                                ::pilota::thrift::ApplicationErrorKind::MissingResult,
+                                format!("{} failed: unknown result", #method_name_str),
+                            ).into()),
                         }
                         #none
                     }
@@ -370,6 +680,12 @@ impl pilota_build::CodegenBackend for VoloThriftBackend {
                 #(#client_methods)*
             }
 
+            impl ::volo::service_info::ServiceInfo for #client_name {
+                const NAME: &'static str = #full_name;
+                const METHODS: &'static [&'static str] = &[#(#method_names),*];
+                const VERSION: &'static str = ::std::env!("CARGO_PKG_VERSION");
+            }
+
             impl ::volo_thrift::client::SetClient<#req_name, #res_name> for #client_name {
                 fn set_client(mut self, client: ::volo_thrift::client::Client<#req_name, #res_name>) -> #client_name {
                     #client_name {
@@ -398,6 +714,12 @@ impl pilota_build::CodegenBackend for VoloThriftBackend {
             }
 
 
+            impl<S, Req> ::volo::service_info::ServiceInfo for #server_name<S, Req> {
+                const NAME: &'static str = #full_name;
+                const METHODS: &'static [&'static str] = &[#(#method_names),*];
+                const VERSION: &'static str = ::std::env!("CARGO_PKG_VERSION");
+            }
+
             impl<S, Req> Clone for #server_name<S, Req> {
                 fn clone(&self) -> Self {
                     Self {
@@ -415,6 +737,13 @@ impl pilota_build::CodegenBackend for VoloThriftBackend {
                     };
                     ::volo_thrift::server::Server::new(service)
                 }
+
+                pub fn service(inner: S) -> Self {
+                    Self {
+                        inner: ::std::sync::Arc::new(inner),
+                        _marker: ::core::marker::PhantomData,
+                    }
+                }
             }
 
             impl<T> ::volo::service::Service<::volo_thrift::context::ServerContext, #req_name> for #server_name<T, #req_name> where T: #service_name + Send + Sync + 'static {
@@ -439,7 +768,21 @@ impl pilota_build::CodegenBackend for VoloThriftBackend {
 
             }
         });
+        if self.descriptors {
+            stream.extend(self.codegen_service_descriptor(&client_name, &full_name, &all_methods));
+            stream.extend(quote! {
+                impl<S, Req> ::volo_thrift::descriptor::DescribeService for #server_name<S, Req> {
+                    fn service_descriptor() -> &'static ::volo_thrift::descriptor::ServiceDescriptor {
+                        <#client_name as ::volo_thrift::descriptor::DescribeService>::service_descriptor()
+                    }
+                }
+            });
+        }
+        if self.mock {
+            stream.extend(self.codegen_mock(&service_name, &client_name, &all_methods));
+        }
         self.codegen_service_anonymous_type(stream, def_id);
+        out.extend(self.features.gate(&s.name, std::mem::take(stream)));
     }
 
     fn codegen_service_method(
@@ -456,7 +799,32 @@ impl pilota_build::CodegenBackend for VoloThriftBackend {
         stream: &mut proc_macro2::TokenStream,
         e: &rir::Enum,
     ) {
-        self.inner.codegen_enum_impl(def_id, stream, e)
+        self.inner.codegen_enum_impl(def_id, stream, e);
+        if self.descriptors {
+            let name = format_ident!("{}", e.name.to_upper_camel_case());
+            let full_name = self.full_name(def_id, &e.name);
+            if e.repr.is_some() {
+                let values = e.variants.iter().map(|v| {
+                    let variant_name = &**v.name;
+                    let value = v.discr.unwrap_or_default() as i32;
+                    quote!((#variant_name, #value))
+                });
+                stream.extend(quote! {
+                    impl ::volo_thrift::descriptor::DescribeEnum for #name {
+                        fn enum_descriptor() -> &'static ::volo_thrift::descriptor::EnumDescriptor {
+                            static DESCRIPTOR: ::volo_thrift::descriptor::EnumDescriptor = ::volo_thrift::descriptor::EnumDescriptor {
+                                name: #full_name,
+                                values: &[#(#values),*],
+                            };
+                            &DESCRIPTOR
+                        }
+                    }
+                });
+            } else {
+                let fields = self.codegen_variant_descriptors(e);
+                stream.extend(self.codegen_struct_descriptor(&name, &full_name, fields, true));
+            }
+        }
     }
 
     fn codegen_newtype_impl(
@@ -469,7 +837,33 @@ impl pilota_build::CodegenBackend for VoloThriftBackend {
     }
 }
 
-pub struct MkThriftBackend;
+#[derive(Default)]
+pub struct MkThriftBackend {
+    descriptors: bool,
+    features: ServiceFeatures,
+    mock: bool,
+}
+
+impl MkThriftBackend {
+    /// Sets whether to generate the runtime descriptors of the structs, enums and services, see
+    /// `volo_thrift::descriptor`.
+    pub fn descriptors(mut self, enable: bool) -> Self {
+        self.descriptors = enable;
+        self
+    }
+}
+
+impl crate::MakeVoloBackend for MkThriftBackend {
+    fn service_features(mut self, features: ServiceFeatures) -> Self {
+        self.features = features;
+        self
+    }
+
+    fn gen_mock(mut self, enable: bool) -> Self {
+        self.mock = enable;
+        self
+    }
+}
 
 impl pilota_build::MakeBackend for MkThriftBackend {
     type Target = VoloThriftBackend;
@@ -478,6 +872,85 @@ impl pilota_build::MakeBackend for MkThriftBackend {
         VoloThriftBackend {
             cx: context.clone(),
             inner: ThriftBackend::new(context),
+            descriptors: self.descriptors,
+            features: self.features,
+            mock: self.mock,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    const IDL: &str = r#"namespace rs volo.example
+
+struct Item {
+    1: required i64 id,
+}
+
+exception NotFound {
+    1: required string message,
+}
+
+service ItemService {
+    Item GetItem(1: i64 id) throws (1: NotFound not_found),
+}
+"#;
+
+    /// Generates the code of [`IDL`], checked to be valid, without the whitespaces and the trailing
+    /// commas.
+    fn generate(mock: bool) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let idl = dir.path().join("item.thrift");
+        std::fs::write(&idl, IDL).unwrap();
+        crate::Builder::thrift()
+            .add_service(&idl)
+            .gen_mock(mock)
+            .out_dir(dir.path())
+            .write()
+            .unwrap();
+        let source = std::fs::read_to_string(dir.path().join("volo_gen")).unwrap();
+        syn::parse_file(&source).unwrap();
+        source
+            .split_whitespace()
+            .collect::<String>()
+            .replace(",)", ")")
+            .replace(",>", ">")
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let source = generate(false);
+
+        // the declared exceptions are typed on both sides
+        assert!(source.contains("pubenumItemServiceGetItemException{"));
+        assert!(source.contains(
+            "ItemServiceResponse::GetItem(ItemServiceGetItemResult::NotFound(err))=>Err(::pilota::thrift::ResponseError::UserException(ItemServiceGetItemException::NotFound(err)))"
+        ));
+        assert!(source.contains(
+            "Err(::pilota::thrift::UserError::UserException(ItemServiceGetItemException::NotFound(err)))=>ItemServiceGetItemResult::NotFound(err)"
+        ));
+        // and the undeclared results fail the call instead of panicking
+        assert!(!source.contains("_=>unreachable!()"));
+        assert!(source.contains(
+            "_=>Err(::pilota::thrift::new_application_error(::pilota::thrift::ApplicationErrorKind::MissingResult"
+        ));
+    }
+
+    #[test]
+    fn test_mock() {
+        assert!(!generate(false).contains("ItemServiceClientTrait"));
+
+        let source = generate(true);
+        assert!(source.contains(
+            "pubtraitItemServiceClientTrait:::core::marker::Send{asyncfnget_item(&mutself,id:\
+             i64)->::std::result::Result<Item,\
+             ::pilota::thrift::ResponseError<ItemServiceGetItemException>>;}"
+        ));
+        assert!(source.contains("implItemServiceClientTraitforItemServiceClient{"));
+        assert!(source.contains("pubstructMockItemServiceClient{"));
+        assert!(source.contains("pubfnon_get_item(mutself,f:implFn(ItemServiceGetItemArgs)->"));
+        assert!(source.contains("implItemServiceClientTraitforMockItemServiceClient{"));
+        // the methods not programmed fail
+        assert!(source.contains("ApplicationErrorKind::UnknownMethod,\"GetItemisnotmocked\""));
+    }
+}
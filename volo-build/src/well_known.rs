@@ -0,0 +1,50 @@
+//! The protobuf IDLs imported by most of the services, bundled so that the imports such as
+//! `google/api/annotations.proto`, `google/rpc/status.proto` and the well-known types resolve
+//! without copying the files into every project.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+macro_rules! bundle {
+    ($($path:literal),* $(,)?) => {
+        &[$(($path, include_str!(concat!("../proto/", $path)))),*]
+    };
+}
+
+/// The bundled files by their import paths.
+const FILES: &[(&str, &str)] = bundle![
+    "google/api/annotations.proto",
+    "google/api/field_behavior.proto",
+    "google/api/http.proto",
+    "google/protobuf/any.proto",
+    "google/protobuf/descriptor.proto",
+    "google/protobuf/duration.proto",
+    "google/protobuf/empty.proto",
+    "google/protobuf/field_mask.proto",
+    "google/protobuf/struct.proto",
+    "google/protobuf/timestamp.proto",
+    "google/protobuf/wrappers.proto",
+    "google/rpc/code.proto",
+    "google/rpc/error_details.proto",
+    "google/rpc/status.proto",
+    "volo/annotations.proto",
+];
+
+/// Writes the bundled files under `dir` and returns it as the include dir.
+///
+/// The files not changed are not written again, so that their mtime doesn't trigger rebuilds.
+pub(crate) fn write_includes(dir: &Path) -> io::Result<PathBuf> {
+    for (path, content) in FILES {
+        let file = dir.join(path);
+        if matches!(std::fs::read_to_string(&file), Ok(old) if old == *content) {
+            continue;
+        }
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(file, content)?;
+    }
+    Ok(dir.to_path_buf())
+}
@@ -146,6 +146,11 @@ impl CliCommand for Add {
                         protocol: new_idl.protocol(),
                         filename: PathBuf::from(&self.filename),
                         idls: vec![new_idl],
+                        descriptors: false,
+                        service_features: false,
+                        gen_mock: false,
+                        serde: false,
+                        file_descriptor_set: false,
                     },
                 );
             }
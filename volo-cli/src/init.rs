@@ -329,6 +329,11 @@ impl CliCommand for Init {
                         protocol: idl.protocol(),
                         filename: PathBuf::from(DEFAULT_FILENAME),
                         idls: vec![idl],
+                        descriptors: false,
+                        service_features: false,
+                        gen_mock: false,
+                        serde: false,
+                        file_descriptor_set: false,
                     });
                 }
             }
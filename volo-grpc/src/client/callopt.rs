@@ -34,10 +34,24 @@
 //!     }
 //! }
 //! ```
+//!
+//! Or pass it to the `*_with_callopt` variant of the method, which only applies it to that call
+//! and leaves the client untouched, so a shared client can be used concurrently with different
+//! options:
+//!
+//! ```rust,ignore
+//! let resp = CLIENT
+//!     .clone()
+//!     .get_item_with_callopt(req, callopt)
+//!     .await;
+//! ```
+
+use std::time::Duration;
 
 use metainfo::TypeMap;
 use volo::net::Address;
 
+use super::RetryConfig;
 use crate::context::Config;
 
 #[derive(Debug, Default)]
@@ -49,6 +63,8 @@ pub struct CallOpt {
     pub config: Config,
     /// Sets the caller tags for the call.
     pub caller_tags: TypeMap,
+    /// Retries the call on the failures by the policy, such as for an idempotent method.
+    pub retry: Option<RetryConfig>,
 }
 
 impl CallOpt {
@@ -56,4 +72,17 @@ impl CallOpt {
     pub fn new() -> Self {
         Default::default()
     }
+
+    /// Sets the rpc timeout for the call, see
+    /// [`ClientBuilder::rpc_timeout`](super::ClientBuilder::rpc_timeout).
+    pub fn with_rpc_timeout(mut self, timeout: Duration) -> Self {
+        self.config.rpc_timeout = Some(timeout);
+        self
+    }
+
+    /// Retries the call by the policy, see [`RetryConfig`].
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = Some(retry);
+        self
+    }
 }
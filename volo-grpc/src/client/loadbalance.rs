@@ -0,0 +1,87 @@
+//! Picks the instance of every call from the service discovery.
+//!
+//! Unlike the load balance of thrift, the calls are not retried on the other instances here, the
+//! retries are left to the [`RetryService`](super::retry::RetryService) outside, which keeps the
+//! requests to be sent again.
+
+use std::{future::Future, sync::Arc};
+
+use motore::Service;
+use volo::{
+    context::Context,
+    discovery::Discover,
+    loadbalance::{watch_changes, LoadBalance, TriedAddresses},
+};
+
+use crate::{context::ClientContext, Request, Status};
+
+/// The [`Service`] picking the instance of every call by the load balance.
+#[derive(Clone)]
+pub struct LoadBalanceService<D, LB, S> {
+    discover: D,
+    load_balance: Arc<LB>,
+    inner: S,
+}
+
+impl<D, LB, S> LoadBalanceService<D, LB, S>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    pub(crate) fn new(discover: D, load_balance: LB, inner: S) -> Self {
+        let load_balance = Arc::new(load_balance);
+        watch_changes(&discover, load_balance.clone());
+        Self {
+            discover,
+            load_balance,
+            inner,
+        }
+    }
+}
+
+impl<T, D, LB, S> Service<ClientContext, Request<T>> for LoadBalanceService<D, LB, S>
+where
+    T: Send + 'static,
+    D: Discover,
+    LB: LoadBalance<D>,
+    S: Service<ClientContext, Request<T>, Error = Status> + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut ClientContext, req: Request<T>) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let callee = match &cx.rpc_info.callee {
+                // the address of the target or the callopt
+                Some(callee) if callee.address.is_none() => callee,
+                _ => return self.inner.call(cx, req).await,
+            };
+            let picker = self
+                .load_balance
+                .get_picker(callee, &self.discover)
+                .await
+                .map_err(|e| Status::unavailable(format!("discover instance error: {}", e)))?;
+            // the instances called by the previous attempts of the call are picked last
+            let mut picker = cx
+                .extensions()
+                .get::<TriedAddresses>()
+                .cloned()
+                .unwrap_or_default()
+                .untried_first(picker);
+            let address = picker.next().ok_or_else(|| {
+                Status::unavailable(format!("no instance of {}", callee.service_name))
+            })?;
+            if let Some(callee) = cx.rpc_info.callee_mut() {
+                callee.set_address(address);
+            }
+            // the picker is kept until the response, such as for counting the calls in flight
+            let resp = self.inner.call(cx, req).await;
+            drop(picker);
+            resp
+        }
+    }
+}
@@ -4,24 +4,56 @@
 //! Instead, they should use the `Builder` type in the generated code.
 //!
 //! For users need to specify some options at call time, they may use ['callopt'][callopt].
+//!
+//! For the calls to be retried on the failures, they may set a [`RetryConfig`] to the
+//! [`CallOpt`].
+//!
+//! For the server-streaming subscriptions to resume after the transient errors, they may use
+//! [`Resubscribe`].
+//!
+//! For the APM agents to trace the connections and the calls, they may set a
+//! [`StatsHandler`](stats::StatsHandler).
 
 mod callopt;
+mod loadbalance;
+mod resubscribe;
+mod retry;
+pub mod stats;
 
-use std::{marker::PhantomData, sync::Arc, time::Duration};
+use std::{future::Future, io, marker::PhantomData, sync::Arc, time::Duration};
 
 pub use callopt::CallOpt;
+use loadbalance::LoadBalanceService;
 use motore::{
     layer::{Identity, Layer, Stack},
     service::{BoxCloneService, Service},
 };
+pub use resubscribe::Resubscribe;
+pub use retry::RetryConfig;
+use retry::RetryService;
+use stats::StatsHandler;
+use tokio::sync::watch;
 use volo::{
-    context::{Endpoint, Role, RpcInfo},
-    net::Address,
+    context::{Context, Deadline, Endpoint, Role, RpcInfo},
+    discovery::{Discover, DummyDiscover},
+    loadbalance::{random::WeightedRandomBalance, LoadBalance},
+    net::{
+        conn::DynStream,
+        dial::{connector, Dialer},
+        Address,
+    },
+    profile::{Profile, Profiles},
 };
 
 use crate::{
+    codec::{
+        compression::{AcceptCompression, CompressionEncoding, SendCompression},
+        MessageSize,
+    },
     context::{ClientContext, Config},
-    transport::ClientTransport,
+    layer::interceptor::{AsyncInterceptor, AsyncInterceptorLayer, InterceptorLayer},
+    metadata::MetadataMap,
+    transport::{ClientTransport, Connectivity},
     Request, Response, Status,
 };
 
@@ -33,13 +65,24 @@ pub trait SetClient<T, U> {
 }
 
 /// [`ClientBuilder`] provides a [builder-like interface][builder] to construct a [`Client`].
-pub struct ClientBuilder<C, L, T, U> {
+pub struct ClientBuilder<C, L, T, U, LB = WeightedRandomBalance<()>, D = DummyDiscover> {
     http2_config: Http2Config,
     rpc_config: Config,
+    profiles: Option<watch::Receiver<Arc<Profiles>>>,
     callee_name: smol_str::SmolStr,
     caller_name: smol_str::SmolStr,
     // Maybe address use Arc avoid memory alloc.
     target: Option<Address>,
+    dialer: Option<Arc<dyn Dialer>>,
+    connectivity: Connectivity,
+    stats_handler: Option<Arc<dyn StatsHandler>>,
+    compression: SendCompression,
+    accept_compression: AcceptCompression,
+    message_size: MessageSize,
+    #[cfg(feature = "rustls")]
+    tls: Option<crate::tls::TlsConnector>,
+    load_balance: LB,
+    discover: D,
     layer: L,
     service_client: C,
     _marker: PhantomData<fn(T, U)>,
@@ -54,9 +97,20 @@ impl<C, T, U> ClientBuilder<C, Identity, T, U> {
         ClientBuilder {
             http2_config: Default::default(),
             rpc_config: Default::default(),
+            profiles: None,
             callee_name: service_name.into(),
             caller_name: "".into(),
             target: None,
+            dialer: None,
+            connectivity: Connectivity::new(),
+            stats_handler: None,
+            compression: Default::default(),
+            accept_compression: Default::default(),
+            message_size: Default::default(),
+            #[cfg(feature = "rustls")]
+            tls: None,
+            load_balance: WeightedRandomBalance::new(),
+            discover: DummyDiscover,
             layer: Identity::new(),
             service_client,
             _marker: PhantomData,
@@ -64,7 +118,7 @@ impl<C, T, U> ClientBuilder<C, Identity, T, U> {
     }
 }
 
-impl<C, L, T, U> ClientBuilder<C, L, T, U>
+impl<C, L, T, U, LB, D> ClientBuilder<C, L, T, U, LB, D>
 where
     C: SetClient<T, U>,
 {
@@ -147,7 +201,8 @@ where
     }
 
     /// Sets whether to retry requests that get disrupted before ever starting
-    /// to write.
+    /// to write, or that are refused by the server going away, such as during a
+    /// rolling restart, which are sent again on a fresh connection.
     ///
     /// Default is `true`.
     pub fn retry_canceled_requests(mut self, enabled: bool) -> Self {
@@ -181,6 +236,28 @@ where
         self
     }
 
+    /// Sets the timeout for the idle connections in the pool, which are closed when idle for
+    /// longer than it by the background checks of the pool.
+    ///
+    /// If `None`, the idle connections are kept until closed by the servers.
+    ///
+    /// Default is 90 seconds.
+    pub fn pool_idle_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.http2_config.pool_idle_timeout = timeout.into();
+        self
+    }
+
+    /// Sets the max number of idle connections to each endpoint kept in the pool.
+    ///
+    /// The HTTP/2 connections are shared by the calls, so this mostly bounds the connections kept
+    /// when accepting HTTP/1, see [`accept_http1`](Self::accept_http1).
+    ///
+    /// Default is unlimited.
+    pub fn pool_max_idle_per_host(mut self, max_idle: usize) -> Self {
+        self.http2_config.pool_max_idle_per_host = max_idle;
+        self
+    }
+
     /// Sets the timeout for connecting to a URL.
     ///
     /// Default is no timeout.
@@ -205,6 +282,50 @@ where
         self
     }
 
+    /// Sets the timeout of the calls, from the call until the response is received, which is also
+    /// sent to the server as the `grpc-timeout`, so that the server gives up on the call as soon
+    /// as the client does. The calls timed out fail with `DEADLINE_EXCEEDED`.
+    ///
+    /// Default is no timeout.
+    pub fn rpc_timeout(mut self, timeout: Duration) -> Self {
+        self.rpc_config.rpc_timeout = Some(timeout);
+        self
+    }
+
+    /// Applies the timeouts of the profile of the callee in the profiles, such as loaded from a
+    /// config file, the read write timeout sets both the read timeout and the write timeout.
+    ///
+    /// The fields unset in the profile keep the settings made before, and the settings made after
+    /// override the profile. The retries and the pool sizes don't apply to the gRPC clients.
+    pub fn profiles(mut self, profiles: &Profiles) -> Self {
+        let profile = profiles.get(&self.callee_name);
+        if let Some(timeout) = profile.rpc_timeout() {
+            self.rpc_config.rpc_timeout = timeout;
+        }
+        if let Some(timeout) = profile.connect_timeout() {
+            self.rpc_config.connect_timeout = Some(timeout);
+        }
+        if let Some(timeout) = profile.read_write_timeout() {
+            self.rpc_config.read_timeout = Some(timeout);
+            self.rpc_config.write_timeout = Some(timeout);
+        }
+        self
+    }
+
+    /// Applies the profile of the callee in the profiles from the receiver to every call, such as
+    /// reloaded by `volo::profile::FileWatcher`, so that the changes apply to the calls made after
+    /// them without rebuilding the client.
+    ///
+    /// The rpc timeout of the profile overrides the one of the client, and is overridden by the
+    /// one of the [`CallOpt`]. The retry count retries the calls without a [`RetryConfig`] of
+    /// their own by the default policy of it. The load balance and the cluster are switched by
+    /// the `ProfileBalance` and the `ProfileDiscover` of volo, see `volo::profile`. The rest of
+    /// the profile is only applied by [`profiles`](Self::profiles) when the client is built.
+    pub fn watch_profiles(mut self, profiles: watch::Receiver<Arc<Profiles>>) -> Self {
+        self.profiles = Some(profiles);
+        self
+    }
+
     /// Sets the caller name for the client.
     ///
     /// Default is the empty string.
@@ -221,6 +342,163 @@ where
         self
     }
 
+    /// Sets the load balance picking the instance of every call from the
+    /// [`discover`](Self::discover), such as the ones in [`volo::loadbalance`].
+    ///
+    /// The calls to the [`target`](Self::target) or the address of the [`CallOpt`] skip it. The
+    /// load balance is inside all the layers, so that the layers retrying the calls pick the
+    /// instances again.
+    ///
+    /// Default is [`WeightedRandomBalance`].
+    pub fn load_balance<NLB>(self, load_balance: NLB) -> ClientBuilder<C, L, T, U, NLB, D> {
+        ClientBuilder {
+            http2_config: self.http2_config,
+            rpc_config: self.rpc_config,
+            profiles: self.profiles,
+            callee_name: self.callee_name,
+            caller_name: self.caller_name,
+            target: self.target,
+            dialer: self.dialer,
+            connectivity: self.connectivity,
+            stats_handler: self.stats_handler,
+            compression: self.compression,
+            accept_compression: self.accept_compression,
+            message_size: self.message_size,
+            #[cfg(feature = "rustls")]
+            tls: self.tls,
+            load_balance,
+            discover: self.discover,
+            layer: self.layer,
+            service_client: self.service_client,
+            _marker: self._marker,
+        }
+    }
+
+    /// Sets the service discovery of the instances of the callee, which are watched by the load
+    /// balance.
+    ///
+    /// Default is [`DummyDiscover`], which discovers nothing.
+    pub fn discover<ND>(self, discover: ND) -> ClientBuilder<C, L, T, U, LB, ND> {
+        ClientBuilder {
+            http2_config: self.http2_config,
+            rpc_config: self.rpc_config,
+            profiles: self.profiles,
+            callee_name: self.callee_name,
+            caller_name: self.caller_name,
+            target: self.target,
+            dialer: self.dialer,
+            connectivity: self.connectivity,
+            stats_handler: self.stats_handler,
+            compression: self.compression,
+            accept_compression: self.accept_compression,
+            message_size: self.message_size,
+            #[cfg(feature = "rustls")]
+            tls: self.tls,
+            load_balance: self.load_balance,
+            discover,
+            layer: self.layer,
+            service_client: self.service_client,
+            _marker: self._marker,
+        }
+    }
+
+    /// Makes the connections to the server by the dialer, such as through a tunnel or a
+    /// [`Proxy`](volo::net::proxy::Proxy), instead of dialing the address.
+    ///
+    /// The tcp options and the connect timeout are ignored when the dialer is set.
+    pub fn dialer(mut self, dialer: impl Dialer) -> Self {
+        self.dialer = Some(Arc::new(dialer));
+        self
+    }
+
+    /// Makes the connections to the server by `f`, which returns a stream of any
+    /// `AsyncRead + AsyncWrite` to the address, such as of a custom tunnel, see
+    /// [`connector`](volo::net::dial::connector).
+    ///
+    /// It's a shorthand of the [`dialer`](Self::dialer) of the streams.
+    pub fn connector<F, Fut, IO>(self, f: F) -> Self
+    where
+        F: Fn(Address) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<IO>> + Send + 'static,
+        IO: DynStream,
+    {
+        self.dialer(connector(f))
+    }
+
+    /// Establishes TLS sessions over the connections to the server, see [`tls`](crate::tls).
+    ///
+    /// The connections made by the [`dialer`](Self::dialer) must be tcp ones then.
+    ///
+    /// Default is None.
+    #[cfg(feature = "rustls")]
+    pub fn tls_config(mut self, tls: crate::tls::TlsConnector) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Tracks the connectivity state of the client in the handle, such as to wait for the server
+    /// to be ready at startup.
+    pub fn connectivity(mut self, connectivity: &Connectivity) -> Self {
+        self.connectivity = connectivity.clone();
+        self
+    }
+
+    /// Sets the handler of the stats of the connections and the calls, see [`stats`].
+    ///
+    /// Default is None.
+    pub fn stats_handler(mut self, handler: impl StatsHandler) -> Self {
+        self.stats_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Sets the encoding to compress the messages of the requests by, which the server must
+    /// accept, see [`compression`](crate::codec::compression).
+    ///
+    /// Default is None.
+    pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+        self.compression.encoding = Some(encoding);
+        self
+    }
+
+    /// Sets whether to compress each message of the requests by its encoded bytes, when
+    /// [`send_compressed`](Self::send_compressed), such as to skip the small ones.
+    ///
+    /// Default is to compress all of them.
+    pub fn should_compress(mut self, f: impl Fn(&[u8]) -> bool + Send + Sync + 'static) -> Self {
+        self.compression.should_compress = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets the encodings of the compressed messages of the responses accepted, which are
+    /// advertised to the server by `grpc-accept-encoding`.
+    ///
+    /// Default is all the encodings supported, see [`CompressionEncoding::SUPPORTED`].
+    pub fn accept_compressed(
+        mut self,
+        encodings: impl IntoIterator<Item = CompressionEncoding>,
+    ) -> Self {
+        self.accept_compression = AcceptCompression::new(encodings);
+        self
+    }
+
+    /// Sets the max size of each message of the responses, the calls receiving a larger one fail
+    /// with `RESOURCE_EXHAUSTED`.
+    ///
+    /// Default is `4MB`.
+    pub fn max_recv_message_size(mut self, size: usize) -> Self {
+        self.message_size.max_recv = size;
+        self
+    }
+
+    /// Sets the max size of each message of the requests, the calls sending a larger one fail
+    /// with `RESOURCE_EXHAUSTED` without sending the message.
+    ///
+    /// Default is no limit (`usize::MAX`).
+    pub fn max_send_message_size(mut self, size: usize) -> Self {
+        self.message_size.max_send = size;
+        self
+    }
+
     /// Adds a new layer to the client.
     ///
     /// # Order
@@ -230,35 +508,91 @@ where
     /// The current order is: foo -> bar (the request will come to foo first, and then bar).
     ///
     /// After we call `.layer(baz)`, we will get: foo -> bar -> baz.
-    pub fn layer<O>(self, layer: O) -> ClientBuilder<C, Stack<O, L>, T, U> {
+    pub fn layer<O>(self, layer: O) -> ClientBuilder<C, Stack<O, L>, T, U, LB, D> {
         ClientBuilder {
             http2_config: self.http2_config,
             rpc_config: self.rpc_config,
+            profiles: self.profiles,
             callee_name: self.callee_name,
             caller_name: self.caller_name,
             target: self.target,
+            dialer: self.dialer,
+            connectivity: self.connectivity,
+            stats_handler: self.stats_handler,
+            compression: self.compression,
+            accept_compression: self.accept_compression,
+            message_size: self.message_size,
+            #[cfg(feature = "rustls")]
+            tls: self.tls,
+            load_balance: self.load_balance,
+            discover: self.discover,
             layer: Stack::new(layer, self.layer),
             service_client: self.service_client,
             _marker: self._marker,
         }
     }
+
+    /// Intercepts the metadata and the extensions of the requests by `f` as a new layer, see
+    /// [`interceptor`](crate::layer::interceptor).
+    pub fn intercept<F>(self, f: F) -> ClientBuilder<C, Stack<InterceptorLayer<F>, L>, T, U, LB, D>
+    where
+        F: Fn(&mut MetadataMap, &mut http::Extensions) -> Result<(), Status>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.layer(InterceptorLayer::new(f))
+    }
+
+    /// Intercepts the requests by the asynchronous interceptor as a new layer, see
+    /// [`interceptor`](crate::layer::interceptor).
+    pub fn intercept_async<I>(
+        self,
+        interceptor: I,
+    ) -> ClientBuilder<C, Stack<AsyncInterceptorLayer<I>, L>, T, U, LB, D>
+    where
+        I: AsyncInterceptor,
+    {
+        self.layer(AsyncInterceptorLayer::new(interceptor))
+    }
 }
 
-impl<T, U, C, L> ClientBuilder<C, L, T, U>
+impl<T, U, C, L, LB, D> ClientBuilder<C, L, T, U, LB, D>
 where
     C: SetClient<T, U>,
-    T: 'static,
+    T: Send + 'static,
+    LB: LoadBalance<D>,
+    D: Discover,
 {
     /// Builds a new [`Client`].
     pub fn build(self) -> C
     where
-        L: Layer<ClientTransport<U>>,
+        L: Layer<RetryService<LoadBalanceService<D, LB, ClientTransport<U>>>>,
         L::Service: Service<ClientContext, Request<T>, Response = Response<U>, Error = Status>
             + Clone
             + Send
             + 'static,
     {
-        let transport = ClientTransport::new(&self.http2_config, &self.rpc_config);
+        #[cfg(feature = "rustls")]
+        let dialer = match self.tls {
+            Some(tls) => Some(tls_dialer(self.dialer, tls, &self.rpc_config)),
+            None => self.dialer,
+        };
+        #[cfg(not(feature = "rustls"))]
+        let dialer = self.dialer;
+        let transport = ClientTransport::with_connectivity(
+            &self.http2_config,
+            &self.rpc_config,
+            dialer,
+            self.connectivity,
+            self.stats_handler,
+        )
+        .send_compression(self.compression)
+        .accept_compression(self.accept_compression)
+        .message_size(self.message_size);
+        let transport = LoadBalanceService::new(self.discover, self.load_balance, transport);
+        // the retries pick the instances again
+        let transport = RetryService::new(transport);
         let transport = self.layer.layer(transport);
         let transport = BoxCloneService::new(transport);
 
@@ -267,6 +601,7 @@ where
                 callee_name: self.callee_name,
                 caller_name: self.caller_name,
                 rpc_config: self.rpc_config,
+                profiles: self.profiles,
                 target: self.target,
             }),
             callopt: None,
@@ -275,11 +610,32 @@ where
     }
 }
 
+/// Makes the connections by the dialer, or else by tcp, and establishes TLS sessions over them.
+#[cfg(feature = "rustls")]
+fn tls_dialer(
+    dialer: Option<Arc<dyn Dialer>>,
+    tls: crate::tls::TlsConnector,
+    rpc_config: &Config,
+) -> Arc<dyn Dialer> {
+    match dialer {
+        Some(dialer) => Arc::new(move |addr| {
+            let (dialer, tls) = (dialer.clone(), tls.clone());
+            async move { tls.connect(dialer.dial(addr).await?).await }
+        }),
+        None => {
+            let config =
+                volo::net::dial::Config::new(rpc_config.connect_timeout, rpc_config.read_timeout);
+            Arc::new(volo::net::dial::MakeConnection::new(Some(config)).with_tls(tls))
+        }
+    }
+}
+
 /// A struct indicating the rpc configuration of the client.
 struct ClientInner {
     callee_name: smol_str::SmolStr,
     caller_name: smol_str::SmolStr,
     rpc_config: Config,
+    profiles: Option<watch::Receiver<Arc<Profiles>>>,
     target: Option<Address>,
 }
 
@@ -312,11 +668,47 @@ impl<T, U> Clone for Client<T, U> {
 impl<T, U> Client<T, U> {
     pub async fn call(
         &mut self,
-        path: &'static str,
+        path: impl Into<smol_str::SmolStr>,
         req: Request<T>,
     ) -> Result<Response<U>, Status> {
-        let mut cx = ClientContext::new(self.make_rpc_info(path));
-        self.transport.call(&mut cx, req).await
+        let callopt = self.callopt.take();
+        self.call_with_callopt(path, req, callopt).await
+    }
+
+    /// Calls the service with the given [`CallOpt`], which only applies to this call.
+    ///
+    /// Unlike [`Client::set_callopt`], this does not touch the state of the client, so the
+    /// `CallOpt` set on the client (if any) is left for the next call.
+    pub async fn call_with_callopt(
+        &mut self,
+        path: impl Into<smol_str::SmolStr>,
+        req: Request<T>,
+        mut callopt: Option<CallOpt>,
+    ) -> Result<Response<U>, Status> {
+        let profile = self
+            .inner
+            .profiles
+            .as_ref()
+            .map(|profiles| profiles.borrow().get(&self.inner.callee_name));
+        let retry = callopt.as_mut().and_then(|co| co.retry.take()).or_else(|| {
+            let count = profile.as_ref()?.retry_count.filter(|count| *count > 0)?;
+            Some(RetryConfig::new().max_attempts(count + 1))
+        });
+        let mut cx = ClientContext::new(self.make_rpc_info(path.into(), callopt, profile.as_ref()));
+        if let Some(retry) = retry {
+            cx.extensions_mut().insert(retry);
+        }
+        match cx.rpc_info.config().and_then(|config| config.rpc_timeout) {
+            Some(timeout) => {
+                // sent to the server by the transport, and bounds the retries as well
+                cx.extensions_mut()
+                    .insert(Deadline(std::time::Instant::now() + timeout));
+                tokio::time::timeout(timeout, self.transport.call(&mut cx, req))
+                    .await
+                    .unwrap_or_else(|_| Err(Status::deadline_exceeded("rpc timeout")))
+            }
+            None => self.transport.call(&mut cx, req).await,
+        }
     }
 
     #[inline]
@@ -324,14 +716,22 @@ impl<T, U> Client<T, U> {
         self.callopt = Some(callopt);
     }
 
-    fn make_rpc_info(&mut self, method: &'static str) -> RpcInfo<Config> {
+    fn make_rpc_info(
+        &self,
+        method: smol_str::SmolStr,
+        callopt: Option<CallOpt>,
+        profile: Option<&Profile>,
+    ) -> RpcInfo<Config> {
         let mut caller = Endpoint::new(self.inner.caller_name.clone());
         let mut callee = Endpoint::new(self.inner.callee_name.clone());
         if let Some(target) = &self.inner.target {
             callee.set_address(target.clone());
         }
         let mut rpc_config = self.inner.rpc_config;
-        if let Some(co) = self.callopt.take() {
+        if let Some(timeout) = profile.and_then(Profile::rpc_timeout) {
+            rpc_config.rpc_timeout = timeout;
+        }
+        if let Some(co) = callopt {
             caller.tags.extend(co.caller_tags);
             callee.tags.extend(co.callee_tags);
             if let Some(addr) = co.address {
@@ -339,7 +739,7 @@ impl<T, U> Client<T, U> {
             }
             rpc_config.merge(co.config);
         }
-        RpcInfo::new(Role::Client, method.into(), caller, callee, rpc_config)
+        RpcInfo::new(Role::Client, method, caller, callee, rpc_config)
     }
 }
 
@@ -348,6 +748,7 @@ const DEFAULT_CONN_WINDOW_SIZE: u32 = 1024 * 1024 * 5; // 5MB
 const DEFAULT_MAX_FRAME_SIZE: u32 = 1024 * 16; // 16KB
 const DEFAULT_KEEPALIVE_TIMEOUT_SECS: Duration = Duration::from_secs(20); // 20s
 const DEFAULT_MAX_CONCURRENT_RESET_STREAMS: usize = 10;
+const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
 
 /// Configuration for the underlying h2 connection.
 #[derive(Debug, Clone, Copy)]
@@ -364,6 +765,8 @@ pub struct Http2Config {
     pub(crate) accept_http1: bool,
     pub(crate) tcp_keepalive: Option<Duration>,
     pub(crate) tcp_nodelay: bool,
+    pub(crate) pool_idle_timeout: Option<Duration>,
+    pub(crate) pool_max_idle_per_host: usize,
 }
 
 impl Default for Http2Config {
@@ -381,6 +784,95 @@ impl Default for Http2Config {
             accept_http1: false,
             tcp_keepalive: None,
             tcp_nodelay: true,
+            pool_idle_timeout: Some(DEFAULT_POOL_IDLE_TIMEOUT),
+            pool_max_idle_per_host: usize::MAX,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use motore::service::service_fn;
+    use volo::net::incoming::Incoming;
+
+    use super::*;
+    use crate::{
+        context::ServerContext,
+        proxy::{RawBody, RawFrame},
+        server::Server,
+        Code,
+    };
+
+    #[derive(Clone, Default)]
+    struct Raw(Option<Client<RawBody, RawBody>>);
+
+    impl SetClient<RawBody, RawBody> for Raw {
+        fn set_client(self, client: Client<RawBody, RawBody>) -> Self {
+            Self(Some(client))
+        }
+    }
+
+    async fn echo(
+        cx: &mut ServerContext,
+        req: Request<RawBody>,
+    ) -> Result<Response<RawBody>, Status> {
+        if cx.rpc_info.method().map(|m| m.as_str()) == Some("/test.Echo/Slow") {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        Ok(Response::new(req.into_inner()))
+    }
+
+    fn request() -> Request<RawBody> {
+        Request::new(RawBody::from_frames(futures::stream::once(async {
+            Ok(RawFrame {
+                compressed: false,
+                data: bytes::Bytes::from_static(b"hello"),
+            })
+        })))
+    }
+
+    #[tokio::test]
+    async fn test_call_with_callopt() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::new(service_fn(echo)).run(Incoming::from(listener)));
+
+        // no target, so the calls only reach the server by the address of the callopt
+        let mut client = ClientBuilder::new(Raw::default(), "echo")
+            .build()
+            .0
+            .unwrap();
+        client.set_callopt(CallOpt {
+            address: Some(addr.into()),
+            ..Default::default()
+        });
+
+        // the timeout of the callopt applies to the call
+        let callopt = CallOpt {
+            address: Some(addr.into()),
+            ..Default::default()
         }
+        .with_rpc_timeout(Duration::from_millis(50));
+        let start = std::time::Instant::now();
+        let status = client
+            .call_with_callopt("/test.Echo/Slow", request(), Some(callopt))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+        assert!(start.elapsed() < Duration::from_millis(900));
+
+        // and the callopt set on the client is left for the next call
+        let resp = client.call("/test.Echo/Say", request()).await.unwrap();
+        let frames: Vec<_> = resp.into_inner().into_frames().collect().await;
+        assert_eq!(&frames[0].as_ref().unwrap().data[..], b"hello");
+        // which takes it
+        let status = client
+            .call("/test.Echo/Say", request())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), Code::Unavailable);
     }
 }
@@ -0,0 +1,198 @@
+//! Keeps a server-streaming subscription alive, such as of the watch APIs.
+//!
+//! [`Resubscribe`] calls the server-streaming method again when the stream breaks by a transient
+//! error, with the exponential backoff between the attempts, and passes the cursor taken from the
+//! last received message to the new call, so that the subscription resumes where it was instead
+//! of starting over. The messages of all the calls are yielded as one stream, which ends when a
+//! call ends normally, or yields the error and ends when the error isn't retryable or the retries
+//! run out.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::client::Resubscribe;
+//!
+//! let mut events = Resubscribe::new().subscribe(
+//!     0,
+//!     move |revision| {
+//!         let mut client = CLIENT.clone();
+//!         async move { client.watch(WatchRequest { revision }).await }
+//!     },
+//!     |event: &WatchEvent| Some(event.revision + 1),
+//! );
+//! while let Some(event) = events.next().await {
+//!     ...
+//! }
+//! ```
+
+use std::{fmt, future::Future, sync::Arc, time::Duration};
+
+use futures::{Stream, StreamExt};
+use volo::layer::retry::Backoff;
+
+use crate::{BoxStream, Code, Response, Status};
+
+const DEFAULT_BACKOFF_BASE: Duration = Duration::from_millis(100);
+const DEFAULT_BACKOFF_MAX: Duration = Duration::from_secs(10);
+
+type Retryable = Arc<dyn Fn(&Status) -> bool + Send + Sync>;
+
+/// Re-establishes the server-streaming calls broken by the transient errors.
+#[derive(Clone)]
+pub struct Resubscribe {
+    backoff: Backoff,
+    max_retries: Option<usize>,
+    retryable: Retryable,
+}
+
+impl Default for Resubscribe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Resubscribe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resubscribe")
+            .field("backoff", &self.backoff)
+            .field("max_retries", &self.max_retries)
+            .finish()
+    }
+}
+
+impl Resubscribe {
+    /// Creates a [`Resubscribe`] retrying forever on `UNAVAILABLE`, `ABORTED` and
+    /// `DEADLINE_EXCEEDED`, with the backoff from 100ms up to 10s.
+    pub fn new() -> Self {
+        Self {
+            backoff: Backoff::new(DEFAULT_BACKOFF_BASE, DEFAULT_BACKOFF_MAX),
+            max_retries: None,
+            retryable: Arc::new(|status| {
+                matches!(
+                    status.code(),
+                    Code::Unavailable | Code::Aborted | Code::DeadlineExceeded
+                )
+            }),
+        }
+    }
+
+    /// Sets the backoff between the attempts.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Sets the max retries in a row, which are reset once a message is received.
+    ///
+    /// Defaults to no limit.
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Sets which errors break the stream transiently and are retried.
+    pub fn retryable(mut self, f: impl Fn(&Status) -> bool + Send + Sync + 'static) -> Self {
+        self.retryable = Arc::new(f);
+        self
+    }
+
+    /// Subscribes by `call` from `cursor`, and resubscribes from the cursor returned by
+    /// `next_cursor` for the last message, or the previous one if it returns `None`.
+    pub fn subscribe<C, T, S, F, Fut, K>(
+        self,
+        cursor: C,
+        mut call: F,
+        mut next_cursor: K,
+    ) -> BoxStream<'static, Result<T, Status>>
+    where
+        C: Clone + Send + 'static,
+        T: Send + 'static,
+        S: Stream<Item = Result<T, Status>> + Send + Unpin + 'static,
+        F: FnMut(C) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Response<S>, Status>> + Send,
+        K: FnMut(&T) -> Option<C> + Send + 'static,
+    {
+        Box::pin(async_stream::stream! {
+            let mut cursor = cursor;
+            let mut retries = 0;
+            loop {
+                let resp = call(cursor.clone());
+                let status = match resp.await {
+                    Ok(resp) => {
+                        let mut stream = resp.into_inner();
+                        loop {
+                            match stream.next().await {
+                                Some(Ok(msg)) => {
+                                    retries = 0;
+                                    if let Some(next) = next_cursor(&msg) {
+                                        cursor = next;
+                                    }
+                                    yield Ok(msg);
+                                }
+                                Some(Err(status)) => break status,
+                                None => return,
+                            }
+                        }
+                    }
+                    Err(status) => status,
+                };
+                retries += 1;
+                let exhausted = self.max_retries.map_or(false, |max| retries > max);
+                if exhausted || !(self.retryable)(&status) {
+                    yield Err(status);
+                    return;
+                }
+                tracing::warn!(
+                    "[VOLO] subscription broken, resubscribing, retries: {}, status: {:?}",
+                    retries,
+                    status
+                );
+                tokio::time::sleep(self.backoff.delay(retries)).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use futures::stream;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resubscribe() {
+        let cursors = Arc::new(Mutex::new(Vec::new()));
+        let calls = cursors.clone();
+        let events = Resubscribe::new()
+            .backoff(Backoff::new(Duration::ZERO, Duration::ZERO))
+            .max_retries(1)
+            .subscribe(
+                0,
+                move |cursor: u32| {
+                    let n = {
+                        let mut calls = calls.lock().unwrap();
+                        calls.push(cursor);
+                        calls.len()
+                    };
+                    let items = match n {
+                        1 => vec![Ok(1), Ok(2), Err(Status::unavailable("reset"))],
+                        // the retries are reset by the message
+                        2 => vec![Ok(3), Err(Status::unavailable("reset"))],
+                        _ => vec![Err(Status::unavailable("refused"))],
+                    };
+                    futures::future::ready(Ok(Response::new(stream::iter(items))))
+                },
+                |event: &u32| Some(*event),
+            );
+        let events: Vec<_> = events.collect().await;
+        assert_eq!(events.len(), 4);
+        assert!(events[..3]
+            .iter()
+            .map(|e| e.as_ref().unwrap())
+            .eq(&[1, 2, 3]));
+        assert_eq!(events[3].as_ref().unwrap_err().code(), Code::Unavailable);
+        assert_eq!(*cursors.lock().unwrap(), vec![0, 2, 3]);
+    }
+}
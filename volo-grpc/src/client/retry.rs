@@ -0,0 +1,186 @@
+//! Retries the failed calls by the [`RetryConfig`] of their [`CallOpt`](super::CallOpt).
+//!
+//! The retry service is right outside the load balance, so each retry picks an instance again,
+//! the ones tried by the previous attempts last, unless the call is to a fixed address. The
+//! messages of the request are kept while being sent to be sent again, up to 64KB, over which the
+//! call isn't retried, and only the errors before the response are retried, not the ones of the
+//! streaming responses.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::{client::{CallOpt, RetryConfig}, Code};
+//!
+//! let retry = RetryConfig::new()
+//!     .max_attempts(3)
+//!     .retryable_codes([Code::Unavailable, Code::ResourceExhausted])
+//!     .attempt_timeout(Duration::from_millis(200));
+//! let resp = CLIENT
+//!     .clone()
+//!     .get_item_with_callopt(req, CallOpt::new().with_retry(retry))
+//!     .await;
+//! ```
+
+use std::{future::Future, time::Duration};
+
+use motore::Service;
+use volo::{
+    context::Context,
+    layer::retry::{self, Backoff, RetryPolicy},
+};
+
+use crate::{
+    context::ClientContext,
+    message::SendEntryMessage,
+    metadata::MetadataMap,
+    transport::replay::{AttemptBody, ReplayBody},
+    Code, Request, Response, Status,
+};
+
+const DEFAULT_MAX_ATTEMPTS: usize = 3;
+const DEFAULT_BACKOFF_BASE: Duration = Duration::from_millis(20);
+const DEFAULT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// The retry policy of a call.
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    max_attempts: usize,
+    retryable_codes: Vec<Code>,
+    backoff: Backoff,
+    attempt_timeout: Option<Duration>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RetryConfig {
+    /// Creates a [`RetryConfig`] of 3 attempts on `UNAVAILABLE`, with the backoff from 20ms up to
+    /// 1s.
+    pub fn new() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            retryable_codes: vec![Code::Unavailable],
+            backoff: Backoff::new(DEFAULT_BACKOFF_BASE, DEFAULT_BACKOFF_MAX),
+            attempt_timeout: None,
+        }
+    }
+
+    /// Sets the max attempts of the call, including the first one.
+    ///
+    /// Default is `3`.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sets the codes of the statuses which are retried.
+    ///
+    /// Default is `UNAVAILABLE`.
+    pub fn retryable_codes(mut self, codes: impl IntoIterator<Item = Code>) -> Self {
+        self.retryable_codes = codes.into_iter().collect();
+        self
+    }
+
+    /// Sets the backoff between the attempts.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Sets the timeout of each attempt, the attempts timed out are always retried.
+    ///
+    /// Default is no timeout.
+    pub fn attempt_timeout(mut self, timeout: Duration) -> Self {
+        self.attempt_timeout = Some(timeout);
+        self
+    }
+}
+
+/// The [`RetryPolicy`] of a call by its [`RetryConfig`].
+struct CallPolicy {
+    config: RetryConfig,
+    body: ReplayBody,
+    metadata: MetadataMap,
+}
+
+impl<U> RetryPolicy<ClientContext, Request<AttemptBody>, Response<U>, Status> for CallPolicy {
+    fn retryable(&self, _cx: &ClientContext, status: &Status) -> bool {
+        self.config.retryable_codes.contains(&status.code())
+    }
+
+    fn backoff(&self, attempt: usize) -> Duration {
+        self.config.backoff.delay(attempt)
+    }
+
+    // the extensions can't be cloned, so they are only sent with the first attempt
+    fn clone_request(&self, _req: &Request<AttemptBody>) -> Option<Request<AttemptBody>> {
+        Some(Request::from_parts(
+            self.metadata.clone(),
+            Default::default(),
+            self.body.attempt(),
+        ))
+    }
+
+    fn replayable(&self, next: &Request<AttemptBody>) -> bool {
+        next.get_ref().replayable()
+    }
+
+    fn attempt_timeout(&self) -> Option<Duration> {
+        self.config.attempt_timeout
+    }
+}
+
+/// The [`Service`] retrying the calls of a [`RetryConfig`] in the extensions of the context, by
+/// the retries of [`volo::layer::retry`].
+#[derive(Clone)]
+pub struct RetryService<S> {
+    inner: S,
+}
+
+impl<S> RetryService<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<T, U, S> Service<ClientContext, Request<T>> for RetryService<S>
+where
+    T: SendEntryMessage + Send + 'static,
+    U: Send + 'static,
+    S: Service<ClientContext, Request<T>, Response = Response<U>, Error = Status>
+        + Service<ClientContext, Request<AttemptBody>, Response = Response<U>, Error = Status>
+        + Send
+        + 'static,
+{
+    type Response = Response<U>;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut ClientContext, req: Request<T>) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let config = match cx.extensions_mut().remove::<RetryConfig>() {
+                Some(config) => config,
+                None => {
+                    return Service::<ClientContext, Request<T>>::call(&mut self.inner, cx, req)
+                        .await
+                }
+            };
+            let (metadata, extensions, message) = req.into_parts();
+            let body = ReplayBody::new(message.into_body());
+            let req = Request::from_parts(metadata.clone(), extensions, body.attempt());
+            let max_retries = config.max_attempts - 1;
+            let policy = CallPolicy {
+                config,
+                body,
+                metadata,
+            };
+            retry::retry(&mut self.inner, &policy, max_retries, None, cx, req).await
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! The stats handler of the client, in the style of the `stats.Handler` of grpc-go, for the APM
+//! agents to trace the connections and the calls without patching the client.
+//!
+//! The handler set by [`ClientBuilder::stats_handler`](super::ClientBuilder::stats_handler) is
+//! called with:
+//!
+//! - [`ConnStats::Begin`] and [`ConnStats::End`] when a connection is made and closed.
+//! - The [`RpcStats`] of each call, by its path: the [`Begin`](RpcStats::Begin), the headers sent
+//!   and received, every message sent and received, the trailers, and the [`End`](RpcStats::End)
+//!   with the error of the call if any.
+//!
+//! A call ends when its response, including the stream of a streaming call, is consumed to the end
+//! or dropped, which ends it with `Cancelled`. The handler is called inline by the client, so it
+//! should be quick, such as only recording the events.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::client::stats::{ConnStats, RpcStats, StatsHandler};
+//!
+//! struct Apm;
+//!
+//! impl StatsHandler for Apm {
+//!     fn handle_conn(&self, stats: &ConnStats) {
+//!         tracing::info!("conn: {:?}", stats);
+//!     }
+//!
+//!     fn handle_rpc(&self, method: &str, stats: &RpcStats<'_>) {
+//!         if let RpcStats::End { duration, error } = stats {
+//!             tracing::info!("{} took {:?}, error: {:?}", method, duration, error);
+//!         }
+//!     }
+//! }
+//!
+//! let client = ItemServiceClientBuilder::new("item")
+//!     .stats_handler(Apm)
+//!     .target(addr)
+//!     .build();
+//! ```
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::StreamExt;
+use http::HeaderMap;
+use http_body::Body as _;
+use volo::net::Address;
+
+use crate::{codec::PREFIX_LEN, BoxStream, Status};
+
+/// The events of a connection.
+#[derive(Debug, Clone)]
+pub enum ConnStats {
+    /// The connection to the address is made.
+    Begin { remote: Address },
+    /// The connection to the address is closed, after being alive for the duration.
+    End { remote: Address, duration: Duration },
+}
+
+/// The events of a call.
+#[derive(Debug)]
+pub enum RpcStats<'a> {
+    /// The call begins.
+    Begin,
+    /// The headers of the request are being sent.
+    OutHeader { headers: &'a HeaderMap },
+    /// A message of the request is sent, of the length encoded, without the prefix.
+    OutPayload { length: usize },
+    /// The headers of the response are received.
+    InHeader { headers: &'a HeaderMap },
+    /// A message of the response is received, of the length encoded, without the prefix.
+    InPayload { length: usize },
+    /// The trailers of the response are received.
+    InTrailer { trailers: &'a HeaderMap },
+    /// The call ends after the duration, with the error if failed.
+    End {
+        duration: Duration,
+        error: Option<&'a Status>,
+    },
+}
+
+/// Handles the stats of the connections and the calls of a client.
+pub trait StatsHandler: Send + Sync + 'static {
+    /// Handles the events of a connection.
+    fn handle_conn(&self, stats: &ConnStats) {
+        let _ = stats;
+    }
+
+    /// Handles the events of a call of `method`, such as `/volo.example.ItemService/GetItem`.
+    fn handle_rpc(&self, method: &str, stats: &RpcStats<'_>) {
+        let _ = (method, stats);
+    }
+}
+
+/// Ends the connection when dropped with it.
+pub(crate) struct ConnGuard {
+    handler: Arc<dyn StatsHandler>,
+    remote: Address,
+    begin: Instant,
+}
+
+impl ConnGuard {
+    pub(crate) fn begin(handler: Arc<dyn StatsHandler>, remote: Address) -> Self {
+        handler.handle_conn(&ConnStats::Begin {
+            remote: remote.clone(),
+        });
+        Self {
+            handler,
+            remote,
+            begin: Instant::now(),
+        }
+    }
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        self.handler.handle_conn(&ConnStats::End {
+            remote: self.remote.clone(),
+            duration: self.begin.elapsed(),
+        });
+    }
+}
+
+/// The stats of a call, which ends it with `Cancelled` if dropped before [`CallStats::end`].
+pub(crate) struct CallStats {
+    handler: Arc<dyn StatsHandler>,
+    method: smol_str::SmolStr,
+    begin: Instant,
+    ended: bool,
+}
+
+impl CallStats {
+    pub(crate) fn begin(handler: Arc<dyn StatsHandler>, method: smol_str::SmolStr) -> Self {
+        handler.handle_rpc(&method, &RpcStats::Begin);
+        Self {
+            handler,
+            method,
+            begin: Instant::now(),
+            ended: false,
+        }
+    }
+
+    pub(crate) fn emit(&self, stats: &RpcStats<'_>) {
+        self.handler.handle_rpc(&self.method, stats);
+    }
+
+    pub(crate) fn end(mut self, error: Option<&Status>) {
+        self.ended = true;
+        self.emit(&RpcStats::End {
+            duration: self.begin.elapsed(),
+            error,
+        });
+    }
+
+    /// Emits [`RpcStats::OutPayload`] for every message of the encoded request.
+    pub(crate) fn out_payloads(
+        &self,
+        body: BoxStream<'static, Result<bytes::Bytes, Status>>,
+    ) -> BoxStream<'static, Result<bytes::Bytes, Status>> {
+        let handler = self.handler.clone();
+        let method = self.method.clone();
+        // every chunk of the encoded stream is a message
+        Box::pin(body.inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                handler.handle_rpc(
+                    &method,
+                    &RpcStats::OutPayload {
+                        length: chunk.len().saturating_sub(PREFIX_LEN),
+                    },
+                );
+            }
+        }))
+    }
+
+    /// Pipes the body of the response, emitting the [`RpcStats::InPayload`] of its messages and
+    /// its trailers, and ends the call at the end of it.
+    pub(crate) fn pipe_response(self, mut body: hyper::Body) -> hyper::Body {
+        let (mut sender, piped) = hyper::Body::channel();
+        tokio::spawn(async move {
+            let mut counter = MessageCounter::default();
+            while let Some(data) = body.data().await {
+                match data {
+                    Ok(data) => {
+                        counter.feed(&data, |length| self.emit(&RpcStats::InPayload { length }));
+                        // dropped by the caller, which ends the call as cancelled
+                        if sender.send_data(data).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        sender.abort();
+                        self.end(Some(&Status::from_error(Box::new(e))));
+                        return;
+                    }
+                }
+            }
+            match body.trailers().await {
+                Ok(Some(trailers)) => {
+                    self.emit(&RpcStats::InTrailer {
+                        trailers: &trailers,
+                    });
+                    let status =
+                        Status::from_header_map(&trailers).filter(|s| s.code() != crate::Code::Ok);
+                    let _ = sender.send_trailers(trailers).await;
+                    self.end(status.as_ref());
+                }
+                Ok(None) => self.end(None),
+                Err(e) => {
+                    sender.abort();
+                    self.end(Some(&Status::from_error(Box::new(e))));
+                }
+            }
+        });
+        piped
+    }
+}
+
+impl Drop for CallStats {
+    fn drop(&mut self) {
+        if !self.ended {
+            self.emit(&RpcStats::End {
+                duration: self.begin.elapsed(),
+                error: Some(&Status::cancelled("the call is cancelled")),
+            });
+        }
+    }
+}
+
+/// Counts the messages in the chunks of a body by their length prefixes.
+#[derive(Default)]
+struct MessageCounter {
+    prefix: [u8; PREFIX_LEN],
+    filled: usize,
+    len: usize,
+    remaining: usize,
+}
+
+impl MessageCounter {
+    /// Feeds a chunk, calls `on_message` with the length of every message completed by it.
+    fn feed(&mut self, mut data: &[u8], mut on_message: impl FnMut(usize)) {
+        while !data.is_empty() {
+            if self.filled < PREFIX_LEN {
+                let n = (PREFIX_LEN - self.filled).min(data.len());
+                self.prefix[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+                self.filled += n;
+                data = &data[n..];
+                if self.filled < PREFIX_LEN {
+                    return;
+                }
+                let mut len = [0; 4];
+                len.copy_from_slice(&self.prefix[1..]);
+                self.len = u32::from_be_bytes(len) as usize;
+                self.remaining = self.len;
+            }
+            let n = self.remaining.min(data.len());
+            self.remaining -= n;
+            data = &data[n..];
+            if self.remaining == 0 {
+                on_message(self.len);
+                self.filled = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_counter() {
+        let mut counter = MessageCounter::default();
+        let mut lengths = Vec::new();
+        let mut body = vec![0, 0, 0, 0, 3, 1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+        body.extend_from_slice(&[4, 5]);
+        // split across the prefixes and the messages
+        for chunk in [&body[..2], &body[2..6], &body[6..13], &body[13..]] {
+            counter.feed(chunk, |len| lengths.push(len));
+        }
+        assert_eq!(lengths, [3, 0, 2]);
+    }
+}
@@ -0,0 +1,360 @@
+//! The compression of the messages, by the `grpc-encoding` of the stream.
+//!
+//! Every message is prefixed with a flag of whether it's compressed, so a stream can mix the
+//! compressed messages with the uncompressed ones. The received messages are decompressed only
+//! when flagged, and the senders can skip compressing some of the messages by the
+//! [`ShouldCompress`] hook, such as the small ones, or the ones of the payloads compressed already.
+//!
+//! Both the clients and the servers advertise the encodings they accept by
+//! `grpc-accept-encoding`, which are all the encodings supported by default, and can be limited
+//! by `accept_compressed`. A server rejects the requests compressed by an encoding it doesn't
+//! accept with `Unimplemented`, and compresses the responses only by an encoding the client
+//! accepts.
+//!
+//! The encodings supported are `gzip`, and `zstd` with the `zstd` feature.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::codec::compression::CompressionEncoding;
+//!
+//! let client = ItemServiceClientBuilder::new("item")
+//!     .send_compressed(CompressionEncoding::Gzip)
+//!     // the small messages aren't worth it
+//!     .should_compress(|msg| msg.len() >= 1024)
+//!     .accept_compressed([CompressionEncoding::Gzip])
+//!     .target(addr)
+//!     .build();
+//! ```
+
+use std::{
+    io::{self, Write},
+    sync::Arc,
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::StreamExt;
+use http::{HeaderMap, HeaderValue};
+
+use super::PREFIX_LEN;
+use crate::{BoxStream, Status};
+
+/// The header of the encoding of the compressed messages.
+pub const ENCODING_HEADER: &str = "grpc-encoding";
+/// The header of the encodings accepted by the peer.
+pub const ACCEPT_ENCODING_HEADER: &str = "grpc-accept-encoding";
+
+/// The encoding of the compressed messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionEncoding {
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl CompressionEncoding {
+    /// The encodings supported.
+    pub const SUPPORTED: &'static [Self] = &[
+        CompressionEncoding::Gzip,
+        #[cfg(feature = "zstd")]
+        CompressionEncoding::Zstd,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionEncoding::Gzip => "gzip",
+            #[cfg(feature = "zstd")]
+            CompressionEncoding::Zstd => "zstd",
+        }
+    }
+
+    pub(crate) fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_static(self.as_str())
+    }
+
+    /// Returns the encoding of the compressed messages by the `grpc-encoding` of the headers,
+    /// `None` if it's absent or `identity`.
+    ///
+    /// Fails with `Unimplemented` if the encoding isn't supported.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, Status> {
+        let value = match headers.get(ENCODING_HEADER) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        match value.as_bytes() {
+            b"identity" => Ok(None),
+            b"gzip" => Ok(Some(CompressionEncoding::Gzip)),
+            #[cfg(feature = "zstd")]
+            b"zstd" => Ok(Some(CompressionEncoding::Zstd)),
+            other => Err(Status::unimplemented(format!(
+                "unsupported grpc-encoding: {}",
+                String::from_utf8_lossy(other)
+            ))),
+        }
+    }
+
+    /// Returns whether the encoding is in the `grpc-accept-encoding` of the headers.
+    pub fn is_accepted_by(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get_all(ACCEPT_ENCODING_HEADER)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|v| v.trim() == self.as_str())
+    }
+
+    pub(crate) fn compress(&self, src: &[u8], dst: &mut BytesMut) -> io::Result<()> {
+        match self {
+            CompressionEncoding::Gzip => {
+                let mut encoder = GzEncoder::new(dst.writer(), Compression::default());
+                encoder.write_all(src)?;
+                encoder.finish()?;
+            }
+            #[cfg(feature = "zstd")]
+            CompressionEncoding::Zstd => {
+                let mut encoder =
+                    zstd::Encoder::new(dst.writer(), zstd::DEFAULT_COMPRESSION_LEVEL)?;
+                encoder.write_all(src)?;
+                encoder.finish()?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn decompress(&self, src: &[u8], dst: &mut BytesMut) -> io::Result<()> {
+        self.decompress_limited(src, dst, u64::MAX)
+    }
+
+    /// Decompresses at most `limit` bytes, the rest of the message is left undecompressed.
+    pub(crate) fn decompress_limited(
+        &self,
+        src: &[u8],
+        dst: &mut BytesMut,
+        limit: u64,
+    ) -> io::Result<()> {
+        match self {
+            CompressionEncoding::Gzip => {
+                let mut writer = dst.writer();
+                io::copy(&mut io::Read::take(GzDecoder::new(src), limit), &mut writer)?;
+            }
+            #[cfg(feature = "zstd")]
+            CompressionEncoding::Zstd => {
+                let mut writer = dst.writer();
+                let decoder = zstd::Decoder::new(src)?;
+                io::copy(&mut io::Read::take(decoder, limit), &mut writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The encodings of the compressed messages accepted.
+#[derive(Debug, Clone)]
+pub(crate) struct AcceptCompression {
+    encodings: Vec<CompressionEncoding>,
+}
+
+impl Default for AcceptCompression {
+    fn default() -> Self {
+        Self::new(CompressionEncoding::SUPPORTED.iter().copied())
+    }
+}
+
+impl AcceptCompression {
+    pub(crate) fn new(encodings: impl IntoIterator<Item = CompressionEncoding>) -> Self {
+        let mut accepted = Vec::new();
+        for encoding in encodings {
+            if !accepted.contains(&encoding) {
+                accepted.push(encoding);
+            }
+        }
+        Self {
+            encodings: accepted,
+        }
+    }
+
+    /// Returns the value of `grpc-accept-encoding`, `None` if no encoding is accepted.
+    pub(crate) fn header_value(&self) -> Option<HeaderValue> {
+        if self.encodings.is_empty() {
+            return None;
+        }
+        let value = self
+            .encodings
+            .iter()
+            .map(|e| e.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+        // SAFETY: the names of the encodings are valid header values.
+        Some(HeaderValue::from_str(&value).unwrap())
+    }
+
+    /// Returns the encoding of the compressed messages by the `grpc-encoding` of the headers.
+    ///
+    /// Fails with `Unimplemented` if the encoding isn't accepted.
+    pub(crate) fn encoding_of(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Option<CompressionEncoding>, Status> {
+        match CompressionEncoding::from_headers(headers)? {
+            Some(encoding) if !self.encodings.contains(&encoding) => Err(Status::unimplemented(
+                format!("grpc-encoding {} is not accepted", encoding.as_str()),
+            )),
+            encoding => Ok(encoding),
+        }
+    }
+}
+
+/// Returns whether to compress a message sent by its encoded bytes.
+pub type ShouldCompress = Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// The compression of the messages sent.
+#[derive(Clone, Default)]
+pub(crate) struct SendCompression {
+    pub(crate) encoding: Option<CompressionEncoding>,
+    pub(crate) should_compress: Option<ShouldCompress>,
+}
+
+impl SendCompression {
+    /// Compresses the messages of the body, except the ones skipped by the hook and the ones
+    /// compressed already, such as by a proxy.
+    pub(crate) fn compress(
+        &self,
+        body: BoxStream<'static, Result<Bytes, Status>>,
+    ) -> BoxStream<'static, Result<Bytes, Status>> {
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None => return body,
+        };
+        let should_compress = self.should_compress.clone();
+        Box::pin(body.map(move |chunk| {
+            compress_frames(encoding, should_compress.as_ref(), chunk?)
+                .map_err(|e| Status::internal(format!("failed to compress the message: {}", e)))
+        }))
+    }
+}
+
+/// Compresses the frames of the chunk, which is left as it is if not made of whole frames.
+fn compress_frames(
+    encoding: CompressionEncoding,
+    should_compress: Option<&ShouldCompress>,
+    chunk: Bytes,
+) -> io::Result<Bytes> {
+    let mut frames = Vec::new();
+    let mut rest = &chunk[..];
+    while !rest.is_empty() {
+        if rest.len() < PREFIX_LEN {
+            return Ok(chunk);
+        }
+        let flag = rest[0];
+        let len = u32::from_be_bytes(rest[1..PREFIX_LEN].try_into().unwrap()) as usize;
+        if rest.len() < PREFIX_LEN + len {
+            return Ok(chunk);
+        }
+        frames.push((flag, &rest[PREFIX_LEN..PREFIX_LEN + len]));
+        rest = &rest[PREFIX_LEN + len..];
+    }
+
+    let mut buf = BytesMut::with_capacity(chunk.len());
+    for (flag, msg) in frames {
+        let start = buf.len();
+        buf.put_u8(flag);
+        buf.put_u32(0);
+        if flag == 0 && should_compress.map_or(true, |f| f(msg)) {
+            encoding.compress(msg, &mut buf)?;
+            buf[start] = 1;
+        } else {
+            buf.put_slice(msg);
+        }
+        let len = buf.len() - start - PREFIX_LEN;
+        assert!(len <= std::u32::MAX as usize);
+        buf[start + 1..start + PREFIX_LEN].copy_from_slice(&(len as u32).to_be_bytes());
+    }
+    Ok(buf.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(flag: u8, msg: &[u8]) -> Vec<u8> {
+        let mut frame = vec![flag];
+        frame.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+        frame.extend_from_slice(msg);
+        frame
+    }
+
+    #[test]
+    fn test_compress_frames() {
+        let (small, large) = (vec![1; 8], vec![2; 4096]);
+        let mut chunk = frame(0, &small);
+        chunk.extend(frame(0, &large));
+        let encoding = CompressionEncoding::Gzip;
+        let should_compress: ShouldCompress = Arc::new(|msg| msg.len() >= 1024);
+        let compressed = compress_frames(encoding, Some(&should_compress), chunk.into()).unwrap();
+
+        // the small one is skipped
+        assert_eq!(&compressed[..PREFIX_LEN + small.len()], frame(0, &small));
+        let large_frame = &compressed[PREFIX_LEN + small.len()..];
+        assert_eq!(large_frame[0], 1);
+        let mut decompressed = BytesMut::new();
+        encoding
+            .decompress(&large_frame[PREFIX_LEN..], &mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, large);
+
+        // not whole frames
+        let partial = Bytes::from(frame(0, &small)[..3].to_vec());
+        assert_eq!(
+            compress_frames(encoding, None, partial.clone()).unwrap(),
+            partial
+        );
+    }
+
+    #[test]
+    fn test_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(CompressionEncoding::from_headers(&headers).unwrap(), None);
+        headers.insert(ENCODING_HEADER, HeaderValue::from_static("gzip"));
+        assert_eq!(
+            CompressionEncoding::from_headers(&headers).unwrap(),
+            Some(CompressionEncoding::Gzip)
+        );
+        headers.insert(ENCODING_HEADER, HeaderValue::from_static("snappy"));
+        CompressionEncoding::from_headers(&headers).unwrap_err();
+
+        headers.insert(
+            ACCEPT_ENCODING_HEADER,
+            HeaderValue::from_static("identity, gzip"),
+        );
+        assert!(CompressionEncoding::Gzip.is_accepted_by(&headers));
+    }
+
+    #[test]
+    fn test_accept_compression() {
+        let mut headers = HeaderMap::new();
+        headers.insert(ENCODING_HEADER, HeaderValue::from_static("gzip"));
+        let accept = AcceptCompression::default();
+        assert_eq!(
+            accept.encoding_of(&headers).unwrap(),
+            Some(CompressionEncoding::Gzip)
+        );
+
+        let accept = AcceptCompression::new([]);
+        assert_eq!(accept.header_value(), None);
+        let status = accept.encoding_of(&headers).unwrap_err();
+        assert_eq!(status.code(), crate::Code::Unimplemented);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        let msg = vec![3; 4096];
+        let (mut compressed, mut decompressed) = (BytesMut::new(), BytesMut::new());
+        let encoding = CompressionEncoding::Zstd;
+        encoding.compress(&msg, &mut compressed).unwrap();
+        encoding.decompress(&compressed, &mut decompressed).unwrap();
+        assert_eq!(decompressed, msg);
+    }
+}
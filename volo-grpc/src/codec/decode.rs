@@ -13,19 +13,21 @@ use http_body::Body;
 use prost::Message;
 use tracing::{debug, trace};
 
-use super::{DefaultDecoder, BUFFER_SIZE, PREFIX_LEN};
-use crate::{codec::Decoder, metadata::MetadataMap, status::Code, Status};
+use super::{compression::CompressionEncoding, DefaultDecoder, BUFFER_SIZE, PREFIX_LEN};
+use crate::{codec::Decoder, memory::RequestMemory, metadata::MetadataMap, status::Code, Status};
 
 /// Streaming Received Request and Received Response.
 ///
-/// Provides an interface for receiving messages and trailers.
+/// Provides an interface for receiving messages, the initial metadata and the trailers.
 pub struct RecvStream<T> {
     body: hyper::Body,
     decoder: DefaultDecoder<T>,
+    headers: Option<MetadataMap>,
     trailers: Option<MetadataMap>,
     buf: BytesMut,
     state: State,
     kind: Kind,
+    config: DecodeConfig,
 }
 
 impl<T> Unpin for RecvStream<T> {}
@@ -33,7 +35,11 @@ impl<T> Unpin for RecvStream<T> {}
 #[derive(Debug, Clone)]
 enum State {
     Header,
-    Body(usize),
+    /// The length of the message, and its encoding if compressed.
+    Body {
+        len: usize,
+        encoding: Option<CompressionEncoding>,
+    },
     Error,
 }
 
@@ -43,15 +49,51 @@ pub enum Kind {
     Response(StatusCode),
 }
 
+/// The config of decoding the messages received, by the headers of the stream.
+#[derive(Default)]
+pub struct DecodeConfig {
+    /// The encoding of the messages flagged as compressed, by the `grpc-encoding` header.
+    pub(crate) encoding: Option<CompressionEncoding>,
+    /// The accounting of the bytes buffered, see [`memory`](crate::memory).
+    pub(crate) memory: Option<RequestMemory>,
+    /// The max size of each message, no limit if `None`.
+    pub(crate) max_message_size: Option<usize>,
+    /// The initial metadata of the responses received by the clients.
+    pub(crate) headers: Option<MetadataMap>,
+}
+
 impl<T> RecvStream<T> {
     pub fn new(body: hyper::Body, kind: Kind) -> Self {
+        Self::with_config(body, kind, DecodeConfig::default())
+    }
+
+    pub fn with_config(body: hyper::Body, kind: Kind, mut config: DecodeConfig) -> Self {
         RecvStream {
             body,
             decoder: DefaultDecoder(PhantomData),
+            headers: config.headers.take(),
             trailers: None,
             buf: BytesMut::with_capacity(BUFFER_SIZE),
             state: State::Header,
             kind,
+            config,
+        }
+    }
+
+    /// Get the initial metadata of the response stream, which has been received when the call of
+    /// the client returns, so it's kept with the stream after `into_inner` of the response,
+    /// before the messages and the trailers.
+    ///
+    /// Returns `None` for the streams not received by the clients, such as the requests.
+    pub fn headers(&self) -> Option<&MetadataMap> {
+        self.headers.as_ref()
+    }
+
+    /// Accounts the bytes buffered if there is a memory budget.
+    fn account(&mut self) -> Result<(), Status> {
+        match &mut self.config.memory {
+            Some(memory) => memory.set(self.buf.capacity()),
+            None => Ok(()),
         }
     }
 }
@@ -67,6 +109,9 @@ impl<T: Message + Default> RecvStream<T> {
     }
 
     /// Get the trailers from the stream.
+    ///
+    /// Any messages that have not been consumed yet will be drained and discarded, so this is
+    /// usually called after the stream returns `None`.
     pub async fn trailers(&mut self) -> Result<Option<MetadataMap>, Status> {
         if let Some(trailers) = self.trailers.take() {
             return Ok(Some(trailers));
@@ -93,13 +138,17 @@ impl<T: Message + Default> RecvStream<T> {
                 return Ok(None);
             }
 
-            match self.buf.get_u8() {
-                0 => false,
+            // the flag of every message, a stream may mix the compressed and the uncompressed ones
+            let encoding = match self.buf.get_u8() {
+                0 => None,
+                1 if self.config.encoding.is_some() => self.config.encoding,
                 1 => {
-                    trace!("[VOLO] compression not supported yet");
+                    trace!("[VOLO] compressed message without grpc-encoding");
+                    // https://github.com/grpc/grpc/blob/master/doc/compression.md
                     return Err(Status::new(
-                        Code::Unimplemented,
-                        "Compression not supported yet".to_string(),
+                        Code::Internal,
+                        "protocol error: received a compressed message without grpc-encoding"
+                            .to_string(),
                     ));
                 }
                 flag => {
@@ -114,18 +163,34 @@ impl<T: Message + Default> RecvStream<T> {
                 }
             };
             let len = self.buf.get_u32() as usize;
+            if let Some(max) = self.config.max_message_size {
+                if len > max {
+                    return Err(too_large(len, max));
+                }
+            }
+            if let Some(memory) = &self.config.memory {
+                memory.check(len)?;
+            }
             self.buf.reserve(len);
+            self.account()?;
 
-            self.state = State::Body(len);
+            self.state = State::Body { len, encoding };
         }
 
-        if let State::Body(len) = &self.state {
+        if let State::Body { len, encoding } = self.state {
             // data is not enough to decode body, return and keep reading
-            if self.buf.remaining() < *len || self.buf.len() < *len {
+            if self.buf.remaining() < len || self.buf.len() < len {
                 return Ok(None);
             }
 
-            return match DefaultDecoder::<T>::decode(&mut self.decoder, &mut self.buf) {
+            // split the frame out of the read buffer, so the decoder only sees the current
+            // message and `Bytes` fields can share the underlying memory.
+            let mut frame = self.buf.split_to(len).freeze();
+            self.account()?;
+            if let Some(encoding) = encoding {
+                frame = decompress(encoding, &frame, self.config.max_message_size)?;
+            }
+            return match DefaultDecoder::<T>::decode_frame(&mut self.decoder, frame) {
                 Ok(Some(msg)) => {
                     self.state = State::Header;
                     Ok(Some(msg))
@@ -139,6 +204,35 @@ impl<T: Message + Default> RecvStream<T> {
     }
 }
 
+fn too_large(len: usize, max: usize) -> Status {
+    Status::resource_exhausted(format!(
+        "received message larger than max ({} vs. {})",
+        len, max
+    ))
+}
+
+fn decompress(
+    encoding: CompressionEncoding,
+    frame: &[u8],
+    max: Option<usize>,
+) -> Result<bytes::Bytes, Status> {
+    let mut buf = BytesMut::with_capacity(frame.len() * 2);
+    // one more byte than the max, to tell the messages over it
+    let limit = max.map_or(u64::MAX, |max| max as u64 + 1);
+    encoding
+        .decompress_limited(frame, &mut buf, limit)
+        .map_err(|e| {
+            Status::new(
+                Code::Internal,
+                format!("failed to decompress the message: {}", e),
+            )
+        })?;
+    match max {
+        Some(max) if buf.len() > max => Err(too_large(buf.len(), max)),
+        _ => Ok(buf.freeze()),
+    }
+}
+
 impl<T: Message + Default> Stream for RecvStream<T> {
     type Item = Result<T, Status>;
 
@@ -150,6 +244,10 @@ impl<T: Message + Default> Stream for RecvStream<T> {
             if let Some(item) = self.decode_chunk()? {
                 return Poll::Ready(Some(Ok(item)));
             }
+            // stops reading until the memory is released
+            if let Some(memory) = &self.config.memory {
+                ready!(memory.poll_available(cx));
+            }
 
             let chunk = match ready!(Pin::new(&mut self.body).poll_data(cx)) {
                 Some(Ok(d)) => Some(d),
@@ -165,6 +263,7 @@ impl<T: Message + Default> Stream for RecvStream<T> {
 
             if let Some(data) = chunk {
                 self.buf.put(data);
+                self.account()?;
             } else if self.buf.has_remaining() {
                 trace!("[VOLO] unexpected EOF decoding stream");
                 return Poll::Ready(Some(Err(Status::new(
@@ -209,3 +308,56 @@ impl<T> fmt::Debug for RecvStream<T> {
         f.debug_struct("RecvStream").finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn body() -> hyper::Body {
+        let mut body = BytesMut::new();
+        for (flag, msg) in [(0, "plain"), (1, "compressed")] {
+            let mut frame = BytesMut::new();
+            if flag == 1 {
+                CompressionEncoding::Gzip
+                    .compress(&msg.to_string().encode_to_vec(), &mut frame)
+                    .unwrap();
+            } else {
+                msg.to_string().encode(&mut frame).unwrap();
+            }
+            body.put_u8(flag);
+            body.put_u32(frame.len() as u32);
+            body.put(frame);
+        }
+        hyper::Body::from(body.freeze())
+    }
+
+    #[tokio::test]
+    async fn test_mixed_compression() {
+        let config = DecodeConfig {
+            encoding: Some(CompressionEncoding::Gzip),
+            ..Default::default()
+        };
+        let stream = RecvStream::<String>::with_config(body(), Kind::Request, config);
+        let msgs: Vec<_> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(msgs, ["plain", "compressed"]);
+
+        // the compressed one is larger than the max
+        let config = DecodeConfig {
+            encoding: Some(CompressionEncoding::Gzip),
+            max_message_size: Some(8),
+            ..Default::default()
+        };
+        let mut stream = RecvStream::<String>::with_config(body(), Kind::Request, config);
+        assert_eq!(stream.next().await.unwrap().unwrap(), "plain");
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+
+        // compressed without grpc-encoding
+        let mut stream = RecvStream::<String>::new(body(), Kind::Request);
+        assert_eq!(stream.next().await.unwrap().unwrap(), "plain");
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), Code::Internal);
+    }
+}
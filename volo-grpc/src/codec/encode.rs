@@ -1,4 +1,4 @@
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures::{Stream, StreamExt};
 use prost::Message;
 
@@ -42,3 +42,83 @@ where
         }
     })
 }
+
+/// Fails the stream with `RESOURCE_EXHAUSTED` at the first message larger than `max`.
+///
+/// The messages may be split across the chunks, or a chunk may have many of them, such as of
+/// [`RawBody`](crate::proxy::RawBody), so the prefixes are tracked across the chunks.
+pub(crate) fn limit(
+    mut source: BoxStream<'static, Result<Bytes, Status>>,
+    max: usize,
+) -> BoxStream<'static, Result<Bytes, Status>> {
+    Box::pin(async_stream::stream! {
+        // the prefix of the next message read so far, and the bytes left of the current one
+        let mut prefix = BytesMut::with_capacity(PREFIX_LEN);
+        let mut remaining: usize = 0;
+
+        while let Some(chunk) = source.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(status) => {
+                    yield Err(status);
+                    continue;
+                }
+            };
+            let mut rest = &chunk[..];
+            while !rest.is_empty() {
+                if remaining > 0 {
+                    let n = remaining.min(rest.len());
+                    remaining -= n;
+                    rest.advance(n);
+                    continue;
+                }
+                let n = (PREFIX_LEN - prefix.len()).min(rest.len());
+                prefix.put_slice(&rest[..n]);
+                rest.advance(n);
+                if prefix.len() == PREFIX_LEN {
+                    let len = (&prefix[1..]).get_u32() as usize;
+                    prefix.clear();
+                    if len > max {
+                        yield Err(Status::resource_exhausted(format!(
+                            "trying to send message larger than max ({} vs. {})",
+                            len, max
+                        )));
+                        return;
+                    }
+                    remaining = len;
+                }
+            }
+            yield Ok(chunk);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+    use crate::Code;
+
+    #[tokio::test]
+    async fn test_limit() {
+        let msgs = ["small".to_string(), "larger than max".to_string()];
+        let frames: Vec<_> = encode(stream::iter(msgs.map(Ok))).collect().await;
+        let mut body = BytesMut::new();
+        for frame in frames {
+            body.put(frame.unwrap());
+        }
+        // split within the prefix of the second message
+        let body = body.freeze();
+        let chunks = vec![
+            Ok(body.slice(..PREFIX_LEN + 9)),
+            Ok(body.slice(PREFIX_LEN + 9..)),
+        ];
+
+        let mut limited = limit(Box::pin(stream::iter(chunks)), 10);
+        limited.next().await.unwrap().unwrap();
+        let status = limited.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(limited.next().await.is_none());
+    }
+}
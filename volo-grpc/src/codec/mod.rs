@@ -3,18 +3,43 @@
 //! This module contains the generic `Encoder` and `Decoder` traits as well as
 //! the 'DefaultEncoder' and 'DefaultDecoder' implementations based on prost.
 
+pub mod compression;
 pub mod decode;
 pub mod encode;
 
 use std::{io, marker::PhantomData, mem::size_of};
 
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use prost::Message;
 
 use crate::{status::Code::Internal, Status};
 
-const PREFIX_LEN: usize = size_of::<u32>() + size_of::<u8>();
+pub(crate) const PREFIX_LEN: usize = size_of::<u32>() + size_of::<u8>();
 const BUFFER_SIZE: usize = 8 * 1024;
+/// The max size of the messages received by default, the same as of the other gRPC
+/// implementations.
+const DEFAULT_MAX_RECV_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// The max sizes of the messages of a client or a server, over which the calls fail with
+/// `RESOURCE_EXHAUSTED`.
+///
+/// The size received is checked by the prefix of each message before it's buffered, and after
+/// it's decompressed if compressed, so neither a large message nor a compression bomb is buffered
+/// wholly. The size sent is of the encoded message before compressed.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MessageSize {
+    pub(crate) max_recv: usize,
+    pub(crate) max_send: usize,
+}
+
+impl Default for MessageSize {
+    fn default() -> Self {
+        Self {
+            max_recv: DEFAULT_MAX_RECV_MESSAGE_SIZE,
+            max_send: usize::MAX,
+        }
+    }
+}
 
 /// Encoder for gRPC messages.
 pub trait Encoder {
@@ -59,6 +84,15 @@ pub trait Decoder {
 
     /// Decode a message from the buffer.
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error>;
+
+    /// Decode a message from a complete frame.
+    ///
+    /// The frame is handed over as [`Bytes`], so that the decoders of the messages with
+    /// [`Bytes`] fields can slice them out of it without copying. Defaults to
+    /// [`decode`](Self::decode) on a copy of the frame.
+    fn decode_frame(&mut self, src: Bytes) -> Result<Option<Self::Item>, Self::Error> {
+        self.decode(&mut BytesMut::from(&src[..]))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -73,6 +107,12 @@ impl<T: Message + Default> Decoder for DefaultDecoder<T> {
             .map(Option::Some)
             .map_err(|e| Status::new(Internal, e.to_string()))
     }
+
+    fn decode_frame(&mut self, src: Bytes) -> Result<Option<Self::Item>, Self::Error> {
+        Message::decode(src)
+            .map(Option::Some)
+            .map_err(|e| Status::new(Internal, e.to_string()))
+    }
 }
 
 impl<T> Default for DefaultDecoder<T> {
@@ -80,3 +120,44 @@ impl<T> Default for DefaultDecoder<T> {
         DefaultDecoder(PhantomData)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[derive(Clone, PartialEq, Eq, Message)]
+    struct Blob {
+        #[prost(bytes = "bytes", tag = "1")]
+        data: Bytes,
+    }
+
+    #[test]
+    fn test_decode_frame() {
+        let blob = Blob {
+            data: Bytes::from(vec![7; 1024]),
+        };
+        let frame = Bytes::from(blob.encode_to_vec());
+        let decoded = DefaultDecoder::<Blob>::default()
+            .decode_frame(frame.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded, blob);
+        // sliced out of the frame
+        let range = frame.as_ptr_range();
+        assert!(range.contains(&decoded.data.as_ptr()));
+
+        // the decoders of the buffers decode the frames too
+        struct Len;
+        impl Decoder for Len {
+            type Item = usize;
+            type Error = Status;
+
+            fn decode(&mut self, src: &mut BytesMut) -> Result<Option<usize>, Status> {
+                Ok(Some(src.len()))
+            }
+        }
+        assert_eq!(Len.decode_frame(frame.clone()).unwrap(), Some(frame.len()));
+    }
+}
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub use volo::context::*;
 use volo::newtype_impl_context;
@@ -15,6 +15,12 @@ impl ClientContext {
     pub fn new(ri: RpcInfo<Config>) -> Self {
         Self(RpcCx::new(ri, ClientCxInner))
     }
+
+    /// Returns the deadline of the call, such as by the rpc timeout, which is sent to the server
+    /// as the `grpc-timeout`.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.extensions().get::<Deadline>().map(|d| d.0)
+    }
 }
 
 impl Default for ClientContext {
@@ -45,6 +51,14 @@ pub struct ServerContext(pub(crate) volo::context::RpcCx<ServerCxInner, Config>)
 
 newtype_impl_context!(ServerContext, Config, 0);
 
+impl ServerContext {
+    /// Returns the deadline of the request by the `grpc-timeout` of the client, after which the
+    /// handler is cancelled, such as to propagate it to the downstream calls.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.extensions().get::<Deadline>().map(|d| d.0)
+    }
+}
+
 impl Default for ServerContext {
     fn default() -> Self {
         Self(RpcCx::new(RpcInfo::with_role(Role::Server), ServerCxInner))
@@ -73,6 +87,8 @@ pub struct Config {
     pub(crate) read_timeout: Option<Duration>,
     /// Amount of time to wait reading response.
     pub(crate) write_timeout: Option<Duration>,
+    /// Amount of time to wait for the response of the call.
+    pub(crate) rpc_timeout: Option<Duration>,
 }
 
 impl Config {
@@ -86,5 +102,8 @@ impl Config {
         if let Some(t) = other.write_timeout {
             self.write_timeout = Some(t);
         }
+        if let Some(t) = other.rpc_timeout {
+            self.rpc_timeout = Some(t);
+        }
     }
 }
@@ -0,0 +1,453 @@
+//! The richer error model of gRPC, the `google.rpc.Status` with the typed details in
+//! `grpc-status-details-bin`, which the clients of tonic, grpc-go and grpc-java read as well.
+//!
+//! The details are the standard messages of `google/rpc/error_details.proto`, such as
+//! [`BadRequest`] and [`RetryInfo`], packed as `google.protobuf.Any` into [`ErrorDetails`].
+//! [`Status::with_error_details`] encodes them with the code and the message of the status, which
+//! the server sends as is, both as the trailers-only rejection and as the trailers of a stream,
+//! and [`Status::error_details`] decodes them on the client side.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use std::time::Duration;
+//!
+//! use volo_grpc::{
+//!     error_details::{BadRequest, ErrorDetails, RetryInfo},
+//!     Code, Status,
+//! };
+//!
+//! // server
+//! let details = ErrorDetails::new()
+//!     .with(BadRequest::with_violation("name", "must not be empty"))
+//!     .with(RetryInfo::new(Duration::from_secs(1)));
+//! return Err(Status::with_error_details(Code::InvalidArgument, "invalid item", details));
+//!
+//! // client
+//! if let Ok(details) = status.error_details() {
+//!     if let Some(bad_request) = details.get::<BadRequest>() {
+//!         for violation in bad_request.field_violations {
+//!             println!("{}: {}", violation.field, violation.description);
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::{collections::HashMap, time::Duration as StdDuration};
+
+use bytes::Bytes;
+use prost::{DecodeError, Message};
+
+use crate::{Code, Status};
+
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/";
+
+/// A typed detail of a status, one of the messages of `google/rpc/error_details.proto`.
+pub trait ErrorDetail: Message + Default {
+    /// The full name of the message, such as `google.rpc.BadRequest`.
+    const NAME: &'static str;
+
+    /// Returns the type url of the message in `google.protobuf.Any`.
+    fn type_url() -> String {
+        format!("{}{}", TYPE_URL_PREFIX, Self::NAME)
+    }
+}
+
+/// `google.protobuf.Any`, a message of any type identified by its type url.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct Any {
+    #[prost(string, tag = "1")]
+    pub type_url: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub value: Vec<u8>,
+}
+
+/// `google.protobuf.Duration`.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct Duration {
+    #[prost(int64, tag = "1")]
+    pub seconds: i64,
+    #[prost(int32, tag = "2")]
+    pub nanos: i32,
+}
+
+impl From<StdDuration> for Duration {
+    fn from(d: StdDuration) -> Self {
+        Self {
+            seconds: d.as_secs().min(i64::MAX as u64) as i64,
+            nanos: d.subsec_nanos() as i32,
+        }
+    }
+}
+
+impl From<Duration> for StdDuration {
+    /// The negative durations are zero.
+    fn from(d: Duration) -> Self {
+        if d.seconds < 0 || d.nanos < 0 {
+            return StdDuration::ZERO;
+        }
+        StdDuration::new(d.seconds as u64, d.nanos as u32)
+    }
+}
+
+/// `google.rpc.Status`, the message encoded in `grpc-status-details-bin`.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    pub code: i32,
+    #[prost(string, tag = "2")]
+    pub message: String,
+    #[prost(message, repeated, tag = "3")]
+    pub details: Vec<Any>,
+}
+
+/// The reason of an error, such as `API_DISABLED`, of the domain of the service, with the
+/// structured metadata of it.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ErrorInfo {
+    #[prost(string, tag = "1")]
+    pub reason: String,
+    #[prost(string, tag = "2")]
+    pub domain: String,
+    #[prost(map = "string, string", tag = "3")]
+    pub metadata: HashMap<String, String>,
+}
+
+impl ErrorInfo {
+    pub fn new(reason: impl Into<String>, domain: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            domain: domain.into(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// Adds a metadata of the error.
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// How long the client should wait before retrying the call.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    pub retry_delay: Option<Duration>,
+}
+
+impl RetryInfo {
+    pub fn new(delay: StdDuration) -> Self {
+        Self {
+            retry_delay: Some(delay.into()),
+        }
+    }
+
+    /// Returns the delay before retrying, if any.
+    pub fn delay(&self) -> Option<StdDuration> {
+        self.retry_delay.clone().map(Into::into)
+    }
+}
+
+/// The debugging information of the server, which shouldn't be sent to the untrusted clients.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct DebugInfo {
+    #[prost(string, repeated, tag = "1")]
+    pub stack_entries: Vec<String>,
+    #[prost(string, tag = "2")]
+    pub detail: String,
+}
+
+/// The quotas exceeded by the call.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct QuotaFailure {
+    #[prost(message, repeated, tag = "1")]
+    pub violations: Vec<quota_failure::Violation>,
+}
+
+pub mod quota_failure {
+    #[derive(Clone, PartialEq, Eq, prost::Message)]
+    pub struct Violation {
+        #[prost(string, tag = "1")]
+        pub subject: String,
+        #[prost(string, tag = "2")]
+        pub description: String,
+    }
+}
+
+impl QuotaFailure {
+    pub fn with_violation(subject: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            violations: vec![quota_failure::Violation {
+                subject: subject.into(),
+                description: description.into(),
+            }],
+        }
+    }
+}
+
+/// The preconditions failed by the call, such as the terms of service not accepted.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct PreconditionFailure {
+    #[prost(message, repeated, tag = "1")]
+    pub violations: Vec<precondition_failure::Violation>,
+}
+
+pub mod precondition_failure {
+    #[derive(Clone, PartialEq, Eq, prost::Message)]
+    pub struct Violation {
+        #[prost(string, tag = "1")]
+        pub r#type: String,
+        #[prost(string, tag = "2")]
+        pub subject: String,
+        #[prost(string, tag = "3")]
+        pub description: String,
+    }
+}
+
+impl PreconditionFailure {
+    pub fn with_violation(
+        r#type: impl Into<String>,
+        subject: impl Into<String>,
+        description: impl Into<String>,
+    ) -> Self {
+        Self {
+            violations: vec![precondition_failure::Violation {
+                r#type: r#type.into(),
+                subject: subject.into(),
+                description: description.into(),
+            }],
+        }
+    }
+}
+
+/// The invalid fields of the request.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct BadRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub field_violations: Vec<bad_request::FieldViolation>,
+}
+
+pub mod bad_request {
+    #[derive(Clone, PartialEq, Eq, prost::Message)]
+    pub struct FieldViolation {
+        /// The path of the field, such as `item.tags[2].name`.
+        #[prost(string, tag = "1")]
+        pub field: String,
+        #[prost(string, tag = "2")]
+        pub description: String,
+    }
+}
+
+impl BadRequest {
+    pub fn with_violation(field: impl Into<String>, description: impl Into<String>) -> Self {
+        Self::default().add_violation(field, description)
+    }
+
+    /// Adds an invalid field.
+    pub fn add_violation(
+        mut self,
+        field: impl Into<String>,
+        description: impl Into<String>,
+    ) -> Self {
+        self.field_violations.push(bad_request::FieldViolation {
+            field: field.into(),
+            description: description.into(),
+        });
+        self
+    }
+}
+
+/// The request of the error, mostly for the bug reports.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct RequestInfo {
+    #[prost(string, tag = "1")]
+    pub request_id: String,
+    #[prost(string, tag = "2")]
+    pub serving_data: String,
+}
+
+/// The resource accessed by the call, such as the one not found.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ResourceInfo {
+    #[prost(string, tag = "1")]
+    pub resource_type: String,
+    #[prost(string, tag = "2")]
+    pub resource_name: String,
+    #[prost(string, tag = "3")]
+    pub owner: String,
+    #[prost(string, tag = "4")]
+    pub description: String,
+}
+
+/// The links to the documentation of the error.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct Help {
+    #[prost(message, repeated, tag = "1")]
+    pub links: Vec<help::Link>,
+}
+
+pub mod help {
+    #[derive(Clone, PartialEq, Eq, prost::Message)]
+    pub struct Link {
+        #[prost(string, tag = "1")]
+        pub description: String,
+        #[prost(string, tag = "2")]
+        pub url: String,
+    }
+}
+
+impl Help {
+    pub fn with_link(description: impl Into<String>, url: impl Into<String>) -> Self {
+        Self {
+            links: vec![help::Link {
+                description: description.into(),
+                url: url.into(),
+            }],
+        }
+    }
+}
+
+/// The error message localized for the end users, such as of the locale `zh-CN`.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct LocalizedMessage {
+    #[prost(string, tag = "1")]
+    pub locale: String,
+    #[prost(string, tag = "2")]
+    pub message: String,
+}
+
+macro_rules! error_details {
+    ($($ty:ident => $name:literal),* $(,)?) => {
+        $(
+            impl ErrorDetail for $ty {
+                const NAME: &'static str = $name;
+            }
+        )*
+    };
+}
+
+error_details! {
+    ErrorInfo => "google.rpc.ErrorInfo",
+    RetryInfo => "google.rpc.RetryInfo",
+    DebugInfo => "google.rpc.DebugInfo",
+    QuotaFailure => "google.rpc.QuotaFailure",
+    PreconditionFailure => "google.rpc.PreconditionFailure",
+    BadRequest => "google.rpc.BadRequest",
+    RequestInfo => "google.rpc.RequestInfo",
+    ResourceInfo => "google.rpc.ResourceInfo",
+    Help => "google.rpc.Help",
+    LocalizedMessage => "google.rpc.LocalizedMessage",
+}
+
+/// The details of a status, in the order they are added.
+///
+/// Besides the standard ones, the application may add its own messages by implementing
+/// [`ErrorDetail`] for them, or as [`Any`] by [`ErrorDetails::with_any`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorDetails {
+    details: Vec<Any>,
+}
+
+impl ErrorDetails {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a typed detail.
+    pub fn with<T: ErrorDetail>(self, detail: T) -> Self {
+        self.with_any(Any {
+            type_url: T::type_url(),
+            value: detail.encode_to_vec(),
+        })
+    }
+
+    /// Adds a detail packed already.
+    pub fn with_any(mut self, any: Any) -> Self {
+        self.details.push(any);
+        self
+    }
+
+    /// Returns the first detail of the type `T`, the ones failed to decode are skipped.
+    pub fn get<T: ErrorDetail>(&self) -> Option<T> {
+        let type_url = T::type_url();
+        self.details
+            .iter()
+            .filter(|any| any.type_url == type_url)
+            .find_map(|any| T::decode(&any.value[..]).ok())
+    }
+
+    /// Returns all the details packed, including the ones of the unknown types.
+    pub fn as_any(&self) -> &[Any] {
+        &self.details
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.details.is_empty()
+    }
+}
+
+impl Status {
+    /// Create a new `Status` with the associated code, message, and the typed details encoded as
+    /// `google.rpc.Status` in the binary details field.
+    pub fn with_error_details(
+        code: Code,
+        message: impl Into<String>,
+        details: ErrorDetails,
+    ) -> Status {
+        let message = message.into();
+        let status = RpcStatus {
+            code: code.into(),
+            message: message.clone(),
+            details: details.details,
+        };
+        Status::with_details(code, message, Bytes::from(status.encode_to_vec()))
+    }
+
+    /// Decodes the typed details from the binary details field, which are empty if there are no
+    /// details.
+    pub fn error_details(&self) -> Result<ErrorDetails, DecodeError> {
+        let status = RpcStatus::decode(self.details())?;
+        Ok(ErrorDetails {
+            details: status.details,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_details() {
+        let details = ErrorDetails::new()
+            .with(BadRequest::with_violation("name", "must not be empty").add_violation("id", ""))
+            .with(RetryInfo::new(StdDuration::from_millis(1500)))
+            .with(ErrorInfo::new("ITEM_LOCKED", "item.example.com").with_metadata("id", "1"));
+        let status = Status::with_error_details(Code::InvalidArgument, "invalid item", details);
+
+        // through the trailers, as the peers see it
+        let status = Status::from_header_map(&status.to_header_map().unwrap()).unwrap();
+        let rpc_status = RpcStatus::decode(status.details()).unwrap();
+        assert_eq!(rpc_status.code, Code::InvalidArgument as i32);
+        assert_eq!(rpc_status.message, "invalid item");
+        assert_eq!(
+            rpc_status.details[0].type_url,
+            "type.googleapis.com/google.rpc.BadRequest"
+        );
+
+        let details = status.error_details().unwrap();
+        assert_eq!(
+            details.get::<BadRequest>().unwrap().field_violations.len(),
+            2
+        );
+        assert_eq!(
+            details.get::<RetryInfo>().unwrap().delay(),
+            Some(StdDuration::from_millis(1500))
+        );
+        assert_eq!(details.get::<ErrorInfo>().unwrap().metadata["id"], "1");
+        assert!(details.get::<Help>().is_none());
+
+        assert!(Status::internal("").error_details().unwrap().is_empty());
+    }
+}
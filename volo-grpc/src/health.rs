@@ -0,0 +1,257 @@
+//! The health checking, `grpc.health.v1.Health`, probed by the load balancers and the
+//! orchestrators, such as Envoy and Kubernetes.
+//!
+//! [`health_reporter`] returns a [`HealthReporter`], which the application flips the serving
+//! status of every service by at runtime, and the [`HealthServer`] which serves the statuses. The
+//! status of the empty service name `""` is of the whole server, which is serving by default,
+//! while the other services are unknown until their statuses are set.
+//!
+//! `Check` answers the current status, failing with `NotFound` for the unknown services, and
+//! `Watch` streams the status whenever it changes, starting with the current one.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::{health::health_reporter, server::Router};
+//!
+//! let (reporter, health) = health_reporter();
+//! reporter.set_serving::<ItemServiceServer<S>>();
+//!
+//! // such as when the dependencies of the service are down
+//! reporter.set_not_serving::<ItemServiceServer<S>>();
+//!
+//! Server::new(Router::new())
+//!     .add_service(ItemServiceServer::service(S))
+//!     .add_service(health)
+//!     .run(addr)
+//!     .await;
+//! ```
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use futures::StreamExt;
+use motore::Service;
+use prost::Message;
+use tokio::sync::watch;
+use volo::service_info::ServiceInfo;
+
+use crate::{context::ServerContext, BoxStream, Code, RecvStream, Request, Response, Status};
+
+const CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+const WATCH_PATH: &str = "/grpc.health.v1.Health/Watch";
+
+/// The serving status of a service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum ServingStatus {
+    Unknown = 0,
+    Serving = 1,
+    NotServing = 2,
+    /// Only sent by `Watch`, for the services whose statuses are not set.
+    ServiceUnknown = 3,
+}
+
+/// The request of the health checking.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct HealthCheckRequest {
+    /// The full name of the service, such as `volo.example.ItemService`, or `""` of the server.
+    #[prost(string, tag = "1")]
+    pub service: String,
+}
+
+/// The response of the health checking.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct HealthCheckResponse {
+    #[prost(enumeration = "ServingStatus", tag = "1")]
+    pub status: i32,
+}
+
+type Statuses = Arc<Mutex<HashMap<String, watch::Sender<ServingStatus>>>>;
+
+/// Creates a [`HealthReporter`] and the [`HealthServer`] serving the statuses it sets.
+pub fn health_reporter() -> (HealthReporter, HealthServer) {
+    let reporter = HealthReporter {
+        statuses: Default::default(),
+    };
+    reporter.set_service_status("", ServingStatus::Serving);
+    let server = HealthServer {
+        statuses: reporter.statuses.clone(),
+    };
+    (reporter, server)
+}
+
+/// The handle to set the serving statuses of the services.
+#[derive(Clone)]
+pub struct HealthReporter {
+    statuses: Statuses,
+}
+
+impl HealthReporter {
+    /// Sets the status of the service, such as a generated server, to serving.
+    pub fn set_serving<S: ServiceInfo>(&self) {
+        self.set_service_status(S::NAME, ServingStatus::Serving);
+    }
+
+    /// Sets the status of the service, such as a generated server, to not serving.
+    pub fn set_not_serving<S: ServiceInfo>(&self) {
+        self.set_service_status(S::NAME, ServingStatus::NotServing);
+    }
+
+    /// Sets the status of the service of the full name, or of the server by `""`, notifying the
+    /// clients watching it.
+    pub fn set_service_status(&self, service: &str, status: ServingStatus) {
+        let mut statuses = self.statuses.lock().unwrap();
+        let sender = statuses.get(service);
+        match sender {
+            Some(sender) => {
+                if *sender.borrow() != status {
+                    sender.send_replace(status);
+                }
+            }
+            None => {
+                statuses.insert(service.to_string(), watch::channel(status).0);
+            }
+        }
+    }
+
+    /// Returns the status of the service, [`ServingStatus::ServiceUnknown`] if it's not set.
+    pub fn service_status(&self, service: &str) -> ServingStatus {
+        self.statuses
+            .lock()
+            .unwrap()
+            .get(service)
+            .map_or(ServingStatus::ServiceUnknown, |sender| *sender.borrow())
+    }
+}
+
+/// The health service, serving the statuses set by the [`HealthReporter`].
+#[derive(Clone)]
+pub struct HealthServer {
+    statuses: Statuses,
+}
+
+impl HealthServer {
+    fn check(&self, service: &str) -> Result<HealthCheckResponse, Status> {
+        let status = self
+            .statuses
+            .lock()
+            .unwrap()
+            .get(service)
+            .map_or(ServingStatus::ServiceUnknown, |sender| *sender.borrow());
+        if status == ServingStatus::ServiceUnknown {
+            return Err(Status::not_found(format!("unknown service: {}", service)));
+        }
+        Ok(HealthCheckResponse {
+            status: status as i32,
+        })
+    }
+
+    fn watch(&self, service: String) -> BoxStream<'static, Result<HealthCheckResponse, Status>> {
+        // the unknown services are watched too, until their statuses are set
+        let mut receiver = self
+            .statuses
+            .lock()
+            .unwrap()
+            .entry(service)
+            .or_insert_with(|| watch::channel(ServingStatus::ServiceUnknown).0)
+            .subscribe();
+        Box::pin(async_stream::stream! {
+            let mut last = None;
+            loop {
+                let status = *receiver.borrow();
+                if last != Some(status) {
+                    last = Some(status);
+                    yield Ok(HealthCheckResponse { status: status as i32 });
+                }
+                if receiver.changed().await.is_err() {
+                    return;
+                }
+            }
+        })
+    }
+}
+
+impl ServiceInfo for HealthServer {
+    const NAME: &'static str = "grpc.health.v1.Health";
+    const METHODS: &'static [&'static str] = &["Check", "Watch"];
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+}
+
+impl Service<ServerContext, Request<RecvStream<HealthCheckRequest>>> for HealthServer {
+    type Response = Response<BoxStream<'static, Result<HealthCheckResponse, Status>>>;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(
+        &'s mut self,
+        cx: &'cx mut ServerContext,
+        req: Request<RecvStream<HealthCheckRequest>>,
+    ) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let method = cx.rpc_info.method.as_deref();
+            if method != Some(CHECK_PATH) && method != Some(WATCH_PATH) {
+                return Err(Status::new(Code::Unimplemented, "Method not found."));
+            }
+            let request = req
+                .into_inner()
+                .next()
+                .await
+                .transpose()?
+                .ok_or_else(|| Status::new(Code::Internal, "Missing request message."))?;
+            let responses = if method == Some(CHECK_PATH) {
+                let response = self.check(&request.service);
+                Box::pin(futures::stream::once(futures::future::ready(response)))
+                    as BoxStream<'static, _>
+            } else {
+                self.watch(request.service)
+            };
+            Ok(Response::new(responses))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Item;
+
+    impl ServiceInfo for Item {
+        const NAME: &'static str = "volo.example.ItemService";
+        const METHODS: &'static [&'static str] = &["GetItem"];
+        const VERSION: &'static str = "0.1.0";
+    }
+
+    fn status_of(response: HealthCheckResponse) -> ServingStatus {
+        ServingStatus::from_i32(response.status).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_health() {
+        let (reporter, server) = health_reporter();
+        assert_eq!(status_of(server.check("").unwrap()), ServingStatus::Serving);
+        let err = server.check(Item::NAME).unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+
+        let mut watch = server.watch(Item::NAME.into());
+        let next = watch.next().await.unwrap().unwrap();
+        assert_eq!(status_of(next), ServingStatus::ServiceUnknown);
+        reporter.set_serving::<Item>();
+        let next = watch.next().await.unwrap().unwrap();
+        assert_eq!(status_of(next), ServingStatus::Serving);
+        reporter.set_not_serving::<Item>();
+        let next = watch.next().await.unwrap().unwrap();
+        assert_eq!(status_of(next), ServingStatus::NotServing);
+        assert_eq!(
+            status_of(server.check(Item::NAME).unwrap()),
+            ServingStatus::NotServing
+        );
+    }
+}
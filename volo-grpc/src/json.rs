@@ -0,0 +1,187 @@
+//! The transcoding of the messages of the services from and to JSON, such as for a small HTTP
+//! shim, say of axum, to front the same service implementation as the gRPC server.
+//!
+//! With `serde(true)` of `volo-build`, the generated messages derive `serde::{Serialize,
+//! Deserialize}`, and the request enum of every service, such as `ItemServiceRequestRecv`,
+//! implements [`FromJson`], and its response enum, such as `ItemServiceResponseSend`, implements
+//! [`IntoJson`], both by the paths of the methods. [`call`] calls the service, such as
+//! `ItemServiceServer::service(S)`, by the JSON of the request with the path of the method.
+//!
+//! The JSON of a message is by its serde derives, with the field names of the generated code. The
+//! client streaming requests and the server streaming responses are the JSON arrays of their
+//! messages.
+//!
+//! The invalid JSON of the requests fails with `INVALID_ARGUMENT`, and the unknown paths with
+//! `UNIMPLEMENTED`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use axum::{body::Bytes, extract::Path, http::HeaderMap, routing::post, Router};
+//!
+//! async fn handle(Path(method): Path<String>, headers: HeaderMap, body: Bytes) -> Vec<u8> {
+//!     let mut service = ItemServiceServer::service(S);
+//!     let path = format!("/volo.example.ItemService/{}", method);
+//!     let req = volo_grpc::Request::from_parts(
+//!         volo_grpc::metadata::MetadataMap::from_headers(headers),
+//!         Default::default(),
+//!         body,
+//!     );
+//!     let mut cx = volo_grpc::context::ServerContext::default();
+//!     match volo_grpc::json::call(&mut service, &mut cx, &path, req).await {
+//!         Ok(resp) => resp.into_inner().to_vec(),
+//!         Err(status) => status.message().as_bytes().to_vec(),
+//!     }
+//! }
+//!
+//! let app = Router::new().route("/item/:method", post(handle));
+//! ```
+
+use bytes::Bytes;
+pub use futures::future::BoxFuture;
+use futures::{stream, TryStreamExt};
+use motore::Service;
+use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    codec::decode::Kind, context::ServerContext, BoxStream, RecvStream, Request, Response, Status,
+};
+
+/// Decodes the request of a method from its JSON, implemented by the generated request enums.
+pub trait FromJson: Sized {
+    fn from_json(method: &str, json: &[u8]) -> Result<Self, Status>;
+}
+
+/// Encodes the response of a method into its JSON, implemented by the generated response enums.
+pub trait IntoJson {
+    fn into_json(self) -> BoxFuture<'static, Result<Bytes, Status>>;
+}
+
+/// Decodes the JSON of a message into the stream of it, as the request of a method.
+pub fn decode<T>(json: &[u8]) -> Result<RecvStream<T>, Status>
+where
+    T: DeserializeOwned + Message + Send + 'static,
+{
+    Ok(into_stream(vec![from_json::<T>(json)?]))
+}
+
+/// Decodes the JSON array of the messages into the stream of them, as the request of a client
+/// streaming method.
+pub fn decode_stream<T>(json: &[u8]) -> Result<RecvStream<T>, Status>
+where
+    T: DeserializeOwned + Message + Send + 'static,
+{
+    Ok(into_stream(from_json::<Vec<T>>(json)?))
+}
+
+/// Encodes the message of the response stream into its JSON.
+pub async fn encode<T: Serialize>(
+    mut messages: BoxStream<'static, Result<T, Status>>,
+) -> Result<Bytes, Status> {
+    let message = messages
+        .try_next()
+        .await?
+        .ok_or_else(|| Status::internal("Missing response message."))?;
+    to_json(&message)
+}
+
+/// Encodes the messages of the response stream into the JSON array of them, as the response of a
+/// server streaming method.
+pub async fn encode_stream<T: Serialize>(
+    messages: BoxStream<'static, Result<T, Status>>,
+) -> Result<Bytes, Status> {
+    let messages = messages.try_collect::<Vec<_>>().await?;
+    to_json(&messages)
+}
+
+/// Calls the service by the JSON of the request with the path of the method, such as
+/// `/volo.example.ItemService/GetItem`, and responds with the JSON of the response.
+///
+/// The method of the context is set to the path, the rest of it, such as the caller, is of the
+/// shim to set.
+pub async fn call<S, T, U>(
+    service: &mut S,
+    cx: &mut ServerContext,
+    path: &str,
+    req: Request<Bytes>,
+) -> Result<Response<Bytes>, Status>
+where
+    S: Service<ServerContext, Request<T>, Response = Response<U>, Error = Status>,
+    T: FromJson,
+    U: IntoJson,
+{
+    cx.rpc_info.method = Some(path.into());
+    let (metadata, extensions, json) = req.into_parts();
+    let req = Request::from_parts(metadata, extensions, T::from_json(path, &json)?);
+    let (metadata, extensions, message) = service.call(cx, req).await?.into_parts();
+    Ok(Response::from_parts(
+        metadata,
+        extensions,
+        message.into_json().await?,
+    ))
+}
+
+fn from_json<T: DeserializeOwned>(json: &[u8]) -> Result<T, Status> {
+    serde_json::from_slice(json)
+        .map_err(|e| Status::invalid_argument(format!("invalid json message: {}", e)))
+}
+
+fn to_json<T: Serialize>(message: &T) -> Result<Bytes, Status> {
+    serde_json::to_vec(message)
+        .map(Bytes::from)
+        .map_err(|e| Status::internal(format!("failed to encode json message: {}", e)))
+}
+
+/// The messages as the stream decoded from the body, the same as the ones received by the server.
+fn into_stream<T>(messages: Vec<T>) -> RecvStream<T>
+where
+    T: Message + Send + 'static,
+{
+    let body = hyper::Body::wrap_stream(crate::codec::encode::encode(stream::iter(
+        messages.into_iter().map(Ok),
+    )));
+    RecvStream::new(body, Kind::Request)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::Code;
+
+    #[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+    struct Item {
+        #[prost(int64, tag = "1")]
+        id: i64,
+        #[prost(string, tag = "2")]
+        title: String,
+    }
+
+    #[tokio::test]
+    async fn test_transcode() {
+        let mut stream = decode::<Item>(br#"{"id":1,"title":"hello"}"#).unwrap();
+        let item = stream.next().await.unwrap().unwrap();
+        assert_eq!(item.id, 1);
+        assert_eq!(item.title, "hello");
+        assert!(stream.next().await.is_none());
+
+        let json = encode(Box::pin(stream::once(async move { Ok(item) })))
+            .await
+            .unwrap();
+        assert_eq!(&json[..], br#"{"id":1,"title":"hello"}"#);
+
+        let items = decode_stream::<Item>(br#"[{"id":1,"title":"a"},{"id":2,"title":"b"}]"#)
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(items.len(), 2);
+        let json = encode_stream(Box::pin(stream::iter(items))).await.unwrap();
+        assert_eq!(&json[..], br#"[{"id":1,"title":"a"},{"id":2,"title":"b"}]"#);
+
+        let status = decode::<Item>(b"{").unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+}
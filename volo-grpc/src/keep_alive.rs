@@ -0,0 +1,135 @@
+//! Keeps the long running unary-like calls alive by the heartbeats.
+//!
+//! The proxies and the load balancers between the client and the server usually close the
+//! streams idle for a while, which a call computing its response for minutes is. Such a call can
+//! be declared as a server streaming method instead, whose response message has a `bool
+//! heartbeat` field:
+//!
+//! ```protobuf
+//! message ExportResponse {
+//!     bool heartbeat = 1;
+//!     string url = 2;
+//! }
+//!
+//! service ExportService {
+//!     rpc Export(ExportRequest) returns (stream ExportResponse);
+//! }
+//! ```
+//!
+//! The server then returns the stream of [`keep_alive`], which sends a heartbeat message every
+//! interval until the computation finishes, followed by its result. For such methods the
+//! generated client has an `_unary` variant as well, `export_unary` here, which skips the
+//! heartbeats by [`final_message`] and returns the result as a unary call does.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use std::time::Duration;
+//!
+//! use volo_grpc::keep_alive::keep_alive;
+//!
+//! // in the handler
+//! let stream = keep_alive(
+//!     Duration::from_secs(10),
+//!     || ExportResponse { heartbeat: true, ..Default::default() },
+//!     async move { export(req.into_inner()).await },
+//! );
+//! Ok(Response::new(stream))
+//!
+//! // on the client side
+//! let resp = client.export_unary(req).await?;
+//! ```
+
+use std::{future::Future, time::Duration};
+
+use futures::{
+    future::{self, Either},
+    Stream, StreamExt,
+};
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::{BoxStream, Status};
+
+/// Returns the stream of a heartbeat message made by `heartbeat` every `interval` until `fut`
+/// completes, followed by its result.
+pub fn keep_alive<T, H, F>(
+    interval: Duration,
+    mut heartbeat: H,
+    fut: F,
+) -> BoxStream<'static, Result<T, Status>>
+where
+    T: Send + 'static,
+    H: FnMut() -> T + Send + 'static,
+    F: Future<Output = Result<T, Status>> + Send + 'static,
+{
+    Box::pin(async_stream::stream! {
+        let mut fut = Box::pin(fut);
+        let mut ticker = tokio::time::interval_at(Instant::now() + interval, interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            let tick = Box::pin(ticker.tick());
+            match future::select(fut.as_mut(), tick).await {
+                Either::Left((result, _)) => {
+                    yield result;
+                    break;
+                }
+                Either::Right(_) => yield Ok(heartbeat()),
+            }
+        }
+    })
+}
+
+/// Returns the last message of the stream which isn't a heartbeat by `is_heartbeat`, as the
+/// result of a call kept alive by [`keep_alive`].
+///
+/// Fails with `Internal` if the stream ends with no result.
+pub async fn final_message<T, S>(
+    mut stream: S,
+    is_heartbeat: impl Fn(&T) -> bool,
+) -> Result<T, Status>
+where
+    S: Stream<Item = Result<T, Status>> + Unpin,
+{
+    let mut last = None;
+    while let Some(msg) = stream.next().await {
+        let msg = msg?;
+        if !is_heartbeat(&msg) {
+            last = Some(msg);
+        }
+    }
+    last.ok_or_else(|| Status::internal("the stream ended without a result"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Msg {
+        Heartbeat,
+        Result(u32),
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive() {
+        let stream = keep_alive(Duration::from_millis(10), || Msg::Heartbeat, async {
+            tokio::time::sleep(Duration::from_millis(55)).await;
+            Ok(Msg::Result(42))
+        });
+        let msgs: Vec<_> = stream.collect().await;
+        assert!(msgs.len() >= 3);
+        assert!(msgs[..msgs.len() - 1]
+            .iter()
+            .all(|m| m.as_ref().unwrap() == &Msg::Heartbeat));
+
+        let stream = futures::stream::iter(msgs);
+        let result = final_message(stream, |m| m == &Msg::Heartbeat).await;
+        assert_eq!(result.unwrap(), Msg::Result(42));
+
+        let stream = futures::stream::iter(vec![Ok(Msg::Heartbeat)]);
+        let status = final_message(stream, |m| m == &Msg::Heartbeat)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), crate::Code::Internal);
+    }
+}
@@ -0,0 +1,405 @@
+//! Authorizes the requests by a policy on the server side, per method of the identity of the
+//! caller.
+//!
+//! The identity is normalized into a [`Principal`], which is taken from the context extensions
+//! if inserted by the authentication layers before, such as from the verified TLS certificate or
+//! the claims of a JWT, or else from the first [`Extractor`] that finds one, such as
+//! [`TlsExtractor`] or [`MetadataExtractor`]. The [`Policy`] then decides whether the principal,
+//! `None` for the anonymous callers, may call the method, which can be done asynchronously by an
+//! external policy engine.
+//!
+//! The denied requests are rejected with `PermissionDenied`. Every decision is emitted as an
+//! audit event, logged and passed to the hook of [`AuthzLayer::audit`] if any, and the principal
+//! of an allowed request is in the context extensions for the handler.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::layer::authz::{AuthzLayer, Decision, MetadataExtractor, Principal};
+//!
+//! let layer = AuthzLayer::new(|principal: Option<&Principal>, method: &str| {
+//!     let decision = match principal {
+//!         Some(p) if p.id == "admin" || method.ends_with("/GetItem") => Decision::Allow,
+//!         _ => Decision::Deny("not allowed".into()),
+//!     };
+//!     async move { decision }
+//! })
+//! .extractor(MetadataExtractor::new("x-caller"));
+//!
+//! ItemServiceServer::new(S).layer(layer).run(addr).await;
+//!
+//! // in the handler
+//! let principal = cx.extensions().get::<Principal>();
+//! ```
+
+use std::{collections::HashMap, fmt, future::Future, sync::Arc};
+
+use futures::future::{BoxFuture, FutureExt};
+use motore::{layer::Layer, Service};
+use volo::{
+    context::{Context, Extensions},
+    net::conn::PeerIdentity,
+};
+
+use crate::{
+    metadata::{AsciiMetadataKey, MetadataMap},
+    Request, Status,
+};
+
+/// Where the identity of a [`Principal`] is from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PrincipalSource {
+    /// The verified certificate of the client by mTLS.
+    Tls,
+    /// The verified claims of a JWT.
+    Jwt,
+    /// The metadata of the request, which is trusted only behind a proxy authenticating it.
+    Metadata,
+    /// Any other source, by the custom extractors.
+    Custom,
+}
+
+/// The normalized identity of the caller, in the context extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    /// The id of the caller, such as the SAN of the certificate or the `sub` of the JWT.
+    pub id: String,
+    pub source: PrincipalSource,
+    /// The other attributes for the policy, such as the roles or the tenant.
+    pub attributes: HashMap<String, String>,
+}
+
+impl Principal {
+    pub fn new(id: impl Into<String>, source: PrincipalSource) -> Self {
+        Self {
+            id: id.into(),
+            source,
+            attributes: HashMap::new(),
+        }
+    }
+
+    /// Adds an attribute.
+    pub fn with_attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.attributes.insert(key.into(), value.into());
+        self
+    }
+
+    /// Returns the attribute of the key.
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes.get(key).map(|v| v.as_str())
+    }
+}
+
+impl fmt::Display for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}:{}", self.source, self.id)
+    }
+}
+
+/// Extracts the [`Principal`] of the request if not in the context extensions.
+pub trait Extractor: Send + Sync + 'static {
+    fn extract(&self, extensions: &Extensions, metadata: &MetadataMap) -> Option<Principal>;
+}
+
+impl<F> Extractor for F
+where
+    F: Fn(&Extensions, &MetadataMap) -> Option<Principal> + Send + Sync + 'static,
+{
+    fn extract(&self, extensions: &Extensions, metadata: &MetadataMap) -> Option<Principal> {
+        self(extensions, metadata)
+    }
+}
+
+/// Extracts the [`Principal`] from the value of the metadata key, which should be set only by a
+/// trusted proxy.
+#[derive(Debug, Clone)]
+pub struct MetadataExtractor {
+    key: AsciiMetadataKey,
+}
+
+impl MetadataExtractor {
+    /// Creates a new [`MetadataExtractor`] of the key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not a valid ascii metadata key.
+    pub fn new(key: &'static str) -> Self {
+        Self {
+            key: AsciiMetadataKey::from_static(key),
+        }
+    }
+}
+
+impl Extractor for MetadataExtractor {
+    fn extract(&self, _: &Extensions, metadata: &MetadataMap) -> Option<Principal> {
+        metadata
+            .get(&self.key)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .map(|v| Principal::new(v, PrincipalSource::Metadata))
+    }
+}
+
+/// Extracts the [`Principal`] from the certificate of the client verified by mTLS, the
+/// [`PeerIdentity`] in the context extensions.
+///
+/// The id is the first URI SAN, such as the SPIFFE id, or else the common name of the subject.
+/// The subject and the DNS SANs are in the attributes `subject` and `dns`, comma separated.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TlsExtractor;
+
+impl Extractor for TlsExtractor {
+    fn extract(&self, extensions: &Extensions, _: &MetadataMap) -> Option<Principal> {
+        let identity = extensions.get::<PeerIdentity>()?;
+        let id = identity
+            .uri_sans
+            .first()
+            .or(identity.common_name.as_ref())?;
+        let mut principal = Principal::new(id.as_str(), PrincipalSource::Tls)
+            .with_attribute("subject", identity.subject.as_str());
+        if !identity.dns_sans.is_empty() {
+            principal = principal.with_attribute("dns", identity.dns_sans.join(","));
+        }
+        Some(principal)
+    }
+}
+
+/// The decision of the [`Policy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// Denies the request, with the reason sent to the client.
+    Deny(String),
+}
+
+/// Decides whether the principal may call the method.
+pub trait Policy: Send + Sync + 'static {
+    /// Returns the decision of the principal, `None` if anonymous, calling `method`, which is the
+    /// path of the request, such as `/volo.example.ItemService/GetItem`.
+    fn decide(&self, principal: Option<&Principal>, method: &str) -> BoxFuture<'static, Decision>;
+}
+
+impl<F, Fut> Policy for F
+where
+    F: Fn(Option<&Principal>, &str) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Decision> + Send + 'static,
+{
+    fn decide(&self, principal: Option<&Principal>, method: &str) -> BoxFuture<'static, Decision> {
+        self(principal, method).boxed()
+    }
+}
+
+/// A decision of the [`Policy`], for the auditing.
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub principal: Option<Principal>,
+    pub method: String,
+    pub decision: Decision,
+}
+
+type AuditHook = Arc<dyn Fn(&AuditEvent) + Send + Sync>;
+
+#[derive(Clone)]
+struct Shared {
+    policy: Arc<dyn Policy>,
+    extractors: Vec<Arc<dyn Extractor>>,
+    audit: Option<AuditHook>,
+}
+
+/// A [`Service`] that authorizes the requests by the policy.
+pub struct AuthzService<S> {
+    inner: S,
+    shared: Arc<Shared>,
+}
+
+impl<S: Clone> Clone for AuthzService<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<Cx, T, S> Service<Cx, Request<T>> for AuthzService<S>
+where
+    Cx: Context + 'static + Send,
+    T: 'static + Send,
+    S: Service<Cx, Request<T>, Error = Status> + 'static + Send,
+{
+    type Response = S::Response;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Request<T>) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let principal = cx.extensions().get::<Principal>().cloned().or_else(|| {
+                self.shared
+                    .extractors
+                    .iter()
+                    .find_map(|e| e.extract(cx.extensions(), req.metadata()))
+            });
+            let method = cx
+                .rpc_info()
+                .method()
+                .map_or_else(String::new, |m| m.to_string());
+            let decision = self.shared.policy.decide(principal.as_ref(), &method).await;
+
+            let who = principal
+                .as_ref()
+                .map_or_else(|| "anonymous".to_string(), |p| p.to_string());
+            match &decision {
+                Decision::Allow => {
+                    tracing::debug!(
+                        "[VOLO] authz allowed, principal: {}, method: {}",
+                        who,
+                        method
+                    )
+                }
+                Decision::Deny(reason) => tracing::warn!(
+                    "[VOLO] authz denied, principal: {}, method: {}, reason: {}",
+                    who,
+                    method,
+                    reason
+                ),
+            }
+            let event = AuditEvent {
+                principal,
+                method,
+                decision,
+            };
+            if let Some(audit) = &self.shared.audit {
+                audit(&event);
+            }
+
+            match event.decision {
+                Decision::Allow => {
+                    if let Some(principal) = event.principal {
+                        cx.extensions_mut().insert(principal);
+                    }
+                    self.inner.call(cx, req).await
+                }
+                Decision::Deny(reason) => Err(Status::permission_denied(reason)),
+            }
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`AuthzService`].
+///
+/// The layer should be inside the authentication layers, which insert the [`Principal`].
+#[derive(Clone)]
+pub struct AuthzLayer {
+    shared: Shared,
+}
+
+impl AuthzLayer {
+    /// Creates a new [`AuthzLayer`] authorizing the requests by `policy`.
+    pub fn new(policy: impl Policy) -> Self {
+        Self {
+            shared: Shared {
+                policy: Arc::new(policy),
+                extractors: Vec::new(),
+                audit: None,
+            },
+        }
+    }
+
+    /// Adds an extractor of the principal, tried in the order added.
+    pub fn extractor(mut self, extractor: impl Extractor) -> Self {
+        self.shared.extractors.push(Arc::new(extractor));
+        self
+    }
+
+    /// Sets the hook called with every decision, such as to send it to the audit log.
+    pub fn audit(mut self, f: impl Fn(&AuditEvent) + Send + Sync + 'static) -> Self {
+        self.shared.audit = Some(Arc::new(f));
+        self
+    }
+}
+
+impl<S> Layer<S> for AuthzLayer {
+    type Service = AuthzService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        AuthzService {
+            inner,
+            shared: Arc::new(self.shared),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use motore::service::service_fn;
+
+    use super::*;
+    use crate::{context::ServerContext, status::Code};
+
+    async fn handler(cx: &mut ServerContext, _: Request<()>) -> Result<String, Status> {
+        Ok(cx
+            .extensions()
+            .get::<Principal>()
+            .map_or_else(String::new, |p| p.id.clone()))
+    }
+
+    #[tokio::test]
+    async fn test_authz() {
+        let denied = Arc::new(AtomicUsize::new(0));
+        let counter = denied.clone();
+        let layer = AuthzLayer::new(|principal: Option<&Principal>, _: &str| {
+            let decision = match principal {
+                Some(p) if p.id == "admin" => Decision::Allow,
+                _ => Decision::Deny("admin only".into()),
+            };
+            async move { decision }
+        })
+        .extractor(MetadataExtractor::new("x-caller"))
+        .audit(move |event: &AuditEvent| {
+            if matches!(event.decision, Decision::Deny(_)) {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        let mut service = layer.layer(service_fn(handler));
+
+        let mut cx = ServerContext::default();
+        let status = service.call(&mut cx, Request::new(())).await.unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(status.message(), "admin only");
+
+        let mut req = Request::new(());
+        req.metadata_mut()
+            .insert("x-caller", "admin".parse().unwrap());
+        let mut cx = ServerContext::default();
+        assert_eq!(service.call(&mut cx, req).await.unwrap(), "admin");
+
+        // the principal inserted by the authentication layers takes precedence
+        let mut req = Request::new(());
+        req.metadata_mut()
+            .insert("x-caller", "admin".parse().unwrap());
+        let mut cx = ServerContext::default();
+        cx.extensions_mut()
+            .insert(Principal::new("guest", PrincipalSource::Jwt));
+        service.call(&mut cx, req).await.unwrap_err();
+        assert_eq!(denied.load(Ordering::Relaxed), 2);
+
+        let mut extensions = Extensions::default();
+        extensions.insert(PeerIdentity {
+            subject: "CN=admin".into(),
+            common_name: Some("admin".into()),
+            ..Default::default()
+        });
+        let principal = TlsExtractor
+            .extract(&extensions, &MetadataMap::new())
+            .unwrap();
+        assert_eq!(
+            principal,
+            Principal::new("admin", PrincipalSource::Tls).with_attribute("subject", "CN=admin")
+        );
+    }
+}
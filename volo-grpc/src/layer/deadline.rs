@@ -0,0 +1,205 @@
+//! Deadline propagation to the downstream calls.
+//!
+//! On the server side, [`DeadlineLayer`] stores the [`Deadline`] of the request, which is set by
+//! the server from the `grpc-timeout` of the client, in the task local [`metainfo::METAINFO`].
+//! On the client side, the calls made in the same task then inherit the remaining budget minus the
+//! margin of the layer, sent as their `grpc-timeout`, so that the downstream gives up as soon as
+//! the upstream does. The calls are failed with `DeadlineExceeded` without being sent if nothing
+//! is left.
+//!
+//! The requests with a timeout set by [`Request::set_timeout`] use theirs instead, and the ones
+//! with a [`Deadline`] in the context extensions of the client, such as by the rpc timeout of the
+//! client, use the earlier one of theirs and the inherited one.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use std::time::Duration;
+//!
+//! use volo_grpc::layer::deadline::DeadlineLayer;
+//!
+//! // server
+//! ItemServiceServer::new(S).layer(DeadlineLayer::new()).run(addr).await;
+//!
+//! // client, leaves 10ms to the handler for the rest of its work
+//! ItemServiceClientBuilder::new("item")
+//!     .layer(DeadlineLayer::new().margin(Duration::from_millis(10)))
+//!     .build();
+//! ```
+
+use std::{cell::RefCell, future::Future, time::Duration};
+
+use motore::{layer::Layer, Service};
+use volo::context::{Context, Deadline, Role};
+
+use crate::{Request, Status};
+
+/// A [`Service`] that propagates the deadline of the request to the downstream calls.
+#[derive(Clone)]
+pub struct DeadlineService<S> {
+    inner: S,
+    margin: Duration,
+}
+
+impl<S> DeadlineService<S> {
+    /// Returns the deadline of the downstream call, the earlier one of its own and the inherited
+    /// one brought forward by the margin.
+    fn deadline_of<Cx: Context>(&self, cx: &Cx) -> Option<Deadline> {
+        let inherited = metainfo::METAINFO
+            .try_with(|mi| mi.borrow().get::<Deadline>().copied())
+            .ok()
+            .flatten()
+            .map(|Deadline(d)| Deadline(d.checked_sub(self.margin).unwrap_or(d)));
+        match (cx.extensions().get::<Deadline>().copied(), inherited) {
+            (Some(own), Some(inherited)) => Some(Deadline(own.0.min(inherited.0))),
+            (own, inherited) => own.or(inherited),
+        }
+    }
+}
+
+impl<Cx, T, S> Service<Cx, Request<T>> for DeadlineService<S>
+where
+    Cx: Context + 'static + Send,
+    T: 'static + Send,
+    S: Service<Cx, Request<T>, Error = Status> + 'static + Send,
+{
+    type Response = S::Response;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, mut req: Request<T>) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let role = cx.rpc_info().role();
+            match role {
+                Role::Server => {
+                    let deadline = cx.extensions().get::<Deadline>().copied();
+                    let deadline = match deadline {
+                        Some(deadline) => deadline,
+                        None => return self.inner.call(cx, req).await,
+                    };
+                    if metainfo::METAINFO.try_with(|_| {}).is_ok() {
+                        metainfo::METAINFO.with(|mi| mi.borrow_mut().insert(deadline));
+                        self.inner.call(cx, req).await
+                    } else {
+                        let mut mi = metainfo::MetaInfo::default();
+                        mi.insert(deadline);
+                        metainfo::METAINFO
+                            .scope(RefCell::new(mi), self.inner.call(cx, req))
+                            .await
+                    }
+                }
+                Role::Client => {
+                    if matches!(req.metadata().grpc_timeout(), Ok(Some(_))) {
+                        return self.inner.call(cx, req).await;
+                    }
+                    let deadline = self.deadline_of(&*cx);
+                    let deadline = match deadline {
+                        Some(deadline) => deadline,
+                        None => return self.inner.call(cx, req).await,
+                    };
+                    let remaining = deadline
+                        .0
+                        .saturating_duration_since(std::time::Instant::now());
+                    if remaining.is_zero() {
+                        return Err(Status::deadline_exceeded(
+                            "deadline exceeded before the call",
+                        ));
+                    }
+                    req.set_timeout(remaining);
+                    // bounds the call locally as well, such as by `volo::layer::TimeoutLayer`
+                    cx.extensions_mut().insert(deadline);
+                    self.inner.call(cx, req).await
+                }
+            }
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`DeadlineService`], with no margin by default.
+#[derive(Clone, Copy, Default)]
+pub struct DeadlineLayer {
+    margin: Duration,
+}
+
+impl DeadlineLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the margin subtracted from the inherited budget of the downstream calls, such as the
+    /// time the handler needs to build its response after them.
+    pub fn margin(mut self, margin: Duration) -> Self {
+        self.margin = margin;
+        self
+    }
+}
+
+impl<S> Layer<S> for DeadlineLayer {
+    type Service = DeadlineService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        DeadlineService {
+            inner,
+            margin: self.margin,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use motore::service::service_fn;
+
+    use super::*;
+    use crate::{context::ClientContext, status::Code};
+
+    async fn handler(_: &mut ClientContext, req: Request<()>) -> Result<Duration, Status> {
+        Ok(req.metadata().grpc_timeout().unwrap().unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let mut service = DeadlineLayer::new()
+            .margin(Duration::from_millis(100))
+            .layer(service_fn(handler));
+
+        let mut mi = metainfo::MetaInfo::default();
+        mi.insert(Deadline(Instant::now() + Duration::from_secs(1)));
+        metainfo::METAINFO
+            .scope(RefCell::new(mi), async {
+                let mut cx = ClientContext::default();
+                let timeout = service.call(&mut cx, Request::new(())).await.unwrap();
+                assert!(timeout <= Duration::from_millis(900));
+                assert!(timeout > Duration::from_millis(800));
+                assert!(cx.extensions().get::<Deadline>().is_some());
+
+                // the timeout set explicitly is kept
+                let req = Request::new(()).with_timeout(Duration::from_secs(5));
+                let mut cx = ClientContext::default();
+                let timeout = service.call(&mut cx, req).await.unwrap();
+                assert_eq!(timeout, Duration::from_secs(5));
+
+                // the earlier one of the own deadline, such as by the rpc timeout
+                let mut cx = ClientContext::default();
+                cx.extensions_mut()
+                    .insert(Deadline(Instant::now() + Duration::from_millis(200)));
+                let timeout = service.call(&mut cx, Request::new(())).await.unwrap();
+                assert!(timeout <= Duration::from_millis(200));
+            })
+            .await;
+
+        let mut mi = metainfo::MetaInfo::default();
+        mi.insert(Deadline(Instant::now() + Duration::from_millis(50)));
+        metainfo::METAINFO
+            .scope(RefCell::new(mi), async {
+                let mut cx = ClientContext::default();
+                let status = service.call(&mut cx, Request::new(())).await.unwrap_err();
+                assert_eq!(status.code(), Code::DeadlineExceeded);
+            })
+            .await;
+    }
+}
@@ -0,0 +1,210 @@
+//! Maps the errors and the panics of the handlers to [`Status`] in one place on the server side,
+//! instead of converting them in every handler.
+//!
+//! The handlers return their application errors as the source of a [`Status`], such as by
+//! [`Status::from_error`], and the [`ErrorHandler`] downcasts the source to decide the code and
+//! the details sent to the client. A panic of the handler, or of the layers inside, is caught and
+//! mapped as well, instead of resetting the stream. Every mapped error is logged as an error
+//! event with the method and the code.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use std::error::Error;
+//!
+//! use volo_grpc::{
+//!     layer::error_handler::{ErrorHandlerLayer, HandlerError},
+//!     Status,
+//! };
+//!
+//! let layer = ErrorHandlerLayer::new(|_method: &str, err: HandlerError| async move {
+//!     match err {
+//!         HandlerError::Status(status) => match status.source().and_then(|e| e.downcast_ref()) {
+//!             Some(DbError::NotFound) => Status::not_found("no such item"),
+//!             _ => status,
+//!         },
+//!         HandlerError::Panic(_) => Status::internal("internal error"),
+//!     }
+//! });
+//!
+//! ItemServiceServer::new(S).layer(layer).run(addr).await;
+//! ```
+
+use std::{any::Any, future::Future, panic::AssertUnwindSafe, sync::Arc};
+
+use futures::future::{BoxFuture, FutureExt};
+use motore::{layer::Layer, Service};
+use volo::context::Context;
+
+use crate::Status;
+
+/// An error of a handler to be mapped.
+#[derive(Debug)]
+pub enum HandlerError {
+    /// The handler returned the status, with the application error as the source if any.
+    Status(Status),
+    /// The handler panicked with the message.
+    Panic(String),
+}
+
+/// Maps the errors of the handlers to the status sent to the clients.
+pub trait ErrorHandler: Send + Sync + 'static {
+    /// Returns the status of the error of the handler of `method`.
+    fn handle(&self, method: &str, err: HandlerError) -> BoxFuture<'static, Status>;
+}
+
+impl<F, Fut> ErrorHandler for F
+where
+    F: Fn(&str, HandlerError) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Status> + Send + 'static,
+{
+    fn handle(&self, method: &str, err: HandlerError) -> BoxFuture<'static, Status> {
+        self(method, err).boxed()
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(msg) => msg.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+/// A [`Service`] that maps the errors and the panics of the inner service by the handler.
+pub struct ErrorHandlerService<S> {
+    inner: S,
+    handler: Arc<dyn ErrorHandler>,
+}
+
+impl<S: Clone> Clone for ErrorHandlerService<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+impl<Cx, Req, S> Service<Cx, Req> for ErrorHandlerService<S>
+where
+    Cx: Context + 'static + Send,
+    Req: 'static + Send,
+    S: Service<Cx, Req, Error = Status> + 'static + Send,
+{
+    type Response = S::Response;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let err = match AssertUnwindSafe(self.inner.call(cx, req))
+                .catch_unwind()
+                .await
+            {
+                Ok(Ok(resp)) => return Ok(resp),
+                Ok(Err(status)) => HandlerError::Status(status),
+                Err(payload) => HandlerError::Panic(panic_message(payload)),
+            };
+            let method = cx.rpc_info().method().map_or("", |m| m.as_str());
+            let status = self.handler.handle(method, err).await;
+            tracing::error!(
+                "[VOLO] handler error, method: {}, code: {:?}, message: {}",
+                method,
+                status.code(),
+                status.message()
+            );
+            Err(status)
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`ErrorHandlerService`].
+///
+/// The layer should be the outermost one, so that the errors and the panics of the other layers
+/// are mapped as well.
+#[derive(Clone)]
+pub struct ErrorHandlerLayer {
+    handler: Arc<dyn ErrorHandler>,
+}
+
+impl ErrorHandlerLayer {
+    /// Creates a new [`ErrorHandlerLayer`] mapping the errors by `handler`.
+    pub fn new(handler: impl ErrorHandler) -> Self {
+        Self {
+            handler: Arc::new(handler),
+        }
+    }
+}
+
+impl<S> Layer<S> for ErrorHandlerLayer {
+    type Service = ErrorHandlerService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        ErrorHandlerService {
+            inner,
+            handler: self.handler,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, fmt};
+
+    use motore::service::service_fn;
+
+    use super::*;
+    use crate::{context::ServerContext, status::Code};
+
+    #[derive(Debug)]
+    struct NotFound;
+
+    impl fmt::Display for NotFound {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("not found")
+        }
+    }
+
+    impl Error for NotFound {}
+
+    async fn not_found(_: &mut ServerContext, _: ()) -> Result<(), Status> {
+        Err(Status::from_error(Box::new(NotFound)))
+    }
+
+    async fn panics(_: &mut ServerContext, _: ()) -> Result<(), Status> {
+        panic!("boom")
+    }
+
+    fn layer() -> ErrorHandlerLayer {
+        ErrorHandlerLayer::new(|_: &str, err: HandlerError| async move {
+            match err {
+                HandlerError::Status(status) => {
+                    match status.source().and_then(|e| e.downcast_ref::<NotFound>()) {
+                        Some(_) => Status::not_found("no such item"),
+                        None => status,
+                    }
+                }
+                HandlerError::Panic(msg) => Status::internal(msg),
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_error_handler() {
+        let mut cx = ServerContext::default();
+        let mut service = layer().layer(service_fn(not_found));
+        let status = service.call(&mut cx, ()).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let mut service = layer().layer(service_fn(panics));
+        let status = service.call(&mut cx, ()).await.unwrap_err();
+        assert_eq!(status.code(), Code::Internal);
+        assert_eq!(status.message(), "boom");
+    }
+}
@@ -0,0 +1,317 @@
+//! Injects the faults into the requests for the chaos testing, such as to validate the retry and
+//! the timeout policies, on either the client or the server side.
+//!
+//! The faults are configured by the [`FaultConfig`] shared by a [`FaultHandle`], which toggles
+//! and changes them at runtime:
+//!
+//! - [`Abort`] fails the requests with the status before they reach the inner service.
+//! - [`Delay`] adds the latency before the requests reach the inner service.
+//! - [`Truncate`] ends the response streams with the status after some messages, only on the server
+//!   side, where the response messages are sent.
+//!
+//! Each fault applies to a percentage of the requests, 100 to all of them.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use std::time::Duration;
+//!
+//! use volo_grpc::{
+//!     layer::fault::{Abort, Delay, FaultConfig, FaultLayer},
+//!     Status,
+//! };
+//!
+//! let layer = FaultLayer::new(FaultConfig {
+//!     abort: Some(Abort {
+//!         percent: 10.0,
+//!         status: Status::unavailable("injected"),
+//!     }),
+//!     delay: Some(Delay {
+//!         percent: 50.0,
+//!         duration: Duration::from_millis(200),
+//!     }),
+//!     ..Default::default()
+//! });
+//! let handle = layer.handle();
+//! let client = ItemServiceClientBuilder::new("item").layer(layer).build();
+//! // later, stops injecting the faults
+//! handle.update(|config| config.enabled = false);
+//! ```
+
+use std::{
+    future::Future,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use futures::StreamExt;
+use motore::{layer::Layer, Service};
+use rand::Rng;
+use volo::context::Context;
+
+use crate::{BoxStream, Status};
+
+/// Fails the requests with the status.
+#[derive(Debug, Clone)]
+pub struct Abort {
+    /// The percentage of the requests aborted, between 0 and 100.
+    pub percent: f64,
+    /// The status returned instead of calling the inner service.
+    pub status: Status,
+}
+
+/// Adds the latency to the requests.
+#[derive(Debug, Clone)]
+pub struct Delay {
+    /// The percentage of the requests delayed, between 0 and 100.
+    pub percent: f64,
+    /// The latency added.
+    pub duration: Duration,
+}
+
+/// Ends the response streams with the status after the messages.
+#[derive(Debug, Clone)]
+pub struct Truncate {
+    /// The percentage of the responses truncated, between 0 and 100.
+    pub percent: f64,
+    /// The messages sent before the status.
+    pub messages: usize,
+    /// The status ending the response stream.
+    pub status: Status,
+}
+
+/// The faults to inject.
+#[derive(Debug, Clone)]
+pub struct FaultConfig {
+    /// Whether to inject the faults.
+    pub enabled: bool,
+    /// The methods to inject the faults into, such as `/helloworld.Greeter/SayHello`, all the
+    /// methods if empty.
+    pub methods: Vec<String>,
+    /// Fails the requests.
+    pub abort: Option<Abort>,
+    /// Delays the requests, before they are aborted.
+    pub delay: Option<Delay>,
+    /// Truncates the responses, on the server side.
+    pub truncate: Option<Truncate>,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            methods: Vec::new(),
+            abort: None,
+            delay: None,
+            truncate: None,
+        }
+    }
+}
+
+/// The faults picked for a request.
+#[derive(Debug, Default)]
+struct Faults {
+    abort: Option<Status>,
+    delay: Option<Duration>,
+    truncate: Option<TruncateResponse>,
+}
+
+fn hit(percent: f64) -> bool {
+    percent >= 100.0 || (percent > 0.0 && rand::thread_rng().gen::<f64>() * 100.0 < percent)
+}
+
+impl FaultConfig {
+    fn pick(&self, method: &str) -> Faults {
+        if !self.enabled || (!self.methods.is_empty() && !self.methods.iter().any(|m| m == method))
+        {
+            return Faults::default();
+        }
+        Faults {
+            abort: self
+                .abort
+                .as_ref()
+                .filter(|a| hit(a.percent))
+                .map(|a| a.status.clone()),
+            delay: self
+                .delay
+                .as_ref()
+                .filter(|d| hit(d.percent))
+                .map(|d| d.duration),
+            truncate: self
+                .truncate
+                .as_ref()
+                .filter(|t| hit(t.percent))
+                .map(|t| TruncateResponse {
+                    messages: t.messages,
+                    status: t.status.clone(),
+                }),
+        }
+    }
+}
+
+/// Truncates the response stream, put in the extensions of the server context by the
+/// [`FaultService`].
+#[derive(Debug, Clone)]
+pub(crate) struct TruncateResponse {
+    messages: usize,
+    status: Status,
+}
+
+impl TruncateResponse {
+    pub(crate) fn apply<T: Send + 'static>(
+        self,
+        mut stream: BoxStream<'static, Result<T, Status>>,
+    ) -> BoxStream<'static, Result<T, Status>> {
+        Box::pin(async_stream::stream! {
+            let mut sent = 0;
+            while let Some(item) = stream.next().await {
+                if sent == self.messages {
+                    yield Err(self.status);
+                    return;
+                }
+                sent += 1;
+                yield item;
+            }
+        })
+    }
+}
+
+/// The handle to toggle and change the faults of a layer at runtime.
+#[derive(Debug, Clone)]
+pub struct FaultHandle(Arc<RwLock<FaultConfig>>);
+
+impl FaultHandle {
+    /// Returns the current config.
+    pub fn config(&self) -> FaultConfig {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Updates the config, which applies to the following requests.
+    pub fn update(&self, f: impl FnOnce(&mut FaultConfig)) {
+        f(&mut self.0.write().unwrap());
+    }
+}
+
+/// A [`Service`] that injects the faults into the requests.
+#[derive(Clone)]
+pub struct FaultService<S> {
+    inner: S,
+    handle: FaultHandle,
+}
+
+impl<Cx, Req, S> Service<Cx, Req> for FaultService<S>
+where
+    Cx: Context + 'static + Send,
+    Req: 'static + Send,
+    S: Service<Cx, Req, Error = Status> + 'static + Send,
+{
+    type Response = S::Response;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        let method = cx.rpc_info().method().map_or("", |m| m.as_str());
+        let faults = self.handle.0.read().unwrap().pick(method);
+        async move {
+            if let Some(delay) = faults.delay {
+                tokio::time::sleep(delay).await;
+            }
+            if let Some(status) = faults.abort {
+                tracing::debug!(
+                    "[VOLO] request aborted by fault injection, method: {:?}",
+                    cx.rpc_info().method()
+                );
+                return Err(status);
+            }
+            if let Some(truncate) = faults.truncate {
+                cx.extensions_mut().insert(truncate);
+            }
+            self.inner.call(cx, req).await
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`FaultService`].
+#[derive(Clone)]
+pub struct FaultLayer {
+    handle: FaultHandle,
+}
+
+impl FaultLayer {
+    /// Creates a new [`FaultLayer`] injecting the faults of the config.
+    pub fn new(config: FaultConfig) -> Self {
+        Self {
+            handle: FaultHandle(Arc::new(RwLock::new(config))),
+        }
+    }
+
+    /// Returns the handle shared by all the services made by this layer.
+    pub fn handle(&self) -> FaultHandle {
+        self.handle.clone()
+    }
+}
+
+impl<S> Layer<S> for FaultLayer {
+    type Service = FaultService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        FaultService {
+            inner,
+            handle: self.handle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::Code;
+
+    #[test]
+    fn test_pick() {
+        let mut config = FaultConfig {
+            methods: vec!["/a".to_string()],
+            abort: Some(Abort {
+                percent: 100.0,
+                status: Status::unavailable("injected"),
+            }),
+            delay: Some(Delay {
+                percent: 0.0,
+                duration: Duration::from_secs(1),
+            }),
+            ..Default::default()
+        };
+        let faults = config.pick("/a");
+        assert_eq!(faults.abort.unwrap().code(), Code::Unavailable);
+        assert!(faults.delay.is_none());
+        assert!(config.pick("/b").abort.is_none());
+
+        config.enabled = false;
+        assert!(config.pick("/a").abort.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_truncate() {
+        let truncate = TruncateResponse {
+            messages: 2,
+            status: Status::unavailable("truncated"),
+        };
+        let items: Vec<_> = truncate
+            .clone()
+            .apply(Box::pin(futures::stream::iter((0..5).map(Ok))))
+            .collect()
+            .await;
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[2].as_ref().unwrap_err().code(), Code::Unavailable);
+
+        // the shorter streams are kept as they are
+        let items: Vec<_> = truncate
+            .apply(Box::pin(futures::stream::iter((0..2).map(Ok))))
+            .collect()
+            .await;
+        assert!(items.iter().all(Result::is_ok));
+    }
+}
@@ -55,35 +55,68 @@ impl<S> Layer<S> for GrpcTimeoutLayer {
 ///  Ok(Some(duration)) => if parse success.
 ///  Ok(None)           => if no success field.
 ///  Err(&HeaderValue)  => if parse timeout failed or wrong format.
-fn try_parse_client_timeout(
+pub(crate) fn try_parse_client_timeout(
     headers: &HeaderMap<HeaderValue>,
 ) -> Result<Option<Duration>, &HeaderValue> {
+    match headers.get(crate::metadata::GRPC_TIMEOUT_HEADER) {
+        Some(val) => match val.to_str().ok().and_then(parse_grpc_timeout) {
+            Some(duration) => Ok(Some(duration)),
+            None => Err(val),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Parse the value of 'grpc-timeout' header, `None` if it's in wrong format.
+pub(crate) fn parse_grpc_timeout(s: &str) -> Option<Duration> {
     const SECONDS_HOUR: u64 = 60 * 60;
     const SECONDS_MINUTE: u64 = 60;
 
-    match headers.get(crate::metadata::GRPC_TIMEOUT_HEADER) {
-        Some(val) => {
-            // parse the value and unit
-            let (timeout_value, timeout_unit) = val
-                .to_str()
-                .map_err(|_| val)
-                .and_then(|s| if s.is_empty() { Err(val) } else { Ok(s) })?
-                .split_at(val.len() - 1);
-            let timeout_value = timeout_value.parse::<u64>().map_err(|_| val)?;
-            // match the unit with Hour | Minute | Second | Milliseconds | Microsecond | Nanosecond
-            let duration = match timeout_unit {
-                "H" => Duration::from_secs(timeout_value * SECONDS_HOUR),
-                "M" => Duration::from_secs(timeout_value * SECONDS_MINUTE),
-                "S" => Duration::from_secs(timeout_value),
-                "m" => Duration::from_millis(timeout_value),
-                "u" => Duration::from_micros(timeout_value),
-                "n" => Duration::from_nanos(timeout_value),
-                _ => return Err(val),
-            };
-            Ok(Some(duration))
+    if s.is_empty() || !s.is_ascii() {
+        return None;
+    }
+    // parse the value and unit
+    let (timeout_value, timeout_unit) = s.split_at(s.len() - 1);
+    let timeout_value = timeout_value.parse::<u64>().ok()?;
+    // match the unit with Hour | Minute | Second | Milliseconds | Microsecond | Nanosecond
+    let duration = match timeout_unit {
+        "H" => Duration::from_secs(timeout_value * SECONDS_HOUR),
+        "M" => Duration::from_secs(timeout_value * SECONDS_MINUTE),
+        "S" => Duration::from_secs(timeout_value),
+        "m" => Duration::from_millis(timeout_value),
+        "u" => Duration::from_micros(timeout_value),
+        "n" => Duration::from_nanos(timeout_value),
+        _ => return None,
+    };
+    Some(duration)
+}
+
+/// Format the duration into the value of 'grpc-timeout' header.
+///
+/// The gRPC spec limits the value to at most 8 digits, so the most precise unit that fits is
+/// chosen.
+pub(crate) fn duration_to_grpc_timeout(duration: Duration) -> String {
+    fn try_format<T: Into<u128>>(
+        duration: Duration,
+        unit: char,
+        convert: impl FnOnce(Duration) -> T,
+    ) -> Option<String> {
+        const MAX_SIZE: u128 = 99_999_999;
+        let value = convert(duration).into();
+        if value > MAX_SIZE {
+            None
+        } else {
+            Some(format!("{}{}", value, unit))
         }
-        None => Ok(None),
     }
+
+    try_format(duration, 'n', |d| d.as_nanos())
+        .or_else(|| try_format(duration, 'u', |d| d.as_micros()))
+        .or_else(|| try_format(duration, 'm', |d| d.as_millis()))
+        .or_else(|| try_format(duration, 'S', |d| d.as_secs()))
+        .or_else(|| try_format(duration, 'M', |d| d.as_secs() / 60))
+        .or_else(|| try_format(duration, 'H', |d| d.as_secs() / 60 / 60))
+        .expect("duration is unrealistically large")
 }
 
 impl<Cx, S, ReqBody> Service<Cx, hyper::Request<ReqBody>> for GrpcTimeout<S>
@@ -224,6 +257,31 @@ mod tests {
         assert_eq!(Duration::from_nanos(82), parsed_duration);
     }
 
+    #[test]
+    fn test_duration_to_grpc_timeout() {
+        assert_eq!(duration_to_grpc_timeout(Duration::from_nanos(82)), "82n");
+        assert_eq!(
+            duration_to_grpc_timeout(Duration::from_millis(13)),
+            "13000000n"
+        );
+        assert_eq!(
+            duration_to_grpc_timeout(Duration::from_secs(42)),
+            "42000000u"
+        );
+        assert_eq!(
+            duration_to_grpc_timeout(Duration::from_secs(3 * 60 * 60)),
+            "10800000m"
+        );
+
+        let duration = Duration::from_millis(1234);
+        let mut hm = HeaderMap::new();
+        hm.insert(
+            GRPC_TIMEOUT_HEADER,
+            HeaderValue::from_str(&duration_to_grpc_timeout(duration)).unwrap(),
+        );
+        assert_eq!(try_parse_client_timeout(&hm).unwrap(), Some(duration));
+    }
+
     #[test]
     fn test_corner_cases() {
         // error postfix
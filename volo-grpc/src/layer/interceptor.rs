@@ -0,0 +1,270 @@
+//! Interceptors of the metadata and the extensions of the requests, on both sides.
+//!
+//! An interceptor sees the requests of all the methods without the generated request types, so
+//! it's for the cross-cutting concerns such as the auth tokens, the tracing headers and the
+//! tenants. It may change the metadata and the extensions, the latter for the handlers on the
+//! server side, or reject the request with its status.
+//!
+//! The synchronous interceptors are the closures, added by `ClientBuilder::intercept` and
+//! [`Server::intercept`](crate::server::Server::intercept). The asynchronous ones, such as
+//! querying a token service, implement [`AsyncInterceptor`], added by `intercept_async`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::{metadata::MetadataMap, Status};
+//!
+//! // client
+//! ItemServiceClientBuilder::new("item")
+//!     .intercept(|metadata: &mut MetadataMap, _: &mut _| {
+//!         metadata.insert("authorization", "Bearer token".parse().unwrap());
+//!         Ok(())
+//!     })
+//!     .build();
+//!
+//! // server
+//! Server::new(ItemServiceServer::new(S))
+//!     .intercept(|metadata: &mut MetadataMap, extensions: &mut _| {
+//!         match metadata.get("x-tenant-id") {
+//!             Some(tenant) => {
+//!                 extensions.insert(Tenant(tenant.to_str().unwrap().to_string()));
+//!                 Ok(())
+//!             }
+//!             None => Err(Status::unauthenticated("missing the tenant")),
+//!         }
+//!     })
+//!     .run(addr)
+//!     .await;
+//! ```
+
+use std::{future::Future, sync::Arc};
+
+use http::Extensions;
+use motore::{layer::Layer, Service};
+
+use crate::{metadata::MetadataMap, Request, Status};
+
+/// An asynchronous interceptor of the requests.
+pub trait AsyncInterceptor: Send + Sync + 'static {
+    type Future<'a>: Future<Output = Result<(), Status>> + Send + 'a;
+
+    /// Intercepts the metadata and the extensions of a request, the request is rejected by the
+    /// status returned.
+    fn intercept<'a>(
+        &'a self,
+        metadata: &'a mut MetadataMap,
+        extensions: &'a mut Extensions,
+    ) -> Self::Future<'a>;
+}
+
+/// A [`Service`] that intercepts the requests by a closure.
+pub struct InterceptorService<S, F> {
+    inner: S,
+    f: Arc<F>,
+}
+
+impl<S: Clone, F> Clone for InterceptorService<S, F> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            f: self.f.clone(),
+        }
+    }
+}
+
+impl<Cx, T, S, F> Service<Cx, Request<T>> for InterceptorService<S, F>
+where
+    Cx: 'static + Send,
+    T: 'static + Send,
+    S: Service<Cx, Request<T>, Error = Status> + 'static + Send,
+    F: Fn(&mut MetadataMap, &mut Extensions) -> Result<(), Status> + Send + Sync + 'static,
+{
+    type Response = S::Response;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Request<T>) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        let (mut metadata, mut extensions, message) = req.into_parts();
+        let result = (self.f)(&mut metadata, &mut extensions);
+        async move {
+            result?;
+            let req = Request::from_parts(metadata, extensions, message);
+            self.inner.call(cx, req).await
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`InterceptorService`].
+pub struct InterceptorLayer<F> {
+    f: Arc<F>,
+}
+
+impl<F> InterceptorLayer<F>
+where
+    F: Fn(&mut MetadataMap, &mut Extensions) -> Result<(), Status> + Send + Sync + 'static,
+{
+    /// Creates a new [`InterceptorLayer`] intercepting the requests by `f`.
+    pub fn new(f: F) -> Self {
+        Self { f: Arc::new(f) }
+    }
+}
+
+impl<F> Clone for InterceptorLayer<F> {
+    fn clone(&self) -> Self {
+        Self { f: self.f.clone() }
+    }
+}
+
+impl<S, F> Layer<S> for InterceptorLayer<F> {
+    type Service = InterceptorService<S, F>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        InterceptorService { inner, f: self.f }
+    }
+}
+
+/// A [`Service`] that intercepts the requests by an [`AsyncInterceptor`].
+pub struct AsyncInterceptorService<S, I> {
+    inner: S,
+    interceptor: Arc<I>,
+}
+
+impl<S: Clone, I> Clone for AsyncInterceptorService<S, I> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            interceptor: self.interceptor.clone(),
+        }
+    }
+}
+
+impl<Cx, T, S, I> Service<Cx, Request<T>> for AsyncInterceptorService<S, I>
+where
+    Cx: 'static + Send,
+    T: 'static + Send,
+    S: Service<Cx, Request<T>, Error = Status> + 'static + Send,
+    I: AsyncInterceptor,
+{
+    type Response = S::Response;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Request<T>) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let (mut metadata, mut extensions, message) = req.into_parts();
+            self.interceptor
+                .intercept(&mut metadata, &mut extensions)
+                .await?;
+            let req = Request::from_parts(metadata, extensions, message);
+            self.inner.call(cx, req).await
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`AsyncInterceptorService`].
+pub struct AsyncInterceptorLayer<I> {
+    interceptor: Arc<I>,
+}
+
+impl<I: AsyncInterceptor> AsyncInterceptorLayer<I> {
+    /// Creates a new [`AsyncInterceptorLayer`] intercepting the requests by `interceptor`.
+    pub fn new(interceptor: I) -> Self {
+        Self {
+            interceptor: Arc::new(interceptor),
+        }
+    }
+}
+
+impl<I> Clone for AsyncInterceptorLayer<I> {
+    fn clone(&self) -> Self {
+        Self {
+            interceptor: self.interceptor.clone(),
+        }
+    }
+}
+
+impl<S, I> Layer<S> for AsyncInterceptorLayer<I> {
+    type Service = AsyncInterceptorService<S, I>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        AsyncInterceptorService {
+            inner,
+            interceptor: self.interceptor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motore::service::service_fn;
+
+    use super::*;
+    use crate::{context::ServerContext, status::Code};
+
+    #[derive(Clone)]
+    struct Tenant(String);
+
+    async fn handler(_: &mut ServerContext, req: Request<()>) -> Result<String, Status> {
+        Ok(req.extensions().get::<Tenant>().unwrap().0.clone())
+    }
+
+    struct Tokens;
+
+    impl AsyncInterceptor for Tokens {
+        type Future<'a> = impl Future<Output = Result<(), Status>> + Send + 'a;
+
+        fn intercept<'a>(
+            &'a self,
+            metadata: &'a mut MetadataMap,
+            extensions: &'a mut Extensions,
+        ) -> Self::Future<'a> {
+            async move {
+                tokio::task::yield_now().await;
+                match metadata.get("authorization") {
+                    Some(_) => {
+                        extensions.insert(Tenant("async".to_string()));
+                        Ok(())
+                    }
+                    None => Err(Status::unauthenticated("missing the token")),
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interceptor() {
+        let mut service =
+            InterceptorLayer::new(|metadata: &mut MetadataMap, extensions: &mut Extensions| {
+                match metadata.get("x-tenant-id") {
+                    Some(tenant) => {
+                        extensions.insert(Tenant(tenant.to_str().unwrap().to_string()));
+                        Ok(())
+                    }
+                    None => Err(Status::unauthenticated("missing the tenant")),
+                }
+            })
+            .layer(service_fn(handler));
+
+        let mut cx = ServerContext::default();
+        let status = service.call(&mut cx, Request::new(())).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        let mut req = Request::new(());
+        req.metadata_mut()
+            .insert("x-tenant-id", "t".parse().unwrap());
+        assert_eq!(service.call(&mut cx, req).await.unwrap(), "t");
+
+        let mut service = AsyncInterceptorLayer::new(Tokens).layer(service_fn(handler));
+        let status = service.call(&mut cx, Request::new(())).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        let mut req = Request::new(());
+        req.metadata_mut()
+            .insert("authorization", "Bearer t".parse().unwrap());
+        assert_eq!(service.call(&mut cx, req).await.unwrap(), "async");
+    }
+}
@@ -0,0 +1,523 @@
+//! Validates the bearer tokens of the requests on the server side, by the keys of the JWKS
+//! endpoints of the issuers.
+//!
+//! The token is read from the `authorization` metadata as `Bearer <token>`, and verified by the
+//! key of its `kid` with the expiration, and the issuer and the audience if configured. The keys
+//! are cached and fetched again when they are older than the refresh interval, or when a token
+//! is signed by an unknown key, such as after the issuer rotated its keys, at most once per the
+//! minimum refresh interval.
+//!
+//! The verified [`Claims`] are in the context extensions for the handlers, together with the
+//! [`Principal`] of the `sub` claim for the [`AuthzLayer`]. The requests without a valid token
+//! are rejected with `Unauthenticated`, unless the token is optional.
+//!
+//! This module requires the `jwt` feature.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::layer::jwt::{Claims, JwtLayer};
+//!
+//! let layer = JwtLayer::new(["https://auth.example.com/.well-known/jwks.json".parse().unwrap()])
+//!     .issuer(["https://auth.example.com"])
+//!     .audience(["item"]);
+//!
+//! ItemServiceServer::new(S).layer(layer).run(addr).await;
+//!
+//! // in the handler
+//! let tenant = cx.extensions().get::<Claims>().and_then(|c| c.get("tenant"));
+//! ```
+//!
+//! [`AuthzLayer`]: super::authz::AuthzLayer
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use http::Uri;
+use jsonwebtoken::{
+    jwk::{AlgorithmParameters, Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use motore::{layer::Layer, BoxError, Service};
+use volo::context::Context;
+
+use super::authz::{Principal, PrincipalSource};
+use crate::{metadata::AsciiMetadataKey, Request, Status};
+
+const DEFAULT_HEADER: &str = "authorization";
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+const DEFAULT_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The verified claims of the token, in the context extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Claims(pub serde_json::Map<String, serde_json::Value>);
+
+impl Claims {
+    /// Returns the claim of the name.
+    pub fn get(&self, name: &str) -> Option<&serde_json::Value> {
+        self.0.get(name)
+    }
+
+    /// Returns the `sub` claim.
+    pub fn subject(&self) -> Option<&str> {
+        self.get("sub").and_then(|v| v.as_str())
+    }
+
+    /// Returns the `iss` claim.
+    pub fn issuer(&self) -> Option<&str> {
+        self.get("iss").and_then(|v| v.as_str())
+    }
+}
+
+/// Fetches the key set of a JWKS endpoint.
+#[async_trait::async_trait]
+pub trait JwksFetcher: Send + Sync + 'static {
+    async fn fetch(&self, uri: &Uri) -> Result<JwkSet, BoxError>;
+}
+
+/// Fetches the key sets over HTTP or HTTPS, verified by the native roots.
+pub struct HttpFetcher {
+    client: hyper::Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+}
+
+impl HttpFetcher {
+    pub fn new() -> Self {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Self {
+            client: hyper::Client::builder().build(connector),
+        }
+    }
+}
+
+impl Default for HttpFetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl JwksFetcher for HttpFetcher {
+    async fn fetch(&self, uri: &Uri) -> Result<JwkSet, BoxError> {
+        let resp = self.client.get(uri.clone()).await?;
+        if !resp.status().is_success() {
+            return Err(format!("unexpected status {} of {}", resp.status(), uri).into());
+        }
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+#[derive(Default)]
+struct Keys {
+    // by the kid, the keys without kid are under ""
+    keys: HashMap<String, (DecodingKey, Option<Algorithm>)>,
+    fetched_at: Option<Instant>,
+    tried_at: Option<Instant>,
+}
+
+/// The keys of the endpoints, fetched on demand.
+struct Jwks {
+    endpoints: Vec<Uri>,
+    fetcher: Arc<dyn JwksFetcher>,
+    keys: RwLock<Keys>,
+    // only one request fetches the keys at a time, the others wait for it
+    refreshing: tokio::sync::Mutex<()>,
+    refresh_interval: Duration,
+    min_refresh_interval: Duration,
+}
+
+impl Jwks {
+    fn lookup(&self, kid: &str) -> (Option<(DecodingKey, Option<Algorithm>)>, bool) {
+        let keys = self.keys.read().unwrap();
+        let key = keys.keys.get(kid).cloned();
+        let stale = keys
+            .fetched_at
+            .map_or(true, |at| at.elapsed() >= self.refresh_interval);
+        // a token of an unknown key may be signed by a new one
+        let should_refresh = stale
+            || (key.is_none()
+                && keys
+                    .tried_at
+                    .map_or(true, |at| at.elapsed() >= self.min_refresh_interval));
+        (key, should_refresh)
+    }
+
+    async fn key(&self, kid: &str) -> Option<(DecodingKey, Option<Algorithm>)> {
+        let (key, should_refresh) = self.lookup(kid);
+        if !should_refresh {
+            return key;
+        }
+        let _guard = self.refreshing.lock().await;
+        // refreshed by another request while waiting
+        let (key, should_refresh) = self.lookup(kid);
+        if !should_refresh {
+            return key;
+        }
+        self.refresh().await;
+        // the stale keys are still used if the refresh failed
+        self.lookup(kid).0.or(key)
+    }
+
+    async fn refresh(&self) {
+        let mut keys = HashMap::new();
+        let mut failed = false;
+        for endpoint in &self.endpoints {
+            match self.fetcher.fetch(endpoint).await {
+                Ok(set) => {
+                    for jwk in set.keys {
+                        match decoding_key(&jwk) {
+                            Ok(key) => {
+                                let kid = jwk.common.key_id.clone().unwrap_or_default();
+                                keys.insert(kid, (key, jwk.common.algorithm));
+                            }
+                            Err(e) => {
+                                tracing::warn!("[VOLO] unsupported jwk of {}: {}", endpoint, e)
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("[VOLO] failed to fetch jwks of {}: {}", endpoint, e);
+                    failed = true;
+                }
+            }
+        }
+        let mut current = self.keys.write().unwrap();
+        current.tried_at = Some(Instant::now());
+        if !failed {
+            current.keys = keys;
+            current.fetched_at = current.tried_at;
+        } else {
+            // keeps the old keys, and adds the new ones of the endpoints that succeeded
+            current.keys.extend(keys);
+        }
+    }
+}
+
+struct Shared {
+    jwks: Jwks,
+    header: AsciiMetadataKey,
+    algorithms: Vec<Algorithm>,
+    issuer: Vec<String>,
+    audience: Vec<String>,
+    leeway: u64,
+    optional: bool,
+}
+
+impl Shared {
+    async fn verify(&self, token: &str) -> Result<Claims, Status> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| Status::unauthenticated(format!("invalid token: {}", e)))?;
+        if !self.algorithms.contains(&header.alg) {
+            return Err(Status::unauthenticated(format!(
+                "unsupported algorithm {:?}",
+                header.alg
+            )));
+        }
+        let kid = header.kid.as_deref().unwrap_or_default();
+        let (key, alg) = self
+            .jwks
+            .key(kid)
+            .await
+            .ok_or_else(|| Status::unauthenticated(format!("unknown key {:?}", kid)))?;
+        // the key of an algorithm can't verify the tokens of the others
+        if alg.map_or(false, |alg| alg != header.alg) {
+            return Err(Status::unauthenticated(format!(
+                "algorithm {:?} mismatches the key",
+                header.alg
+            )));
+        }
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.leeway;
+        if !self.issuer.is_empty() {
+            validation.set_issuer(&self.issuer);
+        }
+        if !self.audience.is_empty() {
+            validation.set_audience(&self.audience);
+        }
+        let data = jsonwebtoken::decode::<serde_json::Map<String, serde_json::Value>>(
+            token,
+            &key,
+            &validation,
+        )
+        .map_err(|e| Status::unauthenticated(format!("invalid token: {}", e)))?;
+        Ok(Claims(data.claims))
+    }
+}
+
+fn bearer_token(value: &str) -> Option<&str> {
+    let (scheme, token) = value.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim())
+        .filter(|t| !t.is_empty())
+}
+
+/// A [`Service`] that validates the bearer tokens.
+pub struct JwtService<S> {
+    inner: S,
+    shared: Arc<Shared>,
+}
+
+impl<S: Clone> Clone for JwtService<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<Cx, T, S> Service<Cx, Request<T>> for JwtService<S>
+where
+    Cx: Context + 'static + Send,
+    T: 'static + Send,
+    S: Service<Cx, Request<T>, Error = Status> + 'static + Send,
+{
+    type Response = S::Response;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Request<T>) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let token = req
+                .metadata()
+                .get(&self.shared.header)
+                .and_then(|v| v.to_str().ok())
+                .and_then(bearer_token)
+                .map(|t| t.to_string());
+            match token {
+                Some(token) => {
+                    let claims = self.shared.verify(&token).await?;
+                    if let Some(sub) = claims.subject() {
+                        let mut principal = Principal::new(sub, PrincipalSource::Jwt);
+                        if let Some(iss) = claims.issuer() {
+                            principal = principal.with_attribute("iss", iss);
+                        }
+                        cx.extensions_mut().insert(principal);
+                    }
+                    cx.extensions_mut().insert(claims);
+                }
+                None if self.shared.optional => {}
+                None => return Err(Status::unauthenticated("missing bearer token")),
+            }
+            self.inner.call(cx, req).await
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`JwtService`].
+pub struct JwtLayer {
+    endpoints: Vec<Uri>,
+    fetcher: Arc<dyn JwksFetcher>,
+    header: AsciiMetadataKey,
+    algorithms: Vec<Algorithm>,
+    issuer: Vec<String>,
+    audience: Vec<String>,
+    leeway: u64,
+    optional: bool,
+    refresh_interval: Duration,
+    min_refresh_interval: Duration,
+}
+
+impl JwtLayer {
+    /// Creates a new [`JwtLayer`] verifying the tokens by the keys of the JWKS endpoints.
+    ///
+    /// The RSA and the ECDSA algorithms are allowed by default, the keys are refreshed every 5
+    /// minutes, or at most every 30 seconds on an unknown key.
+    pub fn new(endpoints: impl IntoIterator<Item = Uri>) -> Self {
+        Self {
+            endpoints: endpoints.into_iter().collect(),
+            fetcher: Arc::new(HttpFetcher::new()),
+            header: AsciiMetadataKey::from_static(DEFAULT_HEADER),
+            algorithms: vec![
+                Algorithm::RS256,
+                Algorithm::RS384,
+                Algorithm::RS512,
+                Algorithm::PS256,
+                Algorithm::PS384,
+                Algorithm::PS512,
+                Algorithm::ES256,
+                Algorithm::ES384,
+            ],
+            issuer: Vec::new(),
+            audience: Vec::new(),
+            leeway: 60,
+            optional: false,
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            min_refresh_interval: DEFAULT_MIN_REFRESH_INTERVAL,
+        }
+    }
+
+    /// Sets the fetcher of the key sets, such as to fetch them with the custom TLS config.
+    pub fn fetcher(mut self, fetcher: impl JwksFetcher) -> Self {
+        self.fetcher = Arc::new(fetcher);
+        self
+    }
+
+    /// Sets the metadata key of the token.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not a valid ascii metadata key.
+    pub fn header(mut self, header: &'static str) -> Self {
+        self.header = AsciiMetadataKey::from_static(header);
+        self
+    }
+
+    /// Sets the allowed algorithms of the tokens.
+    pub fn algorithms(mut self, algorithms: Vec<Algorithm>) -> Self {
+        self.algorithms = algorithms;
+        self
+    }
+
+    /// Sets the accepted issuers, any issuer is accepted if empty.
+    pub fn issuer<I: IntoIterator<Item = T>, T: Into<String>>(mut self, issuer: I) -> Self {
+        self.issuer = issuer.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the accepted audiences, the audience isn't checked if empty.
+    pub fn audience<I: IntoIterator<Item = T>, T: Into<String>>(mut self, audience: I) -> Self {
+        self.audience = audience.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the leeway of the expiration and the not before time, defaults to 60 seconds.
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway.as_secs();
+        self
+    }
+
+    /// Sets whether the requests without a token are passed to the inner service, which are
+    /// still rejected with an invalid token.
+    ///
+    /// Defaults to false.
+    pub fn optional(mut self, optional: bool) -> Self {
+        self.optional = optional;
+        self
+    }
+
+    /// Sets the interval to fetch the keys again.
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    /// Sets the minimum interval to fetch the keys again on a token of an unknown key.
+    pub fn min_refresh_interval(mut self, interval: Duration) -> Self {
+        self.min_refresh_interval = interval;
+        self
+    }
+}
+
+impl<S> Layer<S> for JwtLayer {
+    type Service = JwtService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        JwtService {
+            inner,
+            shared: Arc::new(Shared {
+                jwks: Jwks {
+                    endpoints: self.endpoints,
+                    fetcher: self.fetcher,
+                    keys: Default::default(),
+                    refreshing: Default::default(),
+                    refresh_interval: self.refresh_interval,
+                    min_refresh_interval: self.min_refresh_interval,
+                },
+                header: self.header,
+                algorithms: self.algorithms,
+                issuer: self.issuer,
+                audience: self.audience,
+                leeway: self.leeway,
+                optional: self.optional,
+            }),
+        }
+    }
+}
+
+/// The key of the jwk, of the RSA and the symmetric ones.
+fn decoding_key(jwk: &Jwk) -> jsonwebtoken::errors::Result<DecodingKey> {
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(rsa) => DecodingKey::from_rsa_components(&rsa.n, &rsa.e),
+        AlgorithmParameters::OctetKey(oct) => DecodingKey::from_base64_secret(&oct.value),
+        _ => Err(jsonwebtoken::errors::ErrorKind::InvalidKeyFormat.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    struct Fetcher(Arc<AtomicUsize>);
+
+    #[async_trait::async_trait]
+    impl JwksFetcher for Fetcher {
+        async fn fetch(&self, _: &Uri) -> Result<JwkSet, BoxError> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(JwkSet { keys: Vec::new() })
+        }
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        assert_eq!(bearer_token("Bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("bearer  abc "), Some("abc"));
+        assert_eq!(bearer_token("Basic abc"), None);
+        assert_eq!(bearer_token("Bearer "), None);
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let layer = JwtLayer::new(["http://127.0.0.1/jwks".parse().unwrap()])
+            .fetcher(Fetcher(fetches.clone()))
+            .algorithms(vec![Algorithm::HS256])
+            .audience(["item"]);
+        let service = layer.layer(());
+        let shared = &service.shared;
+        {
+            let mut keys = shared.jwks.keys.write().unwrap();
+            keys.keys.insert(
+                "k1".into(),
+                (DecodingKey::from_secret(b"secret"), Some(Algorithm::HS256)),
+            );
+            keys.fetched_at = Some(Instant::now());
+        }
+
+        let encode = |kid: &str, aud: &str| {
+            let mut header = Header::new(Algorithm::HS256);
+            header.kid = Some(kid.into());
+            let claims = json!({ "sub": "alice", "aud": aud, "exp": u32::MAX });
+            jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(b"secret")).unwrap()
+        };
+
+        let claims = shared.verify(&encode("k1", "item")).await.unwrap();
+        assert_eq!(claims.subject(), Some("alice"));
+        shared.verify(&encode("k1", "other")).await.unwrap_err();
+        assert_eq!(fetches.load(Ordering::Relaxed), 0);
+
+        // an unknown key is fetched, but only once per the minimum interval
+        shared.verify(&encode("k2", "item")).await.unwrap_err();
+        shared.verify(&encode("k2", "item")).await.unwrap_err();
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,330 @@
+//! Records the latency and the status of every call to a metrics [`Recorder`], on either the
+//! client or the server side, with the labels kept to a bounded cardinality.
+//!
+//! The labels are the method, the code of the status and the peer, which can each blow up the
+//! time series of a metrics backend such as Prometheus, so [`LabelConfig`] controls them:
+//!
+//! - The methods not in the allowlist, or beyond the maximum distinct ones, are labeled `other`.
+//! - The codes are grouped by [`CodeGrouping`], such as only telling the successes from the
+//!   failures.
+//! - The peer is dropped by default, or labeled by its IP or full address by [`PeerLabel`], and the
+//!   peers beyond the maximum distinct ones are labeled `other`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::layer::metrics::{CodeGrouping, LabelConfig, Labels, MetricsLayer};
+//!
+//! let layer = MetricsLayer::new(|labels: &Labels, latency: std::time::Duration| {
+//!     histogram.with_label_values(&[&labels.method, &labels.code]).observe(latency.as_secs_f64());
+//! })
+//! .labels(LabelConfig {
+//!     codes: CodeGrouping::Class,
+//!     max_methods: 50,
+//!     ..Default::default()
+//! });
+//!
+//! ItemServiceServer::new(S).layer(layer).run(addr).await;
+//! ```
+
+use std::{
+    collections::HashSet,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use motore::{layer::Layer, Service};
+use volo::{
+    context::{Context, Role},
+    net::Address,
+};
+
+use crate::{Code, Status};
+
+const OTHER: &str = "other";
+
+/// The labels of a call.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Labels {
+    /// The path of the method, such as `/volo.example.ItemService/GetItem`, or `other`.
+    pub method: String,
+    /// The code of the status, grouped by [`CodeGrouping`].
+    pub code: String,
+    /// The peer, `None` if dropped by [`PeerLabel::None`].
+    pub peer: Option<String>,
+}
+
+/// Records the calls, such as to the histograms of a metrics backend.
+pub trait Recorder: Send + Sync + 'static {
+    fn record(&self, labels: &Labels, latency: Duration);
+}
+
+impl<F> Recorder for F
+where
+    F: Fn(&Labels, Duration) + Send + Sync + 'static,
+{
+    fn record(&self, labels: &Labels, latency: Duration) {
+        self(labels, latency)
+    }
+}
+
+/// How the codes of the status are labeled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeGrouping {
+    /// Each code as is, such as `NotFound`.
+    Exact,
+    /// `ok`, `client_error` as the codes caused by the callers, such as `InvalidArgument`, or
+    /// `server_error`.
+    Class,
+    /// `ok` or `error`.
+    Success,
+}
+
+/// How the peer is labeled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerLabel {
+    /// The peer isn't labeled.
+    None,
+    /// The IP of the peer without the port, which is random on the client side.
+    Ip,
+    /// The full address of the peer.
+    Address,
+}
+
+/// Controls the cardinality of the labels.
+#[derive(Debug, Clone)]
+pub struct LabelConfig {
+    /// The methods labeled as is, all of them if `None`.
+    pub methods: Option<HashSet<String>>,
+    /// The maximum distinct methods labeled as is, the later ones are labeled `other`.
+    pub max_methods: usize,
+    pub codes: CodeGrouping,
+    pub peer: PeerLabel,
+    /// The maximum distinct peers labeled as is, the later ones are labeled `other`.
+    pub max_peers: usize,
+}
+
+impl Default for LabelConfig {
+    fn default() -> Self {
+        Self {
+            methods: None,
+            max_methods: 200,
+            codes: CodeGrouping::Exact,
+            peer: PeerLabel::None,
+            max_peers: 100,
+        }
+    }
+}
+
+fn code_label(code: Code, grouping: CodeGrouping) -> String {
+    match (grouping, code) {
+        (CodeGrouping::Exact, code) => format!("{:?}", code),
+        (_, Code::Ok) => "ok".to_string(),
+        (CodeGrouping::Success, _) => "error".to_string(),
+        (
+            CodeGrouping::Class,
+            Code::Cancelled
+            | Code::InvalidArgument
+            | Code::NotFound
+            | Code::AlreadyExists
+            | Code::PermissionDenied
+            | Code::FailedPrecondition
+            | Code::OutOfRange
+            | Code::Unauthenticated,
+        ) => "client_error".to_string(),
+        (CodeGrouping::Class, _) => "server_error".to_string(),
+    }
+}
+
+/// The distinct values of a label seen, up to the maximum.
+struct Bounded {
+    seen: Mutex<HashSet<String>>,
+    max: usize,
+}
+
+impl Bounded {
+    fn new(max: usize) -> Self {
+        Self {
+            seen: Default::default(),
+            max,
+        }
+    }
+
+    fn label(&self, value: String) -> String {
+        let mut seen = self.seen.lock().unwrap();
+        if seen.contains(&value) {
+            value
+        } else if seen.len() < self.max {
+            seen.insert(value.clone());
+            value
+        } else {
+            OTHER.to_string()
+        }
+    }
+}
+
+struct Shared {
+    recorder: Arc<dyn Recorder>,
+    config: LabelConfig,
+    methods: Bounded,
+    peers: Bounded,
+}
+
+impl Shared {
+    fn labels<Cx: Context>(&self, cx: &Cx, code: Code) -> Labels {
+        let method = cx.rpc_info().method().map_or("", |m| m.as_str());
+        let method = match &self.config.methods {
+            Some(methods) if !methods.contains(method) => OTHER.to_string(),
+            _ => self.methods.label(method.to_string()),
+        };
+
+        let peer = match cx.rpc_info().role() {
+            Role::Server => cx.rpc_info().caller(),
+            Role::Client => cx.rpc_info().callee(),
+        }
+        .and_then(|e| e.address.as_ref());
+        let peer = match (self.config.peer, peer) {
+            (PeerLabel::None, _) => None,
+            (_, None) => Some("unknown".to_string()),
+            (PeerLabel::Ip, Some(Address::Ip(addr))) => {
+                Some(self.peers.label(addr.ip().to_string()))
+            }
+            (_, Some(addr)) => Some(self.peers.label(addr.to_string())),
+        };
+
+        Labels {
+            method,
+            code: code_label(code, self.config.codes),
+            peer,
+        }
+    }
+}
+
+/// A [`Service`] that records the calls.
+pub struct MetricsService<S> {
+    inner: S,
+    shared: Arc<Shared>,
+}
+
+impl<S: Clone> Clone for MetricsService<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<Cx, Req, S> Service<Cx, Req> for MetricsService<S>
+where
+    Cx: Context + 'static + Send,
+    Req: 'static + Send,
+    S: Service<Cx, Req, Error = Status> + 'static + Send,
+{
+    type Response = S::Response;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let start = Instant::now();
+            let result = self.inner.call(cx, req).await;
+            // the latency of the streaming calls is until the response headers
+            let code = result.as_ref().map_or_else(|s| s.code(), |_| Code::Ok);
+            let labels = self.shared.labels(&*cx, code);
+            self.shared.recorder.record(&labels, start.elapsed());
+            result
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`MetricsService`].
+pub struct MetricsLayer {
+    recorder: Arc<dyn Recorder>,
+    config: LabelConfig,
+}
+
+impl MetricsLayer {
+    /// Creates a new [`MetricsLayer`] recording to `recorder` with the default [`LabelConfig`].
+    pub fn new(recorder: impl Recorder) -> Self {
+        Self {
+            recorder: Arc::new(recorder),
+            config: Default::default(),
+        }
+    }
+
+    /// Sets the controls of the labels.
+    pub fn labels(mut self, config: LabelConfig) -> Self {
+        self.config = config;
+        self
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        MetricsService {
+            inner,
+            shared: Arc::new(Shared {
+                methods: Bounded::new(self.config.max_methods),
+                peers: Bounded::new(self.config.max_peers),
+                recorder: self.recorder,
+                config: self.config,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use volo::context::Endpoint;
+
+    use super::*;
+    use crate::context::ServerContext;
+
+    fn cx(method: &str, peer: &str) -> ServerContext {
+        let mut cx = ServerContext::default();
+        cx.rpc_info.method = Some(method.into());
+        let mut caller = Endpoint::new("".into());
+        caller.address = Some(Address::from(peer.parse::<std::net::SocketAddr>().unwrap()));
+        cx.rpc_info.caller = Some(caller);
+        cx
+    }
+
+    #[test]
+    fn test_labels() {
+        let layer = MetricsLayer::new(|_: &Labels, _: Duration| {}).labels(LabelConfig {
+            max_methods: 1,
+            codes: CodeGrouping::Class,
+            peer: PeerLabel::Ip,
+            ..Default::default()
+        });
+        let service = layer.layer(());
+        let shared = &service.shared;
+
+        let labels = shared.labels(&cx("/a.S/A", "10.0.0.1:1234"), Code::NotFound);
+        assert_eq!(labels.method, "/a.S/A");
+        assert_eq!(labels.code, "client_error");
+        assert_eq!(labels.peer.as_deref(), Some("10.0.0.1"));
+
+        let labels = shared.labels(&cx("/a.S/B", "10.0.0.1:4321"), Code::Internal);
+        assert_eq!(labels.method, OTHER);
+        assert_eq!(labels.code, "server_error");
+        assert_eq!(labels.peer.as_deref(), Some("10.0.0.1"));
+
+        assert_eq!(code_label(Code::Ok, CodeGrouping::Success), "ok");
+        assert_eq!(
+            code_label(Code::Unavailable, CodeGrouping::Success),
+            "error"
+        );
+        assert_eq!(
+            code_label(Code::Unavailable, CodeGrouping::Exact),
+            "Unavailable"
+        );
+    }
+}
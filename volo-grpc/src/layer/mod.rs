@@ -1,3 +1,16 @@
+pub mod authz;
 pub mod cross_origin;
+pub mod deadline;
+pub mod error_handler;
+pub mod fault;
 pub mod grpc_timeout;
+pub mod interceptor;
+#[cfg(feature = "jwt")]
+pub mod jwt;
+pub mod metrics;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod rate_limit;
+pub mod request_id;
+pub mod required_headers;
 pub mod user_agent;
@@ -0,0 +1,353 @@
+//! The tracing and the metrics of the calls by OpenTelemetry, on either the client or the server
+//! side, by the semantic conventions of the RPC spans and metrics.
+//!
+//! [`OtelLayer`] starts a span of each call by the global tracer and records its duration by the
+//! global meter, so the SDK and the exporters are set up by the application, such as by
+//! `opentelemetry-otlp`. The spans and the metrics have the attributes `rpc.system`,
+//! `rpc.service`, `rpc.method` and `rpc.grpc.status_code`.
+//!
+//! The context of the span is propagated by the global text map propagator, such as the W3C
+//! `traceparent`: the clients inject it into the metadata of the requests, and the servers extract
+//! the parent of their spans from the metadata, so the calls made by the handlers are in the trace
+//! of the request. A call ends with its response, which of the streaming calls is before their
+//! streams, the same as [`metrics`](super::metrics).
+//!
+//! The sizes of the messages are recorded by [`OtelStats`] on the client side, set by
+//! `ClientBuilder::stats_handler`, to `rpc.client.request.size` and `rpc.client.response.size`.
+//! The servers don't record them, since the messages are decoded before the layers.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use opentelemetry::{global, sdk::propagation::TraceContextPropagator};
+//! use volo_grpc::layer::otel::{OtelLayer, OtelStats};
+//!
+//! global::set_text_map_propagator(TraceContextPropagator::new());
+//!
+//! // server
+//! ItemServiceServer::new(S).layer(OtelLayer::new()).run(addr).await;
+//!
+//! // client
+//! ItemServiceClientBuilder::new("item")
+//!     .layer(OtelLayer::new())
+//!     .stats_handler(OtelStats::new())
+//!     .build();
+//! ```
+
+use std::{future::Future, sync::Arc, time::Instant};
+
+use motore::{layer::Layer, Service};
+use opentelemetry::{
+    global::{self, BoxedTracer},
+    metrics::{Counter, Meter, Unit, ValueRecorder},
+    propagation::{Extractor, Injector},
+    trace::{FutureExt, SpanKind, StatusCode, TraceContextExt, Tracer},
+    Context as OtelContext, KeyValue,
+};
+use volo::{
+    context::{Context, Role},
+    net::Address,
+};
+
+use crate::{
+    client::stats::{RpcStats, StatsHandler},
+    metadata::{AsciiMetadataKey, KeyRef, MetadataMap},
+    Code, Request, Status,
+};
+
+const INSTRUMENTATION_NAME: &str = "volo-grpc";
+
+const RPC_SYSTEM: &str = "rpc.system";
+const RPC_SERVICE: &str = "rpc.service";
+const RPC_METHOD: &str = "rpc.method";
+const RPC_GRPC_STATUS_CODE: &str = "rpc.grpc.status_code";
+const NET_PEER_IP: &str = "net.peer.ip";
+const NET_PEER_PORT: &str = "net.peer.port";
+
+/// Reads the propagated context from the metadata of a request.
+struct MetadataExtractor<'a>(&'a MetadataMap);
+
+impl Extractor for MetadataExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .keys()
+            .map(|key| match key {
+                KeyRef::Ascii(key) => key.as_str(),
+                KeyRef::Binary(key) => key.as_str(),
+            })
+            .collect()
+    }
+}
+
+/// Writes the propagated context into the metadata of a request.
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (AsciiMetadataKey::from_bytes(key.as_bytes()), value.parse())
+        {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+/// The attributes of the service and the method of a path, such as
+/// `/volo.example.ItemService/GetItem`.
+fn method_attributes(path: &str) -> Vec<KeyValue> {
+    let (service, method) = path
+        .trim_start_matches('/')
+        .split_once('/')
+        .unwrap_or(("", path));
+    vec![
+        KeyValue::new(RPC_SYSTEM, "grpc"),
+        KeyValue::new(RPC_SERVICE, service.to_string()),
+        KeyValue::new(RPC_METHOD, method.to_string()),
+    ]
+}
+
+/// Whether the code fails the span, all the errors on the client side, and on the server side only
+/// the ones of the server rather than of the callers, by the semantic conventions.
+fn is_error(role: Role, code: Code) -> bool {
+    match role {
+        Role::Client => code != Code::Ok,
+        Role::Server => matches!(
+            code,
+            Code::Unknown
+                | Code::DeadlineExceeded
+                | Code::Unimplemented
+                | Code::Internal
+                | Code::Unavailable
+                | Code::DataLoss
+        ),
+    }
+}
+
+struct Instruments {
+    duration: ValueRecorder<f64>,
+    calls: Counter<u64>,
+}
+
+impl Instruments {
+    fn new(meter: &Meter, side: &str) -> Self {
+        Self {
+            duration: meter
+                .f64_value_recorder(format!("rpc.{}.duration", side))
+                .with_description("The duration of the calls.")
+                .with_unit(Unit::new("ms"))
+                .init(),
+            calls: meter
+                .u64_counter(format!("rpc.{}.calls", side))
+                .with_description("The calls by their status codes.")
+                .init(),
+        }
+    }
+}
+
+struct Shared {
+    tracer: BoxedTracer,
+    client: Instruments,
+    server: Instruments,
+}
+
+/// A [`Service`] that traces and measures the calls by OpenTelemetry.
+pub struct OtelService<S> {
+    inner: S,
+    shared: Arc<Shared>,
+}
+
+impl<S: Clone> Clone for OtelService<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<Cx, T, S> Service<Cx, Request<T>> for OtelService<S>
+where
+    Cx: Context + 'static + Send,
+    T: 'static + Send,
+    S: Service<Cx, Request<T>, Error = Status> + 'static + Send,
+{
+    type Response = S::Response;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, mut req: Request<T>) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let role = cx.rpc_info().role();
+            let path = cx
+                .rpc_info()
+                .method()
+                .map_or_else(String::new, |m| m.to_string());
+            let mut attributes = method_attributes(&path);
+
+            let (parent, kind, peer) = match role {
+                Role::Server => (
+                    global::get_text_map_propagator(|propagator| {
+                        propagator.extract(&MetadataExtractor(req.metadata()))
+                    }),
+                    SpanKind::Server,
+                    cx.rpc_info().caller(),
+                ),
+                Role::Client => (
+                    OtelContext::current(),
+                    SpanKind::Client,
+                    cx.rpc_info().callee(),
+                ),
+            };
+            let mut span_attributes = attributes.clone();
+            if let Some(Address::Ip(addr)) = peer.and_then(|e| e.address.as_ref()) {
+                span_attributes.push(KeyValue::new(NET_PEER_IP, addr.ip().to_string()));
+                span_attributes.push(KeyValue::new(NET_PEER_PORT, addr.port() as i64));
+            }
+            let tracer = &self.shared.tracer;
+            let span = tracer
+                .span_builder(path.trim_start_matches('/').to_string())
+                .with_kind(kind)
+                .with_attributes(span_attributes)
+                .start_with_context(tracer, &parent);
+            let otel_cx = parent.with_span(span);
+            if let Role::Client = role {
+                global::get_text_map_propagator(|propagator| {
+                    propagator.inject_context(&otel_cx, &mut MetadataInjector(req.metadata_mut()))
+                });
+            }
+
+            let start = Instant::now();
+            let result = self.inner.call(cx, req).with_context(otel_cx.clone()).await;
+            let elapsed = start.elapsed();
+
+            let code = result
+                .as_ref()
+                .map_or_else(|status| status.code(), |_| Code::Ok);
+            let status_code = KeyValue::new(RPC_GRPC_STATUS_CODE, i32::from(code) as i64);
+            let span = otel_cx.span();
+            span.set_attribute(status_code.clone());
+            if let Err(status) = &result {
+                if is_error(role, code) {
+                    span.set_status(StatusCode::Error, status.message().to_string());
+                }
+            }
+            span.end();
+
+            attributes.push(status_code);
+            let instruments = match role {
+                Role::Client => &self.shared.client,
+                Role::Server => &self.shared.server,
+            };
+            instruments
+                .duration
+                .record(elapsed.as_secs_f64() * 1000.0, &attributes);
+            instruments.calls.add(1, &attributes);
+            result
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`OtelService`], by the global tracer and meter.
+#[derive(Clone)]
+pub struct OtelLayer {
+    shared: Arc<Shared>,
+}
+
+impl OtelLayer {
+    pub fn new() -> Self {
+        let meter = global::meter(INSTRUMENTATION_NAME);
+        Self {
+            shared: Arc::new(Shared {
+                tracer: global::tracer(INSTRUMENTATION_NAME),
+                client: Instruments::new(&meter, "client"),
+                server: Instruments::new(&meter, "server"),
+            }),
+        }
+    }
+}
+
+impl Default for OtelLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for OtelLayer {
+    type Service = OtelService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        OtelService {
+            inner,
+            shared: self.shared,
+        }
+    }
+}
+
+/// A [`StatsHandler`] that records the sizes of the messages of the calls by the global meter.
+pub struct OtelStats {
+    request_size: ValueRecorder<u64>,
+    response_size: ValueRecorder<u64>,
+}
+
+impl OtelStats {
+    pub fn new() -> Self {
+        let meter = global::meter(INSTRUMENTATION_NAME);
+        Self {
+            request_size: meter
+                .u64_value_recorder("rpc.client.request.size")
+                .with_description("The sizes of the messages of the requests.")
+                .with_unit(Unit::new("By"))
+                .init(),
+            response_size: meter
+                .u64_value_recorder("rpc.client.response.size")
+                .with_description("The sizes of the messages of the responses.")
+                .with_unit(Unit::new("By"))
+                .init(),
+        }
+    }
+}
+
+impl Default for OtelStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatsHandler for OtelStats {
+    fn handle_rpc(&self, method: &str, stats: &RpcStats<'_>) {
+        let (recorder, length) = match stats {
+            RpcStats::OutPayload { length } => (&self.request_size, *length),
+            RpcStats::InPayload { length } => (&self.response_size, *length),
+            _ => return,
+        };
+        recorder.record(length as u64, &method_attributes(method));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_propagation() {
+        let mut metadata = MetadataMap::new();
+        MetadataInjector(&mut metadata).set("traceparent", "00-01-02-01".to_string());
+        // the keys and the values not allowed in the metadata are dropped
+        MetadataInjector(&mut metadata).set("bad key", "v".to_string());
+        MetadataInjector(&mut metadata).set("k", "bad\nvalue".to_string());
+
+        let extractor = MetadataExtractor(&metadata);
+        assert_eq!(extractor.get("traceparent"), Some("00-01-02-01"));
+        assert_eq!(extractor.keys(), vec!["traceparent"]);
+
+        let attributes = method_attributes("/volo.example.ItemService/GetItem");
+        assert_eq!(attributes[1].value.as_str(), "volo.example.ItemService");
+        assert_eq!(attributes[2].value.as_str(), "GetItem");
+    }
+}
@@ -0,0 +1,155 @@
+//! Server side rate limiting based on token buckets.
+//!
+//! The token buckets of [`volo::layer::rate_limit`], keyed by the methods or the peers of the
+//! gRPC requests. Requests exceeding the limit are rejected with [`Code::ResourceExhausted`]
+//! before they reach the inner service.
+//!
+//! [`Code::ResourceExhausted`]: crate::status::Code::ResourceExhausted
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::layer::rate_limit::{RateLimitKey, RateLimitLayer};
+//!
+//! // 1000 qps per method, allow bursts of 100 requests
+//! let layer = RateLimitLayer::new(1000, 100).key(RateLimitKey::Method);
+//! let stats = layer.stats();
+//!
+//! ItemServiceServer::new(S).layer(layer).run(addr).await;
+//! ```
+
+use std::sync::Arc;
+
+use motore::layer::Layer;
+pub use volo::layer::rate_limit::RateLimitStats;
+use volo::{context::Context, layer::rate_limit::KeyExtractor, net::Address};
+
+/// How the requests are grouped into buckets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// All requests share one bucket.
+    Global,
+    /// One bucket for each method.
+    Method,
+    /// One bucket for each peer ip.
+    Peer,
+    /// One bucket for each method of each peer ip.
+    MethodAndPeer,
+}
+
+impl<Cx: Context> KeyExtractor<Cx> for RateLimitKey {
+    fn key(&self, cx: &Cx) -> Option<String> {
+        let method = || {
+            cx.rpc_info()
+                .method()
+                .map(|m| m.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let peer = || match cx.rpc_info().caller().and_then(|c| c.address.as_ref()) {
+            // ignore the port, so all connections from the same host share the bucket
+            Some(Address::Ip(addr)) => addr.ip().to_string(),
+            Some(Address::Unix(path)) => path.display().to_string(),
+            None => String::new(),
+        };
+        Some(match self {
+            RateLimitKey::Global => String::new(),
+            RateLimitKey::Method => method(),
+            RateLimitKey::Peer => peer(),
+            RateLimitKey::MethodAndPeer => format!("{}|{}", method(), peer()),
+        })
+    }
+}
+
+/// A [`Service`](motore::Service) that rejects requests when the rate limit is exceeded.
+pub type RateLimit<S> = volo::layer::rate_limit::RateLimit<S, RateLimitKey>;
+
+/// A [`Layer`] that applies [`RateLimit`].
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    inner: volo::layer::rate_limit::RateLimitLayer<RateLimitKey>,
+}
+
+impl RateLimitLayer {
+    /// Creates a new [`RateLimitLayer`] which allows `rate` requests per second with bursts
+    /// of at most `burst` requests.
+    pub fn new(rate: u32, burst: u32) -> Self {
+        Self {
+            inner: volo::layer::rate_limit::RateLimitLayer::new(rate as f64, burst)
+                .key_by(RateLimitKey::Global),
+        }
+    }
+
+    /// Sets how the requests are grouped into buckets.
+    ///
+    /// Default is [`RateLimitKey::Global`].
+    pub fn key(self, key: RateLimitKey) -> Self {
+        Self {
+            inner: self.inner.key_by(key),
+        }
+    }
+
+    /// Returns the counters shared by all the services made by this layer.
+    pub fn stats(&self) -> Arc<RateLimitStats> {
+        self.inner.stats()
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        self.inner.layer(inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motore::{service::service_fn, Service};
+    use volo::context::Endpoint;
+
+    use super::*;
+    use crate::{context::ServerContext, status::Code, Request, Status};
+
+    async fn handler(_: &mut ServerContext, _: Request<()>) -> Result<(), Status> {
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let layer = RateLimitLayer::new(1, 1);
+        let stats = layer.stats();
+        let mut service = layer.layer(service_fn(handler));
+
+        let mut cx = ServerContext::default();
+        service.call(&mut cx, Request::new(())).await.unwrap();
+        let status = service.call(&mut cx, Request::new(())).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(stats.allowed(), 1);
+        assert_eq!(stats.rejected(), 1);
+    }
+
+    fn from_peer(ip: [u8; 4], port: u16) -> ServerContext {
+        let mut caller = Endpoint::new("caller".into());
+        caller.set_address(Address::Ip((ip, port).into()));
+        let mut cx = ServerContext::default();
+        cx.rpc_info.caller = Some(caller);
+        cx
+    }
+
+    #[tokio::test]
+    async fn test_peer() {
+        let mut service = RateLimitLayer::new(1, 1)
+            .key(RateLimitKey::Peer)
+            .layer(service_fn(handler));
+
+        let mut cx = from_peer([10, 0, 0, 1], 1000);
+        service.call(&mut cx, Request::new(())).await.unwrap();
+        // the other connections of the same host share the bucket
+        let mut cx = from_peer([10, 0, 0, 1], 1001);
+        let status = service.call(&mut cx, Request::new(())).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        let mut cx = from_peer([10, 0, 0, 2], 1000);
+        service.call(&mut cx, Request::new(())).await.unwrap();
+    }
+}
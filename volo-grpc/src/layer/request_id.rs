@@ -0,0 +1,289 @@
+//! Request id generation and propagation.
+//!
+//! On the server side, [`RequestIdLayer`] reads the request id from the incoming metadata, or
+//! generates a new one if missing. The id is stored in the context extensions, and also in the
+//! task local [`metainfo::METAINFO`], so that client calls made in the same task will forward it
+//! automatically when they also use [`RequestIdLayer`]. The id is added to the
+//! [`LogFields`](volo::context::LogFields) of the request as `request_id` on both sides.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::layer::request_id::{RequestId, RequestIdLayer};
+//!
+//! // server
+//! ItemServiceServer::new(S).layer(RequestIdLayer::new()).run(addr).await;
+//!
+//! // client
+//! ItemServiceClientBuilder::new("item").layer(RequestIdLayer::new()).build();
+//!
+//! // in the handler
+//! let id = cx.extensions().get::<RequestId>();
+//! ```
+
+use std::{cell::RefCell, fmt, future::Future};
+
+use motore::{layer::Layer, Service};
+use volo::context::{Context, Role};
+
+use crate::{
+    metadata::{AsciiMetadataKey, MetadataValue},
+    Request,
+};
+
+const DEFAULT_REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The id of the request, can be retrieved from the context extensions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Generates a random 128 bits id in hex.
+fn default_generator() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// A [`Service`] that generates and propagates the request id.
+#[derive(Clone)]
+pub struct RequestIdService<S> {
+    inner: S,
+    header: AsciiMetadataKey,
+    generator: fn() -> String,
+}
+
+impl<S> RequestIdService<S> {
+    fn request_id_of<T>(&self, req: &Request<T>) -> Option<RequestId> {
+        req.metadata()
+            .get(&self.header)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .map(|v| RequestId(v.to_string()))
+    }
+}
+
+impl<Cx, T, S> Service<Cx, Request<T>> for RequestIdService<S>
+where
+    Cx: Context + 'static + Send,
+    T: 'static + Send,
+    S: Service<Cx, Request<T>> + 'static + Send,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, mut req: Request<T>) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let role = cx.rpc_info().role();
+            match role {
+                Role::Server => {
+                    let id = self
+                        .request_id_of(&req)
+                        .unwrap_or_else(|| RequestId((self.generator)()));
+                    cx.log_field("request_id", &id);
+                    cx.extensions_mut().insert(id.clone());
+
+                    if metainfo::METAINFO.try_with(|_| {}).is_ok() {
+                        metainfo::METAINFO.with(|mi| mi.borrow_mut().insert(id));
+                        self.inner.call(cx, req).await
+                    } else {
+                        let mut mi = metainfo::MetaInfo::default();
+                        mi.insert(id);
+                        metainfo::METAINFO
+                            .scope(RefCell::new(mi), self.inner.call(cx, req))
+                            .await
+                    }
+                }
+                Role::Client => {
+                    let id = match self.request_id_of(&req) {
+                        Some(id) => id,
+                        None => cx
+                            .extensions()
+                            .get::<RequestId>()
+                            .cloned()
+                            .or_else(|| {
+                                metainfo::METAINFO
+                                    .try_with(|mi| mi.borrow().get::<RequestId>().cloned())
+                                    .ok()
+                                    .flatten()
+                            })
+                            .unwrap_or_else(|| RequestId((self.generator)())),
+                    };
+                    match id.as_str().parse::<MetadataValue<_>>() {
+                        Ok(value) => {
+                            req.metadata_mut().insert(self.header.clone(), value);
+                        }
+                        Err(_) => {
+                            tracing::warn!("[VOLO] invalid request id: {:?}", id);
+                        }
+                    }
+                    cx.log_field("request_id", &id);
+                    cx.extensions_mut().insert(id);
+                    self.inner.call(cx, req).await
+                }
+            }
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`RequestIdService`], the header is `x-request-id` by default.
+#[derive(Clone)]
+pub struct RequestIdLayer {
+    header: AsciiMetadataKey,
+    generator: fn() -> String,
+}
+
+impl RequestIdLayer {
+    pub fn new() -> Self {
+        Self {
+            header: AsciiMetadataKey::from_static(DEFAULT_REQUEST_ID_HEADER),
+            generator: default_generator,
+        }
+    }
+
+    /// Sets the metadata key used to carry the request id.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not a valid ascii metadata key.
+    pub fn header(mut self, header: &'static str) -> Self {
+        self.header = AsciiMetadataKey::from_static(header);
+        self
+    }
+
+    /// Sets the function used to generate the request id when it is missing.
+    pub fn generator(mut self, generator: fn() -> String) -> Self {
+        self.generator = generator;
+        self
+    }
+}
+
+impl Default for RequestIdLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        RequestIdService {
+            inner,
+            header: self.header,
+            generator: self.generator,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motore::service::service_fn;
+
+    use super::*;
+    use crate::{
+        context::{ClientContext, ServerContext},
+        Status,
+    };
+
+    /// Responds with the request id sent by the client.
+    async fn sent(_: &mut ClientContext, req: Request<()>) -> Result<String, Status> {
+        Ok(req
+            .metadata()
+            .get(DEFAULT_REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string())
+    }
+
+    /// Responds with the request id of the server and the one sent by a downstream call.
+    async fn forwarded(
+        cx: &mut ServerContext,
+        _: Request<()>,
+    ) -> Result<(RequestId, String), Status> {
+        let id = cx.extensions().get::<RequestId>().cloned().unwrap();
+        let mut client = RequestIdLayer::new().layer(service_fn(sent));
+        let sent = client
+            .call(&mut ClientContext::default(), Request::new(()))
+            .await?;
+        Ok((id, sent))
+    }
+
+    #[tokio::test]
+    async fn test_client_injection() {
+        let mut client = RequestIdLayer::new()
+            .generator(|| "generated".to_string())
+            .layer(service_fn(sent));
+
+        let mut cx = ClientContext::default();
+        let sent = client.call(&mut cx, Request::new(())).await.unwrap();
+        assert_eq!(sent, "generated");
+        assert_eq!(
+            cx.extensions().get::<RequestId>(),
+            Some(&RequestId("generated".into()))
+        );
+
+        // the id of the context is sent
+        let mut cx = ClientContext::default();
+        cx.extensions_mut().insert(RequestId("context".into()));
+        let sent = client.call(&mut cx, Request::new(())).await.unwrap();
+        assert_eq!(sent, "context");
+
+        // and the one set by the caller is kept
+        let mut req = Request::new(());
+        req.metadata_mut()
+            .insert(DEFAULT_REQUEST_ID_HEADER, "caller".parse().unwrap());
+        let sent = client
+            .call(&mut ClientContext::default(), req)
+            .await
+            .unwrap();
+        assert_eq!(sent, "caller");
+    }
+
+    #[tokio::test]
+    async fn test_server_propagation() {
+        let mut server = RequestIdLayer::new()
+            .generator(|| "generated".to_string())
+            .layer(service_fn(forwarded));
+
+        let mut req = Request::new(());
+        req.metadata_mut()
+            .insert(DEFAULT_REQUEST_ID_HEADER, "upstream".parse().unwrap());
+        let (id, sent) = server
+            .call(&mut ServerContext::default(), req)
+            .await
+            .unwrap();
+        assert_eq!(id, RequestId("upstream".into()));
+        // forwarded to the downstream by the task local
+        assert_eq!(sent, "upstream");
+
+        let (id, sent) = server
+            .call(&mut ServerContext::default(), Request::new(()))
+            .await
+            .unwrap();
+        assert_eq!(id, RequestId("generated".into()));
+        assert_eq!(sent, "generated");
+    }
+
+    #[test]
+    fn test_default_generator() {
+        let id = default_generator();
+        assert_eq!(id.len(), 32);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(id, default_generator());
+    }
+}
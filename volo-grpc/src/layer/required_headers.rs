@@ -0,0 +1,128 @@
+//! Rejects the requests missing the headers required by their methods on the server side.
+//!
+//! The required headers are annotated in the IDL, see `volo_build::headers`, and the generated
+//! `{Service}Headers::required` returns them by the path of the method. The requests missing any
+//! of them are rejected with `InvalidArgument` before getting to the handler.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::layer::required_headers::RequiredHeadersLayer;
+//!
+//! ItemServiceServer::new(S)
+//!     .layer(RequiredHeadersLayer::new(ItemServiceHeaders::required))
+//!     .run(addr)
+//!     .await;
+//!
+//! // in the handler
+//! let tenant = ItemServiceHeaders::x_tenant_id(req.metadata()).unwrap();
+//! ```
+
+use std::future::Future;
+
+use motore::{layer::Layer, Service};
+use volo::context::Context;
+
+use crate::{Request, Status};
+
+/// Returns the headers required by the method of the path.
+pub type Required = fn(&str) -> &'static [&'static str];
+
+/// A [`Service`] that rejects the requests missing the required headers.
+#[derive(Clone)]
+pub struct RequiredHeadersService<S> {
+    inner: S,
+    required: Required,
+}
+
+impl<Cx, T, S> Service<Cx, Request<T>> for RequiredHeadersService<S>
+where
+    Cx: Context + 'static + Send,
+    T: 'static + Send,
+    S: Service<Cx, Request<T>, Error = Status> + 'static + Send,
+{
+    type Response = S::Response;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Request<T>) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let method = cx.rpc_info().method().map_or("", |m| m.as_str());
+            let missing = (self.required)(method)
+                .iter()
+                .find(|h| req.metadata().get(**h).is_none());
+            if let Some(header) = missing {
+                return Err(Status::invalid_argument(format!(
+                    "missing the required header: {}",
+                    header
+                )));
+            }
+            self.inner.call(cx, req).await
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`RequiredHeadersService`].
+#[derive(Clone, Copy)]
+pub struct RequiredHeadersLayer {
+    required: Required,
+}
+
+impl RequiredHeadersLayer {
+    /// Creates a new [`RequiredHeadersLayer`] of the headers returned by `required`, such as the
+    /// generated `{Service}Headers::required`.
+    pub fn new(required: Required) -> Self {
+        Self { required }
+    }
+}
+
+impl<S> Layer<S> for RequiredHeadersLayer {
+    type Service = RequiredHeadersService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        RequiredHeadersService {
+            inner,
+            required: self.required,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motore::service::service_fn;
+
+    use super::*;
+    use crate::{context::ServerContext, status::Code};
+
+    fn required(path: &str) -> &'static [&'static str] {
+        match path {
+            "/a.S/A" => &["x-tenant-id"],
+            _ => &[],
+        }
+    }
+
+    async fn handler(_: &mut ServerContext, _: Request<()>) -> Result<(), Status> {
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_required_headers() {
+        let mut service = RequiredHeadersLayer::new(required).layer(service_fn(handler));
+
+        let mut cx = ServerContext::default();
+        cx.rpc_info.method = Some("/a.S/A".into());
+        let status = service.call(&mut cx, Request::new(())).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let mut req = Request::new(());
+        req.metadata_mut()
+            .insert("x-tenant-id", "t".parse().unwrap());
+        service.call(&mut cx, req).await.unwrap();
+
+        cx.rpc_info.method = Some("/a.S/B".into());
+        service.call(&mut cx, Request::new(())).await.unwrap();
+    }
+}
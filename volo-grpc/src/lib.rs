@@ -11,13 +11,24 @@ pub mod codec;
 #[doc(hidden)]
 pub mod codegen;
 pub mod context;
+pub mod error_details;
+pub mod health;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod keep_alive;
 pub mod layer;
+pub mod memory;
 mod message;
 pub mod metadata;
+pub mod proxy;
+#[cfg(feature = "reflection")]
+pub mod reflection;
 mod request;
 mod response;
 pub mod server;
 pub mod status;
+#[cfg(feature = "rustls")]
+pub mod tls;
 pub mod transport;
 
 pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
@@ -0,0 +1,297 @@
+//! The accounting of the memory buffered for the requests received by the server.
+//!
+//! The messages of a request are buffered until they are received whole, so without a limit, one
+//! client streaming huge messages can exhaust the memory of the process. With a
+//! [`MemoryBudget`] set by [`Server::memory_budget`](crate::server::Server::memory_budget), the
+//! bytes buffered by every request are accounted by its connection and in total, and when the
+//! total exceeds the budget:
+//!
+//! - By default the request buffering more fails with `ResourceExhausted`.
+//! - With [`OverBudget::Backpressure`], the requests stop reading until the memory is released,
+//!   which stops the clients from sending by the flow control of HTTP2.
+//!
+//! A message larger than the whole budget fails with `ResourceExhausted` either way. The buffers
+//! of HTTP2 itself are bounded by its window sizes, see the `http2_*` options of the server.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::memory::{MemoryBudget, MemoryHook, MemoryUsage, OverBudget};
+//!
+//! struct Report;
+//!
+//! impl MemoryHook for Report {
+//!     fn on_change(&self, usage: &MemoryUsage<'_>) {
+//!         metrics::gauge!("grpc_buffered_bytes", usage.total as f64);
+//!     }
+//! }
+//!
+//! let budget = MemoryBudget::new(512 * 1024 * 1024)
+//!     .over_budget(OverBudget::Backpressure)
+//!     .hook(Report);
+//! ItemServiceServer::new(S).memory_budget(budget).run(addr).await;
+//! ```
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use volo::net::Address;
+
+use crate::Status;
+
+/// What to do with the requests buffering more when the budget is exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverBudget {
+    /// Fails the requests with `ResourceExhausted`.
+    Reject,
+    /// Stops reading the requests until the memory is released.
+    Backpressure,
+}
+
+/// The bytes buffered when they change.
+#[derive(Debug)]
+pub struct MemoryUsage<'a> {
+    /// The address of the client.
+    pub peer: Option<&'a Address>,
+    /// The path of the request, such as `/volo.example.ItemService/GetItem`.
+    pub method: &'a str,
+    /// The bytes buffered by the request.
+    pub request: usize,
+    /// The bytes buffered by all the requests of the connection.
+    pub connection: usize,
+    /// The bytes buffered by all the requests.
+    pub total: usize,
+}
+
+/// Tracks the bytes buffered, such as by the metrics.
+pub trait MemoryHook: Send + Sync + 'static {
+    /// Called when the bytes buffered by a request change, so it should be quick.
+    fn on_change(&self, usage: &MemoryUsage<'_>);
+}
+
+/// The bytes buffered by all the requests, shared by the clones of a [`MemoryBudget`].
+#[derive(Default)]
+struct Usage {
+    used: AtomicUsize,
+    /// The requests waiting for the memory to be released.
+    waiters: Mutex<Vec<Waker>>,
+}
+
+/// The budget of a server, made when the server starts.
+pub(crate) struct Budget {
+    limit: usize,
+    over_budget: OverBudget,
+    hook: Option<Arc<dyn MemoryHook>>,
+    usage: Arc<Usage>,
+}
+
+impl Budget {
+    pub(crate) fn connection(self: &Arc<Self>, peer: Option<Address>) -> ConnMemory {
+        ConnMemory {
+            budget: self.clone(),
+            peer: peer.map(Arc::new),
+            used: Default::default(),
+        }
+    }
+}
+
+/// The budget of the bytes buffered by all the requests.
+///
+/// The clones share the bytes buffered, so [`used`](Self::used) can be read by a clone kept after
+/// setting the budget to the server.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    limit: usize,
+    over_budget: OverBudget,
+    hook: Option<Arc<dyn MemoryHook>>,
+    usage: Arc<Usage>,
+}
+
+impl MemoryBudget {
+    /// Creates a new [`MemoryBudget`] of `limit` bytes, rejecting the requests over it.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            over_budget: OverBudget::Reject,
+            hook: None,
+            usage: Default::default(),
+        }
+    }
+
+    /// Sets what to do with the requests buffering more when the budget is exceeded.
+    ///
+    /// Default is [`OverBudget::Reject`].
+    pub fn over_budget(mut self, over_budget: OverBudget) -> Self {
+        self.over_budget = over_budget;
+        self
+    }
+
+    /// Sets the hook tracking the bytes buffered.
+    pub fn hook(mut self, hook: impl MemoryHook) -> Self {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Returns the bytes buffered by all the requests.
+    pub fn used(&self) -> usize {
+        self.usage.used.load(Ordering::Relaxed)
+    }
+
+    /// Returns the limit of the bytes buffered.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub(crate) fn build(&self) -> Arc<Budget> {
+        Arc::new(Budget {
+            limit: self.limit,
+            over_budget: self.over_budget,
+            hook: self.hook.clone(),
+            usage: self.usage.clone(),
+        })
+    }
+}
+
+/// The bytes buffered by the requests of a connection.
+#[derive(Clone)]
+pub(crate) struct ConnMemory {
+    budget: Arc<Budget>,
+    peer: Option<Arc<Address>>,
+    used: Arc<AtomicUsize>,
+}
+
+impl ConnMemory {
+    pub(crate) fn request(&self, method: &str) -> RequestMemory {
+        RequestMemory {
+            conn: self.clone(),
+            method: method.into(),
+            used: 0,
+        }
+    }
+}
+
+/// The bytes buffered by a request, which are released when dropped.
+pub(crate) struct RequestMemory {
+    conn: ConnMemory,
+    method: smol_str::SmolStr,
+    used: usize,
+}
+
+impl RequestMemory {
+    /// Fails if a message of `len` bytes can never be buffered in the budget.
+    pub(crate) fn check(&self, len: usize) -> Result<(), Status> {
+        if len > self.conn.budget.limit {
+            return Err(Status::resource_exhausted(format!(
+                "the message of {} bytes exceeds the memory budget",
+                len
+            )));
+        }
+        Ok(())
+    }
+
+    /// Sets the bytes buffered by the request, fails if it exceeds the budget when rejecting.
+    pub(crate) fn set(&mut self, used: usize) -> Result<(), Status> {
+        if used == self.used {
+            return Ok(());
+        }
+        let budget = &self.conn.budget;
+        let (total, connection) = if used > self.used {
+            let delta = used - self.used;
+            (
+                budget.usage.used.fetch_add(delta, Ordering::Relaxed) + delta,
+                self.conn.used.fetch_add(delta, Ordering::Relaxed) + delta,
+            )
+        } else {
+            let delta = self.used - used;
+            (
+                budget.usage.used.fetch_sub(delta, Ordering::Relaxed) - delta,
+                self.conn.used.fetch_sub(delta, Ordering::Relaxed) - delta,
+            )
+        };
+        let released = used < self.used;
+        self.used = used;
+
+        if let Some(hook) = &budget.hook {
+            hook.on_change(&MemoryUsage {
+                peer: self.conn.peer.as_deref(),
+                method: &self.method,
+                request: used,
+                connection,
+                total,
+            });
+        }
+        if released && total < budget.limit {
+            for waker in std::mem::take(&mut *budget.usage.waiters.lock().unwrap()) {
+                waker.wake();
+            }
+        }
+        if !released && total > budget.limit && budget.over_budget == OverBudget::Reject {
+            return Err(Status::resource_exhausted(
+                "the memory budget of the server is exceeded",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Waits for the memory to be released when exceeding the budget with backpressure.
+    pub(crate) fn poll_available(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let budget = &self.conn.budget;
+        if budget.over_budget == OverBudget::Reject
+            || budget.usage.used.load(Ordering::Relaxed) < budget.limit
+        {
+            return Poll::Ready(());
+        }
+        let mut waiters = budget.usage.waiters.lock().unwrap();
+        // released before being locked
+        if budget.usage.used.load(Ordering::Relaxed) < budget.limit {
+            return Poll::Ready(());
+        }
+        waiters.push(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for RequestMemory {
+    fn drop(&mut self) {
+        let _ = self.set(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::task::noop_waker_ref;
+
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let budget = MemoryBudget::new(100);
+        let conn = budget.build().connection(None);
+        let mut a = conn.request("/a.S/A");
+        let mut b = conn.request("/a.S/B");
+        a.set(60).unwrap();
+        b.check(120).unwrap_err();
+        b.set(60).unwrap_err();
+        assert_eq!(budget.used(), 120);
+        drop(b);
+        assert_eq!(budget.used(), 60);
+
+        // configured after cloned
+        let kept = MemoryBudget::new(100);
+        let budget = kept.clone().over_budget(OverBudget::Backpressure);
+        let conn = budget.build().connection(None);
+        let mut a = conn.request("/a.S/A");
+        let b = conn.request("/a.S/B");
+        a.set(120).unwrap();
+        assert_eq!(kept.used(), 120);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert!(b.poll_available(&mut cx).is_pending());
+        a.set(10).unwrap();
+        assert!(b.poll_available(&mut cx).is_ready());
+    }
+}
@@ -1,12 +1,42 @@
 use bytes::Bytes;
 use hyper::Body;
+use prost::Message;
 
-use crate::codec::decode::Kind;
+use crate::{
+    codec::decode::{DecodeConfig, Kind},
+    RecvStream,
+};
 
 pub trait SendEntryMessage {
     fn into_body(self) -> crate::BoxStream<'static, Result<Bytes, crate::Status>>;
 }
 
 pub trait RecvEntryMessage: Sized {
-    fn from_body(method: Option<&str>, body: Body, kind: Kind) -> Result<Self, crate::Status>;
+    fn from_body(
+        method: Option<&str>,
+        body: Body,
+        kind: Kind,
+        config: DecodeConfig,
+    ) -> Result<Self, crate::Status>;
+}
+
+/// The stream of the messages of a single method, such as of the built-in services.
+impl<T: Message + 'static> SendEntryMessage
+    for crate::BoxStream<'static, Result<T, crate::Status>>
+{
+    fn into_body(self) -> crate::BoxStream<'static, Result<Bytes, crate::Status>> {
+        crate::codec::encode::encode(self)
+    }
+}
+
+/// The stream of the messages of a single method, such as of the built-in services.
+impl<T: Message + Default> RecvEntryMessage for RecvStream<T> {
+    fn from_body(
+        _method: Option<&str>,
+        body: Body,
+        kind: Kind,
+        config: DecodeConfig,
+    ) -> Result<Self, crate::Status> {
+        Ok(RecvStream::with_config(body, kind, config))
+    }
 }
@@ -4,6 +4,7 @@
 mod encoding;
 mod key;
 mod map;
+mod typed;
 mod value;
 
 pub(crate) use self::map::GRPC_TIMEOUT_HEADER;
@@ -15,12 +16,14 @@ pub use self::{
         OccupiedEntry, VacantEntry, ValueDrain, ValueIter, ValueRef, ValueRefMut, Values,
         ValuesMut,
     },
+    typed::ContentType,
     value::{AsciiMetadataValue, BinaryMetadataValue, MetadataValue},
 };
 pub mod errors {
     pub use super::{
         encoding::{InvalidMetadataValue, InvalidMetadataValueBytes},
         key::InvalidMetadataKey,
+        typed::InvalidTypedMetadata,
         value::ToStrError,
     };
 }
@@ -0,0 +1,219 @@
+//! Typed accessors of the common metadata, which parse and validate the values instead of the
+//! layers and the handlers matching the strings of them each.
+
+use std::{error::Error, fmt, str::FromStr, time::Duration};
+
+use super::{MetadataMap, MetadataValue, GRPC_TIMEOUT_HEADER};
+use crate::layer::grpc_timeout::{duration_to_grpc_timeout, parse_grpc_timeout};
+
+const CONTENT_TYPE_HEADER: &str = "content-type";
+const AUTHORIZATION_HEADER: &str = "authorization";
+
+const GRPC_CONTENT_TYPE: &str = "application/grpc";
+const BEARER_SCHEME: &str = "Bearer";
+
+/// An error of the metadata value in wrong format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidTypedMetadata {
+    key: &'static str,
+}
+
+impl InvalidTypedMetadata {
+    fn new(key: &'static str) -> Self {
+        Self { key }
+    }
+
+    /// Returns the key of the invalid metadata.
+    pub fn key(&self) -> &'static str {
+        self.key
+    }
+}
+
+impl fmt::Display for InvalidTypedMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid value of metadata `{}`", self.key)
+    }
+}
+
+impl Error for InvalidTypedMetadata {}
+
+/// The content type of gRPC, `application/grpc` with an optional subtype such as `proto` or
+/// `json`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ContentType {
+    subtype: Option<String>,
+}
+
+impl ContentType {
+    /// Creates the content type of the subtype, such as `application/grpc+json`.
+    pub fn with_subtype(subtype: impl Into<String>) -> Self {
+        Self {
+            subtype: Some(subtype.into()),
+        }
+    }
+
+    /// Returns the subtype if any.
+    pub fn subtype(&self) -> Option<&str> {
+        self.subtype.as_deref()
+    }
+
+    /// Returns whether the messages are encoded in protobuf, which is the default of no subtype.
+    pub fn is_proto(&self) -> bool {
+        matches!(self.subtype(), None | Some("proto"))
+    }
+}
+
+impl FromStr for ContentType {
+    type Err = InvalidTypedMetadata;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || InvalidTypedMetadata::new(CONTENT_TYPE_HEADER);
+        // the parameters, such as the charset, are ignored
+        let mime = s.split(';').next().unwrap_or_default().trim();
+        match mime.get(..GRPC_CONTENT_TYPE.len()) {
+            Some(prefix) if prefix.eq_ignore_ascii_case(GRPC_CONTENT_TYPE) => {}
+            _ => return Err(err()),
+        }
+        match &mime[GRPC_CONTENT_TYPE.len()..] {
+            "" => Ok(Self::default()),
+            rest => match rest.strip_prefix('+') {
+                Some(subtype) if is_token(subtype) => {
+                    Ok(Self::with_subtype(subtype.to_ascii_lowercase()))
+                }
+                _ => Err(err()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.subtype {
+            Some(subtype) => write!(f, "{}+{}", GRPC_CONTENT_TYPE, subtype),
+            None => f.write_str(GRPC_CONTENT_TYPE),
+        }
+    }
+}
+
+// the token of RFC 7230
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+// the token68 of RFC 7235
+fn is_token68(s: &str) -> bool {
+    let token = s.trim_end_matches('=');
+    !token.is_empty()
+        && token
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._~+/".contains(&b))
+}
+
+impl MetadataMap {
+    fn get_str(&self, key: &'static str) -> Result<Option<&str>, InvalidTypedMetadata> {
+        self.get(key)
+            .map(|value| value.to_str().map_err(|_| InvalidTypedMetadata::new(key)))
+            .transpose()
+    }
+
+    /// Returns the timeout of the request in the `grpc-timeout` metadata.
+    pub fn grpc_timeout(&self) -> Result<Option<Duration>, InvalidTypedMetadata> {
+        self.get_str(GRPC_TIMEOUT_HEADER)?
+            .map(|s| {
+                parse_grpc_timeout(s).ok_or_else(|| InvalidTypedMetadata::new(GRPC_TIMEOUT_HEADER))
+            })
+            .transpose()
+    }
+
+    /// Sets the timeout of the request as the `grpc-timeout` metadata.
+    pub fn set_grpc_timeout(&mut self, timeout: Duration) {
+        let value: MetadataValue<_> = duration_to_grpc_timeout(timeout)
+            .parse()
+            .expect("grpc-timeout is always a valid ascii value");
+        self.insert(GRPC_TIMEOUT_HEADER, value);
+    }
+
+    /// Returns the `content-type` metadata, which is only present in the received requests, and
+    /// is set by the transports when sending.
+    pub fn content_type(&self) -> Result<Option<ContentType>, InvalidTypedMetadata> {
+        self.get_str(CONTENT_TYPE_HEADER)?
+            .map(str::parse)
+            .transpose()
+    }
+
+    /// Returns the token of the `authorization` metadata of the bearer scheme.
+    ///
+    /// Returns `Ok(None)` if the metadata is absent, and an error if it's of another scheme or
+    /// the token is invalid.
+    pub fn bearer_token(&self) -> Result<Option<&str>, InvalidTypedMetadata> {
+        let value = match self.get_str(AUTHORIZATION_HEADER)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let err = || InvalidTypedMetadata::new(AUTHORIZATION_HEADER);
+        let (scheme, token) = value.split_once(' ').ok_or_else(err)?;
+        let token = token.trim_start_matches(' ');
+        if !scheme.eq_ignore_ascii_case(BEARER_SCHEME) || !is_token68(token) {
+            return Err(err());
+        }
+        Ok(Some(token))
+    }
+
+    /// Sets the `authorization` metadata of the bearer scheme, marked as sensitive.
+    ///
+    /// Returns an error if the token isn't a valid token68, such as containing the whitespaces.
+    pub fn set_bearer_token(&mut self, token: &str) -> Result<(), InvalidTypedMetadata> {
+        if !is_token68(token) {
+            return Err(InvalidTypedMetadata::new(AUTHORIZATION_HEADER));
+        }
+        let mut value: MetadataValue<_> = format!("{} {}", BEARER_SCHEME, token)
+            .parse()
+            .expect("bearer token is always a valid ascii value");
+        value.set_sensitive(true);
+        self.insert(AUTHORIZATION_HEADER, value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_metadata() {
+        let mut metadata = MetadataMap::new();
+        assert_eq!(metadata.grpc_timeout(), Ok(None));
+        metadata.set_grpc_timeout(Duration::from_millis(1500));
+        assert_eq!(
+            metadata.grpc_timeout(),
+            Ok(Some(Duration::from_millis(1500)))
+        );
+        metadata.insert(GRPC_TIMEOUT_HEADER, MetadataValue::from_static("10x"));
+        metadata.grpc_timeout().unwrap_err();
+
+        metadata.set_bearer_token("abc.DEF-123==").unwrap();
+        assert_eq!(metadata.bearer_token(), Ok(Some("abc.DEF-123==")));
+        metadata.set_bearer_token("a b").unwrap_err();
+        metadata.insert(
+            AUTHORIZATION_HEADER,
+            MetadataValue::from_static("Basic YTpi"),
+        );
+        metadata.bearer_token().unwrap_err();
+
+        metadata.insert(
+            CONTENT_TYPE_HEADER,
+            MetadataValue::from_static("application/grpc+JSON; charset=utf-8"),
+        );
+        let content_type = metadata.content_type().unwrap().unwrap();
+        assert_eq!(content_type.subtype(), Some("json"));
+        assert_eq!(content_type.to_string(), "application/grpc+json");
+        assert!("application/grpc"
+            .parse::<ContentType>()
+            .unwrap()
+            .is_proto());
+        "application/json".parse::<ContentType>().unwrap_err();
+        "application/grpcweb".parse::<ContentType>().unwrap_err();
+    }
+}
@@ -0,0 +1,351 @@
+//! Raw body passthrough, used to build gRPC proxies without generated code.
+//!
+//! [`RawBody`] carries the gRPC frames as they are on the wire, without protobuf decoding, so a
+//! request received by the server can be forwarded to the upstream by [`RawClient`] untouched.
+//! The proxies inspecting the messages, such as counting or rewriting them, can split the body
+//! into [`RawFrame`]s, which are still encoded by protobuf.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::{
+//!     client::ClientBuilder,
+//!     proxy::{ProxyService, RawClient},
+//!     server::Server,
+//! };
+//!
+//! let client = ClientBuilder::new(RawClient::new(), "upstream")
+//!     .target(upstream_addr)
+//!     .build();
+//!
+//! Server::new(ProxyService::new(client)).run(addr).await;
+//! ```
+
+use std::future::Future;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use hyper::body::HttpBody;
+use motore::Service;
+use volo::Unwrap;
+
+use crate::{
+    client::{Client, SetClient},
+    codec::{
+        decode::{DecodeConfig, Kind},
+        PREFIX_LEN,
+    },
+    context::ServerContext,
+    message::{RecvEntryMessage, SendEntryMessage},
+    BoxStream, Code, Request, Response, Status,
+};
+
+/// A length-prefixed message of a gRPC body, with the payload still encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawFrame {
+    /// Whether the payload is compressed by the `grpc-encoding` of the metadata.
+    pub compressed: bool,
+    pub data: Bytes,
+}
+
+/// The undecoded gRPC body, including the length-prefixed frames.
+///
+/// A non-ok `grpc-status` in the trailers of a received body is yielded as the last item of
+/// the stream, so that it will be sent as the trailers again when forwarded.
+pub struct RawBody {
+    stream: BoxStream<'static, Result<Bytes, Status>>,
+}
+
+impl RawBody {
+    /// Creates a new [`RawBody`] from the stream of frames.
+    pub fn new<S>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<Bytes, Status>> + Send + 'static,
+    {
+        Self {
+            stream: Box::pin(stream),
+        }
+    }
+
+    /// Consumes `self`, returning the stream of frames.
+    pub fn into_stream(self) -> BoxStream<'static, Result<Bytes, Status>> {
+        self.stream
+    }
+
+    /// Creates a new [`RawBody`] by prefixing the frames with their lengths.
+    pub fn from_frames<S>(frames: S) -> Self
+    where
+        S: Stream<Item = Result<RawFrame, Status>> + Send + 'static,
+    {
+        Self::new(frames.map(|frame| {
+            let frame = frame?;
+            let mut buf = BytesMut::with_capacity(PREFIX_LEN + frame.data.len());
+            buf.put_u8(frame.compressed as u8);
+            buf.put_u32(frame.data.len() as u32);
+            buf.put_slice(&frame.data);
+            Ok(buf.freeze())
+        }))
+    }
+
+    /// Consumes `self`, returning the stream of the messages split at the length prefixes,
+    /// regardless of how the body is chunked.
+    pub fn into_frames(self) -> BoxStream<'static, Result<RawFrame, Status>> {
+        let mut stream = self.stream;
+        Box::pin(async_stream::stream! {
+            let mut buf = BytesMut::new();
+            while let Some(data) = stream.next().await {
+                match data {
+                    Ok(data) => buf.extend_from_slice(&data),
+                    Err(status) => {
+                        yield Err(status);
+                        return;
+                    }
+                }
+                while buf.len() >= PREFIX_LEN {
+                    let len = (&buf[1..PREFIX_LEN]).get_u32() as usize;
+                    if buf.len() < PREFIX_LEN + len {
+                        break;
+                    }
+                    let compressed = buf.get_u8() != 0;
+                    buf.advance(PREFIX_LEN - 1);
+                    yield Ok(RawFrame {
+                        compressed,
+                        data: buf.split_to(len).freeze(),
+                    });
+                }
+            }
+            if !buf.is_empty() {
+                yield Err(Status::new(
+                    Code::Internal,
+                    "the body ends within a frame".to_string(),
+                ));
+            }
+        })
+    }
+
+    fn from_hyper(mut body: hyper::Body) -> Self {
+        Self::new(async_stream::stream! {
+            while let Some(data) = body.data().await {
+                match data {
+                    Ok(data) => yield Ok(data),
+                    Err(err) => {
+                        yield Err(Status::from_error(err.into()));
+                        return;
+                    }
+                }
+            }
+            match body.trailers().await {
+                Ok(Some(trailers)) => {
+                    if let Some(status) = Status::from_header_map(&trailers) {
+                        if status.code() != Code::Ok {
+                            yield Err(status);
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => yield Err(Status::from_error(err.into())),
+            }
+        })
+    }
+}
+
+impl SendEntryMessage for RawBody {
+    fn into_body(self) -> BoxStream<'static, Result<Bytes, Status>> {
+        self.stream
+    }
+}
+
+impl RecvEntryMessage for RawBody {
+    fn from_body(
+        _method: Option<&str>,
+        body: hyper::Body,
+        _kind: Kind,
+        _config: DecodeConfig,
+    ) -> Result<Self, Status> {
+        Ok(Self::from_hyper(body))
+    }
+}
+
+/// A client that sends [`RawBody`] to any path, built by
+/// [`ClientBuilder`][crate::client::ClientBuilder].
+#[derive(Clone, Default)]
+pub struct RawClient {
+    client: Option<Client<RawBody, RawBody>>,
+}
+
+impl RawClient {
+    pub fn new() -> Self {
+        Self { client: None }
+    }
+
+    /// Sends the request to the given path, such as `/package.Service/Method`.
+    pub async fn call(
+        &mut self,
+        path: impl Into<smol_str::SmolStr>,
+        req: Request<RawBody>,
+    ) -> Result<Response<RawBody>, Status> {
+        self.client.as_mut().volo_unwrap().call(path, req).await
+    }
+}
+
+impl SetClient<RawBody, RawBody> for RawClient {
+    fn set_client(self, client: Client<RawBody, RawBody>) -> Self {
+        Self {
+            client: Some(client),
+        }
+    }
+}
+
+/// A server side [`Service`] that forwards every request to the upstream as it is.
+#[derive(Clone)]
+pub struct ProxyService {
+    client: RawClient,
+}
+
+impl ProxyService {
+    pub fn new(client: RawClient) -> Self {
+        Self { client }
+    }
+}
+
+impl Service<ServerContext, Request<RawBody>> for ProxyService {
+    type Response = Response<RawBody>;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(
+        &'s mut self,
+        cx: &'cx mut ServerContext,
+        req: Request<RawBody>,
+    ) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let path = cx.rpc_info.method().cloned().ok_or_else(|| {
+                Status::new(Code::Internal, "missing method in the context".to_string())
+            })?;
+            self.client.call(path, req).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motore::service::service_fn;
+    use volo::net::incoming::Incoming;
+
+    use super::*;
+    use crate::{client::ClientBuilder, server::Server};
+
+    async fn upstream(
+        cx: &mut ServerContext,
+        req: Request<RawBody>,
+    ) -> Result<Response<RawBody>, Status> {
+        if cx.rpc_info.method().map(|m| m.as_str()) == Some("/test.Echo/Fail") {
+            return Err(Status::not_found("no such item"));
+        }
+        let user = req.metadata().get("x-user").cloned();
+        let mut resp = Response::new(req.into_inner());
+        if let Some(user) = user {
+            resp.metadata_mut().insert("x-user", user);
+        }
+        Ok(resp)
+    }
+
+    async fn serve<S>(service: S) -> std::net::SocketAddr
+    where
+        S: Service<ServerContext, Request<RawBody>, Response = Response<RawBody>, Error = Status>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        for<'cx> S::Future<'cx>: Send,
+    {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::new(service).run(Incoming::from(listener)));
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_proxy() {
+        let upstream = serve(service_fn(upstream)).await;
+        let client = ClientBuilder::new(RawClient::new(), "upstream")
+            .target(upstream)
+            .build();
+        let proxy = serve(ProxyService::new(client)).await;
+        let mut client = ClientBuilder::new(RawClient::new(), "proxy")
+            .target(proxy)
+            .build();
+
+        let frames = vec![
+            RawFrame {
+                compressed: false,
+                data: Bytes::from_static(b"hello"),
+            },
+            RawFrame {
+                compressed: false,
+                data: Bytes::from_static(b"world"),
+            },
+        ];
+        let mut req = Request::new(RawBody::from_frames(
+            futures::stream::iter(frames.clone()).map(Ok),
+        ));
+        req.metadata_mut()
+            .insert("x-user", "alice".parse().unwrap());
+        let resp = client.call("/test.Echo/Say", req).await.unwrap();
+        // the metadata and the frames are forwarded both ways untouched
+        assert_eq!(resp.metadata().get("x-user").unwrap(), "alice");
+        let got: Vec<_> = resp
+            .into_inner()
+            .into_frames()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(got, frames);
+
+        // and so is the status of the upstream
+        let req = Request::new(RawBody::from_frames(futures::stream::empty()));
+        let status = match client.call("/test.Echo/Fail", req).await {
+            Ok(resp) => resp
+                .into_inner()
+                .into_stream()
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .find_map(Result::err)
+                .unwrap(),
+            Err(status) => status,
+        };
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(status.message(), "no such item");
+    }
+
+    #[tokio::test]
+    async fn test_frames() {
+        let frames = vec![
+            RawFrame {
+                compressed: false,
+                data: Bytes::from_static(b"hello"),
+            },
+            RawFrame {
+                compressed: true,
+                data: Bytes::new(),
+            },
+        ];
+        let body = RawBody::from_frames(futures::stream::iter(frames.clone()).map(Ok));
+        let mut bytes = BytesMut::new();
+        for chunk in body.into_stream().collect::<Vec<_>>().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        // rechunked byte by byte
+        let bytes = bytes.freeze();
+        let chunks: Vec<_> = (0..bytes.len())
+            .map(|i| Ok(bytes.slice(i..i + 1)))
+            .collect();
+        let body = RawBody::new(futures::stream::iter(chunks));
+        let got: Vec<_> = body.into_frames().map(Result::unwrap).collect().await;
+        assert_eq!(got, frames);
+    }
+}
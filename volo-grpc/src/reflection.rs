@@ -0,0 +1,592 @@
+//! The server reflection, `grpc.reflection.v1alpha.ServerReflection`, for the tools such as
+//! grpcurl and Postman to discover the services of the server and the schemas of their messages.
+//!
+//! The schemas are served from the encoded `FileDescriptorSet`s of the protos, which `volo-build`
+//! writes with `file_descriptor_set(true)`, as the `FILE_DESCRIPTOR_SET` of the generated code.
+//! The files are sent with all the files they import.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! ItemServiceServer::new(S)
+//!     .enable_reflection(volo_gen::FILE_DESCRIPTOR_SET)
+//!     .run(addr)
+//!     .await;
+//!
+//! // or along with the other services of a router
+//! use volo_grpc::{reflection::ServerReflection, server::Router};
+//!
+//! Server::new(Router::new())
+//!     .add_service(ItemServiceServer::service(S))
+//!     .add_service(ServerReflection::new(volo_gen::FILE_DESCRIPTOR_SET))
+//!     .run(addr)
+//!     .await;
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::Arc,
+};
+
+use futures::StreamExt;
+use motore::Service;
+use once_cell::sync::OnceCell;
+use prost::Message;
+use prost_types::{DescriptorProto, EnumDescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use volo::service_info::ServiceInfo;
+
+use crate::{context::ServerContext, BoxStream, Code, RecvStream, Request, Response, Status};
+
+const PATH: &str = "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo";
+
+/// The request of the reflection.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ServerReflectionRequest {
+    #[prost(string, tag = "1")]
+    pub host: String,
+    #[prost(
+        oneof = "server_reflection_request::MessageRequest",
+        tags = "3, 4, 5, 6, 7"
+    )]
+    pub message_request: Option<server_reflection_request::MessageRequest>,
+}
+
+pub mod server_reflection_request {
+    #[derive(Clone, PartialEq, Eq, prost::Oneof)]
+    pub enum MessageRequest {
+        /// The file of the name, such as `volo/example/item.proto`.
+        #[prost(string, tag = "3")]
+        FileByFilename(String),
+        /// The file defining the symbol, such as `volo.example.ItemService.GetItem`.
+        #[prost(string, tag = "4")]
+        FileContainingSymbol(String),
+        /// The file defining the extension.
+        #[prost(message, tag = "5")]
+        FileContainingExtension(super::ExtensionRequest),
+        /// The numbers of the extensions of the message type.
+        #[prost(string, tag = "6")]
+        AllExtensionNumbersOfType(String),
+        /// The services of the server.
+        #[prost(string, tag = "7")]
+        ListServices(String),
+    }
+}
+
+/// The extension of a message type.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ExtensionRequest {
+    #[prost(string, tag = "1")]
+    pub containing_type: String,
+    #[prost(int32, tag = "2")]
+    pub extension_number: i32,
+}
+
+/// The response of the reflection.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ServerReflectionResponse {
+    #[prost(string, tag = "1")]
+    pub valid_host: String,
+    #[prost(message, optional, tag = "2")]
+    pub original_request: Option<ServerReflectionRequest>,
+    #[prost(
+        oneof = "server_reflection_response::MessageResponse",
+        tags = "4, 5, 6, 7"
+    )]
+    pub message_response: Option<server_reflection_response::MessageResponse>,
+}
+
+pub mod server_reflection_response {
+    #[derive(Clone, PartialEq, Eq, prost::Oneof)]
+    pub enum MessageResponse {
+        #[prost(message, tag = "4")]
+        FileDescriptorResponse(super::FileDescriptorResponse),
+        #[prost(message, tag = "5")]
+        AllExtensionNumbersResponse(super::ExtensionNumberResponse),
+        #[prost(message, tag = "6")]
+        ListServicesResponse(super::ListServiceResponse),
+        #[prost(message, tag = "7")]
+        ErrorResponse(super::ErrorResponse),
+    }
+}
+
+/// The encoded `FileDescriptorProto`s of the file and the files it imports.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct FileDescriptorResponse {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub file_descriptor_proto: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ExtensionNumberResponse {
+    #[prost(string, tag = "1")]
+    pub base_type_name: String,
+    #[prost(int32, repeated, tag = "2")]
+    pub extension_number: Vec<i32>,
+}
+
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ListServiceResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub service: Vec<ServiceResponse>,
+}
+
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ServiceResponse {
+    /// The full name of the service, such as `volo.example.ItemService`.
+    #[prost(string, tag = "1")]
+    pub name: String,
+}
+
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ErrorResponse {
+    /// The code of the [`Status`].
+    #[prost(int32, tag = "1")]
+    pub error_code: i32,
+    #[prost(string, tag = "2")]
+    pub error_message: String,
+}
+
+struct File {
+    encoded: Vec<u8>,
+    dependencies: Vec<String>,
+}
+
+#[derive(Default)]
+struct Index {
+    services: Vec<String>,
+    files: HashMap<String, File>,
+    /// The files by the full names of the symbols defined in them.
+    symbols: HashMap<String, String>,
+    /// The files by the extended message types and the numbers of the extensions.
+    extensions: HashMap<(String, i32), String>,
+}
+
+impl Index {
+    fn add(&mut self, file: FileDescriptorProto) {
+        let name = file.name().to_string();
+        let package = file.package();
+        for service in file.service.iter() {
+            let service_name = full_name(package, service.name());
+            for method in service.method.iter() {
+                self.symbol(full_name(&service_name, method.name()), &name);
+            }
+            self.symbol(service_name.clone(), &name);
+            self.services.push(service_name);
+        }
+        for message in file.message_type.iter() {
+            self.message(package, message, &name);
+        }
+        for e in file.enum_type.iter() {
+            self.enumeration(package, e, &name);
+        }
+        for extension in file.extension.iter() {
+            self.extension(package, extension, &name);
+        }
+        self.files.insert(
+            name,
+            File {
+                encoded: file.encode_to_vec(),
+                dependencies: file.dependency,
+            },
+        );
+    }
+
+    fn message(&mut self, scope: &str, message: &DescriptorProto, file: &str) {
+        let name = full_name(scope, message.name());
+        for nested in message.nested_type.iter() {
+            self.message(&name, nested, file);
+        }
+        for e in message.enum_type.iter() {
+            self.enumeration(&name, e, file);
+        }
+        for field in message.field.iter() {
+            self.symbol(full_name(&name, field.name()), file);
+        }
+        for extension in message.extension.iter() {
+            self.extension(&name, extension, file);
+        }
+        self.symbol(name, file);
+    }
+
+    fn enumeration(&mut self, scope: &str, e: &EnumDescriptorProto, file: &str) {
+        // the values are in the scope of the enum
+        for value in e.value.iter() {
+            self.symbol(full_name(scope, value.name()), file);
+        }
+        self.symbol(full_name(scope, e.name()), file);
+    }
+
+    fn extension(
+        &mut self,
+        scope: &str,
+        extension: &prost_types::FieldDescriptorProto,
+        file: &str,
+    ) {
+        let extendee = extension.extendee().trim_start_matches('.').to_string();
+        self.extensions
+            .insert((extendee, extension.number()), file.to_string());
+        self.symbol(full_name(scope, extension.name()), file);
+    }
+
+    fn symbol(&mut self, name: String, file: &str) {
+        self.symbols.insert(name, file.to_string());
+    }
+
+    /// Returns the file and the files it imports, transitively.
+    fn files_of(&self, name: &str) -> Result<Vec<Vec<u8>>, Status> {
+        let mut files = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = vec![name];
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name) {
+                continue;
+            }
+            let file = self
+                .files
+                .get(name)
+                .ok_or_else(|| Status::not_found(format!("file not found: {}", name)))?;
+            files.push(file.encoded.clone());
+            pending.extend(file.dependencies.iter().map(String::as_str));
+        }
+        Ok(files)
+    }
+
+    fn respond(
+        &self,
+        request: &server_reflection_request::MessageRequest,
+    ) -> Result<Reply, Status> {
+        use server_reflection_request::MessageRequest;
+
+        let file = match request {
+            MessageRequest::FileByFilename(name) => name,
+            MessageRequest::FileContainingSymbol(symbol) => self
+                .symbols
+                .get(symbol.trim_start_matches('.'))
+                .ok_or_else(|| Status::not_found(format!("symbol not found: {}", symbol)))?,
+            MessageRequest::FileContainingExtension(extension) => self
+                .extensions
+                .get(&(
+                    extension.containing_type.clone(),
+                    extension.extension_number,
+                ))
+                .ok_or_else(|| {
+                    Status::not_found(format!(
+                        "extension not found: {}({})",
+                        extension.containing_type, extension.extension_number
+                    ))
+                })?,
+            MessageRequest::AllExtensionNumbersOfType(ty) => {
+                if !self.symbols.contains_key(ty) {
+                    return Err(Status::not_found(format!("type not found: {}", ty)));
+                }
+                let mut numbers: Vec<_> = self
+                    .extensions
+                    .keys()
+                    .filter(|(extendee, _)| extendee == ty)
+                    .map(|(_, number)| *number)
+                    .collect();
+                numbers.sort_unstable();
+                return Ok(Reply::ExtensionNumbers(ty.clone(), numbers));
+            }
+            MessageRequest::ListServices(_) => return Ok(Reply::Services),
+        };
+        self.files_of(file).map(Reply::Files)
+    }
+}
+
+enum Reply {
+    Files(Vec<Vec<u8>>),
+    ExtensionNumbers(String, Vec<i32>),
+    Services,
+}
+
+fn full_name(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+/// The reflection service, serving the schemas of the encoded `FileDescriptorSet`s registered.
+#[derive(Clone)]
+pub struct ServerReflection {
+    files: Vec<FileDescriptorProto>,
+    /// The index of the files, built by the first request and shared by the clones made by then.
+    index: Arc<OnceCell<Index>>,
+}
+
+impl ServerReflection {
+    /// Creates a new [`ServerReflection`] of the encoded `FileDescriptorSet`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `FileDescriptorSet` is invalid.
+    pub fn new(file_descriptor_set: &[u8]) -> Self {
+        Self {
+            files: Vec::new(),
+            index: Default::default(),
+        }
+        .register(file_descriptor_set)
+    }
+
+    /// Registers another encoded `FileDescriptorSet`, such as of the protos compiled by another
+    /// crate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `FileDescriptorSet` is invalid.
+    pub fn register(mut self, file_descriptor_set: &[u8]) -> Self {
+        let set =
+            FileDescriptorSet::decode(file_descriptor_set).expect("invalid FileDescriptorSet");
+        self.files.extend(set.file);
+        // the clones made before keep the files of their own
+        self.index = Default::default();
+        self
+    }
+
+    fn index(&self) -> &Index {
+        self.index.get_or_init(|| {
+            let mut index = Index::default();
+            for file in self.files.iter().cloned() {
+                index.add(file);
+            }
+            index
+        })
+    }
+
+    fn reply(&self, request: ServerReflectionRequest) -> ServerReflectionResponse {
+        use server_reflection_response::MessageResponse;
+
+        let response = match request.message_request.as_ref() {
+            Some(message_request) => match self.index().respond(message_request) {
+                Ok(Reply::Files(files)) => {
+                    MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+                        file_descriptor_proto: files,
+                    })
+                }
+                Ok(Reply::ExtensionNumbers(base_type_name, extension_number)) => {
+                    MessageResponse::AllExtensionNumbersResponse(ExtensionNumberResponse {
+                        base_type_name,
+                        extension_number,
+                    })
+                }
+                Ok(Reply::Services) => MessageResponse::ListServicesResponse(ListServiceResponse {
+                    service: self
+                        .index()
+                        .services
+                        .iter()
+                        .map(|name| ServiceResponse { name: name.clone() })
+                        .collect(),
+                }),
+                Err(status) => error_response(status),
+            },
+            None => error_response(Status::invalid_argument("empty request")),
+        };
+        ServerReflectionResponse {
+            valid_host: request.host.clone(),
+            original_request: Some(request),
+            message_response: Some(response),
+        }
+    }
+}
+
+fn error_response(status: Status) -> server_reflection_response::MessageResponse {
+    server_reflection_response::MessageResponse::ErrorResponse(ErrorResponse {
+        error_code: status.code() as i32,
+        error_message: status.message().to_string(),
+    })
+}
+
+impl ServiceInfo for ServerReflection {
+    const NAME: &'static str = "grpc.reflection.v1alpha.ServerReflection";
+    const METHODS: &'static [&'static str] = &["ServerReflectionInfo"];
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+}
+
+impl Service<ServerContext, Request<RecvStream<ServerReflectionRequest>>> for ServerReflection {
+    type Response = Response<BoxStream<'static, Result<ServerReflectionResponse, Status>>>;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(
+        &'s mut self,
+        cx: &'cx mut ServerContext,
+        req: Request<RecvStream<ServerReflectionRequest>>,
+    ) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            if cx.rpc_info.method.as_deref() != Some(PATH) {
+                return Err(Status::new(Code::Unimplemented, "Method not found."));
+            }
+            let this = self.clone();
+            let responses = req
+                .into_inner()
+                .map(move |request| request.map(|request| this.reply(request)));
+            Ok(Response::new(Box::pin(responses) as BoxStream<'static, _>))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::{FieldDescriptorProto, MethodDescriptorProto, ServiceDescriptorProto};
+    use server_reflection_request::MessageRequest;
+    use server_reflection_response::MessageResponse;
+
+    use super::*;
+
+    fn reflection() -> ServerReflection {
+        let common = FileDescriptorProto {
+            name: Some("volo/common.proto".into()),
+            package: Some("volo.common".into()),
+            message_type: vec![DescriptorProto {
+                name: Some("Base".into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let item = FileDescriptorProto {
+            name: Some("volo/item.proto".into()),
+            package: Some("volo.example".into()),
+            dependency: vec!["volo/common.proto".into()],
+            message_type: vec![DescriptorProto {
+                name: Some("Item".into()),
+                nested_type: vec![DescriptorProto {
+                    name: Some("Tag".into()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            service: vec![ServiceDescriptorProto {
+                name: Some("ItemService".into()),
+                method: vec![MethodDescriptorProto {
+                    name: Some("GetItem".into()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            extension: vec![FieldDescriptorProto {
+                name: Some("weight".into()),
+                number: Some(100),
+                extendee: Some(".volo.common.Base".into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let set = FileDescriptorSet {
+            file: vec![common, item],
+        };
+        ServerReflection::new(&set.encode_to_vec())
+    }
+
+    fn reply(reflection: &ServerReflection, request: MessageRequest) -> MessageResponse {
+        let request = ServerReflectionRequest {
+            host: String::new(),
+            message_request: Some(request),
+        };
+        reflection.reply(request).message_response.unwrap()
+    }
+
+    fn file_names(response: MessageResponse) -> Vec<String> {
+        match response {
+            MessageResponse::FileDescriptorResponse(files) => files
+                .file_descriptor_proto
+                .iter()
+                .map(|f| {
+                    FileDescriptorProto::decode(&f[..])
+                        .unwrap()
+                        .name()
+                        .to_string()
+                })
+                .collect(),
+            _ => panic!("not a file descriptor response"),
+        }
+    }
+
+    #[test]
+    fn test_reflection() {
+        let reflection = reflection();
+        match reply(&reflection, MessageRequest::ListServices(String::new())) {
+            MessageResponse::ListServicesResponse(list) => {
+                assert_eq!(list.service[0].name, "volo.example.ItemService")
+            }
+            _ => panic!("not a list services response"),
+        }
+
+        for symbol in ["volo.example.ItemService.GetItem", "volo.example.Item.Tag"] {
+            let response = reply(
+                &reflection,
+                MessageRequest::FileContainingSymbol(symbol.into()),
+            );
+            assert_eq!(
+                file_names(response),
+                ["volo/item.proto", "volo/common.proto"]
+            );
+        }
+        let extension = ExtensionRequest {
+            containing_type: "volo.common.Base".into(),
+            extension_number: 100,
+        };
+        let response = reply(
+            &reflection,
+            MessageRequest::FileContainingExtension(extension),
+        );
+        assert_eq!(file_names(response)[0], "volo/item.proto");
+        match reply(
+            &reflection,
+            MessageRequest::AllExtensionNumbersOfType("volo.common.Base".into()),
+        ) {
+            MessageResponse::AllExtensionNumbersResponse(numbers) => {
+                assert_eq!(numbers.extension_number, [100])
+            }
+            _ => panic!("not an extension numbers response"),
+        }
+
+        match reply(
+            &reflection,
+            MessageRequest::FileByFilename("none.proto".into()),
+        ) {
+            MessageResponse::ErrorResponse(error) => {
+                assert_eq!(error.error_code, Code::NotFound as i32)
+            }
+            _ => panic!("not an error response"),
+        }
+    }
+
+    fn services(reflection: &ServerReflection) -> Vec<String> {
+        match reply(reflection, MessageRequest::ListServices(String::new())) {
+            MessageResponse::ListServicesResponse(list) => {
+                list.service.into_iter().map(|s| s.name).collect()
+            }
+            _ => panic!("not a list services response"),
+        }
+    }
+
+    #[test]
+    fn test_register_after_clone() {
+        let served = reflection();
+        assert_eq!(services(&served), ["volo.example.ItemService"]);
+
+        let other = FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                name: Some("volo/other.proto".into()),
+                package: Some("volo.example".into()),
+                service: vec![ServiceDescriptorProto {
+                    name: Some("OtherService".into()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let registered = served.clone().register(&other.encode_to_vec());
+        assert_eq!(
+            services(&registered),
+            ["volo.example.ItemService", "volo.example.OtherService"]
+        );
+        assert_eq!(services(&served), ["volo.example.ItemService"]);
+    }
+}
@@ -1,9 +1,13 @@
 //! These codes are copied from `tonic/src/request.rs` and may be modified by us.
 
-use std::fmt::Debug;
+use std::{
+    fmt::Debug,
+    time::{Duration, Instant},
+};
 
 use futures::prelude::*;
-use http::Extensions;
+use http::{uri::Authority, Extensions};
+use volo::{context::Deadline, net::conn::ConnInfo};
 
 use crate::metadata::MetadataMap;
 
@@ -44,6 +48,25 @@ impl<T> Request<T> {
         &mut self.metadata
     }
 
+    /// Sets the metadata of the request, replacing the existing one.
+    pub fn with_metadata(mut self, metadata: MetadataMap) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sets the timeout of the request.
+    ///
+    /// The timeout will be sent to the server as the `grpc-timeout` header.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.metadata.set_grpc_timeout(timeout);
+    }
+
+    /// Sets the timeout of the request, see [`Request::set_timeout`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+
     /// Consumes `self`, returning the message
     pub fn into_inner(self) -> T {
         self.message
@@ -67,10 +90,14 @@ impl<T> Request<T> {
     }
 
     pub fn from_http_parts(parts: http::request::Parts, message: T) -> Self {
+        let mut extensions = parts.extensions;
+        if let Some(authority) = parts.uri.authority() {
+            extensions.insert(authority.clone());
+        }
         Request {
             metadata: MetadataMap::from_headers(parts.headers),
             message,
-            extensions: parts.extensions,
+            extensions,
         }
     }
 
@@ -84,6 +111,23 @@ impl<T> Request<T> {
         &mut self.extensions
     }
 
+    /// Returns the info of the connection the request is received from, such as the peer address
+    /// and the TLS parameters, only on the server side.
+    pub fn conn_info(&self) -> Option<&ConnInfo> {
+        self.extensions.get()
+    }
+
+    /// Returns the authority of the request, such as `example.com:8080`, only on the server side.
+    pub fn authority(&self) -> Option<&Authority> {
+        self.extensions.get()
+    }
+
+    /// Returns the deadline of the request by the `grpc-timeout` of the client, only on the server
+    /// side, such as for the handlers to propagate it to the downstream calls.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.extensions.get::<Deadline>().map(|d| d.0)
+    }
+
     #[doc(hidden)]
     pub fn map<F, U>(self, f: F) -> Request<U>
     where
@@ -116,6 +160,12 @@ impl<T> IntoRequest<T> for Request<T> {
     }
 }
 
+impl<T> IntoRequest<T> for (T, MetadataMap) {
+    fn into_request(self) -> Request<T> {
+        Request::new(self.0).with_metadata(self.1)
+    }
+}
+
 pub trait IntoStreamingRequest: sealed::Sealed {
     /// The RPC request stream type
     type Stream: Stream<Item = Self::Message> + Send + 'static;
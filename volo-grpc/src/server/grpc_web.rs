@@ -0,0 +1,596 @@
+//! Serving the gRPC-Web requests of the browsers without a proxy translating them, such as Envoy.
+//!
+//! [`GrpcWebLayer`] translates the requests of `application/grpc-web` and
+//! `application/grpc-web-text`, the latter encoded by base64, to the native gRPC ones, and their
+//! responses back, with the trailers sent as the last frame of the body. So the generated services
+//! serve them unmodified, and the native gRPC requests on the same server pass through.
+//!
+//! It also handles the CORS of the browsers: the preflight requests are answered by the layer, and
+//! the requests from the origins not allowed are rejected with 403. All the origins are allowed by
+//! default.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use std::time::Duration;
+//!
+//! use volo_grpc::server::{grpc_web::GrpcWebLayer, Server};
+//!
+//! Server::new(ItemServiceServer::new(S))
+//!     .grpc_web(
+//!         GrpcWebLayer::new()
+//!             .allow_origins(["https://example.com"])
+//!             .expose_headers(["x-request-id"])
+//!             .max_age(Duration::from_secs(600)),
+//!     )
+//!     .run(addr)
+//!     .await;
+//! ```
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::stream;
+use http::{
+    header::{self, HeaderName, HeaderValue},
+    HeaderMap, Method, StatusCode,
+};
+use hyper::body::HttpBody;
+
+use crate::{body::Body, BoxStream, Status};
+
+const GRPC_WEB: &str = "application/grpc-web";
+const GRPC_WEB_PROTO: &str = "application/grpc-web+proto";
+const GRPC_WEB_TEXT_PROTO: &str = "application/grpc-web-text+proto";
+
+/// The flag of the frame of the trailers, in place of the compression flag of the messages.
+const TRAILERS_FLAG: u8 = 0x80;
+
+const DEFAULT_ALLOW_HEADERS: &[&str] = &[
+    "content-type",
+    "x-grpc-web",
+    "x-user-agent",
+    "grpc-timeout",
+    "grpc-encoding",
+    "grpc-accept-encoding",
+];
+const DEFAULT_EXPOSE_HEADERS: &[&str] = &["grpc-status", "grpc-message", "grpc-status-details-bin"];
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The encoding of the body of a gRPC-Web call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Binary,
+    /// Encoded by base64, for the clients which can't read the binary bodies.
+    Text,
+}
+
+impl Encoding {
+    /// Returns the encoding of the gRPC-Web content type and the suffix of its codec, such as
+    /// `+proto`, or `None` if it isn't gRPC-Web.
+    fn of(content_type: &str) -> Option<(Self, &str)> {
+        let rest = content_type.strip_prefix(GRPC_WEB)?;
+        let (encoding, suffix) = match rest.strip_prefix("-text") {
+            Some(suffix) => (Self::Text, suffix),
+            None => (Self::Binary, rest),
+        };
+        (suffix.is_empty() || suffix.starts_with('+') || suffix.starts_with(';'))
+            .then_some((encoding, suffix))
+    }
+
+    fn content_type(self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            Self::Binary => GRPC_WEB_PROTO,
+            Self::Text => GRPC_WEB_TEXT_PROTO,
+        })
+    }
+
+    fn encode(self, data: Bytes) -> Bytes {
+        match self {
+            Self::Binary => data,
+            Self::Text => base64::encode(&data).into(),
+        }
+    }
+}
+
+/// Decodes the base64 text aligned to the groups of 4, which may be padded in the middle since the
+/// clients may encode each message separately.
+fn decode_text(text: &[u8]) -> Result<Bytes, Status> {
+    let mut decoded = Vec::with_capacity(text.len() / 4 * 3);
+    let mut start = 0;
+    for (i, group) in text.chunks(4).enumerate() {
+        let end = (i + 1) * 4;
+        if group[3] == b'=' || end == text.len() {
+            base64::decode_config_buf(&text[start..end], base64::STANDARD, &mut decoded)
+                .map_err(|e| Status::invalid_argument(format!("invalid base64 body: {}", e)))?;
+            start = end;
+        }
+    }
+    Ok(decoded.into())
+}
+
+fn decode_text_body(mut body: hyper::Body) -> hyper::Body {
+    hyper::Body::wrap_stream(async_stream::stream! {
+        let mut buf = BytesMut::new();
+        while let Some(data) = body.data().await {
+            match data {
+                Ok(data) => buf.extend_from_slice(&data),
+                Err(err) => {
+                    yield Err(Status::from_error(err.into()));
+                    return;
+                }
+            }
+            let aligned = buf.len() / 4 * 4;
+            if aligned > 0 {
+                let text = buf.split_to(aligned);
+                yield decode_text(&text);
+            }
+        }
+        if !buf.is_empty() {
+            yield Err(Status::invalid_argument("the base64 body ends within a group"));
+        }
+    })
+}
+
+/// Encodes the trailers as the last frame of the body, by the lines of the HTTP/1 headers.
+fn encode_trailers(trailers: &HeaderMap) -> Bytes {
+    let mut lines = BytesMut::new();
+    for (key, value) in trailers {
+        lines.put_slice(key.as_str().as_bytes());
+        lines.put_slice(b": ");
+        lines.put_slice(value.as_bytes());
+        lines.put_slice(b"\r\n");
+    }
+    let mut frame = BytesMut::with_capacity(5 + lines.len());
+    frame.put_u8(TRAILERS_FLAG);
+    frame.put_u32(lines.len() as u32);
+    frame.put_slice(&lines);
+    frame.freeze()
+}
+
+fn encode_body(mut body: Body, encoding: Encoding) -> BoxStream<'static, Result<Bytes, Status>> {
+    Box::pin(async_stream::stream! {
+        let mut error = None;
+        while let Some(data) = body.data().await {
+            match data {
+                Ok(data) => yield Ok(encoding.encode(data)),
+                Err(status) => {
+                    error = Some(status);
+                    break;
+                }
+            }
+        }
+        let trailers = match error {
+            Some(status) => Err(status),
+            None => body.trailers().await.map(Option::unwrap_or_default),
+        };
+        let trailers = trailers.unwrap_or_else(|status| {
+            status.to_header_map().unwrap_or_else(|_| {
+                Status::internal("invalid status").to_header_map().unwrap_or_default()
+            })
+        });
+        yield Ok(encoding.encode(encode_trailers(&trailers)));
+    })
+}
+
+/// The body of the responses of [`GrpcWebService`], with the trailers in the body of the gRPC-Web
+/// ones.
+pub struct GrpcWebBody(Kind);
+
+enum Kind {
+    Grpc(Body),
+    Web(BoxStream<'static, Result<Bytes, Status>>),
+}
+
+impl GrpcWebBody {
+    fn empty() -> Self {
+        Self(Kind::Web(Box::pin(stream::empty())))
+    }
+}
+
+impl HttpBody for GrpcWebBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match &mut self.get_mut().0 {
+            Kind::Grpc(body) => Pin::new(body).poll_data(cx),
+            Kind::Web(stream) => stream.as_mut().poll_next(cx),
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        match &mut self.get_mut().0 {
+            Kind::Grpc(body) => Pin::new(body).poll_trailers(cx),
+            Kind::Web(_) => Poll::Ready(Ok(None)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Cors {
+    /// All the origins are allowed if `None`.
+    allow_origins: Option<Vec<HeaderValue>>,
+    allow_headers: Vec<HeaderName>,
+    expose_headers: Vec<HeaderName>,
+    max_age: Duration,
+    allow_credentials: bool,
+}
+
+impl Cors {
+    fn allows(&self, origin: &HeaderValue) -> bool {
+        self.allow_origins
+            .as_ref()
+            .map_or(true, |origins| origins.contains(origin))
+    }
+
+    /// Returns the headers of the responses to the origin, both the preflight and the actual ones.
+    fn headers(&self, origin: &HeaderValue) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        headers.insert(header::VARY, HeaderValue::from_static("origin"));
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        headers
+    }
+
+    fn preflight(&self, origin: &HeaderValue) -> hyper::Response<GrpcWebBody> {
+        let mut resp = hyper::Response::new(GrpcWebBody::empty());
+        *resp.status_mut() = StatusCode::NO_CONTENT;
+        let headers = resp.headers_mut();
+        headers.extend(self.headers(origin));
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static("POST"),
+        );
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            join(DEFAULT_ALLOW_HEADERS, &self.allow_headers),
+        );
+        headers.insert(
+            header::ACCESS_CONTROL_MAX_AGE,
+            HeaderValue::from(self.max_age.as_secs()),
+        );
+        resp
+    }
+
+    fn expose_headers(&self) -> HeaderValue {
+        join(DEFAULT_EXPOSE_HEADERS, &self.expose_headers)
+    }
+}
+
+fn join(defaults: &[&str], names: &[HeaderName]) -> HeaderValue {
+    let names: Vec<&str> = defaults
+        .iter()
+        .copied()
+        .chain(names.iter().map(HeaderName::as_str))
+        .collect();
+    HeaderValue::from_str(&names.join(", ")).expect("the header names are valid values")
+}
+
+fn forbidden() -> hyper::Response<GrpcWebBody> {
+    let mut resp = hyper::Response::new(GrpcWebBody::empty());
+    *resp.status_mut() = StatusCode::FORBIDDEN;
+    resp
+}
+
+fn header_names<I, T>(names: I) -> impl Iterator<Item = HeaderName>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+{
+    names
+        .into_iter()
+        .map(|name| HeaderName::from_bytes(name.as_ref().as_bytes()).expect("invalid header name"))
+}
+
+/// A layer that applies [`GrpcWebService`], added by
+/// [`Server::grpc_web`](super::Server::grpc_web).
+#[derive(Debug, Clone)]
+pub struct GrpcWebLayer {
+    cors: Arc<Cors>,
+}
+
+impl GrpcWebLayer {
+    pub fn new() -> Self {
+        Self {
+            cors: Arc::new(Cors {
+                allow_origins: None,
+                allow_headers: Vec::new(),
+                expose_headers: Vec::new(),
+                max_age: DEFAULT_MAX_AGE,
+                allow_credentials: false,
+            }),
+        }
+    }
+
+    /// Sets the origins allowed, such as `https://example.com`, and the requests from the others
+    /// are rejected with 403.
+    ///
+    /// Defaults to all the origins.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an origin isn't a valid header value.
+    pub fn allow_origins<I, T>(mut self, origins: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let origins = origins
+            .into_iter()
+            .map(|origin| HeaderValue::from_str(origin.as_ref()).expect("invalid origin"))
+            .collect();
+        Arc::make_mut(&mut self.cors).allow_origins = Some(origins);
+        self
+    }
+
+    /// Adds the headers the browsers may send besides the ones of gRPC-Web, such as the custom
+    /// metadata of the requests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a header name is invalid.
+    pub fn allow_headers<I, T>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        Arc::make_mut(&mut self.cors)
+            .allow_headers
+            .extend(header_names(headers));
+        self
+    }
+
+    /// Adds the headers of the responses the scripts may read besides the status of gRPC, such as
+    /// the custom metadata of the responses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a header name is invalid.
+    pub fn expose_headers<I, T>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        Arc::make_mut(&mut self.cors)
+            .expose_headers
+            .extend(header_names(headers));
+        self
+    }
+
+    /// Sets how long the browsers cache the result of a preflight request.
+    ///
+    /// Defaults to 24 hours.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        Arc::make_mut(&mut self.cors).max_age = max_age;
+        self
+    }
+
+    /// Sets whether the browsers send the cookies and the other credentials with the requests.
+    ///
+    /// Defaults to `false`.
+    pub fn allow_credentials(mut self, allow: bool) -> Self {
+        Arc::make_mut(&mut self.cors).allow_credentials = allow;
+        self
+    }
+}
+
+impl Default for GrpcWebLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> tower::Layer<S> for GrpcWebLayer {
+    type Service = GrpcWebService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcWebService {
+            inner,
+            cors: Some(self.cors.clone()),
+        }
+    }
+}
+
+/// A service that translates the gRPC-Web requests to the native ones served by the inner
+/// `tower::Service`, such as `HyperAdaptorService`, and their responses back.
+#[derive(Clone)]
+pub struct GrpcWebService<S> {
+    inner: S,
+    /// Passes all the requests through if `None`.
+    cors: Option<Arc<Cors>>,
+}
+
+impl<S> GrpcWebService<S> {
+    /// Wraps the service by the layer if any, so that the servers with and without gRPC-Web serve
+    /// their connections by the same type.
+    pub(crate) fn optional(layer: Option<&GrpcWebLayer>, inner: S) -> Self {
+        Self {
+            inner,
+            cors: layer.map(|layer| layer.cors.clone()),
+        }
+    }
+}
+
+enum Action<F> {
+    Respond(hyper::Response<GrpcWebBody>),
+    Grpc(F),
+    Web {
+        future: F,
+        encoding: Encoding,
+        cors: Option<HeaderMap>,
+    },
+}
+
+impl<S> tower::Service<hyper::Request<hyper::Body>> for GrpcWebService<S>
+where
+    S: tower::Service<
+        hyper::Request<hyper::Body>,
+        Response = hyper::Response<Body>,
+        Error = Status,
+    >,
+{
+    type Response = hyper::Response<GrpcWebBody>;
+    type Error = Status;
+    type Future = impl Future<Output = Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<hyper::Body>) -> Self::Future {
+        let action = match self.cors.clone() {
+            None => Action::Grpc(self.inner.call(req)),
+            Some(cors) => self.translate(&cors, req),
+        };
+
+        async move {
+            let (future, encoding, cors) = match action {
+                Action::Respond(resp) => return Ok(resp),
+                Action::Grpc(future) => {
+                    return Ok(future.await?.map(|b| GrpcWebBody(Kind::Grpc(b))))
+                }
+                Action::Web {
+                    future,
+                    encoding,
+                    cors,
+                } => (future, encoding, cors),
+            };
+            let resp = future.await.unwrap_or_else(Status::to_http);
+            let (mut parts, body) = resp.into_parts();
+            parts
+                .headers
+                .insert(header::CONTENT_TYPE, encoding.content_type());
+            if let Some(cors) = cors {
+                parts.headers.extend(cors);
+            }
+            // the trailers-only responses, such as the rejections, have the status in the headers
+            let body = if parts.headers.contains_key("grpc-status") {
+                GrpcWebBody::empty()
+            } else {
+                GrpcWebBody(Kind::Web(encode_body(body, encoding)))
+            };
+            Ok(hyper::Response::from_parts(parts, body))
+        }
+    }
+}
+
+impl<S> GrpcWebService<S>
+where
+    S: tower::Service<
+        hyper::Request<hyper::Body>,
+        Response = hyper::Response<Body>,
+        Error = Status,
+    >,
+{
+    fn translate(
+        &mut self,
+        cors: &Cors,
+        mut req: hyper::Request<hyper::Body>,
+    ) -> Action<S::Future> {
+        let origin = req.headers().get(header::ORIGIN).cloned();
+        if let Some(origin) = &origin {
+            if req.method() == Method::OPTIONS
+                && req
+                    .headers()
+                    .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+            {
+                return Action::Respond(if cors.allows(origin) {
+                    cors.preflight(origin)
+                } else {
+                    forbidden()
+                });
+            }
+        }
+
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(Encoding::of)
+            .map(|(encoding, suffix)| (encoding, format!("application/grpc{}", suffix)));
+        let (encoding, content_type) = match content_type {
+            Some(content_type) => content_type,
+            None => return Action::Grpc(self.inner.call(req)),
+        };
+        let cors = match origin {
+            Some(origin) if !cors.allows(&origin) => return Action::Respond(forbidden()),
+            Some(origin) => {
+                let mut headers = cors.headers(&origin);
+                headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, cors.expose_headers());
+                Some(headers)
+            }
+            None => None,
+        };
+
+        let headers = req.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(&content_type).expect("the suffix is from a valid value"),
+        );
+        if encoding == Encoding::Text {
+            // the length is of the text
+            headers.remove(header::CONTENT_LENGTH);
+            let (parts, body) = req.into_parts();
+            req = hyper::Request::from_parts(parts, decode_text_body(body));
+        }
+        Action::Web {
+            future: self.inner.call(req),
+            encoding,
+            cors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(
+            Encoding::of("application/grpc-web-text+proto"),
+            Some((Encoding::Text, "+proto"))
+        );
+        assert_eq!(
+            Encoding::of("application/grpc-web"),
+            Some((Encoding::Binary, ""))
+        );
+        assert_eq!(Encoding::of("application/grpc"), None);
+        assert_eq!(Encoding::of("application/grpc-websocket"), None);
+
+        // the messages encoded separately, the first one padded
+        let text = [
+            base64::encode([0, 0, 0, 0, 2, 7, 7]),
+            base64::encode([1, 2, 3]),
+        ]
+        .concat();
+        assert_eq!(
+            decode_text(text.as_bytes()).unwrap(),
+            Bytes::from_static(&[0, 0, 0, 0, 2, 7, 7, 1, 2, 3])
+        );
+        decode_text(b"AA=A").unwrap_err();
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        let frame = encode_trailers(&trailers);
+        assert_eq!(&frame[..], b"\x80\x00\x00\x00\x10grpc-status: 0\r\n");
+    }
+}
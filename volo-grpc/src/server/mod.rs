@@ -1,10 +1,22 @@
 //! gRPC server for Volo.
 //!
 //! This module contains the low level component to build a gRPC server.
+//!
+//! The layers can add the extra HTTP headers of the response by [`ResponseHeaders`], and change
+//! the HTTP status of a rejection by [`HttpStatus`], for the load balancers and the browsers in
+//! front of the server.
+
+pub mod grpc_web;
+mod response;
+mod router;
 
-use std::{marker::PhantomData, time::Duration};
+use std::{marker::PhantomData, sync::Arc, time::Duration};
 
-use futures::{Future, TryStreamExt};
+use futures::{
+    future::{self, Either},
+    Future, FutureExt, TryStreamExt,
+};
+use grpc_web::{GrpcWebLayer, GrpcWebService};
 use hyper::server::conn::Http;
 use motore::{
     builder::ServiceBuilder,
@@ -12,22 +24,58 @@ use motore::{
     service::Service,
     BoxError,
 };
+use rand::Rng;
+use response::{add_headers, reject, HttpStatusMapper};
+pub use response::{HttpStatus, ResponseHeaders};
+pub use router::{MetadataRouter, RoutedRequest, Router};
+use tokio::sync::watch;
 use tower::Layer as TowerLayer;
-use volo::{context::Endpoint, net::Address, spawn};
+use volo::{
+    context::{Context, Deadline, Endpoint},
+    net::{conn::ConnInfo, Address},
+    rt::Runtime,
+    service_info::ServiceInfo,
+};
 
 use crate::{
     body::Body,
-    codec::decode::Kind,
+    codec::{
+        compression::{
+            AcceptCompression, CompressionEncoding, SendCompression, ACCEPT_ENCODING_HEADER,
+            ENCODING_HEADER,
+        },
+        decode::{DecodeConfig, Kind},
+        encode, MessageSize,
+    },
     context::ServerContext,
+    layer::{
+        fault::TruncateResponse,
+        grpc_timeout::try_parse_client_timeout,
+        interceptor::{AsyncInterceptor, AsyncInterceptorLayer, InterceptorLayer},
+    },
+    memory::{ConnMemory, MemoryBudget},
     message::{RecvEntryMessage, SendEntryMessage},
+    metadata::MetadataMap,
     Request, Response, Status,
 };
 
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// A server for a gRPC service.
 pub struct Server<S, L> {
     service: S,
     layer: L,
     http2_config: Http2Config,
+    runtime: Runtime,
+    http_status: Option<HttpStatusMapper>,
+    compression: SendCompression,
+    accept_compression: AcceptCompression,
+    memory_budget: Option<MemoryBudget>,
+    message_size: MessageSize,
+    shutdown_timeout: Duration,
+    grpc_web: Option<GrpcWebLayer>,
+    #[cfg(feature = "rustls")]
+    tls: Option<crate::tls::TlsAcceptor>,
 }
 
 impl<S> Server<S, Identity> {
@@ -37,6 +85,92 @@ impl<S> Server<S, Identity> {
             service,
             layer: Identity::new(),
             http2_config: Http2Config::default(),
+            runtime: Runtime::default(),
+            http_status: None,
+            compression: Default::default(),
+            accept_compression: Default::default(),
+            memory_budget: None,
+            message_size: Default::default(),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            grpc_web: None,
+            #[cfg(feature = "rustls")]
+            tls: None,
+        }
+    }
+}
+
+impl<L> Server<Router, L> {
+    /// Adds a generated service to the [`Router`] served, see [`Router::add_service`].
+    pub fn add_service<S, T, U>(mut self, service: S) -> Self
+    where
+        S: ServiceInfo
+            + Service<ServerContext, Request<T>, Response = Response<U>, Error = Status>
+            + Clone
+            + Send
+            + 'static,
+        for<'cx> S::Future<'cx>: Send,
+        T: RecvEntryMessage + Send + 'static,
+        U: SendEntryMessage + 'static,
+    {
+        self.service = self.service.add_service(service);
+        self
+    }
+
+    /// Adds a service to the [`Router`] served, see [`Router::add_named`].
+    pub fn add_named<S, T, U>(mut self, name: &str, service: S) -> Self
+    where
+        S: Service<ServerContext, Request<T>, Response = Response<U>, Error = Status>
+            + Clone
+            + Send
+            + 'static,
+        for<'cx> S::Future<'cx>: Send,
+        T: RecvEntryMessage + Send + 'static,
+        U: SendEntryMessage + 'static,
+    {
+        self.service = self.service.add_named(name, service);
+        self
+    }
+}
+
+#[cfg(feature = "reflection")]
+impl<S, L> Server<S, L> {
+    /// Serves the reflection of the encoded `FileDescriptorSet` along with the service, such as
+    /// the `FILE_DESCRIPTOR_SET` written by `volo-build`, see [`crate::reflection`].
+    ///
+    /// When serving a [`Router`], add a [`ServerReflection`](crate::reflection::ServerReflection)
+    /// to it instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `FileDescriptorSet` is invalid.
+    pub fn enable_reflection<T, U>(self, file_descriptor_set: &[u8]) -> Server<Router, L>
+    where
+        S: ServiceInfo
+            + Service<ServerContext, Request<T>, Response = Response<U>, Error = Status>
+            + Clone
+            + Send
+            + 'static,
+        for<'cx> S::Future<'cx>: Send,
+        T: RecvEntryMessage + Send + 'static,
+        U: SendEntryMessage + 'static,
+    {
+        let reflection = crate::reflection::ServerReflection::new(file_descriptor_set);
+        Server {
+            service: Router::new()
+                .add_service(self.service)
+                .add_service::<crate::reflection::ServerReflection, _, _>(reflection),
+            layer: self.layer,
+            http2_config: self.http2_config,
+            runtime: self.runtime,
+            http_status: self.http_status,
+            compression: self.compression,
+            accept_compression: self.accept_compression,
+            memory_budget: self.memory_budget,
+            message_size: self.message_size,
+            shutdown_timeout: self.shutdown_timeout,
+            grpc_web: self.grpc_web,
+            #[cfg(feature = "rustls")]
+            tls: self.tls,
         }
     }
 }
@@ -126,6 +260,134 @@ impl<S, L> Server<S, L> {
         self
     }
 
+    /// Sets the maximum age of the connections, after which the server sends GOAWAY to close them
+    /// gracefully, so that the clients holding their connections forever reconnect and the load
+    /// rebalances, such as to the servers scaled out.
+    ///
+    /// A jitter of +/-10% is added to the age of each connection, so that the connections made
+    /// together don't reconnect together.
+    ///
+    /// Default is no limit (`None`).
+    pub fn max_connection_age(mut self, age: impl Into<Option<Duration>>) -> Self {
+        self.http2_config.max_connection_age = age.into();
+        self
+    }
+
+    /// Sets the time the calls in flight have to complete after the GOAWAY by
+    /// [`Server::max_connection_age`], after which the connection is closed forcibly.
+    ///
+    /// Default is waiting for them to complete (`None`).
+    pub fn max_connection_age_grace(mut self, grace: impl Into<Option<Duration>>) -> Self {
+        self.http2_config.max_connection_age_grace = grace.into();
+        self
+    }
+
+    /// Sets the time the calls in flight have to complete when the server shuts down by
+    /// [`Server::run_with_graceful_shutdown`], after which the connections are closed forcibly.
+    ///
+    /// Default is `30s`.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Serves the gRPC-Web requests of the browsers along with the native ones, see
+    /// [`grpc_web`].
+    ///
+    /// The server accepts the HTTP/1.1 connections as well, which the browsers may make.
+    pub fn grpc_web(mut self, layer: GrpcWebLayer) -> Self {
+        self.grpc_web = Some(layer);
+        self.http2_config.accept_http1 = true;
+        self
+    }
+
+    /// Sets the runtime to spawn the tasks of the connections.
+    ///
+    /// Defaults to the tokio runtime.
+    pub fn runtime(mut self, runtime: Runtime) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Sets the HTTP status of the rejections, such as 404 for the paths served by no service,
+    /// for the load balancers and the browsers not speaking gRPC. The gRPC clients still decode
+    /// the error from the `grpc-status` header.
+    ///
+    /// The rejections mapped to `None` are sent with 200 as gRPC requires, and [`HttpStatus`]
+    /// overrides the mapping for one rejection.
+    pub fn http_status(
+        mut self,
+        f: impl Fn(&Status) -> Option<http::StatusCode> + Send + Sync + 'static,
+    ) -> Self {
+        self.http_status = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets the encoding to compress the messages of the responses by, for the clients accepting
+    /// it by `grpc-accept-encoding`, see [`compression`](crate::codec::compression).
+    ///
+    /// Default is None.
+    pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+        self.compression.encoding = Some(encoding);
+        self
+    }
+
+    /// Sets whether to compress each message of the responses by its encoded bytes, when
+    /// [`send_compressed`](Self::send_compressed), such as to skip the small ones.
+    ///
+    /// Default is to compress all of them.
+    pub fn should_compress(mut self, f: impl Fn(&[u8]) -> bool + Send + Sync + 'static) -> Self {
+        self.compression.should_compress = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets the encodings of the compressed messages of the requests accepted, the requests
+    /// compressed by the others are rejected with `Unimplemented`.
+    ///
+    /// Default is all the encodings supported, see [`CompressionEncoding::SUPPORTED`].
+    pub fn accept_compressed(
+        mut self,
+        encodings: impl IntoIterator<Item = CompressionEncoding>,
+    ) -> Self {
+        self.accept_compression = AcceptCompression::new(encodings);
+        self
+    }
+
+    /// Sets the budget of the bytes buffered by the requests, see [`memory`](crate::memory).
+    ///
+    /// Default is None.
+    pub fn memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.memory_budget = Some(budget);
+        self
+    }
+
+    /// Sets the max size of each message of the requests, the calls receiving a larger one are
+    /// failed with `RESOURCE_EXHAUSTED`.
+    ///
+    /// Default is `4MB`.
+    pub fn max_recv_message_size(mut self, size: usize) -> Self {
+        self.message_size.max_recv = size;
+        self
+    }
+
+    /// Sets the max size of each message of the responses, the calls sending a larger one are
+    /// failed with `RESOURCE_EXHAUSTED` without sending the message.
+    ///
+    /// Default is no limit (`usize::MAX`).
+    pub fn max_send_message_size(mut self, size: usize) -> Self {
+        self.message_size.max_send = size;
+        self
+    }
+
+    /// Terminates TLS on the connections accepted, see [`tls`](crate::tls).
+    ///
+    /// Default is None.
+    #[cfg(feature = "rustls")]
+    pub fn tls_config(mut self, tls: crate::tls::TlsAcceptor) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
     /// Adds a new inner layer to the server.
     ///
     /// # Order
@@ -140,9 +402,40 @@ impl<S, L> Server<S, L> {
             layer: Stack::new(layer, self.layer),
             service: self.service,
             http2_config: self.http2_config,
+            runtime: self.runtime,
+            http_status: self.http_status,
+            compression: self.compression,
+            accept_compression: self.accept_compression,
+            memory_budget: self.memory_budget,
+            message_size: self.message_size,
+            shutdown_timeout: self.shutdown_timeout,
+            grpc_web: self.grpc_web,
+            #[cfg(feature = "rustls")]
+            tls: self.tls,
         }
     }
 
+    /// Intercepts the metadata and the extensions of the requests by `f` as a new inner layer,
+    /// see [`interceptor`](crate::layer::interceptor).
+    pub fn intercept<F>(self, f: F) -> Server<S, Stack<InterceptorLayer<F>, L>>
+    where
+        F: Fn(&mut MetadataMap, &mut http::Extensions) -> Result<(), Status>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.layer(InterceptorLayer::new(f))
+    }
+
+    /// Intercepts the requests by the asynchronous interceptor as a new inner layer, see
+    /// [`interceptor`](crate::layer::interceptor).
+    pub fn intercept_async<I>(self, interceptor: I) -> Server<S, Stack<AsyncInterceptorLayer<I>, L>>
+    where
+        I: AsyncInterceptor,
+    {
+        self.layer(AsyncInterceptorLayer::new(interceptor))
+    }
+
     /// The main entry point for the server.
     pub async fn run<A: volo::net::MakeIncoming, T, U>(self, incoming: A) -> Result<(), BoxError>
     where
@@ -157,23 +450,147 @@ impl<S, L> Server<S, L> {
             + 'static,
         T: Send + 'static + RecvEntryMessage,
         U: Send + 'static + SendEntryMessage,
+    {
+        self.run_with_graceful_shutdown(incoming, future::pending())
+            .await
+    }
+
+    /// Runs the server until `signal` completes, and then shuts down gracefully.
+    ///
+    /// When shutting down, the server stops accepting new connections and sends GOAWAY on the
+    /// existing ones, and waits for the calls in flight, including the streams of the responses,
+    /// to complete until the [`shutdown_timeout`](Self::shutdown_timeout), after which the
+    /// connections left are closed forcibly.
+    pub async fn run_with_graceful_shutdown<A, T, U, F>(
+        self,
+        incoming: A,
+        signal: F,
+    ) -> Result<(), BoxError>
+    where
+        A: volo::net::MakeIncoming,
+        L: Layer<S>,
+        L::Service: Service<ServerContext, Request<T>, Response = Response<U>, Error = Status>
+            + Clone
+            + Send
+            + 'static,
+        S: Service<ServerContext, Request<T>, Response = Response<U>, Error = Status>
+            + Send
+            + Clone
+            + 'static,
+        T: Send + 'static + RecvEntryMessage,
+        U: Send + 'static + SendEntryMessage,
+        F: Future<Output = ()>,
     {
         let mut incoming = incoming.make_incoming().await?;
         let service = ServiceBuilder::new()
             .layer(self.layer)
             .service(self.service);
-        while let Some(conn) = incoming.try_next().await? {
-            let peer_addr = conn.info.peer_addr.clone();
-            let service = HyperAdaptorLayer::new(peer_addr).layer(service.clone());
+        // each connection holds a receiver until it's closed
+        let (shutdown_tx, shutdown_rx) = watch::channel(Shutdown::Running);
+        let memory_budget = self.memory_budget.as_ref().map(MemoryBudget::build);
+        let mut signal = Box::pin(signal);
+        loop {
+            let conn = match future::select(incoming.try_next(), signal.as_mut()).await {
+                Either::Left((conn, _)) => match conn? {
+                    Some(conn) => conn,
+                    None => return Ok(()),
+                },
+                Either::Right(_) => break,
+            };
+            let adaptor = HyperAdaptorLayer::with_conn_info(conn.info.clone())
+                .http_status(self.http_status.clone())
+                .send_compression(self.compression.clone())
+                .accept_compression(self.accept_compression.clone())
+                .message_size(self.message_size)
+                .memory(
+                    memory_budget
+                        .as_ref()
+                        .map(|b| b.connection(conn.info.peer_addr.clone())),
+                );
+            let service = service.clone();
+            let grpc_web = self.grpc_web.clone();
+            #[cfg(feature = "rustls")]
+            let tls = self.tls.clone();
             // init server
             let server = Self::create_http_server(&self.http2_config);
-            spawn(async move {
-                let result = server.serve_connection(conn, service).await;
+            let max_age = self.http2_config.max_connection_age.map(jittered);
+            let grace = self.http2_config.max_connection_age_grace;
+            let shutdown = shutdown_rx.clone();
+            self.runtime.spawn(async move {
+                // handshake in the connection task, so that a slow peer doesn't block the accept
+                // loop
+                #[cfg(feature = "rustls")]
+                let (conn, adaptor) = match tls {
+                    Some(tls) => match tls.accept(conn).await {
+                        // with the tls info of the connection
+                        Ok(conn) => {
+                            let adaptor = adaptor.conn_info(conn.info.clone());
+                            (conn, adaptor)
+                        }
+                        Err(e) => {
+                            tracing::warn!("[VOLO] tls handshake error: {:?}", e);
+                            return;
+                        }
+                    },
+                    None => (conn, adaptor),
+                };
+                let service = GrpcWebService::optional(grpc_web.as_ref(), adaptor.layer(service));
+                let mut conn = Box::pin(server.serve_connection(conn, service));
+                // sends GOAWAY when the connection reaches the max age or the server shuts down
+                let goaway = future::select(
+                    Box::pin(sleep_or_pending(max_age)),
+                    Box::pin(reached(shutdown.clone(), Shutdown::Draining)),
+                )
+                .map(|reason| matches!(reason, Either::Left(_)));
+                let result = match future::select(conn.as_mut(), goaway).await {
+                    Either::Left((result, _)) => result,
+                    Either::Right((max_aged, _)) => {
+                        let grace = if max_aged {
+                            tracing::debug!(
+                                "[VOLO] connection reaches the max age, sending goaway"
+                            );
+                            grace
+                        } else {
+                            tracing::debug!("[VOLO] server is shutting down, sending goaway");
+                            None
+                        };
+                        // waits for the calls in flight, and closes it after the grace of the max
+                        // age if any, or when the shutdown timeout of the server expires
+                        conn.as_mut().graceful_shutdown();
+                        let abort = future::select(
+                            Box::pin(sleep_or_pending(grace)),
+                            Box::pin(reached(shutdown, Shutdown::Aborting)),
+                        );
+                        match future::select(conn, abort).await {
+                            Either::Left((result, _)) => result,
+                            Either::Right(_) => {
+                                tracing::debug!(
+                                    "[VOLO] connection closed with the calls in flight"
+                                );
+                                Ok(())
+                            }
+                        }
+                    }
+                };
                 if let Err(err) = result {
                     tracing::warn!("[VOLO] http server fail to serve: {:?}", err);
                 }
             });
         }
+
+        tracing::info!("[VOLO] received signal, gracefully exiting now");
+        // stop accepting, the listener is closed when dropped
+        drop(incoming);
+        drop(shutdown_rx);
+        let _ = shutdown_tx.send(Shutdown::Draining);
+        let drained = tokio::time::timeout(self.shutdown_timeout, shutdown_tx.closed()).await;
+        if drained.is_err() {
+            tracing::warn!(
+                "[VOLO] shutdown timeout, {} connections are closed forcibly",
+                shutdown_tx.receiver_count()
+            );
+            let _ = shutdown_tx.send(Shutdown::Aborting);
+        }
         Ok(())
     }
 
@@ -192,27 +609,108 @@ impl<S, L> Server<S, L> {
     }
 }
 
+/// Returns the duration with a jitter of +/-10%.
+fn jittered(duration: Duration) -> Duration {
+    duration.mul_f64(rand::thread_rng().gen_range(0.9..=1.1))
+}
+
+async fn sleep_or_pending(duration: Option<Duration>) {
+    match duration {
+        Some(duration) => tokio::time::sleep(duration).await,
+        None => future::pending().await,
+    }
+}
+
+/// The stages of the shutdown of the server, told to its connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Shutdown {
+    Running,
+    /// The connections send GOAWAY and wait for the calls in flight.
+    Draining,
+    /// The connections left are closed forcibly.
+    Aborting,
+}
+
+/// Waits for the server to reach the stage of the shutdown, forever if the server is dropped.
+async fn reached(mut shutdown: watch::Receiver<Shutdown>, stage: Shutdown) {
+    while *shutdown.borrow() < stage {
+        if shutdown.changed().await.is_err() {
+            return future::pending().await;
+        }
+    }
+}
+
 macro_rules! trans {
-    ($result:expr) => {
+    ($result:expr, $cx:expr, $http_status:expr) => {
         match $result {
             Ok(value) => value,
-            Err(status) => return Ok(status.to_http()),
+            Err(status) => return Ok(reject(status, &mut $cx, $http_status.as_ref())),
         }
     };
 }
 /// A layer that adapts a `motore::Service` to `tower::Service`.
 pub struct HyperAdaptorLayer<T, U> {
-    peer_addr: Option<Address>,
+    conn_info: ConnInfo,
+    http_status: Option<HttpStatusMapper>,
+    compression: SendCompression,
+    accept_compression: AcceptCompression,
+    message_size: MessageSize,
+    memory: Option<ConnMemory>,
     _marker: PhantomData<(T, U)>,
 }
 
 impl<T, U> HyperAdaptorLayer<T, U> {
     pub fn new(peer_addr: Option<Address>) -> Self {
-        Self {
+        Self::with_conn_info(ConnInfo {
             peer_addr,
+            ..Default::default()
+        })
+    }
+
+    /// Creates a new [`HyperAdaptorLayer`] of the connection, whose info is put in the
+    /// extensions of the [`ServerContext`].
+    pub fn with_conn_info(conn_info: ConnInfo) -> Self {
+        Self {
+            conn_info,
+            http_status: None,
+            compression: Default::default(),
+            accept_compression: Default::default(),
+            message_size: Default::default(),
+            memory: None,
             _marker: PhantomData,
         }
     }
+
+    #[cfg(feature = "rustls")]
+    fn conn_info(mut self, conn_info: ConnInfo) -> Self {
+        self.conn_info = conn_info;
+        self
+    }
+
+    fn http_status(mut self, http_status: Option<HttpStatusMapper>) -> Self {
+        self.http_status = http_status;
+        self
+    }
+
+    fn send_compression(mut self, compression: SendCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    fn accept_compression(mut self, accept_compression: AcceptCompression) -> Self {
+        self.accept_compression = accept_compression;
+        self
+    }
+
+    fn message_size(mut self, message_size: MessageSize) -> Self {
+        self.message_size = message_size;
+        self
+    }
+
+    fn memory(mut self, memory: Option<ConnMemory>) -> Self {
+        self.memory = memory;
+        self
+    }
 }
 
 impl<T, S, U> tower::Layer<S> for HyperAdaptorLayer<T, U> {
@@ -221,7 +719,12 @@ impl<T, S, U> tower::Layer<S> for HyperAdaptorLayer<T, U> {
     fn layer(&self, inner: S) -> Self::Service {
         HyperAdaptorService {
             inner,
-            peer_addr: self.peer_addr.clone(),
+            conn_info: self.conn_info.clone(),
+            http_status: self.http_status.clone(),
+            compression: self.compression.clone(),
+            accept_compression: self.accept_compression.clone(),
+            message_size: self.message_size,
+            memory: self.memory.clone(),
             _marker: self._marker,
         }
     }
@@ -234,7 +737,12 @@ impl<T, S, U> tower::Layer<S> for HyperAdaptorLayer<T, U> {
 #[derive(Clone)]
 pub struct HyperAdaptorService<T, S, U> {
     inner: S,
-    peer_addr: Option<Address>,
+    conn_info: ConnInfo,
+    http_status: Option<HttpStatusMapper>,
+    compression: SendCompression,
+    accept_compression: AcceptCompression,
+    message_size: MessageSize,
+    memory: Option<ConnMemory>,
     _marker: PhantomData<(T, U)>,
 }
 
@@ -260,31 +768,113 @@ where
 
     fn call(&mut self, req: hyper::Request<hyper::Body>) -> Self::Future {
         let mut inner = self.inner.clone();
-        let peer_addr = self.peer_addr.clone();
+        let conn_info = self.conn_info.clone();
+        let http_status = self.http_status.clone();
+        let compression = self.compression.clone();
+        let accept_compression = self.accept_compression.clone();
+        let message_size = self.message_size;
+        let memory = self.memory.as_ref().map(|m| m.request(req.uri().path()));
 
         async move {
             let mut cx = ServerContext::default();
-            let mut endpoint = Endpoint::new("".into());
-            endpoint.address = peer_addr.clone();
-            cx.rpc_info.caller = Some(endpoint);
+            let mut caller = Endpoint::new("".into());
+            caller.address = conn_info.peer_addr.clone();
+            cx.rpc_info.caller = Some(caller);
+            let mut callee = Endpoint::new("".into());
+            callee.address = conn_info.local_addr.clone();
+            cx.rpc_info.callee = Some(callee);
+            // the tls parameters and so on, for the layers
+            cx.extensions_mut().insert(conn_info.clone());
+            // the verified identity of the client by mTLS, such as for the authorization
+            if let Some(identity) = conn_info.tls.as_ref().and_then(|t| t.peer_identity.clone()) {
+                cx.extensions_mut().insert(identity);
+            }
             cx.rpc_info.method = Some(req.uri().path().into());
 
+            // the deadline set by the client, the handler will be cancelled when exceeded
+            let timeout = try_parse_client_timeout(req.headers()).unwrap_or_else(|_| {
+                tracing::trace!("[VOLO] error parsing grpc-timeout header");
+                None
+            });
+            // for the layers and the handler, such as to propagate it to the downstream calls
+            let deadline = timeout.map(|timeout| Deadline(std::time::Instant::now() + timeout));
+            if let Some(deadline) = deadline {
+                cx.extensions_mut().insert(deadline);
+            }
+
+            // the encodings accepted, with both the response and the rejection
+            if let Some(accepted) = accept_compression.header_value() {
+                ResponseHeaders::of(&mut cx).insert(ACCEPT_ENCODING_HEADER, accepted);
+            }
+            // the encoding of the compressed messages of the request
+            let encoding = trans!(
+                accept_compression.encoding_of(req.headers()),
+                cx,
+                http_status
+            );
+            // and of the response, if the client accepts it
+            let compression = SendCompression {
+                encoding: compression
+                    .encoding
+                    .filter(|e| e.is_accepted_by(req.headers())),
+                ..compression
+            };
+
             let (parts, body) = req.into_parts();
-            let body = trans!(T::from_body(
-                cx.rpc_info.method.as_deref(),
-                body,
-                Kind::Request
-            ));
-            let volo_req = Request::from_http_parts(parts, body);
+            let body = trans!(
+                T::from_body(
+                    cx.rpc_info.method.as_deref(),
+                    body,
+                    Kind::Request,
+                    DecodeConfig {
+                        encoding,
+                        memory,
+                        max_message_size: Some(message_size.max_recv),
+                        headers: None,
+                    }
+                ),
+                cx,
+                http_status
+            );
+            let mut volo_req = Request::from_http_parts(parts, body);
+            // and for the handlers, which only see the requests
+            volo_req.extensions_mut().insert(conn_info);
+            if let Some(deadline) = deadline {
+                volo_req.extensions_mut().insert(deadline);
+            }
 
-            let volo_resp = trans!(inner.call(&mut cx, volo_req).await);
+            // the only place the deadline is enforced, the layers and the clients only read it
+            let result = match deadline {
+                // dropping the handler future on timeout releases everything it holds,
+                // the client has already given up on the response.
+                Some(Deadline(deadline)) => {
+                    tokio::time::timeout_at(deadline.into(), inner.call(&mut cx, volo_req))
+                        .await
+                        .unwrap_or_else(|_| Err(Status::deadline_exceeded("deadline exceeded")))
+                }
+                None => inner.call(&mut cx, volo_req).await,
+            };
+            let volo_resp = trans!(result, cx, http_status);
 
             let (mut parts, body) = volo_resp.into_http().into_parts();
             parts.headers.insert(
                 http::header::CONTENT_TYPE,
                 http::header::HeaderValue::from_static("application/grpc"),
             );
-            let bytes_stream = body.into_body();
+            add_headers(&mut cx, &mut parts.headers);
+            let mut bytes_stream = body.into_body();
+            if message_size.max_send != usize::MAX {
+                bytes_stream = encode::limit(bytes_stream, message_size.max_send);
+            }
+            if let Some(encoding) = compression.encoding {
+                parts
+                    .headers
+                    .insert(ENCODING_HEADER, encoding.header_value());
+            }
+            bytes_stream = compression.compress(bytes_stream);
+            if let Some(truncate) = cx.extensions_mut().remove::<TruncateResponse>() {
+                bytes_stream = truncate.apply(bytes_stream);
+            }
             Ok(hyper::Response::from_parts(parts, Body::new(bytes_stream)))
         }
     }
@@ -305,6 +895,8 @@ pub struct Http2Config {
     pub(crate) http2_keepalive_timeout: Duration,
     pub(crate) max_frame_size: Option<u32>,
     pub(crate) accept_http1: bool,
+    pub(crate) max_connection_age: Option<Duration>,
+    pub(crate) max_connection_age_grace: Option<Duration>,
 }
 
 impl Default for Http2Config {
@@ -318,6 +910,182 @@ impl Default for Http2Config {
             http2_keepalive_timeout: DEFAULT_KEEPALIVE_TIMEOUT_SECS,
             max_frame_size: None,
             accept_http1: false,
+            max_connection_age: None,
+            max_connection_age_grace: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use motore::service::service_fn;
+    use volo::{
+        context::{Endpoint, Role, RpcInfo},
+        net::incoming::Incoming,
+    };
+
+    use super::*;
+    use crate::{
+        client::Http2Config as ClientHttp2Config,
+        context::{ClientContext, Config},
+        transport::ClientTransport,
+        BoxStream, Code, RecvStream,
+    };
+
+    /// Sets the flag when dropped, such as with the handler cancelled.
+    struct Cancelled(Arc<AtomicBool>);
+
+    impl Drop for Cancelled {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Calls the server, and collects the messages of the response.
+    async fn call(
+        addr: std::net::SocketAddr,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<String>, Status> {
+        let mut transport = ClientTransport::<RecvStream<String>>::new(
+            &ClientHttp2Config::default(),
+            &Config::default(),
+        );
+        let mut cx = ClientContext::new(RpcInfo::with_role(Role::Client));
+        cx.rpc_info.method = Some("/item.ItemService/GetItem".into());
+        let mut callee = Endpoint::new("item".into());
+        callee.set_address(addr.into());
+        cx.rpc_info.callee = Some(callee);
+        let req: BoxStream<'static, Result<String, Status>> =
+            Box::pin(futures::stream::once(async { Ok(String::new()) }));
+        let mut req = Request::new(req);
+        if let Some(timeout) = timeout {
+            req = req.with_timeout(timeout);
         }
+        transport
+            .call(&mut cx, req)
+            .await?
+            .into_inner()
+            .try_collect()
+            .await
+    }
+
+    /// Serves the handler until the returned sender is dropped or sent, the server is done once
+    /// the returned handle is.
+    async fn serve_until<H, F>(
+        handler: H,
+        shutdown_timeout: Duration,
+    ) -> (
+        std::net::SocketAddr,
+        tokio::sync::oneshot::Sender<()>,
+        tokio::task::JoinHandle<()>,
+    )
+    where
+        H: Fn(&mut ServerContext, Request<RecvStream<String>>) -> F + Clone + Send + Sync + 'static,
+        F: Future<Output = Result<Response<BoxStream<'static, Result<String, Status>>>, Status>>
+            + Send
+            + 'static,
+    {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, signal) = tokio::sync::oneshot::channel::<()>();
+        let server = Server::new(service_fn(handler))
+            .shutdown_timeout(shutdown_timeout)
+            .run_with_graceful_shutdown(Incoming::from(listener), async move {
+                let _ = signal.await;
+            });
+        let handle = tokio::spawn(async move { server.await.unwrap() });
+        (addr, shutdown, handle)
+    }
+
+    fn reply(item: &str) -> Response<BoxStream<'static, Result<String, Status>>> {
+        let item = item.to_string();
+        Response::new(Box::pin(futures::stream::once(async move { Ok(item) })))
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drain() {
+        let handler = |_: &mut ServerContext, _: Request<RecvStream<String>>| async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Ok::<_, Status>(reply("done"))
+        };
+        let (addr, shutdown, server) = serve_until(handler, Duration::from_secs(10)).await;
+        let call = tokio::spawn(call(addr, None));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let start = std::time::Instant::now();
+        shutdown.send(()).unwrap();
+        // the call in flight completes during the drain
+        assert_eq!(call.await.unwrap().unwrap(), ["done"]);
+        // and then the server is done, without waiting for the timeout
+        server.await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_timeout() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let handler = {
+            let cancelled = cancelled.clone();
+            move |_: &mut ServerContext, _: Request<RecvStream<String>>| {
+                let guard = Cancelled(cancelled.clone());
+                async move {
+                    futures::future::pending::<()>().await;
+                    drop(guard);
+                    Ok::<_, Status>(reply("never"))
+                }
+            }
+        };
+        let (addr, shutdown, server) = serve_until(handler, Duration::from_millis(200)).await;
+        let call = tokio::spawn(call(addr, None));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let start = std::time::Instant::now();
+        shutdown.send(()).unwrap();
+        // the hung call doesn't block the shutdown, it's aborted after the timeout
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        call.await.unwrap().unwrap_err();
+        assert!(cancelled.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_deadline_exceeded() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let handler = {
+            let cancelled = cancelled.clone();
+            move |cx: &mut ServerContext, _: Request<RecvStream<String>>| {
+                let has_deadline = cx.deadline().is_some();
+                let guard = Cancelled(cancelled.clone());
+                async move {
+                    assert!(has_deadline);
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    drop(guard);
+                    let items = futures::stream::empty();
+                    Ok::<_, Status>(Response::new(
+                        Box::pin(items) as BoxStream<'static, Result<String, Status>>
+                    ))
+                }
+            }
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::new(service_fn(handler)).run(Incoming::from(listener)));
+
+        let start = std::time::Instant::now();
+        let status = call(addr, Some(Duration::from_millis(100)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // the handler is dropped by the server instead of running to the end
+        assert!(cancelled.load(Ordering::Relaxed));
     }
 }
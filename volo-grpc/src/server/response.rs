@@ -0,0 +1,95 @@
+//! The HTTP parts of the responses, for the callers which don't understand the gRPC status.
+//!
+//! The extra headers in [`ResponseHeaders`] are sent with both the responses and the rejections,
+//! and the HTTP status of the rejections is decided by [`HttpStatus`] or else the mapper of
+//! [`Server::http_status`].
+//!
+//! [`Server::http_status`]: super::Server::http_status
+
+use std::sync::Arc;
+
+use http::{HeaderMap, StatusCode};
+use volo::context::Context;
+
+use crate::{body::Body, context::ServerContext, Status};
+
+/// The extra headers of the response, in the extensions of the [`ServerContext`].
+///
+/// The headers override the ones of the same names set by the handler, and shouldn't be the ones
+/// reserved by gRPC, such as `grpc-status`.
+#[derive(Debug, Default, Clone)]
+pub struct ResponseHeaders(pub HeaderMap);
+
+impl ResponseHeaders {
+    /// Returns the extra headers of the call to add to.
+    pub fn of(cx: &mut ServerContext) -> &mut HeaderMap {
+        &mut cx
+            .extensions_mut()
+            .entry::<Self>()
+            .or_insert_with(Self::default)
+            .0
+    }
+}
+
+/// The HTTP status of the rejection of the call, in the extensions of the [`ServerContext`].
+///
+/// It takes precedence over [`Server::http_status`], and is ignored when the call succeeds.
+///
+/// [`Server::http_status`]: super::Server::http_status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpStatus(pub StatusCode);
+
+pub(crate) type HttpStatusMapper = Arc<dyn Fn(&Status) -> Option<StatusCode> + Send + Sync>;
+
+pub(crate) fn add_headers(cx: &mut ServerContext, headers: &mut HeaderMap) {
+    if let Some(ResponseHeaders(extra)) = cx.extensions_mut().remove::<ResponseHeaders>() {
+        headers.extend(extra);
+    }
+}
+
+/// Returns the response of the rejection by `status`.
+pub(crate) fn reject(
+    status: Status,
+    cx: &mut ServerContext,
+    mapper: Option<&HttpStatusMapper>,
+) -> http::Response<Body> {
+    let http_status = match cx.extensions_mut().remove::<HttpStatus>() {
+        Some(HttpStatus(http_status)) => Some(http_status),
+        None => mapper.and_then(|mapper| mapper(&status)),
+    };
+    let mut resp = status.to_http();
+    if let Some(http_status) = http_status {
+        *resp.status_mut() = http_status;
+    }
+    add_headers(cx, resp.headers_mut());
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{header::WWW_AUTHENTICATE, HeaderValue};
+
+    use super::*;
+    use crate::Code;
+
+    #[test]
+    fn test_reject() {
+        let mapper: HttpStatusMapper = Arc::new(|status: &Status| {
+            (status.code() == Code::Unimplemented).then_some(StatusCode::NOT_FOUND)
+        });
+
+        let mut cx = ServerContext::default();
+        let resp = reject(Status::unimplemented(""), &mut cx, Some(&mapper));
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = reject(Status::internal(""), &mut cx, Some(&mapper));
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        cx.extensions_mut()
+            .insert(HttpStatus(StatusCode::UNAUTHORIZED));
+        ResponseHeaders::of(&mut cx).insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        let resp = reject(Status::unimplemented(""), &mut cx, Some(&mapper));
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.headers()[WWW_AUTHENTICATE], "Bearer");
+        assert!(resp.headers().contains_key("grpc-status"));
+    }
+}
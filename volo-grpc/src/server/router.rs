@@ -0,0 +1,414 @@
+//! Route requests between services on one server.
+//!
+//! A [`Router`] serves several generated services on one listener, dispatching each request by
+//! its path `/package.Service/Method` to the service of the package and name. The messages are
+//! decoded only after being routed, by the service routed to, so a service never sees the paths
+//! of the others.
+//!
+//! A [`MetadataRouter`] routes the requests between implementations of the same service by their
+//! metadata, such as to a canary.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::{
+//!     metadata::{AsciiMetadataKey, AsciiMetadataValue},
+//!     server::{MetadataRouter, Router, Server},
+//! };
+//!
+//! let item = MetadataRouter::new(ItemServiceServer::service(Stable)).route(
+//!     AsciiMetadataKey::from_static("x-env"),
+//!     AsciiMetadataValue::from_static("canary"),
+//!     ItemServiceServer::service(Canary),
+//! );
+//!
+//! Server::new(Router::new())
+//!     .add_named("volo.example.ItemService", item)
+//!     .add_service(UserServiceServer::service(UserImpl))
+//!     .run(addr)
+//!     .await;
+//! ```
+
+use std::{future::Future, marker::PhantomData};
+
+use motore::service::{BoxCloneService, Service};
+use volo::service_info::ServiceInfo;
+
+use crate::{
+    codec::decode::{DecodeConfig, Kind},
+    context::ServerContext,
+    message::{RecvEntryMessage, SendEntryMessage},
+    metadata::{AsciiMetadataKey, AsciiMetadataValue},
+    proxy::RawBody,
+    Request, Response, Status,
+};
+
+/// The request received by a [`Router`], whose messages are decoded by the service routed to.
+pub struct RoutedRequest {
+    body: hyper::Body,
+    kind: Kind,
+    config: DecodeConfig,
+}
+
+impl RecvEntryMessage for RoutedRequest {
+    fn from_body(
+        _method: Option<&str>,
+        body: hyper::Body,
+        kind: Kind,
+        config: DecodeConfig,
+    ) -> Result<Self, Status> {
+        Ok(Self { body, kind, config })
+    }
+}
+
+type BoxRoute = BoxCloneService<ServerContext, Request<RoutedRequest>, Response<RawBody>, Status>;
+
+/// Decodes the [`RoutedRequest`] for the service, and encodes its response.
+struct Decode<S, T> {
+    inner: S,
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<S: Clone, T> Clone for Decode<S, T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, T, U> Service<ServerContext, Request<RoutedRequest>> for Decode<S, T>
+where
+    S: Service<ServerContext, Request<T>, Response = Response<U>, Error = Status> + Send + 'static,
+    T: RecvEntryMessage + Send + 'static,
+    U: SendEntryMessage + 'static,
+{
+    type Response = Response<RawBody>;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(
+        &'s mut self,
+        cx: &'cx mut ServerContext,
+        req: Request<RoutedRequest>,
+    ) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let (metadata, extensions, routed) = req.into_parts();
+            let message = T::from_body(
+                cx.rpc_info.method.as_deref(),
+                routed.body,
+                routed.kind,
+                routed.config,
+            )?;
+            let req = Request::from_parts(metadata, extensions, message);
+            let resp = self.inner.call(cx, req).await?;
+            Ok(resp.map(|message| RawBody::new(message.into_body())))
+        }
+    }
+}
+
+/// A [`Service`] that dispatches the requests to the services by the service of their paths.
+#[derive(Clone, Default)]
+pub struct Router {
+    /// The services by the prefix of their paths, such as `/volo.example.ItemService/`.
+    routes: Vec<(smol_str::SmolStr, BoxRoute)>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a generated service, such as `ItemServiceServer::service(S)`, serving the paths of
+    /// its full name in the IDL, such as `/volo.example.ItemService/GetItem`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a service of the same name is added already.
+    pub fn add_service<S, T, U>(self, service: S) -> Self
+    where
+        S: ServiceInfo
+            + Service<ServerContext, Request<T>, Response = Response<U>, Error = Status>
+            + Clone
+            + Send
+            + 'static,
+        for<'cx> S::Future<'cx>: Send,
+        T: RecvEntryMessage + Send + 'static,
+        U: SendEntryMessage + 'static,
+    {
+        self.add_named(S::NAME, service)
+    }
+
+    /// Adds a service serving the paths of the full name `name`, such as a [`MetadataRouter`]
+    /// of `volo.example.ItemService`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a service of the same name is added already.
+    pub fn add_named<S, T, U>(mut self, name: &str, service: S) -> Self
+    where
+        S: Service<ServerContext, Request<T>, Response = Response<U>, Error = Status>
+            + Clone
+            + Send
+            + 'static,
+        for<'cx> S::Future<'cx>: Send,
+        T: RecvEntryMessage + Send + 'static,
+        U: SendEntryMessage + 'static,
+    {
+        let prefix = smol_str::SmolStr::from(format!("/{}/", name));
+        assert!(
+            self.routes.iter().all(|(p, _)| *p != prefix),
+            "the service {} is added twice",
+            name
+        );
+        self.routes.push((
+            prefix,
+            BoxRoute::new(Decode {
+                inner: service,
+                _marker: PhantomData,
+            }),
+        ));
+        self
+    }
+}
+
+impl Service<ServerContext, Request<RoutedRequest>> for Router {
+    type Response = Response<RawBody>;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(
+        &'s mut self,
+        cx: &'cx mut ServerContext,
+        req: Request<RoutedRequest>,
+    ) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let path = cx.rpc_info.method.clone().unwrap_or_default();
+            match self
+                .routes
+                .iter_mut()
+                .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+            {
+                Some((_, route)) => route.call(cx, req).await,
+                None => Err(Status::unimplemented(format!(
+                    "Unimplemented http path: {}",
+                    path
+                ))),
+            }
+        }
+    }
+}
+
+struct Route<T, U> {
+    key: AsciiMetadataKey,
+    value: AsciiMetadataValue,
+    service: BoxCloneService<ServerContext, Request<T>, Response<U>, Status>,
+}
+
+impl<T, U> Clone for Route<T, U> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            value: self.value.clone(),
+            service: self.service.clone(),
+        }
+    }
+}
+
+/// A [`Service`] that dispatches the request to the first route whose metadata matches, or to
+/// the default service if none matches.
+pub struct MetadataRouter<T, U> {
+    routes: Vec<Route<T, U>>,
+    default: BoxCloneService<ServerContext, Request<T>, Response<U>, Status>,
+}
+
+impl<T, U> Clone for MetadataRouter<T, U> {
+    fn clone(&self) -> Self {
+        Self {
+            routes: self.routes.clone(),
+            default: self.default.clone(),
+        }
+    }
+}
+
+impl<T: 'static, U: 'static> MetadataRouter<T, U> {
+    /// Creates a new [`MetadataRouter`] with the service used when no route matches.
+    pub fn new<S>(default: S) -> Self
+    where
+        S: Service<ServerContext, Request<T>, Response = Response<U>, Error = Status>
+            + Clone
+            + Send
+            + 'static,
+    {
+        Self {
+            routes: Vec::new(),
+            default: BoxCloneService::new(default),
+        }
+    }
+
+    /// Routes the requests whose metadata `key` equals to `value` to `service`.
+    ///
+    /// Routes are matched in the order they are added. The key and the value can be parsed from
+    /// the strings, such as by [`AsciiMetadataKey::from_bytes`] and [`str::parse`], to route by
+    /// the ones of the config.
+    pub fn route<S>(mut self, key: AsciiMetadataKey, value: AsciiMetadataValue, service: S) -> Self
+    where
+        S: Service<ServerContext, Request<T>, Response = Response<U>, Error = Status>
+            + Clone
+            + Send
+            + 'static,
+    {
+        self.routes.push(Route {
+            key,
+            value,
+            service: BoxCloneService::new(service),
+        });
+        self
+    }
+}
+
+impl<T, U> Service<ServerContext, Request<T>> for MetadataRouter<T, U>
+where
+    T: Send + 'static,
+    U: 'static,
+{
+    type Response = Response<U>;
+    type Error = Status;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut ServerContext, req: Request<T>) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        let metadata = req.metadata();
+        let service = match self
+            .routes
+            .iter_mut()
+            .find(|route| metadata.get(&route.key) == Some(&route.value))
+        {
+            Some(route) => &mut route.service,
+            None => &mut self.default,
+        };
+        service.call(cx, req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use motore::service::service_fn;
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct Echo;
+
+    impl ServiceInfo for Echo {
+        const NAME: &'static str = "volo.test.Echo";
+        const METHODS: &'static [&'static str] = &["Say"];
+        const VERSION: &'static str = "";
+    }
+
+    impl Service<ServerContext, Request<RawBody>> for Echo {
+        type Response = Response<RawBody>;
+        type Error = Status;
+        type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+        fn call<'cx, 's>(
+            &'s mut self,
+            _cx: &'cx mut ServerContext,
+            req: Request<RawBody>,
+        ) -> Self::Future<'cx>
+        where
+            's: 'cx,
+        {
+            async move { Ok(Response::new(req.into_inner())) }
+        }
+    }
+
+    async fn call(router: &mut Router, path: &str) -> Result<Vec<Bytes>, Status> {
+        let mut cx = ServerContext::default();
+        cx.rpc_info.method = Some(path.into());
+        let req = RoutedRequest::from_body(
+            Some(path),
+            hyper::Body::from("hello"),
+            Kind::Request,
+            Default::default(),
+        )?;
+        let resp = router.call(&mut cx, Request::new(req)).await?;
+        let chunks = resp.into_inner().into_stream().collect::<Vec<_>>().await;
+        chunks.into_iter().collect()
+    }
+
+    #[tokio::test]
+    async fn test_router() {
+        let mut router = Router::new().add_service(Echo);
+        let chunks = call(&mut router, "/volo.test.Echo/Say").await.unwrap();
+        assert_eq!(chunks, [Bytes::from_static(b"hello")]);
+
+        for path in ["/volo.test.EchoV2/Say", "/volo.test.Other/Say"] {
+            let status = call(&mut router, path).await.unwrap_err();
+            assert_eq!(status.code(), crate::Code::Unimplemented);
+        }
+    }
+
+    fn named(
+        name: &'static str,
+    ) -> impl Service<ServerContext, Request<()>, Response = Response<&'static str>, Error = Status>
+           + Clone
+           + Send
+           + 'static {
+        service_fn(move |_: &mut ServerContext, _: Request<()>| async move {
+            Ok::<_, Status>(Response::new(name))
+        })
+    }
+
+    async fn route(
+        router: &mut MetadataRouter<(), &'static str>,
+        env: Option<&str>,
+    ) -> &'static str {
+        let mut req = Request::new(());
+        if let Some(env) = env {
+            req.metadata_mut().insert("x-env", env.parse().unwrap());
+        }
+        let mut cx = ServerContext::default();
+        router.call(&mut cx, req).await.unwrap().into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_metadata_router() {
+        let mut router = MetadataRouter::new(named("stable"))
+            .route(
+                AsciiMetadataKey::from_static("x-env"),
+                AsciiMetadataValue::from_static("canary"),
+                named("canary"),
+            )
+            .route(
+                AsciiMetadataKey::from_bytes(b"x-env").unwrap(),
+                "canary".parse().unwrap(),
+                named("shadowed"),
+            )
+            .route(
+                AsciiMetadataKey::from_static("x-env"),
+                "dev".parse().unwrap(),
+                named("dev"),
+            );
+
+        assert_eq!(route(&mut router, None).await, "stable");
+        assert_eq!(route(&mut router, Some("prod")).await, "stable");
+        // the first route matching wins
+        assert_eq!(route(&mut router, Some("canary")).await, "canary");
+        assert_eq!(route(&mut router, Some("dev")).await, "dev");
+
+        // the invalid keys are rejected when parsed instead of panicking
+        AsciiMetadataKey::from_bytes(b"x env").unwrap_err();
+    }
+}
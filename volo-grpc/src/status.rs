@@ -333,9 +333,17 @@ impl Status {
 
     // ==== transform between Error and HeaderMap ====
 
+    /// Create a [`Status`] from an arbitrary error.
+    ///
+    /// A [`Status`], h2, hyper or io error found in the source chain decides the code, otherwise
+    /// [`Code::Unknown`] is used. The original error is kept as the source of the returned
+    /// status, see [`Status::source`][Error::source].
     pub fn from_error(err: BoxError) -> Status {
-        Status::try_from_error(err)
-            .unwrap_or_else(|err| Status::new(Code::Unknown, err.to_string()))
+        Status::try_from_error(err).unwrap_or_else(|err| {
+            let mut status = Status::new(Code::Unknown, err.to_string());
+            status.source = Some(err);
+            status
+        })
     }
 
     pub fn try_from_error(err: BoxError) -> Result<Status, BoxError> {
@@ -353,13 +361,23 @@ impl Status {
             Err(err) => err,
         };
 
-        if let Some(status) = find_status_in_source_chain(&*err) {
+        if let Some(mut status) = find_status_in_source_chain(&*err) {
+            status.source = Some(err);
             return Ok(status);
         }
 
         Err(err)
     }
 
+    /// Sets the underlying error of the status, which can be retrieved with
+    /// [`Status::source`][Error::source].
+    ///
+    /// Note that the source is not sent to the peer and will be dropped when cloning.
+    pub fn with_source(mut self, source: impl Into<BoxError>) -> Status {
+        self.source = Some(source.into());
+        self
+    }
+
     // transform between http2 and grpc error code.
     // refer to https://github.com/grpc/grpc/blob/master/doc/statuscodes.md.
     pub fn from_h2_error(err: Box<h2::Error>) -> Status {
@@ -443,11 +461,14 @@ impl Status {
             // the detail message from 'grpc-status-details-bin'
             let details = header_map
                 .get(GRPC_STATUS_DETAILS_HEADER)
-                .map(|h| {
-                    base64::decode(h.as_bytes())
-                        .expect("Invalid status header, expected base64 encoded value")
+                .and_then(|h| match base64::decode(h.as_bytes()) {
+                    Ok(details) => Some(Bytes::from(details)),
+                    // sent by the peer, so skipped instead of failing the call
+                    Err(err) => {
+                        warn!("[VOLO] Error decoding status details header: {}", err);
+                        None
+                    }
                 })
-                .map(Bytes::from)
                 .unwrap_or_else(Bytes::new);
 
             // must remove these redundant message from the header map
@@ -530,7 +551,8 @@ impl Status {
         &self.message
     }
 
-    /// Get the opaque error details of this `Status`.
+    /// Get the opaque error details of this `Status`, see
+    /// [`Status::error_details`] of the typed ones.
     pub fn details(&self) -> &[u8] {
         &self.details
     }
@@ -649,6 +671,10 @@ fn find_status_in_source_chain(err: &(dyn Error + 'static)) -> Option<Status> {
             return Some(hyper);
         }
 
+        if let Some(io) = err.downcast_ref::<std::io::Error>() {
+            return Some(Status::new(io_error_code(io.kind()), io.to_string()));
+        }
+
         source = err.source();
     }
 
@@ -700,30 +726,71 @@ impl From<Status> for h2::Error {
     }
 }
 
+impl From<volo::layer::timeout::Elapsed> for Status {
+    fn from(err: volo::layer::timeout::Elapsed) -> Self {
+        Status::deadline_exceeded(err.to_string())
+    }
+}
+
+impl From<volo::layer::circuit_breaker::BreakerOpen> for Status {
+    fn from(err: volo::layer::circuit_breaker::BreakerOpen) -> Self {
+        Status::unavailable(err.to_string())
+    }
+}
+
+impl From<volo::layer::concurrency_limit::ConcurrencyLimited> for Status {
+    fn from(err: volo::layer::concurrency_limit::ConcurrencyLimited) -> Self {
+        Status::resource_exhausted(err.to_string())
+    }
+}
+
+impl From<volo::layer::load_shed::Overloaded> for Status {
+    fn from(err: volo::layer::load_shed::Overloaded) -> Self {
+        Status::unavailable(err.to_string())
+    }
+}
+
+impl From<volo::layer::rate_limit::RateLimited> for Status {
+    fn from(err: volo::layer::rate_limit::RateLimited) -> Self {
+        Status::resource_exhausted(err.to_string())
+    }
+}
+
 impl From<std::io::Error> for Status {
     fn from(err: std::io::Error) -> Self {
-        use std::io::ErrorKind;
-        let code = match err.kind() {
-            ErrorKind::BrokenPipe
-            | ErrorKind::WouldBlock
-            | ErrorKind::WriteZero
-            | ErrorKind::Interrupted => Code::Internal,
-            ErrorKind::ConnectionRefused
-            | ErrorKind::ConnectionReset
-            | ErrorKind::NotConnected
-            | ErrorKind::AddrInUse
-            | ErrorKind::AddrNotAvailable => Code::Unavailable,
-            ErrorKind::AlreadyExists => Code::AlreadyExists,
-            ErrorKind::ConnectionAborted => Code::Aborted,
-            ErrorKind::InvalidData => Code::DataLoss,
-            ErrorKind::InvalidInput => Code::InvalidArgument,
-            ErrorKind::NotFound => Code::NotFound,
-            ErrorKind::PermissionDenied => Code::PermissionDenied,
-            ErrorKind::TimedOut => Code::DeadlineExceeded,
-            ErrorKind::UnexpectedEof => Code::OutOfRange,
-            _ => Code::Unknown,
-        };
-        Status::new(code, err.to_string())
+        let mut status = Status::new(io_error_code(err.kind()), err.to_string());
+        status.source = Some(Box::new(err));
+        status
+    }
+}
+
+impl From<anyhow::Error> for Status {
+    fn from(err: anyhow::Error) -> Self {
+        Status::from_error(err.into())
+    }
+}
+
+fn io_error_code(kind: std::io::ErrorKind) -> Code {
+    use std::io::ErrorKind;
+    match kind {
+        ErrorKind::BrokenPipe
+        | ErrorKind::WouldBlock
+        | ErrorKind::WriteZero
+        | ErrorKind::Interrupted => Code::Internal,
+        ErrorKind::ConnectionRefused
+        | ErrorKind::ConnectionReset
+        | ErrorKind::NotConnected
+        | ErrorKind::AddrInUse
+        | ErrorKind::AddrNotAvailable => Code::Unavailable,
+        ErrorKind::AlreadyExists => Code::AlreadyExists,
+        ErrorKind::ConnectionAborted => Code::Aborted,
+        ErrorKind::InvalidData => Code::DataLoss,
+        ErrorKind::InvalidInput => Code::InvalidArgument,
+        ErrorKind::NotFound => Code::NotFound,
+        ErrorKind::PermissionDenied => Code::PermissionDenied,
+        ErrorKind::TimedOut => Code::DeadlineExceeded,
+        ErrorKind::UnexpectedEof => Code::OutOfRange,
+        _ => Code::Unknown,
     }
 }
 
@@ -910,6 +977,47 @@ mod tests {
         assert_eq!(source.reason(), Some(h2::Reason::CANCEL));
     }
 
+    #[test]
+    fn from_error_keeps_source() {
+        use std::error::Error as _;
+
+        let orig = Nested(Box::new(Status::new(Code::OutOfRange, "weeaboo")));
+        let found = Status::from_error(Box::new(orig));
+        assert!(found.source().unwrap().downcast_ref::<Nested>().is_some());
+
+        let orig: Error = "peek-a-boo".into();
+        let found = Status::from_error(orig);
+        assert_eq!(found.source().unwrap().to_string(), "peek-a-boo");
+    }
+
+    #[test]
+    fn from_error_io() {
+        let orig = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        let found = Status::from_error(Box::new(Nested(Box::new(orig))));
+        assert_eq!(found.code(), Code::Unavailable);
+    }
+
+    #[test]
+    fn from_anyhow() {
+        let orig = anyhow::Error::new(Status::new(Code::NotFound, "nothing")).context("wrapped");
+        let found = Status::from(orig);
+        assert_eq!(found.code(), Code::NotFound);
+
+        let found = Status::from(anyhow::anyhow!("oops"));
+        assert_eq!(found.code(), Code::Unknown);
+        assert_eq!(found.message(), "oops");
+    }
+
+    #[test]
+    #[allow(clippy::redundant_clone)]
+    fn with_source() {
+        use std::error::Error as _;
+
+        let status = Status::internal("internal").with_source("root cause");
+        assert_eq!(status.source().unwrap().to_string(), "root cause");
+        assert!(status.clone().source().is_none());
+    }
+
     #[test]
     fn to_h2_error() {
         let orig = Status::new(Code::Cancelled, "stop eet!");
@@ -0,0 +1,204 @@
+//! TLS of the clients and the servers, based on [`rustls`].
+//!
+//! [`ServerTlsConfig`] and [`ClientTlsConfig`] build the [`TlsAcceptor`] of
+//! [`Server::tls_config`] and the [`TlsConnector`] of [`ClientBuilder::tls_config`], negotiating
+//! `h2` by ALPN as required by gRPC. The server verifies the certificates of the clients when it
+//! has the CA certificates of them, which is mTLS, and the verified identity of the client is put
+//! in the extensions of the requests then.
+//!
+//! The acceptor and the connector can also be made of the [`rustls`] configs directly, such as
+//! to be reloaded when the certificates change, see [`volo::net::tls`].
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::net::tls::{load_certs, load_private_key};
+//! use volo_grpc::tls::{ClientTlsConfig, ServerTlsConfig};
+//!
+//! let acceptor = ServerTlsConfig::new()
+//!     .identity(load_certs("server.pem")?, load_private_key("server.key")?)
+//!     .client_ca_certificates(load_certs("ca.pem")?)
+//!     .build()?;
+//! ItemServiceServer::new(S).tls_config(acceptor).run(addr).await;
+//!
+//! let connector = ClientTlsConfig::new("item.example.com")
+//!     .ca_certificates(load_certs("ca.pem")?)
+//!     .identity(load_certs("client.pem")?, load_private_key("client.key")?)
+//!     .build()?;
+//! let client = ItemServiceClientBuilder::new("item")
+//!     .tls_config(connector)
+//!     .target(addr)
+//!     .build();
+//! ```
+//!
+//! [`Server::tls_config`]: crate::server::Server::tls_config
+//! [`ClientBuilder::tls_config`]: crate::client::ClientBuilder::tls_config
+
+use std::io;
+
+pub use volo::net::tls::{rustls, TlsAcceptor, TlsConnector};
+
+use self::rustls::{
+    server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient},
+    Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig,
+};
+
+const ALPN_H2: &[u8] = b"h2";
+
+fn root_store(mut roots: RootCertStore, certs: &[Certificate]) -> io::Result<RootCertStore> {
+    for cert in certs {
+        roots.add(cert).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid CA certificate: {:?}", e),
+            )
+        })?;
+    }
+    Ok(roots)
+}
+
+fn invalid_config(e: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
+
+/// The TLS config of the server, built into a [`TlsAcceptor`].
+#[derive(Debug, Clone, Default)]
+pub struct ServerTlsConfig {
+    identity: Option<(Vec<Certificate>, PrivateKey)>,
+    client_ca_certificates: Vec<Certificate>,
+    client_auth_optional: bool,
+}
+
+impl ServerTlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the certificate chain and the private key of the server, which are required.
+    pub fn identity(mut self, certs: Vec<Certificate>, key: PrivateKey) -> Self {
+        self.identity = Some((certs, key));
+        self
+    }
+
+    /// Adds the CA certificates to verify the certificates of the clients by, which enables
+    /// mTLS.
+    pub fn client_ca_certificates(mut self, certs: Vec<Certificate>) -> Self {
+        self.client_ca_certificates.extend(certs);
+        self
+    }
+
+    /// Sets whether the clients without certificates are accepted too, when verifying the
+    /// certificates of the clients.
+    ///
+    /// Default is false.
+    pub fn client_auth_optional(mut self, optional: bool) -> Self {
+        self.client_auth_optional = optional;
+        self
+    }
+
+    /// Builds the [`TlsAcceptor`], failing if the identity is missing or invalid.
+    pub fn build(self) -> io::Result<TlsAcceptor> {
+        let (certs, key) = self.identity.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the identity of the server is required",
+            )
+        })?;
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = if self.client_ca_certificates.is_empty() {
+            builder.with_no_client_auth()
+        } else {
+            let roots = root_store(RootCertStore::empty(), &self.client_ca_certificates)?;
+            if self.client_auth_optional {
+                builder
+                    .with_client_cert_verifier(AllowAnyAnonymousOrAuthenticatedClient::new(roots))
+            } else {
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+            }
+        };
+        let mut config = builder
+            .with_single_cert(certs, key)
+            .map_err(invalid_config)?;
+        config.alpn_protocols = vec![ALPN_H2.to_vec()];
+        Ok(TlsAcceptor::new(config))
+    }
+}
+
+/// The TLS config of the client, built into a [`TlsConnector`].
+#[derive(Debug, Clone)]
+pub struct ClientTlsConfig {
+    domain_name: String,
+    roots: RootCertStore,
+    ca_certificates: Vec<Certificate>,
+    identity: Option<(Vec<Certificate>, PrivateKey)>,
+}
+
+impl ClientTlsConfig {
+    /// Creates a new [`ClientTlsConfig`] of the domain name of the server, which is sent by SNI
+    /// and verified against the certificate of the server.
+    pub fn new(domain_name: impl Into<String>) -> Self {
+        Self {
+            domain_name: domain_name.into(),
+            roots: RootCertStore::empty(),
+            ca_certificates: Vec::new(),
+            identity: None,
+        }
+    }
+
+    /// Adds the CA certificates to verify the certificate of the server by.
+    pub fn ca_certificates(mut self, certs: Vec<Certificate>) -> Self {
+        self.ca_certificates.extend(certs);
+        self
+    }
+
+    /// Sets the root certificates to verify the certificate of the server by, such as the ones
+    /// of `webpki-roots` for the public servers, which the CA certificates are added to.
+    ///
+    /// Default is empty.
+    pub fn roots(mut self, roots: RootCertStore) -> Self {
+        self.roots = roots;
+        self
+    }
+
+    /// Sets the certificate chain and the private key of the client, for the servers verifying
+    /// the clients by mTLS.
+    pub fn identity(mut self, certs: Vec<Certificate>, key: PrivateKey) -> Self {
+        self.identity = Some((certs, key));
+        self
+    }
+
+    /// Builds the [`TlsConnector`], failing if the certificates or the domain name are invalid.
+    pub fn build(self) -> io::Result<TlsConnector> {
+        let roots = root_store(self.roots, &self.ca_certificates)?;
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let mut config = match self.identity {
+            Some((certs, key)) => builder
+                .with_single_cert(certs, key)
+                .map_err(invalid_config)?,
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![ALPN_H2.to_vec()];
+        TlsConnector::new(config, &self.domain_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let err = ServerTlsConfig::new().build().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = ServerTlsConfig::new()
+            .identity(Vec::new(), PrivateKey(Vec::new()))
+            .build()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        ClientTlsConfig::new("item.example.com").build().unwrap();
+        ClientTlsConfig::new("").build().unwrap_err();
+    }
+}
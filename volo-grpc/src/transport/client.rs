@@ -1,4 +1,4 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, sync::Arc, time::Instant};
 
 use futures::Future;
 use http::{
@@ -9,19 +9,44 @@ use hyper::{client::HttpConnector, Client as HyperClient};
 use hyper_timeout::TimeoutConnector;
 use motore::Service;
 use tower::{util::ServiceExt, Service as TowerService};
-use volo::{net::Address, Unwrap};
+use volo::{
+    context::{Context, Deadline},
+    net::{dial::Dialer, Address},
+    Unwrap,
+};
 
+use super::{
+    connect::Connector,
+    connectivity::{Connectivity, TrackedConnector},
+    replay::{self, ReplayBody},
+};
 use crate::{
-    client::Http2Config,
-    codec::decode::Kind,
+    client::{
+        stats::{CallStats, RpcStats, StatsHandler},
+        Http2Config,
+    },
+    codec::{
+        compression::{
+            AcceptCompression, SendCompression, ACCEPT_ENCODING_HEADER, ENCODING_HEADER,
+        },
+        decode::{DecodeConfig, Kind},
+        encode, MessageSize,
+    },
     context::{ClientContext, Config},
+    metadata::MetadataMap,
     Code, Request, Response, Status,
 };
 
 /// A simple wrapper of [`hyper::client::client`] that implements [`Service`]
 /// to make outgoing requests.
 pub struct ClientTransport<U> {
-    http_client: HyperClient<TimeoutConnector<HttpConnector>>,
+    http_client: HyperClient<TrackedConnector>,
+    retry_refused: bool,
+    connectivity: Connectivity,
+    stats: Option<Arc<dyn StatsHandler>>,
+    compression: SendCompression,
+    accept_compression: AcceptCompression,
+    message_size: MessageSize,
     _marker: PhantomData<fn(U)>,
 }
 
@@ -29,6 +54,12 @@ impl<U> Clone for ClientTransport<U> {
     fn clone(&self) -> Self {
         Self {
             http_client: self.http_client.clone(),
+            retry_refused: self.retry_refused,
+            connectivity: self.connectivity.clone(),
+            stats: self.stats.clone(),
+            compression: self.compression.clone(),
+            accept_compression: self.accept_compression.clone(),
+            message_size: self.message_size,
             _marker: self._marker,
         }
     }
@@ -38,6 +69,43 @@ impl<U> ClientTransport<U> {
     /// Creates a new [`ClientTransport`] by setting the underlying connection
     /// with the given config.
     pub fn new(http2_config: &Http2Config, rpc_config: &Config) -> Self {
+        Self::with_connectivity(http2_config, rpc_config, None, Connectivity::new(), None)
+    }
+
+    /// Creates a new [`ClientTransport`] making the underlying connections by the dialer, the
+    /// tcp options and the timeouts of the connections are left to the dialer.
+    pub fn with_dialer(http2_config: &Http2Config, dialer: Arc<dyn Dialer>) -> Self {
+        Self::with_connectivity(
+            http2_config,
+            &Config::default(),
+            Some(dialer),
+            Connectivity::new(),
+            None,
+        )
+    }
+
+    /// Creates a new [`ClientTransport`] tracking the connectivity state in `connectivity`, and
+    /// the stats of the connections and the calls by `stats` if any.
+    pub(crate) fn with_connectivity(
+        http2_config: &Http2Config,
+        rpc_config: &Config,
+        dialer: Option<Arc<dyn Dialer>>,
+        connectivity: Connectivity,
+        stats: Option<Arc<dyn StatsHandler>>,
+    ) -> Self {
+        let connector = match dialer {
+            Some(dialer) => Connector::Dialer(dialer),
+            None => Self::http_connector(http2_config, rpc_config),
+        };
+        Self::with_connector(http2_config, connector, connectivity, stats)
+    }
+
+    /// Returns the connectivity state of the connections.
+    pub fn connectivity(&self) -> &Connectivity {
+        &self.connectivity
+    }
+
+    fn http_connector(http2_config: &Http2Config, rpc_config: &Config) -> Connector {
         let mut connector = HttpConnector::new();
         connector.enforce_http(false);
         connector.set_nodelay(http2_config.tcp_nodelay);
@@ -48,7 +116,15 @@ impl<U> ClientTransport<U> {
             connector.set_read_timeout(rpc_config.read_timeout);
             connector.set_write_timeout(rpc_config.write_timeout);
         }
+        Connector::Http(connector)
+    }
 
+    fn with_connector(
+        http2_config: &Http2Config,
+        connector: Connector,
+        connectivity: Connectivity,
+        stats: Option<Arc<dyn StatsHandler>>,
+    ) -> Self {
         let http = HyperClient::builder()
             .http2_only(!http2_config.accept_http1)
             .http2_initial_stream_window_size(http2_config.init_stream_window_size)
@@ -60,13 +136,43 @@ impl<U> ClientTransport<U> {
             .http2_keep_alive_while_idle(http2_config.http2_keepalive_while_idle)
             .http2_max_concurrent_reset_streams(http2_config.max_concurrent_reset_streams)
             .retry_canceled_requests(http2_config.retry_canceled_requests)
-            .build(connector);
+            .pool_idle_timeout(http2_config.pool_idle_timeout)
+            .pool_max_idle_per_host(http2_config.pool_max_idle_per_host)
+            .build(TrackedConnector::new(
+                connector,
+                connectivity.clone(),
+                stats.clone(),
+            ));
 
         ClientTransport {
             http_client: http,
+            retry_refused: http2_config.retry_canceled_requests,
+            connectivity,
+            stats,
+            compression: Default::default(),
+            accept_compression: Default::default(),
+            message_size: Default::default(),
             _marker: PhantomData,
         }
     }
+
+    /// Compresses the messages of the requests by the compression.
+    pub(crate) fn send_compression(mut self, compression: SendCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Accepts the messages of the responses compressed by the encodings.
+    pub(crate) fn accept_compression(mut self, accept_compression: AcceptCompression) -> Self {
+        self.accept_compression = accept_compression;
+        self
+    }
+
+    /// Limits the sizes of the messages of the requests and the responses.
+    pub(crate) fn message_size(mut self, message_size: MessageSize) -> Self {
+        self.message_size = message_size;
+        self
+    }
 }
 
 impl<T, U> Service<ClientContext, Request<T>> for ClientTransport<U>
@@ -83,12 +189,17 @@ where
     fn call<'cx, 's>(
         &'s mut self,
         cx: &'cx mut ClientContext,
-        volo_req: Request<T>,
+        mut volo_req: Request<T>,
     ) -> Self::Future<'cx>
     where
         's: 'cx,
     {
         let mut http_client = self.http_client.clone();
+        let retry_refused = self.retry_refused;
+        let stats = self.stats.clone();
+        let compression = self.compression.clone();
+        let accept_compression = self.accept_compression.clone();
+        let message_size = self.message_size;
         async move {
             // SAFETY: parameters controlled by volo-grpc are guaranteed to be valid.
             // get the call address from the context
@@ -101,39 +212,122 @@ where
                     std::io::Error::new(std::io::ErrorKind::InvalidData, "address is required")
                 })?;
             let path = cx.rpc_info.method().volo_unwrap();
+            // the remaining time of the deadline of the call, such as by the rpc timeout, so that
+            // the server gives up as soon as the client does
+            if let Some(Deadline(deadline)) = cx.extensions().get::<Deadline>().copied() {
+                if matches!(volo_req.metadata().grpc_timeout(), Ok(None)) {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(Status::deadline_exceeded(
+                            "deadline exceeded before the call",
+                        ));
+                    }
+                    volo_req.set_timeout(remaining);
+                }
+            }
+            let call = stats.map(|stats| CallStats::begin(stats, path.clone()));
 
             let (metadata, extensions, message) = volo_req.into_parts();
-            let body = message.into_body();
-            let body = hyper::Body::wrap_stream(body);
-
-            let mut req = hyper::Request::new(body);
-            *req.version_mut() = http::Version::HTTP_2;
-            *req.method_mut() = http::Method::POST;
-            *req.uri_mut() = build_uri(target, path.as_str());
-            *req.headers_mut() = metadata.into_headers();
-            *req.extensions_mut() = extensions;
-            req.headers_mut()
-                .insert(TE, HeaderValue::from_static("trailers"));
-            req.headers_mut()
-                .insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
-
-            // call the service through hyper client
-            let resp = http_client
-                .ready()
-                .await
-                .map_err(|err| Status::from_error(err.into()))?
-                .call(req)
-                .await
-                .map_err(|err| Status::from_error(err.into()))?;
+            let body = match &call {
+                Some(call) => call.out_payloads(message.into_body()),
+                None => message.into_body(),
+            };
+            let body = match message_size.max_send {
+                usize::MAX => body,
+                max => encode::limit(body, max),
+            };
+            let body = ReplayBody::new(compression.compress(body));
+            let uri = build_uri(target, path.as_str());
+            let mut headers = metadata.into_headers();
+            headers.insert(TE, HeaderValue::from_static("trailers"));
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+            if let Some(encoding) = compression.encoding {
+                headers.insert(ENCODING_HEADER, encoding.header_value());
+            }
+            if let Some(accepted) = accept_compression.header_value() {
+                headers.insert(ACCEPT_ENCODING_HEADER, accepted);
+            }
+            if let Some(call) = &call {
+                call.emit(&RpcStats::OutHeader { headers: &headers });
+            }
+            // the extensions can't be cloned, so they are only sent with the first attempt
+            let mut extensions = Some(extensions);
+
+            let mut replays = 0;
+            let resp = loop {
+                let mut req = hyper::Request::new(hyper::Body::wrap_stream(body.attempt()));
+                *req.version_mut() = http::Version::HTTP_2;
+                *req.method_mut() = http::Method::POST;
+                *req.uri_mut() = uri.clone();
+                *req.headers_mut() = headers.clone();
+                if let Some(extensions) = extensions.take() {
+                    *req.extensions_mut() = extensions;
+                }
+
+                // call the service through hyper client
+                let result = match http_client.ready().await {
+                    Ok(http_client) => http_client.call(req).await,
+                    Err(err) => Err(err),
+                };
+                match result {
+                    Ok(resp) => break resp,
+                    Err(err)
+                        if retry_refused
+                            && replays < replay::MAX_REPLAYS
+                            && replay::is_refused(&err)
+                            && body.replayable() =>
+                    {
+                        replays += 1;
+                        tracing::debug!(
+                            "[VOLO] request to {} refused, sending it again: {}",
+                            uri,
+                            err
+                        );
+                    }
+                    Err(err) => {
+                        let status = Status::from_error(err.into());
+                        if let Some(call) = call {
+                            call.end(Some(&status));
+                        }
+                        return Err(status);
+                    }
+                }
+            };
 
             let status_code = resp.status();
+            if let Some(call) = &call {
+                call.emit(&RpcStats::InHeader {
+                    headers: resp.headers(),
+                });
+            }
             if let Some(status) = Status::from_header_map(resp.headers()) {
                 if status.code() != Code::Ok {
+                    if let Some(call) = call {
+                        call.end(Some(&status));
+                    }
                     return Err(status);
                 }
             }
+            let config = match accept_compression.encoding_of(resp.headers()) {
+                Ok(encoding) => DecodeConfig {
+                    encoding,
+                    max_message_size: Some(message_size.max_recv),
+                    headers: Some(MetadataMap::from_headers(resp.headers().clone())),
+                    ..Default::default()
+                },
+                Err(status) => {
+                    if let Some(call) = call {
+                        call.end(Some(&status));
+                    }
+                    return Err(status);
+                }
+            };
             let (parts, body) = resp.into_parts();
-            let body = U::from_body(Some(path), body, Kind::Response(status_code))?;
+            let body = match call {
+                Some(call) => call.pipe_response(body),
+                None => body,
+            };
+            let body = U::from_body(Some(path), body, Kind::Response(status_code), config)?;
             let resp = hyper::Response::from_parts(parts, body);
 
             Ok(Response::from_http(resp))
@@ -141,7 +335,7 @@ where
     }
 }
 
-fn build_uri(addr: Address, path: &str) -> hyper::Uri {
+pub(crate) fn build_uri(addr: Address, path: &str) -> hyper::Uri {
     match addr {
         Address::Ip(ip) => hyper::Uri::builder()
             .scheme(http::uri::Scheme::HTTP)
@@ -149,17 +343,76 @@ fn build_uri(addr: Address, path: &str) -> hyper::Uri {
             .path_and_query(path)
             .build()
             .expect("fail to build ip uri"),
+        // the path isn't a valid authority, so it's hex encoded, see `connect::uri_address`
         Address::Unix(unix) => hyper::Uri::builder()
-            .scheme("http+unix")
-            .authority(unix.display().to_string())
+            .scheme(UNIX_SCHEME)
+            .authority(hex_encode(unix.as_os_str().to_string_lossy().as_bytes()))
             .path_and_query(path)
             .build()
             .expect("fail to build unix uri"),
     }
 }
 
+pub(crate) const UNIX_SCHEME: &str = "http+unix";
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 #[cfg(test)]
 mod tests {
+    use futures::StreamExt;
+    use motore::service::service_fn;
+    use volo::{
+        context::{Endpoint, Role, RpcInfo},
+        net::incoming::Incoming,
+    };
+
+    use super::*;
+    use crate::{
+        client::Http2Config, context::ServerContext, server::Server, BoxStream, RecvStream,
+    };
+
+    async fn list(
+        _: &mut ServerContext,
+        _: Request<RecvStream<String>>,
+    ) -> Result<Response<BoxStream<'static, Result<String, Status>>>, Status> {
+        let items = futures::stream::iter(["a", "b"].map(|item| Ok(item.to_string())));
+        let mut resp = Response::new(Box::pin(items) as BoxStream<'static, _>);
+        resp.metadata_mut()
+            .insert("x-initial", "1".parse().unwrap());
+        Ok(resp)
+    }
+
+    #[tokio::test]
+    async fn test_headers() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Server::new(service_fn(list)).run(Incoming::from(listener)));
+
+        let mut transport =
+            ClientTransport::<RecvStream<String>>::new(&Http2Config::default(), &Config::default());
+        let mut cx = ClientContext::new(RpcInfo::with_role(Role::Client));
+        cx.rpc_info.method = Some("/item.ItemService/ListItems".into());
+        let mut callee = Endpoint::new("item".into());
+        callee.set_address(addr.into());
+        cx.rpc_info.callee = Some(callee);
+        let req: BoxStream<'static, Result<String, Status>> =
+            Box::pin(futures::stream::once(async { Ok(String::new()) }));
+
+        let mut stream = transport
+            .call(&mut cx, Request::new(req))
+            .await
+            .unwrap()
+            .into_inner();
+        // the initial metadata are kept with the stream, before the messages
+        let headers = stream.headers().unwrap();
+        assert_eq!(headers.get("x-initial").unwrap(), "1");
+        let items: Vec<_> = (&mut stream).map(Result::unwrap).collect().await;
+        assert_eq!(items, ["a", "b"]);
+        assert!(stream.trailers().await.unwrap().is_some());
+    }
+
     #[test]
     fn test_build_uri() {
         let addr = "127.0.0.1:8000".parse::<std::net::SocketAddr>().unwrap();
@@ -168,5 +421,10 @@ mod tests {
             .parse::<hyper::Uri>()
             .unwrap();
         assert_eq!(super::build_uri(volo::net::Address::from(addr), path), uri);
+
+        let addr = volo::net::Address::from(std::path::PathBuf::from("/run/proxy.sock"));
+        let uri = super::build_uri(addr, path);
+        assert_eq!(uri.scheme_str(), Some(super::UNIX_SCHEME));
+        assert_eq!(uri.path(), "/path");
     }
 }
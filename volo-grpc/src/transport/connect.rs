@@ -0,0 +1,184 @@
+use std::{
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::future::{BoxFuture, FutureExt};
+use hyper::{
+    client::{
+        connect::{Connected, Connection},
+        HttpConnector,
+    },
+    Uri,
+};
+use hyper_timeout::TimeoutConnector;
+use motore::BoxError;
+use pin_project::pin_project;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tower::Service;
+use volo::net::{
+    conn::Conn,
+    dial::{Dialer, MakeConnection},
+    Address,
+};
+
+use super::client::UNIX_SCHEME;
+
+/// Makes the connections of the hyper client, by tcp or unix socket, or by the dialer of the
+/// user.
+#[derive(Clone)]
+pub(crate) enum Connector {
+    Http(TimeoutConnector<HttpConnector>),
+    Dialer(Arc<dyn Dialer>),
+}
+
+/// The tcp connection of hyper with the read and write timeouts, whose type isn't exported.
+type HttpStream = <TimeoutConnector<HttpConnector> as Service<Uri>>::Response;
+
+#[allow(clippy::large_enum_variant)]
+#[pin_project(project = ConnectorStreamProj)]
+pub(crate) enum ConnectorStream {
+    Http(#[pin] HttpStream),
+    /// The connections of the dialer, and of the unix sockets.
+    Dialer(#[pin] Conn),
+}
+
+impl Service<Uri> for Connector {
+    type Response = ConnectorStream;
+
+    type Error = BoxError;
+
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self {
+            Connector::Http(connector) => connector.poll_ready(cx),
+            Connector::Dialer(_) => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        match self {
+            Connector::Http(_) if uri.scheme_str() == Some(UNIX_SCHEME) => async move {
+                let conn = MakeConnection::default()
+                    .make_connection(uri_address(&uri)?)
+                    .await?;
+                Ok(ConnectorStream::Dialer(conn))
+            }
+            .boxed(),
+            Connector::Http(connector) => connector
+                .call(uri)
+                .map(|resp| resp.map(ConnectorStream::Http))
+                .boxed(),
+            Connector::Dialer(dialer) => {
+                let dialer = dialer.clone();
+                async move {
+                    let conn = dialer.dial(uri_address(&uri)?).await?;
+                    Ok(ConnectorStream::Dialer(conn))
+                }
+                .boxed()
+            }
+        }
+    }
+}
+
+/// Returns the address of the uri built by [`build_uri`](super::client::build_uri).
+pub(super) fn uri_address(uri: &Uri) -> io::Result<Address> {
+    let authority = uri
+        .authority()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "uri without authority"))?;
+    if uri.scheme_str() == Some(UNIX_SCHEME) {
+        return hex_decode(authority.as_str())
+            .and_then(|path| String::from_utf8(path).ok())
+            .map(|path| PathBuf::from(path).into())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid unix uri"));
+    }
+    authority
+        .as_str()
+        .parse::<SocketAddr>()
+        .map(Address::from)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl Connection for ConnectorStream {
+    fn connected(&self) -> Connected {
+        match self {
+            ConnectorStream::Http(stream) => stream.connected(),
+            ConnectorStream::Dialer(_) => Connected::new(),
+        }
+    }
+}
+
+impl AsyncRead for ConnectorStream {
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.project() {
+            ConnectorStreamProj::Http(s) => s.poll_read(cx, buf),
+            ConnectorStreamProj::Dialer(s) => s.poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ConnectorStream {
+    #[inline]
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        match self.project() {
+            ConnectorStreamProj::Http(s) => s.poll_write(cx, buf),
+            ConnectorStreamProj::Dialer(s) => s.poll_write(cx, buf),
+        }
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        match self.project() {
+            ConnectorStreamProj::Http(s) => s.poll_flush(cx),
+            ConnectorStreamProj::Dialer(s) => s.poll_flush(cx),
+        }
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        match self.project() {
+            ConnectorStreamProj::Http(s) => s.poll_shutdown(cx),
+            ConnectorStreamProj::Dialer(s) => s.poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uri_address() {
+        let addr = "127.0.0.1:8000".parse::<SocketAddr>().unwrap();
+        let uri = super::super::client::build_uri(Address::from(addr), "/path");
+        assert_eq!(uri_address(&uri).unwrap(), Address::from(addr));
+
+        let addr = Address::from(PathBuf::from("/run/proxy.sock"));
+        let uri = super::super::client::build_uri(addr.clone(), "/path");
+        assert_eq!(uri_address(&uri).unwrap(), addr);
+    }
+}
@@ -0,0 +1,370 @@
+//! The connectivity state of the client, for the applications to wait for the server to be
+//! reachable at startup, or to alert when it's been unreachable for long.
+//!
+//! Each address the client connects to is a subchannel, whose state is decided by its
+//! connections: [`Ready`] with any connection alive, [`Connecting`] with any connection being
+//! made, [`TransientFailure`] if the last connection failed, or else [`Idle`]. The state of the
+//! channel of the client is the best state of the subchannels, in that order.
+//!
+//! The connections are made on demand by the calls, so the channel is [`Idle`] until the first
+//! call, and goes back to [`Idle`] when its connections are closed, such as by the idle timeout.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_grpc::transport::{Connectivity, ConnectivityState};
+//!
+//! let connectivity = Connectivity::new();
+//! let client = ItemServiceClientBuilder::new("item")
+//!     .connectivity(&connectivity)
+//!     .target(addr)
+//!     .build();
+//!
+//! tokio::spawn(async move {
+//!     loop {
+//!         let state = connectivity.wait_for_state_change(connectivity.state()).await;
+//!         if state == ConnectivityState::TransientFailure {
+//!             tracing::warn!("item service is unreachable");
+//!         }
+//!     }
+//! });
+//! ```
+//!
+//! [`Ready`]: ConnectivityState::Ready
+//! [`Connecting`]: ConnectivityState::Connecting
+//! [`TransientFailure`]: ConnectivityState::TransientFailure
+//! [`Idle`]: ConnectivityState::Idle
+
+use std::{
+    collections::HashMap,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures::future::{BoxFuture, FutureExt};
+use hyper::{
+    client::connect::{Connected, Connection},
+    Uri,
+};
+use motore::BoxError;
+use pin_project::pin_project;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::watch,
+};
+use tower::Service;
+use volo::net::Address;
+
+use super::connect::{uri_address, Connector, ConnectorStream};
+use crate::client::stats::{ConnGuard, StatsHandler};
+
+/// The connectivity state of a channel or a subchannel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectivityState {
+    /// No connection is alive or being made.
+    Idle,
+    /// A connection is being made.
+    Connecting,
+    /// A connection is alive.
+    Ready,
+    /// The last connection failed, and no connection is alive or being made.
+    TransientFailure,
+}
+
+#[derive(Debug, Default)]
+struct Subchannel {
+    connecting: usize,
+    live: usize,
+    failed: bool,
+}
+
+impl Subchannel {
+    fn state(&self) -> ConnectivityState {
+        if self.live > 0 {
+            ConnectivityState::Ready
+        } else if self.connecting > 0 {
+            ConnectivityState::Connecting
+        } else if self.failed {
+            ConnectivityState::TransientFailure
+        } else {
+            ConnectivityState::Idle
+        }
+    }
+}
+
+struct Shared {
+    subchannels: Mutex<HashMap<Address, Subchannel>>,
+    sender: watch::Sender<ConnectivityState>,
+    // keeps the channel open, so that the state is always updated
+    receiver: watch::Receiver<ConnectivityState>,
+}
+
+/// The handle of the connectivity state of a client, shared by its clones.
+///
+/// A handle should only be set to one client, or the states of the clients are mixed.
+#[derive(Clone)]
+pub struct Connectivity {
+    shared: Arc<Shared>,
+}
+
+impl Default for Connectivity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Connectivity {
+    /// Creates a new [`Connectivity`] in [`ConnectivityState::Idle`].
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(ConnectivityState::Idle);
+        Self {
+            shared: Arc::new(Shared {
+                subchannels: Default::default(),
+                sender,
+                receiver,
+            }),
+        }
+    }
+
+    /// Returns the state of the channel.
+    pub fn state(&self) -> ConnectivityState {
+        *self.shared.receiver.borrow()
+    }
+
+    /// Returns the states of the subchannels, which are the addresses connected to.
+    pub fn subchannels(&self) -> Vec<(Address, ConnectivityState)> {
+        self.shared
+            .subchannels
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(addr, subchannel)| (addr.clone(), subchannel.state()))
+            .collect()
+    }
+
+    /// Returns the number of the connections alive to all the subchannels, such as for the
+    /// metrics of the pool.
+    pub fn connections(&self) -> usize {
+        self.shared
+            .subchannels
+            .lock()
+            .unwrap()
+            .values()
+            .map(|subchannel| subchannel.live)
+            .sum()
+    }
+
+    /// Returns the receiver of the state of the channel, which is notified on every change.
+    pub fn watch(&self) -> watch::Receiver<ConnectivityState> {
+        self.shared.receiver.clone()
+    }
+
+    /// Waits until the channel is in `state`, such as [`ConnectivityState::Ready`].
+    pub async fn wait_for_state(&self, state: ConnectivityState) {
+        let mut receiver = self.watch();
+        while *receiver.borrow() != state {
+            // the sender lives as long as the receiver
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Waits until the channel leaves the state `from`, and returns the new state.
+    pub async fn wait_for_state_change(&self, from: ConnectivityState) -> ConnectivityState {
+        let mut receiver = self.watch();
+        loop {
+            let state = *receiver.borrow();
+            if state != from || receiver.changed().await.is_err() {
+                return state;
+            }
+        }
+    }
+
+    fn update(&self, addr: &Address, f: impl FnOnce(&mut Subchannel)) {
+        let mut subchannels = self.shared.subchannels.lock().unwrap();
+        let subchannel = subchannels.entry(addr.clone()).or_default();
+        f(subchannel);
+        if subchannel.state() == ConnectivityState::Idle {
+            subchannels.remove(addr);
+        }
+        let state = [
+            ConnectivityState::Ready,
+            ConnectivityState::Connecting,
+            ConnectivityState::TransientFailure,
+        ]
+        .into_iter()
+        .find(|state| subchannels.values().any(|s| s.state() == *state))
+        .unwrap_or(ConnectivityState::Idle);
+        if *self.shared.receiver.borrow() != state {
+            let _ = self.shared.sender.send(state);
+        }
+    }
+}
+
+/// Makes the connections by the [`Connector`], tracking them in the [`Connectivity`] and the
+/// stats handler if any.
+#[derive(Clone)]
+pub(crate) struct TrackedConnector {
+    inner: Connector,
+    connectivity: Connectivity,
+    stats: Option<Arc<dyn StatsHandler>>,
+}
+
+impl TrackedConnector {
+    pub(crate) fn new(
+        inner: Connector,
+        connectivity: Connectivity,
+        stats: Option<Arc<dyn StatsHandler>>,
+    ) -> Self {
+        Self {
+            inner,
+            connectivity,
+            stats,
+        }
+    }
+}
+
+/// Moves the subchannel out of [`ConnectivityState::Ready`] when the connection is dropped.
+struct Live {
+    addr: Address,
+    connectivity: Connectivity,
+}
+
+impl Drop for Live {
+    fn drop(&mut self) {
+        self.connectivity.update(&self.addr, |s| s.live -= 1);
+    }
+}
+
+#[pin_project]
+pub(crate) struct TrackedStream {
+    #[pin]
+    inner: ConnectorStream,
+    _live: Option<Live>,
+    _stats: Option<ConnGuard>,
+}
+
+impl Service<Uri> for TrackedConnector {
+    type Response = TrackedStream;
+
+    type Error = BoxError;
+
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let addr = uri_address(&uri).ok();
+        let connectivity = self.connectivity.clone();
+        let stats = self.stats.clone();
+        if let Some(addr) = &addr {
+            connectivity.update(addr, |s| s.connecting += 1);
+        }
+        let connect = self.inner.call(uri);
+        async move {
+            let result = connect.await;
+            let addr = match addr {
+                Some(addr) => addr,
+                None => {
+                    return result.map(|inner| TrackedStream {
+                        inner,
+                        _live: None,
+                        _stats: None,
+                    });
+                }
+            };
+            let ok = result.is_ok();
+            connectivity.update(&addr, |s| {
+                s.connecting -= 1;
+                s.failed = !ok;
+                if ok {
+                    s.live += 1;
+                }
+            });
+            let inner = result?;
+            Ok(TrackedStream {
+                inner,
+                _stats: stats.map(|stats| ConnGuard::begin(stats, addr.clone())),
+                _live: Some(Live { addr, connectivity }),
+            })
+        }
+        .boxed()
+    }
+}
+
+impl Connection for TrackedStream {
+    fn connected(&self) -> Connected {
+        self.inner.connected()
+    }
+}
+
+impl AsyncRead for TrackedStream {
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.project().inner.poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TrackedStream {
+    #[inline]
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connectivity() {
+        let connectivity = Connectivity::new();
+        let a = Address::from(std::net::SocketAddr::from(([127, 0, 0, 1], 8080)));
+        let b = Address::from(std::net::SocketAddr::from(([127, 0, 0, 1], 8081)));
+
+        connectivity.update(&a, |s| s.connecting += 1);
+        assert_eq!(connectivity.state(), ConnectivityState::Connecting);
+        connectivity.update(&a, |s| {
+            s.connecting -= 1;
+            s.failed = true;
+        });
+        assert_eq!(connectivity.state(), ConnectivityState::TransientFailure);
+
+        let waiter = connectivity.clone();
+        let ready =
+            tokio::spawn(async move { waiter.wait_for_state(ConnectivityState::Ready).await });
+        connectivity.update(&b, |s| s.live += 1);
+        ready.await.unwrap();
+        assert_eq!(connectivity.subchannels().len(), 2);
+        assert_eq!(connectivity.connections(), 1);
+
+        connectivity.update(&b, |s| s.live -= 1);
+        assert_eq!(connectivity.state(), ConnectivityState::TransientFailure);
+        connectivity.update(&a, |s| s.failed = false);
+        assert_eq!(connectivity.state(), ConnectivityState::Idle);
+        assert!(connectivity.subchannels().is_empty());
+    }
+}
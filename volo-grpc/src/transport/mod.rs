@@ -1,5 +1,9 @@
 //! Used to make underlying connection to other endpoints.
 
 mod client;
+mod connect;
+mod connectivity;
+pub(crate) mod replay;
 
 pub use client::ClientTransport;
+pub use connectivity::{Connectivity, ConnectivityState};
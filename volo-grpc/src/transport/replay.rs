@@ -0,0 +1,187 @@
+//! Sends the requests refused by a server going away again on a fresh connection.
+//!
+//! A server shutting down gracefully, such as during a rolling restart, sends GOAWAY with the
+//! last stream it will process, and the streams after it are never processed, so the requests
+//! on them can be sent again safely, as well as the ones reset by `REFUSED_STREAM`. The body of a
+//! request is kept while being sent to be replayed, up to [`MAX_REPLAY_SIZE`], over which the
+//! request fails as before.
+//!
+//! The bodies are also replayed by the retries of the calls, see
+//! [`RetryConfig`](crate::client::RetryConfig).
+
+use std::{
+    error::Error as StdError,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::Stream;
+
+use crate::{message::SendEntryMessage, BoxStream, Status};
+
+/// The max size of the request body kept to be replayed.
+pub(crate) const MAX_REPLAY_SIZE: usize = 64 * 1024;
+
+/// The max times to send a refused request again.
+pub(crate) const MAX_REPLAYS: usize = 2;
+
+struct Replay {
+    source: BoxStream<'static, Result<Bytes, Status>>,
+    chunks: Vec<Bytes>,
+    size: usize,
+    // the body can't be replayed once it's too large or failed
+    replayable: bool,
+    ended: bool,
+    // only the stream of the latest attempt reads the body
+    attempt: usize,
+}
+
+/// The body of a request which can be sent multiple times.
+#[derive(Clone)]
+pub(crate) struct ReplayBody {
+    shared: Arc<Mutex<Replay>>,
+}
+
+impl ReplayBody {
+    pub(crate) fn new(source: BoxStream<'static, Result<Bytes, Status>>) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Replay {
+                source,
+                chunks: Vec::new(),
+                size: 0,
+                replayable: true,
+                ended: false,
+                attempt: 0,
+            })),
+        }
+    }
+
+    /// Returns whether the body read so far can be replayed.
+    pub(crate) fn replayable(&self) -> bool {
+        self.shared.lock().unwrap().replayable
+    }
+
+    /// Returns the body for a new attempt, the bodies of the previous attempts end once it's
+    /// read, so it can be made before the previous attempts fail.
+    pub(crate) fn attempt(&self) -> AttemptBody {
+        AttemptBody {
+            shared: self.shared.clone(),
+            attempt: None,
+            index: 0,
+        }
+    }
+}
+
+/// The body of an attempt of a request.
+pub struct AttemptBody {
+    shared: Arc<Mutex<Replay>>,
+    // taken by the first read
+    attempt: Option<usize>,
+    index: usize,
+}
+
+impl AttemptBody {
+    /// Returns whether the body read so far can be replayed.
+    pub(crate) fn replayable(&self) -> bool {
+        self.shared.lock().unwrap().replayable
+    }
+}
+
+impl Stream for AttemptBody {
+    type Item = Result<Bytes, Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let mut shared = this.shared.lock().unwrap();
+        let attempt = *this.attempt.get_or_insert_with(|| {
+            shared.attempt += 1;
+            shared.attempt
+        });
+        if shared.attempt != attempt {
+            return Poll::Ready(None);
+        }
+        if this.index < shared.chunks.len() {
+            this.index += 1;
+            return Poll::Ready(Some(Ok(shared.chunks[this.index - 1].clone())));
+        }
+        if shared.ended {
+            return Poll::Ready(None);
+        }
+        let polled = shared.source.as_mut().poll_next(cx);
+        match polled {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if shared.replayable {
+                    if shared.size + chunk.len() <= MAX_REPLAY_SIZE {
+                        shared.size += chunk.len();
+                        shared.chunks.push(chunk.clone());
+                        this.index += 1;
+                    } else {
+                        shared.replayable = false;
+                        shared.chunks = Vec::new();
+                    }
+                }
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Poll::Ready(Some(Err(status))) => {
+                shared.replayable = false;
+                Poll::Ready(Some(Err(status)))
+            }
+            Poll::Ready(None) => {
+                shared.ended = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl SendEntryMessage for AttemptBody {
+    fn into_body(self) -> BoxStream<'static, Result<Bytes, Status>> {
+        Box::pin(self)
+    }
+}
+
+/// Returns whether the request is never processed by the server, by the GOAWAY or the
+/// `REFUSED_STREAM` of the server.
+pub(crate) fn is_refused(err: &hyper::Error) -> bool {
+    let mut source = err.source();
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref::<h2::Error>() {
+            return err.reason() == Some(h2::Reason::REFUSED_STREAM)
+                || (err.is_go_away()
+                    && err.is_remote()
+                    && err.reason() == Some(h2::Reason::NO_ERROR));
+        }
+        source = err.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replay() {
+        let chunks = vec![Bytes::from_static(b"a"), Bytes::from_static(b"b")];
+        let body = ReplayBody::new(Box::pin(futures::stream::iter(
+            chunks.clone().into_iter().map(Ok),
+        )));
+
+        // the first attempt reads a chunk before refused
+        let mut first = body.attempt();
+        assert_eq!(first.next().await.unwrap().unwrap(), chunks[0]);
+
+        let second: Vec<_> = body.attempt().map(Result::unwrap).collect().await;
+        assert_eq!(second, chunks);
+        assert!(first.next().await.is_none());
+        assert!(body.replayable());
+
+        let third: Vec<_> = body.attempt().map(Result::unwrap).collect().await;
+        assert_eq!(third, chunks);
+    }
+}
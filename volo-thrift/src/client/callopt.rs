@@ -23,9 +23,7 @@
 //!
 //! #[volo::main]
 //! async fn main() {
-//!     let callopt = CallOpt::default();
-//!     // Do something with callopt here
-//!     ...
+//!     let callopt = CallOpt::new().with_rpc_timeout(Duration::from_millis(100));
 //!     let req = volo_gen::volo::example::item::GetItemRequest { id: 1024 };
 //!     let resp = CLIENT.clone().with_callopt(callopt).get_item(req).await;
 //!     match resp {
@@ -35,9 +33,12 @@
 //! }
 //! ```
 
+use std::time::Duration;
+
 use metainfo::TypeMap;
-use volo::net::Address;
+use volo::{context::Priority, net::Address};
 
+use super::layer::retry::RetryConfig;
 use crate::context::Config;
 
 #[derive(Debug, Default)]
@@ -51,6 +52,13 @@ pub struct CallOpt {
     pub config: Config,
     /// Sets the caller tags for the call.
     pub caller_tags: TypeMap,
+    /// Sets the priority of the call, which is sent to the server by the TTHeader transport.
+    pub priority: Option<Priority>,
+    /// Skips compressing the request of the call even if the client enables the compression,
+    /// such as for the payloads already compressed like images and archives.
+    pub disable_compression: bool,
+    /// Retries the call on the failures by the policy, such as for an idempotent method.
+    pub retry: Option<RetryConfig>,
 }
 
 impl CallOpt {
@@ -58,4 +66,47 @@ impl CallOpt {
     pub fn new() -> Self {
         Default::default()
     }
+
+    /// Sets the rpc timeout for the call.
+    pub fn with_rpc_timeout(mut self, timeout: Duration) -> Self {
+        self.config.set_rpc_timeout(Some(timeout));
+        self
+    }
+
+    /// Sets the connect timeout for the call, which bounds the time to get a connection.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.set_connect_timeout(Some(timeout));
+        self
+    }
+
+    /// Sends the call to the address, skipping the discovery and loadbalance.
+    pub fn with_address<A: Into<Address>>(mut self, address: A) -> Self {
+        self.address = Some(address.into());
+        self
+    }
+
+    /// Adds a callee tag for the call, which can be used by the discovery and loadbalance.
+    pub fn with_callee_tag<T: Send + Sync + 'static>(mut self, tag: T) -> Self {
+        self.callee_tags.insert(tag);
+        self
+    }
+
+    /// Sets the priority of the call, so that the overloaded server rejects the low priority
+    /// calls first.
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Skips compressing the request of the call, see [`CallOpt::disable_compression`].
+    pub fn without_compression(mut self) -> Self {
+        self.disable_compression = true;
+        self
+    }
+
+    /// Retries the call by the policy, see [`RetryConfig`].
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = Some(retry);
+        self
+    }
 }
@@ -0,0 +1,220 @@
+//! Per-endpoint circuit breaker.
+//!
+//! The breakers of [`volo::layer::circuit_breaker`] for the thrift clients. The breaker of an
+//! endpoint opens when too many requests to it fail, either by consecutive failures or by the
+//! failure rate in a window, or by the slow calls if set. While open, requests to the endpoint
+//! fail fast by a transport error without being sent. After a cool down, a few probe requests are
+//! let through (half-open), and the breaker closes again if they succeed.
+//!
+//! Only transport errors (including the requests dropped by the rpc timeout) are counted as
+//! failures, since application errors mean the downstream is alive.
+//!
+//! This layer should be added by `layer_inner`, so that the endpoint picked by the load balancer
+//! is known when it runs.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_thrift::client::layer::circuit_breaker::{CircuitBreakerConfig, CircuitBreakerLayer};
+//!
+//! let client = ItemServiceClientBuilder::new("item")
+//!     .layer_inner(CircuitBreakerLayer::new(CircuitBreakerConfig::default()))
+//!     .build();
+//! ```
+
+use futures::Future;
+use motore::{layer::Layer, service::Service};
+pub use volo::layer::circuit_breaker::{BreakerKey, CircuitBreakerConfig, State};
+use volo::{
+    layer::circuit_breaker::{self, BreakerOpen, Classify},
+    loadbalance::Evictions,
+};
+
+use crate::{context::ClientContext, Error};
+
+/// The errors of the inner service and of the open breakers.
+#[derive(Debug)]
+enum BreakerError {
+    Thrift(Error),
+    Open(BreakerOpen),
+}
+
+impl From<BreakerOpen> for BreakerError {
+    fn from(err: BreakerOpen) -> Self {
+        BreakerError::Open(err)
+    }
+}
+
+impl From<BreakerError> for Error {
+    fn from(err: BreakerError) -> Self {
+        match err {
+            BreakerError::Thrift(err) => err,
+            BreakerError::Open(err) => {
+                std::io::Error::new(std::io::ErrorKind::ConnectionRefused, err.to_string()).into()
+            }
+        }
+    }
+}
+
+/// Counts only the transport errors as failures.
+#[derive(Debug, Clone, Copy)]
+struct TransportFailure;
+
+impl Classify<BreakerError> for TransportFailure {
+    fn is_failure(&self, err: &BreakerError) -> bool {
+        matches!(err, BreakerError::Thrift(Error::Transport(_)))
+    }
+}
+
+/// The inner service with the errors the breakers of volo fail fast by.
+#[derive(Clone)]
+struct Inner<S>(S);
+
+impl<Req, S> Service<ClientContext, Req> for Inner<S>
+where
+    Req: 'static + Send,
+    S: Service<ClientContext, Req, Error = Error> + 'static + Send,
+{
+    type Response = S::Response;
+
+    type Error = BreakerError;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut ClientContext, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move { self.0.call(cx, req).await.map_err(BreakerError::Thrift) }
+    }
+}
+
+/// A [`Service`] that fails fast when the breaker of the target endpoint is open.
+#[derive(Clone)]
+pub struct CircuitBreaker<S> {
+    inner: circuit_breaker::CircuitBreaker<Inner<S>, TransportFailure>,
+}
+
+impl<Req, S> Service<ClientContext, Req> for CircuitBreaker<S>
+where
+    Req: 'static + Send,
+    S: Service<ClientContext, Req, Error = Error> + 'static + Send,
+{
+    type Response = S::Response;
+
+    type Error = Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut ClientContext, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move { self.inner.call(cx, req).await.map_err(Into::into) }
+    }
+}
+
+/// A [`Layer`] that applies [`CircuitBreaker`].
+///
+/// All the services made by the same layer share the breakers.
+#[derive(Clone)]
+pub struct CircuitBreakerLayer {
+    inner: circuit_breaker::CircuitBreakerLayer<TransportFailure>,
+}
+
+impl CircuitBreakerLayer {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            inner: circuit_breaker::CircuitBreakerLayer::new(config).classify(TransportFailure),
+        }
+    }
+
+    /// Calls `f` with the endpoint, the old state and the new state when a breaker changes its
+    /// state.
+    pub fn on_state_change(self, f: impl Fn(&str, State, State) + Send + Sync + 'static) -> Self {
+        Self {
+            inner: self.inner.on_state_change(f),
+        }
+    }
+
+    /// Evicts the endpoints from the load balance by `evictions` while their breakers are open,
+    /// see the `evictions` of the client builder.
+    pub fn evictions(self, evictions: Evictions) -> Self {
+        Self {
+            inner: self.inner.evictions(evictions),
+        }
+    }
+
+    /// Returns the state of the breaker of the endpoint, such as `127.0.0.1:8080`.
+    pub fn state(&self, address: &str) -> State {
+        self.inner.state(address)
+    }
+}
+
+impl Default for CircuitBreakerLayer {
+    fn default() -> Self {
+        Self::new(CircuitBreakerConfig::default())
+    }
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer {
+    type Service = CircuitBreaker<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        CircuitBreaker {
+            inner: self.inner.layer(Inner(inner)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use motore::service::service_fn;
+    use pilota::thrift::{ApplicationError, ApplicationErrorKind};
+    use volo::context::{Endpoint, Role, RpcInfo};
+
+    use super::*;
+    use crate::protocol::TMessageType;
+
+    #[tokio::test]
+    async fn test_transport_failures() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let layer = CircuitBreakerLayer::new(CircuitBreakerConfig::new().consecutive_failures(2));
+        let mut service = layer.clone().layer(service_fn({
+            let calls = calls.clone();
+            move |_: &mut ClientContext, transport: bool| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                let err: Error = if transport {
+                    std::io::Error::from(std::io::ErrorKind::BrokenPipe).into()
+                } else {
+                    ApplicationError::new(ApplicationErrorKind::Unknown, "app").into()
+                };
+                async move { Err::<(), _>(err) }
+            }
+        }));
+        let mut cx = ClientContext::new(0, RpcInfo::with_role(Role::Client), TMessageType::Call);
+        let mut callee = Endpoint::new("item".into());
+        callee.set_address(volo::net::Address::Ip(([127, 0, 0, 1], 8080).into()));
+        cx.rpc_info.callee = Some(callee);
+
+        // the application errors are not failures
+        for _ in 0..3 {
+            service.call(&mut cx, false).await.unwrap_err();
+        }
+        assert_eq!(layer.state("127.0.0.1:8080"), State::Closed);
+        for _ in 0..2 {
+            service.call(&mut cx, true).await.unwrap_err();
+        }
+        assert_eq!(layer.state("127.0.0.1:8080"), State::Open);
+
+        // fails fast by a transport error
+        let err = service.call(&mut cx, false).await.unwrap_err();
+        assert!(matches!(err, Error::Transport(_)));
+        assert_eq!(calls.load(Ordering::Relaxed), 5);
+    }
+}
@@ -0,0 +1,259 @@
+//! Retries the failed calls by the [`RetryConfig`] of their [`CallOpt`](crate::client::CallOpt).
+//!
+//! The retry layer is right inside the rpc timeout, which bounds all the attempts, and outside
+//! the outer layers and the load balance, so each retry goes through them again and picks an
+//! instance again, the ones tried by the previous attempts last, unless the call is to a fixed
+//! address.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_thrift::{client::{layer::retry::RetryConfig, CallOpt}, ApplicationErrorKind};
+//!
+//! let retry = RetryConfig::new()
+//!     .max_attempts(3)
+//!     .retryable_kinds([ApplicationErrorKind::InternalError])
+//!     .attempt_timeout(Duration::from_millis(200));
+//! let resp = CLIENT
+//!     .clone()
+//!     .with_callopt(CallOpt::new().with_retry(retry))
+//!     .get_item(req)
+//!     .await;
+//! ```
+
+use std::time::Duration;
+
+use futures::Future;
+use motore::{layer::Layer, service::Service, BoxError};
+use volo::{
+    context::Context,
+    layer::{
+        retry::{self, Backoff, RetryPolicy},
+        timeout::Elapsed,
+    },
+};
+
+use crate::{context::ClientContext, layer::load_shed::is_overloaded, ApplicationErrorKind, Error};
+
+const DEFAULT_MAX_ATTEMPTS: usize = 3;
+const DEFAULT_BACKOFF_BASE: Duration = Duration::from_millis(20);
+const DEFAULT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// The retry policy of a call.
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    max_attempts: usize,
+    transport_errors: bool,
+    retryable_kinds: Vec<ApplicationErrorKind>,
+    backoff: Backoff,
+    attempt_timeout: Option<Duration>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RetryConfig {
+    /// Creates a [`RetryConfig`] of 3 attempts on the transport errors and the exceptions of the
+    /// overloaded servers, see [`is_overloaded`], with the backoff from 20ms up to 1s.
+    pub fn new() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            transport_errors: true,
+            retryable_kinds: Vec::new(),
+            backoff: Backoff::new(DEFAULT_BACKOFF_BASE, DEFAULT_BACKOFF_MAX),
+            attempt_timeout: None,
+        }
+    }
+
+    /// Sets the max attempts of the call, including the first one.
+    ///
+    /// Defaults to 3.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sets whether the transport errors are retried, such as the connections refused or reset.
+    ///
+    /// Defaults to true.
+    pub fn transport_errors(mut self, enabled: bool) -> Self {
+        self.transport_errors = enabled;
+        self
+    }
+
+    /// Sets the kinds of the application exceptions which are retried, besides the ones of the
+    /// overloaded servers.
+    ///
+    /// Defaults to none.
+    pub fn retryable_kinds(
+        mut self,
+        kinds: impl IntoIterator<Item = ApplicationErrorKind>,
+    ) -> Self {
+        self.retryable_kinds = kinds.into_iter().collect();
+        self
+    }
+
+    /// Sets the backoff between the attempts.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Sets the timeout of each attempt, the attempts timed out are always retried.
+    ///
+    /// Defaults to no timeout.
+    pub fn attempt_timeout(mut self, timeout: Duration) -> Self {
+        self.attempt_timeout = Some(timeout);
+        self
+    }
+
+    fn retryable(&self, err: &BoxError) -> bool {
+        let err = match err.downcast_ref::<Error>() {
+            Some(err) => err,
+            None => return false,
+        };
+        match err {
+            Error::Transport(_) => self.transport_errors,
+            Error::Application(e) => self.retryable_kinds.contains(&e.kind) || is_overloaded(err),
+            _ => false,
+        }
+    }
+}
+
+/// The errors of the attempts, the ones timed out are transport errors.
+struct AttemptError(BoxError);
+
+impl From<Elapsed> for AttemptError {
+    fn from(_: Elapsed) -> Self {
+        AttemptError(std::io::Error::new(std::io::ErrorKind::TimedOut, "attempt time out").into())
+    }
+}
+
+/// The inner service with the errors of the attempts.
+#[derive(Clone)]
+struct Attempts<S>(S);
+
+impl<Req, S> Service<ClientContext, Req> for Attempts<S>
+where
+    Req: Send + 'static,
+    S: Service<ClientContext, Req> + Send + 'static,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+
+    type Error = AttemptError;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut ClientContext, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            self.0
+                .call(cx, req)
+                .await
+                .map_err(|err| AttemptError(err.into()))
+        }
+    }
+}
+
+/// The [`RetryPolicy`] of a call by its [`RetryConfig`].
+struct CallPolicy(RetryConfig);
+
+impl<Req: Clone, Resp> RetryPolicy<ClientContext, Req, Resp, AttemptError> for CallPolicy {
+    fn retryable(&self, _cx: &ClientContext, err: &AttemptError) -> bool {
+        self.0.retryable(&err.0)
+    }
+
+    fn backoff(&self, attempt: usize) -> Duration {
+        self.0.backoff.delay(attempt)
+    }
+
+    fn clone_request(&self, req: &Req) -> Option<Req> {
+        Some(req.clone())
+    }
+
+    fn attempt_timeout(&self) -> Option<Duration> {
+        self.0.attempt_timeout
+    }
+}
+
+/// The [`Service`] retrying the calls of a [`RetryConfig`] in the extensions of the context, by
+/// the retries of [`volo::layer::retry`].
+#[derive(Clone)]
+pub struct Retry<S> {
+    inner: Attempts<S>,
+}
+
+impl<Req, S> Service<ClientContext, Req> for Retry<S>
+where
+    Req: Clone + Send + 'static,
+    S: Service<ClientContext, Req> + Send + 'static,
+    S::Response: Send,
+    S::Error: Send + Sync + Into<BoxError>,
+{
+    type Response = S::Response;
+
+    type Error = BoxError;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut ClientContext, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let config = match cx.extensions_mut().remove::<RetryConfig>() {
+                Some(config) => config,
+                None => return self.inner.0.call(cx, req).await.map_err(Into::into),
+            };
+            let max_retries = config.max_attempts - 1;
+            let policy = CallPolicy(config);
+            retry::retry(&mut self.inner, &policy, max_retries, None, cx, req)
+                .await
+                .map_err(|err| err.0)
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`Retry`].
+#[derive(Clone, Default, Copy)]
+pub struct RetryLayer;
+
+impl RetryLayer {
+    pub fn new() -> Self {
+        RetryLayer
+    }
+}
+
+impl<S> Layer<S> for RetryLayer {
+    type Service = Retry<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        Retry {
+            inner: Attempts(inner),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{layer::load_shed::OVERLOADED, ApplicationError};
+
+    #[test]
+    fn test_retryable() {
+        let config = RetryConfig::new().retryable_kinds([ApplicationErrorKind::Unknown]);
+        let app = |kind, message: &str| -> BoxError {
+            Error::Application(ApplicationError::new(kind, message.to_string())).into()
+        };
+        assert!(config.retryable(&app(ApplicationErrorKind::Unknown, "")));
+        assert!(config.retryable(&app(ApplicationErrorKind::InternalError, OVERLOADED)));
+        assert!(!config.retryable(&app(ApplicationErrorKind::InternalError, "")));
+        assert!(!config.retryable(&"not a thrift error".into()));
+    }
+}
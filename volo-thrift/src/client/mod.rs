@@ -4,9 +4,13 @@
 //! Instead, they should use the `Builder` type in the generated code.
 //!
 //! For users need to specify some options at call time, they may use ['callopt'][callopt].
+//!
+//! For the calls to be retried on the failures, they may set a
+//! [`RetryConfig`](layer::retry::RetryConfig) to the [`CallOpt`].
 
 use std::{
     cell::RefCell,
+    io,
     marker::PhantomData,
     sync::{atomic::AtomicI32, Arc},
 };
@@ -18,30 +22,39 @@ use motore::{
     BoxError,
 };
 use pilota::thrift::{EntryMessage, TMessageType};
-use tokio::time::Duration;
+use tokio::{sync::watch, time::Duration};
 use volo::{
     context::{Context, Endpoint, Role, RpcInfo},
     discovery::{Discover, DummyDiscover},
-    loadbalance::{random::WeightedRandomBalance, LbConfig, MkLbLayer},
-    net::{dial::MakeConnection, Address},
+    loadbalance::{random::WeightedRandomBalance, Evictions, LbConfig, MkLbLayer},
+    net::{
+        conn::DynStream,
+        dial::{connector, Dialer, MakeConnection},
+        Address,
+    },
+    profile::{Profile, Profiles},
+    rt::Runtime,
 };
 
 use crate::{
     codec::{
-        tt_header::DefaultTTHeaderCodec, CodecType, MakeClientDecoder, MakeClientEncoder,
-        MkDecoder, MkEncoder,
+        tt_header::DefaultTTHeaderCodec, unknown_fields::DecodeMode, CodecType, MakeClientDecoder,
+        MakeClientEncoder, MkDecoder, MkEncoder, Protocol,
     },
     context::{ClientContext, Config},
     error::{Error, Result},
-    tags::TransportType,
-    transport::{pingpong, pool},
+    tags::{Compression, PayloadChecksum, TransportType},
+    transport::{multiplex, pingpong, pool},
     Size, ThriftMessage,
 };
 
 mod callopt;
 pub use callopt::CallOpt;
 
-use self::layer::timeout::TimeoutLayer;
+use self::layer::{
+    retry::{RetryConfig, RetryLayer},
+    timeout::TimeoutLayer,
+};
 
 pub mod layer;
 
@@ -54,15 +67,26 @@ pub trait SetClient<Req, Resp> {
 
 pub struct ClientBuilder<IL, OL, C, Req, Resp, MkE, MkD, LB> {
     config: Config,
+    profiles: Option<watch::Receiver<Arc<Profiles>>>,
     pool: Option<pool::Config>,
+    runtime: Runtime,
     callee_name: smol_str::SmolStr,
     caller_name: smol_str::SmolStr,
     address: Option<Address>, // maybe address use Arc avoid memory alloc
     inner_layer: IL,
     outer_layer: OL,
     codec_type: CodecType,
+    codec_fallbacks: Vec<CodecType>,
     service_client: C,
-    pipeline: bool,
+    multiplex: Option<usize>,
+    multiplex_max_in_flight: Option<usize>,
+    multiplex_health_check: Option<Duration>,
+    mesh_proxy: Option<Address>,
+    compression: Option<Compression>,
+    multiplexed_service: Option<smol_str::SmolStr>,
+    #[cfg(feature = "rustls")]
+    tls: Option<volo::net::tls::TlsConnector>,
+    dialer: Option<Arc<dyn Dialer>>,
     mk_encoder: MkE,
     mk_decoder: MkD,
     mk_lb: LB,
@@ -86,15 +110,26 @@ where
     pub fn new(service_name: impl AsRef<str>, service_client: C) -> Self {
         ClientBuilder {
             config: Default::default(),
+            profiles: None,
             pool: None,
+            runtime: Runtime::default(),
             caller_name: "".into(),
             callee_name: service_name.into(),
             address: None,
             inner_layer: Identity::new(),
             outer_layer: Identity::new(),
             codec_type: CodecType::TTHeaderFramed,
+            codec_fallbacks: Vec::new(),
             service_client,
-            pipeline: false,
+            multiplex: None,
+            multiplex_max_in_flight: None,
+            multiplex_health_check: None,
+            mesh_proxy: None,
+            compression: None,
+            multiplexed_service: None,
+            #[cfg(feature = "rustls")]
+            tls: None,
+            dialer: None,
             mk_encoder: MakeClientEncoder {
                 tt_encoder: DefaultTTHeaderCodec,
             },
@@ -117,20 +152,8 @@ where
         load_balance: NLB,
     ) -> ClientBuilder<IL, OL, C, Req, Resp, E, D, LbConfig<NLB, DISC>> {
         ClientBuilder {
-            config: self.config,
-            pool: self.pool,
-            caller_name: self.caller_name,
-            callee_name: self.callee_name,
-            address: self.address,
-            codec_type: self.codec_type,
-            inner_layer: self.inner_layer,
-            outer_layer: self.outer_layer,
-            service_client: self.service_client,
-            pipeline: self.pipeline,
-            _marker: PhantomData,
-            mk_encoder: self.mk_encoder,
-            mk_decoder: self.mk_decoder,
             mk_lb: self.mk_lb.load_balance(load_balance),
+            ..self
         }
     }
 
@@ -139,20 +162,8 @@ where
         discover: NDISC,
     ) -> ClientBuilder<IL, OL, C, Req, Resp, E, D, LbConfig<LB, NDISC>> {
         ClientBuilder {
-            config: self.config,
-            pool: self.pool,
-            caller_name: self.caller_name,
-            callee_name: self.callee_name,
-            address: self.address,
-            codec_type: self.codec_type,
-            inner_layer: self.inner_layer,
-            outer_layer: self.outer_layer,
-            service_client: self.service_client,
-            pipeline: self.pipeline,
-            _marker: PhantomData,
-            mk_encoder: self.mk_encoder,
-            mk_decoder: self.mk_decoder,
             mk_lb: self.mk_lb.discover(discover),
+            ..self
         }
     }
 
@@ -161,6 +172,65 @@ where
         self.mk_lb = self.mk_lb.retry_count(count);
         self
     }
+
+    /// Picks the instances evicted by `evictions` last, such as by the circuit breakers of the
+    /// endpoints added by [`layer_inner`](Self::layer_inner).
+    pub fn evictions(mut self, evictions: Evictions) -> Self {
+        self.mk_lb = self.mk_lb.evictions(evictions);
+        self
+    }
+
+    /// Applies the profile of the callee in the profiles, such as loaded from a config file.
+    ///
+    /// The fields unset in the profile keep the settings made before, and the settings made after
+    /// override the profile.
+    pub fn profiles(mut self, profiles: &Profiles) -> Self {
+        let profile = profiles.get(&self.callee_name);
+        if let Some(timeout) = profile.rpc_timeout() {
+            self.config.set_rpc_timeout(timeout);
+        }
+        if let Some(timeout) = profile.connect_timeout() {
+            self.config.set_connect_timeout(Some(timeout));
+        }
+        if let Some(timeout) = profile.read_write_timeout() {
+            self.config.set_read_write_timeout(Some(timeout));
+        }
+        if let Some(count) = profile.retry_count {
+            self = self.retry_count(count);
+        }
+        if profile.max_idle_conns.is_some()
+            || profile.min_idle_conns.is_some()
+            || profile.max_conns.is_some()
+        {
+            let mut pool = self.pool.take().unwrap_or_default();
+            if let Some(n) = profile.max_idle_conns {
+                pool = pool.max_idle_per_key(n);
+            }
+            if let Some(n) = profile.min_idle_conns {
+                pool = pool.min_idle_per_key(n);
+            }
+            if let Some(n) = profile.max_conns {
+                pool = pool.max_conns_per_key(n);
+            }
+            self.pool = Some(pool);
+        }
+        self
+    }
+
+    /// Applies the profile of the callee in the profiles from the receiver to every call, such as
+    /// reloaded by `volo::profile::FileWatcher`, so that the changes apply to the calls made after
+    /// them without rebuilding the client.
+    ///
+    /// The rpc timeout and the connect timeout of the profile override the ones of the client,
+    /// and are overridden by the ones of the [`CallOpt`]. The retry count retries the calls
+    /// without a [`RetryConfig`] of their own by the retry layer, see [`layer::retry`]. The
+    /// load balance and the cluster are switched by the `ProfileBalance` and the
+    /// `ProfileDiscover` of volo, see `volo::profile`. The rest of the profile, such as the pool
+    /// sizes, is only applied by [`profiles`](Self::profiles) when the client is built.
+    pub fn watch_profiles(mut self, profiles: watch::Receiver<Arc<Profiles>>) -> Self {
+        self.profiles = Some(profiles);
+        self
+    }
 }
 
 impl<IL, OL, C, Req, Resp, E, D, LB> ClientBuilder<IL, OL, C, Req, Resp, E, D, LB>
@@ -178,12 +248,25 @@ where
     }
 
     /// Sets the config for connection pool.
+    ///
+    /// Use [`pool::Config::stats`] to observe the pool.
     pub fn pool_config(mut self, config: pool::Config) -> Self {
         self.pool = Some(config);
         self
     }
 
+    /// Sets the runtime to spawn the background tasks of the connection pool, such as making the
+    /// idle connections.
+    ///
+    /// Defaults to the tokio runtime.
+    pub fn runtime(mut self, runtime: Runtime) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
     /// Sets the connect timeout for the client.
+    ///
+    /// This also bounds the time waiting for a connection from the pool.
     pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
         self.config.set_connect_timeout(timeout);
         self
@@ -195,7 +278,7 @@ where
         self
     }
 
-    /// Sets the max frame size for the client.
+    /// Sets the max frame size for the client, larger responses are rejected before being read.
     ///
     /// Defaults to 16MB.
     pub fn max_frame_size(mut self, max_frame_size: u32) -> Self {
@@ -203,6 +286,135 @@ where
         self
     }
 
+    /// Sets how to decode the responses with fields unknown to the local IDL, see
+    /// [`unknown_fields`](crate::codec::unknown_fields).
+    ///
+    /// Defaults to [`DecodeMode::Lenient`].
+    pub fn decode_mode(mut self, decode_mode: DecodeMode) -> Self {
+        self.config.set_decode_mode(decode_mode);
+        self
+    }
+
+    /// Sets the service name to prefix the method name with, as `ServiceName:methodName`.
+    ///
+    /// This is needed when calling an Apache Thrift server which serves multiple services on
+    /// one port with `TMultiplexedProcessor`.
+    pub fn multiplexed_service(mut self, name: impl AsRef<str>) -> Self {
+        self.multiplexed_service = Some(name.into());
+        self
+    }
+
+    /// Enables connection multiplexing, so that concurrent requests share `connections`
+    /// connections to each endpoint and the responses are matched by seq_id.
+    ///
+    /// The pool config is not used when multiplexing is enabled.
+    pub fn multiplex(mut self, connections: usize) -> Self {
+        self.multiplex = Some(connections);
+        self
+    }
+
+    /// Limits the requests in flight on each multiplexed connection, the requests beyond the
+    /// limit of all the connections to an endpoint spill over to an additional connection, so a
+    /// high QPS caller isn't serialized by the flow control of a few connections.
+    ///
+    /// Default is no limit, this only applies when [`multiplex`](Self::multiplex) is enabled.
+    pub fn multiplex_max_in_flight(mut self, max: usize) -> Self {
+        self.multiplex_max_in_flight = Some(max);
+        self
+    }
+
+    /// Closes the multiplexed connections where the requests wait for `timeout` without any
+    /// response arriving, such as the half-open ones, failing the requests in flight on them. The
+    /// timeout should be longer than the slowest requests.
+    ///
+    /// Default is no health check, this only applies when [`multiplex`](Self::multiplex) is
+    /// enabled.
+    pub fn multiplex_health_check(mut self, timeout: Duration) -> Self {
+        self.multiplex_health_check = Some(timeout);
+        self
+    }
+
+    /// Sends all the requests to a local mesh proxy, which forwards them to the callee.
+    ///
+    /// The callee service name and address are carried by the `ToService` and `DestAddress`
+    /// TTHeader keys, so a TTHeader codec type must be used. The callee address is still
+    /// resolved by [`address`](Self::address) or the discover, and only used as the
+    /// destination for the proxy.
+    pub fn mesh_proxy<A: Into<Address>>(mut self, proxy: A) -> Self {
+        self.mesh_proxy = Some(proxy.into());
+        self
+    }
+
+    /// Compresses the requests of at least `threshold` bytes with zlib, the server will compress
+    /// the responses too if it supports it.
+    ///
+    /// This only works with the TTHeader codec types.
+    pub fn compression(mut self, threshold: usize) -> Self {
+        self.compression = Some(Compression { threshold });
+        self
+    }
+
+    /// Checksums the payloads of the requests with CRC32C, the server will checksum the
+    /// responses too if it supports it.
+    ///
+    /// This only works with the TTHeader codec types.
+    pub fn payload_checksum(mut self, enable: bool) -> Self {
+        self.config.set_payload_checksum(enable);
+        self
+    }
+
+    /// Sets whether to verify the checksums of the responses, the responses of mismatched
+    /// checksums are rejected as protocol errors.
+    ///
+    /// Defaults to true.
+    pub fn verify_checksum(mut self, enable: bool) -> Self {
+        self.config.set_verify_checksum(enable);
+        self
+    }
+
+    /// Sets the protocol to encode the requests, such as [`Protocol::Compact`] for the servers
+    /// defaulting to `TCompactProtocol`. The responses are decoded by the protocol they are
+    /// encoded with.
+    ///
+    /// The compact protocol only works with the framed and the TTHeader codec types, and the
+    /// generic [`Binary`](crate::generic::Binary) payloads are always of the binary protocol.
+    ///
+    /// Defaults to [`Protocol::Binary`].
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.config.set_protocol(protocol);
+        self
+    }
+
+    /// Enables TLS for the connections to the server.
+    #[cfg(feature = "rustls")]
+    pub fn tls_config(mut self, tls: volo::net::tls::TlsConnector) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Makes the connections to the server by the dialer, such as through a tunnel, instead of
+    /// dialing the address.
+    ///
+    /// The TLS config is ignored when the dialer is set, which should wrap the TLS itself.
+    pub fn dialer(mut self, dialer: impl Dialer) -> Self {
+        self.dialer = Some(Arc::new(dialer));
+        self
+    }
+
+    /// Makes the connections to the server by `f`, which returns a stream of any
+    /// `AsyncRead + AsyncWrite` to the address, such as of a custom tunnel, see
+    /// [`connector`](volo::net::dial::connector).
+    ///
+    /// It's a shorthand of the [`dialer`](Self::dialer) of the streams.
+    pub fn connector<F, Fut, IO>(self, f: F) -> Self
+    where
+        F: Fn(Address) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<IO>> + Send + 'static,
+        IO: DynStream,
+    {
+        self.dialer(connector(f))
+    }
+
     /// Sets the client's name sent to the server.
     pub fn caller_name(mut self, name: impl AsRef<str>) -> Self {
         self.caller_name = name.into();
@@ -214,20 +426,8 @@ where
         mk_load_balance: NLB,
     ) -> ClientBuilder<IL, OL, C, Req, Resp, E, D, NLB> {
         ClientBuilder {
-            config: self.config,
-            pool: self.pool,
-            caller_name: self.caller_name,
-            callee_name: self.callee_name,
-            address: self.address,
-            codec_type: self.codec_type,
-            inner_layer: self.inner_layer,
-            outer_layer: self.outer_layer,
-            service_client: self.service_client,
-            pipeline: self.pipeline,
-            _marker: PhantomData,
-            mk_encoder: self.mk_encoder,
-            mk_decoder: self.mk_decoder,
             mk_lb: mk_load_balance,
+            ..self
         }
     }
 
@@ -245,20 +445,8 @@ where
         tt_encoder: TTEncoder,
     ) -> ClientBuilder<IL, OL, C, Req, Resp, MakeClientEncoder<TTEncoder>, D, LB> {
         ClientBuilder {
-            config: self.config,
-            pool: self.pool,
-            caller_name: self.caller_name,
-            callee_name: self.callee_name,
-            address: self.address,
-            codec_type: self.codec_type,
-            inner_layer: self.inner_layer,
-            outer_layer: self.outer_layer,
-            service_client: self.service_client,
-            pipeline: self.pipeline,
-            _marker: PhantomData,
             mk_encoder: MakeClientEncoder { tt_encoder },
-            mk_decoder: self.mk_decoder,
-            mk_lb: self.mk_lb,
+            ..self
         }
     }
 
@@ -276,20 +464,8 @@ where
         tt_decoder: TTDecoder,
     ) -> ClientBuilder<IL, OL, C, Req, Resp, E, MakeClientDecoder<TTDecoder>, LB> {
         ClientBuilder {
-            config: self.config,
-            pool: self.pool,
-            caller_name: self.caller_name,
-            callee_name: self.callee_name,
-            address: self.address,
-            codec_type: self.codec_type,
-            inner_layer: self.inner_layer,
-            outer_layer: self.outer_layer,
-            service_client: self.service_client,
-            pipeline: self.pipeline,
-            _marker: PhantomData,
-            mk_encoder: self.mk_encoder,
             mk_decoder: MakeClientDecoder { tt_decoder },
-            mk_lb: self.mk_lb,
+            ..self
         }
     }
 
@@ -305,7 +481,11 @@ where
 
     /// Sets the codec type used for the client.
     ///
-    /// Most users don't need to change this.
+    /// Most users don't need to change this, except for calling the legacy Apache Thrift servers
+    /// with `CodecType::Framed`, or `CodecType::Buffered` for the ones using `TBufferedTransport`
+    /// or `TSocket` directly, which read the messages from the connection without a length.
+    /// The messages are always sent with the strict binary protocol, which is accepted by such
+    /// servers by default.
     ///
     /// Defaults to `CodecType::TTHeaderFramed`.
     pub fn codec_type(mut self, t: CodecType) -> Self {
@@ -313,6 +493,26 @@ where
         self
     }
 
+    /// Sets the codec types to try in order for each endpoint, the first one is the same as
+    /// [`codec_type`](Self::codec_type).
+    ///
+    /// When a call to an endpoint fails before any call succeeds with the codec type, it is sent
+    /// again with the next codec type, which is then kept for the endpoint. So a client can call
+    /// both the new servers with `[TTHeaderFramed, Framed, Buffered]` and the legacy ones
+    /// understanding only the latter.
+    ///
+    /// This is ignored by the multiplex transport, which always uses the first one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `types` is empty.
+    pub fn codec_types(mut self, types: impl IntoIterator<Item = CodecType>) -> Self {
+        let mut types = types.into_iter();
+        self.codec_type = types.next().expect("at least one codec type is required");
+        self.codec_fallbacks = types.collect();
+        self
+    }
+
     /// Adds a new inner layer to the client.
     ///
     /// The layer's `Service` should be `Send + Clone + 'static`.
@@ -325,26 +525,15 @@ where
     ///
     /// After we call `.layer(baz)`, we will get: foo -> bar -> baz.
     ///
-    /// The overall order for layers is: Timeout -> outer -> LoadBalance -> [inner] -> transport.
+    /// The overall order for layers is: Timeout -> Retry -> outer -> LoadBalance -> [inner] ->
+    /// transport.
     pub fn layer_inner<Inner>(
         self,
         layer: Inner,
     ) -> ClientBuilder<Stack<Inner, IL>, OL, C, Req, Resp, E, D, LB> {
         ClientBuilder {
-            config: self.config,
-            pool: self.pool,
-            caller_name: self.caller_name,
-            callee_name: self.callee_name,
-            address: self.address,
-            codec_type: self.codec_type,
             inner_layer: Stack::new(layer, self.inner_layer),
-            outer_layer: self.outer_layer,
-            service_client: self.service_client,
-            pipeline: self.pipeline,
-            _marker: PhantomData,
-            mk_encoder: self.mk_encoder,
-            mk_decoder: self.mk_decoder,
-            mk_lb: self.mk_lb,
+            ..self
         }
     }
 
@@ -360,26 +549,15 @@ where
     ///
     /// After we call `.layer_outer(baz)`, we will get: foo -> bar -> baz.
     ///
-    /// The overall order for layers is: Timeout -> [outer] -> LoadBalance -> inner -> transport.
+    /// The overall order for layers is: Timeout -> Retry -> [outer] -> LoadBalance -> inner ->
+    /// transport.
     pub fn layer_outer<Outer>(
         self,
         layer: Outer,
     ) -> ClientBuilder<IL, Stack<Outer, OL>, C, Req, Resp, E, D, LB> {
         ClientBuilder {
-            config: self.config,
-            pool: self.pool,
-            caller_name: self.caller_name,
-            callee_name: self.callee_name,
-            address: self.address,
-            codec_type: self.codec_type,
-            inner_layer: self.inner_layer,
             outer_layer: Stack::new(layer, self.outer_layer),
-            service_client: self.service_client,
-            pipeline: self.pipeline,
-            _marker: PhantomData,
-            mk_encoder: self.mk_encoder,
-            mk_decoder: self.mk_decoder,
-            mk_lb: self.mk_lb,
+            ..self
         }
     }
 
@@ -395,41 +573,36 @@ where
     ///
     /// After we call `.layer_outer(baz)`, we will get: baz -> foo -> bar.
     ///
-    /// The overall order for layers is: Timeout -> [outer] -> LoadBalance -> inner -> transport.
+    /// The overall order for layers is: Timeout -> Retry -> [outer] -> LoadBalance -> inner ->
+    /// transport.
     pub fn layer_outer_front<Outer>(
         self,
         layer: Outer,
     ) -> ClientBuilder<IL, Stack<OL, Outer>, C, Req, Resp, E, D, LB> {
         ClientBuilder {
-            config: self.config,
-            pool: self.pool,
-            caller_name: self.caller_name,
-            callee_name: self.callee_name,
-            address: self.address,
-            codec_type: self.codec_type,
-            inner_layer: self.inner_layer,
             outer_layer: Stack::new(self.outer_layer, layer),
-            service_client: self.service_client,
-            pipeline: self.pipeline,
-            _marker: PhantomData,
-            mk_encoder: self.mk_encoder,
-            mk_decoder: self.mk_decoder,
-            mk_lb: self.mk_lb,
+            ..self
         }
     }
 }
 
+#[derive(Clone)]
+enum Transport<Resp, MkE: MkEncoder + 'static, MkD: MkDecoder + 'static> {
+    PingPong(pingpong::Client<Resp, MkE, MkD>),
+    Multiplex(multiplex::Client<Resp, MkE, MkD>),
+}
+
 #[derive(Clone)]
 pub struct MessageService<Resp, MkE: MkEncoder + 'static, MkD: MkDecoder + 'static> {
-    inner: pingpong::Client<Resp, MkE, MkD>,
+    inner: Transport<Resp, MkE, MkD>,
 }
 
 impl<Req, Resp, MkE, MkD> Service<ClientContext, Req> for MessageService<Resp, MkE, MkD>
 where
     MkE: MkEncoder + 'static,
     MkD: MkDecoder + 'static,
-    Req: EntryMessage + Size + 'static + Send,
-    Resp: Send + 'static + EntryMessage,
+    Req: EntryMessage + Size + 'static + Send + Clone,
+    Resp: Send + 'static + EntryMessage + Size,
 {
     type Response = Option<Resp>;
 
@@ -443,7 +616,10 @@ where
     {
         async move {
             let msg = ThriftMessage::mk_client_msg(cx, Ok(req))?;
-            let resp = self.inner.call(cx, msg).await;
+            let resp = match &mut self.inner {
+                Transport::PingPong(client) => client.call(cx, msg).await,
+                Transport::Multiplex(client) => client.call(cx, msg).await,
+            };
             match resp {
                 Ok(Some(ThriftMessage { data: Ok(data), .. })) => Ok(Some(data)),
                 Ok(Some(ThriftMessage { data: Err(e), .. })) => Err(e),
@@ -465,7 +641,7 @@ where
     <<LB::Layer as Layer<IL::Service>>::Service as Service<ClientContext, Req>>::Error:
         Into<BoxError>,
     Req: EntryMessage + Send + 'static + Size + Sync + Clone,
-    Resp: EntryMessage + Send + 'static,
+    Resp: EntryMessage + Size + Send + 'static,
     IL: Layer<MessageService<Resp, MkE, MkD>>,
     OL: Layer<
         BoxCloneService<
@@ -485,20 +661,63 @@ where
             self.config.read_write_timeout(),
         );
 
-        let transport = TimeoutLayer::new().layer(
-            self.outer_layer
-                .layer(BoxCloneService::new(self.mk_lb.make().layer(
-                    self.inner_layer.layer(MessageService {
-                        inner: pingpong::Client::new(
-                            MakeConnection::new(Some(mc_cfg)),
-                            self.codec_type,
-                            self.pool,
-                            self.mk_encoder,
-                            self.mk_decoder,
-                        ),
-                    }),
-                ))),
-        );
+        let mut make_connection = MakeConnection::new(Some(mc_cfg));
+        #[cfg(feature = "rustls")]
+        if let Some(tls) = self.tls {
+            make_connection = make_connection.with_tls(tls);
+        }
+        if let Some(dialer) = self.dialer {
+            make_connection = make_connection.with_shared_dialer(dialer);
+        }
+
+        let removed = self.mk_lb.removed_instances();
+        let warm_up = self
+            .pool
+            .as_ref()
+            .map(pool::Config::warm_up_connections)
+            .unwrap_or(0);
+        // the endpoints from the service discovery are unknown until the first call
+        let warm_up_targets: Vec<_> = self
+            .mesh_proxy
+            .clone()
+            .or_else(|| self.address.clone())
+            .into_iter()
+            .collect();
+        let inner = match self.multiplex {
+            Some(connections) => Transport::Multiplex(
+                multiplex::Client::new(
+                    make_connection,
+                    self.codec_type,
+                    connections,
+                    self.mk_encoder,
+                    self.mk_decoder,
+                )
+                .with_proxy(self.mesh_proxy)
+                .with_max_in_flight(self.multiplex_max_in_flight)
+                .with_health_check(self.multiplex_health_check)
+                .with_removed_instances(removed),
+            ),
+            None => Transport::PingPong(
+                pingpong::Client::new(
+                    make_connection,
+                    self.codec_type,
+                    Some(self.pool.unwrap_or_default().with_runtime(self.runtime)),
+                    self.mk_encoder,
+                    self.mk_decoder,
+                )
+                .with_proxy(self.mesh_proxy)
+                .with_codec_fallbacks(self.codec_fallbacks)
+                .with_removed_instances(removed)
+                .with_warm_up(warm_up_targets, warm_up),
+            ),
+        };
+
+        let transport = self.outer_layer.layer(BoxCloneService::new(
+            self.mk_lb
+                .make()
+                .layer(self.inner_layer.layer(MessageService { inner })),
+        ));
+        let transport = TimeoutLayer::new().layer(RetryLayer::new().layer(transport));
 
         let transport = transport.map_err(Into::<crate::Error>::into);
         let transport = BoxCloneService::new(transport);
@@ -507,12 +726,23 @@ where
             inner: Arc::new(ClientInner {
                 callee_name: self.callee_name,
                 config: self.config,
+                profiles: self.profiles,
                 address: self.address,
                 caller_name: self.caller_name,
+                multiplexed_service: self.multiplexed_service,
+                compression: self.compression,
+                transport_type: if self.codec_type.is_framed() {
+                    TransportType::TRANSPORT_FRAMED
+                } else {
+                    TransportType::TRANSPORT_UNFRAMED
+                },
                 seq_id: AtomicI32::new(0),
             }),
             callopt: None,
+            #[cfg(not(feature = "forbid-unsafe"))]
             transport,
+            #[cfg(feature = "forbid-unsafe")]
+            transport: std::sync::Mutex::new(transport),
         })
     }
 }
@@ -523,17 +753,25 @@ where
 /// One important thing is that the `CallOpt` will not be cloned, because
 /// it's designed to be per-request.
 pub struct Client<Req, Resp> {
+    #[cfg(not(feature = "forbid-unsafe"))]
     transport: BoxCloneService<ClientContext, Req, Option<Resp>, Error>,
+    // the boxed service is not `Sync`, the mutex is only locked when the client is cloned
+    #[cfg(feature = "forbid-unsafe")]
+    transport: std::sync::Mutex<BoxCloneService<ClientContext, Req, Option<Resp>, Error>>,
     callopt: Option<CallOpt>,
     inner: Arc<ClientInner>,
 }
 
+#[cfg(not(feature = "forbid-unsafe"))]
 unsafe impl<Req, Resp> Sync for Client<Req, Resp> {}
 
 impl<Req, Resp> Clone for Client<Req, Resp> {
     fn clone(&self) -> Self {
         Self {
+            #[cfg(not(feature = "forbid-unsafe"))]
             transport: self.transport.clone(),
+            #[cfg(feature = "forbid-unsafe")]
+            transport: std::sync::Mutex::new(self.transport.lock().unwrap().clone()),
             callopt: None,
             inner: self.inner.clone(),
         }
@@ -551,22 +789,45 @@ struct ClientInner {
     callee_name: smol_str::SmolStr,
     caller_name: smol_str::SmolStr,
     config: Config,
+    profiles: Option<watch::Receiver<Arc<Profiles>>>,
     address: Option<Address>,
+    multiplexed_service: Option<smol_str::SmolStr>,
+    compression: Option<Compression>,
+    transport_type: TransportType,
     seq_id: AtomicI32,
 }
 
 impl<Req, Resp> Client<Req, Resp> {
     pub async fn call(
         &mut self,
-        method: &'static str,
+        method: impl Into<smol_str::SmolStr>,
         req: Req,
         oneway: bool,
     ) -> Result<Option<Resp>, Error> {
+        let profile = self
+            .inner
+            .profiles
+            .as_ref()
+            .map(|profiles| profiles.borrow().get(&self.inner.callee_name));
+        let priority = self.callopt.as_ref().and_then(|co| co.priority);
+        let retry = self
+            .callopt
+            .as_mut()
+            .and_then(|co| co.retry.take())
+            .or_else(|| {
+                let count = profile.as_ref()?.retry_count.filter(|count| *count > 0)?;
+                Some(RetryConfig::new().max_attempts(count + 1))
+            });
+        let disable_compression = self
+            .callopt
+            .as_ref()
+            .map_or(false, |co| co.disable_compression);
+        let compression = self.inner.compression.filter(|_| !disable_compression);
         let mut cx = ClientContext::new(
             self.inner
                 .seq_id
                 .fetch_add(1, std::sync::atomic::Ordering::Relaxed),
-            self.make_rpc_info(method),
+            self.make_rpc_info(method.into(), profile.as_ref()),
             if oneway {
                 TMessageType::OneWay
             } else {
@@ -574,11 +835,31 @@ impl<Req, Resp> Client<Req, Resp> {
             },
         );
 
-        cx.extensions_mut().insert(TransportType::TRANSPORT_FRAMED);
+        cx.extensions_mut().insert(self.inner.transport_type);
+        cx.multiplexed_service = self.inner.multiplexed_service.clone();
+        if let Some(compression) = compression {
+            cx.extensions_mut().insert(compression);
+        }
+        if let Some(priority) = priority {
+            cx.extensions_mut().insert(priority);
+        }
+        if let Some(retry) = retry {
+            cx.extensions_mut().insert(retry);
+        }
+        if let Some(config) = cx.rpc_info().config().copied() {
+            if config.payload_checksum() {
+                cx.extensions_mut().insert(PayloadChecksum);
+            }
+            cx.extensions_mut().insert(config.protocol());
+        }
 
         let has_metainfo = metainfo::METAINFO.try_with(|_| {}).is_ok();
 
-        let mk_call = async { self.transport.call(&mut cx, req).await };
+        #[cfg(not(feature = "forbid-unsafe"))]
+        let transport = &mut self.transport;
+        #[cfg(feature = "forbid-unsafe")]
+        let transport = self.transport.get_mut().unwrap();
+        let mk_call = async { transport.call(&mut cx, req).await };
 
         if has_metainfo {
             mk_call.await
@@ -589,13 +870,25 @@ impl<Req, Resp> Client<Req, Resp> {
         }
     }
 
-    fn make_rpc_info(&mut self, method: &'static str) -> RpcInfo<Config> {
+    fn make_rpc_info(
+        &mut self,
+        method: smol_str::SmolStr,
+        profile: Option<&Profile>,
+    ) -> RpcInfo<Config> {
         let mut caller = Endpoint::new(self.inner.caller_name.clone());
         let mut callee = Endpoint::new(self.inner.callee_name.clone());
         if let Some(target) = &self.inner.address {
             callee.set_address(target.clone());
         }
         let mut config = self.inner.config;
+        if let Some(profile) = profile {
+            if let Some(timeout) = profile.rpc_timeout() {
+                config.set_rpc_timeout(timeout);
+            }
+            if let Some(timeout) = profile.connect_timeout() {
+                config.set_connect_timeout(Some(timeout));
+            }
+        }
         if let Some(co) = self.callopt.take() {
             callee.tags.extend(co.callee_tags);
             caller.tags.extend(co.caller_tags);
@@ -605,6 +898,6 @@ impl<Req, Resp> Client<Req, Resp> {
             config.merge(co.config);
         }
 
-        RpcInfo::new(Role::Client, method.into(), caller, callee, config)
+        RpcInfo::new(Role::Client, method, caller, callee, config)
     }
 }
@@ -0,0 +1,102 @@
+//! A thread local pool of the encode buffers.
+//!
+//! Every encoder takes its buffer from the pool and puts it back when dropped, so that short
+//! connections and the compressed payloads don't allocate new buffers every time.
+
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use bytes::BytesMut;
+
+static MAX_BUFFERS: AtomicUsize = AtomicUsize::new(64);
+static MAX_CAPACITY: AtomicUsize = AtomicUsize::new(1024 * 1024);
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static POOL: RefCell<Vec<BytesMut>> = RefCell::new(Vec::new());
+}
+
+/// Sets the max number of the buffers kept by each thread.
+///
+/// Defaults to 64.
+pub fn set_max_buffers(n: usize) {
+    MAX_BUFFERS.store(n, Ordering::Relaxed);
+}
+
+/// Sets the max capacity of the buffers to keep, larger buffers are freed instead, so that a
+/// few large messages don't hold the memory forever.
+///
+/// Defaults to 1MB.
+pub fn set_max_capacity(capacity: usize) {
+    MAX_CAPACITY.store(capacity, Ordering::Relaxed);
+}
+
+/// The statistics of the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of the buffers taken from the pool.
+    pub hits: u64,
+    /// The number of the buffers allocated as the pool is empty.
+    pub misses: u64,
+}
+
+/// Returns the statistics of the pool, counted across all the threads.
+pub fn stats() -> Stats {
+    Stats {
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+    }
+}
+
+/// Takes an empty buffer with at least `capacity` bytes from the pool.
+pub(crate) fn get(capacity: usize) -> BytesMut {
+    let buf = POOL.try_with(|pool| pool.borrow_mut().pop()).ok().flatten();
+    match buf {
+        Some(mut buf) => {
+            HITS.fetch_add(1, Ordering::Relaxed);
+            buf.reserve(capacity);
+            buf
+        }
+        None => {
+            MISSES.fetch_add(1, Ordering::Relaxed);
+            BytesMut::with_capacity(capacity)
+        }
+    }
+}
+
+/// Puts the buffer back to the pool.
+pub(crate) fn put(mut buf: BytesMut) {
+    if buf.capacity() == 0 || buf.capacity() > MAX_CAPACITY.load(Ordering::Relaxed) {
+        return;
+    }
+    buf.clear();
+    let _ = POOL.try_with(|pool| {
+        let mut pool = pool.borrow_mut();
+        if pool.len() < MAX_BUFFERS.load(Ordering::Relaxed) {
+            pool.push(buf);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse() {
+        let buf = get(128);
+        assert!(buf.capacity() >= 128);
+        let ptr = buf.as_ptr();
+        put(buf);
+
+        let before = stats();
+        let buf = get(64);
+        assert_eq!(buf.as_ptr(), ptr);
+        assert!(buf.is_empty());
+        assert!(stats().hits > before.hits);
+    }
+}
@@ -1,5 +1,5 @@
 use pilota::thrift::EntryMessage;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
 use volo::{
     net::conn::{OwnedReadHalf, OwnedWriteHalf},
     util::buf_reader::BufReader,
@@ -32,7 +32,7 @@ where
     }
 
     #[inline]
-    pub async fn next<M: EntryMessage + Send, Cx: ThriftContext>(
+    pub async fn next<M: EntryMessage + crate::Size + Send, Cx: ThriftContext>(
         &mut self,
         cx: &mut Cx,
     ) -> Result<Option<ThriftMessage<M>>, crate::Error> {
@@ -41,6 +41,15 @@ where
             .await
     }
 
+    /// Waits for the peer to close the connection, returns false if more data is received
+    /// instead, which is kept for [`next`](Self::next).
+    pub async fn peer_closed(&mut self) -> bool {
+        match self.read_half.fill_buf().await {
+            Ok(buf) => buf.is_empty(),
+            Err(_) => true,
+        }
+    }
+
     #[inline]
     pub async fn send<M: EntryMessage + crate::Size, Cx: ThriftContext>(
         &mut self,
@@ -1,21 +1,31 @@
-use bytes::{Buf, BytesMut};
+use std::io::{Read, Write};
+
+use bytes::{Buf, BufMut, BytesMut};
 use pilota::thrift::EntryMessage;
 use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tracing::trace;
 use volo::util::buf_reader::BufReader;
 
-use self::tt_header::{TTHeaderDecoder, TTHeaderEncoder};
+use self::{
+    tt_header::{Crc32c, TTHeaderDecoder, TTHeaderEncoder},
+    unknown_fields::DecodeMode,
+};
 use crate::{
     context::ThriftContext,
     error::Result,
     new_protocol_error,
-    protocol::{binary::TAsyncBinaryProtocol, rw_ext::WriteExt, TBinaryProtocol},
+    protocol::{
+        binary::TAsyncBinaryProtocol, compact, rw_ext::WriteExt, TBinaryProtocol, TCompactProtocol,
+    },
+    tags::{Compression, PayloadChecksum, ReceivedAt, DEFAULT_COMPRESSION_THRESHOLD},
     ProtocolErrorKind, Size, ThriftMessage,
 };
 
+pub mod buffer_pool;
 pub mod framed;
 mod mesh_header;
 pub mod tt_header;
+pub mod unknown_fields;
 
 mod magic {
     pub const TT_HEADER: u16 = 0x1000;
@@ -23,6 +33,7 @@ mod magic {
     pub const MESH_HEADER: u16 = 0xFFAF;
     #[allow(dead_code)]
     pub const THRIFT_V1_HEADER: u16 = 0x8001;
+    pub const THRIFT_COMPACT_PROTOCOL_ID: u8 = 0x82;
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -38,7 +49,7 @@ impl CodecType {
         matches!(self, CodecType::TTHeaderFramed | CodecType::TTHeader)
     }
 
-    fn is_framed(&self) -> bool {
+    pub(crate) fn is_framed(&self) -> bool {
         matches!(self, CodecType::TTHeaderFramed | CodecType::Framed)
     }
 
@@ -47,10 +58,23 @@ impl CodecType {
     }
 }
 
+/// The protocol encoding the messages, orthogonal to the [`CodecType`] framing them.
+///
+/// The protocol of a request is set into the context extensions, by the client from its config
+/// and by the server from the message decoded, so that the response is encoded by the same one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    #[default]
+    Binary,
+    Compact,
+}
+
 pub const DEFAULT_TTHEADER_SIZE: usize = 4096; // 4KB should be enough for most headers
 pub const MAX_TTHEADER_SIZE: usize = 64 * 1024; // 64KB
 pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024; // 16MB
 pub const DEFAULT_BUFFER_SIZE: usize = 8192; // 8KB
+/// The bytes needed to detect the protocol.
+const HEADER_DETECT_LENGTH: usize = 6;
 
 #[derive(Clone)]
 pub struct DefaultEncoder<TT> {
@@ -71,6 +95,9 @@ impl<TT: Send + TTHeaderEncoder> DefaultEncoder<TT> {
         writer: &mut W,
         item: ThriftMessage<Req>,
     ) -> Result<()> {
+        if cx.extensions().get::<Protocol>() == Some(&Protocol::Compact) {
+            return self.encode_compact(cx, writer, item).await;
+        }
         // we only need to get size here.
         let p = TBinaryProtocol::new(&mut self.buffer);
         let mut size = item.size(&p);
@@ -78,6 +105,15 @@ impl<TT: Send + TTHeaderEncoder> DefaultEncoder<TT> {
         if self.codec_type.is_framed() {
             size += 4;
         }
+
+        if self.codec_type.is_ttheader() {
+            let compress = should_compress(cx, size);
+            if compress || cx.extensions().get::<PayloadChecksum>().is_some() {
+                return self
+                    .encode_transformed(cx, writer, item, size, compress)
+                    .await;
+            }
+        }
         self.buffer.reserve(DEFAULT_TTHEADER_SIZE + size);
 
         // 1. encode header.
@@ -127,11 +163,143 @@ impl<TT: Send + TTHeaderEncoder> DefaultEncoder<TT> {
         self.buffer.clear();
         Ok(())
     }
+
+    /// Encodes the payload after the TTHeader by the binary protocol, including the framed header,
+    /// then writes it by [`write_transformed`](Self::write_transformed).
+    async fn encode_transformed<
+        W: AsyncWrite + Unpin + Send,
+        Req: Send + EntryMessage + Size,
+        Cx: ThriftContext,
+    >(
+        &mut self,
+        cx: &mut Cx,
+        writer: &mut W,
+        item: ThriftMessage<Req>,
+        size: usize,
+        compress: bool,
+    ) -> Result<()> {
+        let mut payload = buffer_pool::get(size);
+        if self.codec_type.is_framed() {
+            if size - 4 > self.max_frame_size {
+                return Err(new_protocol_error(
+                    ProtocolErrorKind::SizeLimit,
+                    format!("Frame of length {} is too large.", size - 4),
+                ));
+            }
+            payload.put_u32((size - 4) as u32);
+        }
+        let mut p = TBinaryProtocol::new(&mut payload);
+        item.encode(&mut p)?;
+        self.write_transformed(cx, writer, payload, size, compress)
+            .await
+    }
+
+    /// Encodes the message by the compact protocol, into the payload before the lengths as its
+    /// size is only known after encoding.
+    async fn encode_compact<
+        W: AsyncWrite + Unpin + Send,
+        Req: Send + EntryMessage + Size,
+        Cx: ThriftContext,
+    >(
+        &mut self,
+        cx: &mut Cx,
+        writer: &mut W,
+        item: ThriftMessage<Req>,
+    ) -> Result<()> {
+        let mut payload = buffer_pool::get(DEFAULT_BUFFER_SIZE);
+        if self.codec_type.is_framed() {
+            // filled after encoding
+            payload.put_u32(0);
+        }
+        item.encode(&mut TCompactProtocol::new(&mut payload))?;
+        let size = payload.len();
+        trace!("[VOLO] encode compact message size: {}", size);
+        if self.codec_type.is_framed() {
+            if size - 4 > self.max_frame_size {
+                buffer_pool::put(payload);
+                return Err(new_protocol_error(
+                    ProtocolErrorKind::SizeLimit,
+                    format!("Frame of length {} is too large.", size - 4),
+                ));
+            }
+            (&mut payload[..4]).put_u32((size - 4) as u32);
+        }
+
+        if !self.codec_type.is_ttheader() {
+            writer.write_all(&payload).await?;
+            writer.flush().await?;
+            buffer_pool::put(payload);
+            return Ok(());
+        }
+        let compress = should_compress(cx, size);
+        self.write_transformed(cx, writer, payload, size, compress)
+            .await
+    }
+
+    /// Writes the TTHeader and the encoded payload, compressed by zlib if `compress`, and
+    /// checksummed if [`PayloadChecksum`] is in the extensions.
+    async fn write_transformed<W: AsyncWrite + Unpin + Send, Cx: ThriftContext>(
+        &mut self,
+        cx: &mut Cx,
+        writer: &mut W,
+        payload: BytesMut,
+        size: usize,
+        compress: bool,
+    ) -> Result<()> {
+        let (payload, transform_ids): (_, &[u8]) = if compress {
+            let mut encoder = flate2::write::ZlibEncoder::new(
+                buffer_pool::get(size / 2).writer(),
+                flate2::Compression::fast(),
+            );
+            encoder.write_all(&payload)?;
+            buffer_pool::put(payload);
+            let compressed = encoder.finish()?.into_inner();
+            trace!(
+                "[VOLO] encode message compressed size: {} -> {}",
+                size,
+                compressed.len()
+            );
+            (compressed, &[tt_header::transform::ZLIB])
+        } else {
+            (payload, &[])
+        };
+        if cx.extensions().get::<PayloadChecksum>().is_some() {
+            cx.extensions_mut().insert(Crc32c(crc32c::crc32c(&payload)));
+        }
+
+        self.buffer.reserve(DEFAULT_TTHEADER_SIZE + payload.len());
+        let header_size = self.ttheader_encoder.encode_with_transforms(
+            cx,
+            &mut self.buffer,
+            payload.len(),
+            transform_ids,
+        )?;
+        if header_size > MAX_TTHEADER_SIZE {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::SizeLimit,
+                "TTHeader size too large".to_string(),
+            ));
+        }
+        self.buffer.extend_from_slice(&payload);
+        buffer_pool::put(payload);
+
+        writer.write_all_buf(&mut self.buffer).await?;
+        writer.flush().await?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+/// Whether to compress the payload of `size` bytes, by the [`Compression`] in the extensions.
+fn should_compress<Cx: ThriftContext>(cx: &Cx, size: usize) -> bool {
+    cx.extensions()
+        .get::<Compression>()
+        .map_or(false, |compression| size >= compression.threshold)
 }
 
 impl<TT> DefaultEncoder<TT> {
     pub fn new(codec_type: CodecType, ttheader_encoder: TT) -> Self {
-        let buffer = BytesMut::with_capacity(DEFAULT_BUFFER_SIZE);
+        let buffer = buffer_pool::get(DEFAULT_BUFFER_SIZE);
         Self {
             codec_type,
             buffer,
@@ -141,11 +309,22 @@ impl<TT> DefaultEncoder<TT> {
     }
 }
 
+impl<TT> Drop for DefaultEncoder<TT> {
+    fn drop(&mut self) {
+        buffer_pool::put(std::mem::take(&mut self.buffer));
+    }
+}
+
 #[derive(Clone)]
 pub struct DetectedDecoder<TT> {
     pub(crate) codec_type: Option<CodecType>,
     bytes: BytesMut,
     has_mesh_header: bool,
+    max_frame_size: usize,
+    decode_mode: DecodeMode,
+    compression_threshold: usize,
+    verify_checksum: bool,
+    protocols: Vec<Protocol>,
     ttheader_decoder: TT,
 }
 
@@ -153,7 +332,11 @@ impl<TT> DetectedDecoder<TT>
 where
     TT: Send + TTHeaderDecoder,
 {
-    async fn decode<Resp: Send + EntryMessage, R: AsyncRead + Unpin + Send, Cx: ThriftContext>(
+    async fn decode<
+        Resp: Send + EntryMessage + Size,
+        R: AsyncRead + Unpin + Send,
+        Cx: ThriftContext,
+    >(
         &mut self,
         cx: &mut Cx,
         reader: &mut BufReader<R>,
@@ -162,7 +345,22 @@ where
         if buf.is_empty() {
             return Ok(None);
         }
+        if cx.rpc_info().role() == volo::context::Role::Server {
+            cx.extensions_mut()
+                .insert(ReceivedAt(std::time::Instant::now()));
+        }
         if let Some(codec_type) = self.codec_type {
+            // the protocol of a connection is fixed by its first message
+            let buf = reader.fill_buf_at_least(HEADER_DETECT_LENGTH).await?;
+            if !self.is_same_protocol(codec_type, buf) {
+                return Err(new_protocol_error(
+                    ProtocolErrorKind::BadVersion,
+                    format!(
+                        "the protocol of the connection is {:?}, but got another one",
+                        codec_type
+                    ),
+                ));
+            }
             // FIXME: make this zero-copy
             if codec_type.is_ttheader() {
                 self.decode_ttheader(cx, reader).await?;
@@ -182,7 +380,6 @@ where
         }
 
         // detect the protocol
-        const HEADER_DETECT_LENGTH: usize = 6;
         buf = reader.fill_buf_at_least(HEADER_DETECT_LENGTH).await?;
         let mut codec_type;
         // 1. check if has header: ttheader or mesh header
@@ -195,12 +392,13 @@ where
                 // we have ttheader, so we can just ignore framed header
                 codec_type = CodecType::TTHeaderFramed;
                 self.bytes.advance(4);
-            } else if !is_binary(self.bytes.as_ref()) {
+            } else if message_protocol(self.bytes.as_ref()).is_none() {
                 return Err(new_protocol_error(
                     ProtocolErrorKind::BadVersion,
                     "Unknown version".to_string(),
                 ));
             }
+            trace!("[VOLO] detected codec type: {:?}", codec_type);
             self.codec_type = Some(codec_type);
             // 3. decode item
             return Ok(Some(self.decode_message(cx, reader).await?));
@@ -212,11 +410,12 @@ where
         let mut buf = reader.fill_buf().await?;
         trace!("[VOLO] decode buf len after header: {}", buf.len());
         buf = reader.fill_buf_at_least(HEADER_DETECT_LENGTH).await?;
-        // 2. check if is framed or buffered
+        // 2. check if is framed or buffered, the length of a frame never begins with the compact
+        // protocol id
         if is_framed(buf) {
             codec_type = CodecType::Framed;
             self.decode_framed(cx, reader).await?;
-        } else if is_binary(buf) {
+        } else if is_binary(buf) || is_compact(buf) {
             codec_type = CodecType::Buffered;
         } else {
             return Err(new_protocol_error(
@@ -224,6 +423,7 @@ where
                 "Unknown version".to_string(),
             ));
         }
+        trace!("[VOLO] detected codec type: {:?}", codec_type);
         self.codec_type = Some(codec_type);
         // 3. decode item
         Ok(Some(self.decode_message(cx, reader).await?))
@@ -231,15 +431,70 @@ where
 }
 
 impl<TT> DetectedDecoder<TT> {
+    /// Returns the protocol detected from the first message.
+    pub fn codec_type(&self) -> Option<CodecType> {
+        self.codec_type
+    }
+
+    fn is_same_protocol(&self, codec_type: CodecType, buf: &[u8]) -> bool {
+        if codec_type.is_ttheader() {
+            return is_ttheader(buf);
+        }
+        if self.has_mesh_header {
+            return is_mesh_header(buf);
+        }
+        match codec_type {
+            CodecType::Framed => is_framed(buf),
+            _ => is_binary(buf) || is_compact(buf),
+        }
+    }
+
     pub fn new(ttheader_decoder: TT) -> Self {
         let bytes = BytesMut::with_capacity(DEFAULT_BUFFER_SIZE);
         Self {
             codec_type: None,
             bytes,
             has_mesh_header: false,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            decode_mode: DecodeMode::default(),
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            verify_checksum: true,
+            protocols: vec![Protocol::Binary, Protocol::Compact],
             ttheader_decoder,
         }
     }
+
+    /// Sets the max size of a frame, frames larger than it are rejected before being read.
+    ///
+    /// The unframed messages are limited too, they are rejected once they are read past it.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Sets how to decode the messages with unknown fields, see [`unknown_fields`].
+    pub fn with_decode_mode(mut self, decode_mode: DecodeMode) -> Self {
+        self.decode_mode = decode_mode;
+        self
+    }
+
+    /// Sets the min size of the responses compressed when the requests are compressed.
+    pub fn with_compression_threshold(mut self, threshold: usize) -> Self {
+        self.compression_threshold = threshold;
+        self
+    }
+
+    /// Sets whether to verify the checksums of the payloads carrying them.
+    pub fn with_verify_checksum(mut self, enable: bool) -> Self {
+        self.verify_checksum = enable;
+        self
+    }
+
+    /// Sets the protocols accepted, the messages of the others are rejected.
+    pub fn with_protocols(mut self, protocols: Vec<Protocol>) -> Self {
+        self.protocols = protocols;
+        self
+    }
 }
 impl<TT: TTHeaderDecoder> DetectedDecoder<TT> {
     pub async fn decode_ttheader<Cx, R: AsyncRead + Unpin>(
@@ -253,16 +508,64 @@ impl<TT: TTHeaderDecoder> DetectedDecoder<TT> {
         let mut size_bytes: [u8; 4] = [0; 4];
         reader.read_exact(&mut size_bytes).await?;
         let size = u32::from_be_bytes(size_bytes) as usize;
-        self.bytes.reserve(size);
-        unsafe {
-            self.bytes.set_len(size);
-        }
+        // the size covers both the header and the payload
+        check_frame_size(size, self.max_frame_size + MAX_TTHEADER_SIZE)?;
+        set_len(&mut self.bytes, size);
         reader.read_exact(&mut self.bytes[..size]).await?;
+        let compressed = tt_header::is_zlib_transformed(&self.bytes)?;
         self.ttheader_decoder.decode(cx, &mut self.bytes)?;
+        if let Some(Crc32c(expected)) = cx.extensions_mut().remove::<Crc32c>() {
+            self.verify_checksum(expected)?;
+            // respond with checksum as the peer supports it
+            if cx.rpc_info().role() == volo::context::Role::Server {
+                cx.extensions_mut().insert(PayloadChecksum);
+            }
+        }
+        if compressed {
+            self.decompress()?;
+            // respond with compression as the peer supports it
+            if cx.rpc_info().role() == volo::context::Role::Server {
+                cx.extensions_mut().insert(Compression {
+                    threshold: self.compression_threshold,
+                });
+            }
+        }
 
         Ok(())
     }
 
+    /// Verifies the checksum of the remaining payload in bytes, if enabled.
+    fn verify_checksum(&self, expected: u32) -> Result<()> {
+        if !self.verify_checksum {
+            return Ok(());
+        }
+        let actual = crc32c::crc32c(&self.bytes);
+        if actual != expected {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::InvalidData,
+                format!(
+                    "ttheader payload checksum mismatch, expected: {}, actual: {}",
+                    expected, actual
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Decompresses the remaining payload in bytes.
+    fn decompress(&mut self) -> Result<()> {
+        // the framed header is included in the payload
+        let limit = (self.max_frame_size + 4) as u64;
+        let mut payload = Vec::with_capacity(self.bytes.len() * 2);
+        let size = flate2::read::ZlibDecoder::new(&self.bytes[..])
+            .take(limit + 1)
+            .read_to_end(&mut payload)?;
+        check_frame_size(size, limit as usize)?;
+        self.bytes.clear();
+        self.bytes.extend_from_slice(&payload);
+        Ok(())
+    }
+
     pub async fn decode_mesh_header<Cx: ThriftContext, R: AsyncRead + Unpin>(
         &mut self,
         cx: &mut Cx,
@@ -271,10 +574,7 @@ impl<TT: TTHeaderDecoder> DetectedDecoder<TT> {
         let mut size_bytes: [u8; 4] = [0; 4];
         reader.read_exact(&mut size_bytes).await?;
         let size = u16::from_be_bytes(size_bytes[2..4].try_into().unwrap()) as usize;
-        self.bytes.reserve(size);
-        unsafe {
-            self.bytes.set_len(size);
-        }
+        set_len(&mut self.bytes, size);
         reader.read_exact(&mut self.bytes[..size]).await?;
         mesh_header::decode(&mut self.bytes, cx)
     }
@@ -287,37 +587,151 @@ impl<TT: TTHeaderDecoder> DetectedDecoder<TT> {
         let mut size_bytes: [u8; 4] = [0; 4];
         reader.read_exact(&mut size_bytes).await?;
         let size = u32::from_be_bytes(size_bytes) as usize;
-        self.bytes.reserve(size);
+        check_frame_size(size, self.max_frame_size)?;
         let index = self.bytes.len();
-        unsafe {
-            self.bytes.set_len(index + size);
-        }
+        set_len(&mut self.bytes, index + size);
         reader
             .read_exact(&mut self.bytes[index..index + size])
             .await?;
         Ok(())
     }
 
-    pub async fn decode_message<R: AsyncRead + Unpin + Send, Cx: ThriftContext, T: EntryMessage>(
+    pub async fn decode_message<
+        R: AsyncRead + Unpin + Send,
+        Cx: ThriftContext,
+        T: EntryMessage + Size,
+    >(
         &mut self,
         cx: &mut Cx,
         reader: &mut BufReader<R>,
     ) -> Result<ThriftMessage<T>> {
-        if unsafe { self.codec_type.unwrap_unchecked() }.has_length() {
-            // data is in bytes
+        #[cfg(not(feature = "forbid-unsafe"))]
+        let codec_type = unsafe { self.codec_type.unwrap_unchecked() };
+        #[cfg(feature = "forbid-unsafe")]
+        let codec_type = self.codec_type.unwrap();
+        if !codec_type.has_length() {
+            if !is_compact(reader.fill_buf_at_least(2).await?) {
+                // data in self.reader, so we can directly read from self.reader, at most
+                // max_frame_size bytes of it
+                let mut limited = reader.take(self.max_frame_size as u64);
+                let mut protocol = TAsyncBinaryProtocol::new(&mut limited);
+                let result = ThriftMessage::<T>::decode_async(&mut protocol, cx).await;
+                if result.is_err() && limited.limit() == 0 {
+                    return Err(new_protocol_error(
+                        ProtocolErrorKind::SizeLimit,
+                        format!("Message larger than {} bytes.", self.max_frame_size),
+                    ));
+                }
+                return result;
+            }
+            self.read_compact(reader).await?;
+        }
+        // data is in bytes, which the unframed compact messages are read into too
+        let protocol = match message_protocol(&self.bytes) {
+            Some(protocol) if self.protocols.contains(&protocol) => protocol,
+            Some(protocol) => {
+                return Err(new_protocol_error(
+                    ProtocolErrorKind::NotImplemented,
+                    format!("the {:?} protocol is not accepted", protocol),
+                ))
+            }
+            None => {
+                return Err(new_protocol_error(
+                    ProtocolErrorKind::BadVersion,
+                    "Unknown version".to_string(),
+                ))
+            }
+        };
+        // for the response, the server encoder responds by the same protocol
+        cx.extensions_mut().insert(protocol);
+        if protocol == Protocol::Compact {
+            // the unknown fields are not checked, as the sizes are measured by the binary
+            // protocol
+            let msg = ThriftMessage::<T>::decode(&mut TCompactProtocol::new(&mut self.bytes), cx)?;
+            self.bytes.clear();
+            return Ok(msg);
+        }
+        let len = self.bytes.len();
+        let header_len = unknown_fields::message_header_len(&self.bytes);
+        let (msg, decoded) = {
             let mut protocol = TBinaryProtocol::new(&mut self.bytes);
             let msg = ThriftMessage::<T>::decode(&mut protocol, cx)?;
-            self.bytes.clear();
-            Ok(msg)
-        } else {
-            // data in self.reader, so we can directly read from self.reader
-            let mut protocol = TAsyncBinaryProtocol::new(reader);
-            let msg = ThriftMessage::<T>::decode_async(&mut protocol, cx).await?;
-            Ok(msg)
+            // the exceptions are defined by thrift itself, so only the messages are checked
+            let decoded = match &msg.data {
+                Ok(data) => Some(data.size(&protocol)),
+                Err(_) => None,
+            };
+            (msg, decoded)
+        };
+        if let (Some(header_len), Some(decoded)) = (header_len, decoded) {
+            let payload = len - self.bytes.len();
+            unknown_fields::check(
+                self.decode_mode,
+                cx,
+                payload.saturating_sub(header_len),
+                decoded,
+            )?;
+        }
+        self.bytes.clear();
+        Ok(msg)
+    }
+
+    /// Reads the unframed compact message into bytes, as its length is only known by walking it.
+    async fn read_compact<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut BufReader<R>,
+    ) -> Result<()> {
+        self.bytes.clear();
+        let mut measure = compact::MessageLen::default();
+        let mut len = 0;
+        loop {
+            let buf = reader.fill_buf().await?;
+            if buf.is_empty() {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            let index = self.bytes.len();
+            self.bytes.extend_from_slice(buf);
+            // the lower bound of the length skips walking the messages along with each read
+            if self.bytes.len() >= len {
+                len = measure.measure(&self.bytes)?;
+                check_frame_size(len, self.max_frame_size)?;
+                if len <= self.bytes.len() {
+                    // the rest are of the next message
+                    reader.consume(len - index);
+                    self.bytes.truncate(len);
+                    return Ok(());
+                }
+            }
+            reader.consume(self.bytes.len() - index);
         }
     }
 }
 
+/// Rejects the frames, or the unframed messages, larger than `max_size`.
+fn check_frame_size(size: usize, max_size: usize) -> Result<()> {
+    if size > max_size {
+        return Err(new_protocol_error(
+            ProtocolErrorKind::SizeLimit,
+            format!("Frame of length {} is too large.", size),
+        ));
+    }
+    Ok(())
+}
+
+/// Sets the length of `bytes` to `len`, the new bytes are to be overwritten by the reads.
+#[inline]
+fn set_len(bytes: &mut BytesMut, len: usize) {
+    #[cfg(not(feature = "forbid-unsafe"))]
+    {
+        bytes.reserve(len.saturating_sub(bytes.len()));
+        unsafe {
+            bytes.set_len(len);
+        }
+    }
+    #[cfg(feature = "forbid-unsafe")]
+    bytes.resize(len, 0);
+}
+
 fn is_ttheader(buf: &[u8]) -> bool {
     buf[4..6] == [0x10, 0x00]
 }
@@ -326,14 +740,38 @@ fn is_mesh_header(buf: &[u8]) -> bool {
     buf[0..2] == [0xff, 0xaf]
 }
 
+/// Checks the framed messages of both the binary and the compact protocol.
 fn is_framed(buf: &[u8]) -> bool {
-    buf[4..6] == [0x80, 0x01]
+    message_protocol(&buf[4..]).is_some()
 }
 
 fn is_binary(buf: &[u8]) -> bool {
     buf[0..2] == [0x80, 0x01]
 }
 
+/// Checks the unframed compact protocol, the protocol id and the version.
+fn is_compact(buf: &[u8]) -> bool {
+    buf[0] == magic::THRIFT_COMPACT_PROTOCOL_ID && buf[1] & 0x1f == compact::VERSION
+}
+
+/// Returns the protocol of the message beginning `buf`.
+fn message_protocol(buf: &[u8]) -> Option<Protocol> {
+    if buf.len() < 2 {
+        None
+    } else if is_binary(buf) {
+        Some(Protocol::Binary)
+    } else if is_compact(buf) {
+        Some(Protocol::Compact)
+    } else {
+        None
+    }
+}
+
+/// The decoder of a server connection.
+///
+/// The protocol is detected from the first message and kept for the connection, it's also set
+/// into the context extensions as [`CodecType`], so that [`ServerEncoder`] responds with it.
+/// Thus TTHeader and framed clients can be served on the same port at the same time.
 #[derive(Clone)]
 pub struct ServerDecoder<TT>(DetectedDecoder<TT>);
 
@@ -400,7 +838,11 @@ impl<TT: TTHeaderEncoder + Send> Encoder for ServerEncoder<TT> {
 #[async_trait::async_trait]
 impl<TT: TTHeaderDecoder + Send> Decoder for ServerDecoder<TT> {
     #[inline]
-    async fn decode<Resp: Send + EntryMessage, R: AsyncRead + Unpin + Send, Cx: ThriftContext>(
+    async fn decode<
+        Resp: Send + EntryMessage + Size,
+        R: AsyncRead + Unpin + Send,
+        Cx: ThriftContext,
+    >(
         &mut self,
         cx: &mut Cx,
         reader: &mut BufReader<R>,
@@ -444,11 +886,22 @@ impl<TT> Decoder for ClientDecoder<TT>
 where
     TT: TTHeaderDecoder,
 {
-    async fn decode<Resp: Send + EntryMessage, R: AsyncRead + Unpin + Send, Cx: ThriftContext>(
+    async fn decode<
+        Resp: Send + EntryMessage + Size,
+        R: AsyncRead + Unpin + Send,
+        Cx: ThriftContext,
+    >(
         &mut self,
         cx: &mut Cx,
         reader: &mut BufReader<R>,
     ) -> Result<Option<ThriftMessage<Resp>>> {
+        // the max frame size, the decode mode and the checksum verification are set by the
+        // client builder
+        if let Some(config) = cx.rpc_info().config() {
+            self.0.max_frame_size = config.max_frame_size() as usize;
+            self.0.decode_mode = config.decode_mode();
+            self.0.verify_checksum = config.verify_checksum();
+        }
         self.0.decode(cx, reader).await
     }
 }
@@ -469,7 +922,11 @@ pub trait Encoder {
 
 #[async_trait::async_trait]
 pub trait Decoder {
-    async fn decode<Resp: Send + EntryMessage, R: AsyncRead + Unpin + Send, Cx: ThriftContext>(
+    async fn decode<
+        Resp: Send + EntryMessage + Size,
+        R: AsyncRead + Unpin + Send,
+        Cx: ThriftContext,
+    >(
         &mut self,
         cx: &mut Cx,
         reader: &mut BufReader<R>,
@@ -537,11 +994,23 @@ impl<T> MakeServerEncoder<T> {
 #[derive(Clone, Debug)]
 pub struct MakeServerDecoder<TTDecoder> {
     pub(crate) tt_decoder: TTDecoder,
+    pub(crate) max_frame_size: usize,
+    pub(crate) decode_mode: DecodeMode,
+    pub(crate) compression_threshold: usize,
+    pub(crate) verify_checksum: bool,
+    pub(crate) protocols: Vec<Protocol>,
 }
 
 impl<T> MakeServerDecoder<T> {
     pub fn new(tt_decoder: T) -> Self {
-        Self { tt_decoder }
+        Self {
+            tt_decoder,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            decode_mode: DecodeMode::default(),
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            verify_checksum: true,
+            protocols: vec![Protocol::Binary, Protocol::Compact],
+        }
     }
 }
 
@@ -563,6 +1032,259 @@ where
     type Target = ServerDecoder<TTDecoder>;
 
     fn mk_decoder(&self, _codec_type: Option<CodecType>) -> Self::Target {
-        ServerDecoder::new(self.tt_decoder)
+        ServerDecoder(
+            DetectedDecoder::new(self.tt_decoder)
+                .with_max_frame_size(self.max_frame_size)
+                .with_decode_mode(self.decode_mode)
+                .with_compression_threshold(self.compression_threshold)
+                .with_verify_checksum(self.verify_checksum)
+                .with_protocols(self.protocols.clone()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use volo::context::Context;
+
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        // TTHeader: length, magic, flags, seq id
+        let ttheader = [0x00, 0x00, 0x00, 0x20, 0x10, 0x00, 0x00, 0x00];
+        assert!(is_ttheader(&ttheader));
+        assert!(!is_framed(&ttheader));
+        assert!(!is_binary(&ttheader));
+
+        // framed binary: length, version, type
+        let framed = [0x00, 0x00, 0x00, 0x20, 0x80, 0x01, 0x00, 0x01];
+        assert!(is_framed(&framed));
+        assert!(!is_ttheader(&framed));
+        assert!(!is_binary(&framed));
+
+        // unframed binary: version, type, name length
+        let buffered = [0x80, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04];
+        assert!(is_binary(&buffered));
+        assert!(!is_ttheader(&buffered));
+        assert!(!is_framed(&buffered));
+
+        // framed compact: length, protocol id, type and version, seq id
+        let compact = [0x00, 0x00, 0x00, 0x20, 0x82, 0x21, 0x01, 0x04];
+        assert!(is_framed(&compact));
+        assert_eq!(message_protocol(&compact[4..]), Some(Protocol::Compact));
+        assert_eq!(message_protocol(&framed[4..]), Some(Protocol::Binary));
+        assert_eq!(message_protocol(&ttheader), None);
+        assert!(is_compact(&compact[4..]));
+        assert!(!is_compact(&compact));
+
+        let mesh = [0xff, 0xaf, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00];
+        assert!(is_mesh_header(&mesh));
+    }
+
+    #[tokio::test]
+    async fn test_frame_size_limit() {
+        let mut decoder =
+            DetectedDecoder::new(tt_header::DefaultTTHeaderCodec).with_max_frame_size(16);
+        let mut cx = crate::context::ServerContext::default();
+
+        // a corrupted length must be rejected before allocating
+        let frame = [0xff, 0xff, 0xff, 0xff, 0x80, 0x01, 0x00, 0x01];
+        let mut reader = BufReader::new(&frame[..]);
+        let err = decoder
+            .decode_framed(&mut cx, &mut reader)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::Protocol(ref e) if matches!(e.kind, ProtocolErrorKind::SizeLimit)
+        ));
+        assert!(decoder.bytes.capacity() < 1024 * 1024);
+    }
+
+    #[test]
+    fn test_same_protocol() {
+        let ttheader = [0x00, 0x00, 0x00, 0x20, 0x10, 0x00, 0x00, 0x00];
+        let framed = [0x00, 0x00, 0x00, 0x20, 0x80, 0x01, 0x00, 0x01];
+
+        let decoder = DetectedDecoder::new(tt_header::DefaultTTHeaderCodec);
+        assert!(decoder.is_same_protocol(CodecType::TTHeaderFramed, &ttheader));
+        assert!(!decoder.is_same_protocol(CodecType::TTHeaderFramed, &framed));
+        assert!(decoder.is_same_protocol(CodecType::Framed, &framed));
+        assert!(!decoder.is_same_protocol(CodecType::Framed, &ttheader));
+    }
+
+    #[test]
+    fn test_decompress() {
+        let payload = vec![7u8; 4096];
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&payload).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decoder = DetectedDecoder::new(tt_header::DefaultTTHeaderCodec);
+        decoder.bytes.extend_from_slice(&compressed);
+        decoder.decompress().unwrap();
+        assert_eq!(&decoder.bytes[..], &payload[..]);
+
+        // the decompressed size is limited too
+        let mut decoder =
+            DetectedDecoder::new(tt_header::DefaultTTHeaderCodec).with_max_frame_size(1024);
+        decoder.bytes.extend_from_slice(&compressed);
+        decoder.decompress().unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_buffered() {
+        let mut ri = volo::context::RpcInfo::with_role(volo::context::Role::Client);
+        ri.method = Some("echo".into());
+        let mut cx = crate::context::ClientContext::new(1, ri, crate::protocol::TMessageType::Call);
+        // an empty struct
+        let req = crate::generic::Binary(bytes::Bytes::from_static(&[0]));
+        let msg = ThriftMessage::mk_client_msg(&cx, Ok(req.clone())).unwrap();
+
+        let mut encoder = DefaultEncoder::new(CodecType::Buffered, tt_header::DefaultTTHeaderCodec);
+        let mut buf = Vec::new();
+        encoder.encode(&mut cx, &mut buf, msg).await.unwrap();
+        // no length before the message
+        assert!(is_binary(&buf));
+
+        let mut decoder = DetectedDecoder::new(tt_header::DefaultTTHeaderCodec);
+        let mut cx = crate::context::ServerContext::default();
+        let mut reader = BufReader::new(&buf[..]);
+        let msg = decoder
+            .decode::<crate::generic::Binary, _, _>(&mut cx, &mut reader)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(decoder.codec_type(), Some(CodecType::Buffered)));
+        assert_eq!(msg.data.unwrap(), req);
+    }
+
+    #[tokio::test]
+    async fn test_buffered_compact() {
+        let mut ri = volo::context::RpcInfo::with_role(volo::context::Role::Client);
+        ri.method = Some("echo".into());
+        let mut cx = crate::context::ClientContext::new(1, ri, crate::protocol::TMessageType::Call);
+        cx.extensions_mut().insert(Protocol::Compact);
+        // a struct of a string field longer than the buffer of the reader, by the compact protocol
+        let mut args = vec![0x18, 20];
+        args.extend_from_slice(&[b'a'; 20]);
+        args.push(0);
+        let req = crate::generic::Binary(args.into());
+
+        let mut encoder = DefaultEncoder::new(CodecType::Buffered, tt_header::DefaultTTHeaderCodec);
+        let mut buf = Vec::new();
+        for _ in 0..2 {
+            let msg = ThriftMessage::mk_client_msg(&cx, Ok(req.clone())).unwrap();
+            encoder.encode(&mut cx, &mut buf, msg).await.unwrap();
+        }
+        // no length before the message
+        assert!(is_compact(&buf));
+
+        // the same struct by the binary protocol
+        let mut decoded = vec![0x0b, 0x00, 0x01, 0x00, 0x00, 0x00, 20];
+        decoded.extend_from_slice(&[b'a'; 20]);
+        decoded.push(0);
+        let mut decoder = DetectedDecoder::new(tt_header::DefaultTTHeaderCodec);
+        let mut reader = BufReader::with_capacity(16, &buf[..]);
+        // the messages sent back to back are split apart
+        for _ in 0..2 {
+            let mut cx = crate::context::ServerContext::default();
+            let msg = decoder
+                .decode::<crate::generic::Binary, _, _>(&mut cx, &mut reader)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(matches!(decoder.codec_type(), Some(CodecType::Buffered)));
+            assert_eq!(cx.extensions().get::<Protocol>(), Some(&Protocol::Compact));
+            assert_eq!(&msg.data.unwrap().0[..], &decoded[..]);
+        }
+
+        // the truncated message fails by the eof
+        let mut decoder = DetectedDecoder::new(tt_header::DefaultTTHeaderCodec);
+        let mut cx = crate::context::ServerContext::default();
+        let mut reader = BufReader::new(&buf[..buf.len() / 2 - 1]);
+        let result = decoder
+            .decode::<crate::generic::Binary, _, _>(&mut cx, &mut reader)
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_buffered_size_limit() {
+        let mut ri = volo::context::RpcInfo::with_role(volo::context::Role::Client);
+        ri.method = Some("echo".into());
+        let mut cx = crate::context::ClientContext::new(1, ri, crate::protocol::TMessageType::Call);
+        // a struct of a string field, by the binary and the compact protocols
+        let mut binary = vec![0x0b, 0x00, 0x01, 0x00, 0x00, 0x00, 20];
+        binary.extend_from_slice(&[b'a'; 20]);
+        binary.push(0);
+        let mut compact = vec![0x18, 20];
+        compact.extend_from_slice(&[b'a'; 20]);
+        compact.push(0);
+
+        for (protocol, args) in [(Protocol::Binary, binary), (Protocol::Compact, compact)] {
+            cx.extensions_mut().insert(protocol);
+            let req = crate::generic::Binary(args.into());
+            let msg = ThriftMessage::mk_client_msg(&cx, Ok(req)).unwrap();
+            let mut encoder =
+                DefaultEncoder::new(CodecType::Buffered, tt_header::DefaultTTHeaderCodec);
+            let mut buf = Vec::new();
+            encoder.encode(&mut cx, &mut buf, msg).await.unwrap();
+
+            let mut decoder =
+                DetectedDecoder::new(tt_header::DefaultTTHeaderCodec).with_max_frame_size(16);
+            let mut cx = crate::context::ServerContext::default();
+            let mut reader = BufReader::new(&buf[..]);
+            let result = decoder
+                .decode::<crate::generic::Binary, _, _>(&mut cx, &mut reader)
+                .await;
+            assert!(
+                matches!(
+                    result,
+                    Err(crate::Error::Protocol(ref e)) if matches!(e.kind, ProtocolErrorKind::SizeLimit)
+                ),
+                "{:?}",
+                protocol
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_payload_checksum() {
+        let mi = || std::cell::RefCell::new(metainfo::MetaInfo::default());
+        let mut ri = volo::context::RpcInfo::with_role(volo::context::Role::Client);
+        ri.method = Some("echo".into());
+        let mut cx = crate::context::ClientContext::new(1, ri, crate::protocol::TMessageType::Call);
+        cx.extensions_mut().insert(PayloadChecksum);
+        let req = crate::generic::Binary(bytes::Bytes::from_static(&[0]));
+        let msg = ThriftMessage::mk_client_msg(&cx, Ok(req.clone())).unwrap();
+
+        let mut encoder =
+            DefaultEncoder::new(CodecType::TTHeaderFramed, tt_header::DefaultTTHeaderCodec);
+        let mut buf = Vec::new();
+        metainfo::METAINFO
+            .scope(mi(), encoder.encode(&mut cx, &mut buf, msg))
+            .await
+            .unwrap();
+
+        let decode = |buf: Vec<u8>| async move {
+            let mut decoder = DetectedDecoder::new(tt_header::DefaultTTHeaderCodec);
+            let mut cx = crate::context::ServerContext::default();
+            let mut reader = BufReader::new(&buf[..]);
+            let result = decoder
+                .decode::<crate::generic::Binary, _, _>(&mut cx, &mut reader)
+                .await;
+            (result, cx.extensions().get::<PayloadChecksum>().is_some())
+        };
+        let (result, checksum) = metainfo::METAINFO.scope(mi(), decode(buf.clone())).await;
+        assert_eq!(result.unwrap().unwrap().data.unwrap(), req);
+        // the response is checksummed too
+        assert!(checksum);
+
+        // the corrupted payload is rejected
+        *buf.last_mut().unwrap() ^= 0xff;
+        let (result, _) = metainfo::METAINFO.scope(mi(), decode(buf)).await;
+        assert!(result.is_err());
     }
 }
@@ -13,14 +13,20 @@ use bytes::{Buf, BufMut, BytesMut};
 use metainfo::{Backward, Forward};
 use num_enum::TryFromPrimitive;
 use tracing::{trace, warn};
-use volo::context::{Endpoint, Role};
+use volo::context::{Endpoint, Priority, Role};
 
+use super::Protocol;
 use crate::{
     context::{Config, ThriftContext},
     error::{new_protocol_error, ProtocolErrorKind},
     tags::TransportType,
 };
 
+/// The transform ids of the TTHeader.
+pub(crate) mod transform {
+    pub const ZLIB: u8 = 0x01;
+}
+
 mod info {
     pub const INFO_PADDING: u8 = 0x00;
     pub const INFO_KEY_VALUE: u8 = 0x01;
@@ -33,6 +39,15 @@ pub(crate) const HEADER_TRANS_REMOTE_ADDR: &str = "rip";
 // the connection peer will shutdown later, so it send back the header to tell client to close the
 // connection.
 pub(crate) const HEADER_CONNECTION_READY_TO_RESET: &str = "crrst";
+// the priority of the request, for the overloaded server to reject the low priority ones first.
+pub(crate) const HEADER_PRIORITY: &str = "priority";
+// the CRC32C checksum of the payload after the header, in decimal.
+pub(crate) const HEADER_CRC32C: &str = "crc32c";
+
+/// The checksum of the payload, put in the extensions by the codec to encode it in the header, or
+/// decoded from the header to verify the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Crc32c(pub(crate) u32);
 
 #[derive(TryFromPrimitive)]
 #[repr(u8)]
@@ -49,25 +64,42 @@ impl Default for ProtocolId {
     }
 }
 
+/// The int keys of the TTHeader info, keep consistent with Kitex.
 #[derive(PartialEq, Eq, Hash, Clone, Copy, TryFromPrimitive)]
 #[repr(u16)]
 pub enum IntMetaKey {
     // framed / unframed
     TransportType = 1,
+    LogID = 2,
     FromService = 3,
+    FromCluster = 4,
+    FromIDC = 5,
 
     ToService = 6,
+    ToCluster = 7,
+    ToIDC = 8,
     ToMethod = 9,
+    Env = 10,
     DestAddress = 11,
 
     // in ms
     RPCTimeout = 12,
     // in ms
-    ConnTimeout = 17,
+    ReadTimeout = 13,
+    RingHashKey = 14,
+    DDPTag = 15,
     // always set to 3
     WithHeader = 16,
-
+    // in ms
+    ConnTimeout = 17,
+    SpanContext = 18,
+    ShortConnection = 19,
+    FromMethod = 20,
+    StressTag = 21,
     MsgType = 22,
+    HTTPContentType = 23,
+    RawRingHashKey = 24,
+    LBType = 25,
 }
 
 /// TTHeader Protocol detailed: https://www.cloudwego.io/docs/kitex/reference/transport_protocol_ttheader/
@@ -113,16 +145,23 @@ impl TTHeaderEncoder for DefaultTTHeaderCodec {
         cx: &mut Cx,
         dst: &mut BytesMut,
         size: usize,
+    ) -> Result<usize, crate::Error> {
+        self.encode_with_transforms(cx, dst, size, &[])
+    }
+
+    fn encode_with_transforms<Cx: ThriftContext>(
+        &mut self,
+        cx: &mut Cx,
+        dst: &mut BytesMut,
+        size: usize,
+        transform_ids: &[u8],
     ) -> Result<usize, crate::Error> {
         let thrift_cx = cx;
         Ok(metainfo::METAINFO.with(|metainfo| {
             let metainfo = metainfo.borrow_mut();
             let zero_index = dst.len();
             // Alloc 4-byte space as length
-            dst.reserve(4);
-            unsafe {
-                dst.advance_mut(4);
-            }
+            skip(dst, 4);
 
             // tt header magic
             dst.put_u16(super::magic::TT_HEADER);
@@ -132,43 +171,41 @@ impl TTHeaderEncoder for DefaultTTHeaderCodec {
             dst.put_u32(seq_id as u32); // TODO: thrift seq_id is i32, tt header is u32?
 
             // Alloc 2-byte space as header length
-            dst.reserve(2);
-            unsafe {
-                dst.advance_mut(2);
-            }
+            skip(dst, 2);
 
             // protocol_id
-            dst.put_u8(0); // TODO: item.protocol_id as u8(0=Binary; 2=Compact)
-            dst.put_u8(0); // TODO: item.transform_ids_num
-
-            // TODO: item.transform_ids
-            // if let Some(ids) = &item.transform_ids {
-            //     dst.put_slice(ids);
-            // }
+            let protocol_id = match thrift_cx.extensions().get::<Protocol>() {
+                Some(Protocol::Compact) => ProtocolId::Compact,
+                _ => ProtocolId::Binary,
+            };
+            dst.put_u8(protocol_id as u8);
+            dst.put_u8(transform_ids.len() as u8);
+            dst.put_slice(transform_ids);
 
             let role = thrift_cx.rpc_info().role();
 
             // Write string KV start.
 
-            let has_string_kv = match role {
-                Role::Client => {
-                    metainfo.get_all_persistents().is_some()
-                        || metainfo.get_all_transients().is_some()
-                }
-                Role::Server => {
-                    metainfo.get_all_backward_transients().is_some()
-                        || thrift_cx.encode_conn_reset().unwrap_or(false)
-                }
-            };
+            let priority = thrift_cx.extensions().get::<Priority>().copied();
+            let crc32c = thrift_cx.extensions_mut().remove::<Crc32c>();
+            let has_string_kv = crc32c.is_some()
+                || match role {
+                    Role::Client => {
+                        metainfo.get_all_persistents().is_some()
+                            || metainfo.get_all_transients().is_some()
+                            || priority.is_some()
+                    }
+                    Role::Server => {
+                        metainfo.get_all_backward_transients().is_some()
+                            || thrift_cx.encode_conn_reset().unwrap_or(false)
+                    }
+                };
 
             if has_string_kv {
                 dst.put_u8(info::INFO_KEY_VALUE);
                 let string_kv_index = dst.len();
                 let mut string_kv_len = 0_u16;
-                dst.reserve(2);
-                unsafe {
-                    dst.advance_mut(2);
-                }
+                skip(dst, 2);
 
                 match role {
                     Role::Client => {
@@ -194,6 +231,14 @@ impl TTHeaderEncoder for DefaultTTHeaderCodec {
                                 string_kv_len += 1;
                             }
                         }
+                        if let Some(priority) = priority {
+                            let value = priority.as_str();
+                            dst.put_u16(HEADER_PRIORITY.len() as u16);
+                            dst.put_slice(HEADER_PRIORITY.as_bytes());
+                            dst.put_u16(value.len() as u16);
+                            dst.put_slice(value.as_bytes());
+                            string_kv_len += 1;
+                        }
                     }
                     Role::Server => {
                         if let Some(at) = metainfo.get_all_backward_transients() {
@@ -216,6 +261,14 @@ impl TTHeaderEncoder for DefaultTTHeaderCodec {
                         }
                     }
                 }
+                if let Some(Crc32c(crc32c)) = crc32c {
+                    let value = crc32c.to_string();
+                    dst.put_u16(HEADER_CRC32C.len() as u16);
+                    dst.put_slice(HEADER_CRC32C.as_bytes());
+                    dst.put_u16(value.len() as u16);
+                    dst.put_slice(value.as_bytes());
+                    string_kv_len += 1;
+                }
 
                 let mut buf = &mut dst[string_kv_index..string_kv_index + 2];
                 buf.put_u16(string_kv_len as u16);
@@ -225,10 +278,7 @@ impl TTHeaderEncoder for DefaultTTHeaderCodec {
             dst.put_u8(info::INFO_INT_KEY_VALUE);
             let int_kv_index = dst.len();
             let mut int_kv_len = 0_u16;
-            dst.reserve(2);
-            unsafe {
-                dst.advance_mut(2);
-            }
+            skip(dst, 2);
 
             match role {
                 Role::Server => {
@@ -248,6 +298,13 @@ impl TTHeaderEncoder for DefaultTTHeaderCodec {
                         int_kv_len += 1;
                     };
 
+                    // MsgType, so that the peer knows whether a response is expected
+                    let msg_type: u8 = thrift_cx.msg_type().into();
+                    dst.put_u16(IntMetaKey::MsgType as u16);
+                    dst.put_u16(1);
+                    dst.put_slice(&[msg_type]);
+                    int_kv_len += 1;
+
                     // WithHeader
                     dst.put_u16(IntMetaKey::WithHeader as u16);
                     dst.put_u16(1);
@@ -296,7 +353,8 @@ impl TTHeaderEncoder for DefaultTTHeaderCodec {
                         dst.put_slice(svc.as_bytes());
                         int_kv_len += 1;
 
-                        if let Some(addr) = callee.address() {
+                        // the path of unix socket is meaningless to the peer
+                        if let Some(addr @ volo::net::Address::Ip(_)) = callee.address() {
                             let addr = addr.to_string();
                             dst.put_u16(IntMetaKey::DestAddress as u16);
                             dst.put_u16(addr.len() as u16);
@@ -349,7 +407,14 @@ impl TTHeaderDecoder for DefaultTTHeaderCodec {
             let _flags = src.get_u16();
             let _sequence_id = src.get_u32();
             let header_size = src.get_u16();
-            let _protocol_id = src.get_u8();
+            let protocol_id = src.get_u8();
+            // the protocol of the payload is detected from the message itself
+            if protocol_id != ProtocolId::Binary as u8 && protocol_id != ProtocolId::Compact as u8 {
+                return Err(new_protocol_error(
+                    ProtocolErrorKind::NotImplemented,
+                    format!("unsupported ttheader protocol id: {}", protocol_id),
+                ));
+            }
             let transform_ids_num = src.get_u8();
             let mut _transform_ids = None;
             if transform_ids_num > 0 {
@@ -423,12 +488,14 @@ impl TTHeaderDecoder for DefaultTTHeaderCodec {
                             remaining_header_size -= value_len as usize;
                             let mut value = vec![0u8; value_len as usize];
                             src.copy_to_slice(&mut value);
-                            let key = IntMetaKey::try_from(key).map_err(|e| {
-                                new_protocol_error(
-                                    ProtocolErrorKind::InvalidData,
-                                    format!("invalid int meta key {}: {}", key, e),
-                                )
-                            })?;
+                            // peers may send keys we don't know yet, just skip them
+                            let key = match IntMetaKey::try_from(key) {
+                                Ok(key) => key,
+                                Err(_) => {
+                                    trace!("[VOLO] skip unknown ttheader int meta key: {}", key);
+                                    continue;
+                                }
+                            };
 
                             int_headers.insert(
                                 key,
@@ -459,6 +526,16 @@ impl TTHeaderDecoder for DefaultTTHeaderCodec {
                 }
             }
 
+            if let Some(crc32c) = headers.remove(HEADER_CRC32C) {
+                let crc32c = crc32c.parse().map_err(|_| {
+                    new_protocol_error(
+                        ProtocolErrorKind::InvalidData,
+                        format!("invalid ttheader payload checksum: {}", crc32c),
+                    )
+                })?;
+                thrift_cx.extensions_mut().insert(Crc32c(crc32c));
+            }
+
             let role = thrift_cx.rpc_info().role();
             match role {
                 Role::Client => {
@@ -534,9 +611,22 @@ impl TTHeaderDecoder for DefaultTTHeaderCodec {
                     {
                         config.set_rpc_timeout(Some(rpc_timeout));
                     }
+                    if let Some(Ok(conn_timeout)) = int_headers
+                        .get(&IntMetaKey::ConnTimeout)
+                        .map(|x| x.parse::<u64>().map(Duration::from_millis))
+                    {
+                        config.set_connect_timeout(Some(conn_timeout));
+                    }
 
                     thrift_cx.rpc_info_mut().config = Some(config);
 
+                    if let Some(Ok(priority)) = headers
+                        .remove(HEADER_PRIORITY)
+                        .map(|p| p.parse::<Priority>())
+                    {
+                        thrift_cx.extensions_mut().insert(priority);
+                    }
+
                     // Search for forward metainfo.
                     // We are not supposed to use headers, so we can use into_iter to avoid clone.
                     for (k, v) in headers.into_iter() {
@@ -553,6 +643,30 @@ impl TTHeaderDecoder for DefaultTTHeaderCodec {
     }
 }
 
+/// Returns whether the payload after the header is compressed by zlib, `header` starts from the
+/// magic.
+pub(crate) fn is_zlib_transformed(header: &[u8]) -> Result<bool, crate::Error> {
+    if header.len() < 12 || header.len() < 12 + header[11] as usize {
+        return Err(new_protocol_error(
+            ProtocolErrorKind::InvalidData,
+            "ttheader is too short".to_string(),
+        ));
+    }
+    let mut zlib = false;
+    for id in &header[12..12 + header[11] as usize] {
+        match *id {
+            transform::ZLIB => zlib = true,
+            id => {
+                return Err(new_protocol_error(
+                    ProtocolErrorKind::NotImplemented,
+                    format!("unsupported ttheader transform id: {}", id),
+                ))
+            }
+        }
+    }
+    Ok(zlib)
+}
+
 pub trait TTHeaderEncoder: Copy + Send + Sync + 'static {
     fn encode<Cx: ThriftContext>(
         &mut self,
@@ -560,6 +674,29 @@ pub trait TTHeaderEncoder: Copy + Send + Sync + 'static {
         dst: &mut BytesMut,
         size: usize,
     ) -> Result<usize, crate::Error>;
+
+    /// Encodes the header of a payload transformed by `transform_ids`, such as compressed by
+    /// zlib.
+    ///
+    /// By default only the payloads not transformed are supported.
+    fn encode_with_transforms<Cx: ThriftContext>(
+        &mut self,
+        cx: &mut Cx,
+        dst: &mut BytesMut,
+        size: usize,
+        transform_ids: &[u8],
+    ) -> Result<usize, crate::Error> {
+        if !transform_ids.is_empty() {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::NotImplemented,
+                format!(
+                    "the TTHeader transforms {:?} are not supported",
+                    transform_ids
+                ),
+            ));
+        }
+        self.encode(cx, dst, size)
+    }
 }
 
 pub trait TTHeaderDecoder: Copy + Send + Sync + 'static {
@@ -569,3 +706,228 @@ pub trait TTHeaderDecoder: Copy + Send + Sync + 'static {
         src: &mut BytesMut,
     ) -> Result<(), crate::Error>;
 }
+
+/// Skips `n` bytes of `dst`, to be filled later.
+#[inline]
+fn skip(dst: &mut BytesMut, n: usize) {
+    dst.reserve(n);
+    #[cfg(not(feature = "forbid-unsafe"))]
+    unsafe {
+        dst.advance_mut(n);
+    }
+    #[cfg(feature = "forbid-unsafe")]
+    dst.put_bytes(0, n);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use metainfo::{MetaInfo, METAINFO};
+    use volo::{
+        context::{Context, RpcInfo},
+        net::Address,
+    };
+
+    use super::*;
+    use crate::{
+        context::{ClientContext, ServerContext},
+        protocol::TMessageType,
+    };
+
+    /// A header of the int keys as sent by Kitex, starting from the magic.
+    fn int_header(entries: &[(u16, &str)]) -> BytesMut {
+        let mut header = vec![ProtocolId::Binary as u8, 0, info::INFO_INT_KEY_VALUE];
+        header.put_u16(entries.len() as u16);
+        for (key, value) in entries {
+            header.put_u16(*key);
+            header.put_u16(value.len() as u16);
+            header.put_slice(value.as_bytes());
+        }
+        // padded to the multiple of 4 bytes
+        while header.len() % 4 != 0 {
+            header.put_u8(info::INFO_PADDING);
+        }
+
+        let mut buf = BytesMut::new();
+        buf.put_u16(0x1000);
+        buf.put_u16(0);
+        buf.put_u32(1);
+        buf.put_u16((header.len() / 4) as u16);
+        buf.put_slice(&header);
+        buf
+    }
+
+    #[test]
+    fn test_decode_int_keys() {
+        let mut buf = int_header(&[
+            (IntMetaKey::FromService as u16, "caller"),
+            (IntMetaKey::ToService as u16, "item"),
+            // unknown to volo, skipped
+            (999, "unknown"),
+            (IntMetaKey::RPCTimeout as u16, "100"),
+            (IntMetaKey::ConnTimeout as u16, "50"),
+        ]);
+
+        let mut cx = ServerContext::default();
+        METAINFO.sync_scope(RefCell::new(MetaInfo::default()), || {
+            DefaultTTHeaderCodec.decode(&mut cx, &mut buf).unwrap();
+        });
+        // the whole header is read
+        assert!(buf.is_empty());
+        let rpc_info = cx.rpc_info();
+        assert_eq!(&*rpc_info.caller().unwrap().service_name, "caller");
+        assert_eq!(&*rpc_info.callee().unwrap().service_name, "item");
+        let config = rpc_info.config().unwrap();
+        assert_eq!(config.rpc_timeout(), Some(Duration::from_millis(100)));
+        assert_eq!(config.connect_timeout(), Some(Duration::from_millis(50)));
+    }
+
+    /// The int keys and values of the header encoded, starting from the magic.
+    fn int_keys(mut buf: BytesMut) -> HashMap<u16, Vec<u8>> {
+        buf.advance(8);
+        let header_size = buf.get_u16() as usize * 4;
+        let mut header = buf.split_to(header_size);
+        let transforms = header[1] as usize;
+        header.advance(2 + transforms);
+        let mut keys = HashMap::new();
+        while header.has_remaining() {
+            match header.get_u8() {
+                info::INFO_PADDING => {}
+                info::INFO_KEY_VALUE => {
+                    for _ in 0..header.get_u16() {
+                        let len = header.get_u16() as usize;
+                        header.advance(len);
+                        let len = header.get_u16() as usize;
+                        header.advance(len);
+                    }
+                }
+                info::INFO_INT_KEY_VALUE => {
+                    for _ in 0..header.get_u16() {
+                        let key = header.get_u16();
+                        let len = header.get_u16() as usize;
+                        keys.insert(key, header.split_to(len).to_vec());
+                    }
+                }
+                id => panic!("unexpected info id {}", id),
+            }
+        }
+        keys
+    }
+
+    #[test]
+    fn test_client_msg_type() {
+        for msg_type in [TMessageType::Call, TMessageType::OneWay] {
+            let mut cx = ClientContext::new(
+                1,
+                RpcInfo::new(
+                    Role::Client,
+                    "method".into(),
+                    Endpoint::new("caller".into()),
+                    Endpoint::new("callee".into()),
+                    Config::default(),
+                ),
+                msg_type,
+            );
+            let mut buf = BytesMut::new();
+            METAINFO.sync_scope(RefCell::new(MetaInfo::default()), || {
+                DefaultTTHeaderCodec.encode(&mut cx, &mut buf, 0).unwrap();
+            });
+            buf.advance(4);
+            let msg_type: u8 = msg_type.into();
+            assert_eq!(
+                int_keys(buf).get(&(IntMetaKey::MsgType as u16)),
+                Some(&vec![msg_type])
+            );
+        }
+    }
+
+    #[test]
+    fn test_client_destination() {
+        let encode = |address: Address| {
+            let mut callee = Endpoint::new("item".into());
+            callee.set_address(address);
+            let mut cx = ClientContext::new(
+                1,
+                RpcInfo::new(
+                    Role::Client,
+                    "method".into(),
+                    Endpoint::new("caller".into()),
+                    callee,
+                    Config::default(),
+                ),
+                TMessageType::Call,
+            );
+            let mut buf = BytesMut::new();
+            METAINFO.sync_scope(RefCell::new(MetaInfo::default()), || {
+                DefaultTTHeaderCodec.encode(&mut cx, &mut buf, 0).unwrap();
+            });
+            buf.advance(4);
+            int_keys(buf)
+        };
+
+        // the mesh proxy forwards the calls by them
+        let keys = encode(Address::Ip("10.0.0.1:8888".parse().unwrap()));
+        assert_eq!(
+            keys.get(&(IntMetaKey::ToService as u16)),
+            Some(&b"item".to_vec())
+        );
+        assert_eq!(
+            keys.get(&(IntMetaKey::DestAddress as u16)),
+            Some(&b"10.0.0.1:8888".to_vec())
+        );
+
+        let keys = encode(std::path::Path::new("/tmp/item.sock").into());
+        assert!(keys.contains_key(&(IntMetaKey::ToService as u16)));
+        assert!(!keys.contains_key(&(IntMetaKey::DestAddress as u16)));
+    }
+
+    #[tokio::test]
+    async fn test_metainfo_propagation() {
+        let mut upstream = MetaInfo::default();
+        upstream.set_persistent("p", "1");
+        upstream.set_transient("t", "2");
+
+        let mut buf = BytesMut::new();
+        METAINFO
+            .scope(RefCell::new(upstream), async {
+                let mut cx = ClientContext::new(
+                    1,
+                    RpcInfo::new(
+                        Role::Client,
+                        "method".into(),
+                        Endpoint::new("caller".into()),
+                        Endpoint::new("callee".into()),
+                        Config::default(),
+                    ),
+                    TMessageType::Call,
+                );
+                cx.extensions_mut().insert(Priority::Low);
+                DefaultTTHeaderCodec.encode(&mut cx, &mut buf, 0).unwrap();
+            })
+            .await;
+        // the length is read by the codec before decoding the header
+        buf.advance(4);
+
+        METAINFO
+            .scope(RefCell::new(MetaInfo::default()), async {
+                let mut cx = ServerContext::default();
+                DefaultTTHeaderCodec.decode(&mut cx, &mut buf).unwrap();
+                assert_eq!(cx.extensions().get::<Priority>(), Some(&Priority::Low));
+                METAINFO.with(|mi| {
+                    let mi = mi.borrow();
+                    assert_eq!(
+                        mi.get_persistent("p").map(|v| v.to_string()),
+                        Some("1".to_string())
+                    );
+                    assert_eq!(
+                        mi.get_upstream("t").map(|v| v.to_string()),
+                        Some("2".to_string())
+                    );
+                    // transient values are not forwarded to the next hop
+                    assert!(mi.get_all_transients().is_none());
+                });
+            })
+            .await;
+    }
+}
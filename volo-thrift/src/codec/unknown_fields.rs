@@ -0,0 +1,131 @@
+//! Detects the fields unknown to the local IDL, so that the schema drift between services can be
+//! found in production.
+//!
+//! The generated code skips the unknown fields when decoding, so they are detected by comparing
+//! the size of the payload with the size of the decoded message. In the [`DecodeMode::Lenient`]
+//! mode the message is accepted, [`UnknownFields`] is set into the context extensions and the
+//! process-wide [`stats`] are updated; in the [`DecodeMode::Strict`] mode the message is rejected.
+//!
+//! Only the framed protocols are checked, as the payload is read before decoding. The unframed
+//! binary messages are decoded from the connection directly and are always accepted.
+//!
+//! As the decoded message is measured as a whole, the unknown bytes are counted rather than the
+//! fields, and the unknown fields may be hidden if the absent fields are filled with default
+//! values larger than them at the same time.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tracing::warn;
+
+use crate::{context::ThriftContext, error::Result, new_protocol_error, ProtocolErrorKind};
+
+static MESSAGES: AtomicU64 = AtomicU64::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// How to decode the messages with fields unknown to the local IDL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecodeMode {
+    /// Skips the unknown fields and counts them.
+    #[default]
+    Lenient,
+    /// Rejects the messages with unknown fields by a protocol error.
+    Strict,
+}
+
+/// Set into the context extensions when the decoded message has unknown fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownFields {
+    /// The size of the unknown fields in the message.
+    pub bytes: usize,
+}
+
+/// The statistics of the messages with unknown fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of the messages with unknown fields.
+    pub messages: u64,
+    /// The total size of the unknown fields.
+    pub bytes: u64,
+}
+
+/// Returns the statistics of the unknown fields, counted across all the connections.
+pub fn stats() -> Stats {
+    Stats {
+        messages: MESSAGES.load(Ordering::Relaxed),
+        bytes: BYTES.load(Ordering::Relaxed),
+    }
+}
+
+/// Returns the size of the message header of the strict binary protocol starting `buf`, which is
+/// the version, the name and the sequence id.
+pub(crate) fn message_header_len(buf: &[u8]) -> Option<usize> {
+    let name_len = u32::from_be_bytes(buf.get(4..8)?.try_into().ok()?) as usize;
+    Some(12 + name_len)
+}
+
+/// Checks the payload of `payload` bytes, which is decoded into a message of `decoded` bytes.
+pub(crate) fn check<Cx: ThriftContext>(
+    mode: DecodeMode,
+    cx: &mut Cx,
+    payload: usize,
+    decoded: usize,
+) -> Result<()> {
+    if payload <= decoded {
+        return Ok(());
+    }
+    let bytes = payload - decoded;
+    let method = cx.rpc_info().method.clone().unwrap_or_default();
+    if mode == DecodeMode::Strict {
+        return Err(new_protocol_error(
+            ProtocolErrorKind::InvalidData,
+            format!(
+                "the message of {} has {} bytes of unknown fields",
+                method, bytes
+            ),
+        ));
+    }
+    warn!(
+        "[VOLO] the message of {} has {} bytes of unknown fields, the IDL may be outdated",
+        method, bytes
+    );
+    MESSAGES.fetch_add(1, Ordering::Relaxed);
+    BYTES.fetch_add(bytes as u64, Ordering::Relaxed);
+    cx.extensions_mut().insert(UnknownFields { bytes });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use volo::context::Context;
+
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut cx = crate::context::ServerContext::default();
+        check(DecodeMode::Strict, &mut cx, 10, 10).unwrap();
+        // the absent fields may be decoded as the default values
+        check(DecodeMode::Strict, &mut cx, 10, 12).unwrap();
+        assert!(cx.extensions().get::<UnknownFields>().is_none());
+
+        check(DecodeMode::Strict, &mut cx, 12, 10).unwrap_err();
+
+        let before = stats();
+        check(DecodeMode::Lenient, &mut cx, 12, 10).unwrap();
+        assert_eq!(
+            cx.extensions().get::<UnknownFields>(),
+            Some(&UnknownFields { bytes: 2 })
+        );
+        assert!(stats().messages > before.messages);
+    }
+
+    #[test]
+    fn test_message_header_len() {
+        // version, name length, name, seq id
+        let buf = [
+            0x80, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, b'f', b'o', b'o', 0, 0, 0, 1,
+        ];
+        assert_eq!(message_header_len(&buf), Some(15));
+        assert_eq!(message_header_len(&buf[..4]), None);
+    }
+}
@@ -6,7 +6,10 @@ use volo::{
     newtype_impl_context,
 };
 
-use crate::protocol::TMessageType;
+use crate::{
+    codec::{unknown_fields::DecodeMode, Protocol},
+    protocol::TMessageType,
+};
 
 #[derive(Default, Clone, Debug)]
 pub struct ServerTransportInfo {
@@ -45,6 +48,8 @@ pub struct ClientCxInner {
     pub seq_id: i32,
     pub message_type: TMessageType,
     pub transport: PooledTransport,
+    /// The service name prefixed to the method name, used by `TMultiplexedProtocol`.
+    pub multiplexed_service: Option<smol_str::SmolStr>,
 }
 
 pub struct ServerCxInner {
@@ -52,6 +57,8 @@ pub struct ServerCxInner {
     pub req_msg_type: Option<TMessageType>,
     pub msg_type: Option<TMessageType>,
     pub transport: ServerTransportInfo,
+    /// The service name requested by a `TMultiplexedProtocol` peer, if any.
+    pub multiplexed_service: Option<smol_str::SmolStr>,
 }
 
 pub struct ClientContext(pub(crate) volo::context::RpcCx<ClientCxInner, Config>);
@@ -66,6 +73,7 @@ impl ClientContext {
                 seq_id,
                 message_type: msg_type,
                 transport: PooledTransport { should_reuse: true },
+                multiplexed_service: None,
             },
         ))
     }
@@ -85,6 +93,13 @@ impl std::ops::DerefMut for ClientContext {
     }
 }
 
+/// The context of a server request.
+///
+/// The metainfo sent by the upstream with TTHeader is stored in the task local
+/// [`metainfo::METAINFO`] while the request is handled, as Kitex does: the persistent values
+/// can be read by `get_persistent`, and the transient values by `get_upstream`. The persistent
+/// values are forwarded automatically by the client calls made in the same task, while the
+/// transient ones only go one hop.
 pub struct ServerContext(pub(crate) volo::context::RpcCx<ServerCxInner, Config>);
 
 impl Default for ServerContext {
@@ -96,6 +111,7 @@ impl Default for ServerContext {
                 req_msg_type: None,
                 msg_type: None,
                 transport: ServerTransportInfo::default(),
+                multiplexed_service: None,
             },
         ))
     }
@@ -121,6 +137,7 @@ pub trait ThriftContext: volo::context::Context<Config = Config> + Send + 'stati
     fn encode_conn_reset(&self) -> Option<bool>;
     fn set_conn_reset_by_ttheader(&mut self, reset: bool);
     fn handle_decoded_msg_ident(&mut self, ident: &TMessageIdentifier);
+    fn handle_multiplexed_service(&mut self, service: smol_str::SmolStr);
     fn seq_id(&self) -> i32;
     fn msg_type(&self) -> TMessageType;
 }
@@ -139,6 +156,9 @@ impl ThriftContext for ClientContext {
     #[inline]
     fn handle_decoded_msg_ident(&mut self, _ident: &TMessageIdentifier) {}
 
+    #[inline]
+    fn handle_multiplexed_service(&mut self, _service: smol_str::SmolStr) {}
+
     #[inline]
     fn seq_id(&self) -> i32 {
         self.seq_id
@@ -166,6 +186,11 @@ impl ThriftContext for ServerContext {
         self.rpc_info.method = Some(ident.name.clone());
     }
 
+    #[inline]
+    fn handle_multiplexed_service(&mut self, service: smol_str::SmolStr) {
+        self.multiplexed_service = Some(service);
+    }
+
     #[inline]
     fn seq_id(&self) -> i32 {
         self.seq_id.unwrap_or(0)
@@ -189,6 +214,10 @@ pub struct Config {
     connect_timeout: Option<Duration>,
     read_write_timeout: Option<Duration>,
     max_frame_size: u32,
+    decode_mode: DecodeMode,
+    payload_checksum: bool,
+    verify_checksum: bool,
+    protocol: Protocol,
 }
 
 impl Config {
@@ -198,6 +227,10 @@ impl Config {
             connect_timeout: None,
             read_write_timeout: None,
             max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            decode_mode: DecodeMode::Lenient,
+            payload_checksum: false,
+            verify_checksum: true,
+            protocol: Protocol::Binary,
         }
     }
 
@@ -251,8 +284,58 @@ impl Config {
         self.max_frame_size = size
     }
 
+    pub fn decode_mode(&self) -> DecodeMode {
+        self.decode_mode
+    }
+
+    pub(crate) fn set_decode_mode(&mut self, decode_mode: DecodeMode) {
+        self.decode_mode = decode_mode
+    }
+
+    /// Whether to checksum the TTHeader payloads of the requests.
+    pub fn payload_checksum(&self) -> bool {
+        self.payload_checksum
+    }
+
+    pub(crate) fn set_payload_checksum(&mut self, enable: bool) {
+        self.payload_checksum = enable
+    }
+
+    /// Whether to verify the checksums of the TTHeader payloads of the responses.
+    pub fn verify_checksum(&self) -> bool {
+        self.verify_checksum
+    }
+
+    pub(crate) fn set_verify_checksum(&mut self, enable: bool) {
+        self.verify_checksum = enable
+    }
+
+    /// The protocol to encode the requests.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub(crate) fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol
+    }
+
     pub fn merge(&mut self, other: Self) {
-        self.max_frame_size = other.max_frame_size;
+        // the default one is not set explicitly, so don't override the configured one
+        if other.max_frame_size != DEFAULT_MAX_FRAME_SIZE {
+            self.max_frame_size = other.max_frame_size;
+        }
+        if other.decode_mode != DecodeMode::Lenient {
+            self.decode_mode = other.decode_mode;
+        }
+        if other.payload_checksum {
+            self.payload_checksum = true;
+        }
+        if !other.verify_checksum {
+            self.verify_checksum = false;
+        }
+        if other.protocol != Protocol::Binary {
+            self.protocol = other.protocol;
+        }
         if let Some(t) = other.rpc_timeout {
             self.rpc_timeout = Some(t);
         }
@@ -272,6 +355,10 @@ impl Default for Config {
             connect_timeout: None,
             read_write_timeout: None,
             max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            decode_mode: DecodeMode::Lenient,
+            payload_checksum: false,
+            verify_checksum: true,
+            protocol: Protocol::Binary,
         }
     }
 }
@@ -0,0 +1,263 @@
+//! The runtime descriptors of the thrift types and services, generated when the
+//! `descriptors` option of `volo-build` is enabled.
+//!
+//! A descriptor has the names, the field ids and the types as in the IDL, so that the generic
+//! tools, such as the gateways, the schema-aware logging or the conversion to JSON, can walk the
+//! generated types without being compiled against each IDL.
+//!
+//! The struct and enum types refer to each other by the accessors, instead of the descriptors
+//! themselves, so that the recursive types can be described.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_thrift::descriptor::{DescribeService, DescribeStruct};
+//!
+//! let service = ItemServiceClient::service_descriptor();
+//! let method = service.method("GetItem").unwrap();
+//! for field in method.args {
+//!     println!("{}: {} {:?}", field.id, field.name, field.ty);
+//! }
+//! let item = Item::struct_descriptor();
+//! assert_eq!(item.field_by_name("id").unwrap().id, 1);
+//! ```
+
+use std::fmt;
+
+use crate::protocol::TType;
+
+/// The type of a field, an argument or a result.
+#[derive(Clone, Copy)]
+pub enum TypeDescriptor {
+    Void,
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    Double,
+    String,
+    Binary,
+    List(&'static TypeDescriptor),
+    Set(&'static TypeDescriptor),
+    Map(&'static TypeDescriptor, &'static TypeDescriptor),
+    /// A struct, an exception or a union.
+    Struct(fn() -> &'static StructDescriptor),
+    Enum(fn() -> &'static EnumDescriptor),
+}
+
+impl TypeDescriptor {
+    /// Returns the type on the wire.
+    pub fn ttype(&self) -> TType {
+        match self {
+            TypeDescriptor::Void => TType::Void,
+            TypeDescriptor::Bool => TType::Bool,
+            TypeDescriptor::I8 => TType::I08,
+            TypeDescriptor::I16 => TType::I16,
+            TypeDescriptor::I32 | TypeDescriptor::Enum(_) => TType::I32,
+            TypeDescriptor::I64 => TType::I64,
+            TypeDescriptor::Double => TType::Double,
+            TypeDescriptor::String | TypeDescriptor::Binary => TType::String,
+            TypeDescriptor::List(_) => TType::List,
+            TypeDescriptor::Set(_) => TType::Set,
+            TypeDescriptor::Map(..) => TType::Map,
+            TypeDescriptor::Struct(_) => TType::Struct,
+        }
+    }
+}
+
+// the referred types are printed by their names, or the recursive types never end
+impl fmt::Debug for TypeDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeDescriptor::Void => f.write_str("void"),
+            TypeDescriptor::Bool => f.write_str("bool"),
+            TypeDescriptor::I8 => f.write_str("i8"),
+            TypeDescriptor::I16 => f.write_str("i16"),
+            TypeDescriptor::I32 => f.write_str("i32"),
+            TypeDescriptor::I64 => f.write_str("i64"),
+            TypeDescriptor::Double => f.write_str("double"),
+            TypeDescriptor::String => f.write_str("string"),
+            TypeDescriptor::Binary => f.write_str("binary"),
+            TypeDescriptor::List(elem) => write!(f, "list<{:?}>", elem),
+            TypeDescriptor::Set(elem) => write!(f, "set<{:?}>", elem),
+            TypeDescriptor::Map(key, value) => write!(f, "map<{:?}, {:?}>", key, value),
+            TypeDescriptor::Struct(desc) => f.write_str(desc().name),
+            TypeDescriptor::Enum(desc) => f.write_str(desc().name),
+        }
+    }
+}
+
+/// A field of a struct, or an argument or an exception of a method.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldDescriptor {
+    pub id: i16,
+    pub name: &'static str,
+    pub ty: TypeDescriptor,
+    /// Whether the field is required, the fields of the unions are never required.
+    pub required: bool,
+}
+
+/// A struct, an exception or a union.
+#[derive(Debug)]
+pub struct StructDescriptor {
+    /// The full name, with the namespace of the IDL if any.
+    pub name: &'static str,
+    pub fields: &'static [FieldDescriptor],
+    pub is_union: bool,
+}
+
+impl StructDescriptor {
+    /// Returns the field of the id.
+    pub fn field(&self, id: i16) -> Option<&'static FieldDescriptor> {
+        self.fields.iter().find(|f| f.id == id)
+    }
+
+    /// Returns the field named in the IDL.
+    pub fn field_by_name(&self, name: &str) -> Option<&'static FieldDescriptor> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+}
+
+/// An enum, whose values are encoded as `i32`.
+#[derive(Debug)]
+pub struct EnumDescriptor {
+    /// The full name, with the namespace of the IDL if any.
+    pub name: &'static str,
+    pub values: &'static [(&'static str, i32)],
+}
+
+impl EnumDescriptor {
+    /// Returns the name of the value.
+    pub fn name_of(&self, value: i32) -> Option<&'static str> {
+        self.values
+            .iter()
+            .find(|(_, v)| *v == value)
+            .map(|(n, _)| *n)
+    }
+
+    /// Returns the value of the name.
+    pub fn value_of(&self, name: &str) -> Option<i32> {
+        self.values
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| *v)
+    }
+}
+
+/// A method of a service.
+#[derive(Debug)]
+pub struct MethodDescriptor {
+    pub name: &'static str,
+    pub oneway: bool,
+    pub args: &'static [FieldDescriptor],
+    /// The type of the result, [`TypeDescriptor::Void`] if the method returns nothing.
+    pub result: TypeDescriptor,
+    /// The exceptions declared by `throws`, with their ids in the result struct.
+    pub exceptions: &'static [FieldDescriptor],
+}
+
+/// A service, with the methods of the services it extends.
+#[derive(Debug)]
+pub struct ServiceDescriptor {
+    /// The full name, with the namespace of the IDL if any.
+    pub name: &'static str,
+    pub methods: &'static [MethodDescriptor],
+}
+
+impl ServiceDescriptor {
+    /// Returns the method named in the IDL.
+    pub fn method(&self, name: &str) -> Option<&'static MethodDescriptor> {
+        self.methods.iter().find(|m| m.name == name)
+    }
+}
+
+/// Implemented by the generated structs, exceptions and unions.
+pub trait DescribeStruct {
+    fn struct_descriptor() -> &'static StructDescriptor;
+}
+
+/// Implemented by the generated enums.
+pub trait DescribeEnum {
+    fn enum_descriptor() -> &'static EnumDescriptor;
+}
+
+/// Implemented by the generated clients and servers.
+pub trait DescribeService {
+    fn service_descriptor() -> &'static ServiceDescriptor;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // struct Node { 1: required string name, 2: optional list<Node> children, 3: Kind kind }
+    struct Node;
+
+    impl DescribeStruct for Node {
+        fn struct_descriptor() -> &'static StructDescriptor {
+            static DESCRIPTOR: StructDescriptor = StructDescriptor {
+                name: "example.Node",
+                fields: &[
+                    FieldDescriptor {
+                        id: 1,
+                        name: "name",
+                        ty: TypeDescriptor::String,
+                        required: true,
+                    },
+                    FieldDescriptor {
+                        id: 2,
+                        name: "children",
+                        ty: TypeDescriptor::List(&TypeDescriptor::Struct(
+                            <Node as DescribeStruct>::struct_descriptor,
+                        )),
+                        required: false,
+                    },
+                    FieldDescriptor {
+                        id: 3,
+                        name: "kind",
+                        ty: TypeDescriptor::Enum(<Kind as DescribeEnum>::enum_descriptor),
+                        required: false,
+                    },
+                ],
+                is_union: false,
+            };
+            &DESCRIPTOR
+        }
+    }
+
+    struct Kind;
+
+    impl DescribeEnum for Kind {
+        fn enum_descriptor() -> &'static EnumDescriptor {
+            static DESCRIPTOR: EnumDescriptor = EnumDescriptor {
+                name: "example.Kind",
+                values: &[("LEAF", 0), ("BRANCH", 1)],
+            };
+            &DESCRIPTOR
+        }
+    }
+
+    #[test]
+    fn test_descriptor() {
+        let node = Node::struct_descriptor();
+        assert_eq!(node.field(1).unwrap().name, "name");
+        let children = node.field_by_name("children").unwrap();
+        assert_eq!(children.ty.ttype(), TType::List);
+        assert_eq!(format!("{:?}", children.ty), "list<example.Node>");
+        match children.ty {
+            TypeDescriptor::List(TypeDescriptor::Struct(desc)) => {
+                assert!(std::ptr::eq(desc(), node))
+            }
+            _ => panic!("unexpected type {:?}", children.ty),
+        }
+
+        let kind = match node.field(3).unwrap().ty {
+            TypeDescriptor::Enum(desc) => desc(),
+            _ => panic!(),
+        };
+        assert_eq!(kind.name_of(1), Some("BRANCH"));
+        assert_eq!(kind.value_of("LEAF"), Some(0));
+        assert!(node.field(4).is_none());
+    }
+}
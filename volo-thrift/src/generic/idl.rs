@@ -0,0 +1,748 @@
+//! A runtime parser of the thrift IDL, which keeps only what the generic call needs: the
+//! structs, enums, typedefs and services.
+//!
+//! Constants and default values are parsed but dropped. Includes are not followed, so all the
+//! files should be added to the same [`Idl`], and the types are looked up by their names without
+//! the include prefix.
+
+use std::{collections::HashMap, fmt};
+
+/// The error of parsing an IDL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Ty {
+    Void,
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    Double,
+    String,
+    Binary,
+    List(Box<Ty>),
+    Set(Box<Ty>),
+    Map(Box<Ty>, Box<Ty>),
+    Named(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Field {
+    pub(crate) id: i16,
+    pub(crate) name: String,
+    pub(crate) ty: Ty,
+    pub(crate) required: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Struct {
+    pub(crate) name: String,
+    pub(crate) fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Enum {
+    pub(crate) name: String,
+    pub(crate) values: Vec<(String, i32)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Function {
+    pub(crate) name: String,
+    pub(crate) oneway: bool,
+    pub(crate) result: Ty,
+    pub(crate) args: Vec<Field>,
+    pub(crate) throws: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Service {
+    pub(crate) name: String,
+    pub(crate) extends: Option<String>,
+    pub(crate) functions: Vec<Function>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Definition {
+    Struct(Struct),
+    Enum(Enum),
+    Typedef(Ty),
+}
+
+/// A type with the typedefs resolved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Resolved<'a> {
+    Void,
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    Double,
+    String,
+    Binary,
+    List(&'a Ty),
+    Set(&'a Ty),
+    Map(&'a Ty, &'a Ty),
+    Struct(&'a Struct),
+    Enum(&'a Enum),
+}
+
+// typedefs may refer to each other, so limit the steps to resolve a type
+const MAX_TYPEDEF_DEPTH: usize = 32;
+
+/// The definitions of one or more IDL files.
+#[derive(Debug, Clone, Default)]
+pub struct Idl {
+    definitions: HashMap<String, Definition>,
+    services: HashMap<String, Service>,
+}
+
+impl Idl {
+    /// Parses an IDL file.
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let mut idl = Self::default();
+        idl.add(source)?;
+        Ok(idl)
+    }
+
+    /// Adds the definitions of another IDL file, such as the included ones.
+    pub fn add(&mut self, source: &str) -> Result<(), ParseError> {
+        let tokens = tokenize(source)?;
+        Parser {
+            tokens,
+            pos: 0,
+            idl: self,
+        }
+        .parse_document()
+    }
+
+    /// Returns whether the service is defined.
+    pub fn has_service(&self, name: &str) -> bool {
+        self.services.contains_key(base_name(name))
+    }
+
+    /// Finds the function of the service, including the ones inherited by `extends`.
+    pub(crate) fn function(&self, service: &str, method: &str) -> Option<&Function> {
+        let mut service = self.services.get(base_name(service));
+        // the steps are limited in case the services extend each other
+        for _ in 0..MAX_TYPEDEF_DEPTH {
+            let s = service?;
+            if let Some(f) = s.functions.iter().find(|f| f.name == method) {
+                return Some(f);
+            }
+            service = self.services.get(base_name(s.extends.as_deref()?));
+        }
+        None
+    }
+
+    pub(crate) fn resolve<'a>(&'a self, ty: &'a Ty) -> Result<Resolved<'a>, String> {
+        let mut ty = ty;
+        for _ in 0..MAX_TYPEDEF_DEPTH {
+            let resolved = match ty {
+                Ty::Void => Resolved::Void,
+                Ty::Bool => Resolved::Bool,
+                Ty::I8 => Resolved::I8,
+                Ty::I16 => Resolved::I16,
+                Ty::I32 => Resolved::I32,
+                Ty::I64 => Resolved::I64,
+                Ty::Double => Resolved::Double,
+                Ty::String => Resolved::String,
+                Ty::Binary => Resolved::Binary,
+                Ty::List(t) => Resolved::List(t),
+                Ty::Set(t) => Resolved::Set(t),
+                Ty::Map(k, v) => Resolved::Map(k, v),
+                Ty::Named(name) => match self.definitions.get(base_name(name)) {
+                    Some(Definition::Struct(s)) => Resolved::Struct(s),
+                    Some(Definition::Enum(e)) => Resolved::Enum(e),
+                    Some(Definition::Typedef(t)) => {
+                        ty = t;
+                        continue;
+                    }
+                    None => return Err(format!("unknown type {}", name)),
+                },
+            };
+            return Ok(resolved);
+        }
+        Err("too many levels of typedef".to_string())
+    }
+}
+
+/// Strips the include prefix, such as `base` of `base.Item`.
+fn base_name(name: &str) -> &str {
+    name.rsplit('.').next().unwrap_or(name)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(i64),
+    Double(f64),
+    Literal(String),
+    Punct(char),
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => {
+                while chars.peek().map_or(false, |&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '/' => {
+                chars.next();
+                match chars.next() {
+                    Some('/') => {
+                        while chars.peek().map_or(false, |&c| c != '\n') {
+                            chars.next();
+                        }
+                    }
+                    Some('*') => {
+                        let mut prev = ' ';
+                        loop {
+                            match chars.next() {
+                                Some('/') if prev == '*' => break,
+                                Some(c) => {
+                                    if c == '\n' {
+                                        line += 1;
+                                    }
+                                    prev = c;
+                                }
+                                None => {
+                                    return Err(ParseError {
+                                        line,
+                                        message: "unterminated comment".to_string(),
+                                    })
+                                }
+                            }
+                        }
+                    }
+                    _ => {
+                        return Err(ParseError {
+                            line,
+                            message: "unexpected character '/'".to_string(),
+                        })
+                    }
+                }
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut literal = String::new();
+                loop {
+                    match chars.next() {
+                        Some(ch) if ch == c => break,
+                        Some('\\') => {
+                            if let Some(escaped) = chars.next() {
+                                literal.push(escaped);
+                            }
+                        }
+                        Some(ch) => {
+                            if ch == '\n' {
+                                line += 1;
+                            }
+                            literal.push(ch);
+                        }
+                        None => {
+                            return Err(ParseError {
+                                line,
+                                message: "unterminated literal".to_string(),
+                            })
+                        }
+                    }
+                }
+                tokens.push((Token::Literal(literal), line));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+                        ident.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push((Token::Ident(ident), line));
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' => {
+                let mut number = String::new();
+                number.push(c);
+                chars.next();
+                while let Some(&c) = chars.peek() {
+                    let exponent_sign = (c == '-' || c == '+')
+                        && !number.starts_with("0x")
+                        && number.ends_with(|c| c == 'e' || c == 'E');
+                    if c.is_ascii_alphanumeric() || c == '.' || exponent_sign {
+                        number.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push((parse_number(&number, line)?, line));
+            }
+            '{' | '}' | '(' | ')' | '<' | '>' | '[' | ']' | ',' | ';' | ':' | '=' | '*' => {
+                chars.next();
+                tokens.push((Token::Punct(c), line));
+            }
+            c => {
+                return Err(ParseError {
+                    line,
+                    message: format!("unexpected character {:?}", c),
+                })
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_number(number: &str, line: usize) -> Result<Token, ParseError> {
+    let (negative, digits) = match number.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, number.strip_prefix('+').unwrap_or(number)),
+    };
+    let int = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => digits.parse::<i64>().ok(),
+    };
+    if let Some(int) = int {
+        return Ok(Token::Int(if negative { -int } else { int }));
+    }
+    number
+        .parse::<f64>()
+        .map(Token::Double)
+        .map_err(|_| ParseError {
+            line,
+            message: format!("invalid number {}", number),
+        })
+}
+
+struct Parser<'a> {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    idl: &'a mut Idl,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        let line = self
+            .tokens
+            .get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map_or(1, |(_, line)| *line);
+        Err(ParseError {
+            line,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(t, _)| t.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn eat_punct(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_punct(&mut self, c: char) -> Result<(), ParseError> {
+        if self.eat_punct(c) {
+            return Ok(());
+        }
+        self.error(format!("expected '{}'", c))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(ident)) if ident == keyword) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_ident(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Token::Ident(ident)) => {
+                let ident = ident.clone();
+                self.pos += 1;
+                Ok(ident)
+            }
+            _ => self.error("expected an identifier"),
+        }
+    }
+
+    fn expect_literal(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Token::Literal(literal)) => {
+                let literal = literal.clone();
+                self.pos += 1;
+                Ok(literal)
+            }
+            _ => self.error("expected a literal"),
+        }
+    }
+
+    fn eat_separator(&mut self) {
+        let _ = self.eat_punct(',') || self.eat_punct(';');
+    }
+
+    fn skip_annotations(&mut self) -> Result<(), ParseError> {
+        if !self.eat_punct('(') {
+            return Ok(());
+        }
+        while !self.eat_punct(')') {
+            self.expect_ident()?;
+            if self.eat_punct('=') {
+                self.expect_literal()?;
+            }
+            self.eat_separator();
+        }
+        Ok(())
+    }
+
+    fn skip_const_value(&mut self) -> Result<(), ParseError> {
+        match self.next() {
+            Some(Token::Int(_) | Token::Double(_) | Token::Literal(_) | Token::Ident(_)) => Ok(()),
+            Some(Token::Punct('[')) => {
+                while !self.eat_punct(']') {
+                    self.skip_const_value()?;
+                    self.eat_separator();
+                }
+                Ok(())
+            }
+            Some(Token::Punct('{')) => {
+                while !self.eat_punct('}') {
+                    self.skip_const_value()?;
+                    self.expect_punct(':')?;
+                    self.skip_const_value()?;
+                    self.eat_separator();
+                }
+                Ok(())
+            }
+            _ => {
+                self.pos -= 1;
+                self.error("expected a constant value")
+            }
+        }
+    }
+
+    fn parse_document(&mut self) -> Result<(), ParseError> {
+        while self.peek().is_some() {
+            let keyword = self.expect_ident()?;
+            match keyword.as_str() {
+                "include" | "cpp_include" => {
+                    self.expect_literal()?;
+                }
+                "namespace" => {
+                    if !self.eat_punct('*') {
+                        self.expect_ident()?;
+                    }
+                    self.expect_ident()?;
+                    self.skip_annotations()?;
+                }
+                "typedef" => {
+                    let ty = self.parse_type()?;
+                    let name = self.expect_ident()?;
+                    self.skip_annotations()?;
+                    self.idl.definitions.insert(name, Definition::Typedef(ty));
+                }
+                "const" => {
+                    self.parse_type()?;
+                    self.expect_ident()?;
+                    self.expect_punct('=')?;
+                    self.skip_const_value()?;
+                }
+                "enum" => {
+                    let e = self.parse_enum()?;
+                    self.idl
+                        .definitions
+                        .insert(e.name.clone(), Definition::Enum(e));
+                }
+                "struct" | "union" | "exception" => {
+                    let s = self.parse_struct()?;
+                    self.idl
+                        .definitions
+                        .insert(s.name.clone(), Definition::Struct(s));
+                }
+                "service" => {
+                    let s = self.parse_service()?;
+                    self.idl.services.insert(s.name.clone(), s);
+                }
+                keyword => {
+                    self.pos -= 1;
+                    return self.error(format!("unexpected {}", keyword));
+                }
+            }
+            self.eat_separator();
+        }
+        Ok(())
+    }
+
+    fn parse_type(&mut self) -> Result<Ty, ParseError> {
+        let name = self.expect_ident()?;
+        let ty = match name.as_str() {
+            "void" => Ty::Void,
+            "bool" => Ty::Bool,
+            "byte" | "i8" => Ty::I8,
+            "i16" => Ty::I16,
+            "i32" => Ty::I32,
+            "i64" => Ty::I64,
+            "double" => Ty::Double,
+            "string" => Ty::String,
+            "binary" => Ty::Binary,
+            "list" | "set" | "map" => {
+                if self.eat_keyword("cpp_type") {
+                    self.expect_literal()?;
+                }
+                self.expect_punct('<')?;
+                let first = Box::new(self.parse_type()?);
+                let ty = match name.as_str() {
+                    "list" => Ty::List(first),
+                    "set" => Ty::Set(first),
+                    _ => {
+                        self.expect_punct(',')?;
+                        Ty::Map(first, Box::new(self.parse_type()?))
+                    }
+                };
+                self.expect_punct('>')?;
+                ty
+            }
+            _ => Ty::Named(name),
+        };
+        self.skip_annotations()?;
+        Ok(ty)
+    }
+
+    fn parse_enum(&mut self) -> Result<Enum, ParseError> {
+        let name = self.expect_ident()?;
+        self.expect_punct('{')?;
+        let mut values = Vec::new();
+        let mut next = 0;
+        while !self.eat_punct('}') {
+            let value_name = self.expect_ident()?;
+            if self.eat_punct('=') {
+                match self.next() {
+                    Some(Token::Int(v)) => next = v as i32,
+                    _ => {
+                        self.pos -= 1;
+                        return self.error("expected an enum value");
+                    }
+                }
+            }
+            values.push((value_name, next));
+            next = next.wrapping_add(1);
+            self.skip_annotations()?;
+            self.eat_separator();
+        }
+        self.skip_annotations()?;
+        Ok(Enum { name, values })
+    }
+
+    fn parse_fields(&mut self, end: char) -> Result<Vec<Field>, ParseError> {
+        let mut fields = Vec::new();
+        // the fields without an id are numbered from -1 downwards
+        let mut implicit_id = 0;
+        while !self.eat_punct(end) {
+            let id = match (self.peek(), self.tokens.get(self.pos + 1)) {
+                (Some(Token::Int(id)), Some((Token::Punct(':'), _))) => {
+                    let id = i16::try_from(*id)
+                        .map_or_else(|_| self.error(format!("invalid field id {}", id)), Ok)?;
+                    self.pos += 2;
+                    id
+                }
+                _ => {
+                    implicit_id -= 1;
+                    implicit_id
+                }
+            };
+            let required = self.eat_keyword("required");
+            if !required {
+                self.eat_keyword("optional");
+            }
+            let ty = self.parse_type()?;
+            let name = self.expect_ident()?;
+            if self.eat_punct('=') {
+                self.skip_const_value()?;
+            }
+            self.skip_annotations()?;
+            self.eat_separator();
+            fields.push(Field {
+                id,
+                name,
+                ty,
+                required,
+            });
+        }
+        Ok(fields)
+    }
+
+    fn parse_struct(&mut self) -> Result<Struct, ParseError> {
+        let name = self.expect_ident()?;
+        self.eat_keyword("xsd_all");
+        self.expect_punct('{')?;
+        let fields = self.parse_fields('}')?;
+        self.skip_annotations()?;
+        Ok(Struct { name, fields })
+    }
+
+    fn parse_service(&mut self) -> Result<Service, ParseError> {
+        let name = self.expect_ident()?;
+        let extends = if self.eat_keyword("extends") {
+            Some(self.expect_ident()?)
+        } else {
+            None
+        };
+        self.expect_punct('{')?;
+        let mut functions = Vec::new();
+        while !self.eat_punct('}') {
+            let oneway = self.eat_keyword("oneway") || self.eat_keyword("async");
+            let result = self.parse_type()?;
+            let name = self.expect_ident()?;
+            self.expect_punct('(')?;
+            let args = self.parse_fields(')')?;
+            let throws = if self.eat_keyword("throws") {
+                self.expect_punct('(')?;
+                self.parse_fields(')')?
+            } else {
+                Vec::new()
+            };
+            self.skip_annotations()?;
+            self.eat_separator();
+            functions.push(Function {
+                name,
+                oneway,
+                result,
+                args,
+                throws,
+            });
+        }
+        self.skip_annotations()?;
+        Ok(Service {
+            name,
+            extends,
+            functions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDL: &str = r#"
+        namespace rs volo.example
+        include "base.thrift"
+
+        /* the status of an item */
+        enum Status {
+            ONLINE = 1,
+            OFFLINE, // 2
+        }
+
+        typedef map<string, list<i64>> Tags
+        const i32 MAX = 0x10;
+        const list<string> NAMES = ["a", "b"];
+
+        struct Item {
+            1: required i64 id,
+            2: optional string title = "untitled" (go.tag = "json:\"title\""),
+            3: Status status,
+            4: Tags tags,
+            5: base.Extra extra,
+        }
+
+        exception NotFound {
+            1: string message;
+        }
+
+        service BaseService {
+            void ping()
+        }
+
+        service ItemService extends base.BaseService {
+            Item GetItem(1: i64 id) throws (1: NotFound not_found),
+            oneway void Report(1: Item item, string note)
+        } (version = "1")
+    "#;
+
+    #[test]
+    fn test_parse() {
+        let idl = Idl::parse(IDL).unwrap();
+        assert!(idl.has_service("ItemService"));
+
+        let f = idl.function("ItemService", "GetItem").unwrap();
+        assert!(!f.oneway);
+        assert_eq!(f.result, Ty::Named("Item".to_string()));
+        assert_eq!(f.args[0].id, 1);
+        assert_eq!(f.throws[0].name, "not_found");
+
+        let f = idl.function("ItemService", "Report").unwrap();
+        assert!(f.oneway);
+        assert_eq!(f.args[1].id, -1);
+        assert!(idl.function("ItemService", "ping").is_some());
+        assert!(idl.function("ItemService", "Unknown").is_none());
+
+        let ty = Ty::Named("Item".to_string());
+        let item = match idl.resolve(&ty).unwrap() {
+            Resolved::Struct(s) => s,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert!(item.fields[0].required);
+        assert!(!item.fields[1].required);
+        match idl.resolve(&item.fields[2].ty).unwrap() {
+            Resolved::Enum(e) => assert_eq!(
+                e.values,
+                vec![("ONLINE".to_string(), 1), ("OFFLINE".to_string(), 2)]
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            idl.resolve(&item.fields[3].ty).unwrap(),
+            Resolved::Map(Ty::String, Ty::List(_))
+        ));
+        // defined in another file
+        idl.resolve(&item.fields[4].ty).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let err = Idl::parse("struct A {\n 1: i32 a\n 2: }").unwrap_err();
+        assert_eq!(err.line, 3);
+    }
+}
@@ -0,0 +1,544 @@
+//! JSON generic call, which converts the JSON requests into thrift by an [`Idl`] loaded at
+//! runtime, and the responses back to JSON.
+//!
+//! The values are mapped as below:
+//!
+//! - `bool`, the integers and `double` are JSON booleans and numbers, and the integers can also be
+//!   strings, since JSON numbers may lose the precision of `i64`.
+//! - `string` is a string, and `binary` is a base64 string.
+//! - An enum is its number, or its name when converted from JSON.
+//! - A struct is an object keyed by the field names, the unknown keys are ignored and the `null`
+//!   values are taken as absent.
+//! - `list` and `set` are arrays, and `map` is an object, so its keys must be strings, numbers,
+//!   booleans or enums.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_thrift::generic::{idl::Idl, json::JsonClient, BinaryClientBuilder};
+//!
+//! let idl = Arc::new(Idl::parse(&std::fs::read_to_string("item.thrift")?)?);
+//! let client = BinaryClientBuilder::new("item").address(addr).build();
+//! let mut client = JsonClient::new(client, idl, "ItemService");
+//! let item = client.call("GetItem", serde_json::json!({ "id": 1 })).await?;
+//! ```
+
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use pilota::thrift::{new_application_error, ApplicationErrorKind};
+use serde_json::{Map, Number, Value};
+
+use super::{
+    idl::{Field, Function, Idl, Resolved, Ty},
+    Binary, BinaryClient,
+};
+use crate::{new_protocol_error, Error, ProtocolErrorKind};
+
+// the field types of the binary protocol
+mod ttype {
+    pub const STOP: u8 = 0;
+    pub const BOOL: u8 = 2;
+    pub const I8: u8 = 3;
+    pub const DOUBLE: u8 = 4;
+    pub const I16: u8 = 6;
+    pub const I32: u8 = 8;
+    pub const I64: u8 = 10;
+    pub const BINARY: u8 = 11;
+    pub const STRUCT: u8 = 12;
+    pub const MAP: u8 = 13;
+    pub const SET: u8 = 14;
+    pub const LIST: u8 = 15;
+}
+
+// the values are converted recursively, so limit the depth to protect the stack
+const MAX_DEPTH: usize = 64;
+
+/// A client that calls the methods of a service with the JSON requests and responses.
+#[derive(Clone)]
+pub struct JsonClient {
+    client: BinaryClient,
+    idl: Arc<Idl>,
+    service: String,
+}
+
+impl JsonClient {
+    /// Creates a new [`JsonClient`] which calls the methods of `service` defined in `idl`.
+    pub fn new(client: BinaryClient, idl: Arc<Idl>, service: impl Into<String>) -> Self {
+        Self {
+            client,
+            idl,
+            service: service.into(),
+        }
+    }
+
+    /// Calls the method with its arguments as a JSON object keyed by the argument names.
+    ///
+    /// Returns the result of the method, or `null` if the method returns `void` or is oneway.
+    /// The exceptions declared by the method are returned as application errors, with the
+    /// exception in JSON as the message.
+    pub async fn call(&mut self, method: &str, args: Value) -> Result<Value, Error> {
+        let function = self.idl.function(&self.service, method).ok_or_else(|| {
+            new_application_error(
+                ApplicationErrorKind::UnknownMethod,
+                format!("unknown method {} of {}", method, self.service),
+            )
+        })?;
+        let req = encode_args(&self.idl, function, &args)?;
+        if function.oneway {
+            self.client.call_oneway(method.to_string(), req).await?;
+            return Ok(Value::Null);
+        }
+        let resp = self.client.call(method.to_string(), req).await?;
+        decode_result(&self.idl, function, resp)
+    }
+}
+
+fn invalid(message: String) -> Error {
+    new_protocol_error(ProtocolErrorKind::InvalidData, message)
+}
+
+fn check_depth(depth: usize) -> Result<(), String> {
+    if depth >= MAX_DEPTH {
+        return Err(format!("the value is nested deeper than {}", MAX_DEPTH));
+    }
+    Ok(())
+}
+
+fn field_type(ty: Resolved<'_>) -> Result<u8, String> {
+    Ok(match ty {
+        Resolved::Bool => ttype::BOOL,
+        Resolved::I8 => ttype::I8,
+        Resolved::I16 => ttype::I16,
+        Resolved::I32 | Resolved::Enum(_) => ttype::I32,
+        Resolved::I64 => ttype::I64,
+        Resolved::Double => ttype::DOUBLE,
+        Resolved::String | Resolved::Binary => ttype::BINARY,
+        Resolved::Struct(_) => ttype::STRUCT,
+        Resolved::Map(..) => ttype::MAP,
+        Resolved::Set(_) => ttype::SET,
+        Resolved::List(_) => ttype::LIST,
+        Resolved::Void => return Err("void is not a value".to_string()),
+    })
+}
+
+fn encode_args(idl: &Idl, function: &Function, args: &Value) -> Result<Binary, Error> {
+    let empty = Value::Object(Map::new());
+    let args = if args.is_null() { &empty } else { args };
+    let mut buf = BytesMut::new();
+    write_struct(idl, &function.args, args, &mut buf, 0)
+        .map_err(|e| invalid(format!("invalid args of {}: {}", function.name, e)))?;
+    Ok(Binary(buf.freeze()))
+}
+
+fn decode_result(idl: &Idl, function: &Function, resp: Binary) -> Result<Value, Error> {
+    let success = Field {
+        id: 0,
+        name: "success".to_string(),
+        ty: function.result.clone(),
+        required: false,
+    };
+    let mut fields = Vec::with_capacity(function.throws.len() + 1);
+    if function.result != Ty::Void {
+        fields.push(success);
+    }
+    fields.extend(function.throws.iter().cloned());
+
+    let mut buf = resp.0;
+    let mut result = read_struct(idl, &fields, &mut buf, 0)
+        .map_err(|e| invalid(format!("invalid result of {}: {}", function.name, e)))?;
+    for exception in function.throws.iter() {
+        if let Some(e) = result.remove(&exception.name) {
+            return Err(new_application_error(
+                ApplicationErrorKind::Unknown,
+                format!("{}: {}", exception.name, e),
+            ));
+        }
+    }
+    Ok(result.remove("success").unwrap_or(Value::Null))
+}
+
+fn write_struct(
+    idl: &Idl,
+    fields: &[Field],
+    value: &Value,
+    buf: &mut BytesMut,
+    depth: usize,
+) -> Result<(), String> {
+    check_depth(depth)?;
+    let object = value
+        .as_object()
+        .ok_or_else(|| format!("expected an object, found {}", value))?;
+    for field in fields {
+        let value = match object.get(&field.name) {
+            Some(Value::Null) | None if field.required => {
+                return Err(format!("required field {} is missing", field.name))
+            }
+            Some(Value::Null) | None => continue,
+            Some(value) => value,
+        };
+        let ty = idl.resolve(&field.ty)?;
+        buf.put_u8(field_type(ty)?);
+        buf.put_i16(field.id);
+        write_value(idl, ty, value, buf, depth).map_err(|e| format!("{}: {}", field.name, e))?;
+    }
+    buf.put_u8(ttype::STOP);
+    Ok(())
+}
+
+fn as_int(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn write_int<T: TryFrom<i64>>(value: &Value, name: &str) -> Result<T, String> {
+    as_int(value)
+        .and_then(|v| T::try_from(v).ok())
+        .ok_or_else(|| format!("expected {}, found {}", name, value))
+}
+
+fn enum_value(e: &super::idl::Enum, value: &Value) -> Result<i32, String> {
+    if let Value::String(name) = value {
+        if let Some((_, v)) = e.values.iter().find(|(n, _)| n == name) {
+            return Ok(*v);
+        }
+    }
+    write_int(value, &e.name)
+}
+
+fn write_len(buf: &mut BytesMut, len: usize) -> Result<(), String> {
+    let len = i32::try_from(len).map_err(|_| format!("length {} is too large", len))?;
+    buf.put_i32(len);
+    Ok(())
+}
+
+fn write_value(
+    idl: &Idl,
+    ty: Resolved<'_>,
+    value: &Value,
+    buf: &mut BytesMut,
+    depth: usize,
+) -> Result<(), String> {
+    match ty {
+        Resolved::Bool => match value {
+            Value::Bool(b) => buf.put_u8(*b as u8),
+            _ => return Err(format!("expected bool, found {}", value)),
+        },
+        Resolved::I8 => buf.put_i8(write_int(value, "i8")?),
+        Resolved::I16 => buf.put_i16(write_int(value, "i16")?),
+        Resolved::I32 => buf.put_i32(write_int(value, "i32")?),
+        Resolved::I64 => buf.put_i64(write_int(value, "i64")?),
+        Resolved::Enum(e) => buf.put_i32(enum_value(e, value)?),
+        Resolved::Double => {
+            let v = match value {
+                Value::Number(n) => n.as_f64(),
+                Value::String(s) => s.parse().ok(),
+                _ => None,
+            };
+            buf.put_f64(v.ok_or_else(|| format!("expected double, found {}", value))?);
+        }
+        Resolved::String => {
+            let s = value
+                .as_str()
+                .ok_or_else(|| format!("expected string, found {}", value))?;
+            write_len(buf, s.len())?;
+            buf.put_slice(s.as_bytes());
+        }
+        Resolved::Binary => {
+            let b = value
+                .as_str()
+                .and_then(|s| base64::decode(s).ok())
+                .ok_or_else(|| format!("expected base64, found {}", value))?;
+            write_len(buf, b.len())?;
+            buf.put_slice(&b);
+        }
+        Resolved::Struct(s) => write_struct(idl, &s.fields, value, buf, depth + 1)?,
+        Resolved::List(elem) | Resolved::Set(elem) => {
+            let values = value
+                .as_array()
+                .ok_or_else(|| format!("expected an array, found {}", value))?;
+            let elem = idl.resolve(elem)?;
+            buf.put_u8(field_type(elem)?);
+            write_len(buf, values.len())?;
+            for v in values {
+                write_value(idl, elem, v, buf, depth + 1)?;
+            }
+        }
+        Resolved::Map(key, val) => {
+            let entries = value
+                .as_object()
+                .ok_or_else(|| format!("expected an object, found {}", value))?;
+            let (key, val) = (idl.resolve(key)?, idl.resolve(val)?);
+            buf.put_u8(field_type(key)?);
+            buf.put_u8(field_type(val)?);
+            write_len(buf, entries.len())?;
+            for (k, v) in entries {
+                let k = match key {
+                    Resolved::String | Resolved::Binary | Resolved::Enum(_) => {
+                        Value::String(k.clone())
+                    }
+                    Resolved::Bool => k
+                        .parse()
+                        .map(Value::Bool)
+                        .map_err(|_| format!("expected bool, found {}", k))?,
+                    Resolved::Double => k
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(Number::from_f64)
+                        .map(Value::Number)
+                        .ok_or_else(|| format!("expected double, found {}", k))?,
+                    // the integers can be parsed from strings
+                    Resolved::I8 | Resolved::I16 | Resolved::I32 | Resolved::I64 => {
+                        Value::String(k.clone())
+                    }
+                    _ => return Err("the map key must be a scalar".to_string()),
+                };
+                write_value(idl, key, &k, buf, depth + 1)?;
+                write_value(idl, val, v, buf, depth + 1)?;
+            }
+        }
+        Resolved::Void => return Err("void is not a value".to_string()),
+    }
+    Ok(())
+}
+
+fn ensure(buf: &Bytes, len: usize) -> Result<(), String> {
+    if buf.remaining() < len {
+        return Err("unexpected end of the payload".to_string());
+    }
+    Ok(())
+}
+
+fn read_len(buf: &mut Bytes) -> Result<usize, String> {
+    ensure(buf, 4)?;
+    let len = buf.get_i32();
+    usize::try_from(len).map_err(|_| format!("negative length {}", len))
+}
+
+fn read_bytes(buf: &mut Bytes) -> Result<Bytes, String> {
+    let len = read_len(buf)?;
+    ensure(buf, len)?;
+    Ok(buf.split_to(len))
+}
+
+fn read_struct(
+    idl: &Idl,
+    fields: &[Field],
+    buf: &mut Bytes,
+    depth: usize,
+) -> Result<Map<String, Value>, String> {
+    check_depth(depth)?;
+    let mut object = Map::new();
+    loop {
+        ensure(buf, 1)?;
+        let wire_type = buf.get_u8();
+        if wire_type == ttype::STOP {
+            break;
+        }
+        ensure(buf, 2)?;
+        let id = buf.get_i16();
+        let field = match fields.iter().find(|f| f.id == id) {
+            Some(field) => field,
+            None => {
+                skip(buf, wire_type, depth)?;
+                continue;
+            }
+        };
+        let ty = idl.resolve(&field.ty)?;
+        // the peer may use a different version of the IDL
+        if field_type(ty)? != wire_type {
+            skip(buf, wire_type, depth)?;
+            continue;
+        }
+        object.insert(field.name.clone(), read_value(idl, ty, buf, depth)?);
+    }
+    Ok(object)
+}
+
+fn map_key(key: Value) -> String {
+    match key {
+        Value::String(s) => s,
+        key => key.to_string(),
+    }
+}
+
+fn read_value(idl: &Idl, ty: Resolved<'_>, buf: &mut Bytes, depth: usize) -> Result<Value, String> {
+    Ok(match ty {
+        Resolved::Bool => {
+            ensure(buf, 1)?;
+            Value::Bool(buf.get_u8() != 0)
+        }
+        Resolved::I8 => {
+            ensure(buf, 1)?;
+            buf.get_i8().into()
+        }
+        Resolved::I16 => {
+            ensure(buf, 2)?;
+            buf.get_i16().into()
+        }
+        Resolved::I32 | Resolved::Enum(_) => {
+            ensure(buf, 4)?;
+            buf.get_i32().into()
+        }
+        Resolved::I64 => {
+            ensure(buf, 8)?;
+            buf.get_i64().into()
+        }
+        Resolved::Double => {
+            ensure(buf, 8)?;
+            Number::from_f64(buf.get_f64()).map_or(Value::Null, Value::Number)
+        }
+        Resolved::String => {
+            let b = read_bytes(buf)?;
+            Value::String(String::from_utf8_lossy(&b).into_owned())
+        }
+        Resolved::Binary => Value::String(base64::encode(read_bytes(buf)?)),
+        Resolved::Struct(s) => Value::Object(read_struct(idl, &s.fields, buf, depth + 1)?),
+        Resolved::List(elem) | Resolved::Set(elem) => {
+            let elem = idl.resolve(elem)?;
+            ensure(buf, 1)?;
+            let wire_type = buf.get_u8();
+            let len = read_len(buf)?;
+            if wire_type != field_type(elem)? {
+                return Err(format!("unexpected element type {}", wire_type));
+            }
+            let mut values = Vec::with_capacity(len.min(buf.remaining()));
+            for _ in 0..len {
+                values.push(read_value(idl, elem, buf, depth + 1)?);
+            }
+            Value::Array(values)
+        }
+        Resolved::Map(key, val) => {
+            let (key, val) = (idl.resolve(key)?, idl.resolve(val)?);
+            ensure(buf, 2)?;
+            let (key_type, val_type) = (buf.get_u8(), buf.get_u8());
+            let len = read_len(buf)?;
+            if len > 0 && (key_type != field_type(key)? || val_type != field_type(val)?) {
+                return Err(format!("unexpected map type {}, {}", key_type, val_type));
+            }
+            let mut entries = Map::new();
+            for _ in 0..len {
+                let k = map_key(read_value(idl, key, buf, depth + 1)?);
+                entries.insert(k, read_value(idl, val, buf, depth + 1)?);
+            }
+            Value::Object(entries)
+        }
+        Resolved::Void => Value::Null,
+    })
+}
+
+fn skip(buf: &mut Bytes, wire_type: u8, depth: usize) -> Result<(), String> {
+    check_depth(depth)?;
+    match wire_type {
+        ttype::BOOL | ttype::I8 => {
+            ensure(buf, 1)?;
+            buf.advance(1);
+        }
+        ttype::I16 => {
+            ensure(buf, 2)?;
+            buf.advance(2);
+        }
+        ttype::I32 => {
+            ensure(buf, 4)?;
+            buf.advance(4);
+        }
+        ttype::I64 | ttype::DOUBLE => {
+            ensure(buf, 8)?;
+            buf.advance(8);
+        }
+        ttype::BINARY => {
+            read_bytes(buf)?;
+        }
+        ttype::STRUCT => loop {
+            ensure(buf, 1)?;
+            let wire_type = buf.get_u8();
+            if wire_type == ttype::STOP {
+                break;
+            }
+            ensure(buf, 2)?;
+            buf.advance(2);
+            skip(buf, wire_type, depth + 1)?;
+        },
+        ttype::LIST | ttype::SET => {
+            ensure(buf, 1)?;
+            let elem = buf.get_u8();
+            for _ in 0..read_len(buf)? {
+                skip(buf, elem, depth + 1)?;
+            }
+        }
+        ttype::MAP => {
+            ensure(buf, 2)?;
+            let (key, val) = (buf.get_u8(), buf.get_u8());
+            for _ in 0..read_len(buf)? {
+                skip(buf, key, depth + 1)?;
+                skip(buf, val, depth + 1)?;
+            }
+        }
+        wire_type => return Err(format!("unknown field type {}", wire_type)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const IDL: &str = r#"
+        enum Status {
+            ONLINE = 1,
+            OFFLINE = 2,
+        }
+
+        struct Item {
+            1: required i64 id,
+            2: optional string title,
+            3: Status status,
+            4: map<i32, list<binary>> attachments,
+        }
+
+        exception NotFound {
+            1: string message,
+        }
+
+        service ItemService {
+            Item GetItem(1: Item item) throws (1: NotFound not_found),
+        }
+    "#;
+
+    #[test]
+    fn test_round_trip() {
+        let idl = Idl::parse(IDL).unwrap();
+        let function = idl.function("ItemService", "GetItem").unwrap();
+
+        let item = json!({
+            "id": "9007199254740993",
+            "status": "OFFLINE",
+            "attachments": { "1": ["aGVsbG8="] },
+            "unknown": true,
+        });
+        let args = encode_args(&idl, function, &json!({ "item": item })).unwrap();
+
+        // the args struct has the same layout as the result struct with the success field
+        let mut result = BytesMut::new();
+        result.put_u8(ttype::STRUCT);
+        result.put_i16(0);
+        result.extend_from_slice(&args.0[3..]);
+        let resp = decode_result(&idl, function, Binary(result.freeze())).unwrap();
+        assert_eq!(
+            resp,
+            json!({
+                "id": 9007199254740993i64,
+                "status": 2,
+                "attachments": { "1": ["aGVsbG8="] },
+            })
+        );
+
+        let err = encode_args(&idl, function, &json!({ "item": { "title": "a" } })).unwrap_err();
+        assert!(err.to_string().contains("id"));
+    }
+}
@@ -0,0 +1,417 @@
+//! Generic call, which sends and receives the thrift payloads without the generated code.
+//!
+//! A [`Binary`] is the args struct of a request or the result struct of a response, encoded by
+//! the binary protocol, so that a gateway can proxy the thrift traffic only by the service and
+//! method names, without compiling every IDL into the binary.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_thrift::generic::{Binary, BinaryClientBuilder};
+//!
+//! let mut client = BinaryClientBuilder::new("item").address(addr).build();
+//! let resp: Binary = client.call("GetItem", Binary(payload)).await?;
+//! ```
+//!
+//! On the server side, a `Service<ServerContext, Binary, Response = Binary>` can be served by
+//! [`Server::new`](crate::server::Server::new) as usual, and the method name of the request is
+//! in `cx.rpc_info().method()`.
+
+#[cfg(feature = "json-generic")]
+pub mod idl;
+#[cfg(feature = "json-generic")]
+pub mod json;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::future::BoxFuture;
+use pilota::thrift::EntryMessage;
+use tokio::io::AsyncRead;
+
+use crate::{
+    client::{CallOpt, Client, ClientBuilder, SetClient},
+    codec::{tt_header::DefaultTTHeaderCodec, MakeClientDecoder, MakeClientEncoder},
+    new_protocol_error,
+    protocol::{
+        binary::TAsyncBinaryProtocol, TInputProtocol, TLengthProtocol, TMessageIdentifier,
+        TOutputProtocol, TType,
+    },
+    Error, ProtocolErrorKind, Size,
+};
+
+// the payload is copied recursively, so limit the depth to protect the stack
+const MAX_DEPTH: usize = 64;
+
+/// A struct encoded by the binary protocol.
+///
+/// When decoded, the struct is copied field by field without knowing its definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binary(pub Bytes);
+
+#[async_trait::async_trait]
+impl EntryMessage for Binary {
+    fn encode<T: TOutputProtocol>(&self, protocol: &mut T) -> Result<(), Error> {
+        for b in self.0.iter() {
+            protocol.write_byte(*b)?;
+        }
+        Ok(())
+    }
+
+    fn decode<T: TInputProtocol>(
+        protocol: &mut T,
+        _msg_ident: &TMessageIdentifier,
+    ) -> Result<Self, Error> {
+        let mut buf = BytesMut::new();
+        copy_struct(protocol, &mut buf, 0)?;
+        Ok(Binary(buf.freeze()))
+    }
+
+    async fn decode_async<R>(
+        protocol: &mut TAsyncBinaryProtocol<R>,
+        _msg_ident: &TMessageIdentifier,
+    ) -> Result<Self, Error>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        let mut buf = BytesMut::new();
+        copy_struct_async(protocol, &mut buf, 0).await?;
+        Ok(Binary(buf.freeze()))
+    }
+}
+
+impl Size for Binary {
+    fn size<T: TLengthProtocol>(&self, _protocol: &T) -> usize {
+        self.0.len()
+    }
+}
+
+fn check_depth(depth: usize) -> Result<(), Error> {
+    if depth >= MAX_DEPTH {
+        return Err(new_protocol_error(
+            ProtocolErrorKind::DepthLimit,
+            format!("the payload is nested deeper than {}", MAX_DEPTH),
+        ));
+    }
+    Ok(())
+}
+
+fn unsupported_type(ttype: TType) -> Error {
+    new_protocol_error(
+        ProtocolErrorKind::InvalidData,
+        format!("unsupported field type {:?}", ttype),
+    )
+}
+
+fn copy_struct<T: TInputProtocol>(
+    protocol: &mut T,
+    buf: &mut BytesMut,
+    depth: usize,
+) -> Result<(), Error> {
+    check_depth(depth)?;
+    protocol.read_struct_begin()?;
+    loop {
+        let field = protocol.read_field_begin()?;
+        buf.put_u8(field.field_type as u8);
+        if field.field_type == TType::Stop {
+            break;
+        }
+        buf.put_i16(field.id.unwrap_or_default());
+        copy_value(protocol, buf, field.field_type, depth)?;
+        protocol.read_field_end()?;
+    }
+    protocol.read_struct_end()?;
+    Ok(())
+}
+
+fn copy_value<T: TInputProtocol>(
+    protocol: &mut T,
+    buf: &mut BytesMut,
+    ttype: TType,
+    depth: usize,
+) -> Result<(), Error> {
+    check_depth(depth)?;
+    match ttype {
+        TType::Bool => buf.put_u8(protocol.read_bool()? as u8),
+        TType::I08 => buf.put_i8(protocol.read_i8()?),
+        TType::I16 => buf.put_i16(protocol.read_i16()?),
+        TType::I32 => buf.put_i32(protocol.read_i32()?),
+        TType::I64 => buf.put_i64(protocol.read_i64()?),
+        TType::Double => buf.put_f64(protocol.read_double()?),
+        TType::String => {
+            let v = protocol.read_bytes()?;
+            buf.put_i32(v.len() as i32);
+            buf.put_slice(&v);
+        }
+        TType::Struct => copy_struct(protocol, buf, depth + 1)?,
+        TType::List => {
+            let list = protocol.read_list_begin()?;
+            buf.put_u8(list.element_type as u8);
+            buf.put_i32(list.size as i32);
+            for _ in 0..list.size {
+                copy_value(protocol, buf, list.element_type, depth + 1)?;
+            }
+            protocol.read_list_end()?;
+        }
+        TType::Set => {
+            let set = protocol.read_set_begin()?;
+            buf.put_u8(set.element_type as u8);
+            buf.put_i32(set.size as i32);
+            for _ in 0..set.size {
+                copy_value(protocol, buf, set.element_type, depth + 1)?;
+            }
+            protocol.read_set_end()?;
+        }
+        TType::Map => {
+            let map = protocol.read_map_begin()?;
+            buf.put_u8(map.key_type as u8);
+            buf.put_u8(map.value_type as u8);
+            buf.put_i32(map.size as i32);
+            for _ in 0..map.size {
+                copy_value(protocol, buf, map.key_type, depth + 1)?;
+                copy_value(protocol, buf, map.value_type, depth + 1)?;
+            }
+            protocol.read_map_end()?;
+        }
+        ttype => return Err(unsupported_type(ttype)),
+    }
+    Ok(())
+}
+
+async fn copy_struct_async<R>(
+    protocol: &mut TAsyncBinaryProtocol<R>,
+    buf: &mut BytesMut,
+    depth: usize,
+) -> Result<(), Error>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    check_depth(depth)?;
+    protocol.read_struct_begin().await?;
+    loop {
+        let field = protocol.read_field_begin().await?;
+        buf.put_u8(field.field_type as u8);
+        if field.field_type == TType::Stop {
+            break;
+        }
+        buf.put_i16(field.id.unwrap_or_default());
+        copy_value_async(protocol, buf, field.field_type, depth).await?;
+        protocol.read_field_end().await?;
+    }
+    protocol.read_struct_end().await?;
+    Ok(())
+}
+
+// boxed as async fns can't be recursive
+fn copy_value_async<'a, R>(
+    protocol: &'a mut TAsyncBinaryProtocol<R>,
+    buf: &'a mut BytesMut,
+    ttype: TType,
+    depth: usize,
+) -> BoxFuture<'a, Result<(), Error>>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    Box::pin(async move {
+        check_depth(depth)?;
+        match ttype {
+            TType::Bool => buf.put_u8(protocol.read_bool().await? as u8),
+            TType::I08 => buf.put_i8(protocol.read_i8().await?),
+            TType::I16 => buf.put_i16(protocol.read_i16().await?),
+            TType::I32 => buf.put_i32(protocol.read_i32().await?),
+            TType::I64 => buf.put_i64(protocol.read_i64().await?),
+            TType::Double => buf.put_f64(protocol.read_double().await?),
+            TType::String => {
+                let v = protocol.read_bytes().await?;
+                buf.put_i32(v.len() as i32);
+                buf.put_slice(&v);
+            }
+            TType::Struct => copy_struct_async(protocol, buf, depth + 1).await?,
+            TType::List => {
+                let list = protocol.read_list_begin().await?;
+                buf.put_u8(list.element_type as u8);
+                buf.put_i32(list.size as i32);
+                for _ in 0..list.size {
+                    copy_value_async(protocol, buf, list.element_type, depth + 1).await?;
+                }
+                protocol.read_list_end().await?;
+            }
+            TType::Set => {
+                let set = protocol.read_set_begin().await?;
+                buf.put_u8(set.element_type as u8);
+                buf.put_i32(set.size as i32);
+                for _ in 0..set.size {
+                    copy_value_async(protocol, buf, set.element_type, depth + 1).await?;
+                }
+                protocol.read_set_end().await?;
+            }
+            TType::Map => {
+                let map = protocol.read_map_begin().await?;
+                buf.put_u8(map.key_type as u8);
+                buf.put_u8(map.value_type as u8);
+                buf.put_i32(map.size as i32);
+                for _ in 0..map.size {
+                    copy_value_async(protocol, buf, map.key_type, depth + 1).await?;
+                    copy_value_async(protocol, buf, map.value_type, depth + 1).await?;
+                }
+                protocol.read_map_end().await?;
+            }
+            ttype => return Err(unsupported_type(ttype)),
+        }
+        Ok(())
+    })
+}
+
+/// A client that calls any method by name with the [`Binary`] payloads.
+#[derive(Clone)]
+pub struct BinaryClient {
+    client: Option<Client<Binary, Binary>>,
+}
+
+impl BinaryClient {
+    pub fn new() -> Self {
+        BinaryClient { client: None }
+    }
+
+    pub fn with_callopt(mut self, callopt: CallOpt) -> Self {
+        self.client.as_mut().unwrap().set_callopt(callopt);
+        self
+    }
+
+    /// Calls the method, and returns the encoded result struct.
+    pub async fn call(
+        &mut self,
+        method: impl Into<smol_str::SmolStr>,
+        req: Binary,
+    ) -> Result<Binary, Error> {
+        let method = method.into();
+        match self
+            .client
+            .as_mut()
+            .unwrap()
+            .call(method.clone(), req, false)
+            .await?
+        {
+            Some(resp) => Ok(resp),
+            None => Err(pilota::thrift::new_application_error(
+                pilota::thrift::ApplicationErrorKind::MissingResult,
+                format!("{} failed: unknown result", method),
+            )),
+        }
+    }
+
+    /// Calls the oneway method, the call returns once the request is sent.
+    pub async fn call_oneway(
+        &mut self,
+        method: impl Into<smol_str::SmolStr>,
+        req: Binary,
+    ) -> Result<(), Error> {
+        self.client
+            .as_mut()
+            .unwrap()
+            .call(method, req, true)
+            .await
+            .map(|_| ())
+    }
+}
+
+impl Default for BinaryClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SetClient<Binary, Binary> for BinaryClient {
+    fn set_client(self, client: Client<Binary, Binary>) -> Self {
+        BinaryClient {
+            client: Some(client),
+        }
+    }
+}
+
+pub struct BinaryClientBuilder;
+
+impl BinaryClientBuilder {
+    #[allow(clippy::new_ret_no_self, clippy::type_complexity)]
+    pub fn new(
+        service_name: impl AsRef<str>,
+    ) -> ClientBuilder<
+        volo::layer::Identity,
+        volo::layer::Identity,
+        BinaryClient,
+        Binary,
+        Binary,
+        MakeClientEncoder<DefaultTTHeaderCodec>,
+        MakeClientDecoder<DefaultTTHeaderCodec>,
+        volo::loadbalance::LbConfig<
+            volo::loadbalance::random::WeightedRandomBalance<()>,
+            volo::discovery::DummyDiscover,
+        >,
+    > {
+        ClientBuilder::new(service_name, BinaryClient::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::TBinaryProtocol;
+
+    #[test]
+    fn test_copy_struct() {
+        // struct { 1: string name, 2: list<i32> ids, 3: map<i8, bool> flags }
+        let mut payload = BytesMut::new();
+        payload.put_u8(TType::String as u8);
+        payload.put_i16(1);
+        payload.put_i32(4);
+        payload.put_slice(b"item");
+        payload.put_u8(TType::List as u8);
+        payload.put_i16(2);
+        payload.put_u8(TType::I32 as u8);
+        payload.put_i32(2);
+        payload.put_i32(1);
+        payload.put_i32(2);
+        payload.put_u8(TType::Map as u8);
+        payload.put_i16(3);
+        payload.put_u8(TType::I08 as u8);
+        payload.put_u8(TType::Bool as u8);
+        payload.put_i32(1);
+        payload.put_i8(7);
+        payload.put_u8(1);
+        payload.put_u8(TType::Stop as u8);
+        let expected = payload.clone().freeze();
+
+        let mut buf = BytesMut::new();
+        let mut protocol = TBinaryProtocol::new(&mut payload);
+        copy_struct(&mut protocol, &mut buf, 0).unwrap();
+        assert_eq!(buf.freeze(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_nested_lists() {
+        // struct { 1: list<list<list<...>>> }, each list of one element
+        let mut payload = BytesMut::new();
+        payload.put_u8(TType::List as u8);
+        payload.put_i16(1);
+        for _ in 0..10000 {
+            payload.put_u8(TType::List as u8);
+            payload.put_i32(1);
+        }
+        let payload = payload.freeze();
+
+        let mut input = BytesMut::from(&payload[..]);
+        let mut protocol = TBinaryProtocol::new(&mut input);
+        let err = copy_struct(&mut protocol, &mut BytesMut::new(), 0).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Protocol(ref e) if matches!(e.kind, ProtocolErrorKind::DepthLimit)
+        ));
+
+        let mut protocol = TAsyncBinaryProtocol::new(&payload[..]);
+        let err = copy_struct_async(&mut protocol, &mut BytesMut::new(), 0)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Protocol(ref e) if matches!(e.kind, ProtocolErrorKind::DepthLimit)
+        ));
+    }
+}
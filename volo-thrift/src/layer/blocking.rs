@@ -0,0 +1,119 @@
+//! Runs the CPU-heavy handlers without starving the other tasks on the same worker thread.
+//!
+//! The handler is run by [`tokio::task::block_in_place`], which hands the other tasks of the
+//! worker thread over to another one first, so that the acceptor and the codecs of the other
+//! connections keep running. Unlike `spawn_blocking`, the handler can still borrow the context
+//! of the request.
+//!
+//! This requires the multi-thread runtime, `block_in_place` panics on the current thread one.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_thrift::layer::blocking::BlockingLayer;
+//!
+//! // only the `render` method is CPU-heavy
+//! let server = Server::new(ItemServiceServer::new(S)).layer(BlockingLayer::new().method("render"));
+//! ```
+
+use std::{collections::HashSet, sync::Arc};
+
+use futures::Future;
+use motore::{layer::Layer, service::Service};
+use tokio::{runtime::Handle, task::block_in_place};
+use volo::context::Context;
+
+/// A [`Service`] that runs the inner service on the blocking threads.
+#[derive(Clone)]
+pub struct Blocking<S> {
+    inner: S,
+    // `None` for all the methods
+    methods: Option<Arc<HashSet<String>>>,
+}
+
+impl<S> Blocking<S> {
+    fn is_blocking(&self, method: Option<&str>) -> bool {
+        match (&self.methods, method) {
+            (None, _) => true,
+            (Some(methods), Some(method)) => methods.contains(method),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+impl<Cx, Req, S> Service<Cx, Req> for Blocking<S>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let method = cx.rpc_info().method.clone();
+            if !self.is_blocking(method.as_deref()) {
+                return self.inner.call(cx, req).await;
+            }
+            let handle = Handle::current();
+            block_in_place(|| handle.block_on(self.inner.call(cx, req)))
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`Blocking`].
+#[derive(Clone, Default)]
+pub struct BlockingLayer {
+    methods: Option<HashSet<String>>,
+}
+
+impl BlockingLayer {
+    /// Creates a layer running all the methods on the blocking threads.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs only the method on the blocking threads, can be called multiple times for more
+    /// methods.
+    pub fn method(mut self, method: impl Into<String>) -> Self {
+        self.methods
+            .get_or_insert_with(HashSet::new)
+            .insert(method.into());
+        self
+    }
+}
+
+impl<S> Layer<S> for BlockingLayer {
+    type Service = Blocking<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        Blocking {
+            inner,
+            methods: self.methods.map(Arc::new),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_methods() {
+        let all = BlockingLayer::new().layer(());
+        assert!(all.is_blocking(Some("get")));
+        assert!(all.is_blocking(None));
+
+        let render = BlockingLayer::new().method("render").layer(());
+        assert!(render.is_blocking(Some("render")));
+        assert!(!render.is_blocking(Some("get")));
+        assert!(!render.is_blocking(None));
+    }
+}
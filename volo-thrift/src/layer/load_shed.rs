@@ -0,0 +1,223 @@
+//! Sheds the requests waiting too long before being handled, so that the latency of the accepted
+//! requests stays within the SLO when the server is overloaded.
+//!
+//! The queue delay of a request is the time since the server reads it from the connection until
+//! it gets to this layer, which covers the decoding, the scheduling of the connection task and the
+//! layers before this one, such as waiting for a concurrency limit. So the layer should be added
+//! after them, right before the handler.
+//!
+//! Like CoDel, a single slow request doesn't trigger the shedding: the server starts shedding when
+//! the delay stays above the target for a whole interval, and stops once a request sees a delay
+//! below the target again. The rejected requests get an `InternalError` application exception with
+//! the [`OVERLOADED`] message, which the clients can check by [`is_overloaded`] to retry on
+//! another instance.
+//!
+//! The high [`Priority`] requests are never shed, and the low priority ones are shed as soon as
+//! their delay is above the target.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use std::time::Duration;
+//!
+//! use volo_thrift::layer::load_shed::LoadShedLayer;
+//!
+//! let server = Server::new(ItemServiceServer::new(S))
+//!     .layer(LoadShedLayer::new(Duration::from_millis(5)));
+//! ```
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::Future;
+use motore::{layer::Layer, service::Service, BoxError};
+use tracing::warn;
+use volo::context::{Context, Priority};
+
+use crate::{tags::ReceivedAt, ApplicationError, ApplicationErrorKind, Error};
+
+/// The message of the application exceptions of the shed requests.
+pub const OVERLOADED: &str = "server overloaded";
+
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returns whether the error is returned by a server shedding the load.
+pub fn is_overloaded(err: &Error) -> bool {
+    match err {
+        Error::Application(e) => {
+            matches!(e.kind, ApplicationErrorKind::InternalError) && e.message == OVERLOADED
+        }
+        _ => false,
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // when the delay went above the target, `None` if it is below the target
+    above_since: Option<Instant>,
+    shedding: bool,
+}
+
+impl State {
+    fn should_shed(
+        &mut self,
+        delay: Duration,
+        now: Instant,
+        target: Duration,
+        interval: Duration,
+    ) -> bool {
+        if delay < target {
+            self.above_since = None;
+            self.shedding = false;
+            return false;
+        }
+        let since = *self.above_since.get_or_insert(now);
+        if !self.shedding && now.duration_since(since) >= interval {
+            self.shedding = true;
+            warn!(
+                "[VOLO] start shedding, queue delay {:?} above the target {:?} for {:?}",
+                delay, target, interval
+            );
+        }
+        self.shedding
+    }
+}
+
+/// A [`Service`] that rejects the requests when the queue delay stays above the target.
+#[derive(Clone)]
+pub struct LoadShed<S> {
+    inner: S,
+    target: Duration,
+    interval: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+impl<Cx, Req, S> Service<Cx, Req> for LoadShed<S>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+
+    type Error = BoxError;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, BoxError>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            // the requests not from the thrift transports are never shed
+            if let Some(ReceivedAt(at)) = cx.extensions().get::<ReceivedAt>().copied() {
+                let now = Instant::now();
+                let delay = now.saturating_duration_since(at);
+                let shed =
+                    self.state
+                        .lock()
+                        .unwrap()
+                        .should_shed(delay, now, self.target, self.interval);
+                let shed = match cx.extensions().get::<Priority>() {
+                    Some(Priority::High) => false,
+                    Some(Priority::Low) => shed || delay >= self.target,
+                    _ => shed,
+                };
+                if shed {
+                    return Err(Error::Application(ApplicationError::new(
+                        ApplicationErrorKind::InternalError,
+                        OVERLOADED.to_string(),
+                    ))
+                    .into());
+                }
+            }
+            self.inner.call(cx, req).await.map_err(Into::into)
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`LoadShed`].
+#[derive(Clone)]
+pub struct LoadShedLayer {
+    target: Duration,
+    interval: Duration,
+}
+
+impl LoadShedLayer {
+    /// Creates a layer shedding the requests when the queue delay stays above `target`.
+    pub fn new(target: Duration) -> Self {
+        Self {
+            target,
+            interval: DEFAULT_INTERVAL,
+        }
+    }
+
+    /// Sets how long the queue delay must stay above the target before shedding, which tolerates
+    /// the short bursts.
+    ///
+    /// Defaults to 100ms.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+impl<S> Layer<S> for LoadShedLayer {
+    type Service = LoadShed<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        LoadShed {
+            inner,
+            target: self.target,
+            interval: self.interval,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_shed() {
+        let target = Duration::from_millis(5);
+        let interval = Duration::from_millis(100);
+        let high = Duration::from_millis(10);
+        let low = Duration::from_millis(1);
+        let start = Instant::now();
+        let mut state = State::default();
+
+        // a burst shorter than the interval
+        assert!(!state.should_shed(high, start, target, interval));
+        assert!(!state.should_shed(high, start + Duration::from_millis(50), target, interval));
+        assert!(!state.should_shed(low, start + Duration::from_millis(60), target, interval));
+
+        // above the target for the whole interval
+        let start = start + Duration::from_millis(100);
+        assert!(!state.should_shed(high, start, target, interval));
+        assert!(state.should_shed(high, start + interval, target, interval));
+        assert!(state.should_shed(target, start + interval * 2, target, interval));
+
+        // recovered
+        assert!(!state.should_shed(low, start + interval * 3, target, interval));
+        assert!(!state.should_shed(high, start + interval * 3, target, interval));
+    }
+
+    #[test]
+    fn test_is_overloaded() {
+        let err = Error::Application(ApplicationError::new(
+            ApplicationErrorKind::InternalError,
+            OVERLOADED.to_string(),
+        ));
+        assert!(is_overloaded(&err));
+        let err = Error::Application(ApplicationError::new(
+            ApplicationErrorKind::InternalError,
+            "panic".to_string(),
+        ));
+        assert!(!is_overloaded(&err));
+    }
+}
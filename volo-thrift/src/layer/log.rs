@@ -0,0 +1,354 @@
+//! Logs the requests and responses for debugging, such as in the staging environments.
+//!
+//! The messages are logged by their `Debug` output, which the generated code derives, and the
+//! sensitive fields can be redacted by their names, either `password` for the fields in any
+//! struct or `User.password` for the field of a struct. The values of the redacted fields are
+//! replaced by `<redacted>`.
+//!
+//! The same layer works for both the client and the server.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_thrift::layer::log::LogLayer;
+//!
+//! let client = ItemServiceClientBuilder::new("item")
+//!     .layer_inner(LogLayer::new().pretty(true).redact("password").redact("Item.token"))
+//!     .build();
+//! ```
+
+use std::{collections::HashSet, fmt::Debug, sync::Arc};
+
+use futures::Future;
+use motore::{layer::Layer, service::Service};
+use tokio::time::Instant;
+use tracing::Level;
+use volo::context::Context;
+
+const REDACTED: &str = "<redacted>";
+
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        match $level {
+            Level::ERROR => tracing::error!($($arg)+),
+            Level::WARN => tracing::warn!($($arg)+),
+            Level::INFO => tracing::info!($($arg)+),
+            Level::DEBUG => tracing::debug!($($arg)+),
+            _ => tracing::trace!($($arg)+),
+        }
+    };
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    level: Level,
+    pretty: bool,
+    redact: HashSet<String>,
+}
+
+impl Config {
+    fn format<T: Debug>(&self, msg: &T) -> String {
+        let s = if self.pretty {
+            format!("{:#?}", msg)
+        } else {
+            format!("{:?}", msg)
+        };
+        if self.redact.is_empty() {
+            return s;
+        }
+        redact(&s, &self.redact)
+    }
+}
+
+/// A [`Service`] that logs the requests and responses.
+#[derive(Clone)]
+pub struct Log<S> {
+    inner: S,
+    config: Arc<Config>,
+}
+
+impl<Cx, Req, S> Service<Cx, Req> for Log<S>
+where
+    Cx: Context + Send + 'static,
+    Req: Debug + Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+    S::Response: Debug,
+    S::Error: Debug,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            // nothing to format if the level is disabled
+            if !log_enabled(self.config.level) {
+                return self.inner.call(cx, req).await;
+            }
+
+            let role = cx.rpc_info().role;
+            let method = cx.rpc_info().method.clone().unwrap_or_default();
+            log!(
+                self.config.level,
+                "[VOLO] {:?} {} request: {}",
+                role,
+                method,
+                self.config.format(&req)
+            );
+            let start = Instant::now();
+            let resp = self.inner.call(cx, req).await;
+            let elapsed = start.elapsed();
+            match &resp {
+                Ok(resp) => log!(
+                    self.config.level,
+                    "[VOLO] {:?} {} response in {:?}: {}",
+                    role,
+                    method,
+                    elapsed,
+                    self.config.format(resp)
+                ),
+                Err(e) => log!(
+                    self.config.level,
+                    "[VOLO] {:?} {} error in {:?}: {}",
+                    role,
+                    method,
+                    elapsed,
+                    self.config.format(e)
+                ),
+            }
+            resp
+        }
+    }
+}
+
+fn log_enabled(level: Level) -> bool {
+    match level {
+        Level::ERROR => tracing::enabled!(Level::ERROR),
+        Level::WARN => tracing::enabled!(Level::WARN),
+        Level::INFO => tracing::enabled!(Level::INFO),
+        Level::DEBUG => tracing::enabled!(Level::DEBUG),
+        _ => tracing::enabled!(Level::TRACE),
+    }
+}
+
+/// A [`Layer`] that applies [`Log`].
+#[derive(Clone)]
+pub struct LogLayer {
+    config: Config,
+}
+
+impl LogLayer {
+    pub fn new() -> Self {
+        Self {
+            config: Config {
+                level: Level::DEBUG,
+                pretty: false,
+                redact: HashSet::new(),
+            },
+        }
+    }
+
+    /// Sets the level of the logs.
+    ///
+    /// Defaults to `DEBUG`.
+    pub fn level(mut self, level: Level) -> Self {
+        self.config.level = level;
+        self
+    }
+
+    /// Sets whether to pretty-print the messages in multiple lines.
+    ///
+    /// Defaults to false.
+    pub fn pretty(mut self, pretty: bool) -> Self {
+        self.config.pretty = pretty;
+        self
+    }
+
+    /// Redacts the field, either `field` of any struct or `Struct.field` of a struct.
+    pub fn redact(mut self, field: impl Into<String>) -> Self {
+        self.config.redact.insert(field.into());
+        self
+    }
+}
+
+impl Default for LogLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for LogLayer {
+    type Service = Log<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        Log {
+            inner,
+            config: Arc::new(self.config),
+        }
+    }
+}
+
+/// Replaces the values of the redacted fields in the `Debug` output of the derived impls.
+fn redact(input: &str, fields: &HashSet<String>) -> String {
+    let chars: Vec<char> = input.chars().collect();
+    let mut out = String::with_capacity(input.len());
+    // the struct name of each open bracket, `None` for the maps, tuples and lists
+    let mut stack: Vec<Option<String>> = Vec::new();
+    // the identifier before the current position, which names the struct opened by `{`
+    let mut last_ident = None;
+    // whether the current position may start a field, that is after `{` or `,` of a struct
+    let mut field_start = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '"' | '\'' => {
+                let end = skip_quoted(&chars, i);
+                out.extend(&chars[i..end]);
+                i = end;
+                last_ident = None;
+                field_start = false;
+                continue;
+            }
+            '{' => {
+                stack.push(last_ident.take());
+                field_start = matches!(stack.last(), Some(Some(_)));
+            }
+            '(' | '[' => {
+                stack.push(None);
+                last_ident = None;
+                field_start = false;
+            }
+            '}' | ')' | ']' => {
+                stack.pop();
+                last_ident = None;
+                field_start = false;
+            }
+            ',' => {
+                last_ident = None;
+                field_start = matches!(stack.last(), Some(Some(_)));
+            }
+            c if c.is_whitespace() => {}
+            c if c.is_alphanumeric() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let ident: String = chars[start..i].iter().collect();
+                out.push_str(&ident);
+                let is_field =
+                    field_start && chars.get(i) == Some(&':') && chars.get(i + 1) != Some(&':');
+                field_start = false;
+                if is_field {
+                    if let Some(Some(name)) = stack.last() {
+                        if fields.contains(&ident)
+                            || fields.contains(&format!("{}.{}", name, ident))
+                        {
+                            out.push_str(": ");
+                            out.push_str(REDACTED);
+                            i = skip_value(&chars, i + 1);
+                            last_ident = None;
+                            continue;
+                        }
+                    }
+                }
+                last_ident = Some(ident);
+                continue;
+            }
+            _ => {
+                last_ident = None;
+                field_start = false;
+            }
+        }
+        out.push(c);
+        i += 1;
+    }
+    out
+}
+
+/// Returns the end of the quoted literal starting at `start`.
+fn skip_quoted(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            c if c == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+/// Returns the position of the `,` or the closing bracket after the value starting at `start`.
+fn skip_value(chars: &[char], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '"' | '\'' => {
+                i = skip_quoted(chars, i);
+                continue;
+            }
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' if depth == 0 => return i,
+            '}' | ')' | ']' => depth -= 1,
+            ',' if depth == 0 => return i,
+            _ => {}
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[allow(dead_code)]
+    #[derive(Debug)]
+    struct User {
+        name: String,
+        password: String,
+        token: Option<Token>,
+        tags: HashMap<String, String>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug)]
+    struct Token {
+        value: Vec<u8>,
+        password: &'static str,
+    }
+
+    #[test]
+    fn test_redact() {
+        let user = User {
+            name: "a, {b}: \"c\"".to_string(),
+            password: "secret".to_string(),
+            token: Some(Token {
+                value: vec![1, 2],
+                password: "secret",
+            }),
+            tags: HashMap::from([("password".to_string(), "shown".to_string())]),
+        };
+        let fields = HashSet::from(["User.password".to_string(), "value".to_string()]);
+
+        for s in [format!("{:?}", user), format!("{:#?}", user)] {
+            let redacted = redact(&s, &fields);
+            assert_eq!(redacted.matches(REDACTED).count(), 2, "{}", redacted);
+            assert!(redacted.contains(r#"name: "a, {b}: \"c\"""#));
+            // only the password of the user is redacted
+            assert_eq!(redacted.matches("secret").count(), 1, "{}", redacted);
+            // the map keys are not fields
+            assert!(redacted.contains("shown"));
+        }
+    }
+}
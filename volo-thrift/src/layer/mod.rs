@@ -0,0 +1,5 @@
+pub mod blocking;
+pub mod load_shed;
+pub mod log;
+#[cfg(feature = "otel")]
+pub mod otel;
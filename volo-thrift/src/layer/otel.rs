@@ -0,0 +1,353 @@
+//! The tracing and the metrics of the calls by OpenTelemetry, on either the client or the server
+//! side, by the semantic conventions of the RPC spans and metrics.
+//!
+//! [`OtelLayer`] starts a span of each call by the global tracer and records its duration and the
+//! sizes of its messages by the global meter, so the SDK and the exporters are set up by the
+//! application, such as by `opentelemetry-otlp`. The spans and the metrics have the attributes
+//! `rpc.system`, `rpc.service` of the callee and `rpc.method`, and the metrics also
+//! `otel.status_code` of `OK` or `ERROR`.
+//!
+//! The context of the span is propagated by the global text map propagator, such as the W3C
+//! `traceparent`, with the transient metainfo of TTHeader: the clients set it for the next hop, and
+//! the servers extract the parent of their spans from the upstream values, so the calls made by
+//! the handlers are in the trace of the request. The calls must use TTHeader for the propagation.
+//!
+//! On the client side, the layer should be added by `layer_inner`, where the sizes are of the
+//! requests and the responses as encoded by the binary protocol.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use opentelemetry::{global, sdk::propagation::TraceContextPropagator};
+//! use volo_thrift::layer::otel::OtelLayer;
+//!
+//! global::set_text_map_propagator(TraceContextPropagator::new());
+//!
+//! // server
+//! ItemServiceServer::new(S).layer(OtelLayer::new()).run(addr).await;
+//!
+//! // client
+//! ItemServiceClientBuilder::new("item")
+//!     .layer_inner(OtelLayer::new())
+//!     .build();
+//! ```
+
+use std::{fmt::Display, sync::Arc, time::Instant};
+
+use bytes::BytesMut;
+use futures::Future;
+use metainfo::{Forward, MetaInfo};
+use motore::{layer::Layer, service::Service};
+use opentelemetry::{
+    global::{self, BoxedTracer},
+    metrics::{Counter, Meter, Unit, ValueRecorder},
+    propagation::{Extractor, Injector},
+    trace::{FutureExt, SpanKind, StatusCode, TraceContextExt, Tracer},
+    Context as OtelContext, KeyValue,
+};
+use volo::{
+    context::{Context, Role},
+    net::Address,
+};
+
+use crate::{
+    context::{ClientContext, ServerContext},
+    protocol::TBinaryProtocol,
+    Size,
+};
+
+const INSTRUMENTATION_NAME: &str = "volo-thrift";
+
+const RPC_SYSTEM: &str = "rpc.system";
+const RPC_SERVICE: &str = "rpc.service";
+const RPC_METHOD: &str = "rpc.method";
+const OTEL_STATUS_CODE: &str = "otel.status_code";
+const NET_PEER_IP: &str = "net.peer.ip";
+const NET_PEER_PORT: &str = "net.peer.port";
+
+/// Reads the propagated context from the upstream values of the metainfo.
+struct MetaInfoExtractor<'a>(&'a MetaInfo);
+
+impl Extractor for MetaInfoExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get_upstream(key)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .get_all_upstreams()
+            .map(|upstreams| upstreams.keys().map(|k| k.as_ref()).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Writes the propagated context into the transient values of the metainfo.
+struct MetaInfoInjector<'a>(&'a mut MetaInfo);
+
+impl Injector for MetaInfoInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.set_transient(key.to_string(), value);
+    }
+}
+
+fn message_size<T: Size>(msg: &T) -> u64 {
+    msg.size(&TBinaryProtocol::new(&mut BytesMut::new())) as u64
+}
+
+struct Instruments {
+    duration: ValueRecorder<f64>,
+    calls: Counter<u64>,
+    request_size: ValueRecorder<u64>,
+    response_size: ValueRecorder<u64>,
+}
+
+impl Instruments {
+    fn new(meter: &Meter, side: &str) -> Self {
+        Self {
+            duration: meter
+                .f64_value_recorder(format!("rpc.{}.duration", side))
+                .with_description("The duration of the calls.")
+                .with_unit(Unit::new("ms"))
+                .init(),
+            calls: meter
+                .u64_counter(format!("rpc.{}.calls", side))
+                .with_description("The calls by their status codes.")
+                .init(),
+            request_size: meter
+                .u64_value_recorder(format!("rpc.{}.request.size", side))
+                .with_description("The sizes of the requests.")
+                .with_unit(Unit::new("By"))
+                .init(),
+            response_size: meter
+                .u64_value_recorder(format!("rpc.{}.response.size", side))
+                .with_description("The sizes of the responses.")
+                .with_unit(Unit::new("By"))
+                .init(),
+        }
+    }
+}
+
+struct Shared {
+    tracer: BoxedTracer,
+    client: Instruments,
+    server: Instruments,
+}
+
+/// A started call.
+struct Call<'a> {
+    instruments: &'a Instruments,
+    attributes: Vec<KeyValue>,
+    otel_cx: OtelContext,
+    start: Instant,
+}
+
+impl Shared {
+    /// Starts the span of the call as the child of `parent`.
+    fn start<Cx: Context>(&self, cx: &Cx, parent: OtelContext, request_size: u64) -> Call<'_> {
+        let info = cx.rpc_info();
+        let (kind, instruments, peer) = match info.role() {
+            Role::Server => (SpanKind::Server, &self.server, info.caller()),
+            Role::Client => (SpanKind::Client, &self.client, info.callee()),
+        };
+        let service = info
+            .callee()
+            .map_or_else(String::new, |e| e.service_name.to_string());
+        let method = info.method().map_or_else(String::new, |m| m.to_string());
+        let attributes = vec![
+            KeyValue::new(RPC_SYSTEM, "apache_thrift"),
+            KeyValue::new(RPC_SERVICE, service.clone()),
+            KeyValue::new(RPC_METHOD, method.clone()),
+        ];
+        instruments.request_size.record(request_size, &attributes);
+
+        let mut span_attributes = attributes.clone();
+        if let Some(Address::Ip(addr)) = peer.and_then(|e| e.address.as_ref()) {
+            span_attributes.push(KeyValue::new(NET_PEER_IP, addr.ip().to_string()));
+            span_attributes.push(KeyValue::new(NET_PEER_PORT, addr.port() as i64));
+        }
+        let span = self
+            .tracer
+            .span_builder(format!("{}/{}", service, method))
+            .with_kind(kind)
+            .with_attributes(span_attributes)
+            .start_with_context(&self.tracer, &parent);
+        Call {
+            instruments,
+            attributes,
+            otel_cx: parent.with_span(span),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Call<'_> {
+    /// Ends the span of the call, with the size of the response if succeeded.
+    fn end<E: Display>(mut self, result: Result<Option<u64>, &E>) {
+        let elapsed = self.start.elapsed();
+        let span = self.otel_cx.span();
+        let status_code = match result {
+            Ok(response_size) => {
+                if let Some(size) = response_size {
+                    self.instruments
+                        .response_size
+                        .record(size, &self.attributes);
+                }
+                "OK"
+            }
+            Err(e) => {
+                span.set_status(StatusCode::Error, e.to_string());
+                "ERROR"
+            }
+        };
+        span.end();
+
+        self.attributes
+            .push(KeyValue::new(OTEL_STATUS_CODE, status_code));
+        self.instruments
+            .duration
+            .record(elapsed.as_secs_f64() * 1000.0, &self.attributes);
+        self.instruments.calls.add(1, &self.attributes);
+    }
+}
+
+/// A [`Service`] that traces and measures the calls by OpenTelemetry.
+pub struct Otel<S> {
+    inner: S,
+    shared: Arc<Shared>,
+}
+
+impl<S: Clone> Clone for Otel<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<Req, S> Service<ServerContext, Req> for Otel<S>
+where
+    Req: Size + Send + 'static,
+    S: Service<ServerContext, Req> + Send + 'static,
+    S::Response: Size,
+    S::Error: Display,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut ServerContext, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let parent = metainfo::METAINFO
+                .try_with(|mi| {
+                    global::get_text_map_propagator(|propagator| {
+                        propagator.extract(&MetaInfoExtractor(&mi.borrow()))
+                    })
+                })
+                .unwrap_or_default();
+            let call = self.shared.start(&*cx, parent, message_size(&req));
+            let otel_cx = call.otel_cx.clone();
+            let result = self.inner.call(cx, req).with_context(otel_cx).await;
+            call.end(result.as_ref().map(|resp| Some(message_size(resp))));
+            result
+        }
+    }
+}
+
+impl<Req, Resp, S> Service<ClientContext, Req> for Otel<S>
+where
+    Req: Size + Send + 'static,
+    Resp: Size + 'static,
+    S: Service<ClientContext, Req, Response = Option<Resp>> + Send + 'static,
+    S::Error: Display,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut ClientContext, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let call = self
+                .shared
+                .start(&*cx, OtelContext::current(), message_size(&req));
+            let _ = metainfo::METAINFO.try_with(|mi| {
+                global::get_text_map_propagator(|propagator| {
+                    propagator
+                        .inject_context(&call.otel_cx, &mut MetaInfoInjector(&mut mi.borrow_mut()))
+                })
+            });
+            let otel_cx = call.otel_cx.clone();
+            let result = self.inner.call(cx, req).with_context(otel_cx).await;
+            // the oneway calls have no responses
+            call.end(result.as_ref().map(|resp| resp.as_ref().map(message_size)));
+            result
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`Otel`], by the global tracer and meter.
+#[derive(Clone)]
+pub struct OtelLayer {
+    shared: Arc<Shared>,
+}
+
+impl OtelLayer {
+    pub fn new() -> Self {
+        let meter = global::meter(INSTRUMENTATION_NAME);
+        Self {
+            shared: Arc::new(Shared {
+                tracer: global::tracer(INSTRUMENTATION_NAME),
+                client: Instruments::new(&meter, "client"),
+                server: Instruments::new(&meter, "server"),
+            }),
+        }
+    }
+}
+
+impl Default for OtelLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for OtelLayer {
+    type Service = Otel<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        Otel {
+            inner,
+            shared: self.shared,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_propagation() {
+        let mut upstream = MetaInfo::default();
+        MetaInfoInjector(&mut upstream).set("traceparent", "00-01-02-01".to_string());
+        assert_eq!(
+            upstream.get_transient("traceparent").map(|v| v.to_string()),
+            Some("00-01-02-01".to_string())
+        );
+
+        let mut downstream = MetaInfo::default();
+        downstream.set_upstream("traceparent", "00-01-02-01");
+        let extractor = MetaInfoExtractor(&downstream);
+        assert_eq!(extractor.get("traceparent"), Some("00-01-02-01"));
+        assert_eq!(extractor.keys(), vec!["traceparent"]);
+    }
+}
@@ -1,5 +1,7 @@
 #![feature(type_alias_impl_trait)]
 #![feature(generic_associated_types)]
+#![feature(type_changing_struct_update)]
+#![cfg_attr(feature = "forbid-unsafe", forbid(unsafe_code))]
 #![doc(
     html_logo_url = "https://github.com/cloudwego/volo/raw/main/.github/assets/logo.png?sanitize=true"
 )]
@@ -15,6 +17,10 @@ pub mod client;
 pub use client::Client;
 pub mod codec;
 pub mod context;
+pub mod descriptor;
+pub mod generic;
+pub mod layer;
+pub mod router;
 pub mod server;
 pub mod tags;
 pub use error::*;
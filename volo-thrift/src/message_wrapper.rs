@@ -22,6 +22,21 @@ pub struct ThriftMessage<M> {
     pub(crate) meta: MessageMeta,
 }
 
+/// The separator between the service name and the method name used by `TMultiplexedProtocol`.
+const MULTIPLEXED_SEPARATOR: char = ':';
+
+/// Strips the service name prefixed by a `TMultiplexedProtocol` peer, so that the generated code
+/// can dispatch by the method name as usual.
+///
+/// Thrift identifiers can't contain `:`, so the prefix is never part of a real method name.
+fn strip_multiplexed_service<Cx: ThriftContext>(ident: &mut TMessageIdentifier, cx: &mut Cx) {
+    if let Some((service, method)) = ident.name.split_once(MULTIPLEXED_SEPARATOR) {
+        let (service, method): (smol_str::SmolStr, _) = (service.into(), method.into());
+        cx.handle_multiplexed_service(service);
+        ident.name = method;
+    }
+}
+
 pub(crate) struct DummyMessage;
 
 #[async_trait::async_trait]
@@ -60,9 +75,13 @@ impl Size for DummyMessage {
 impl<M> ThriftMessage<M> {
     #[inline]
     pub fn mk_client_msg(cx: &ClientContext, msg: Result<M, Error>) -> Result<Self, crate::Error> {
+        let method = cx.rpc_info.method.clone().unwrap();
         let meta = MessageMeta {
             msg_type: cx.message_type,
-            method: cx.rpc_info.method.clone().unwrap(),
+            method: match &cx.multiplexed_service {
+                Some(service) => format!("{}{}{}", service, MULTIPLEXED_SEPARATOR, method).into(),
+                None => method,
+            },
             seq_id: cx.seq_id,
         };
         Ok(Self { data: msg, meta })
@@ -82,6 +101,21 @@ impl<M> ThriftMessage<M> {
     }
 }
 
+impl<M: Clone> ThriftMessage<M> {
+    /// Clones the message to send it again, the errors can't be cloned.
+    pub(crate) fn try_clone(&self) -> Option<Self> {
+        let data = self.data.as_ref().ok()?.clone();
+        Some(Self {
+            data: Ok(data),
+            meta: MessageMeta {
+                msg_type: self.meta.msg_type,
+                method: self.meta.method.clone(),
+                seq_id: self.meta.seq_id,
+            },
+        })
+    }
+}
+
 impl<U> ThriftMessage<U>
 where
     U: Size,
@@ -105,6 +139,16 @@ where
                         + e.size(protocol)
                         + protocol.write_message_end_len()
                 }
+                // encoded as the application error, see `encode`
+                Error::Protocol(e) => {
+                    let e = ApplicationError::new(
+                        pilota::thrift::ApplicationErrorKind::ProtocolError,
+                        e.message.clone(),
+                    );
+                    protocol.write_message_begin_len(&ident)
+                        + e.size(protocol)
+                        + protocol.write_message_end_len()
+                }
                 _ => 0,
             },
         }
@@ -155,8 +199,9 @@ where
         protocol: &mut T,
         cx: &mut Cx,
     ) -> Result<Self, Error> {
-        let msg_ident = protocol.read_message_begin()?;
+        let mut msg_ident = protocol.read_message_begin()?;
 
+        strip_multiplexed_service(&mut msg_ident, cx);
         cx.handle_decoded_msg_ident(&msg_ident);
 
         let res = match msg_ident.message_type {
@@ -181,8 +226,9 @@ where
     where
         R: AsyncRead + Unpin + Send,
     {
-        let msg_ident = protocol.read_message_begin().await?;
+        let mut msg_ident = protocol.read_message_begin().await?;
 
+        strip_multiplexed_service(&mut msg_ident, cx);
         cx.handle_decoded_msg_ident(&msg_ident);
 
         let res = match msg_ident.message_type {
@@ -202,3 +248,61 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use volo::context::{Role, RpcInfo};
+
+    use super::*;
+    use crate::{generic::Binary, protocol::TBinaryProtocol};
+
+    fn encode(cx: &ClientContext) -> BytesMut {
+        // an empty struct
+        let req = Binary(bytes::Bytes::from_static(&[0]));
+        let msg = ThriftMessage::mk_client_msg(cx, Ok(req)).unwrap();
+        let mut buf = BytesMut::new();
+        msg.encode(&mut TBinaryProtocol::new(&mut buf)).unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn test_multiplexed_service() {
+        let mut ri = RpcInfo::with_role(Role::Client);
+        ri.method = Some("GetItem".into());
+        let mut cx = ClientContext::new(1, ri, TMessageType::Call);
+        cx.multiplexed_service = Some("ItemService".into());
+        let buf = encode(&cx);
+        let ident = TBinaryProtocol::new(&mut buf.clone())
+            .read_message_begin()
+            .unwrap();
+        assert_eq!(&*ident.name, "ItemService:GetItem");
+
+        // the prefix is stripped by both the framed and the unframed decoding
+        let mut cx = ServerContext::default();
+        let msg =
+            ThriftMessage::<Binary>::decode(&mut TBinaryProtocol::new(&mut buf.clone()), &mut cx)
+                .unwrap();
+        assert_eq!(&*msg.meta.method, "GetItem");
+        assert_eq!(cx.rpc_info.method.as_deref(), Some("GetItem"));
+        assert_eq!(cx.multiplexed_service.as_deref(), Some("ItemService"));
+
+        let mut cx = ServerContext::default();
+        let mut protocol = TAsyncBinaryProtocol::new(&buf[..]);
+        let msg = ThriftMessage::<Binary>::decode_async(&mut protocol, &mut cx)
+            .await
+            .unwrap();
+        assert_eq!(&*msg.meta.method, "GetItem");
+        assert_eq!(cx.multiplexed_service.as_deref(), Some("ItemService"));
+
+        // the plain method names are kept as they are
+        let mut ri = RpcInfo::with_role(Role::Client);
+        ri.method = Some("GetItem".into());
+        let mut buf = encode(&ClientContext::new(1, ri, TMessageType::Call));
+        let mut cx = ServerContext::default();
+        let msg =
+            ThriftMessage::<Binary>::decode(&mut TBinaryProtocol::new(&mut buf), &mut cx).unwrap();
+        assert_eq!(&*msg.meta.method, "GetItem");
+        assert_eq!(cx.multiplexed_service, None);
+    }
+}
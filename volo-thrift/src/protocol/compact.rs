@@ -0,0 +1,956 @@
+//! The compact protocol of Apache Thrift, the default one of many Go and Java services.
+//!
+//! The integers are encoded by zigzag varints and the field ids by the deltas to the previous
+//! ones, so the messages are usually much smaller than by the binary protocol. As their sizes are
+//! only known after encoding, the messages are encoded into a buffer before the frame length,
+//! and the unframed ones are measured by [`MessageLen`] before decoding.
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use super::{
+    TFieldIdentifier, TInputProtocol, TLengthProtocol, TListIdentifier, TMapIdentifier,
+    TMessageIdentifier, TMessageType, TOutputProtocol, TSetIdentifier, TStructIdentifier, TType,
+};
+use crate::{new_protocol_error, Error, ProtocolErrorKind};
+
+pub(crate) const PROTOCOL_ID: u8 = 0x82;
+pub(crate) const VERSION: u8 = 1;
+const VERSION_MASK: u8 = 0x1f;
+const TYPE_SHIFT: u8 = 5;
+
+mod compact_type {
+    pub const STOP: u8 = 0x00;
+    pub const BOOLEAN_TRUE: u8 = 0x01;
+    pub const BOOLEAN_FALSE: u8 = 0x02;
+    pub const BYTE: u8 = 0x03;
+    pub const I16: u8 = 0x04;
+    pub const I32: u8 = 0x05;
+    pub const I64: u8 = 0x06;
+    pub const DOUBLE: u8 = 0x07;
+    pub const BINARY: u8 = 0x08;
+    pub const LIST: u8 = 0x09;
+    pub const SET: u8 = 0x0a;
+    pub const MAP: u8 = 0x0b;
+    pub const STRUCT: u8 = 0x0c;
+}
+
+fn invalid_data(message: impl Into<String>) -> Error {
+    new_protocol_error(ProtocolErrorKind::InvalidData, message.into())
+}
+
+fn to_compact_type(ttype: TType) -> Result<u8, Error> {
+    Ok(match ttype {
+        TType::Stop => compact_type::STOP,
+        TType::Bool => compact_type::BOOLEAN_TRUE,
+        TType::I08 => compact_type::BYTE,
+        TType::I16 => compact_type::I16,
+        TType::I32 => compact_type::I32,
+        TType::I64 => compact_type::I64,
+        TType::Double => compact_type::DOUBLE,
+        TType::String => compact_type::BINARY,
+        TType::List => compact_type::LIST,
+        TType::Set => compact_type::SET,
+        TType::Map => compact_type::MAP,
+        TType::Struct => compact_type::STRUCT,
+        ttype => return Err(invalid_data(format!("unsupported field type {:?}", ttype))),
+    })
+}
+
+fn from_compact_type(byte: u8) -> Result<TType, Error> {
+    Ok(match byte & 0x0f {
+        compact_type::STOP => TType::Stop,
+        compact_type::BOOLEAN_TRUE | compact_type::BOOLEAN_FALSE => TType::Bool,
+        compact_type::BYTE => TType::I08,
+        compact_type::I16 => TType::I16,
+        compact_type::I32 => TType::I32,
+        compact_type::I64 => TType::I64,
+        compact_type::DOUBLE => TType::Double,
+        compact_type::BINARY => TType::String,
+        compact_type::LIST => TType::List,
+        compact_type::SET => TType::Set,
+        compact_type::MAP => TType::Map,
+        compact_type::STRUCT => TType::Struct,
+        b => return Err(invalid_data(format!("unknown compact type {}", b))),
+    })
+}
+
+fn to_message_type(byte: u8) -> Result<TMessageType, Error> {
+    Ok(match byte {
+        1 => TMessageType::Call,
+        2 => TMessageType::Reply,
+        3 => TMessageType::Exception,
+        4 => TMessageType::OneWay,
+        b => return Err(invalid_data(format!("unknown message type {}", b))),
+    })
+}
+
+fn varint_len(mut n: u64) -> usize {
+    let mut len = 1;
+    while n >= 0x80 {
+        n >>= 7;
+        len += 1;
+    }
+    len
+}
+
+fn zigzag_len(n: i64) -> usize {
+    varint_len(((n << 1) ^ (n >> 63)) as u64)
+}
+
+fn message_type_id(message_type: TMessageType) -> u8 {
+    match message_type {
+        TMessageType::Call => 1,
+        TMessageType::Reply => 2,
+        TMessageType::Exception => 3,
+        TMessageType::OneWay => 4,
+    }
+}
+
+// the nested values are kept on a stack while measuring, so limit the depth to bound it
+const MAX_DEPTH: usize = 64;
+
+/// The reasons a message can't be measured.
+enum Unmeasured {
+    /// The buffer ends before the message, which is at least this long.
+    Short(usize),
+    Invalid(Error),
+}
+
+impl From<Error> for Unmeasured {
+    fn from(err: Error) -> Self {
+        Unmeasured::Invalid(err)
+    }
+}
+
+/// A struct, list, set or map being measured.
+enum Nested {
+    Struct,
+    /// The elements left of a list or a set.
+    List {
+        element_type: TType,
+        left: u64,
+    },
+    /// The keys and the values left of a map, one after another.
+    Map {
+        key_type: TType,
+        value_type: TType,
+        left: u64,
+    },
+}
+
+/// Walks the compact message beginning a buffer without decoding it.
+///
+/// The buffer may grow between the calls of [`measure`](Self::measure), the walk goes on from
+/// the last value fully in the buffer instead of starting over.
+#[derive(Default)]
+pub(crate) struct MessageLen {
+    pos: usize,
+    begun: bool,
+    nested: Vec<Nested>,
+}
+
+/// Reads a value of a buffer from a position, moving the position only when the value is read.
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn skip(&mut self, n: usize) -> Result<(), Unmeasured> {
+        let end = self.pos.saturating_add(n);
+        if end > self.buf.len() {
+            return Err(Unmeasured::Short(end));
+        }
+        self.pos = end;
+        Ok(())
+    }
+
+    fn byte(&mut self) -> Result<u8, Unmeasured> {
+        self.skip(1)?;
+        Ok(self.buf[self.pos - 1])
+    }
+
+    fn varint(&mut self) -> Result<u64, Unmeasured> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            n |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(invalid_data("the varint is too long").into())
+    }
+}
+
+impl MessageLen {
+    /// Returns the length of the compact message beginning `buf`, or a lower bound of it larger
+    /// than `buf.len()` if `buf` ends before the message.
+    pub(crate) fn measure(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        loop {
+            let mut cursor = Cursor { buf, pos: self.pos };
+            match self.step(&mut cursor) {
+                // the value is read again with the next bytes
+                Err(Unmeasured::Short(len)) => return Ok(len),
+                Err(Unmeasured::Invalid(err)) => return Err(err),
+                Ok(()) => self.pos = cursor.pos,
+            }
+            if self.nested.is_empty() {
+                return Ok(self.pos);
+            }
+        }
+    }
+
+    /// Reads the next value, or the header of it if nested, changing the state only if read.
+    fn step(&mut self, cursor: &mut Cursor) -> Result<(), Unmeasured> {
+        let (ttype, field) = match self.nested.last_mut() {
+            None if self.begun => return Ok(()),
+            None => {
+                // the protocol id and the version are checked by the caller
+                cursor.skip(2)?;
+                cursor.varint()?;
+                let name_len = cursor.varint()?;
+                cursor.skip(name_len as usize)?;
+                self.begun = true;
+                return self.begin(Nested::Struct);
+            }
+            Some(Nested::Struct) => {
+                let header = cursor.byte()?;
+                let field_type = from_compact_type(header)?;
+                if field_type == TType::Stop {
+                    self.nested.pop();
+                    return Ok(());
+                }
+                if header >> 4 == 0 {
+                    cursor.varint()?;
+                }
+                (field_type, true)
+            }
+            Some(Nested::List { left: 0, .. }) | Some(Nested::Map { left: 0, .. }) => {
+                self.nested.pop();
+                return Ok(());
+            }
+            Some(Nested::List { element_type, .. }) => (*element_type, false),
+            Some(Nested::Map {
+                key_type,
+                value_type,
+                left,
+            }) => (
+                if *left % 2 == 0 {
+                    *key_type
+                } else {
+                    *value_type
+                },
+                false,
+            ),
+        };
+        let nested = self.value(cursor, ttype, field)?;
+        // the value is read, so it is not to be read again
+        match self.nested.last_mut() {
+            Some(Nested::List { left, .. }) | Some(Nested::Map { left, .. }) => *left -= 1,
+            _ => {}
+        }
+        match nested {
+            Some(nested) => self.begin(nested),
+            None => Ok(()),
+        }
+    }
+
+    fn begin(&mut self, nested: Nested) -> Result<(), Unmeasured> {
+        if self.nested.len() >= MAX_DEPTH {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::DepthLimit,
+                format!("the message is nested deeper than {}", MAX_DEPTH),
+            )
+            .into());
+        }
+        self.nested.push(nested);
+        Ok(())
+    }
+
+    /// Skips a value, or the header of it if nested, the bools of the fields are in their
+    /// headers.
+    fn value(
+        &self,
+        cursor: &mut Cursor,
+        ttype: TType,
+        field: bool,
+    ) -> Result<Option<Nested>, Unmeasured> {
+        match ttype {
+            TType::Bool if field => {}
+            TType::Bool | TType::I08 => cursor.skip(1)?,
+            TType::I16 | TType::I32 | TType::I64 => {
+                cursor.varint()?;
+            }
+            TType::Double => cursor.skip(8)?,
+            TType::String => {
+                let len = cursor.varint()?;
+                cursor.skip(len as usize)?;
+            }
+            TType::Struct => return Ok(Some(Nested::Struct)),
+            TType::List | TType::Set => {
+                let header = cursor.byte()?;
+                let element_type = from_compact_type(header)?;
+                let left = match header >> 4 {
+                    0x0f => cursor.varint()?,
+                    size => size as u64,
+                };
+                // each element takes at least one byte, so a corrupted size ends the buffer soon
+                return Ok(Some(Nested::List { element_type, left }));
+            }
+            TType::Map => {
+                let size = cursor.varint()?;
+                if size > 0 {
+                    let types = cursor.byte()?;
+                    return Ok(Some(Nested::Map {
+                        key_type: from_compact_type(types >> 4)?,
+                        value_type: from_compact_type(types)?,
+                        left: size.saturating_mul(2),
+                    }));
+                }
+            }
+            ttype => return Err(invalid_data(format!("unsupported field type {:?}", ttype)).into()),
+        }
+        Ok(None)
+    }
+}
+
+/// The compact protocol on a buffer, which is written to when encoding and consumed when
+/// decoding, the same as `TBinaryProtocol`.
+pub struct TCompactProtocol<T> {
+    trans: T,
+    /// The id of the last field of the current struct.
+    last_field_id: i16,
+    /// The ids of the last fields of the outer structs.
+    last_field_ids: Vec<i16>,
+    /// The bool field whose value is written in its header, when encoding.
+    pending_bool_field: Option<i16>,
+    /// The value of the bool field read from its header, when decoding.
+    pending_bool_value: Option<bool>,
+}
+
+impl<T> TCompactProtocol<T> {
+    pub fn new(trans: T) -> Self {
+        Self {
+            trans,
+            last_field_id: 0,
+            last_field_ids: Vec::new(),
+            pending_bool_field: None,
+            pending_bool_value: None,
+        }
+    }
+}
+
+impl TCompactProtocol<&mut BytesMut> {
+    fn write_varint(&mut self, mut n: u64) {
+        while n >= 0x80 {
+            self.trans.put_u8((n as u8) | 0x80);
+            n >>= 7;
+        }
+        self.trans.put_u8(n as u8);
+    }
+
+    fn write_zigzag(&mut self, n: i64) {
+        self.write_varint(((n << 1) ^ (n >> 63)) as u64);
+    }
+
+    fn write_field_header(&mut self, compact_type: u8, id: i16) {
+        let delta = id.wrapping_sub(self.last_field_id);
+        if (1..=15).contains(&delta) {
+            self.trans.put_u8(((delta as u8) << 4) | compact_type);
+        } else {
+            self.trans.put_u8(compact_type);
+            self.write_zigzag(id as i64);
+        }
+        self.last_field_id = id;
+    }
+
+    fn write_collection_begin(&mut self, element_type: TType, size: usize) -> Result<(), Error> {
+        let element_type = to_compact_type(element_type)?;
+        if size < 15 {
+            self.trans.put_u8(((size as u8) << 4) | element_type);
+        } else {
+            self.trans.put_u8(0xf0 | element_type);
+            self.write_varint(size as u64);
+        }
+        Ok(())
+    }
+
+    fn ensure(&self, n: usize) -> Result<(), Error> {
+        if self.trans.remaining() < n {
+            return Err(invalid_data("the compact message ends unexpectedly"));
+        }
+        Ok(())
+    }
+
+    fn read_varint(&mut self) -> Result<u64, Error> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            self.ensure(1)?;
+            let b = self.trans.get_u8();
+            n |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(invalid_data("the varint is too long"))
+    }
+
+    fn read_zigzag(&mut self) -> Result<i64, Error> {
+        let n = self.read_varint()?;
+        Ok(((n >> 1) as i64) ^ -((n & 1) as i64))
+    }
+
+    fn read_size(&mut self) -> Result<usize, Error> {
+        let size = self.read_varint()?;
+        if size > self.trans.remaining() as u64 {
+            // each element takes at least one byte, so a corrupted size is rejected before
+            // allocating
+            return Err(invalid_data(format!("invalid size {}", size)));
+        }
+        Ok(size as usize)
+    }
+
+    fn read_collection_begin(&mut self) -> Result<(TType, usize), Error> {
+        self.ensure(1)?;
+        let header = self.trans.get_u8();
+        let element_type = from_compact_type(header)?;
+        let size = match header >> 4 {
+            0x0f => self.read_size()?,
+            size => size as usize,
+        };
+        Ok((element_type, size))
+    }
+}
+
+/// The lengths are the upper bounds for reserving the buffer, as the field headers depend on the
+/// previous fields.
+impl<T> TLengthProtocol for TCompactProtocol<T> {
+    fn write_message_begin_len(&self, identifier: &TMessageIdentifier) -> usize {
+        2 + varint_len(identifier.sequence_number as u32 as u64)
+            + self.write_string_len(&identifier.name)
+    }
+
+    fn write_message_end_len(&self) -> usize {
+        0
+    }
+
+    fn write_struct_begin_len(&self, _identifier: &TStructIdentifier) -> usize {
+        0
+    }
+
+    fn write_struct_end_len(&self) -> usize {
+        0
+    }
+
+    fn write_field_begin_len(&self, identifier: &TFieldIdentifier) -> usize {
+        1 + zigzag_len(identifier.id.unwrap_or_default() as i64)
+    }
+
+    fn write_field_end_len(&self) -> usize {
+        0
+    }
+
+    fn write_field_stop_len(&self) -> usize {
+        1
+    }
+
+    fn write_bool_len(&self, _b: bool) -> usize {
+        1
+    }
+
+    fn write_bytes_len(&self, b: &[u8]) -> usize {
+        varint_len(b.len() as u64) + b.len()
+    }
+
+    fn write_byte_len(&self, _b: u8) -> usize {
+        1
+    }
+
+    fn write_i8_len(&self, _i: i8) -> usize {
+        1
+    }
+
+    fn write_i16_len(&self, i: i16) -> usize {
+        zigzag_len(i as i64)
+    }
+
+    fn write_i32_len(&self, i: i32) -> usize {
+        zigzag_len(i as i64)
+    }
+
+    fn write_i64_len(&self, i: i64) -> usize {
+        zigzag_len(i)
+    }
+
+    fn write_double_len(&self, _d: f64) -> usize {
+        8
+    }
+
+    fn write_string_len(&self, s: &str) -> usize {
+        self.write_bytes_len(s.as_bytes())
+    }
+
+    fn write_list_begin_len(&self, identifier: &TListIdentifier) -> usize {
+        1 + varint_len(identifier.size as u64)
+    }
+
+    fn write_list_end_len(&self) -> usize {
+        0
+    }
+
+    fn write_set_begin_len(&self, identifier: &TSetIdentifier) -> usize {
+        1 + varint_len(identifier.size as u64)
+    }
+
+    fn write_set_end_len(&self) -> usize {
+        0
+    }
+
+    fn write_map_begin_len(&self, identifier: &TMapIdentifier) -> usize {
+        1 + varint_len(identifier.size as u64)
+    }
+
+    fn write_map_end_len(&self) -> usize {
+        0
+    }
+}
+
+impl TOutputProtocol for TCompactProtocol<&mut BytesMut> {
+    type Buf = BytesMut;
+
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> Result<(), Error> {
+        self.trans.put_u8(PROTOCOL_ID);
+        self.trans
+            .put_u8(VERSION | (message_type_id(identifier.message_type) << TYPE_SHIFT));
+        self.write_varint(identifier.sequence_number as u32 as u64);
+        self.write_string(&identifier.name)
+    }
+
+    fn write_message_end(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn write_struct_begin(&mut self, _identifier: &TStructIdentifier) -> Result<(), Error> {
+        self.last_field_ids.push(self.last_field_id);
+        self.last_field_id = 0;
+        Ok(())
+    }
+
+    fn write_struct_end(&mut self) -> Result<(), Error> {
+        self.last_field_id = self.last_field_ids.pop().unwrap_or_default();
+        Ok(())
+    }
+
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> Result<(), Error> {
+        let (field_type, id) = (identifier.field_type, identifier.id.unwrap_or_default());
+        if field_type == TType::Bool {
+            // the value is written in the header
+            self.pending_bool_field = Some(id);
+        } else {
+            let compact_type = to_compact_type(field_type)?;
+            self.write_field_header(compact_type, id);
+        }
+        Ok(())
+    }
+
+    fn write_field_end(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn write_field_stop(&mut self) -> Result<(), Error> {
+        self.trans.put_u8(compact_type::STOP);
+        Ok(())
+    }
+
+    fn write_bool(&mut self, b: bool) -> Result<(), Error> {
+        let compact_type = if b {
+            compact_type::BOOLEAN_TRUE
+        } else {
+            compact_type::BOOLEAN_FALSE
+        };
+        match self.pending_bool_field.take() {
+            Some(id) => self.write_field_header(compact_type, id),
+            None => self.trans.put_u8(compact_type),
+        }
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, b: &[u8]) -> Result<(), Error> {
+        self.write_varint(b.len() as u64);
+        self.trans.put_slice(b);
+        Ok(())
+    }
+
+    fn write_byte(&mut self, b: u8) -> Result<(), Error> {
+        self.trans.put_u8(b);
+        Ok(())
+    }
+
+    fn write_i8(&mut self, i: i8) -> Result<(), Error> {
+        self.trans.put_i8(i);
+        Ok(())
+    }
+
+    fn write_i16(&mut self, i: i16) -> Result<(), Error> {
+        self.write_zigzag(i as i64);
+        Ok(())
+    }
+
+    fn write_i32(&mut self, i: i32) -> Result<(), Error> {
+        self.write_zigzag(i as i64);
+        Ok(())
+    }
+
+    fn write_i64(&mut self, i: i64) -> Result<(), Error> {
+        self.write_zigzag(i);
+        Ok(())
+    }
+
+    fn write_double(&mut self, d: f64) -> Result<(), Error> {
+        self.trans.put_f64_le(d);
+        Ok(())
+    }
+
+    fn write_string(&mut self, s: &str) -> Result<(), Error> {
+        self.write_bytes(s.as_bytes())
+    }
+
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) -> Result<(), Error> {
+        self.write_collection_begin(identifier.element_type, identifier.size as usize)
+    }
+
+    fn write_list_end(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) -> Result<(), Error> {
+        self.write_collection_begin(identifier.element_type, identifier.size as usize)
+    }
+
+    fn write_set_end(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> Result<(), Error> {
+        if identifier.size == 0 {
+            self.trans.put_u8(0);
+        } else {
+            self.write_varint(identifier.size as u64);
+            let key_type = to_compact_type(identifier.key_type)?;
+            let value_type = to_compact_type(identifier.value_type)?;
+            self.trans.put_u8((key_type << 4) | value_type);
+        }
+        Ok(())
+    }
+
+    fn write_map_end(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn reserve(&mut self, size: usize) {
+        self.trans.reserve(size)
+    }
+
+    fn buf_mut(&mut self) -> &mut Self::Buf {
+        &mut *self.trans
+    }
+}
+
+impl TInputProtocol for TCompactProtocol<&mut BytesMut> {
+    type Buf = BytesMut;
+
+    fn read_message_begin(&mut self) -> Result<TMessageIdentifier, Error> {
+        self.ensure(2)?;
+        let protocol_id = self.trans.get_u8();
+        if protocol_id != PROTOCOL_ID {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::BadVersion,
+                format!("invalid compact protocol id {:#x}", protocol_id),
+            ));
+        }
+        let header = self.trans.get_u8();
+        if header & VERSION_MASK != VERSION {
+            return Err(new_protocol_error(
+                ProtocolErrorKind::BadVersion,
+                format!("invalid compact version {}", header & VERSION_MASK),
+            ));
+        }
+        let message_type = to_message_type(header >> TYPE_SHIFT)?;
+        let sequence_number = self.read_varint()? as u32 as i32;
+        let name = self.read_string()?;
+        Ok(TMessageIdentifier::new(
+            name.into(),
+            message_type,
+            sequence_number,
+        ))
+    }
+
+    fn read_message_end(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn read_struct_begin(&mut self) -> Result<Option<TStructIdentifier>, Error> {
+        self.last_field_ids.push(self.last_field_id);
+        self.last_field_id = 0;
+        Ok(None)
+    }
+
+    fn read_struct_end(&mut self) -> Result<(), Error> {
+        self.last_field_id = self.last_field_ids.pop().unwrap_or_default();
+        Ok(())
+    }
+
+    fn read_field_begin(&mut self) -> Result<TFieldIdentifier, Error> {
+        self.ensure(1)?;
+        let header = self.trans.get_u8();
+        let field_type = from_compact_type(header)?;
+        if field_type == TType::Stop {
+            return Ok(TFieldIdentifier {
+                name: None,
+                field_type,
+                id: None,
+            });
+        }
+        let id = match header >> 4 {
+            0 => self.read_zigzag()? as i16,
+            delta => self.last_field_id.wrapping_add(delta as i16),
+        };
+        self.last_field_id = id;
+        if field_type == TType::Bool {
+            self.pending_bool_value = Some(header & 0x0f == compact_type::BOOLEAN_TRUE);
+        }
+        Ok(TFieldIdentifier {
+            name: None,
+            field_type,
+            id: Some(id),
+        })
+    }
+
+    fn read_field_end(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn read_bool(&mut self) -> Result<bool, Error> {
+        match self.pending_bool_value.take() {
+            Some(b) => Ok(b),
+            None => Ok(self.read_byte()? == compact_type::BOOLEAN_TRUE),
+        }
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>, Error> {
+        let len = self.read_size()?;
+        Ok(self.trans.split_to(len).to_vec())
+    }
+
+    fn read_string(&mut self) -> Result<String, Error> {
+        let bytes = self.read_bytes()?;
+        String::from_utf8(bytes).map_err(|e| invalid_data(e.to_string()))
+    }
+
+    fn read_i8(&mut self) -> Result<i8, Error> {
+        self.ensure(1)?;
+        Ok(self.trans.get_i8())
+    }
+
+    fn read_i16(&mut self) -> Result<i16, Error> {
+        Ok(self.read_zigzag()? as i16)
+    }
+
+    fn read_i32(&mut self) -> Result<i32, Error> {
+        Ok(self.read_zigzag()? as i32)
+    }
+
+    fn read_i64(&mut self) -> Result<i64, Error> {
+        self.read_zigzag()
+    }
+
+    fn read_double(&mut self) -> Result<f64, Error> {
+        self.ensure(8)?;
+        Ok(self.trans.get_f64_le())
+    }
+
+    fn read_list_begin(&mut self) -> Result<TListIdentifier, Error> {
+        let (element_type, size) = self.read_collection_begin()?;
+        Ok(TListIdentifier {
+            element_type,
+            size: size as _,
+        })
+    }
+
+    fn read_list_end(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn read_set_begin(&mut self) -> Result<TSetIdentifier, Error> {
+        let (element_type, size) = self.read_collection_begin()?;
+        Ok(TSetIdentifier {
+            element_type,
+            size: size as _,
+        })
+    }
+
+    fn read_set_end(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn read_map_begin(&mut self) -> Result<TMapIdentifier, Error> {
+        let size = self.read_size()?;
+        let (key_type, value_type) = if size == 0 {
+            (TType::Stop, TType::Stop)
+        } else {
+            self.ensure(1)?;
+            let types = self.trans.get_u8();
+            (from_compact_type(types >> 4)?, from_compact_type(types)?)
+        };
+        Ok(TMapIdentifier {
+            key_type,
+            value_type,
+            size: size as _,
+        })
+    }
+
+    fn read_map_end(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        self.ensure(1)?;
+        Ok(self.trans.get_u8())
+    }
+
+    fn buf_mut(&mut self) -> &mut Self::Buf {
+        &mut *self.trans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut buf = BytesMut::new();
+        let mut p = TCompactProtocol::new(&mut buf);
+        p.write_message_begin(&TMessageIdentifier::new(
+            "echo".into(),
+            TMessageType::Call,
+            7,
+        ))
+        .unwrap();
+        p.write_struct_begin(&TStructIdentifier::new("Args"))
+            .unwrap();
+        p.write_field_begin(&TFieldIdentifier::new("a", TType::I32, 1))
+            .unwrap();
+        p.write_i32(-3).unwrap();
+        p.write_field_begin(&TFieldIdentifier::new("b", TType::Bool, 2))
+            .unwrap();
+        p.write_bool(true).unwrap();
+        // a long delta is written with the id
+        p.write_field_begin(&TFieldIdentifier::new("c", TType::I64, 100))
+            .unwrap();
+        p.write_i64(i64::MAX).unwrap();
+        p.write_field_stop().unwrap();
+        p.write_struct_end().unwrap();
+        p.write_message_end().unwrap();
+        assert_eq!(&buf[..2], &[PROTOCOL_ID, VERSION | (1 << TYPE_SHIFT)]);
+        // the delta and the type in one byte, the zigzag varint, and the bool in the header
+        assert_eq!(&buf[8..11], &[0x15, 0x05, 0x11]);
+
+        let mut p = TCompactProtocol::new(&mut buf);
+        let ident = p.read_message_begin().unwrap();
+        assert_eq!(ident.sequence_number, 7);
+        assert_eq!(&*ident.name, "echo");
+        p.read_struct_begin().unwrap();
+        let field = p.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::I32, Some(1)));
+        assert_eq!(p.read_i32().unwrap(), -3);
+        let field = p.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::Bool, Some(2)));
+        assert!(p.read_bool().unwrap());
+        let field = p.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::I64, Some(100)));
+        assert_eq!(p.read_i64().unwrap(), i64::MAX);
+        assert_eq!(p.read_field_begin().unwrap().field_type, TType::Stop);
+        p.read_struct_end().unwrap();
+        assert!(buf.is_empty());
+
+        // a corrupted size is rejected
+        let mut buf = BytesMut::from(&[0xff, 0xff, 0x03][..]);
+        TCompactProtocol::new(&mut buf).read_bytes().unwrap_err();
+    }
+
+    fn message_len(buf: &[u8]) -> Result<usize, Error> {
+        MessageLen::default().measure(buf)
+    }
+
+    #[test]
+    fn test_message_len() {
+        let mut buf = BytesMut::new();
+        let mut p = TCompactProtocol::new(&mut buf);
+        p.write_message_begin(&TMessageIdentifier::new(
+            "echo".into(),
+            TMessageType::Call,
+            1,
+        ))
+        .unwrap();
+        p.write_struct_begin(&TStructIdentifier::new("Args"))
+            .unwrap();
+        p.write_field_begin(&TFieldIdentifier::new("a", TType::Bool, 1))
+            .unwrap();
+        p.write_bool(true).unwrap();
+        p.write_field_begin(&TFieldIdentifier::new("b", TType::List, 2))
+            .unwrap();
+        p.write_list_begin(&TListIdentifier::new(TType::String, 2))
+            .unwrap();
+        p.write_string("hello").unwrap();
+        p.write_string("world").unwrap();
+        p.write_field_begin(&TFieldIdentifier::new("c", TType::Map, 3))
+            .unwrap();
+        p.write_map_begin(&TMapIdentifier::new(TType::I32, TType::Double, 1))
+            .unwrap();
+        p.write_i32(1).unwrap();
+        p.write_double(1.0).unwrap();
+        p.write_field_stop().unwrap();
+        p.write_struct_end().unwrap();
+        let len = buf.len();
+        buf.extend_from_slice(&[PROTOCOL_ID, VERSION]);
+        // the bytes after the message are not measured
+        assert_eq!(message_len(&buf).unwrap(), len);
+        for end in 0..len {
+            assert!(message_len(&buf[..end]).unwrap() > end);
+        }
+        // the bytes of the string are known from its length
+        assert_eq!(message_len(&buf[..12]).unwrap(), 17);
+
+        // the measure goes on with the bytes read later
+        let mut measure = MessageLen::default();
+        for end in 0..len {
+            assert!(measure.measure(&buf[..end]).unwrap() > end);
+        }
+        assert_eq!(measure.measure(&buf).unwrap(), len);
+
+        buf[len - 1] = 0x0f;
+        message_len(&buf).unwrap_err();
+    }
+
+    #[test]
+    fn test_message_depth() {
+        // a struct of a list field, then the lists of lists
+        let mut buf = vec![PROTOCOL_ID, VERSION | (1 << TYPE_SHIFT), 1, 0, 0x19];
+        buf.extend(std::iter::repeat(0x19).take(1 << 20));
+        let err = message_len(&buf).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Protocol(ref e) if matches!(e.kind, ProtocolErrorKind::DepthLimit)
+        ));
+
+        let mut buf = vec![PROTOCOL_ID, VERSION | (1 << TYPE_SHIFT), 1, 0, 0x1b];
+        buf.extend(std::iter::repeat([0x01, 0xbb]).take(1 << 20).flatten());
+        message_len(&buf).unwrap_err();
+    }
+}
@@ -1,4 +1,7 @@
+pub mod compact;
+
 pub use binary::TBinaryProtocol;
+pub use compact::TCompactProtocol;
 pub(crate) use pilota::thrift::rw_ext;
 pub use pilota::thrift::{
     binary, TFieldIdentifier, TInputProtocol, TLengthProtocol, TListIdentifier, TMapIdentifier,
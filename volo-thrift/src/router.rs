@@ -0,0 +1,257 @@
+//! Serves several thrift services on one listener.
+//!
+//! Each request is dispatched by the name of the service it's for, which is the service prefixed
+//! to the method name by a `TMultiplexedProtocol` client, such as `ItemService:GetItem`, or else
+//! the service in the TTHeader of the request, which is the service name set in the client
+//! builder. The requests for none of the services go to the default service if any, or fail with
+//! `UnknownMethod`.
+//!
+//! The requests are received as [`Binary`] and decoded by the service routed to, so the services
+//! can be of different IDLs, and each service can have its own layers.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo_thrift::{router::ServiceRouter, server::Server};
+//!
+//! let router = ServiceRouter::new()
+//!     .add_service(ItemServiceServer::service(ItemImpl))
+//!     .add_named("user", UserServiceServer::service(UserImpl))
+//!     .default_service(ItemServiceServer::service(ItemImpl));
+//!
+//! Server::new(router).run(addr).await;
+//! ```
+
+use std::{future::Future, marker::PhantomData};
+
+use bytes::BytesMut;
+use motore::{
+    service::{BoxCloneService, Service},
+    BoxError,
+};
+use pilota::thrift::{EntryMessage, TMessageIdentifier};
+use volo::{context::Context, service_info::ServiceInfo};
+
+use crate::{
+    context::{ServerContext, ThriftContext},
+    generic::Binary,
+    protocol::{TBinaryProtocol, TMessageType},
+    ApplicationError, ApplicationErrorKind, Error,
+};
+
+type BoxRoute = BoxCloneService<ServerContext, Binary, Binary, Error>;
+
+/// Decodes the [`Binary`] request for the service, and encodes its response.
+struct Route<S, Req> {
+    inner: S,
+    _marker: PhantomData<fn(Req)>,
+}
+
+impl<S: Clone, Req> Clone for Route<S, Req> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, Req> Service<ServerContext, Binary> for Route<S, Req>
+where
+    S: Service<ServerContext, Req> + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Response: EntryMessage + Send,
+    Req: EntryMessage + Send + 'static,
+{
+    type Response = Binary;
+    type Error = Error;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut ServerContext, req: Binary) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let ident = TMessageIdentifier::new(
+                cx.rpc_info().method().cloned().unwrap_or_default(),
+                cx.req_msg_type.unwrap_or(TMessageType::Call),
+                cx.seq_id(),
+            );
+            let mut buf = BytesMut::from(&req.0[..]);
+            let req = Req::decode(&mut TBinaryProtocol::new(&mut buf), &ident)?;
+            let resp = crate::transport::server::handle(cx, &mut self.inner, req).await?;
+            let mut buf = BytesMut::new();
+            resp.encode(&mut TBinaryProtocol::new(&mut buf))?;
+            Ok(Binary(buf.freeze()))
+        }
+    }
+}
+
+/// A [`Service`] that dispatches the requests to the services by their names.
+#[derive(Clone, Default)]
+pub struct ServiceRouter {
+    routes: Vec<(smol_str::SmolStr, BoxRoute)>,
+    default: Option<BoxRoute>,
+}
+
+impl ServiceRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a generated service, such as `ItemServiceServer::service(S)`, by its name in the IDL.
+    ///
+    /// Both the full name, such as `volo.example.ItemService`, and the name without the package
+    /// are routed to it.
+    pub fn add_service<S, Req>(self, service: S) -> Self
+    where
+        S: ServiceInfo + Service<ServerContext, Req> + Clone + Send + 'static,
+        S::Error: Into<BoxError>,
+        S::Response: EntryMessage + Send,
+        for<'cx> S::Future<'cx>: Send,
+        Req: EntryMessage + Send + 'static,
+    {
+        self.add_named(S::NAME, service)
+    }
+
+    /// Adds a service routed by `name`.
+    ///
+    /// The services are matched in the order they are added.
+    pub fn add_named<S, Req>(mut self, name: impl AsRef<str>, service: S) -> Self
+    where
+        S: Service<ServerContext, Req> + Clone + Send + 'static,
+        S::Error: Into<BoxError>,
+        S::Response: EntryMessage + Send,
+        for<'cx> S::Future<'cx>: Send,
+        Req: EntryMessage + Send + 'static,
+    {
+        self.routes.push((name.as_ref().into(), route(service)));
+        self
+    }
+
+    /// Sets the service of the requests for none of the services, such as the ones from the
+    /// clients of the service served on the port before.
+    pub fn default_service<S, Req>(mut self, service: S) -> Self
+    where
+        S: Service<ServerContext, Req> + Clone + Send + 'static,
+        S::Error: Into<BoxError>,
+        S::Response: EntryMessage + Send,
+        for<'cx> S::Future<'cx>: Send,
+        Req: EntryMessage + Send + 'static,
+    {
+        self.default = Some(route(service));
+        self
+    }
+
+    fn find(&self, name: &str) -> Option<&BoxRoute> {
+        self.routes
+            .iter()
+            .find(|(n, _)| n == name)
+            .or_else(|| {
+                self.routes
+                    .iter()
+                    .find(|(n, _)| n.rsplit('.').next() == Some(name))
+            })
+            .map(|(_, route)| route)
+    }
+
+    /// Returns the route of the request, by the multiplexed service, then the TTHeader service.
+    fn route_of(&self, cx: &ServerContext) -> Result<BoxRoute, Error> {
+        let multiplexed = cx.multiplexed_service.as_deref();
+        if let Some(name) = multiplexed {
+            if let Some(route) = self.find(name) {
+                return Ok(route.clone());
+            }
+        }
+        let callee = cx.rpc_info().callee().map(|c| c.service_name_ref());
+        if let Some(route) = callee.and_then(|name| self.find(name)) {
+            return Ok(route.clone());
+        }
+        self.default.clone().ok_or_else(|| {
+            Error::Application(ApplicationError::new(
+                ApplicationErrorKind::UnknownMethod,
+                format!(
+                    "unknown service: {}",
+                    multiplexed.or(callee).unwrap_or_default()
+                ),
+            ))
+        })
+    }
+}
+
+fn route<S, Req>(service: S) -> BoxRoute
+where
+    S: Service<ServerContext, Req> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Response: EntryMessage + Send,
+    for<'cx> S::Future<'cx>: Send,
+    Req: EntryMessage + Send + 'static,
+{
+    BoxRoute::new(Route {
+        inner: service,
+        _marker: PhantomData,
+    })
+}
+
+impl Service<ServerContext, Binary> for ServiceRouter {
+    type Response = Binary;
+    type Error = Error;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut ServerContext, req: Binary) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let mut route = self.route_of(cx)?;
+            route.call(cx, req).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use motore::service::service_fn;
+    use volo::context::Endpoint;
+
+    use super::*;
+
+    async fn item(_: &mut ServerContext, _: Binary) -> Result<Binary, Error> {
+        Ok(Binary(Bytes::from_static(b"item")))
+    }
+
+    async fn user(_: &mut ServerContext, _: Binary) -> Result<Binary, Error> {
+        Ok(Binary(Bytes::from_static(b"user")))
+    }
+
+    async fn call(
+        router: &mut ServiceRouter,
+        multiplexed: Option<&str>,
+        callee: Option<&str>,
+    ) -> Result<Binary, Error> {
+        let mut cx = ServerContext::default();
+        cx.rpc_info.method = Some("GetItem".into());
+        cx.multiplexed_service = multiplexed.map(Into::into);
+        cx.rpc_info.callee = callee.map(|c| Endpoint::new(c.into()));
+        // an empty args struct
+        router.call(&mut cx, Binary(Bytes::from_static(&[0]))).await
+    }
+
+    #[tokio::test]
+    async fn test_router() {
+        let mut router = ServiceRouter::new()
+            .add_named("volo.example.ItemService", service_fn(item))
+            .add_named("user", service_fn(user));
+
+        let resp = call(&mut router, Some("ItemService"), Some("user")).await;
+        assert_eq!(resp.unwrap().0, "item");
+        let resp = call(&mut router, None, Some("user")).await;
+        assert_eq!(resp.unwrap().0, "user");
+        call(&mut router, None, Some("order")).await.unwrap_err();
+
+        let mut router = router.default_service(service_fn(item));
+        let resp = call(&mut router, Some("order"), None).await;
+        assert_eq!(resp.unwrap().0, "item");
+    }
+}
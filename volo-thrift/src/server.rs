@@ -13,20 +13,35 @@ use motore::{
 use pilota::thrift::EntryMessage;
 use tokio::sync::Notify;
 use tracing::info;
+use volo::{
+    registry::{Registry, RegistryInfo},
+    rt::Runtime,
+};
 
 use crate::{
     codec::{
-        framed::Framed, tt_header, MakeServerDecoder, MakeServerEncoder, MkDecoder, MkEncoder,
+        framed::Framed, tt_header, unknown_fields::DecodeMode, MakeServerDecoder,
+        MakeServerEncoder, MkDecoder, MkEncoder, Protocol,
     },
     context::ServerContext,
     Result, Size,
 };
 
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+// how long the responses carry the connection reset hint before the idle connections are closed
+const CONN_RESET_HINT_DURATION: Duration = Duration::from_secs(2);
+
 pub struct Server<S, L, Req, MkE, MkD> {
     service: S,
     layer: L,
     mk_encoder: MkE,
     mk_decoder: MkD,
+    shutdown_timeout: Duration,
+    conn_reset_hint: bool,
+    registry: Option<(Arc<dyn Registry>, RegistryInfo)>,
+    runtime: Runtime,
+    #[cfg(feature = "rustls")]
+    tls: Option<volo::net::tls::TlsAcceptor>,
     _marker: PhantomData<fn(Req)>,
 }
 
@@ -39,6 +54,11 @@ impl<S, Req>
         MakeServerDecoder<tt_header::DefaultTTHeaderCodec>,
     >
 {
+    /// Creates a new server.
+    ///
+    /// The protocol of each connection is detected from its first message, so TTHeader, framed
+    /// and unframed clients of both the binary and the compact protocol can be served on the same
+    /// listener. The responses are sent back with the same protocol.
     pub fn new(service: S) -> Self
     where
         S: Service<ServerContext, Req>,
@@ -48,6 +68,12 @@ impl<S, Req>
             mk_decoder: MakeServerDecoder::new(tt_header::DefaultTTHeaderCodec),
             service,
             layer: Identity::new(),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            conn_reset_hint: true,
+            registry: None,
+            runtime: Runtime::default(),
+            #[cfg(feature = "rustls")]
+            tls: None,
             _marker: PhantomData,
         }
     }
@@ -71,6 +97,12 @@ impl<S, L, Req, MkE, MkD> Server<S, L, Req, MkE, MkD> {
             service: self.service,
             mk_encoder: self.mk_encoder,
             mk_decoder: self.mk_decoder,
+            shutdown_timeout: self.shutdown_timeout,
+            conn_reset_hint: self.conn_reset_hint,
+            registry: self.registry,
+            runtime: self.runtime,
+            #[cfg(feature = "rustls")]
+            tls: self.tls,
             _marker: PhantomData,
         }
     }
@@ -92,6 +124,12 @@ impl<S, L, Req, MkE, MkD> Server<S, L, Req, MkE, MkD> {
             service: self.service,
             mk_encoder: self.mk_encoder,
             mk_decoder: self.mk_decoder,
+            shutdown_timeout: self.shutdown_timeout,
+            conn_reset_hint: self.conn_reset_hint,
+            registry: self.registry,
+            runtime: self.runtime,
+            #[cfg(feature = "rustls")]
+            tls: self.tls,
             _marker: PhantomData,
         }
     }
@@ -114,6 +152,12 @@ impl<S, L, Req, MkE, MkD> Server<S, L, Req, MkE, MkD> {
             service: self.service,
             mk_encoder: MakeServerEncoder::new(tt_encoder),
             mk_decoder: self.mk_decoder,
+            shutdown_timeout: self.shutdown_timeout,
+            conn_reset_hint: self.conn_reset_hint,
+            registry: self.registry,
+            runtime: self.runtime,
+            #[cfg(feature = "rustls")]
+            tls: self.tls,
             _marker: PhantomData,
         }
     }
@@ -136,11 +180,62 @@ impl<S, L, Req, MkE, MkD> Server<S, L, Req, MkE, MkD> {
             service: self.service,
             mk_encoder: self.mk_encoder,
             mk_decoder: MakeServerDecoder::new(tt_decoder),
+            shutdown_timeout: self.shutdown_timeout,
+            conn_reset_hint: self.conn_reset_hint,
+            registry: self.registry,
+            runtime: self.runtime,
+            #[cfg(feature = "rustls")]
+            tls: self.tls,
             _marker: PhantomData,
         }
     }
 
+    /// Enables TLS for the accepted connections.
+    ///
+    /// The handshakes are bounded by the
+    /// [`handshake_timeout`](volo::net::tls::TlsAcceptor::handshake_timeout) of the acceptor.
+    #[cfg(feature = "rustls")]
+    pub fn tls_config(mut self, tls: volo::net::tls::TlsAcceptor) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sets how long to wait for the in-flight requests to finish when shutting down.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Sets whether to tell the clients to close their connections by the `crrst` TTHeader when
+    /// shutting down, so that they move to the other instances before the connections are
+    /// closed.
+    ///
+    /// Defaults to true.
+    pub fn conn_reset_hint(mut self, enable: bool) -> Self {
+        self.conn_reset_hint = enable;
+        self
+    }
+
+    /// Registers the server to `registry` by `info` once it starts listening, and deregisters it
+    /// when shutting down, before waiting for the in-flight requests.
+    pub fn registry(mut self, registry: impl Registry, info: RegistryInfo) -> Self {
+        self.registry = Some((Arc::new(registry), info));
+        self
+    }
+
+    /// Sets the runtime to spawn the tasks of the connections and to sleep when shutting down.
+    ///
+    /// Defaults to the tokio runtime.
+    pub fn runtime(mut self, runtime: Runtime) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
     /// The main entry point for the server.
+    ///
+    /// The server shuts down gracefully on SIGINT, SIGHUP or SIGTERM.
     pub async fn run<A: volo::net::incoming::MakeIncoming, Resp>(
         self,
         incoming: A,
@@ -153,20 +248,55 @@ impl<S, L, Req, MkE, MkD> Server<S, L, Req, MkE, MkD> {
         <L::Service as Service<ServerContext, Req>>::Error: Into<BoxError> + Send,
         S: Service<ServerContext, Req, Response = Resp> + Clone + Send + 'static,
         S::Error: Into<BoxError>,
-        Req: EntryMessage + Send + 'static,
+        Req: EntryMessage + Size + Send + 'static,
         Resp: EntryMessage + Send + 'static + Size + Sync,
+    {
+        let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?;
+        let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+        let mut sigterm =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        let signal = async move {
+            tokio::select! {
+                _ = sigint.recv() => {}
+                _ = sighup.recv() => {}
+                _ = sigterm.recv() => {}
+            }
+        };
+        self.run_with_shutdown(incoming, signal).await
+    }
+
+    /// Runs the server until `signal` completes, and then shuts down gracefully.
+    ///
+    /// When shutting down, the server stops accepting new connections, and waits for the
+    /// in-flight requests to finish until the [`shutdown_timeout`](Self::shutdown_timeout).
+    pub async fn run_with_shutdown<A: volo::net::incoming::MakeIncoming, Resp, F>(
+        self,
+        incoming: A,
+        signal: F,
+    ) -> Result<(), BoxError>
+    where
+        L: Layer<S>,
+        MkE: MkEncoder,
+        MkD: MkDecoder,
+        L::Service: Service<ServerContext, Req, Response = Resp> + Clone + Send + 'static + Sync,
+        <L::Service as Service<ServerContext, Req>>::Error: Into<BoxError> + Send,
+        S: Service<ServerContext, Req, Response = Resp> + Clone + Send + 'static,
+        S::Error: Into<BoxError>,
+        Req: EntryMessage + Size + Send + 'static,
+        Resp: EntryMessage + Send + 'static + Size + Sync,
+        F: std::future::Future<Output = ()>,
     {
         // init server
         let service = self.layer.layer(self.service);
+        let (shutdown_timeout, conn_reset_hint) = (self.shutdown_timeout, self.conn_reset_hint);
+        let (registry, runtime) = (self.registry, self.runtime);
 
         let mut incoming = incoming.make_incoming().await?;
         info!("[VOLO] server start at: {:?}", incoming);
+        if let Some((registry, info)) = &registry {
+            registry.register(info).await?;
+        }
 
-        // graceful shutdown
-        let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?;
-        let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
-        let mut sigterm =
-            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
         let conn_cnt = Arc::new(std::sync::atomic::AtomicUsize::new(0));
         let gconn_cnt = conn_cnt.clone();
         let (exit_notify, exit_flag, exit_mark) = (
@@ -178,24 +308,52 @@ impl<S, L, Req, MkE, MkD> Server<S, L, Req, MkE, MkD> {
             (exit_notify.clone(), exit_flag.clone(), exit_mark.clone());
 
         // spawn accept loop
-        let handler = tokio::spawn(async move {
+        let conn_runtime = runtime.clone();
+        let (accept, stop_accept) = futures::future::abortable(async move {
             loop {
                 match incoming.try_next().await {
                     Ok(Some(conn)) => {
                         tracing::trace!("[VOLO] recv a connection from: {:?}", conn.info.peer_addr);
                         conn_cnt.fetch_add(1, Ordering::Relaxed);
                         let service = service.clone();
-
-                        tokio::spawn(handle_conn(
-                            conn,
-                            service,
-                            self.mk_encoder.clone(),
-                            self.mk_decoder.clone(),
+                        let mk_encoder = self.mk_encoder.clone();
+                        let mk_decoder = self.mk_decoder.clone();
+                        let (exit_notify, exit_flag, exit_mark, conn_cnt) = (
                             exit_notify_inner.clone(),
                             exit_flag_inner.clone(),
                             exit_mark_inner.clone(),
                             conn_cnt.clone(),
-                        ));
+                        );
+                        #[cfg(feature = "rustls")]
+                        let tls = self.tls.clone();
+
+                        conn_runtime.spawn(async move {
+                            // handshake in the connection task, so that a slow peer doesn't
+                            // block the accept loop
+                            #[cfg(feature = "rustls")]
+                            let conn = match tls {
+                                Some(tls) => match tls.accept(conn).await {
+                                    Ok(conn) => conn,
+                                    Err(e) => {
+                                        tracing::warn!("[VOLO] tls handshake error: {:?}", e);
+                                        conn_cnt.fetch_sub(1, Ordering::Relaxed);
+                                        return;
+                                    }
+                                },
+                                None => conn,
+                            };
+                            handle_conn(
+                                conn,
+                                service,
+                                mk_encoder,
+                                mk_decoder,
+                                exit_notify,
+                                exit_flag,
+                                exit_mark,
+                                conn_cnt,
+                            )
+                            .await
+                        });
                     }
                     // no more incoming connections
                     Ok(None) => break Ok(()),
@@ -203,48 +361,123 @@ impl<S, L, Req, MkE, MkD> Server<S, L, Req, MkE, MkD> {
                 }
             }
         });
+        let (accept_tx, mut accepted) = tokio::sync::oneshot::channel();
+        runtime.spawn(async move {
+            let _ = accept_tx.send(accept.await);
+        });
 
         // graceful shutdown handler
         tokio::select! {
-            _ = sigint.recv() => {}
-            _ = sighup.recv() => {}
-            _ = sigterm.recv() => {}
-            res = handler => {
+            _ = signal => {}
+            res = &mut accepted => {
                 match res {
-                    Ok(res) => {
-                        match res {
-                            Ok(()) => {}
-                            Err(e) => return Err(Box::new(e))
-                        };
-                    }
-                    Err(e) => return Err(Box::new(e)),
+                    Ok(Ok(Ok(()))) => {}
+                    Ok(Ok(Err(e))) => return Err(Box::new(e)),
+                    // the accept loop is dropped by the runtime
+                    _ => return Err("the accept loop of the server is gone".into()),
                 }
             }
         }
 
         // received signal, graceful shutdown now
         info!("[VOLO] received signal, gracefully exiting now");
+        // stop the new requests from the clients first
+        if let Some((registry, info)) = &registry {
+            if let Err(e) = registry.deregister(info).await {
+                tracing::warn!("[VOLO] failed to deregister the server: {}", e);
+            }
+        }
+        let deadline = tokio::time::Instant::now() + shutdown_timeout;
         *exit_flag.write() = true;
-        exit_mark.store(true, Ordering::Relaxed);
+        // stop accepting, the listener is closed when the accept loop is dropped
+        stop_accept.abort();
 
         // Now we won't accept new connections.
         // And we want to send crrst reply to the peers in the short future.
-        if gconn_cnt.load(Ordering::Relaxed) != 0 {
-            tokio::time::sleep(Duration::from_secs(2)).await;
+        if conn_reset_hint {
+            exit_mark.store(true, Ordering::Relaxed);
+            if gconn_cnt.load(Ordering::Relaxed) != 0 {
+                let now = tokio::time::Instant::now();
+                runtime
+                    .sleep(CONN_RESET_HINT_DURATION.min(deadline.saturating_duration_since(now)))
+                    .await;
+            }
         }
+        // close the idle connections, the in-flight requests are not interrupted
         exit_notify.notify_waiters();
 
         // wait for all connections to be closed
-        for _ in 0..28 {
-            if gconn_cnt.load(Ordering::Relaxed) == 0 {
+        while gconn_cnt.load(Ordering::Relaxed) != 0 {
+            if tokio::time::Instant::now() >= deadline {
+                tracing::warn!(
+                    "[VOLO] shutdown timeout, {} connections are not closed",
+                    gconn_cnt.load(Ordering::Relaxed)
+                );
                 break;
             }
-            tokio::time::sleep(Duration::from_secs(1)).await;
+            runtime.sleep(Duration::from_millis(100)).await;
         }
         Ok(())
     }
 }
 
+impl<S, L, Req, MkE, TT> Server<S, L, Req, MkE, MakeServerDecoder<TT>> {
+    /// Sets the max frame size of the requests, larger requests are rejected before being read
+    /// so that a corrupted or malicious length cannot make the server allocate a huge buffer.
+    ///
+    /// The unframed requests are limited too, they are rejected once they are read past it.
+    ///
+    /// Defaults to 16MB.
+    pub fn max_frame_size(mut self, max_frame_size: u32) -> Self {
+        self.mk_decoder.max_frame_size = max_frame_size as usize;
+        self
+    }
+
+    /// Sets how to decode the requests with fields unknown to the local IDL, see
+    /// [`unknown_fields`](crate::codec::unknown_fields).
+    ///
+    /// The strict mode rejects such requests, which is useful to find out the clients generated
+    /// from a newer IDL.
+    ///
+    /// Defaults to [`DecodeMode::Lenient`].
+    pub fn decode_mode(mut self, decode_mode: DecodeMode) -> Self {
+        self.mk_decoder.decode_mode = decode_mode;
+        self
+    }
+
+    /// Sets the min size of the responses compressed with zlib, which happens only when the
+    /// requests are compressed, so that the small responses are sent as they are.
+    ///
+    /// Defaults to [`DEFAULT_COMPRESSION_THRESHOLD`](crate::tags::DEFAULT_COMPRESSION_THRESHOLD).
+    pub fn compression_threshold(mut self, threshold: usize) -> Self {
+        self.mk_decoder.compression_threshold = threshold;
+        self
+    }
+
+    /// Sets whether to verify the CRC32C checksums of the request payloads, the requests of
+    /// mismatched checksums are rejected as protocol errors.
+    ///
+    /// The responses are checksummed when the requests carry checksums in either case.
+    ///
+    /// Defaults to true.
+    pub fn verify_checksum(mut self, enable: bool) -> Self {
+        self.mk_decoder.verify_checksum = enable;
+        self
+    }
+
+    /// Sets the protocols of the requests accepted, which are detected from each message, so a
+    /// single port can serve the clients of both the binary and the compact protocol. The
+    /// responses are encoded by the protocol of their requests.
+    ///
+    /// The compact protocol is only detected with the framed and the TTHeader codec types.
+    ///
+    /// Defaults to both [`Protocol::Binary`] and [`Protocol::Compact`].
+    pub fn protocols(mut self, protocols: impl IntoIterator<Item = Protocol>) -> Self {
+        self.mk_decoder.protocols = protocols.into_iter().collect();
+        self
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn handle_conn<Req, Svc, Resp, MkE, MkD>(
     conn: volo::net::conn::Conn,
@@ -261,13 +494,14 @@ async fn handle_conn<Req, Svc, Resp, MkE, MkD>(
     Svc: Service<ServerContext, Req, Response = Resp> + Clone + Send + 'static,
     Svc::Error: Send,
     Svc::Error: Into<BoxError>,
-    Req: EntryMessage + Send + 'static,
+    Req: EntryMessage + Size + Send + 'static,
     Resp: EntryMessage + Send + 'static + Size,
 {
     // get read lock and create Notified
     let notified = {
         let r = exit_flag.read();
         if *r {
+            conn_cnt.fetch_sub(1, Ordering::Relaxed);
             return;
         }
         exit_notify.notified()
@@ -280,6 +514,199 @@ async fn handle_conn<Req, Svc, Resp, MkE, MkD>(
     let framed = Framed::new(stream, encoder, decoder);
 
     tracing::trace!("[VOLO] handle conn by pingpong");
-    crate::transport::pingpong::serve(framed, notified, exit_mark, service).await;
+    crate::transport::pingpong::serve(framed, notified, exit_mark, service, conn.info).await;
     conn_cnt.fetch_sub(1, Ordering::Relaxed);
 }
+
+#[cfg(test)]
+mod tests {
+    use pilota::thrift::{ApplicationError, ApplicationErrorKind};
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{
+            tcp::{OwnedReadHalf, OwnedWriteHalf},
+            TcpStream,
+        },
+    };
+    use volo::{
+        context::{Role, RpcInfo},
+        net::incoming::Incoming,
+        util::buf_reader::BufReader,
+    };
+
+    use super::*;
+    use crate::{
+        codec::{CodecType, Decoder, Encoder, MakeClientDecoder, MakeClientEncoder},
+        context::ClientContext,
+        generic::Binary,
+        protocol::TMessageType,
+        ThriftMessage,
+    };
+
+    async fn handler(cx: &mut ServerContext, req: Binary) -> Result<Binary, crate::Error> {
+        if cx.req_msg_type == Some(TMessageType::OneWay) {
+            return Err(ApplicationError::new(ApplicationErrorKind::Unknown, "oneway").into());
+        }
+        match cx.rpc_info.method().map(|m| m.as_str()) {
+            Some("slow") => tokio::time::sleep(Duration::from_millis(300)).await,
+            Some("hang") => std::future::pending().await,
+            _ => {}
+        }
+        Ok(req)
+    }
+
+    async fn serve() -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::new(motore::service::service_fn(handler)).run(Incoming::from(listener)),
+        );
+        addr
+    }
+
+    async fn connect(addr: std::net::SocketAddr) -> (BufReader<OwnedReadHalf>, OwnedWriteHalf) {
+        let (read_half, write_half) = TcpStream::connect(addr).await.unwrap().into_split();
+        (BufReader::new(read_half), write_half)
+    }
+
+    /// Sends a framed request of the args, an empty struct is `[0]`.
+    async fn send(
+        conn: &mut OwnedWriteHalf,
+        seq_id: i32,
+        msg_type: TMessageType,
+        method: &'static str,
+        args: &'static [u8],
+    ) {
+        let mut ri = RpcInfo::with_role(Role::Client);
+        ri.method = Some(method.into());
+        let mut cx = ClientContext::new(seq_id, ri, msg_type);
+        let msg = ThriftMessage::mk_client_msg(&cx, Ok(Binary(args.into()))).unwrap();
+        let mut buf = Vec::new();
+        MakeClientEncoder {
+            tt_encoder: tt_header::DefaultTTHeaderCodec,
+        }
+        .mk_encoder(Some(CodecType::Framed))
+        .encode(&mut cx, &mut buf, msg)
+        .await
+        .unwrap();
+        conn.write_all(&buf).await.unwrap();
+    }
+
+    async fn recv(reader: &mut BufReader<OwnedReadHalf>) -> Option<ThriftMessage<Binary>> {
+        let mut cx = ClientContext::new(0, RpcInfo::with_role(Role::Client), TMessageType::Call);
+        MakeClientDecoder {
+            tt_decoder: tt_header::DefaultTTHeaderCodec,
+        }
+        .mk_decoder(None)
+        .decode(&mut cx, reader)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_oneway() {
+        let addr = serve().await;
+        let (mut reader, mut conn) = connect(addr).await;
+
+        // the failed oneway call is not replied
+        send(&mut conn, 1, TMessageType::OneWay, "echo", &[0]).await;
+        send(&mut conn, 2, TMessageType::Call, "echo", &[0]).await;
+        let resp = recv(&mut reader).await.unwrap();
+        assert_eq!(resp.meta.seq_id, 2);
+        resp.data.unwrap();
+
+        // nor the oneway call failed to decode, the connection is closed
+        send(&mut conn, 3, TMessageType::OneWay, "echo", &[0xee]).await;
+        assert!(recv(&mut reader).await.is_none());
+
+        // unlike the call, which is replied by the protocol error
+        let (mut reader, mut conn) = connect(addr).await;
+        send(&mut conn, 4, TMessageType::Call, "echo", &[0xee]).await;
+        let resp = recv(&mut reader).await.unwrap();
+        assert_eq!(resp.meta.msg_type, TMessageType::Exception);
+        assert!(matches!(
+            resp.data,
+            Err(crate::Error::Application(ref e)) if e.kind == ApplicationErrorKind::ProtocolError
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket() {
+        let path = std::env::temp_dir().join(format!("volo-thrift-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        tokio::spawn(
+            Server::new(motore::service::service_fn(handler)).run(Incoming::from(listener)),
+        );
+
+        let mut client = crate::generic::BinaryClientBuilder::new("echo")
+            .address(path.clone())
+            .build();
+        let resp = client.call("echo", Binary(vec![0].into())).await.unwrap();
+        assert_eq!(resp, Binary(vec![0].into()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Serves until `shutdown` is sent, the server is done once the returned handle is.
+    async fn serve_until(
+        shutdown_timeout: Duration,
+    ) -> (
+        std::net::SocketAddr,
+        tokio::sync::oneshot::Sender<()>,
+        tokio::task::JoinHandle<()>,
+    ) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, signal) = tokio::sync::oneshot::channel::<()>();
+        let server = Server::new(motore::service::service_fn(handler))
+            .shutdown_timeout(shutdown_timeout)
+            .conn_reset_hint(false)
+            .run_with_shutdown(Incoming::from(listener), async move {
+                let _ = signal.await;
+            });
+        let handle = tokio::spawn(async move { server.await.unwrap() });
+        (addr, shutdown, handle)
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drain() {
+        let (addr, shutdown, server) = serve_until(Duration::from_secs(10)).await;
+        let (mut idle, _idle_conn) = connect(addr).await;
+        let (mut reader, mut conn) = connect(addr).await;
+        send(&mut conn, 1, TMessageType::Call, "slow", &[0]).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let start = tokio::time::Instant::now();
+        shutdown.send(()).unwrap();
+        // the idle connections are closed
+        assert!(recv(&mut idle).await.is_none());
+        // and the in-flight requests are finished
+        let resp = recv(&mut reader).await.unwrap();
+        assert_eq!(resp.meta.seq_id, 1);
+        resp.data.unwrap();
+        // then the connection is closed too
+        assert!(recv(&mut reader).await.is_none());
+        server.await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // no more connections are accepted
+        TcpStream::connect(addr).await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_timeout() {
+        let (addr, shutdown, server) = serve_until(Duration::from_millis(200)).await;
+        let (_reader, mut conn) = connect(addr).await;
+        send(&mut conn, 1, TMessageType::Call, "hang", &[0]).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let start = tokio::time::Instant::now();
+        shutdown.send(()).unwrap();
+        // the hung requests don't block the shutdown
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
@@ -8,3 +8,36 @@ impl TransportType {
     pub const TRANSPORT_FRAMED: TransportType = TransportType("framed");
     pub const TRANSPORT_UNFRAMED: TransportType = TransportType("unframed");
 }
+
+/// The time when the server reads the request from the connection, used to measure the time the
+/// request waits before being handled.
+#[derive(Debug, Copy, Clone)]
+pub struct ReceivedAt(pub std::time::Instant);
+
+/// The default min size of the payloads to compress.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Compresses the TTHeader payloads with zlib if they are at least `threshold` bytes.
+///
+/// The client sets it in the extensions from the client builder, and the server sets it when
+/// the request is compressed, so that the response is compressed too.
+#[derive(Debug, Copy, Clone)]
+pub struct Compression {
+    pub threshold: usize,
+}
+
+/// Checksums the TTHeader payloads with CRC32C, so that the corrupted payloads are rejected
+/// instead of being decoded.
+///
+/// The client sets it in the extensions from the client config, and the server sets it when the
+/// request carries a checksum, so that the response is checksummed too.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PayloadChecksum;
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        }
+    }
+}
@@ -1,6 +1,7 @@
 pub(crate) mod incoming;
+pub mod multiplex;
 pub mod pingpong;
 pub mod pool;
-mod server;
+pub(crate) mod server;
 
 pub use pool::Config;
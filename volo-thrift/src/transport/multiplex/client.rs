@@ -0,0 +1,331 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::{stream::BoxStream, Future, StreamExt};
+use motore::service::{Service, UnaryService};
+use pilota::thrift::EntryMessage;
+use volo::net::{dial::MakeConnection, Address};
+
+use super::thrift_transport::{InFlight, ThriftTransport};
+use crate::{
+    codec::{CodecType, MkDecoder, MkEncoder},
+    context::ClientContext,
+    protocol::TMessageType,
+    transport::pingpong::{dial_target, with_connect_timeout, MakeTransport},
+    Error, Size, ThriftMessage,
+};
+
+type Transports<E, Resp> = HashMap<Address, Vec<Arc<ThriftTransport<E, Resp>>>>;
+
+/// A client that sends concurrent requests over a few shared connections to each endpoint.
+pub struct Client<Resp, MkE: MkEncoder + 'static, MkD: MkDecoder + 'static> {
+    make_transport: MakeTransport<MkE, MkD>,
+    #[allow(clippy::type_complexity)]
+    transports: Arc<Mutex<Transports<MkE::Target, Resp>>>,
+    connections: usize,
+    max_in_flight: Option<usize>,
+    health_check: Option<Duration>,
+    next: Arc<AtomicUsize>,
+    proxy: Option<Address>,
+}
+
+impl<Resp, MkE: MkEncoder + 'static, MkD: MkDecoder + 'static> Clone for Client<Resp, MkE, MkD> {
+    fn clone(&self) -> Self {
+        Self {
+            make_transport: self.make_transport.clone(),
+            transports: self.transports.clone(),
+            connections: self.connections,
+            max_in_flight: self.max_in_flight,
+            health_check: self.health_check,
+            next: self.next.clone(),
+            proxy: self.proxy.clone(),
+        }
+    }
+}
+
+impl<Resp, MkE: MkEncoder + 'static, MkD: MkDecoder + 'static> Client<Resp, MkE, MkD>
+where
+    Resp: EntryMessage + Size + Send + 'static,
+{
+    /// Creates a new [`Client`] which keeps `connections` connections to each endpoint.
+    pub fn new(
+        make_connection: MakeConnection,
+        codec_type: CodecType,
+        connections: usize,
+        mk_encoder: MkE,
+        mk_decoder: MkD,
+    ) -> Self {
+        Client {
+            make_transport: MakeTransport::new(make_connection, codec_type, mk_encoder, mk_decoder),
+            transports: Default::default(),
+            connections: connections.max(1),
+            max_in_flight: None,
+            health_check: None,
+            next: Default::default(),
+            proxy: None,
+        }
+    }
+
+    /// Sends all the requests to the mesh proxy instead of the callee.
+    pub fn with_proxy(mut self, proxy: Option<Address>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Limits the calls in flight on each connection, the calls beyond the limit of all the
+    /// connections to an endpoint open an additional connection.
+    pub fn with_max_in_flight(mut self, max: Option<usize>) -> Self {
+        self.max_in_flight = max.map(|max| max.max(1));
+        self
+    }
+
+    /// Closes the connections where the calls wait for `timeout` without any response arriving,
+    /// such as the half-open ones, failing their calls.
+    pub fn with_health_check(mut self, timeout: Option<Duration>) -> Self {
+        self.health_check = timeout;
+        self
+    }
+
+    /// Stops sending new calls over the connections to the instances removed by the service
+    /// discovery, the connections are closed once the calls in flight finish.
+    pub fn with_removed_instances(self, removed: Option<BoxStream<'static, Vec<Address>>>) -> Self {
+        if let Some(mut removed) = removed {
+            let transports = Arc::downgrade(&self.transports);
+            tokio::spawn(async move {
+                while let Some(addrs) = removed.next().await {
+                    let transports = match transports.upgrade() {
+                        Some(transports) => transports,
+                        None => return,
+                    };
+                    let mut transports = transports.lock().unwrap();
+                    for addr in addrs.iter() {
+                        let removed = transports.remove(addr);
+                        for transport in removed.into_iter().flatten() {
+                            transport.drain();
+                        }
+                    }
+                }
+            });
+        }
+        self
+    }
+
+    #[allow(clippy::type_complexity)]
+    /// Picks a connection in turn once there are enough of them, the first one under the limit
+    /// of the calls in flight if any.
+    #[allow(clippy::type_complexity)]
+    fn pick(
+        &self,
+        conns: &mut Vec<Arc<ThriftTransport<MkE::Target, Resp>>>,
+    ) -> Option<(Arc<ThriftTransport<MkE::Target, Resp>>, InFlight)> {
+        conns.retain(|t| !t.is_closed());
+        if conns.len() < self.connections {
+            return None;
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % conns.len();
+        match self.max_in_flight {
+            None => Some((conns[start].clone(), conns[start].reserve())),
+            Some(max) => (0..conns.len()).find_map(|i| {
+                let transport = &conns[(start + i) % conns.len()];
+                Some((transport.clone(), transport.try_reserve(max)?))
+            }),
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    async fn get(
+        &mut self,
+        target: Address,
+    ) -> Result<(Arc<ThriftTransport<MkE::Target, Resp>>, InFlight), Error> {
+        {
+            let mut transports = self.transports.lock().unwrap();
+            let conns = transports.entry(target.clone()).or_default();
+            if let Some(picked) = self.pick(conns) {
+                return Ok(picked);
+            }
+            if conns.len() >= self.connections {
+                tracing::debug!(
+                    "[VOLO] all the {} multiplexed connections to {} are full, opening a new one",
+                    conns.len(),
+                    target
+                );
+            }
+        }
+
+        let (read_half, write_half) = self.make_transport.call(target.clone()).await?.split();
+        let transport = Arc::new(ThriftTransport::new(
+            read_half,
+            write_half,
+            self.health_check,
+        ));
+        let mut transports = self.transports.lock().unwrap();
+        let conns = transports.entry(target).or_default();
+        // checked again with the push under the same lock, the connection opened by a concurrent
+        // call first is shared instead, and the new one is closed
+        if let Some(picked) = self.pick(conns) {
+            return Ok(picked);
+        }
+        let in_flight = transport.reserve();
+        conns.push(transport.clone());
+        Ok((transport, in_flight))
+    }
+}
+
+impl<Req, Resp, MkE: MkEncoder + 'static, MkD: MkDecoder + 'static>
+    Service<ClientContext, ThriftMessage<Req>> for Client<Resp, MkE, MkD>
+where
+    Req: Send + 'static + EntryMessage + Size,
+    Resp: EntryMessage + Size + Send + 'static,
+{
+    type Response = Option<ThriftMessage<Resp>>;
+
+    type Error = Error;
+
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + Send + 'cx where Self:'cx;
+
+    fn call<'cx, 's>(
+        &'s mut self,
+        cx: &'cx mut ClientContext,
+        req: ThriftMessage<Req>,
+    ) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let target = dial_target(cx, self.proxy.as_ref())?;
+            let oneway = cx.message_type == TMessageType::OneWay;
+            let timeout = cx.rpc_info.config().and_then(|c| c.connect_timeout());
+            let (transport, _in_flight) = with_connect_timeout(timeout, self.get(target)).await?;
+            transport.send(cx, req, oneway).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use volo::{
+        context::{Endpoint, Role, RpcInfo},
+        net::dial::connector,
+    };
+
+    use super::*;
+    use crate::{
+        codec::{tt_header::DefaultTTHeaderCodec, MakeClientDecoder, MakeClientEncoder},
+        generic::Binary,
+    };
+
+    type TestClient = Client<
+        Binary,
+        MakeClientEncoder<DefaultTTHeaderCodec>,
+        MakeClientDecoder<DefaultTTHeaderCodec>,
+    >;
+
+    fn client(connections: usize, peers: Arc<Mutex<Vec<DuplexStream>>>) -> TestClient {
+        let make_connection = MakeConnection::new(None).with_dialer(connector(move |_| {
+            let peers = peers.clone();
+            async move {
+                // let the concurrent calls dial at the same time
+                tokio::task::yield_now().await;
+                let (stream, peer) = duplex(4096);
+                peers.lock().unwrap().push(peer);
+                Ok(stream)
+            }
+        }));
+        Client::new(
+            make_connection,
+            CodecType::Framed,
+            connections,
+            MakeClientEncoder {
+                tt_encoder: DefaultTTHeaderCodec,
+            },
+            MakeClientDecoder {
+                tt_decoder: DefaultTTHeaderCodec,
+            },
+        )
+    }
+
+    fn call(seq_id: i32) -> (ClientContext, ThriftMessage<Binary>) {
+        let mut cx =
+            ClientContext::new(seq_id, RpcInfo::with_role(Role::Client), TMessageType::Call);
+        cx.rpc_info.method = Some("echo".into());
+        let mut callee = Endpoint::new("echo".into());
+        callee.set_address(Address::Ip(([127, 0, 0, 1], 8080).into()));
+        cx.rpc_info.callee = Some(callee);
+        // an empty struct
+        let msg = ThriftMessage::mk_client_msg(&cx, Ok(Binary(Bytes::from_static(&[0])))).unwrap();
+        (cx, msg)
+    }
+
+    /// Reads a framed binary call, returning its seq_id.
+    async fn read_call(peer: &mut DuplexStream) -> i32 {
+        let len = peer.read_u32().await.unwrap();
+        let mut frame = vec![0; len as usize];
+        peer.read_exact(&mut frame).await.unwrap();
+        let name_len = i32::from_be_bytes(frame[4..8].try_into().unwrap()) as usize;
+        i32::from_be_bytes(frame[8 + name_len..12 + name_len].try_into().unwrap())
+    }
+
+    /// Writes a framed binary reply of an empty struct.
+    async fn write_reply(peer: &mut DuplexStream, seq_id: i32) {
+        let mut frame = vec![0x80, 0x01, 0x00, 0x02];
+        frame.extend_from_slice(&4i32.to_be_bytes());
+        frame.extend_from_slice(b"echo");
+        frame.extend_from_slice(&seq_id.to_be_bytes());
+        frame.push(0);
+        peer.write_u32(frame.len() as u32).await.unwrap();
+        peer.write_all(&frame).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_demux() {
+        let peers = Arc::new(Mutex::new(Vec::new()));
+        let mut client = client(1, peers.clone());
+        let target = Address::Ip(([127, 0, 0, 1], 8080).into());
+        client.get(target).await.unwrap();
+        let mut peer = peers.lock().unwrap().pop().unwrap();
+
+        // both calls are on the only connection, answered in the reverse order
+        let (mut cx1, msg1) = call(1);
+        let (mut cx2, msg2) = call(2);
+        let mut client2 = client.clone();
+        let first = tokio::spawn(async move { client.call(&mut cx1, msg1).await });
+        let second = tokio::spawn(async move { client2.call(&mut cx2, msg2).await });
+        let mut seq_ids = [read_call(&mut peer).await, read_call(&mut peer).await];
+        seq_ids.sort_unstable();
+        assert_eq!(seq_ids, [1, 2]);
+        write_reply(&mut peer, 2).await;
+        write_reply(&mut peer, 1).await;
+
+        let first = first.await.unwrap().unwrap().unwrap();
+        assert_eq!(first.meta.seq_id, 1);
+        let second = second.await.unwrap().unwrap().unwrap();
+        assert_eq!(second.meta.seq_id, 2);
+        assert!(peers.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connections() {
+        let peers = Arc::new(Mutex::new(Vec::new()));
+        let client = client(2, peers.clone());
+        let target = Address::Ip(([127, 0, 0, 1], 8080).into());
+
+        let gets = (0..8).map(|_| {
+            let mut client = client.clone();
+            let target = target.clone();
+            tokio::spawn(async move { client.get(target).await.map(|(t, _)| t) })
+        });
+        for get in futures::future::join_all(gets).await {
+            get.unwrap().unwrap();
+        }
+        // the connections dialed by the concurrent calls beyond the limit are closed
+        assert_eq!(client.transports.lock().unwrap()[&target].len(), 2);
+    }
+}
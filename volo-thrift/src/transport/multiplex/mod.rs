@@ -0,0 +1,23 @@
+//! Connection multiplexing, where concurrent requests share one connection and the responses
+//! are matched by seq_id, as the connection multiplexing of Kitex.
+//!
+//! This greatly reduces the connections of clients calling many endpoints with high
+//! concurrency. The server must handle the requests of a connection concurrently to benefit from
+//! it, a server that responds in turn works but the requests will queue up.
+//!
+//! The requests in flight on each connection can be limited, the requests beyond the limit of all
+//! the connections to an endpoint then spill over to an additional connection, so that the flow
+//! control of one connection doesn't serialize a high QPS caller.
+//!
+//! The requests are batched, those encoded while the connection is busy writing are written
+//! together, which saves the syscalls under high concurrency. A connection can be health checked,
+//! where it's closed if the calls wait too long without any response arriving, so the half-open
+//! connections that never fail by themselves are replaced.
+//!
+//! A framed codec such as `TTHeaderFramed` is recommended.
+
+mod client;
+mod thrift_transport;
+
+pub use client::Client;
+pub use thrift_transport::{InFlight, ThriftTransport};
@@ -0,0 +1,441 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use metainfo::{Backward, MetaInfo, METAINFO};
+use pilota::thrift::EntryMessage;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{oneshot, Notify},
+    task::JoinHandle,
+};
+use volo::{
+    context::{Role, RpcInfo},
+    net::conn::OwnedWriteHalf,
+};
+
+use crate::{
+    codec::{Decoder, Encoder, DEFAULT_BUFFER_SIZE},
+    context::ClientContext,
+    protocol::TMessageType,
+    transport::pool::{ReadHalf, WriteHalf},
+    Error, Size, ThriftMessage, TransportErrorKind,
+};
+
+type Reply<Resp> = Result<(ThriftMessage<Resp>, MetaInfo), Error>;
+
+struct Calls<Resp> {
+    // no more calls can be sent once closed
+    closed: bool,
+    waiters: HashMap<i32, oneshot::Sender<Reply<Resp>>>,
+    // the last time a response arrived, or a call started waiting on an idle connection
+    last_progress: Instant,
+}
+
+struct Shared<Resp> {
+    calls: Mutex<Calls<Resp>>,
+}
+
+impl<Resp> Shared<Resp> {
+    fn register(&self, seq_id: i32) -> Result<oneshot::Receiver<Reply<Resp>>, Error> {
+        let mut calls = self.calls.lock().unwrap();
+        if calls.closed {
+            return Err(crate::error::new_transport_error(
+                TransportErrorKind::NotOpen,
+                "the multiplexed connection is closed",
+            ));
+        }
+        if calls.waiters.is_empty() {
+            calls.last_progress = Instant::now();
+        }
+        let (tx, rx) = oneshot::channel();
+        calls.waiters.insert(seq_id, tx);
+        Ok(rx)
+    }
+
+    fn complete(&self, seq_id: i32, reply: Reply<Resp>) -> bool {
+        let waiter = {
+            let mut calls = self.calls.lock().unwrap();
+            calls.last_progress = Instant::now();
+            calls.waiters.remove(&seq_id)
+        };
+        match waiter {
+            Some(tx) => {
+                let _ = tx.send(reply);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn cancel(&self, seq_id: i32) {
+        self.calls.lock().unwrap().waiters.remove(&seq_id);
+    }
+
+    /// Stops accepting new calls, the calls in flight can still be completed.
+    fn drain(&self) {
+        self.calls.lock().unwrap().closed = true;
+    }
+
+    /// Stops accepting new calls and fails the calls in flight.
+    fn close(&self) {
+        let waiters = {
+            let mut calls = self.calls.lock().unwrap();
+            calls.closed = true;
+            std::mem::take(&mut calls.waiters)
+        };
+        for (_, tx) in waiters {
+            let _ = tx.send(Err(crate::error::new_transport_error(
+                TransportErrorKind::EndOfFile,
+                "the multiplexed connection is closed",
+            )));
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.calls.lock().unwrap().closed
+    }
+
+    /// Whether the calls are waiting but no response has arrived for `timeout`.
+    fn is_stalled(&self, timeout: Duration) -> bool {
+        let calls = self.calls.lock().unwrap();
+        !calls.waiters.is_empty() && calls.last_progress.elapsed() >= timeout
+    }
+
+    /// Whether no call is sent or waiting any more.
+    fn is_done(&self) -> bool {
+        let calls = self.calls.lock().unwrap();
+        calls.closed && calls.waiters.is_empty()
+    }
+}
+
+/// Removes the waiter if the call is dropped before the response arrives.
+struct CallGuard<'a, Resp> {
+    shared: &'a Shared<Resp>,
+    seq_id: i32,
+}
+
+impl<Resp> Drop for CallGuard<'_, Resp> {
+    fn drop(&mut self) {
+        self.shared.cancel(self.seq_id);
+    }
+}
+
+/// Closes the connection if the encoding is cancelled halfway, since the batch may be corrupted.
+struct WriteGuard<'a, Resp> {
+    shared: &'a Shared<Resp>,
+    finished: bool,
+}
+
+impl<Resp> Drop for WriteGuard<'_, Resp> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.shared.close();
+        }
+    }
+}
+
+/// A call counted in flight on a [`ThriftTransport`] until dropped.
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The requests encoded but not written yet.
+struct Batch<E> {
+    encoder: E,
+    buf: Vec<u8>,
+}
+
+/// A connection shared by concurrent calls.
+///
+/// Requests are encoded in turn into a batch, which is written by a background task, so the
+/// requests encoded while the last batch is being written go out in one write. The responses are
+/// read by another task which hands them to the calls by seq_id, so they can come back in any
+/// order.
+pub struct ThriftTransport<E, Resp> {
+    id: usize,
+    batch: Arc<tokio::sync::Mutex<Batch<E>>>,
+    batched: Arc<Notify>,
+    shared: Arc<Shared<Resp>>,
+    in_flight: Arc<AtomicUsize>,
+    reader: JoinHandle<()>,
+    writer: JoinHandle<()>,
+    health_check: Option<JoinHandle<()>>,
+}
+
+impl<E, Resp> ThriftTransport<E, Resp>
+where
+    E: Encoder + Send + 'static,
+    Resp: EntryMessage + Size + Send + 'static,
+{
+    /// Creates a new [`ThriftTransport`], which is closed if the calls wait for `health_check`
+    /// without any response arriving.
+    pub fn new<D>(
+        read_half: ReadHalf<D>,
+        write_half: WriteHalf<E>,
+        health_check: Option<Duration>,
+    ) -> Self
+    where
+        D: Decoder + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            calls: Mutex::new(Calls {
+                closed: false,
+                waiters: HashMap::new(),
+                last_progress: Instant::now(),
+            }),
+        });
+        let id = read_half.id();
+        let (write_half, encoder) = write_half.into_parts();
+        let batch = Arc::new(tokio::sync::Mutex::new(Batch {
+            encoder,
+            buf: Vec::with_capacity(DEFAULT_BUFFER_SIZE),
+        }));
+        let batched = Arc::new(Notify::new());
+        let reader = tokio::spawn(read_loop(read_half, shared.clone()));
+        let writer = tokio::spawn(write_loop(
+            id,
+            write_half,
+            batch.clone(),
+            batched.clone(),
+            shared.clone(),
+        ));
+        let health_check =
+            health_check.map(|timeout| tokio::spawn(check_health(id, shared.clone(), timeout)));
+        Self {
+            id,
+            batch,
+            batched,
+            shared,
+            in_flight: Default::default(),
+            reader,
+            writer,
+            health_check,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+
+    /// Returns the number of the calls in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Counts a call in flight.
+    pub fn reserve(&self) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::AcqRel);
+        InFlight(self.in_flight.clone())
+    }
+
+    /// Counts a call in flight if there are fewer than `max` ones.
+    pub fn try_reserve(&self, max: usize) -> Option<InFlight> {
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| InFlight(self.in_flight.clone()))
+    }
+
+    /// Stops accepting new calls, the connection is closed once the calls in flight finish and
+    /// the transport is dropped.
+    pub fn drain(&self) {
+        self.shared.drain();
+    }
+
+    pub async fn send<Req: EntryMessage + Size>(
+        &self,
+        cx: &mut ClientContext,
+        msg: ThriftMessage<Req>,
+        oneway: bool,
+    ) -> Result<Option<ThriftMessage<Resp>>, Error> {
+        let seq_id = cx.seq_id;
+        // register before writing, the response may arrive before the write returns
+        let rx = if oneway {
+            None
+        } else {
+            Some(self.shared.register(seq_id)?)
+        };
+        let _call = rx.as_ref().map(|_| CallGuard {
+            shared: &self.shared,
+            seq_id,
+        });
+
+        {
+            let mut batch = self.batch.lock().await;
+            let Batch { encoder, buf } = &mut *batch;
+            let len = buf.len();
+            let mut guard = WriteGuard {
+                shared: &self.shared,
+                finished: false,
+            };
+            let resp = encoder.encode(cx, buf, msg).await;
+            guard.finished = true;
+            if let Err(e) = resp {
+                tracing::error!("[VOLO] transport[{}] encode error: {:?}", self.id, e);
+                // drop the partial message, the calls in the batch are still written
+                buf.truncate(len);
+                self.shared.close();
+                return Err(e);
+            }
+        }
+        self.batched.notify_one();
+
+        let rx = match rx {
+            Some(rx) => rx,
+            None => return Ok(None),
+        };
+        let (msg, metainfo) = rx.await.map_err(|_| {
+            crate::error::new_transport_error(
+                TransportErrorKind::EndOfFile,
+                "the multiplexed connection is closed",
+            )
+        })??;
+
+        // the backward metainfo was decoded in the reader task, hand it to the caller
+        if let Some(backward) = metainfo.get_all_backward_downstreams() {
+            let _ = METAINFO.try_with(|mi| {
+                let mut mi = mi.borrow_mut();
+                for (k, v) in backward {
+                    mi.set_backward_downstream(k.clone(), v.clone());
+                }
+            });
+        }
+        Ok(Some(msg))
+    }
+}
+
+impl<E, Resp> Drop for ThriftTransport<E, Resp> {
+    fn drop(&mut self) {
+        self.reader.abort();
+        self.writer.abort();
+        if let Some(health_check) = &self.health_check {
+            health_check.abort();
+        }
+    }
+}
+
+async fn write_loop<E, Resp>(
+    id: usize,
+    mut write_half: OwnedWriteHalf,
+    batch: Arc<tokio::sync::Mutex<Batch<E>>>,
+    batched: Arc<Notify>,
+    shared: Arc<Shared<Resp>>,
+) {
+    let mut spare = Vec::with_capacity(DEFAULT_BUFFER_SIZE);
+    loop {
+        batched.notified().await;
+        let mut buf = std::mem::replace(&mut batch.lock().await.buf, spare);
+        if !buf.is_empty() {
+            let resp = match write_half.write_all(&buf).await {
+                Ok(()) => write_half.flush().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = resp {
+                tracing::warn!("[VOLO] transport[{}] write error: {}", id, e);
+                shared.close();
+                return;
+            }
+            buf.clear();
+        }
+        spare = buf;
+    }
+}
+
+async fn check_health<Resp>(id: usize, shared: Arc<Shared<Resp>>, timeout: Duration) {
+    let mut interval = tokio::time::interval(timeout);
+    loop {
+        interval.tick().await;
+        if shared.is_done() {
+            return;
+        }
+        if shared.is_stalled(timeout) {
+            tracing::warn!(
+                "[VOLO] transport[{}] no response arrived for {:?}, closing the multiplexed \
+                 connection",
+                id,
+                timeout
+            );
+            shared.close();
+            return;
+        }
+    }
+}
+
+async fn read_loop<D, Resp>(mut read_half: ReadHalf<D>, shared: Arc<Shared<Resp>>)
+where
+    D: Decoder,
+    Resp: EntryMessage + Size + Send,
+{
+    loop {
+        let mut cx = ClientContext::new(0, RpcInfo::with_role(Role::Client), TMessageType::Reply);
+        let (resp, metainfo) = METAINFO
+            .scope(RefCell::new(MetaInfo::default()), async {
+                let resp = read_half.decode::<Resp>(&mut cx).await;
+                (resp, METAINFO.with(|mi| mi.take()))
+            })
+            .await;
+        match resp {
+            Ok(Some(msg)) => {
+                let seq_id = msg.meta.seq_id;
+                if !shared.complete(seq_id, Ok((msg, metainfo))) {
+                    tracing::warn!(
+                        "[VOLO] transport[{}] no call is waiting for seq_id: {}",
+                        read_half.id(),
+                        seq_id,
+                    );
+                }
+                // the server is going to close the connection, finish the calls in flight
+                if !cx.transport.should_reuse {
+                    shared.drain();
+                }
+            }
+            Ok(None) => break,
+            Err(_) => break,
+        }
+    }
+    shared.close();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health() {
+        let shared = Shared::<()> {
+            calls: Mutex::new(Calls {
+                closed: false,
+                waiters: HashMap::new(),
+                last_progress: Instant::now(),
+            }),
+        };
+        // an idle connection is never stalled
+        assert!(!shared.is_stalled(Duration::ZERO));
+
+        let _rx = shared.register(1).unwrap();
+        assert!(shared.is_stalled(Duration::ZERO));
+        assert!(!shared.is_stalled(Duration::from_secs(60)));
+
+        shared.drain();
+        assert!(!shared.is_done());
+        let reply = Err(crate::error::new_transport_error(
+            TransportErrorKind::EndOfFile,
+            "closed",
+        ));
+        assert!(shared.complete(1, reply));
+        assert!(shared.is_done());
+    }
+}
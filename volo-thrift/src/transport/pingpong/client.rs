@@ -1,6 +1,12 @@
-use std::{io, marker::PhantomData};
+use std::{
+    collections::HashMap,
+    io,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use futures::Future;
+use futures::{stream::BoxStream, Future};
 use motore::service::{Service, UnaryService};
 use pilota::thrift::EntryMessage;
 use volo::{
@@ -16,10 +22,86 @@ use crate::{
     Error, Size, ThriftMessage,
 };
 
+/// The codec types to try for each endpoint, in order.
+///
+/// Thrift has no handshake, so a legacy server which doesn't understand the codec type is only
+/// found by the failure of the first call, which is then sent again with the next codec type.
+/// Once a call succeeds, the codec type is kept for the endpoint.
+struct Negotiation {
+    codec_types: Vec<CodecType>,
+    endpoints: Mutex<HashMap<Address, Endpoint>>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Endpoint {
+    // the index of the codec type in use
+    index: usize,
+    // whether a call has succeeded with the codec type
+    confirmed: bool,
+}
+
+impl Negotiation {
+    fn new(codec_types: Vec<CodecType>) -> Self {
+        assert!(
+            !codec_types.is_empty(),
+            "at least one codec type is required"
+        );
+        Self {
+            codec_types,
+            endpoints: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn endpoint(&self, target: &Address) -> Endpoint {
+        if self.codec_types.len() == 1 {
+            return Endpoint::default();
+        }
+        self.endpoints
+            .lock()
+            .unwrap()
+            .get(target)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn codec_type(&self, target: &Address) -> CodecType {
+        self.codec_types[self.endpoint(target).index]
+    }
+
+    /// Returns whether the failed calls to the endpoint may be sent again with another codec type.
+    fn pending(&self, endpoint: Endpoint) -> bool {
+        !endpoint.confirmed && endpoint.index + 1 < self.codec_types.len()
+    }
+
+    fn confirm(&self, target: &Address) {
+        self.endpoints
+            .lock()
+            .unwrap()
+            .entry(target.clone())
+            .or_default()
+            .confirmed = true;
+    }
+
+    /// Falls back from the codec type of `index` to the next one, returns false if the codec type
+    /// has been confirmed or there is no more codec types.
+    fn fall_back(&self, target: &Address, index: usize) -> bool {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let endpoint = endpoints.entry(target.clone()).or_default();
+        if !self.pending(*endpoint) {
+            return false;
+        }
+        // the concurrent calls may have fallen back already
+        if endpoint.index == index {
+            endpoint.index += 1;
+        }
+        true
+    }
+}
+
 #[derive(Clone)]
 pub struct MakeTransport<MkE, MkD> {
     make_connection: MakeConnection,
-    codec_type: CodecType,
+    negotiation: Arc<Negotiation>,
     mk_encoder: MkE,
     mk_decoder: MkD,
 }
@@ -34,7 +116,7 @@ impl<E, D> MakeTransport<E, D> {
     ) -> Self {
         Self {
             make_connection,
-            codec_type,
+            negotiation: Arc::new(Negotiation::new(vec![codec_type])),
             mk_decoder,
             mk_encoder,
         }
@@ -49,17 +131,53 @@ impl<E: MkEncoder + 'static, D: MkDecoder + 'static> UnaryService<Address> for M
     fn call(&mut self, target: Address) -> Self::Future<'_> {
         let make_connection = self.make_connection.clone();
         async move {
+            let codec_type = self.negotiation.codec_type(&target);
             let conn = make_connection.make_connection(target).await?;
-            let decoder = self.mk_decoder.mk_decoder(Some(self.codec_type));
-            let encoder = self.mk_encoder.mk_encoder(Some(self.codec_type));
+            let decoder = self.mk_decoder.mk_decoder(Some(codec_type));
+            let encoder = self.mk_encoder.mk_encoder(Some(codec_type));
             Ok(ThriftTransport::new(conn, encoder, decoder))
         }
     }
 }
 
+/// Returns the address to dial, which is the mesh proxy if set, or the callee otherwise.
+pub(crate) fn dial_target(cx: &ClientContext, proxy: Option<&Address>) -> Result<Address, Error> {
+    if let Some(proxy) = proxy {
+        return Ok(proxy.clone());
+    }
+    Ok(cx
+        .rpc_info
+        .callee()
+        .volo_unwrap()
+        .address()
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "address is required")
+        })?)
+}
+
+/// Bounds getting a connection by the connect timeout of the call, which may be overridden by
+/// the `CallOpt`.
+pub(crate) async fn with_connect_timeout<T, E, F>(
+    timeout: Option<Duration>,
+    fut: F,
+) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<Error>,
+{
+    match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, fut).await {
+            Ok(resp) => resp.map_err(Into::into),
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "connect timeout").into()),
+        },
+        None => fut.await.map_err(Into::into),
+    }
+}
+
 pub struct Client<Resp, MkE: MkEncoder + 'static, MkD: MkDecoder + 'static> {
     #[allow(clippy::type_complexity)]
     make_transport: PooledMakeTransport<MakeTransport<MkE, MkD>, Address>,
+    proxy: Option<Address>,
     _maker: PhantomData<Resp>,
 }
 
@@ -67,6 +185,7 @@ impl<Resp, MkE: MkEncoder + 'static, MkD: MkDecoder + 'static> Clone for Client<
     fn clone(&self) -> Self {
         Self {
             make_transport: self.make_transport.clone(),
+            proxy: self.proxy.clone(),
             _maker: self._maker,
         }
     }
@@ -85,16 +204,81 @@ impl<Resp, MkE: MkEncoder + 'static, MkD: MkDecoder + 'static> Client<Resp, MkE,
         let make_transport = PooledMakeTransport::new(make_transport, pool_cfg);
         Client {
             make_transport,
+            proxy: None,
             _maker: PhantomData,
         }
     }
+
+    /// Sends all the requests to the mesh proxy instead of the callee.
+    pub fn with_proxy(mut self, proxy: Option<Address>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Falls back to the `fallbacks` codec types in order, when the calls to an endpoint fail with
+    /// the codec type before, so that the legacy servers can be called as well.
+    ///
+    /// A legacy server closes the connection or responds with garbage mostly, the ones waiting for
+    /// more data are only found by the read write timeout.
+    pub fn with_codec_fallbacks(mut self, fallbacks: Vec<CodecType>) -> Self {
+        if fallbacks.is_empty() {
+            return self;
+        }
+        let mut codec_types = self.make_transport.inner.negotiation.codec_types.clone();
+        codec_types.extend(fallbacks);
+        self.make_transport.inner.negotiation = Arc::new(Negotiation::new(codec_types));
+        self
+    }
+
+    /// Makes `connections` idle connections to each of the targets in the background.
+    pub fn with_warm_up(self, targets: Vec<Address>, connections: usize) -> Self {
+        self.make_transport.warm_up(targets, connections);
+        self
+    }
+
+    /// Closes the pooled connections to the instances removed by the service discovery.
+    pub fn with_removed_instances(self, removed: Option<BoxStream<'static, Vec<Address>>>) -> Self {
+        if let Some(removed) = removed {
+            self.make_transport.pool.evict_from(removed);
+        }
+        self
+    }
+}
+
+impl<Resp, MkE: MkEncoder + 'static, MkD: MkDecoder + 'static> Client<Resp, MkE, MkD>
+where
+    Resp: EntryMessage + Size,
+{
+    async fn send<Req: Send + 'static + EntryMessage + Size>(
+        &mut self,
+        cx: &mut ClientContext,
+        target: Address,
+        req: ThriftMessage<Req>,
+        oneway: bool,
+    ) -> Result<Option<ThriftMessage<Resp>>, Error> {
+        let timeout = cx.rpc_info.config().and_then(|c| c.connect_timeout());
+        let mut transport = with_connect_timeout(timeout, self.make_transport.call(target)).await?;
+        let resp = transport.send(cx, req, oneway).await;
+        if let Ok(None) = resp {
+            if !oneway {
+                return Err(crate::error::new_transport_error(
+                    crate::TransportErrorKind::EndOfFile,
+                    "an unexpected end of file from server",
+                ));
+            }
+        }
+        if cx.transport.should_reuse && resp.is_ok() {
+            transport.reuse();
+        }
+        resp
+    }
 }
 
 impl<Req, Resp, MkE: MkEncoder + 'static, MkD: MkDecoder + 'static>
     Service<ClientContext, ThriftMessage<Req>> for Client<Resp, MkE, MkD>
 where
-    Req: Send + 'static + EntryMessage + Size,
-    Resp: EntryMessage,
+    Req: Send + 'static + EntryMessage + Size + Clone,
+    Resp: EntryMessage + Size,
 {
     type Response = Option<ThriftMessage<Resp>>;
 
@@ -111,25 +295,101 @@ where
         's: 'cx,
     {
         async move {
-            let rpc_info = &cx.rpc_info;
-            let target = rpc_info.callee().volo_unwrap().address().ok_or_else(|| {
-                std::io::Error::new(std::io::ErrorKind::InvalidData, "address is required")
-            })?;
+            let target = dial_target(cx, self.proxy.as_ref())?;
             let oneway = cx.message_type == TMessageType::OneWay;
-            let mut transport = self.make_transport.call(target).await?;
-            let resp = transport.send(cx, req, oneway).await;
-            if let Ok(None) = resp {
-                if !oneway {
-                    return Err(crate::error::new_transport_error(
-                        crate::TransportErrorKind::EndOfFile,
-                        "an unexpected end of file from server",
-                    ));
-                }
-            }
-            if cx.transport.should_reuse && resp.is_ok() {
-                transport.reuse();
+            let negotiation = self.make_transport.inner.negotiation.clone();
+            let mut req = req;
+            loop {
+                let endpoint = negotiation.endpoint(&target);
+                // the oneway calls can't tell whether the server understands them
+                let retry = if !oneway && negotiation.pending(endpoint) {
+                    req.try_clone()
+                } else {
+                    None
+                };
+                let resp = self.send(cx, target.clone(), req, oneway).await;
+                let retry = match (resp, retry) {
+                    (resp @ Ok(_), Some(_)) => {
+                        negotiation.confirm(&target);
+                        return resp;
+                    }
+                    (Err(Error::Transport(_) | Error::Protocol(_)), Some(retry))
+                        if negotiation.fall_back(&target, endpoint.index) =>
+                    {
+                        retry
+                    }
+                    (resp, _) => return resp,
+                };
+                tracing::warn!(
+                    "[VOLO] call to {} failed with codec type {:?}, falling back to {:?}",
+                    target,
+                    negotiation.codec_types[endpoint.index],
+                    negotiation.codec_type(&target),
+                );
+                // the pooled connections are of the old codec type
+                self.make_transport.pool.evict(&target);
+                cx.transport.should_reuse = true;
+                req = retry;
             }
-            resp
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use volo::context::{Role, RpcInfo};
+
+    use super::*;
+
+    #[test]
+    fn test_negotiation() {
+        let negotiation = Negotiation::new(vec![
+            CodecType::TTHeaderFramed,
+            CodecType::Framed,
+            CodecType::Buffered,
+        ]);
+        let legacy = Address::Ip("127.0.0.1:8080".parse().unwrap());
+        let other = Address::Ip("127.0.0.1:8081".parse().unwrap());
+
+        assert!(negotiation.fall_back(&legacy, 0));
+        // a concurrent call failed with the first codec type doesn't skip the second one
+        assert!(negotiation.fall_back(&legacy, 0));
+        assert!(matches!(negotiation.codec_type(&legacy), CodecType::Framed));
+        assert!(matches!(
+            negotiation.codec_type(&other),
+            CodecType::TTHeaderFramed
+        ));
+
+        negotiation.confirm(&legacy);
+        assert!(!negotiation.fall_back(&legacy, 1));
+        assert!(!negotiation.pending(negotiation.endpoint(&legacy)));
+        assert!(matches!(negotiation.codec_type(&legacy), CodecType::Framed));
+    }
+
+    fn with_callee(address: Option<Address>) -> ClientContext {
+        let mut callee = volo::context::Endpoint::new("item".into());
+        if let Some(address) = address {
+            callee.set_address(address);
+        }
+        let mut cx = ClientContext::new(0, RpcInfo::with_role(Role::Client), TMessageType::Call);
+        cx.rpc_info.callee = Some(callee);
+        cx
+    }
+
+    #[test]
+    fn test_dial_target() {
+        let callee = Address::Ip("10.0.0.1:8888".parse().unwrap());
+        let proxy = Address::Ip("127.0.0.1:15001".parse().unwrap());
+
+        let cx = with_callee(Some(callee.clone()));
+        assert_eq!(dial_target(&cx, None).unwrap(), callee);
+        // the proxy is dialed instead, the callee address is kept for the header
+        assert_eq!(dial_target(&cx, Some(&proxy)).unwrap(), proxy);
+        assert_eq!(cx.rpc_info.callee().unwrap().address(), Some(callee));
+
+        // the callee address is only required without the proxy
+        let cx = with_callee(None);
+        assert_eq!(dial_target(&cx, Some(&proxy)).unwrap(), proxy);
+        assert!(matches!(dial_target(&cx, None), Err(Error::Transport(_))));
+    }
+}
@@ -2,4 +2,5 @@ mod client;
 mod server;
 
 pub use client::Client;
+pub(crate) use client::{dial_target, with_connect_timeout, MakeTransport};
 pub use server::serve;
@@ -9,7 +9,7 @@ use motore::{service::Service, BoxError};
 use pilota::thrift::EntryMessage;
 use tokio::sync::futures::Notified;
 use tracing::*;
-use volo::volo_unreachable;
+use volo::{context::Context, net::conn::ConnInfo, volo_unreachable};
 
 use crate::{
     codec::{framed::Framed, Decoder, Encoder},
@@ -23,21 +23,32 @@ pub async fn serve<Svc, Req, Resp, E, D>(
     notified: Notified<'_>,
     exit_mark: Arc<std::sync::atomic::AtomicBool>,
     mut service: Svc,
+    conn_info: ConnInfo,
 ) where
     Svc: Service<ServerContext, Req, Response = Resp>,
     Svc::Error: Into<BoxError>,
-    Req: EntryMessage,
+    Req: EntryMessage + Size,
     Resp: EntryMessage + Size,
     E: Encoder,
     D: Decoder,
 {
     tokio::pin!(notified);
+    // the verified identity of the client by mTLS, for every request of the connection
+    let peer_identity = conn_info
+        .tls
+        .as_ref()
+        .and_then(|tls| tls.peer_identity.clone());
 
     metainfo::METAINFO
         .scope(RefCell::new(MetaInfo::default()), async {
             loop {
                 // new context
                 let mut cx = ServerContext::default();
+                // the addresses and the tls parameters, for the layers and the handler
+                cx.extensions_mut().insert(conn_info.clone());
+                if let Some(identity) = &peer_identity {
+                    cx.extensions_mut().insert(identity.clone());
+                }
 
                 let msg = tokio::select! {
                     _ = &mut notified => {
@@ -54,8 +65,22 @@ pub async fn serve<Svc, Req, Resp, E, D>(
 
                 match msg {
                     Ok(Some(ThriftMessage { data: Ok(req), .. })) => {
-                        let resp =
-                            crate::transport::server::handle(&mut cx, &mut service, req).await;
+                        // the peer of a oneway call may close the connection once it's sent
+                        let oneway = cx.req_msg_type == Some(TMessageType::OneWay);
+                        let resp = {
+                            let handle =
+                                crate::transport::server::handle(&mut cx, &mut service, req);
+                            tokio::pin!(handle);
+                            // drops the handler if the client is gone, nobody waits for the
+                            // response
+                            tokio::select! {
+                                resp = &mut handle => resp,
+                                true = framed.peer_closed(), if !oneway => {
+                                    debug!("[VOLO] connection closed by client, cancel the call");
+                                    return;
+                                }
+                            }
+                        };
 
                         if exit_mark.load(Ordering::Relaxed) {
                             cx.transport.set_conn_reset(true);
@@ -84,7 +109,10 @@ pub async fn serve<Svc, Req, Resp, E, D>(
                     Err(e) => {
                         error!("{:?}", e);
                         cx.msg_type = Some(TMessageType::Exception);
-                        if !matches!(e, Error::Transport(_)) {
+                        // the peer of a oneway call never reads the response
+                        if !matches!(e, Error::Transport(_))
+                            && cx.req_msg_type != Some(TMessageType::OneWay)
+                        {
                             let msg = ThriftMessage::mk_server_resp(&cx, Err::<DummyMessage, _>(e))
                                 .unwrap();
                             if let Err(e) = framed.send(&mut cx, msg).await {
@@ -0,0 +1,35 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use tokio::sync::OwnedSemaphorePermit;
+
+use super::PoolStats;
+
+/// Keeps a connection counted in the stats and the `max_conns_per_key` limit until dropped.
+pub(super) struct ConnGuard {
+    _permit: Option<OwnedSemaphorePermit>,
+    stats: Arc<PoolStats>,
+    // the epoch of the key when the connection was made, see `Inner::epochs`
+    pub(super) epoch: u64,
+}
+
+impl ConnGuard {
+    pub(super) fn new(
+        permit: Option<OwnedSemaphorePermit>,
+        stats: Arc<PoolStats>,
+        epoch: u64,
+    ) -> Self {
+        stats.open.fetch_add(1, Ordering::Relaxed);
+        stats.created.fetch_add(1, Ordering::Relaxed);
+        Self {
+            _permit: permit,
+            stats,
+            epoch,
+        }
+    }
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        self.stats.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
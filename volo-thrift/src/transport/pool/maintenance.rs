@@ -0,0 +1,226 @@
+//! Keeps the idle connections of the pool, by closing the expired ones and making new ones.
+
+use std::{
+    fmt::Debug,
+    future::Future,
+    hash::Hash,
+    pin::Pin,
+    sync::{atomic::Ordering, Arc, Mutex, Weak},
+    task::{Context, Poll},
+};
+
+use futures::ready;
+use motore::{service::UnaryService, BoxError};
+use pin_project::pin_project;
+use tokio::{
+    sync::{oneshot, Semaphore},
+    time::{Duration, Instant, Interval},
+};
+use volo::Unwrap;
+
+use super::{guard::ConnGuard, Inner, Pool, Poolable};
+
+// how often the idle connections are checked and refilled when `min_idle_per_key` is set
+const REFILL_INTERVAL: Duration = Duration::from_secs(1);
+
+impl<Key, T> Pool<Key, T>
+where
+    Key: Clone + Eq + Hash + Debug + Send + 'static,
+    T: Poolable + Send + 'static,
+{
+    /// Makes `n` idle connections to each of the keys in the background.
+    pub fn warm_up<MT>(&self, keys: Vec<Key>, n: usize, mt: MT)
+    where
+        MT: UnaryService<Key, Response = T> + Clone + Send + 'static,
+        MT::Error: Into<BoxError>,
+    {
+        if n == 0 {
+            return;
+        }
+        {
+            let mut inner = self.inner.lock().volo_unwrap();
+            if inner.min_idle_per_key > 0 {
+                inner.keys.extend(keys.iter().cloned());
+            }
+        }
+        for key in keys {
+            let inner = Arc::downgrade(&self.inner);
+            let mut mt = mt.clone();
+            self.runtime.spawn(async move {
+                make_idle(&inner, key, n, &mut mt).await;
+            });
+        }
+    }
+
+    /// Keeps `min_idle_per_key` idle connections to the keys that have been got, until the pool
+    /// is dropped.
+    pub(crate) fn maintain<MT>(&self, mut mt: MT)
+    where
+        MT: UnaryService<Key, Response = T> + Send + 'static,
+        MT::Error: Into<BoxError>,
+    {
+        if self.inner.lock().volo_unwrap().min_idle_per_key == 0 {
+            return;
+        }
+        let inner = Arc::downgrade(&self.inner);
+        let runtime = self.runtime.clone();
+        self.runtime.spawn(async move {
+            loop {
+                runtime.sleep(REFILL_INTERVAL).await;
+                let lacking = match inner.upgrade() {
+                    Some(inner) => {
+                        let mut inner = inner.lock().volo_unwrap();
+                        inner.clear_expired();
+                        inner.lacking()
+                    }
+                    None => return,
+                };
+                for (key, n) in lacking {
+                    make_idle(&inner, key, n, &mut mt).await;
+                }
+            }
+        });
+    }
+}
+
+impl<Key, T: Poolable> Inner<Key, T>
+where
+    Key: Eq + Hash + Debug,
+{
+    // clear expired idle
+    pub(super) fn clear_expired(&mut self) {
+        let timeout = self.timeout;
+        let min_idle = self.min_idle_per_key;
+        let now = Instant::now();
+        let stats = &self.stats;
+        self.idle.retain(|key, values| {
+            // the list is ordered by the idle time, so the last ones are the newest
+            let keep_from = values.len().saturating_sub(min_idle);
+            let mut index = 0;
+            values.retain_mut(|entry| {
+                index += 1;
+                // TODO: check has_idle && remove the (idle, waiters) key
+                if !entry.inner.reuseable() || entry.inner.is_closed() {
+                    tracing::trace!("[VOLO] idle interval evicting closed for {:?}", key);
+                    stats.idle.fetch_sub(1, Ordering::Relaxed);
+                    stats.evicted.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                if index <= keep_from && now - entry.idle_at > timeout {
+                    tracing::trace!("[VOLO] idle interval evicting expired for {:?}", key);
+                    stats.idle.fetch_sub(1, Ordering::Relaxed);
+                    stats.evicted.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+
+                true
+            });
+            !values.is_empty()
+        });
+    }
+
+    /// Returns the keys with less than `min_idle_per_key` idle connections, and the number of
+    /// connections to make for each.
+    pub(super) fn lacking(&self) -> Vec<(Key, usize)>
+    where
+        Key: Clone,
+    {
+        self.keys
+            .iter()
+            .filter_map(|key| {
+                let idle = self.idle.get(key).map(Vec::len).unwrap_or(0);
+                (idle < self.min_idle_per_key).then(|| (key.clone(), self.min_idle_per_key - idle))
+            })
+            .collect()
+    }
+}
+
+/// Makes `n` connections to `key` one by one and puts them into the pool, stops when failed or
+/// the `max_conns_per_key` limit is reached.
+pub(super) async fn make_idle<Key, T, MT>(
+    inner: &Weak<Mutex<Inner<Key, T>>>,
+    key: Key,
+    n: usize,
+    mt: &mut MT,
+) where
+    Key: Clone + Eq + Hash + Debug,
+    T: Poolable,
+    MT: UnaryService<Key, Response = T>,
+    MT::Error: Into<BoxError>,
+{
+    for _ in 0..n {
+        let (limit, stats, epoch) = match inner.upgrade() {
+            Some(inner) => {
+                let mut inner = inner.lock().volo_unwrap();
+                (inner.limit(&key), inner.stats.clone(), inner.epoch(&key))
+            }
+            None => return,
+        };
+        let permit = match limit.map(Semaphore::try_acquire_owned) {
+            Some(Ok(permit)) => Some(permit),
+            Some(Err(_)) => return,
+            None => None,
+        };
+        let fut = mt.call(key.clone());
+        let t = match fut.await {
+            Ok(t) => t,
+            Err(e) => {
+                let e: BoxError = e.into();
+                tracing::debug!("[VOLO] make idle connection error: {:?}, key: {:?}", e, key);
+                return;
+            }
+        };
+        match inner.upgrade() {
+            Some(inner) => {
+                let guard = ConnGuard::new(permit, stats, epoch);
+                inner.lock().volo_unwrap().put(key.clone(), t, Some(guard));
+            }
+            None => return,
+        }
+    }
+}
+
+// Idle refresh task
+#[pin_project]
+pub(super) struct IdleTask<Key, T> {
+    // refresh interval
+    #[pin]
+    pub(super) interval: Interval,
+    // pool
+    pub(super) inner: Weak<Mutex<Inner<Key, T>>>,
+    // drop tx and rx recv error
+    #[pin]
+    pub(super) pool_drop_tx: oneshot::Sender<()>,
+}
+
+impl<Key, T> Future for IdleTask<Key, T>
+where
+    Key: Eq + Hash + Debug,
+    T: Poolable,
+{
+    type Output = ();
+
+    // long loop for check transport timeout
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        loop {
+            match this.pool_drop_tx.as_mut().poll_closed(cx) {
+                Poll::Ready(()) => {
+                    tracing::trace!("[VOLO] pool closed, canceling idle interval");
+                    return Poll::Ready(());
+                }
+                Poll::Pending => (),
+            }
+            ready!(this.interval.as_mut().poll_tick(cx));
+            if let Some(inner) = this.inner.upgrade() {
+                if let Ok(mut inner) = inner.lock() {
+                    tracing::trace!("[VOLO] idle interval checking for expired");
+                    inner.clear_expired();
+
+                    continue;
+                }
+            }
+            return Poll::Ready(());
+        }
+    }
+}
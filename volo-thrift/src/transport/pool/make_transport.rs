@@ -32,15 +32,20 @@ where
 
 impl<MT, Key> PooledMakeTransport<MT, Key>
 where
-    MT: UnaryService<Key>,
+    MT: UnaryService<Key> + Clone + Send + 'static,
     MT::Response: Poolable + Send + 'static,
+    MT::Error: Into<BoxError>,
     Key: Clone + Eq + Hash + Debug + Send + 'static,
 {
     pub fn new(inner: MT, cfg: Option<super::Config>) -> Self {
-        Self {
-            inner,
-            pool: Pool::new(cfg),
-        }
+        let pool = Pool::new(cfg);
+        pool.maintain(inner.clone());
+        Self { inner, pool }
+    }
+
+    /// Makes `n` idle connections to each of the keys in the background.
+    pub fn warm_up(&self, keys: Vec<Key>, n: usize) {
+        self.pool.warm_up(keys, n, self.inner.clone());
     }
 }
 
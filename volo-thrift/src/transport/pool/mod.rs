@@ -1,36 +1,37 @@
 #![allow(dead_code)]
 //! These codes are originally copied from `hyper/client/pool.rs` with a lot of modifications.
 
+mod guard;
+mod maintenance;
 mod make_transport;
 mod started;
+mod stats;
 pub mod thrift_transport;
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::Debug,
-    future::Future,
     hash::Hash,
+    io,
     ops::{Deref, DerefMut},
-    pin::Pin,
-    sync::{Arc, Mutex, Weak},
-    task::{Context, Poll},
+    sync::{atomic::Ordering, Arc, Mutex, Weak},
 };
 
-use futures::{
-    future::{self, Either},
-    ready,
-};
+use futures::future::{self, Either};
+use guard::ConnGuard;
 use linked_hash_map::LinkedHashMap;
+use maintenance::IdleTask;
 pub use make_transport::PooledMakeTransport;
 use motore::{service::UnaryService, BoxError};
 use pin_project::pin_project;
 use started::Started as _;
+pub use stats::PoolStats;
 pub use thrift_transport::{ReadHalf, ThriftTransport, WriteHalf};
 use tokio::{
-    sync::oneshot,
-    time::{interval, Duration, Instant, Interval},
+    sync::{oneshot, Semaphore},
+    time::{interval, timeout, Duration, Instant},
 };
-use volo::Unwrap;
+use volo::{rt::Runtime, Unwrap};
 
 pub trait Poolable: Sized {
     // check if the connection is opened
@@ -47,6 +48,11 @@ pub trait Poolable: Sized {
     fn can_share(&self) -> bool {
         false
     }
+
+    /// Checks whether the idle connection has been closed by the peer, without blocking.
+    fn is_closed(&mut self) -> bool {
+        false
+    }
 }
 
 /// When checking out a pooled connection, it might be that the connection
@@ -70,12 +76,14 @@ pub enum Reservation<T> {
 pub struct Pool<Key, T> {
     // share between threads
     inner: Arc<Mutex<Inner<Key, T>>>,
+    runtime: Runtime,
 }
 
 impl<Key, T> Clone for Pool<Key, T> {
     fn clone(&self) -> Self {
         Pool {
             inner: self.inner.clone(),
+            runtime: self.runtime.clone(),
         }
     }
 }
@@ -83,14 +91,26 @@ impl<Key, T> Clone for Pool<Key, T> {
 #[derive(Clone, Debug)]
 pub struct Config {
     max_idle_per_key: usize,
+    min_idle_per_key: usize,
+    max_conns_per_key: Option<usize>,
+    wait_timeout: Option<Duration>,
+    warm_up: usize,
     timeout: Duration,
+    stats: Arc<PoolStats>,
+    runtime: Runtime,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             max_idle_per_key: 10240,
+            min_idle_per_key: 0,
+            max_conns_per_key: None,
+            wait_timeout: None,
+            warm_up: 0,
             timeout: Duration::from_secs(15),
+            stats: Default::default(),
+            runtime: Default::default(),
         }
     }
 }
@@ -100,6 +120,7 @@ impl Config {
         Config {
             max_idle_per_key,
             timeout,
+            ..Default::default()
         }
     }
 
@@ -108,10 +129,65 @@ impl Config {
         self
     }
 
+    /// Sets the max number of connections to each endpoint, both idle and in use.
+    ///
+    /// When the limit is reached, the caller waits for a connection to be put back into the pool
+    /// or closed. Defaults to unlimited.
+    pub fn max_conns_per_key(mut self, max_conns_per_key: usize) -> Self {
+        self.max_conns_per_key = Some(max_conns_per_key.max(1));
+        self
+    }
+
+    /// Sets the max time to wait for a connection when the `max_conns_per_key` limit is reached,
+    /// after which the call fails with a timed out error instead of waiting longer.
+    ///
+    /// Defaults to waiting until a connection is put back or closed, bounded by the connect
+    /// timeout of the client if set.
+    pub fn wait_timeout(mut self, timeout: Duration) -> Self {
+        self.wait_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the min number of idle connections to each endpoint that has been connected, so that
+    /// the calls after a quiet period don't wait for new connections.
+    ///
+    /// The idle connections are checked every second, the ones closed by the peer are replaced by
+    /// new ones made in the background, and the newest `min_idle_per_key` ones are kept even if
+    /// they are idle for longer than the timeout. Defaults to 0.
+    pub fn min_idle_per_key(mut self, min_idle_per_key: usize) -> Self {
+        self.min_idle_per_key = min_idle_per_key;
+        self
+    }
+
+    /// Sets the number of connections made to the configured address or mesh proxy when the
+    /// client is built, so that the first calls after a deployment don't pay for connecting.
+    ///
+    /// The endpoints from the service discovery are connected on the first calls, and kept at
+    /// [`min_idle_per_key`](Self::min_idle_per_key) after that. Defaults to 0.
+    pub fn warm_up(mut self, connections: usize) -> Self {
+        self.warm_up = connections;
+        self
+    }
+
+    pub(crate) fn warm_up_connections(&self) -> usize {
+        self.warm_up
+    }
+
+    /// Sets the idle timeout, idle connections older than this will be closed.
     pub fn timeout(mut self, timeout: Duration) -> Self {
         self.timeout = timeout;
         self
     }
+
+    pub(crate) fn with_runtime(mut self, runtime: Runtime) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Returns the statistics of the pool built with this config.
+    pub fn stats(&self) -> Arc<PoolStats> {
+        self.stats.clone()
+    }
 }
 
 struct Expiration(Option<Duration>);
@@ -134,15 +210,20 @@ impl Expiration {
 struct IdlePopper<'a, Key, T> {
     key: &'a Key,
     list: &'a mut Vec<Idle<T>>,
+    stats: &'a PoolStats,
+    // the last ones are kept even if expired
+    min_idle: usize,
 }
 
 impl<'a, Key: Debug, T: Poolable + 'a> IdlePopper<'a, Key, T> {
     fn pop(self, expiration: &Expiration) -> Option<Idle<T>> {
         while let Some(entry) = self.list.pop() {
+            self.stats.idle.fetch_sub(1, Ordering::Relaxed);
             // If the connection has been closed, or is older than our idle
             // timeout, simply drop it and keep looking...
             if !entry.inner.reuseable() {
                 tracing::trace!("[VOLO] removing closed connection for {:?}", self.key);
+                self.stats.evicted.fetch_add(1, Ordering::Relaxed);
                 continue;
             }
             // TODO: Actually, since the `idle` list is pushed to the end always,
@@ -151,25 +232,29 @@ impl<'a, Key: Debug, T: Poolable + 'a> IdlePopper<'a, Key, T> {
             //
             // In that case, we could just break out of the loop and drop the
             // whole list...
-            if expiration.expires(entry.idle_at) {
+            if expiration.expires(entry.idle_at) && self.list.len() >= self.min_idle {
                 tracing::trace!("[VOLO] removing expired connection for {:?}", self.key);
+                self.stats.evicted.fetch_add(1, Ordering::Relaxed);
                 continue;
             }
 
-            let value = match entry.inner.reserve() {
+            let (value, guard) = match entry.inner.reserve() {
                 Reservation::Shared(to_reinsert, to_return) => {
                     self.list.push(Idle {
                         idle_at: Instant::now(),
                         inner: to_reinsert,
+                        guard: entry.guard,
                     });
-                    to_return
+                    self.stats.idle.fetch_add(1, Ordering::Relaxed);
+                    (to_return, None)
                 }
-                Reservation::Unique(unique) => unique,
+                Reservation::Unique(unique) => (unique, entry.guard),
             };
 
             return Some(Idle {
                 idle_at: entry.idle_at,
                 inner: value,
+                guard,
             });
         }
 
@@ -189,8 +274,15 @@ where
         let inner = Arc::new(Mutex::new(Inner {
             idle: HashMap::new(),
             waiters: HashMap::new(),
+            limits: HashMap::new(),
+            epochs: HashMap::new(),
+            keys: HashSet::new(),
             timeout: cfg.timeout,
             max_idle_per_key: cfg.max_idle_per_key,
+            min_idle_per_key: cfg.min_idle_per_key,
+            max_conns_per_key: cfg.max_conns_per_key,
+            wait_timeout: cfg.wait_timeout,
+            stats: cfg.stats,
             _pool_drop_rx: rx,
         }));
 
@@ -199,8 +291,11 @@ where
             inner: Arc::downgrade(&inner),
             pool_drop_tx: tx,
         };
-        tokio::spawn(idle_task);
-        Pool { inner }
+        cfg.runtime.spawn(idle_task);
+        Pool {
+            inner,
+            runtime: cfg.runtime,
+        }
     }
 
     pub async fn get<MT>(&self, key: Key, mut mt: MT) -> Result<Pooled<Key, T>, BoxError>
@@ -208,14 +303,24 @@ where
         MT: UnaryService<Key, Response = T> + Send + 'static,
         MT::Error: Into<BoxError>,
     {
-        let (rx, _waiter_token) = {
+        let (rx, _waiter_token, limit, wait_timeout, stats, epoch) = {
             let mut inner = self.inner.lock().volo_unwrap();
+            let stats = inner.stats.clone();
+            let min_idle = inner.min_idle_per_key;
+            if min_idle > 0 && !inner.keys.contains(&key) {
+                inner.keys.insert(key.clone());
+            }
             // 1. check the idle and opened connections
             let expiration = Expiration::new(Some(inner.timeout));
             let maybe_entry = inner.idle.get_mut(&key).and_then(|list| {
                 tracing::trace!("[VOLO] take? {:?}: expiration = {:?}", key, expiration.0);
                 {
-                    let popper = IdlePopper { key: &key, list };
+                    let popper = IdlePopper {
+                        key: &key,
+                        list,
+                        stats: &stats,
+                        min_idle,
+                    };
                     popper.pop(&expiration)
                 }
                 .map(|e| (e, list.is_empty()))
@@ -235,8 +340,10 @@ where
 
             if let Some(t) = entry {
                 tracing::debug!("[VOLO] reuse connection from cache for {:?}", key);
-                return Ok(self.reuse(&key, t.inner));
+                stats.reused.fetch_add(1, Ordering::Relaxed);
+                return Ok(self.reuse(&key, t.inner, t.guard));
             }
+            let limit = inner.limit(&key);
             // 2. no valid idle then add caller into waiters and make connection
             let waiters = if let Some(waiter) = inner.waiters.get_mut(&key) {
                 waiter
@@ -247,41 +354,69 @@ where
                     .or_insert_with(Default::default)
             };
             let (tx, rx) = oneshot::channel();
-            (rx, waiters.insert(tx))
+            let token = waiters.insert(tx);
+            let epoch = inner.epoch(&key);
+            (rx, token, limit, inner.wait_timeout, stats, epoch)
             // drop lock guard before await
         };
 
         // 3. select waiter and mc return future
         let lazy_fut = {
             let key = key.clone();
-            move || Box::pin(async move { mt.call(key).await })
+            let stats = stats.clone();
+            move || {
+                Box::pin(async move {
+                    // wait until the number of connections is under the limit
+                    let permit = match (limit, wait_timeout) {
+                        (Some(limit), Some(wait_timeout)) => {
+                            match timeout(wait_timeout, limit.acquire_owned()).await {
+                                Ok(permit) => Some(permit.volo_unwrap()),
+                                Err(_) => {
+                                    return Err(BoxError::from(io::Error::new(
+                                        io::ErrorKind::TimedOut,
+                                        "timed out waiting for a connection from the pool",
+                                    )))
+                                }
+                            }
+                        }
+                        // the semaphore is never closed
+                        (Some(limit), None) => Some(limit.acquire_owned().await.volo_unwrap()),
+                        (None, _) => None,
+                    };
+                    mt.call(key)
+                        .await
+                        .map(|t| (t, ConnGuard::new(permit, stats, epoch)))
+                        .map_err(Into::into)
+                })
+            }
         };
 
         // waiter or make transport finished
         match future::select(rx, started::lazy(lazy_fut)).await {
-            Either::Left((Ok(v), fut)) => {
+            Either::Left((Ok((v, guard)), fut)) => {
                 // check the make transport future has started
                 if fut.started() {
                     let key = key.clone();
                     let this = self.clone();
                     // complete the make transport and put into pool
-                    tokio::spawn(async move {
-                        if let Ok(t) = fut.await {
+                    self.runtime.spawn(async move {
+                        if let Ok((t, guard)) = fut.await {
                             // drop here and put back into pool
                             // spawn need 'static, so we move weak_pool from out scope
                             tracing::debug!("[VOLO] spawn make_transport finished for {:?}", key);
-                            let _ = this.pooled(&key, t);
+                            let _ = this.pooled(&key, t, guard);
                         }
                     });
                 }
                 tracing::debug!("[VOLO] reuse connection from waiter for {:?}", key);
+                stats.reused.fetch_add(1, Ordering::Relaxed);
                 // get connection from pool
-                Ok(self.reuse(&key, v))
+                Ok(self.reuse(&key, v, guard))
             }
-            Either::Right((Ok(v), _)) => {
+            Either::Right((Ok((v, guard)), _)) => {
                 tracing::debug!("[VOLO] new connection from make_transport for {:?}", key);
                 // FIXME: maybe remove waiter
-                Ok(self.pooled(&key, v))
+                Ok(self.pooled(&key, v, guard))
             }
             // means connection pool is dropped
             Either::Left((Err(e), _)) => {
@@ -292,35 +427,34 @@ where
             // maybe there is no more connection put back into pool and waiter will block forever,
             // so just return error
             Either::Right((Err(e), _)) => {
-                let e = e.into();
                 tracing::error!("[VOLO] create connection error: {:?}, key: {:?}", e, key);
                 Err(e)
             }
         }
     }
 
-    fn pooled(&self, key: &Key, value: T) -> Pooled<Key, T> {
-        let (value, pool_ref) = {
+    fn pooled(&self, key: &Key, value: T, guard: ConnGuard) -> Pooled<Key, T> {
+        let (value, guard, pool_ref) = {
             match value.reserve() {
                 Reservation::Shared(to_insert, to_return) => {
                     let mut inner = self.inner.lock().unwrap();
-                    inner.put(key.clone(), to_insert);
+                    inner.put(key.clone(), to_insert, Some(guard));
                     // Shared reservations don't need a reference to the pool,
                     // since the pool always keeps a copy.
-                    (to_return, None)
+                    (to_return, None, None)
                 }
                 Reservation::Unique(value) => {
                     // Unique reservations must take a reference to the pool
                     // since they hope to reinsert once the reservation is
                     // completed
-                    (value, Some(Arc::downgrade(&self.inner)))
+                    (value, Some(guard), Some(Arc::downgrade(&self.inner)))
                 }
             }
         };
-        Pooled::new(key.clone(), value, pool_ref)
+        Pooled::new(key.clone(), value, guard, pool_ref)
     }
 
-    fn reuse(&self, key: &Key, value: T) -> Pooled<Key, T> {
+    fn reuse(&self, key: &Key, value: T, guard: Option<ConnGuard>) -> Pooled<Key, T> {
         tracing::debug!("[VOLO] reuse idle connection for {:?}", key);
         // TODO: unhack this
         // In Pool::pooled(), which is used for inserting brand new connections,
@@ -333,13 +467,43 @@ where
         if !value.can_share() {
             pool_ref = Some(Arc::downgrade(&self.inner));
         }
-        Pooled::new(key.clone(), value, pool_ref)
+        Pooled::new(key.clone(), value, guard, pool_ref)
+    }
+
+    /// Closes the idle connections to `key`, and the connections in use will be closed instead
+    /// of being put back.
+    pub fn evict(&self, key: &Key) {
+        self.inner.lock().volo_unwrap().evict(key);
+    }
+
+    /// Evicts the connections to the keys from the stream, until the stream ends or the pool is
+    /// dropped.
+    pub fn evict_from<St>(&self, mut keys: St)
+    where
+        St: futures::Stream<Item = Vec<Key>> + Unpin + Send + 'static,
+    {
+        use futures::StreamExt;
+
+        let inner = Arc::downgrade(&self.inner);
+        self.runtime.spawn(async move {
+            while let Some(keys) = keys.next().await {
+                let inner = match inner.upgrade() {
+                    Some(inner) => inner,
+                    None => return,
+                };
+                let mut inner = inner.lock().volo_unwrap();
+                for key in keys.iter() {
+                    inner.evict(key);
+                }
+            }
+        });
     }
 }
 
 struct Idle<T> {
     inner: T,
     idle_at: Instant,
+    guard: Option<ConnGuard>,
 }
 
 #[pin_project]
@@ -351,6 +515,7 @@ where
     key: Option<Key>,
     #[pin]
     t: Option<T>,
+    guard: Option<ConnGuard>,
     // shared transport no need pool ref
     pool: Option<Weak<Mutex<Inner<Key, T>>>>,
 }
@@ -360,10 +525,16 @@ where
     T: Poolable + Send,
     Key: Eq + Hash + Debug + Send,
 {
-    fn new(key: Key, t: T, pool: Option<Weak<Mutex<Inner<Key, T>>>>) -> Self {
+    fn new(
+        key: Key,
+        t: T,
+        guard: Option<ConnGuard>,
+        pool: Option<Weak<Mutex<Inner<Key, T>>>>,
+    ) -> Self {
         Pooled {
             key: Some(key),
             t: Some(t),
+            guard,
             pool,
         }
     }
@@ -380,7 +551,7 @@ where
         if let Some(pool) = pool {
             if let Some(pool) = pool.upgrade() {
                 if let Ok(mut pool) = pool.lock() {
-                    pool.put(key, inner);
+                    pool.put(key, inner, self.guard.take());
                 }
             }
         }
@@ -467,11 +638,23 @@ struct Inner<Key, T> {
     // idle queue
     idle: HashMap<Key, Vec<Idle<T>>>,
     // waiters wait for idle transport
-    waiters: HashMap<Key, WaiterList<T>>,
+    waiters: HashMap<Key, WaiterList<(T, Option<ConnGuard>)>>,
+    // limits the connections per key
+    limits: HashMap<Key, Arc<Semaphore>>,
+    // bumped when the key is evicted, the connections made before that are not put back
+    epochs: HashMap<Key, u64>,
+    // the keys to keep `min_idle_per_key` idle connections for
+    keys: HashSet<Key>,
     // idle timeout and check interval
     timeout: Duration,
     // idle count per key
     max_idle_per_key: usize,
+    min_idle_per_key: usize,
+    // connection count per key
+    max_conns_per_key: Option<usize>,
+    // the time to wait for the limit of the connection count
+    wait_timeout: Option<Duration>,
+    stats: Arc<PoolStats>,
     // when rx dropped, then tx poll_closed will return Poll::Ready(())
     // then idle task exist
     _pool_drop_rx: oneshot::Receiver<()>,
@@ -481,26 +664,47 @@ impl<Key, T: Poolable> Inner<Key, T>
 where
     Key: Eq + Hash + Debug,
 {
-    // clear expired idle
-    fn clear_expired(&mut self) {
-        let timeout = self.timeout;
-        let now = Instant::now();
-        self.idle.retain(|key, values| {
-            values.retain(|entry| {
-                // TODO: check has_idle && remove the (idle, waiters) key
-                if !entry.inner.reuseable() {
-                    tracing::trace!("[VOLO] idle interval evicting closed for {:?}", key);
-                    return false;
-                }
-                if now - entry.idle_at > timeout {
-                    tracing::trace!("[VOLO] idle interval evicting expired for {:?}", key);
-                    return false;
-                }
+    fn epoch(&self, key: &Key) -> u64 {
+        self.epochs.get(key).copied().unwrap_or(0)
+    }
 
-                true
-            });
-            !values.is_empty()
-        });
+    fn limit(&mut self, key: &Key) -> Option<Arc<Semaphore>>
+    where
+        Key: Clone,
+    {
+        let max = self.max_conns_per_key?;
+        Some(
+            self.limits
+                .entry(key.clone())
+                .or_insert_with(|| Arc::new(Semaphore::new(max)))
+                .clone(),
+        )
+    }
+
+    fn evict(&mut self, key: &Key)
+    where
+        Key: Clone,
+    {
+        *self.epochs.entry(key.clone()).or_insert(0) += 1;
+        self.keys.remove(key);
+        if let Some(list) = self.idle.remove(key) {
+            tracing::debug!(
+                "[VOLO] closing {} idle connections to removed {:?}",
+                list.len(),
+                key
+            );
+            self.stats.idle.fetch_sub(list.len(), Ordering::Relaxed);
+            self.stats
+                .removed
+                .fetch_add(list.len() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<Key, T> Drop for Inner<Key, T> {
+    fn drop(&mut self) {
+        let idle = self.idle.values().map(Vec::len).sum();
+        self.stats.idle.fetch_sub(idle, Ordering::Relaxed);
     }
 }
 
@@ -509,7 +713,14 @@ where
     Key: Eq + Hash + Debug,
     T: Poolable,
 {
-    fn put(&mut self, key: Key, t: T) {
+    fn put(&mut self, key: Key, t: T, guard: Option<ConnGuard>) {
+        if let Some(guard) = &guard {
+            if guard.epoch < self.epoch(&key) {
+                tracing::trace!("[VOLO] put; closing connection to removed {:?}", key);
+                self.stats.removed.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
         if t.can_share() && self.idle.contains_key(&key) {
             tracing::trace!(
                 "[VOLO] put; existing idle Shareable connection for {:?}",
@@ -518,19 +729,19 @@ where
             return;
         }
         // check the wait queue
-        let mut value = Some(t);
+        let mut value = Some((t, guard));
         if let Some(waiters) = self.waiters.get_mut(&key) {
             // find a waiter and send
             while let Some(waiter) = waiters.pop() {
                 // check if waiter is dropped
                 if !waiter.is_closed() {
-                    let t = value.take().volo_unwrap();
+                    let (t, guard) = value.take().volo_unwrap();
                     let t = match t.reserve() {
                         Reservation::Shared(to_keep, to_send) => {
-                            value = Some(to_keep);
-                            to_send
+                            value = Some((to_keep, guard));
+                            (to_send, None)
                         }
-                        Reservation::Unique(unique) => unique,
+                        Reservation::Unique(unique) => (unique, guard),
                     };
                     match waiter.send(t) {
                         Ok(()) => {
@@ -549,7 +760,7 @@ where
             }
         }
         // check if send to some waiter
-        if let Some(t) = value {
+        if let Some((t, guard)) = value {
             // means doesn't send success
             // then put back to idle list
             let idle = self.idle.entry(key).or_insert_with(Vec::new);
@@ -557,60 +768,22 @@ where
                 idle.push(Idle {
                     inner: t,
                     idle_at: Instant::now(),
+                    guard,
                 });
+                self.stats.idle.fetch_add(1, Ordering::Relaxed);
             }
         }
     }
 }
 
-// Idle refresh task
-#[pin_project]
-struct IdleTask<Key, T> {
-    // refresh interval
-    #[pin]
-    interval: Interval,
-    // pool
-    inner: Weak<Mutex<Inner<Key, T>>>,
-    // drop tx and rx recv error
-    #[pin]
-    pool_drop_tx: oneshot::Sender<()>,
-}
-
-impl<Key, T> Future for IdleTask<Key, T>
-where
-    Key: Eq + Hash + Debug,
-    T: Poolable,
-{
-    type Output = ();
-
-    // long loop for check transport timeout
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut this = self.project();
-        loop {
-            match this.pool_drop_tx.as_mut().poll_closed(cx) {
-                Poll::Ready(()) => {
-                    tracing::trace!("[VOLO] pool closed, canceling idle interval");
-                    return Poll::Ready(());
-                }
-                Poll::Pending => (),
-            }
-            ready!(this.interval.as_mut().poll_tick(cx));
-            if let Some(inner) = this.inner.upgrade() {
-                if let Ok(mut inner) = inner.lock() {
-                    tracing::trace!("[VOLO] idle interval checking for expired");
-                    inner.clear_expired();
-
-                    continue;
-                }
-            }
-            return Poll::Ready(());
-        }
-    }
-}
-
 #[cfg(test)]
 mod test {
-    use std::io;
+    use std::{
+        future::Future,
+        io,
+        pin::Pin,
+        task::{Context, Poll},
+    };
 
     use tokio::{
         io::{AsyncRead, AsyncWrite},
@@ -658,4 +831,122 @@ mod test {
             self.project().0.poll_shutdown(cx)
         }
     }
+
+    #[derive(Clone)]
+    struct MockMakeTransport;
+
+    impl UnaryService<String> for MockMakeTransport {
+        type Response = MockConnection;
+        type Error = io::Error;
+        type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>>;
+
+        fn call(&mut self, _key: String) -> Self::Future<'_> {
+            async move {
+                let (conn, _) = UnixStream::pair()?;
+                Ok(MockConnection(conn))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_conns_and_stats() {
+        let cfg = Config::default().max_conns_per_key(1);
+        let stats = cfg.stats();
+        let pool = Pool::new(Some(cfg));
+
+        let conn = pool.get("a".into(), MockMakeTransport).await.unwrap();
+        assert_eq!(stats.open(), 1);
+        assert_eq!(stats.in_use(), 1);
+
+        // the limit is reached, so the caller waits for the connection to be put back
+        let mut waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.get("a".into(), MockMakeTransport).await.unwrap() }
+        });
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), &mut waiting)
+                .await
+                .is_err()
+        );
+
+        conn.reuse();
+        let _conn = waiting.await.unwrap();
+        assert_eq!(stats.created(), 1);
+        assert_eq!(stats.reused(), 1);
+        assert_eq!(stats.open(), 1);
+        assert_eq!(stats.idle(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_wait_timeout() {
+        let cfg = Config::default()
+            .max_conns_per_key(1)
+            .wait_timeout(Duration::from_millis(10));
+        let pool = Pool::new(Some(cfg));
+
+        let conn = pool.get("a".into(), MockMakeTransport).await.unwrap();
+        let err = pool.get("a".into(), MockMakeTransport).await.err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<io::Error>().unwrap().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        // the waiters still get the connections put back in time
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.get("a".into(), MockMakeTransport).await }
+        });
+        conn.reuse();
+        waiting.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_evict() {
+        let cfg = Config::default();
+        let stats = cfg.stats();
+        let pool = Pool::new(Some(cfg));
+
+        let idle = pool.get("a".into(), MockMakeTransport).await.unwrap();
+        let in_use = pool.get("a".into(), MockMakeTransport).await.unwrap();
+        idle.reuse();
+        assert_eq!(stats.idle(), 1);
+
+        pool.evict(&"a".into());
+        assert_eq!(stats.idle(), 0);
+        // the connection in use is closed instead of being put back
+        in_use.reuse();
+        assert_eq!(stats.idle(), 0);
+        assert_eq!(stats.open(), 0);
+        assert_eq!(stats.removed(), 2);
+
+        // new connections can be pooled again
+        pool.get("a".into(), MockMakeTransport)
+            .await
+            .unwrap()
+            .reuse();
+        assert_eq!(stats.idle(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_warm_up_and_min_idle() {
+        let cfg = Config::default().min_idle_per_key(2);
+        let stats = cfg.stats();
+        let pool = Pool::new(Some(cfg));
+
+        pool.warm_up(vec!["a".to_string()], 3, MockMakeTransport);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(stats.idle(), 3);
+        assert_eq!(stats.created(), 3);
+
+        let _a = pool.get("a".into(), MockMakeTransport).await.unwrap();
+        let _b = pool.get("a".into(), MockMakeTransport).await.unwrap();
+        let _c = pool.get("b".into(), MockMakeTransport).await.unwrap();
+        let mut lacking = pool.inner.lock().unwrap().lacking();
+        lacking.sort();
+        assert_eq!(lacking, vec![("a".into(), 1), ("b".into(), 2)]);
+
+        // the removed endpoints are not refilled
+        pool.evict(&"b".into());
+        assert_eq!(pool.inner.lock().unwrap().lacking(), vec![("a".into(), 1)]);
+    }
 }
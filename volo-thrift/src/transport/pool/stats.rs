@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Statistics of the connection pool, can be used to export metrics.
+#[derive(Debug, Default)]
+pub struct PoolStats {
+    pub(super) open: AtomicUsize,
+    pub(super) idle: AtomicUsize,
+    pub(super) created: AtomicU64,
+    pub(super) reused: AtomicU64,
+    pub(super) evicted: AtomicU64,
+    pub(super) removed: AtomicU64,
+}
+
+impl PoolStats {
+    /// The number of open connections, both idle and in use.
+    pub fn open(&self) -> usize {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    /// The number of idle connections in the pool.
+    pub fn idle(&self) -> usize {
+        self.idle.load(Ordering::Relaxed)
+    }
+
+    /// The number of connections in use.
+    pub fn in_use(&self) -> usize {
+        self.open().saturating_sub(self.idle())
+    }
+
+    /// The number of connections that have been created.
+    pub fn created(&self) -> u64 {
+        self.created.load(Ordering::Relaxed)
+    }
+
+    /// The number of times a connection has been reused.
+    pub fn reused(&self) -> u64 {
+        self.reused.load(Ordering::Relaxed)
+    }
+
+    /// The number of idle connections that have been closed because they are expired or broken.
+    pub fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    /// The number of connections that have been closed because the endpoints were removed by
+    /// the service discovery.
+    pub fn removed(&self) -> u64 {
+        self.removed.load(Ordering::Relaxed)
+    }
+}
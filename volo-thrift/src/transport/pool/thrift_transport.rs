@@ -1,7 +1,9 @@
 use std::sync::atomic::AtomicUsize;
 
+use futures::FutureExt;
 use pilota::thrift::EntryMessage;
 use pin_project::pin_project;
+use tokio::io::AsyncBufReadExt;
 use volo::{
     net::conn::{Conn, OwnedReadHalf, OwnedWriteHalf},
     util::buf_reader::BufReader,
@@ -53,7 +55,7 @@ where
     E: Encoder,
     D: Decoder,
 {
-    pub async fn send<Req: EntryMessage + Size, Resp: EntryMessage>(
+    pub async fn send<Req: EntryMessage + Size, Resp: EntryMessage + Size>(
         &mut self,
         cx: &mut ClientContext,
         msg: ThriftMessage<Req>,
@@ -78,18 +80,11 @@ impl<D> ReadHalf<D>
 where
     D: Decoder,
 {
-    pub async fn try_next<T: EntryMessage>(
+    pub async fn try_next<T: EntryMessage + Size>(
         &mut self,
         cx: &mut ClientContext,
     ) -> Result<Option<ThriftMessage<T>>, Error> {
-        let thrift_msg = self
-            .decoder
-            .decode(cx, &mut self.read_half)
-            .await
-            .map_err(|e| {
-                tracing::error!("[VOLO] transport[{}] decode error: {}", self.id, e);
-                e
-            })?;
+        let thrift_msg = self.decode(cx).await?;
 
         if let Some(ThriftMessage { meta, .. }) = &thrift_msg {
             if meta.seq_id != cx.seq_id {
@@ -107,6 +102,25 @@ where
         };
         Ok(thrift_msg)
     }
+
+    /// Decodes the next message without checking the seq_id, which is left to the caller when
+    /// the responses may come out of order.
+    pub(crate) async fn decode<T: EntryMessage + Size>(
+        &mut self,
+        cx: &mut ClientContext,
+    ) -> Result<Option<ThriftMessage<T>>, Error> {
+        self.decoder
+            .decode(cx, &mut self.read_half)
+            .await
+            .map_err(|e| {
+                tracing::error!("[VOLO] transport[{}] decode error: {}", self.id, e);
+                e
+            })
+    }
+
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
 }
 
 pub struct WriteHalf<E> {
@@ -135,12 +149,22 @@ where
 
         Ok(())
     }
+
+    /// Takes the connection and the encoder, for the transports that write by themselves.
+    pub(crate) fn into_parts(self) -> (OwnedWriteHalf, E) {
+        (self.write_half, self.encoder)
+    }
 }
 
 impl<TTEncoder, TTDecoder> Poolable for ThriftTransport<TTEncoder, TTDecoder> {
     fn reuseable(&self) -> bool {
         self.read_half.reuseable && self.write_half.reuseable
     }
+
+    fn is_closed(&mut self) -> bool {
+        // nothing is sent on an idle connection, so it's only readable when closed or broken
+        self.read_half.read_half.fill_buf().now_or_never().is_some()
+    }
 }
 
 #[cfg(test)]
@@ -1,4 +1,5 @@
 use motore::{service::Service, BoxError};
+use volo::layer::{concurrency_limit::ConcurrencyLimited, rate_limit::RateLimited};
 
 use crate::{context::ServerContext, ApplicationError, ApplicationErrorKind, Error};
 
@@ -15,6 +16,13 @@ where
         Ok(resp) => Ok(resp),
         Err(e) => match e.into().downcast::<Error>() {
             Ok(e) => Err(*e),
+            // the load shed by the limit layers of volo, which the clients may retry elsewhere
+            Err(e) if e.is::<ConcurrencyLimited>() || e.is::<RateLimited>() => {
+                Err(Error::Application(ApplicationError::new(
+                    ApplicationErrorKind::InternalError,
+                    e.to_string(),
+                )))
+            }
             Err(e) => Err(Error::Application(ApplicationError::new(
                 ApplicationErrorKind::Unknown,
                 e.to_string(),
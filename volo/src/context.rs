@@ -1,9 +1,12 @@
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 
 pub use metainfo::MetaInfo;
 use metainfo::TypeMap;
 
-use super::net::Address;
+use super::net::{
+    conn::{ConnInfo, PeerIdentity, TlsInfo},
+    Address,
+};
 
 #[macro_export]
 macro_rules! newtype_impl_context {
@@ -65,6 +68,52 @@ pub trait Context {
 
     fn extensions(&self) -> &Extensions;
     fn extensions_mut(&mut self) -> &mut Extensions;
+
+    /// Adds a key/value field of the request to the logs, see [`LogFields`].
+    fn log_field(&mut self, key: &'static str, value: impl fmt::Display)
+    where
+        Self: Sized,
+    {
+        self.extensions_mut()
+            .entry::<LogFields>()
+            .or_insert_with(LogFields::default)
+            .insert(key, value.to_string());
+    }
+
+    /// Returns the info of the connection of the request on the servers, in the extensions.
+    fn conn_info(&self) -> Option<&ConnInfo> {
+        self.extensions().get::<ConnInfo>()
+    }
+
+    /// Returns the address of the peer of the connection, which is the proxy rather than the
+    /// client if there is one in between.
+    fn peer_addr(&self) -> Option<&Address> {
+        self.conn_info()?.peer_addr.as_ref()
+    }
+
+    /// Returns the local address of the connection.
+    fn local_addr(&self) -> Option<&Address> {
+        self.conn_info()?.local_addr.as_ref()
+    }
+
+    /// Returns the parameters negotiated by the TLS handshake of the connection, `None` if it
+    /// isn't over TLS.
+    fn tls_info(&self) -> Option<&TlsInfo> {
+        self.conn_info()?.tls.as_ref()
+    }
+
+    /// Returns the DER certificate chain verified of the peer, the leaf first, empty if it isn't
+    /// sent.
+    fn tls_peer_certificates(&self) -> &[Vec<u8>] {
+        self.tls_info()
+            .map(|tls| tls.peer_certificates.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns the identity in the certificate of the peer verified by the TLS handshake.
+    fn peer_identity(&self) -> Option<&PeerIdentity> {
+        self.extensions().get::<PeerIdentity>()
+    }
 }
 
 impl<I, Config> Context for RpcCx<I, Config>
@@ -190,6 +239,136 @@ pub enum Role {
     Server,
 }
 
+/// The deadline of the request, which can be set into the extensions to bound the time spent on
+/// it by [`Timeout`](crate::layer::Timeout).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Deadline(pub std::time::Instant);
+
+/// The key/value fields of the request for the logs, in the context extensions and added by
+/// [`Context::log_field`].
+///
+/// Once the request gets to [`Span`](crate::layer::Span), the fields are recorded into its span,
+/// so they're included in all the log records emitted by the layers and the handler during the
+/// request: `request_id` and `caller` are the fields of the span of their own, and the other ones
+/// are recorded together as `fields`.
+#[derive(Debug, Clone, Default)]
+pub struct LogFields {
+    fields: Vec<(&'static str, String)>,
+    span: Option<tracing::Span>,
+}
+
+impl LogFields {
+    /// The keys recorded as the fields of the span of their own.
+    const SPAN_KEYS: [&'static str; 2] = ["request_id", "caller"];
+
+    /// Returns the value of the key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the fields in the order added.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        self.fields.iter().map(|(k, v)| (*k, v.as_str()))
+    }
+
+    /// Adds the field, replacing the value of the same key.
+    pub fn insert(&mut self, key: &'static str, value: String) {
+        match self.fields.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.fields.push((key, value)),
+        }
+        if let Some(span) = &self.span {
+            self.record(span, key);
+        }
+    }
+
+    /// Records the fields into the span, and the ones added later.
+    pub(crate) fn bind(&mut self, span: tracing::Span) {
+        for key in Self::SPAN_KEYS {
+            if self.get(key).is_some() {
+                self.record(&span, key);
+            }
+        }
+        if let Some((key, _)) = self.others().next() {
+            self.record(&span, key);
+        }
+        self.span = Some(span);
+    }
+
+    fn others(&self) -> impl Iterator<Item = &(&'static str, String)> {
+        self.fields
+            .iter()
+            .filter(|(k, _)| !Self::SPAN_KEYS.contains(k))
+    }
+
+    fn record(&self, span: &tracing::Span, key: &'static str) {
+        if Self::SPAN_KEYS.contains(&key) {
+            span.record(key, &self.get(key).unwrap_or_default());
+        } else {
+            let others = self
+                .others()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+                .join(" ");
+            span.record("fields", &others.as_str());
+        }
+    }
+}
+
+impl fmt::Display for LogFields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (k, v)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{}={}", k, v)?;
+        }
+        Ok(())
+    }
+}
+
+/// The priority of the request, which can be set into the extensions, such as by
+/// [`PriorityLayer`](crate::layer::PriorityLayer), so that the low priority requests are rejected
+/// first by [`LoadShed`](crate::layer::LoadShed) and
+/// [`ConcurrencyLimit`](crate::layer::ConcurrencyLimit) when overloaded.
+///
+/// The requests without a priority are [`Priority::Normal`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    /// Never rejected by the load shedding, such as the health checks.
+    High,
+}
+
+impl Priority {
+    /// Returns the name of the priority, used as the value in the metadata.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct RpcInfo<Config> {
     pub role: Role,
@@ -0,0 +1,309 @@
+//! [`Discover`] by the health endpoints of Consul.
+//!
+//! The service name of the endpoint is the name of the Consul service, and only the passing
+//! instances are discovered. Each discovered service is watched by the blocking queries of
+//! Consul in background, and the changes are sent to the watchers.
+//!
+//! The instances are weighted by the passing weights of Consul, and tagged by the service meta
+//! and the service tags, where a tag of `key=value` is split, and the other tags map to empty
+//! values.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::discovery::consul::ConsulDiscover;
+//!
+//! let discover = ConsulDiscover::new("http://127.0.0.1:8500").datacenter("dc1");
+//! ```
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    future::Future,
+    net::IpAddr,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use async_broadcast::Receiver;
+use hyper::{client::HttpConnector, Body, Client, Request, StatusCode};
+use serde::Deserialize;
+use smol_str::SmolStr;
+
+use super::{Change, Discover, Instance, Watched};
+use crate::{context::Endpoint, net::Address};
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The error of querying Consul.
+#[derive(Debug)]
+pub enum ConsulError {
+    Request(hyper::http::Error),
+    Http(hyper::Error),
+    Status(StatusCode),
+    Decode(serde_json::Error),
+}
+
+impl fmt::Display for ConsulError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsulError::Request(e) => write!(f, "invalid consul request: {}", e),
+            ConsulError::Http(e) => write!(f, "consul request failed: {}", e),
+            ConsulError::Status(s) => write!(f, "consul responded {}", s),
+            ConsulError::Decode(e) => write!(f, "invalid consul response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConsulError {}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceEntry {
+    node: Node,
+    service: Service,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Node {
+    address: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Service {
+    address: String,
+    port: u16,
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+    #[serde(default)]
+    meta: Option<HashMap<String, String>>,
+    #[serde(default)]
+    weights: Option<Weights>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Weights {
+    passing: u32,
+}
+
+impl ServiceEntry {
+    fn into_instance(self) -> Option<Instance> {
+        let service = self.service;
+        // the service address defaults to the node address
+        let host = if service.address.is_empty() {
+            self.node.address
+        } else {
+            service.address
+        };
+        let ip = match host.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => {
+                tracing::warn!("[VOLO] ignored consul instance of address {}", host);
+                return None;
+            }
+        };
+        let mut tags = HashMap::new();
+        for (k, v) in service.meta.unwrap_or_default() {
+            tags.insert(Cow::Owned(k), Cow::Owned(v));
+        }
+        for tag in service.tags.unwrap_or_default() {
+            let (k, v) = match tag.split_once('=') {
+                Some((k, v)) => (k.to_string(), v.to_string()),
+                None => (tag, String::new()),
+            };
+            tags.insert(Cow::Owned(k), Cow::Owned(v));
+        }
+        Some(Instance {
+            address: Address::Ip((ip, service.port).into()),
+            weight: service.weights.map_or(1, |w| w.passing.max(1)),
+            tags,
+        })
+    }
+}
+
+#[derive(Clone)]
+struct Agent {
+    client: Client<HttpConnector>,
+    address: String,
+    token: Option<String>,
+    datacenter: Option<String>,
+    wait: Duration,
+}
+
+impl Agent {
+    /// Queries the passing instances of the service, blocking until the index of Consul changes
+    /// from `index` if it's not 0, returns the instances and the new index.
+    async fn health(
+        &self,
+        service: &str,
+        index: u64,
+    ) -> Result<(Vec<Arc<Instance>>, u64), ConsulError> {
+        let mut uri = format!(
+            "{}/v1/health/service/{}?passing=true",
+            self.address.trim_end_matches('/'),
+            service
+        );
+        if let Some(dc) = &self.datacenter {
+            uri.push_str("&dc=");
+            uri.push_str(dc);
+        }
+        if index > 0 {
+            uri.push_str(&format!("&index={}&wait={}s", index, self.wait.as_secs()));
+        }
+        let mut req = Request::get(uri);
+        if let Some(token) = &self.token {
+            req = req.header("X-Consul-Token", token);
+        }
+        let req = req.body(Body::empty()).map_err(ConsulError::Request)?;
+
+        let resp = self.client.request(req).await.map_err(ConsulError::Http)?;
+        if resp.status() != StatusCode::OK {
+            return Err(ConsulError::Status(resp.status()));
+        }
+        let index = resp
+            .headers()
+            .get("X-Consul-Index")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .map_err(ConsulError::Http)?;
+        let entries: Vec<ServiceEntry> =
+            serde_json::from_slice(&body).map_err(ConsulError::Decode)?;
+        let instances = entries
+            .into_iter()
+            .filter_map(ServiceEntry::into_instance)
+            .map(Arc::new)
+            .collect();
+        Ok((instances, index))
+    }
+}
+
+/// A [`Discover`] watching the passing instances of the services in Consul.
+#[derive(Clone)]
+pub struct ConsulDiscover {
+    agent: Agent,
+    watched: Arc<Watched>,
+}
+
+impl ConsulDiscover {
+    /// Creates a discover querying the Consul agent at `address`, like `http://127.0.0.1:8500`.
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            agent: Agent {
+                client: Client::new(),
+                address: address.into(),
+                token: None,
+                datacenter: None,
+                wait: Duration::from_secs(60),
+            },
+            watched: Arc::new(Watched::new()),
+        }
+    }
+
+    /// Sets the ACL token of the queries.
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.agent.token = Some(token.into());
+        self
+    }
+
+    /// Sets the datacenter of the services, defaults to the one of the agent.
+    pub fn datacenter(mut self, datacenter: impl Into<String>) -> Self {
+        self.agent.datacenter = Some(datacenter.into());
+        self
+    }
+
+    /// Sets the longest time for a blocking query to wait for the changes.
+    ///
+    /// Defaults to 60 seconds.
+    pub fn wait(mut self, wait: Duration) -> Self {
+        self.agent.wait = wait;
+        self
+    }
+}
+
+/// Watches the service until all the discovers are dropped.
+async fn watch(agent: Agent, watched: Weak<Watched>, service: SmolStr, mut index: u64) {
+    while watched.strong_count() > 0 {
+        let (next, next_index) = match agent.health(&service, index).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                tracing::warn!("[VOLO] failed to watch consul service {}: {}", service, err);
+                tokio::time::sleep(RETRY_INTERVAL).await;
+                continue;
+            }
+        };
+        let watched = match watched.upgrade() {
+            Some(watched) => watched,
+            None => break,
+        };
+        // the index may go backwards, such as when the cluster is restored
+        index = if next_index < index { 0 } else { next_index };
+        watched.update(&service, next);
+    }
+}
+
+impl Discover for ConsulDiscover {
+    type Key = SmolStr;
+    type Error = ConsulError;
+    type DiscFut<'a> = impl Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+    fn discover(&self, endpoint: &Endpoint) -> Self::DiscFut<'_> {
+        let service = endpoint.service_name.clone();
+        async move {
+            self.watched
+                .discover(
+                    service.clone(),
+                    || self.agent.health(&service, 0),
+                    |watched, index| {
+                        tokio::spawn(watch(self.agent.clone(), watched, service.clone(), index));
+                    },
+                )
+                .await
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        endpoint.service_name.clone()
+    }
+
+    fn watch(&self) -> Option<Receiver<Change<Self::Key>>> {
+        Some(self.watched.watch())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_instance() {
+        let entries: Vec<ServiceEntry> = serde_json::from_str(
+            r#"[{
+                "Node": {"Address": "10.0.0.1"},
+                "Service": {
+                    "Address": "",
+                    "Port": 8080,
+                    "Tags": ["canary", "zone=a"],
+                    "Meta": {"version": "v1"},
+                    "Weights": {"Passing": 10, "Warning": 1}
+                }
+            }]"#,
+        )
+        .unwrap();
+        let instance = entries.into_iter().next().unwrap().into_instance().unwrap();
+        assert_eq!(
+            instance.address,
+            Address::Ip("10.0.0.1:8080".parse().unwrap())
+        );
+        assert_eq!(instance.weight, 10);
+        assert_eq!(instance.tags["canary"], "");
+        assert_eq!(instance.tags["zone"], "a");
+        assert_eq!(instance.tags["version"], "v1");
+    }
+}
@@ -0,0 +1,366 @@
+//! [`Discover`] by the DNS records, such as the headless services on Kubernetes.
+//!
+//! The service name of the endpoint is resolved by:
+//!
+//! - SRV records if it starts with `_`, like `_grpc._tcp.my-svc.my-ns.svc.cluster.local`. The
+//!   targets are resolved to their A/AAAA records with the ports and the weights of the records.
+//! - A/AAAA records otherwise, like `my-svc.my-ns.svc.cluster.local:8080`, the port defaults to
+//!   [`DnsDiscover::default_port`].
+//!
+//! The discovered names are re-resolved every [`DnsDiscover::interval`] in background, and the
+//! changes are sent to the watchers. The names are also re-resolved at once when the [`DnsDialer`]
+//! fails to connect, or by [`DnsDiscover::refresh`], so that the clients follow the DNS failover
+//! without waiting for the interval.
+//!
+//! The names resolved to both IPv4 and IPv6 addresses can be dialed by the [`DnsDialer`], which
+//! falls back to the other addresses of the name by Happy Eyeballs when the picked one, such as
+//! of an unreachable family, doesn't connect in time.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use std::time::Duration;
+//!
+//! use volo::discovery::dns::DnsDiscover;
+//!
+//! let discover = DnsDiscover::new()?.interval(Duration::from_secs(10));
+//! let client = ItemServiceClientBuilder::new("my-svc.my-ns.svc.cluster.local:8080")
+//!     .discover(discover.clone())
+//!     .dialer(discover.dialer())
+//!     .build();
+//! ```
+
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+use async_broadcast::Receiver;
+use futures::{
+    future::{select, BoxFuture, Either, FutureExt},
+    pin_mut,
+};
+use smol_str::SmolStr;
+use tokio::sync::Notify;
+pub use trust_dns_resolver::error::ResolveError;
+use trust_dns_resolver::TokioAsyncResolver;
+
+use super::{diff_address, Change, Discover, Instance, Watched};
+use crate::{
+    context::Endpoint,
+    net::{
+        conn::Conn,
+        dial::{Dialer, MakeConnection},
+        happy_eyeballs, Address,
+    },
+};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+/// The min interval of the re-resolutions triggered by the failures, so that a down service
+/// doesn't flood the DNS servers.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The names resolved before and the watchers, shared with the background task.
+struct Shared {
+    watched: Watched,
+    started: AtomicBool,
+    // wakes the background task to re-resolve the names at once
+    refresh: Arc<Notify>,
+}
+
+#[derive(Clone)]
+struct Resolver {
+    resolver: TokioAsyncResolver,
+    default_port: u16,
+}
+
+impl Resolver {
+    async fn resolve(&self, name: &str) -> Result<Vec<Arc<Instance>>, ResolveError> {
+        if name.starts_with('_') {
+            return self.resolve_srv(name).await;
+        }
+        let (host, port) = split_host_port(name, self.default_port);
+        let ips = self.resolver.lookup_ip(host).await?;
+        Ok(ips
+            .iter()
+            .map(|ip| {
+                Arc::new(Instance {
+                    address: Address::Ip((ip, port).into()),
+                    weight: 1,
+                    tags: Default::default(),
+                })
+            })
+            .collect())
+    }
+
+    async fn resolve_srv(&self, name: &str) -> Result<Vec<Arc<Instance>>, ResolveError> {
+        let records = self.resolver.srv_lookup(name).await?;
+        let mut instances = Vec::new();
+        for srv in records.iter() {
+            let ips = self.resolver.lookup_ip(srv.target().to_utf8()).await?;
+            instances.extend(ips.iter().map(|ip| {
+                Arc::new(Instance {
+                    address: Address::Ip((ip, srv.port()).into()),
+                    weight: (srv.weight() as u32).max(1),
+                    tags: Default::default(),
+                })
+            }));
+        }
+        Ok(instances)
+    }
+}
+
+/// Splits `host[:port]`, where an IPv6 host with the port should be in brackets.
+fn split_host_port(name: &str, default_port: u16) -> (&str, u16) {
+    if let Some(rest) = name.strip_prefix('[') {
+        if let Some((host, port)) = rest.split_once(']') {
+            let port = port
+                .strip_prefix(':')
+                .and_then(|p| p.parse().ok())
+                .unwrap_or(default_port);
+            return (host, port);
+        }
+    }
+    match name.rsplit_once(':') {
+        // a bare IPv6 address has more than one colon
+        Some((host, port)) if !host.contains(':') => match port.parse() {
+            Ok(port) => (host, port),
+            Err(_) => (name, default_port),
+        },
+        _ => (name, default_port),
+    }
+}
+
+/// A [`Discover`] resolving the service names by DNS.
+#[derive(Clone)]
+pub struct DnsDiscover {
+    resolver: Resolver,
+    interval: Duration,
+    shared: Arc<Shared>,
+}
+
+impl DnsDiscover {
+    /// Creates a discover by the system resolver configuration, such as `/etc/resolv.conf`.
+    pub fn new() -> Result<Self, ResolveError> {
+        Ok(Self::with_resolver(
+            TokioAsyncResolver::tokio_from_system_conf()?,
+        ))
+    }
+
+    /// Creates a discover by the given resolver.
+    pub fn with_resolver(resolver: TokioAsyncResolver) -> Self {
+        Self {
+            resolver: Resolver {
+                resolver,
+                default_port: 80,
+            },
+            interval: DEFAULT_INTERVAL,
+            shared: Arc::new(Shared {
+                watched: Watched::new(),
+                started: AtomicBool::new(false),
+                refresh: Arc::new(Notify::new()),
+            }),
+        }
+    }
+
+    /// Sets the port of the A/AAAA records when the service name has none.
+    ///
+    /// Defaults to 80.
+    pub fn default_port(mut self, port: u16) -> Self {
+        self.resolver.default_port = port;
+        self
+    }
+
+    /// Sets the interval to re-resolve the discovered names.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Returns the dialer falling back to the other resolved addresses of the dialed one.
+    pub fn dialer(&self) -> DnsDialer {
+        DnsDialer {
+            shared: self.shared.clone(),
+            delay: happy_eyeballs::DEFAULT_DELAY,
+            refresh_on_failure: true,
+        }
+    }
+
+    /// Re-resolves the discovered names in background at once, such as when the calls to the
+    /// resolved addresses fail.
+    ///
+    /// The re-resolutions triggered are at least one second apart.
+    pub fn refresh(&self) {
+        self.shared.refresh.notify_one();
+    }
+
+    /// Spawns the task re-resolving the names, which exits when all the discovers are dropped.
+    fn start(&self) {
+        if self.shared.started.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let resolver = self.resolver.clone();
+        let shared = Arc::downgrade(&self.shared);
+        let notify = self.shared.refresh.clone();
+        let mut interval = tokio::time::interval(self.interval);
+        tokio::spawn(async move {
+            // the first tick completes immediately
+            interval.tick().await;
+            loop {
+                let triggered = {
+                    let (tick, notified) = (interval.tick(), notify.notified());
+                    pin_mut!(tick, notified);
+                    matches!(select(tick, notified).await, Either::Right(_))
+                };
+                if !refresh(&resolver, &shared).await {
+                    break;
+                }
+                if triggered {
+                    tokio::time::sleep(MIN_REFRESH_INTERVAL).await;
+                }
+            }
+        });
+    }
+}
+
+/// Re-resolves all the names, returns false if the discovers are dropped.
+async fn refresh(resolver: &Resolver, shared: &Weak<Shared>) -> bool {
+    let shared = match shared.upgrade() {
+        Some(shared) => shared,
+        None => return false,
+    };
+    let names: Vec<_> = shared
+        .watched
+        .instances()
+        .iter()
+        .map(|e| e.key().clone())
+        .collect();
+    for name in names {
+        let next = match resolver.resolve(&name).await {
+            Ok(next) => next,
+            Err(err) => {
+                tracing::warn!("[VOLO] failed to re-resolve {}: {}", name, err);
+                continue;
+            }
+        };
+        shared.watched.update_by(&name, next, |name, prev, next| {
+            diff_address(name, prev, next).0
+        });
+    }
+    true
+}
+
+/// A [`Dialer`] connecting to the addresses resolved by a [`DnsDiscover`] by Happy Eyeballs.
+///
+/// The dialed address is tried first, then the other addresses of the same name alternating by
+/// the families. The addresses not resolved by the discover are dialed as they are.
+#[derive(Clone)]
+pub struct DnsDialer {
+    shared: Arc<Shared>,
+    delay: Duration,
+    refresh_on_failure: bool,
+}
+
+impl DnsDialer {
+    /// Sets the delay before trying the next address.
+    ///
+    /// Defaults to 250ms.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Sets whether to re-resolve the names at once when failing to connect to the resolved
+    /// addresses, see [`DnsDiscover::refresh`].
+    ///
+    /// Defaults to true.
+    pub fn refresh_on_failure(mut self, enable: bool) -> Self {
+        self.refresh_on_failure = enable;
+        self
+    }
+
+    /// Returns the dialed address followed by the other addresses of its name.
+    fn candidates(&self, addr: SocketAddr) -> Vec<SocketAddr> {
+        let target = Address::Ip(addr);
+        let mut candidates = vec![addr];
+        for entry in self.shared.watched.instances().iter() {
+            if entry.value().iter().any(|i| i.address == target) {
+                candidates.extend(entry.value().iter().filter_map(|i| match i.address {
+                    Address::Ip(ip) if ip != addr => Some(ip),
+                    _ => None,
+                }));
+                break;
+            }
+        }
+        candidates
+    }
+}
+
+impl Dialer for DnsDialer {
+    fn dial(&self, addr: Address) -> BoxFuture<'static, io::Result<Conn>> {
+        let candidates = match addr {
+            Address::Ip(ip) => self.candidates(ip),
+            addr => return MakeConnection::default().dial(addr),
+        };
+        let delay = self.delay;
+        let refresh = self.refresh_on_failure.then(|| self.shared.refresh.clone());
+        async move {
+            match happy_eyeballs::connect(candidates, delay).await {
+                Ok(stream) => Ok(Conn::from(stream)),
+                Err(err) => {
+                    if let Some(refresh) = refresh {
+                        refresh.notify_one();
+                    }
+                    Err(err)
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+impl Discover for DnsDiscover {
+    type Key = SmolStr;
+    type Error = ResolveError;
+    type DiscFut<'a> = impl Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+    fn discover(&self, endpoint: &Endpoint) -> Self::DiscFut<'_> {
+        let name = endpoint.service_name.clone();
+        async move {
+            self.start();
+            let instances = self.resolver.resolve(&name).await?;
+            self.shared.watched.insert(name, instances.clone());
+            Ok(instances)
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        endpoint.service_name.clone()
+    }
+
+    fn watch(&self) -> Option<Receiver<Change<Self::Key>>> {
+        Some(self.shared.watched.watch())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_host_port;
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("svc.ns", 80), ("svc.ns", 80));
+        assert_eq!(split_host_port("svc.ns:8080", 80), ("svc.ns", 8080));
+        assert_eq!(split_host_port("::1", 80), ("::1", 80));
+        assert_eq!(split_host_port("[::1]:8080", 80), ("::1", 8080));
+        assert_eq!(split_host_port("[::1]", 80), ("::1", 80));
+    }
+}
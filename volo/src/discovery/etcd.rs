@@ -0,0 +1,279 @@
+//! [`Discover`] by the keys in etcd, registered by
+//! [`EtcdRegistry`](crate::registry::etcd::EtcdRegistry).
+//!
+//! The instances of a service are stored under `{prefix}/{service_name}/`, each key has a JSON
+//! value of the address, the weight and the tags of an instance. Each discovered service is
+//! watched in background, and the changes are sent to the watchers.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::discovery::etcd::EtcdDiscover;
+//!
+//! let client = etcd_client::Client::connect(["127.0.0.1:2379"], None).await?;
+//! let discover = EtcdDiscover::new(client);
+//! ```
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use async_broadcast::Receiver;
+pub use etcd_client::Error as EtcdError;
+use etcd_client::{Client, EventType, GetOptions, WatchOptions};
+use serde::{Deserialize, Serialize};
+use smol_str::SmolStr;
+
+use super::{Change, Discover, Instance, Watched};
+use crate::{context::Endpoint, net::Address, registry::RegistryInfo};
+
+pub(crate) const DEFAULT_PREFIX: &str = "volo/services";
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The value of the key of an instance.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Record {
+    address: String,
+    weight: u32,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+impl Record {
+    /// Returns the key of the instance.
+    pub(crate) fn key(prefix: &str, info: &RegistryInfo) -> String {
+        format!("{}/{}/{}", prefix, info.service_name, info.address)
+    }
+
+    fn into_instance(self) -> Option<Instance> {
+        let address = match self.address.parse::<SocketAddr>() {
+            Ok(addr) => Address::Ip(addr),
+            Err(_) if self.address.starts_with('/') => PathBuf::from(self.address).into(),
+            Err(_) => return None,
+        };
+        Some(Instance {
+            address,
+            weight: self.weight.max(1),
+            tags: self
+                .tags
+                .into_iter()
+                .map(|(k, v)| (Cow::Owned(k), Cow::Owned(v)))
+                .collect(),
+        })
+    }
+}
+
+impl From<&RegistryInfo> for Record {
+    fn from(info: &RegistryInfo) -> Self {
+        Self {
+            address: info.address.to_string(),
+            weight: info.weight,
+            tags: info
+                .tags
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+}
+
+fn decode(key: &str, value: &[u8]) -> Option<Arc<Instance>> {
+    match serde_json::from_slice::<Record>(value) {
+        Ok(record) => record.into_instance().map(Arc::new),
+        Err(err) => {
+            tracing::warn!("[VOLO] ignored invalid etcd instance {}: {}", key, err);
+            None
+        }
+    }
+}
+
+/// The instances by their keys under a service.
+type Instances = HashMap<String, Arc<Instance>>;
+
+/// Lists the instances under the prefix, returns them and the revision of etcd.
+async fn list(client: &mut Client, prefix: &str) -> Result<(Instances, i64), EtcdError> {
+    let resp = client
+        .get(prefix, Some(GetOptions::new().with_prefix()))
+        .await?;
+    let revision = resp.header().map_or(0, |h| h.revision());
+    let mut instances = HashMap::with_capacity(resp.kvs().len());
+    for kv in resp.kvs() {
+        let key = kv.key_str()?;
+        if let Some(instance) = decode(key, kv.value()) {
+            instances.insert(key.to_string(), instance);
+        }
+    }
+    Ok((instances, revision))
+}
+
+/// A [`Discover`] watching the instances registered in etcd.
+#[derive(Clone)]
+pub struct EtcdDiscover {
+    client: Client,
+    prefix: String,
+    watched: Arc<Watched>,
+}
+
+impl EtcdDiscover {
+    /// Creates a discover by the connected client.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            prefix: DEFAULT_PREFIX.to_string(),
+            watched: Arc::new(Watched::new()),
+        }
+    }
+
+    /// Sets the prefix of the keys, which should be the same as the one of the registry.
+    ///
+    /// Defaults to `volo/services`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn service_prefix(&self, service: &str) -> String {
+        format!("{}/{}/", self.prefix, service)
+    }
+}
+
+/// Returns the instances of all the keys.
+fn all(instances: &Instances) -> Vec<Arc<Instance>> {
+    instances.values().cloned().collect()
+}
+
+/// Watches the service until all the discovers are dropped, and lists the instances again when
+/// the watch is broken, such as when the revision is compacted.
+async fn watch(
+    mut client: Client,
+    prefix: String,
+    watched: Weak<Watched>,
+    service: SmolStr,
+    mut instances: Instances,
+    mut revision: i64,
+) {
+    loop {
+        let opts = WatchOptions::new()
+            .with_prefix()
+            .with_start_revision(revision + 1);
+        match client.watch(prefix.as_str(), Some(opts)).await {
+            Ok((_watcher, mut stream)) => loop {
+                let resp = match stream.message().await {
+                    Ok(Some(resp)) if !resp.canceled() => resp,
+                    Ok(_) => break,
+                    Err(err) => {
+                        tracing::warn!("[VOLO] etcd watch of {} broken: {}", service, err);
+                        break;
+                    }
+                };
+                for event in resp.events() {
+                    let kv = match event.kv() {
+                        Some(kv) => kv,
+                        None => continue,
+                    };
+                    let key = match kv.key_str() {
+                        Ok(key) => key,
+                        Err(_) => continue,
+                    };
+                    revision = revision.max(kv.mod_revision());
+                    match event.event_type() {
+                        EventType::Put => match decode(key, kv.value()) {
+                            Some(instance) => instances.insert(key.to_string(), instance),
+                            None => instances.remove(key),
+                        },
+                        EventType::Delete => instances.remove(key),
+                    };
+                }
+                match watched.upgrade() {
+                    Some(watched) => watched.update(&service, all(&instances)),
+                    None => return,
+                }
+            },
+            Err(err) => tracing::warn!("[VOLO] failed to watch etcd for {}: {}", service, err),
+        }
+
+        tokio::time::sleep(RETRY_INTERVAL).await;
+        if watched.strong_count() == 0 {
+            return;
+        }
+        match list(&mut client, &prefix).await {
+            Ok((next, next_revision)) => {
+                instances = next;
+                revision = next_revision;
+                if let Some(watched) = watched.upgrade() {
+                    watched.update(&service, all(&instances));
+                }
+            }
+            Err(err) => tracing::warn!("[VOLO] failed to list etcd for {}: {}", service, err),
+        }
+    }
+}
+
+impl Discover for EtcdDiscover {
+    type Key = SmolStr;
+    type Error = EtcdError;
+    type DiscFut<'a> = impl Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+    fn discover(&self, endpoint: &Endpoint) -> Self::DiscFut<'_> {
+        let service = endpoint.service_name.clone();
+        async move {
+            let prefix = self.service_prefix(&service);
+            let mut client = self.client.clone();
+            self.watched
+                .discover(
+                    service.clone(),
+                    || async {
+                        let (instances, revision) = list(&mut client, &prefix).await?;
+                        Ok((all(&instances), (instances, revision)))
+                    },
+                    |watched, (instances, revision)| {
+                        tokio::spawn(watch(
+                            self.client.clone(),
+                            prefix.clone(),
+                            watched,
+                            service.clone(),
+                            instances,
+                            revision,
+                        ));
+                    },
+                )
+                .await
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        endpoint.service_name.clone()
+    }
+
+    fn watch(&self) -> Option<Receiver<Change<Self::Key>>> {
+        Some(self.watched.watch())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut info = RegistryInfo::new("echo", "10.0.0.1:8080".parse::<SocketAddr>().unwrap());
+        info.weight = 10;
+        info.tags.insert("zone".into(), "a".into());
+        assert_eq!(
+            Record::key(DEFAULT_PREFIX, &info),
+            "volo/services/echo/10.0.0.1:8080"
+        );
+
+        let value = serde_json::to_vec(&Record::from(&info)).unwrap();
+        let instance = decode("", &value).unwrap();
+        assert_eq!(instance.address, info.address);
+        assert_eq!(instance.weight, 10);
+        assert_eq!(instance.tags, info.tags);
+    }
+}
@@ -3,6 +3,17 @@
 //! We encourage users to use these traits to implement their own service discovery and
 //! loadbalancer, so that we are able to reuse the same service discovery and loadbalancer
 //! implementation.
+#[cfg(feature = "consul")]
+pub mod consul;
+#[cfg(feature = "dns")]
+pub mod dns;
+#[cfg(feature = "etcd")]
+pub mod etcd;
+#[cfg(feature = "nacos")]
+pub mod nacos;
+pub mod profile;
+pub mod router;
+
 use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
@@ -14,6 +25,19 @@ use std::{
 };
 
 use async_broadcast::Receiver;
+#[cfg(any(
+    feature = "consul",
+    feature = "dns",
+    feature = "etcd",
+    feature = "nacos"
+))]
+use {
+    async_broadcast::{InactiveReceiver, Sender},
+    dashmap::DashMap,
+    smol_str::SmolStr,
+};
+#[cfg(any(feature = "consul", feature = "etcd", feature = "nacos"))]
+use {dashmap::mapref::entry::Entry, std::sync::Weak};
 
 use crate::{context::Endpoint, net::Address};
 
@@ -25,6 +49,39 @@ pub struct Instance {
     pub tags: HashMap<Cow<'static, str>, Cow<'static, str>>,
 }
 
+/// The well-known keys of the [`Instance::tags`], which describe where the instances are
+/// deployed, so that the [`router`]s and the load balancers can tell them apart.
+pub mod labels {
+    /// The availability zone, such as `us-east-1a`.
+    pub const ZONE: &str = "zone";
+    /// The cluster in the service, such as `default` or `canary`.
+    pub const CLUSTER: &str = "cluster";
+    /// The deployment environment, such as `prod` or `staging`.
+    pub const ENV: &str = "env";
+}
+
+impl Instance {
+    /// Returns the value of the tag `key`.
+    pub fn label(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(|v| v.as_ref())
+    }
+
+    /// Returns the [`labels::ZONE`] of the instance.
+    pub fn zone(&self) -> Option<&str> {
+        self.label(labels::ZONE)
+    }
+
+    /// Returns the [`labels::CLUSTER`] of the instance.
+    pub fn cluster(&self) -> Option<&str> {
+        self.label(labels::CLUSTER)
+    }
+
+    /// Returns the [`labels::ENV`] of the instance.
+    pub fn env(&self) -> Option<&str> {
+        self.label(labels::ENV)
+    }
+}
+
 /// [`Discover`] is the most basic trait for Discover.
 pub trait Discover: Send + Sync + 'static {
     /// `Key` identifies a group of instances, such as the cluster name.
@@ -45,9 +102,9 @@ pub trait Discover: Send + Sync + 'static {
 
 /// Change indicates the change of the service discover.
 ///
-/// Change contains the difference between the current discovery result and the previous one.
-/// It is designed for providing detail information when dispatching an event for service
-/// discovery result change.
+/// Change contains only the difference between the current discovery result and the previous
+/// one, instead of all the instances, so that the watchers apply it to the previous result in
+/// place, which keeps the changes of the services of many instances cheap.
 ///
 /// Since the loadbalancer may rely on caching the result of discover to improve performance,
 /// the discover implementation should dispatch an event when result changes.
@@ -56,12 +113,42 @@ pub struct Change<K> {
     /// `key` should be the same as the output of `WatchableDiscover::key`,
     /// which is often used by cache.
     pub key: K,
-    pub all: Vec<Arc<Instance>>,
     pub added: Vec<Arc<Instance>>,
+    /// The instances of the existing addresses whose weight or tags changed.
     pub updated: Vec<Arc<Instance>>,
     pub removed: Vec<Arc<Instance>>,
 }
 
+impl<K> Change<K> {
+    /// Returns whether nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+
+    /// Applies the change to the previous result of the discover.
+    ///
+    /// The instances are upserted by the address, as the result may be discovered after the
+    /// change was made, and already contain the instances added by it.
+    pub fn apply(&self, instances: &mut Vec<Arc<Instance>>) {
+        let removed: HashSet<_> = self.removed.iter().map(|i| &i.address).collect();
+        instances.retain(|i| !removed.contains(&i.address));
+        let mut index: HashMap<_, _> = instances
+            .iter()
+            .enumerate()
+            .map(|(offset, i)| (i.address.clone(), offset))
+            .collect();
+        for instance in self.updated.iter().chain(&self.added) {
+            match index.get(&instance.address) {
+                Some(&offset) => instances[offset] = instance.clone(),
+                None => {
+                    index.insert(instance.address.clone(), instances.len());
+                    instances.push(instance.clone());
+                }
+            }
+        }
+    }
+}
+
 /// [`diff_address`] provides a naive implementation that compares prev and next only by the
 /// address, and returns the [`Change`], which means that the `updated` is always empty when using
 /// this implementation.
@@ -70,31 +157,40 @@ pub struct Change<K> {
 /// that if the bool is false, the [`Change`] should be ignored, and the discover should not send
 /// the event to loadbalancer.
 ///
-/// If users need to compare the instances by also weight or tags, they should not use this.
+/// If users need to compare the instances by also weight or tags, they should use
+/// [`diff_instances`].
 pub fn diff_address<K>(
     key: K,
     prev: Vec<Arc<Instance>>,
     next: Vec<Arc<Instance>>,
 ) -> (Change<K>, bool)
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    let mut change = diff_instances(key, prev, next);
+    change.updated.clear();
+    let changed = !change.is_empty();
+    (change, changed)
+}
+
+/// [`diff_instances`] compares prev and next by the address, and returns the [`Change`] whose
+/// `updated` contains the instances of the same address but different weight or tags.
+pub fn diff_instances<K>(key: K, prev: Vec<Arc<Instance>>, next: Vec<Arc<Instance>>) -> Change<K>
 where
     K: Hash + PartialEq + Eq + Send + Sync + 'static,
 {
     let mut added = Vec::new();
-    let updated = Vec::new();
+    let mut updated = Vec::new();
     let mut removed = Vec::new();
 
-    let mut prev_set = HashSet::with_capacity(prev.len());
-    let mut next_set = HashSet::with_capacity(next.len());
-    for i in &prev {
-        prev_set.insert(i.address.clone());
-    }
-    for i in &next {
-        next_set.insert(i.address.clone());
-    }
+    let prev_map: HashMap<_, _> = prev.iter().map(|i| (&i.address, i)).collect();
+    let next_set: HashSet<_> = next.iter().map(|i| &i.address).collect();
 
     for i in &next {
-        if !prev_set.contains(&i.address) {
-            added.push(i.clone());
+        match prev_map.get(&i.address) {
+            None => added.push(i.clone()),
+            Some(prev) if *prev != i => updated.push(i.clone()),
+            Some(_) => {}
         }
     }
     for i in &prev {
@@ -103,18 +199,110 @@ where
         }
     }
 
-    let changed = !added.is_empty() || !removed.is_empty();
+    Change {
+        key,
+        added,
+        updated,
+        removed,
+    }
+}
 
-    (
-        Change {
-            key,
-            all: next,
-            added,
-            updated,
-            removed,
-        },
-        changed,
-    )
+/// The instances of the discovered services and the watchers of their changes, shared by the
+/// clones of a discover and the background tasks watching the services.
+#[cfg(any(
+    feature = "consul",
+    feature = "dns",
+    feature = "etcd",
+    feature = "nacos"
+))]
+pub(crate) struct Watched {
+    instances: DashMap<SmolStr, Vec<Arc<Instance>>>,
+    sender: Sender<Change<SmolStr>>,
+    receiver: InactiveReceiver<Change<SmolStr>>,
+}
+
+#[cfg(any(
+    feature = "consul",
+    feature = "dns",
+    feature = "etcd",
+    feature = "nacos"
+))]
+impl Watched {
+    pub(crate) fn new() -> Self {
+        let (mut sender, receiver) = async_broadcast::broadcast(32);
+        // the slow watchers miss the old changes instead of blocking the others
+        sender.set_overflow(true);
+        Self {
+            instances: Default::default(),
+            sender,
+            receiver: receiver.deactivate(),
+        }
+    }
+
+    /// Returns the cached instances of the service, or the ones fetched by `fetch` along with
+    /// the state to watch them from, in which case `watch` is called with the state to spawn the
+    /// single watcher of the service, which should exit when the [`Weak`] is gone.
+    #[cfg(any(feature = "consul", feature = "etcd", feature = "nacos"))]
+    pub(crate) async fn discover<T, E, Fut>(
+        self: &Arc<Self>,
+        service: SmolStr,
+        fetch: impl FnOnce() -> Fut,
+        watch: impl FnOnce(Weak<Self>, T),
+    ) -> Result<Vec<Arc<Instance>>, E>
+    where
+        Fut: Future<Output = Result<(Vec<Arc<Instance>>, T), E>>,
+    {
+        if let Some(instances) = self.instances.get(&service) {
+            return Ok(instances.clone());
+        }
+        let (instances, state) = fetch().await?;
+        if let Entry::Vacant(e) = self.instances.entry(service) {
+            e.insert(instances.clone());
+            watch(Arc::downgrade(self), state);
+        }
+        Ok(instances)
+    }
+
+    /// Caches the instances of the service.
+    #[cfg(feature = "dns")]
+    pub(crate) fn insert(&self, service: SmolStr, instances: Vec<Arc<Instance>>) {
+        self.instances.insert(service, instances);
+    }
+
+    /// Replaces the cached instances of the service, and sends the difference by
+    /// [`diff_instances`] to the watchers.
+    #[cfg(any(feature = "consul", feature = "etcd", feature = "nacos"))]
+    pub(crate) fn update(&self, service: &SmolStr, next: Vec<Arc<Instance>>) {
+        self.update_by(service, next, diff_instances)
+    }
+
+    /// Replaces the cached instances of the service, and sends the difference by `diff` to the
+    /// watchers.
+    ///
+    /// The changes of the services not cached before, such as removed, are not sent.
+    pub(crate) fn update_by(
+        &self,
+        service: &SmolStr,
+        next: Vec<Arc<Instance>>,
+        diff: impl FnOnce(SmolStr, Vec<Arc<Instance>>, Vec<Arc<Instance>>) -> Change<SmolStr>,
+    ) {
+        if let Some(prev) = self.instances.insert(service.clone(), next.clone()) {
+            let change = diff(service.clone(), prev, next);
+            if !change.is_empty() {
+                // fails only when nobody is watching
+                let _ = self.sender.try_broadcast(change);
+            }
+        }
+    }
+
+    #[cfg(feature = "dns")]
+    pub(crate) fn instances(&self) -> &DashMap<SmolStr, Vec<Arc<Instance>>> {
+        &self.instances
+    }
+
+    pub(crate) fn watch(&self) -> Receiver<Change<SmolStr>> {
+        self.receiver.activate_cloned()
+    }
 }
 
 /// [`StaticDiscover`] is a simple implementation of [`Discover`] that returns a static list of
@@ -147,6 +335,22 @@ impl From<Vec<SocketAddr>> for StaticDiscover {
     }
 }
 
+impl From<Vec<Address>> for StaticDiscover {
+    fn from(addrs: Vec<Address>) -> Self {
+        let instances = addrs
+            .into_iter()
+            .map(|address| {
+                Arc::new(Instance {
+                    address,
+                    weight: 1,
+                    tags: Default::default(),
+                })
+            })
+            .collect();
+        Self { instances }
+    }
+}
+
 impl Discover for StaticDiscover {
     type Key = ();
     type Error = Infallible;
@@ -187,9 +391,9 @@ impl Discover for DummyDiscover {
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{net::SocketAddr, sync::Arc};
 
-    use super::{Discover, Instance, StaticDiscover};
+    use super::{diff_instances, Discover, Instance, StaticDiscover};
     use crate::{context::Endpoint, net::Address};
 
     #[test]
@@ -200,7 +404,7 @@ mod tests {
             tags: Default::default(),
         };
         let discover = StaticDiscover::from(vec![
-            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.1:8000".parse::<SocketAddr>().unwrap(),
             "127.0.0.2:9000".parse().unwrap(),
         ]);
         let resp = futures::executor::block_on(async { discover.discover(&empty).await }).unwrap();
@@ -218,4 +422,47 @@ mod tests {
         ];
         assert!(resp == expected);
     }
+
+    #[test]
+    fn test_static_discover_unix() {
+        let empty = Endpoint::new("".into());
+        let discover = StaticDiscover::from(vec![
+            "unix:///run/item.sock".parse::<Address>().unwrap(),
+            "127.0.0.1:8000".parse().unwrap(),
+        ]);
+        let resp = futures::executor::block_on(async { discover.discover(&empty).await }).unwrap();
+        let addresses: Vec<_> = resp.iter().map(|i| i.address.clone()).collect();
+        assert_eq!(
+            addresses,
+            [
+                Address::Unix(std::path::Path::new("/run/item.sock").into()),
+                Address::Ip("127.0.0.1:8000".parse().unwrap()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let instance = |port: u16, weight: u32| {
+            Arc::new(Instance {
+                address: Address::Ip(([127, 0, 0, 1], port).into()),
+                weight,
+                tags: Default::default(),
+            })
+        };
+        let change = diff_instances(
+            (),
+            vec![instance(1, 1), instance(2, 1)],
+            vec![instance(2, 5), instance(3, 1)],
+        );
+
+        let mut instances = vec![instance(1, 1), instance(2, 1)];
+        change.apply(&mut instances);
+        assert_eq!(instances, [instance(2, 5), instance(3, 1)]);
+
+        // discovered again before the change arrives, the added instance is not doubled
+        let mut instances = vec![instance(2, 5), instance(3, 1)];
+        change.apply(&mut instances);
+        assert_eq!(instances, [instance(2, 5), instance(3, 1)]);
+    }
 }
@@ -0,0 +1,376 @@
+//! [`Discover`] by the naming service of Nacos.
+//!
+//! The service name of the endpoint is the name of the Nacos service in
+//! [`NacosDiscover::group`] of [`NacosDiscover::namespace`], and only the healthy and enabled
+//! instances of positive weights are discovered. Nacos has no blocking queries in its open API, so
+//! each discovered service is polled every [`NacosDiscover::interval`] in background, and the
+//! changes are sent to the watchers.
+//!
+//! The instances are weighted by the weights of Nacos rounded, and tagged by their metadata, with
+//! the cluster of the instance as [`labels::CLUSTER`](super::labels::CLUSTER).
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::discovery::nacos::NacosDiscover;
+//!
+//! let discover = NacosDiscover::new("http://127.0.0.1:8848/nacos")
+//!     .namespace("prod")
+//!     .auth("volo", "password");
+//! ```
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    future::Future,
+    net::IpAddr,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use async_broadcast::Receiver;
+use hyper::{client::HttpConnector, Body, Client, Request, StatusCode};
+use serde::Deserialize;
+use smol_str::SmolStr;
+
+use super::{labels, Change, Discover, Instance, Watched};
+use crate::{context::Endpoint, net::Address};
+
+const DEFAULT_GROUP: &str = "DEFAULT_GROUP";
+
+/// The error of querying Nacos.
+#[derive(Debug)]
+pub enum NacosError {
+    Request(hyper::http::Error),
+    Http(hyper::Error),
+    Status(StatusCode),
+    Decode(serde_json::Error),
+}
+
+impl fmt::Display for NacosError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NacosError::Request(e) => write!(f, "invalid nacos request: {}", e),
+            NacosError::Http(e) => write!(f, "nacos request failed: {}", e),
+            NacosError::Status(s) => write!(f, "nacos responded {}", s),
+            NacosError::Decode(e) => write!(f, "invalid nacos response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for NacosError {}
+
+#[derive(Deserialize)]
+struct ServiceInfo {
+    #[serde(default)]
+    hosts: Vec<Host>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Host {
+    ip: String,
+    port: u16,
+    #[serde(default = "default_weight")]
+    weight: f64,
+    #[serde(default = "default_true")]
+    healthy: bool,
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(default)]
+    cluster_name: Option<String>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Host {
+    fn into_instance(self) -> Option<Instance> {
+        if !self.healthy || !self.enabled || self.weight <= 0.0 {
+            return None;
+        }
+        let ip = match self.ip.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => {
+                tracing::warn!("[VOLO] ignored nacos instance of address {}", self.ip);
+                return None;
+            }
+        };
+        let mut tags: HashMap<_, _> = self
+            .metadata
+            .into_iter()
+            .map(|(k, v)| (Cow::Owned(k), Cow::Owned(v)))
+            .collect();
+        if let Some(cluster) = self.cluster_name {
+            tags.entry(Cow::Borrowed(labels::CLUSTER))
+                .or_insert(Cow::Owned(cluster));
+        }
+        Some(Instance {
+            address: Address::Ip((ip, self.port).into()),
+            weight: (self.weight.round() as u32).max(1),
+            tags,
+        })
+    }
+}
+
+/// Encodes the value of an `application/x-www-form-urlencoded` body.
+fn form_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Login {
+    access_token: String,
+    token_ttl: u64,
+}
+
+#[derive(Clone)]
+struct Naming {
+    client: Client<HttpConnector>,
+    address: String,
+    namespace: Option<String>,
+    group: String,
+    credentials: Option<(String, String)>,
+    /// The access token logged in by the credentials, and when it expires.
+    token: Arc<Mutex<Option<(String, Instant)>>>,
+}
+
+impl Naming {
+    async fn send(&self, req: Request<Body>) -> Result<hyper::body::Bytes, NacosError> {
+        let resp = self.client.request(req).await.map_err(NacosError::Http)?;
+        if resp.status() != StatusCode::OK {
+            return Err(NacosError::Status(resp.status()));
+        }
+        hyper::body::to_bytes(resp.into_body())
+            .await
+            .map_err(NacosError::Http)
+    }
+
+    /// Returns the access token, logging in again when it's about to expire.
+    async fn access_token(&self) -> Result<Option<String>, NacosError> {
+        let (username, password) = match &self.credentials {
+            Some(credentials) => credentials,
+            None => return Ok(None),
+        };
+        let cached = self.token.lock().unwrap().clone();
+        if let Some((token, expires)) = cached {
+            if Instant::now() < expires {
+                return Ok(Some(token));
+            }
+        }
+        let req = Request::post(format!(
+            "{}/v1/auth/login",
+            self.address.trim_end_matches('/')
+        ))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(Body::from(format!(
+            "username={}&password={}",
+            form_encode(username),
+            form_encode(password)
+        )))
+        .map_err(NacosError::Request)?;
+        let body = self.send(req).await?;
+        let login: Login = serde_json::from_slice(&body).map_err(NacosError::Decode)?;
+        // refreshes the token before it expires, such as at 90% of the ttl
+        let expires = Instant::now() + Duration::from_secs(login.token_ttl * 9 / 10);
+        *self.token.lock().unwrap() = Some((login.access_token.clone(), expires));
+        Ok(Some(login.access_token))
+    }
+
+    /// Queries the available instances of the service.
+    async fn instances(&self, service: &str) -> Result<Vec<Arc<Instance>>, NacosError> {
+        let mut uri = format!(
+            "{}/v1/ns/instance/list?serviceName={}&groupName={}&healthyOnly=true",
+            self.address.trim_end_matches('/'),
+            service,
+            self.group
+        );
+        if let Some(namespace) = &self.namespace {
+            uri.push_str("&namespaceId=");
+            uri.push_str(namespace);
+        }
+        if let Some(token) = self.access_token().await? {
+            uri.push_str("&accessToken=");
+            uri.push_str(&token);
+        }
+        let req = Request::get(uri)
+            .body(Body::empty())
+            .map_err(NacosError::Request)?;
+        let body = self.send(req).await?;
+        let info: ServiceInfo = serde_json::from_slice(&body).map_err(NacosError::Decode)?;
+        Ok(info
+            .hosts
+            .into_iter()
+            .filter_map(Host::into_instance)
+            .map(Arc::new)
+            .collect())
+    }
+}
+
+/// A [`Discover`] polling the available instances of the services in Nacos.
+#[derive(Clone)]
+pub struct NacosDiscover {
+    naming: Naming,
+    interval: Duration,
+    watched: Arc<Watched>,
+}
+
+impl NacosDiscover {
+    /// Creates a discover querying the Nacos server at `address` with its context path, like
+    /// `http://127.0.0.1:8848/nacos`.
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            naming: Naming {
+                client: Client::new(),
+                address: address.into(),
+                namespace: None,
+                group: DEFAULT_GROUP.into(),
+                credentials: None,
+                token: Default::default(),
+            },
+            interval: Duration::from_secs(10),
+            watched: Arc::new(Watched::new()),
+        }
+    }
+
+    /// Sets the namespace id of the services, defaults to the public namespace.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.naming.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Sets the group of the services.
+    ///
+    /// Defaults to `DEFAULT_GROUP`.
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.naming.group = group.into();
+        self
+    }
+
+    /// Sets the username and the password to log in by, when the auth of Nacos is enabled.
+    pub fn auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.naming.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Sets the interval of polling the discovered services.
+    ///
+    /// Defaults to 10 seconds.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+/// Polls the service until all the discovers are dropped.
+async fn watch(naming: Naming, interval: Duration, watched: Weak<Watched>, service: SmolStr) {
+    while watched.strong_count() > 0 {
+        tokio::time::sleep(interval).await;
+        let next = match naming.instances(&service).await {
+            Ok(next) => next,
+            Err(err) => {
+                tracing::warn!("[VOLO] failed to watch nacos service {}: {}", service, err);
+                continue;
+            }
+        };
+        match watched.upgrade() {
+            Some(watched) => watched.update(&service, next),
+            None => break,
+        }
+    }
+}
+
+impl Discover for NacosDiscover {
+    type Key = SmolStr;
+    type Error = NacosError;
+    type DiscFut<'a> = impl Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+    fn discover(&self, endpoint: &Endpoint) -> Self::DiscFut<'_> {
+        let service = endpoint.service_name.clone();
+        async move {
+            self.watched
+                .discover(
+                    service.clone(),
+                    || async { Ok((self.naming.instances(&service).await?, ())) },
+                    |watched, ()| {
+                        tokio::spawn(watch(
+                            self.naming.clone(),
+                            self.interval,
+                            watched,
+                            service.clone(),
+                        ));
+                    },
+                )
+                .await
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        endpoint.service_name.clone()
+    }
+
+    fn watch(&self) -> Option<Receiver<Change<Self::Key>>> {
+        Some(self.watched.watch())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_instance() {
+        let info: ServiceInfo = serde_json::from_str(
+            r#"{
+                "name": "DEFAULT_GROUP@@item",
+                "hosts": [
+                    {
+                        "ip": "10.0.0.1",
+                        "port": 8080,
+                        "weight": 2.0,
+                        "healthy": true,
+                        "enabled": true,
+                        "clusterName": "DEFAULT",
+                        "metadata": {"zone": "a"}
+                    },
+                    {"ip": "10.0.0.2", "port": 8080, "weight": 1.0, "enabled": false},
+                    {"ip": "10.0.0.3", "port": 8080, "weight": 0.0}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let instances: Vec<_> = info
+            .hosts
+            .into_iter()
+            .filter_map(Host::into_instance)
+            .collect();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(
+            instances[0].address,
+            Address::Ip("10.0.0.1:8080".parse().unwrap())
+        );
+        assert_eq!(instances[0].weight, 2);
+        assert_eq!(instances[0].zone(), Some("a"));
+        assert_eq!(instances[0].cluster(), Some("DEFAULT"));
+
+        assert_eq!(form_encode("p@ss&w=rd"), "p%40ss%26w%3Drd");
+    }
+}
@@ -0,0 +1,211 @@
+//! The instances of the [`Profile::cluster`] of the callee, such as reloaded by the
+//! [`FileWatcher`], so that the calls switch to another cluster of the callee, such as `canary`,
+//! without rebuilding the client.
+//!
+//! The cluster is a part of the key of the discover, so the load balancers cache the instances of
+//! each cluster apart, and pick the ones of the new cluster right after the switch. The callees
+//! without it are called on all their instances.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::{discovery::profile::ProfileDiscover, profile::FileWatcher};
+//!
+//! let profiles = FileWatcher::new("profiles.json").watch()?;
+//! let client = ItemServiceClientBuilder::new("item")
+//!     .discover(ProfileDiscover::new(discover, profiles))
+//!     .build();
+//! ```
+//!
+//! [`Profile::cluster`]: crate::profile::Profile::cluster
+//! [`FileWatcher`]: crate::profile::FileWatcher
+
+use std::{collections::HashSet, future::Future, sync::Arc};
+
+use async_broadcast::{Receiver, RecvError};
+use dashmap::DashMap;
+use tokio::sync::watch;
+
+use super::{diff_instances, Change, Discover, Instance};
+use crate::{context::Endpoint, profile::Profiles};
+
+/// The instances of the inner discover of a key, and the clusters of it discovered.
+struct Discovered {
+    instances: Vec<Arc<Instance>>,
+    clusters: HashSet<Option<String>>,
+}
+
+fn in_cluster(instances: &[Arc<Instance>], cluster: &Option<String>) -> Vec<Arc<Instance>> {
+    match cluster {
+        Some(cluster) => instances
+            .iter()
+            .filter(|i| i.cluster() == Some(cluster.as_str()))
+            .cloned()
+            .collect(),
+        None => instances.to_vec(),
+    }
+}
+
+/// A [`Discover`] returning the instances of the inner discover in the cluster of the profile of
+/// the callee.
+///
+/// The changes watched are sent for every cluster discovered, narrowed to the instances of it.
+pub struct ProfileDiscover<D: Discover> {
+    inner: D,
+    profiles: watch::Receiver<Arc<Profiles>>,
+    discovered: Arc<DashMap<D::Key, Discovered>>,
+}
+
+impl<D: Discover + Clone> Clone for ProfileDiscover<D> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            profiles: self.profiles.clone(),
+            discovered: self.discovered.clone(),
+        }
+    }
+}
+
+impl<D: Discover> ProfileDiscover<D> {
+    /// Creates a new [`ProfileDiscover`].
+    pub fn new(inner: D, profiles: watch::Receiver<Arc<Profiles>>) -> Self {
+        Self {
+            inner,
+            profiles,
+            discovered: Arc::new(DashMap::new()),
+        }
+    }
+}
+
+impl<D: Discover> Discover for ProfileDiscover<D> {
+    type Key = (D::Key, Option<String>);
+    type Error = D::Error;
+    type DiscFut<'a> = impl Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+    fn discover(&self, endpoint: &Endpoint) -> Self::DiscFut<'_> {
+        let (key, cluster) = self.key(endpoint);
+        let discover = self.inner.discover(endpoint);
+        async move {
+            let instances = discover.await?;
+            let routed = in_cluster(&instances, &cluster);
+            let mut discovered = self.discovered.entry(key).or_insert_with(|| Discovered {
+                instances: Vec::new(),
+                clusters: HashSet::new(),
+            });
+            discovered.instances = instances;
+            discovered.clusters.insert(cluster);
+            Ok(routed)
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        let cluster = self.profiles.borrow().get(&endpoint.service_name).cluster;
+        (self.inner.key(endpoint), cluster)
+    }
+
+    fn watch(&self) -> Option<Receiver<Change<Self::Key>>> {
+        let mut inner = self.inner.watch()?;
+        let (mut sender, receiver) = async_broadcast::broadcast(32);
+        // the slow watchers miss the old changes instead of blocking the others
+        sender.set_overflow(true);
+        let discovered = self.discovered.clone();
+        tokio::spawn(async move {
+            loop {
+                let change = match inner.recv().await {
+                    Ok(change) => change,
+                    // the instances are discovered again by the watchers missing the changes
+                    Err(RecvError::Overflowed(_)) => {
+                        discovered.clear();
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                // the instances not discovered yet are not cached by the watchers either
+                let routed: Vec<_> = match discovered.get_mut(&change.key) {
+                    Some(mut discovered) => {
+                        let prev = discovered.instances.clone();
+                        change.apply(&mut discovered.instances);
+                        discovered
+                            .clusters
+                            .iter()
+                            .map(|cluster| {
+                                diff_instances(
+                                    (change.key.clone(), cluster.clone()),
+                                    in_cluster(&prev, cluster),
+                                    in_cluster(&discovered.instances, cluster),
+                                )
+                            })
+                            .filter(|change| !change.is_empty())
+                            .collect()
+                    }
+                    None => continue,
+                };
+                for change in routed {
+                    // all the watchers are gone
+                    if sender.broadcast(change).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Some(receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        discovery::{labels, StaticDiscover},
+        net::Address,
+        profile::Profile,
+    };
+
+    fn instance(port: u16, cluster: &'static str) -> Arc<Instance> {
+        Arc::new(Instance {
+            address: Address::Ip(([127, 0, 0, 1], port).into()),
+            weight: 1,
+            tags: [(labels::CLUSTER.into(), cluster.into())]
+                .into_iter()
+                .collect(),
+        })
+    }
+
+    fn cluster(cluster: Option<&str>) -> Arc<Profiles> {
+        let mut profiles = Profiles::default();
+        profiles.callees.insert(
+            "item".to_string(),
+            Profile {
+                cluster: cluster.map(Into::into),
+                ..Default::default()
+            },
+        );
+        Arc::new(profiles)
+    }
+
+    #[tokio::test]
+    async fn test_switch() {
+        let (sender, receiver) = watch::channel(cluster(None));
+        let discover = ProfileDiscover::new(
+            StaticDiscover::new(vec![
+                instance(1, "default"),
+                instance(2, "canary"),
+                instance(3, "default"),
+            ]),
+            receiver,
+        );
+        let endpoint = Endpoint::new("item".into());
+
+        assert_eq!(discover.key(&endpoint), ((), None));
+        assert_eq!(discover.discover(&endpoint).await.unwrap().len(), 3);
+
+        sender.send(cluster(Some("canary"))).unwrap();
+        assert_eq!(discover.key(&endpoint), ((), Some("canary".to_string())));
+        let instances = discover.discover(&endpoint).await.unwrap();
+        assert_eq!(instances, [instance(2, "canary")]);
+
+        // the profiles of the other callees are kept
+        let endpoint = Endpoint::new("report".into());
+        assert_eq!(discover.key(&endpoint), ((), None));
+    }
+}
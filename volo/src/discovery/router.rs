@@ -0,0 +1,254 @@
+//! Narrows the instances of the service discovery before the load balancing, such as to keep the
+//! calls in the local zone or to a cluster of the callee.
+//!
+//! A [`Router`] picks the candidates from the discovered instances by their [`labels`], and
+//! [`RouteDiscover`] applies it to a [`Discover`], so that it works with any load balancer and
+//! with both the thrift and the gRPC clients. The routers are composed as tuples, applied from the
+//! first one.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::discovery::{
+//!     labels,
+//!     router::{LabelRouter, RouteDiscover},
+//! };
+//!
+//! let router = LabelRouter::new()
+//!     .require(labels::ENV, "prod")
+//!     .prefer(labels::ZONE, "us-east-1a");
+//! let client = ItemServiceClientBuilder::new("item")
+//!     .discover(RouteDiscover::new(discover, router))
+//!     .build();
+//! ```
+//!
+//! [`labels`]: super::labels
+
+use std::{borrow::Cow, future::Future, sync::Arc};
+
+use async_broadcast::{Receiver, RecvError};
+use dashmap::DashMap;
+
+use super::{diff_instances, Change, Discover, Instance};
+use crate::context::Endpoint;
+
+/// Picks the candidates of the load balancing from the discovered instances.
+pub trait Router: Send + Sync + 'static {
+    /// Returns the instances to balance the calls among.
+    fn route(&self, instances: Vec<Arc<Instance>>) -> Vec<Arc<Instance>>;
+}
+
+impl<F> Router for F
+where
+    F: Fn(Vec<Arc<Instance>>) -> Vec<Arc<Instance>> + Send + Sync + 'static,
+{
+    fn route(&self, instances: Vec<Arc<Instance>>) -> Vec<Arc<Instance>> {
+        self(instances)
+    }
+}
+
+impl<A: Router, B: Router> Router for (A, B) {
+    fn route(&self, instances: Vec<Arc<Instance>>) -> Vec<Arc<Instance>> {
+        self.1.route(self.0.route(instances))
+    }
+}
+
+type Label = (Cow<'static, str>, Cow<'static, str>);
+
+/// A [`Router`] by the labels of the instances.
+///
+/// The required labels filter out the instances not having them, even if no instance is left. The
+/// preferred labels narrow the instances only if some of them have the labels, one by one in the
+/// order they are added, so that the calls fall back to the other instances, such as the other
+/// zones, instead of failing.
+#[derive(Debug, Default, Clone)]
+pub struct LabelRouter {
+    required: Vec<Label>,
+    preferred: Vec<Label>,
+}
+
+impl LabelRouter {
+    /// Creates a router keeping all the instances.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps only the instances of the label.
+    pub fn require(
+        mut self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.required.push((key.into(), value.into()));
+        self
+    }
+
+    /// Prefers the instances of the label, if there are any.
+    pub fn prefer(
+        mut self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.preferred.push((key.into(), value.into()));
+        self
+    }
+}
+
+fn has_label(instance: &Instance, (key, value): &Label) -> bool {
+    instance.label(key) == Some(value.as_ref())
+}
+
+impl Router for LabelRouter {
+    fn route(&self, mut instances: Vec<Arc<Instance>>) -> Vec<Arc<Instance>> {
+        instances.retain(|i| self.required.iter().all(|label| has_label(i, label)));
+        for label in &self.preferred {
+            if instances.iter().any(|i| has_label(i, label)) {
+                instances.retain(|i| has_label(i, label));
+            }
+        }
+        instances
+    }
+}
+
+/// A [`Discover`] returning the instances of the inner discover narrowed by the router.
+///
+/// The changes watched are narrowed too, the instances leaving or entering the candidates are
+/// sent as removed or added, such as when the preferred zone has no instance left.
+pub struct RouteDiscover<D: Discover, R> {
+    inner: D,
+    router: Arc<R>,
+    // the instances of the inner discover, to which the changes are applied
+    instances: Arc<DashMap<D::Key, Vec<Arc<Instance>>>>,
+}
+
+impl<D: Discover + Clone, R> Clone for RouteDiscover<D, R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            router: self.router.clone(),
+            instances: self.instances.clone(),
+        }
+    }
+}
+
+impl<D: Discover, R> RouteDiscover<D, R> {
+    /// Creates a new [`RouteDiscover`].
+    pub fn new(inner: D, router: R) -> Self {
+        Self {
+            inner,
+            router: Arc::new(router),
+            instances: Arc::new(DashMap::new()),
+        }
+    }
+}
+
+impl<D: Discover, R: Router> Discover for RouteDiscover<D, R> {
+    type Key = D::Key;
+    type Error = D::Error;
+    type DiscFut<'a> = impl Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+    fn discover(&self, endpoint: &Endpoint) -> Self::DiscFut<'_> {
+        let key = self.inner.key(endpoint);
+        let discover = self.inner.discover(endpoint);
+        async move {
+            let instances = discover.await?;
+            self.instances.insert(key, instances.clone());
+            Ok(self.router.route(instances))
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        self.inner.key(endpoint)
+    }
+
+    fn watch(&self) -> Option<Receiver<Change<Self::Key>>> {
+        let mut inner = self.inner.watch()?;
+        let (mut sender, receiver) = async_broadcast::broadcast(32);
+        // the slow watchers miss the old changes instead of blocking the others
+        sender.set_overflow(true);
+        let (router, instances) = (self.router.clone(), self.instances.clone());
+        tokio::spawn(async move {
+            loop {
+                let change = match inner.recv().await {
+                    Ok(change) => change,
+                    // the instances are discovered again by the watchers missing the changes
+                    Err(RecvError::Overflowed(_)) => {
+                        instances.clear();
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                // the instances not discovered yet are not cached by the watchers either
+                let (prev, next) = match instances.get_mut(&change.key) {
+                    Some(mut all) => {
+                        let prev = router.route(all.clone());
+                        change.apply(&mut all);
+                        (prev, router.route(all.clone()))
+                    }
+                    None => continue,
+                };
+                let routed = diff_instances(change.key, prev, next);
+                if routed.is_empty() {
+                    continue;
+                }
+                // all the watchers are gone
+                if sender.broadcast(routed).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Some(receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{discovery::labels, net::Address};
+
+    fn instance(port: u16, zone: &'static str, env: &'static str) -> Arc<Instance> {
+        Arc::new(Instance {
+            address: Address::Ip(([127, 0, 0, 1], port).into()),
+            weight: 1,
+            tags: [
+                (labels::ZONE.into(), zone.into()),
+                (labels::ENV.into(), env.into()),
+            ]
+            .into_iter()
+            .collect(),
+        })
+    }
+
+    fn ports(instances: &[Arc<Instance>]) -> Vec<u16> {
+        instances
+            .iter()
+            .map(|i| match &i.address {
+                Address::Ip(addr) => addr.port(),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_label_router() {
+        let router = LabelRouter::new()
+            .require(labels::ENV, "prod")
+            .prefer(labels::ZONE, "a");
+        let instances = vec![
+            instance(1, "a", "prod"),
+            instance(2, "b", "prod"),
+            instance(3, "a", "test"),
+        ];
+        assert_eq!(ports(&router.route(instances.clone())), vec![1]);
+
+        // falls back to the other zones
+        let instances = instances[1..].to_vec();
+        assert_eq!(ports(&router.route(instances.clone())), vec![2]);
+
+        let router = (router, |mut instances: Vec<Arc<Instance>>| {
+            instances.clear();
+            instances
+        });
+        assert!(router.route(instances).is_empty());
+    }
+}
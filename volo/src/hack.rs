@@ -4,10 +4,10 @@ pub trait Unwrap<T> {
 
 impl<T> Unwrap<T> for Option<T> {
     fn volo_unwrap(self) -> T {
-        #[cfg(not(feature = "unsafe_unchecked"))]
+        #[cfg(any(not(feature = "unsafe_unchecked"), feature = "forbid-unsafe"))]
         return self.unwrap();
 
-        #[cfg(feature = "unsafe_unchecked")]
+        #[cfg(all(feature = "unsafe_unchecked", not(feature = "forbid-unsafe")))]
         unsafe {
             self.unwrap_unchecked()
         }
@@ -16,10 +16,10 @@ impl<T> Unwrap<T> for Option<T> {
 
 impl<T, E: std::fmt::Debug> Unwrap<T> for Result<T, E> {
     fn volo_unwrap(self) -> T {
-        #[cfg(not(feature = "unsafe_unchecked"))]
+        #[cfg(any(not(feature = "unsafe_unchecked"), feature = "forbid-unsafe"))]
         return self.unwrap();
 
-        #[cfg(feature = "unsafe_unchecked")]
+        #[cfg(all(feature = "unsafe_unchecked", not(feature = "forbid-unsafe")))]
         unsafe {
             self.unwrap_unchecked()
         }
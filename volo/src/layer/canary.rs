@@ -0,0 +1,216 @@
+//! Routes the requests between the stable and the canary client stacks by the rules over the
+//! metadata and a percentage rollout, for the clients of any protocol.
+//!
+//! The metadata of a request is read by the lookup function, since each protocol carries it
+//! differently, such as the metainfo of thrift or the headers of gRPC. The [`CanaryRules`] are
+//! shared by a [`CanaryHandle`], which updates them at runtime, such as from a config center, to
+//! roll the canary forward or back without rebuilding the client.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::layer::canary::{CanaryLayer, CanaryRules};
+//!
+//! let canary = ItemServiceClientBuilder::new("item-canary").build();
+//! let layer = CanaryLayer::new(canary, |_cx: &ClientContext, _req: &_, key: &str| {
+//!     metainfo::METAINFO.with(|m| m.borrow().get_transient(key).map(|v| v.to_string()))
+//! })
+//! .rules(CanaryRules {
+//!     matches: vec![("x-env".into(), "canary".into())],
+//!     percent: 5.0,
+//!     sticky_key: Some("x-user-id".into()),
+//! });
+//! let handle = layer.handle();
+//! // later, rolls forward
+//! handle.update(|rules| rules.percent = 50.0);
+//! ```
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::{Arc, RwLock},
+};
+
+use futures::Future;
+use motore::{layer::Layer, service::Service};
+use rand::Rng;
+
+/// The rules to send the requests to the canary.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CanaryRules {
+    /// The requests with all these metadata are always sent to the canary, no request is
+    /// matched if empty.
+    pub matches: Vec<(String, String)>,
+    /// The percentage of the other requests sent to the canary, between 0 and 100.
+    pub percent: f64,
+    /// The metadata hashed for the percentage, so that the requests with the same value, such
+    /// as of the same user, are sent to the same side. The requests are picked randomly if
+    /// `None` or the metadata is missing.
+    pub sticky_key: Option<String>,
+}
+
+impl CanaryRules {
+    fn to_canary<F>(&self, lookup: F) -> bool
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if !self.matches.is_empty()
+            && self
+                .matches
+                .iter()
+                .all(|(k, v)| lookup(k).as_deref() == Some(v.as_str()))
+        {
+            return true;
+        }
+        if self.percent <= 0.0 {
+            return false;
+        }
+        if self.percent >= 100.0 {
+            return true;
+        }
+        let bucket = match self.sticky_key.as_deref().and_then(lookup) {
+            Some(value) => {
+                let mut hasher = DefaultHasher::new();
+                value.hash(&mut hasher);
+                (hasher.finish() % 10000) as f64 / 100.0
+            }
+            None => rand::thread_rng().gen::<f64>() * 100.0,
+        };
+        bucket < self.percent
+    }
+}
+
+/// The handle to read and update the rules of a layer at runtime.
+#[derive(Debug, Clone, Default)]
+pub struct CanaryHandle(Arc<RwLock<CanaryRules>>);
+
+impl CanaryHandle {
+    /// Returns the current rules.
+    pub fn rules(&self) -> CanaryRules {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Updates the rules, which apply to the following requests.
+    pub fn update(&self, f: impl FnOnce(&mut CanaryRules)) {
+        f(&mut self.0.write().unwrap());
+    }
+}
+
+/// A [`Service`] that sends the requests to the canary or the stable service by the rules.
+#[derive(Clone)]
+pub struct CanaryService<S, C, F> {
+    stable: S,
+    canary: C,
+    lookup: F,
+    handle: CanaryHandle,
+}
+
+impl<Cx, Req, S, C, F> Service<Cx, Req> for CanaryService<S, C, F>
+where
+    Cx: Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+    C: Service<Cx, Req, Response = S::Response, Error = S::Error> + Send + 'static,
+    F: Fn(&Cx, &Req, &str) -> Option<String> + Send + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        let to_canary = self
+            .handle
+            .0
+            .read()
+            .unwrap()
+            .to_canary(|key| (self.lookup)(cx, &req, key));
+        async move {
+            if to_canary {
+                self.canary.call(cx, req).await
+            } else {
+                self.stable.call(cx, req).await
+            }
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`CanaryService`], with the inner service as the stable one.
+#[derive(Clone)]
+pub struct CanaryLayer<C, F> {
+    canary: C,
+    lookup: F,
+    handle: CanaryHandle,
+}
+
+impl<C, F> CanaryLayer<C, F> {
+    /// Creates a layer sending the requests to `canary` by the metadata read by `lookup`, which
+    /// sends no request to the canary until the rules are set.
+    pub fn new(canary: C, lookup: F) -> Self {
+        Self {
+            canary,
+            lookup,
+            handle: CanaryHandle::default(),
+        }
+    }
+
+    /// Sets the initial rules.
+    pub fn rules(self, rules: CanaryRules) -> Self {
+        self.handle.update(|r| *r = rules);
+        self
+    }
+
+    /// Returns the handle to update the rules at runtime.
+    pub fn handle(&self) -> CanaryHandle {
+        self.handle.clone()
+    }
+}
+
+impl<S, C, F> Layer<S> for CanaryLayer<C, F> {
+    type Service = CanaryService<S, C, F>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        CanaryService {
+            stable: inner,
+            canary: self.canary,
+            lookup: self.lookup,
+            handle: self.handle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_canary() {
+        let lookup = |key: &str| match key {
+            "x-env" => Some("canary".to_string()),
+            "x-user-id" => Some("42".to_string()),
+            _ => None,
+        };
+        let mut rules = CanaryRules::default();
+        assert!(!rules.to_canary(lookup));
+
+        rules.matches = vec![("x-env".into(), "canary".into())];
+        assert!(rules.to_canary(lookup));
+        rules.matches.push(("x-zone".into(), "a".into()));
+        assert!(!rules.to_canary(lookup));
+
+        rules.percent = 100.0;
+        assert!(rules.to_canary(lookup));
+
+        // the same user is always on the same side
+        rules.percent = 50.0;
+        rules.sticky_key = Some("x-user-id".into());
+        let first = rules.to_canary(lookup);
+        for _ in 0..10 {
+            assert_eq!(rules.to_canary(lookup), first);
+        }
+    }
+}
@@ -0,0 +1,673 @@
+//! Fails fast the requests to the keys failing too much, for the services of any protocol.
+//!
+//! Each key, such as an endpoint or a method, has its own breaker. The breaker opens when the
+//! failure rate in a rolling window, or the number of consecutive failures, reaches the
+//! threshold. While open, the requests fail fast by [`BreakerOpen`] converted into the error of
+//! the inner service. After a cool down, a few probe requests are let through (half-open), and
+//! the breaker closes again if they succeed.
+//!
+//! Which errors are failures is decided by the [`Classify`] of the layer, all the errors by
+//! default, and a request dropped before finishing, such as by a timeout outside, is a failure
+//! too, so are the successful requests slower than [`CircuitBreakerConfig::slow_call`] if set.
+//! The state changes can be watched by [`CircuitBreakerLayer::on_state_change`] for alerting.
+//!
+//! The breakers of the endpoints can evict the instances from the load balance by
+//! [`CircuitBreakerLayer::evictions`], so the requests go to the other instances rather than fail
+//! fast while a breaker is open, and the instance is picked again for the probes after the cool
+//! down. The layer must be inside the load balance to see the endpoints, such as by the
+//! `layer_inner` of the thrift clients, with the layer of `volo_thrift` failing fast by the
+//! transport errors of thrift.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::{
+//!     layer::circuit_breaker::{BreakerKey, CircuitBreakerConfig, CircuitBreakerLayer},
+//!     loadbalance::Evictions,
+//! };
+//!
+//! let layer = CircuitBreakerLayer::new(CircuitBreakerConfig::new().key_by(BreakerKey::Method))
+//!     .classify(|err: &Status| err.code() == Code::Unavailable)
+//!     .on_state_change(|key, from, to| tracing::warn!("breaker of {} {:?} -> {:?}", key, from, to));
+//!
+//! // the breakers of the endpoints evicting the broken instances of a thrift client
+//! let evictions = Evictions::default();
+//! ItemServiceClientBuilder::new("item")
+//!     .evictions(evictions.clone())
+//!     .layer_inner(
+//!         volo_thrift::client::layer::circuit_breaker::CircuitBreakerLayer::default()
+//!             .evictions(evictions),
+//!     )
+//!     .build();
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::Future;
+use motore::{layer::Layer, service::Service};
+use tokio::time::Instant;
+
+use crate::{
+    context::{Context, Role},
+    loadbalance::Evictions,
+    net::Address,
+};
+
+/// What the breakers are keyed by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerKey {
+    /// The address of the peer, the callee for the clients and the caller for the servers.
+    Endpoint,
+    /// The method of the request.
+    Method,
+    /// Both the address of the peer and the method.
+    EndpointMethod,
+}
+
+/// The config of [`CircuitBreakerLayer`].
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreakerConfig {
+    key: BreakerKey,
+    consecutive_failures: u32,
+    failure_rate: f64,
+    min_requests: u32,
+    window: Duration,
+    buckets: u32,
+    cool_down: Duration,
+    half_open_probes: u32,
+    slow_call: Option<Duration>,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            key: BreakerKey::Endpoint,
+            consecutive_failures: 10,
+            failure_rate: 0.5,
+            min_requests: 200,
+            window: Duration::from_secs(10),
+            buckets: 10,
+            cool_down: Duration::from_secs(5),
+            half_open_probes: 1,
+            slow_call: None,
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets what the breakers are keyed by.
+    ///
+    /// Defaults to [`BreakerKey::Endpoint`].
+    pub fn key_by(mut self, key: BreakerKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Opens the breaker after this many consecutive failures.
+    ///
+    /// Defaults to 10.
+    pub fn consecutive_failures(mut self, n: u32) -> Self {
+        self.consecutive_failures = n.max(1);
+        self
+    }
+
+    /// Opens the breaker when the failure rate in the window reaches `rate`, counted only when
+    /// there are at least `min_requests` requests in the window.
+    ///
+    /// Defaults to 50% of at least 200 requests.
+    pub fn failure_rate(mut self, rate: f64, min_requests: u32) -> Self {
+        self.failure_rate = rate;
+        self.min_requests = min_requests.max(1);
+        self
+    }
+
+    /// Sets the length of the rolling window and the number of buckets it is divided into, the
+    /// oldest bucket is dropped when a new one starts.
+    ///
+    /// Defaults to 10 seconds of 10 buckets.
+    pub fn window(mut self, window: Duration, buckets: u32) -> Self {
+        self.window = window;
+        self.buckets = buckets.max(1);
+        self
+    }
+
+    /// Sets how long the breaker stays open before probing.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn cool_down(mut self, cool_down: Duration) -> Self {
+        self.cool_down = cool_down;
+        self
+    }
+
+    /// Sets how many probe requests can be in flight while half-open.
+    ///
+    /// Defaults to 1.
+    pub fn half_open_probes(mut self, n: u32) -> Self {
+        self.half_open_probes = n.max(1);
+        self
+    }
+
+    /// Counts the successful requests taking at least `threshold` as failures, so a breaker also
+    /// opens when its requests are too slow.
+    ///
+    /// Defaults to none.
+    pub fn slow_call(mut self, threshold: Duration) -> Self {
+        self.slow_call = Some(threshold);
+        self
+    }
+
+    fn bucket_len(&self) -> Duration {
+        (self.window / self.buckets).max(Duration::from_millis(1))
+    }
+}
+
+/// The state of a circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Requests are sent as usual.
+    Closed,
+    /// Requests fail fast.
+    Open,
+    /// A limited number of probe requests are sent.
+    HalfOpen,
+}
+
+/// The error of the requests failed fast by an open breaker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakerOpen {
+    key: String,
+}
+
+impl BreakerOpen {
+    /// Returns the key of the open breaker.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+impl fmt::Display for BreakerOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "circuit breaker is open for {}", self.key)
+    }
+}
+
+impl std::error::Error for BreakerOpen {}
+
+/// Decides whether an error is a failure counted by the breakers.
+pub trait Classify<E> {
+    fn is_failure(&self, err: &E) -> bool;
+}
+
+/// Counts all the errors as failures.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnyError;
+
+impl<E> Classify<E> for AnyError {
+    fn is_failure(&self, _err: &E) -> bool {
+        true
+    }
+}
+
+impl<E, F> Classify<E> for F
+where
+    F: Fn(&E) -> bool,
+{
+    fn is_failure(&self, err: &E) -> bool {
+        self(err)
+    }
+}
+
+type OnStateChange = dyn Fn(&str, State, State) + Send + Sync;
+
+#[derive(Debug)]
+struct Breaker {
+    state: State,
+    opened_at: Instant,
+    probes: u32,
+    consecutive_failures: u32,
+    // the (requests, failures) of the buckets, `buckets[current]` starts at `current_start`
+    buckets: Vec<(u32, u32)>,
+    current: usize,
+    current_start: Instant,
+}
+
+impl Breaker {
+    fn new(config: &CircuitBreakerConfig, now: Instant) -> Self {
+        Self {
+            state: State::Closed,
+            opened_at: now,
+            probes: 0,
+            consecutive_failures: 0,
+            buckets: vec![(0, 0); config.buckets as usize],
+            current: 0,
+            current_start: now,
+        }
+    }
+
+    fn try_acquire(&mut self, config: &CircuitBreakerConfig, now: Instant) -> bool {
+        match self.state {
+            State::Closed => true,
+            State::Open => {
+                if now.saturating_duration_since(self.opened_at) < config.cool_down {
+                    return false;
+                }
+                self.state = State::HalfOpen;
+                self.probes = 1;
+                true
+            }
+            State::HalfOpen => {
+                if self.probes >= config.half_open_probes {
+                    return false;
+                }
+                self.probes += 1;
+                true
+            }
+        }
+    }
+
+    fn record(&mut self, config: &CircuitBreakerConfig, success: bool, now: Instant) {
+        match self.state {
+            State::HalfOpen => {
+                if success {
+                    *self = Self::new(config, now);
+                } else {
+                    self.open(now);
+                }
+            }
+            // the request was let through before the breaker opened
+            State::Open => {}
+            State::Closed => {
+                self.roll(config, now);
+                let bucket = &mut self.buckets[self.current];
+                bucket.0 += 1;
+                if success {
+                    self.consecutive_failures = 0;
+                    return;
+                }
+                bucket.1 += 1;
+                self.consecutive_failures += 1;
+                let (requests, failures) = self
+                    .buckets
+                    .iter()
+                    .fold((0, 0), |(r, f), b| (r + b.0, f + b.1));
+                if self.consecutive_failures >= config.consecutive_failures
+                    || (requests >= config.min_requests
+                        && failures as f64 >= requests as f64 * config.failure_rate)
+                {
+                    self.open(now);
+                }
+            }
+        }
+    }
+
+    /// Drops the buckets out of the window.
+    fn roll(&mut self, config: &CircuitBreakerConfig, now: Instant) {
+        let bucket_len = config.bucket_len();
+        let elapsed = now.saturating_duration_since(self.current_start);
+        let passed = (elapsed.as_nanos() / bucket_len.as_nanos()) as usize;
+        if passed == 0 {
+            return;
+        }
+        for _ in 0..passed.min(self.buckets.len()) {
+            self.current = (self.current + 1) % self.buckets.len();
+            self.buckets[self.current] = (0, 0);
+        }
+        self.current_start += bucket_len * passed as u32;
+    }
+
+    fn open(&mut self, now: Instant) {
+        self.state = State::Open;
+        self.opened_at = now;
+        self.probes = 0;
+    }
+}
+
+struct Breakers {
+    config: CircuitBreakerConfig,
+    breakers: Mutex<HashMap<String, Breaker>>,
+    on_state_change: Mutex<Option<Arc<OnStateChange>>>,
+    evictions: Mutex<Option<Evictions>>,
+}
+
+impl Breakers {
+    fn try_acquire(&self, key: &str) -> bool {
+        let now = Instant::now();
+        let (acquired, from, to) = {
+            let mut breakers = self.breakers.lock().unwrap();
+            let breaker = breakers.get_mut(key);
+            match breaker {
+                Some(breaker) => {
+                    let from = breaker.state;
+                    let acquired = breaker.try_acquire(&self.config, now);
+                    (acquired, from, breaker.state)
+                }
+                // no breaker means no failure yet
+                None => return true,
+            }
+        };
+        self.notify(key, from, to);
+        acquired
+    }
+
+    /// Records the result of a request, evicting or restoring the instance of `address` if the
+    /// breaker opens or closes.
+    fn record(&self, key: String, success: bool, address: Option<&Address>) {
+        let now = Instant::now();
+        let (from, to) = {
+            let mut breakers = self.breakers.lock().unwrap();
+            if success && !breakers.contains_key(&key) {
+                return;
+            }
+            let breaker = breakers
+                .entry(key.clone())
+                .or_insert_with(|| Breaker::new(&self.config, now));
+            let from = breaker.state;
+            breaker.record(&self.config, success, now);
+            (from, breaker.state)
+        };
+        if let (Some(address), true) = (address, from != to) {
+            self.evict(address, to, now);
+        }
+        self.notify(&key, from, to);
+    }
+
+    fn evict(&self, address: &Address, state: State, now: Instant) {
+        let evictions = self.evictions.lock().unwrap().clone();
+        if let Some(evictions) = evictions {
+            match state {
+                State::Open => evictions.evict(address.clone(), now + self.config.cool_down),
+                State::Closed => evictions.restore(address),
+                State::HalfOpen => {}
+            }
+        }
+    }
+
+    // called without holding the breakers, so that the callback can get the states
+    fn notify(&self, key: &str, from: State, to: State) {
+        if from == to {
+            return;
+        }
+        let f = self.on_state_change.lock().unwrap().clone();
+        if let Some(f) = f {
+            f(key, from, to);
+        }
+    }
+
+    fn state(&self, key: &str) -> State {
+        self.breakers
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|b| b.state)
+            .unwrap_or(State::Closed)
+    }
+}
+
+/// Records the result of a request, a request dropped before finishing counts as a failure.
+struct Recorder {
+    breakers: Arc<Breakers>,
+    key: Option<String>,
+    // the instance of the breaker keyed by the endpoint
+    address: Option<Address>,
+}
+
+impl Recorder {
+    fn record(mut self, success: bool) {
+        if let Some(key) = self.key.take() {
+            self.breakers.record(key, success, self.address.as_ref());
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.breakers.record(key, false, self.address.as_ref());
+        }
+    }
+}
+
+/// A [`Service`] that fails fast when the breaker of the request is open.
+#[derive(Clone)]
+pub struct CircuitBreaker<S, C> {
+    inner: S,
+    classify: C,
+    breakers: Arc<Breakers>,
+}
+
+impl<S, C> CircuitBreaker<S, C> {
+    /// Returns the key of the breaker of the request, with the address of the instance evicted
+    /// by the breaker of the endpoint of a client.
+    fn key<Cx: Context>(&self, cx: &Cx) -> Option<(String, Option<Address>)> {
+        let info = cx.rpc_info();
+        let peer = match info.role() {
+            Role::Client => info.callee.as_ref(),
+            Role::Server => info.caller.as_ref(),
+        };
+        let address = peer.and_then(|p| p.address.as_ref());
+        let method = info.method.as_deref();
+        match self.breakers.config.key {
+            BreakerKey::Endpoint => address.map(|a| {
+                let evicted = (info.role() == Role::Client).then(|| a.clone());
+                (a.to_string(), evicted)
+            }),
+            BreakerKey::Method => method.map(|m| (m.to_string(), None)),
+            BreakerKey::EndpointMethod => match (address, method) {
+                (Some(a), Some(m)) => Some((format!("{}#{}", a, m), None)),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl<Cx, Req, S, C> Service<Cx, Req> for CircuitBreaker<S, C>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+    S::Error: From<BreakerOpen>,
+    C: Classify<S::Error> + Send + Sync + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let key = self.key(cx);
+            let (key, address) = match key {
+                Some(key) => key,
+                None => return self.inner.call(cx, req).await,
+            };
+            if !self.breakers.try_acquire(&key) {
+                tracing::debug!("[VOLO] circuit breaker is open for {}", key);
+                return Err(BreakerOpen { key }.into());
+            }
+
+            let recorder = Recorder {
+                breakers: self.breakers.clone(),
+                key: Some(key),
+                address,
+            };
+            let start = Instant::now();
+            let resp = self.inner.call(cx, req).await;
+            let slow = matches!(self.breakers.config.slow_call, Some(t) if start.elapsed() >= t);
+            recorder.record(!slow && !matches!(&resp, Err(e) if self.classify.is_failure(e)));
+            resp
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`CircuitBreaker`].
+///
+/// All the services made by the same layer share the breakers.
+#[derive(Clone)]
+pub struct CircuitBreakerLayer<C = AnyError> {
+    classify: C,
+    breakers: Arc<Breakers>,
+}
+
+impl CircuitBreakerLayer {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            classify: AnyError,
+            breakers: Arc::new(Breakers {
+                config,
+                breakers: Mutex::new(HashMap::new()),
+                on_state_change: Mutex::new(None),
+                evictions: Mutex::new(None),
+            }),
+        }
+    }
+}
+
+impl<C> CircuitBreakerLayer<C> {
+    /// Sets which errors are failures.
+    ///
+    /// Defaults to all the errors.
+    pub fn classify<C2>(self, classify: C2) -> CircuitBreakerLayer<C2> {
+        CircuitBreakerLayer {
+            classify,
+            breakers: self.breakers,
+        }
+    }
+
+    /// Calls `f` with the key, the old state and the new state when a breaker changes its state.
+    ///
+    /// The callback is shared by the clones of the layer.
+    pub fn on_state_change(self, f: impl Fn(&str, State, State) + Send + Sync + 'static) -> Self {
+        *self.breakers.on_state_change.lock().unwrap() = Some(Arc::new(f));
+        self
+    }
+
+    /// Evicts the instances from the load balance by `evictions` while their breakers are open,
+    /// which only applies to the breakers of the endpoints of the clients.
+    ///
+    /// The evictions are shared by the clones of the layer.
+    pub fn evictions(self, evictions: Evictions) -> Self {
+        *self.breakers.evictions.lock().unwrap() = Some(evictions);
+        self
+    }
+
+    /// Returns the state of the breaker of the key, such as `127.0.0.1:8080` for the endpoints.
+    pub fn state(&self, key: &str) -> State {
+        self.breakers.state(key)
+    }
+}
+
+impl Default for CircuitBreakerLayer {
+    fn default() -> Self {
+        Self::new(CircuitBreakerConfig::default())
+    }
+}
+
+impl<S, C> Layer<S> for CircuitBreakerLayer<C> {
+    type Service = CircuitBreaker<S, C>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        CircuitBreaker {
+            inner,
+            classify: self.classify,
+            breakers: self.breakers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn test_rolling_window() {
+        let config = CircuitBreakerConfig::new()
+            .consecutive_failures(100)
+            .failure_rate(0.5, 4)
+            .window(Duration::from_secs(4), 4);
+        let now = Instant::now();
+        let mut breaker = Breaker::new(&config, now);
+
+        breaker.record(&config, false, now);
+        breaker.record(&config, false, now);
+        // the failures above are out of the window
+        let now = now + Duration::from_secs(4);
+        breaker.record(&config, true, now);
+        breaker.record(&config, true, now);
+        breaker.record(&config, false, now);
+        assert_eq!(breaker.state, State::Closed);
+
+        // the failure above is still in the window
+        let now = now + Duration::from_secs(2);
+        breaker.record(&config, false, now);
+        assert_eq!(breaker.state, State::Open);
+    }
+
+    #[test]
+    fn test_state_change() {
+        let changes = Arc::new(AtomicUsize::new(0));
+        let layer = CircuitBreakerLayer::new(
+            CircuitBreakerConfig::new()
+                .consecutive_failures(1)
+                .cool_down(Duration::ZERO),
+        )
+        .on_state_change({
+            let changes = changes.clone();
+            move |key, _, _| {
+                assert_eq!(key, "a");
+                changes.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        let breakers = &layer.breakers;
+
+        breakers.record("a".to_string(), false, None);
+        assert_eq!(layer.state("a"), State::Open);
+        assert!(breakers.try_acquire("a"));
+        assert_eq!(layer.state("a"), State::HalfOpen);
+        breakers.record("a".to_string(), true, None);
+        assert_eq!(layer.state("a"), State::Closed);
+        assert_eq!(changes.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_evictions() {
+        let evictions = Evictions::default();
+        let layer = CircuitBreakerLayer::new(
+            CircuitBreakerConfig::new()
+                .consecutive_failures(1)
+                .cool_down(Duration::from_secs(10)),
+        )
+        .evictions(evictions.clone());
+        let breakers = &layer.breakers;
+        let address = Address::Ip(([127, 0, 0, 1], 8080).into());
+
+        breakers.record(address.to_string(), false, Some(&address));
+        assert!(evictions.is_evicted(&address));
+
+        // the probe after the cool down succeeds
+        breakers
+            .breakers
+            .lock()
+            .unwrap()
+            .get_mut("127.0.0.1:8080")
+            .unwrap()
+            .state = State::HalfOpen;
+        breakers.record(address.to_string(), true, Some(&address));
+        assert_eq!(layer.state("127.0.0.1:8080"), State::Closed);
+        assert!(!evictions.is_evicted(&address));
+    }
+}
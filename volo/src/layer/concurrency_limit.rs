@@ -0,0 +1,323 @@
+//! Limits the requests in flight, for the clients and the servers of any protocol.
+//!
+//! The requests over the limit fail by [`ConcurrencyLimited`] converted into the error of the
+//! inner service, or wait in a bounded queue for a while if [`ConcurrencyLimitLayer::queue`] is
+//! set. The numbers of the requests in flight and queued can be read from the [`Gauges`] for
+//! the metrics. The gRPC servers respond `RESOURCE_EXHAUSTED` to the rejected requests, and the
+//! thrift servers an `InternalError` application exception.
+//!
+//! The low [`Priority`] requests, read from the context extensions, never wait in the queue, and
+//! can be kept from the last permits by [`ConcurrencyLimitLayer::reserve`], so that they are
+//! rejected first when overloaded.
+//!
+//! A method can have its own limit by [`ConcurrencyLimitLayer::method`], keyed by the method of the
+//! rpc info, which is the path for gRPC. Its requests must be within both its limit and the one of
+//! the layer, so a slow method can't take up all the permits.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use std::time::Duration;
+//!
+//! use volo::layer::ConcurrencyLimitLayer;
+//!
+//! let layer = ConcurrencyLimitLayer::new(1000)
+//!     .queue(100, Duration::from_millis(50))
+//!     .reserve(100)
+//!     .method("/volo.example.ItemService/ListItems", 100);
+//! let gauges = layer.gauges();
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::Future;
+use motore::{layer::Layer, service::Service};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::context::{Context, Priority};
+
+/// The error of the requests rejected by the concurrency limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConcurrencyLimited(());
+
+impl fmt::Display for ConcurrencyLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("too many requests in flight")
+    }
+}
+
+impl std::error::Error for ConcurrencyLimited {}
+
+/// The numbers of the requests in flight and queued, shared by all the services of a layer.
+#[derive(Debug, Clone, Default)]
+pub struct Gauges {
+    in_flight: Arc<AtomicUsize>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl Gauges {
+    /// Returns the number of the requests in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of the requests waiting in the queue.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+}
+
+/// Decrements the gauge when dropped.
+struct Counted<'a>(&'a AtomicUsize);
+
+impl<'a> Counted<'a> {
+    fn new(gauge: &'a AtomicUsize) -> Self {
+        gauge.fetch_add(1, Ordering::Relaxed);
+        Self(gauge)
+    }
+}
+
+impl Drop for Counted<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Queue {
+    max: usize,
+    timeout: Duration,
+}
+
+struct Limit {
+    semaphore: Arc<Semaphore>,
+    methods: HashMap<String, Arc<Semaphore>>,
+    queue: Option<Queue>,
+    reserved: usize,
+    gauges: Gauges,
+}
+
+/// The permits of a request, of its method limit if any and of the layer.
+type Permits = (Option<OwnedSemaphorePermit>, OwnedSemaphorePermit);
+
+impl Limit {
+    async fn acquire(&self, method: Option<&str>, priority: Priority) -> Option<Permits> {
+        let method = match method.and_then(|m| self.methods.get(m)) {
+            Some(semaphore) => Some(self.acquire_from(semaphore, priority).await?),
+            None => None,
+        };
+        Some((method, self.acquire_from(&self.semaphore, priority).await?))
+    }
+
+    async fn acquire_from(
+        &self,
+        semaphore: &Arc<Semaphore>,
+        priority: Priority,
+    ) -> Option<OwnedSemaphorePermit> {
+        if priority == Priority::Low {
+            if semaphore.available_permits() <= self.reserved {
+                return None;
+            }
+            return semaphore.clone().try_acquire_owned().ok();
+        }
+        if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+        let queue = self.queue?;
+        let _queued = Counted::new(&self.gauges.queued);
+        if self.gauges.queued() > queue.max {
+            return None;
+        }
+        tokio::time::timeout(queue.timeout, semaphore.clone().acquire_owned())
+            .await
+            .ok()?
+            .ok()
+    }
+}
+
+/// A [`Service`] that limits the requests in flight.
+#[derive(Clone)]
+pub struct ConcurrencyLimit<S> {
+    inner: S,
+    limit: Arc<Limit>,
+}
+
+impl<Cx, Req, S> Service<Cx, Req> for ConcurrencyLimit<S>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+    S::Error: From<ConcurrencyLimited>,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let limit = self.limit.clone();
+            let priority = cx
+                .extensions()
+                .get::<Priority>()
+                .copied()
+                .unwrap_or_default();
+            let method = cx.rpc_info().method.clone();
+            let _permits = match limit.acquire(method.as_deref(), priority).await {
+                Some(permit) => permit,
+                None => {
+                    tracing::debug!("[VOLO] too many requests in flight");
+                    return Err(ConcurrencyLimited(()).into());
+                }
+            };
+            let _in_flight = Counted::new(&limit.gauges.in_flight);
+            self.inner.call(cx, req).await
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`ConcurrencyLimit`].
+///
+/// All the services made by the same layer share the limit.
+#[derive(Clone)]
+pub struct ConcurrencyLimitLayer {
+    semaphore: Arc<Semaphore>,
+    methods: HashMap<String, Arc<Semaphore>>,
+    queue: Option<Queue>,
+    reserved: usize,
+    gauges: Gauges,
+}
+
+impl ConcurrencyLimitLayer {
+    /// Creates a layer allowing at most `max` requests in flight.
+    pub fn new(max: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max)),
+            methods: HashMap::new(),
+            queue: None,
+            reserved: 0,
+            gauges: Gauges::default(),
+        }
+    }
+
+    /// Lets at most `max` requests over the limit wait for up to `timeout`, instead of failing
+    /// them at once.
+    ///
+    /// Defaults to no queue.
+    pub fn queue(mut self, max: usize, timeout: Duration) -> Self {
+        self.queue = Some(Queue { max, timeout });
+        self
+    }
+
+    /// Keeps the last `n` permits from the low priority requests, which fail instead when fewer
+    /// permits are left.
+    ///
+    /// Defaults to 0.
+    pub fn reserve(mut self, n: usize) -> Self {
+        self.reserved = n;
+        self
+    }
+
+    /// Allows at most `max` requests of `method` in flight, which are limited by the layer as
+    /// well. The queue and the reserved permits apply to it the same.
+    pub fn method(mut self, method: impl Into<String>, max: usize) -> Self {
+        self.methods
+            .insert(method.into(), Arc::new(Semaphore::new(max)));
+        self
+    }
+
+    /// Returns the gauges of the requests through the layer.
+    pub fn gauges(&self) -> Gauges {
+        self.gauges.clone()
+    }
+}
+
+impl<S> Layer<S> for ConcurrencyLimitLayer {
+    type Service = ConcurrencyLimit<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        ConcurrencyLimit {
+            inner,
+            limit: Arc::new(Limit {
+                semaphore: self.semaphore,
+                methods: self.methods,
+                queue: self.queue,
+                reserved: self.reserved,
+                gauges: self.gauges,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_acquire() {
+        let layer = ConcurrencyLimitLayer::new(1).queue(1, Duration::from_millis(10));
+        let gauges = layer.gauges();
+        let limit = layer.layer(()).limit;
+
+        let permit = limit.acquire(None, Priority::Normal).await.unwrap();
+        // waits in the queue until timed out
+        assert!(limit.acquire(None, Priority::Normal).await.is_none());
+        assert_eq!(gauges.queued(), 0);
+
+        drop(permit);
+        assert!(limit.acquire(None, Priority::Normal).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_reserved() {
+        let limit = ConcurrencyLimitLayer::new(2).reserve(1).layer(()).limit;
+
+        let low = limit.acquire(None, Priority::Low).await.unwrap();
+        // the last permit is reserved
+        assert!(limit.acquire(None, Priority::Low).await.is_none());
+        let _normal = limit.acquire(None, Priority::Normal).await.unwrap();
+
+        drop(low);
+        assert!(limit.acquire(None, Priority::Low).await.is_none());
+        assert!(limit.acquire(None, Priority::High).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_method() {
+        let limit = ConcurrencyLimitLayer::new(2)
+            .method("slow", 1)
+            .layer(())
+            .limit;
+
+        let slow = limit.acquire(Some("slow"), Priority::Normal).await.unwrap();
+        assert!(limit
+            .acquire(Some("slow"), Priority::Normal)
+            .await
+            .is_none());
+        // the other methods are only limited by the layer
+        let other = limit
+            .acquire(Some("other"), Priority::Normal)
+            .await
+            .unwrap();
+        assert!(limit.acquire(None, Priority::Normal).await.is_none());
+
+        drop(other);
+        drop(slow);
+        assert!(limit
+            .acquire(Some("slow"), Priority::Normal)
+            .await
+            .is_some());
+    }
+}
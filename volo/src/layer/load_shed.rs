@@ -0,0 +1,291 @@
+//! Rejects a part of the requests when overloaded, so that the service degrades gracefully
+//! instead of collapsing, for the servers of any protocol.
+//!
+//! The load is measured by a [`Signal`], such as the [`EwmaLatency`] of the requests, the
+//! [`InFlight`] requests, or any closure returning a value like the CPU usage. Below the low
+//! threshold no request is rejected, and the probability to reject a request grows linearly up to
+//! the high threshold, above which only 1% of the requests are accepted, so that the signals fed
+//! by the accepted requests can still recover. The rejected requests fail by [`Overloaded`]
+//! converted into the error of the inner service.
+//!
+//! The [`Priority`] of the requests is read from the context extensions: the low priority
+//! requests are rejected with twice the probability, and the high priority ones are never
+//! rejected.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::layer::load_shed::{InFlight, LoadShedLayer};
+//!
+//! // starts shedding at 800 requests in flight and sheds all at 1000
+//! let layer = LoadShedLayer::new(InFlight::default(), 800.0, 1000.0);
+//! // by the cpu usage in percent read by the application
+//! let layer = LoadShedLayer::new(|| cpu_usage(), 80.0, 95.0);
+//! ```
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::Future;
+use motore::{layer::Layer, service::Service};
+use rand::Rng;
+use tokio::time::Instant;
+
+use crate::context::{Context, Priority};
+
+const MAX_PROBABILITY: f64 = 0.99;
+
+/// The error of the requests rejected by the load shedding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overloaded(());
+
+impl fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("service overloaded")
+    }
+}
+
+impl std::error::Error for Overloaded {}
+
+/// Measures the load of a service.
+pub trait Signal: Send + Sync + 'static {
+    /// Returns the current load, compared with the thresholds of the layer.
+    fn value(&self) -> f64;
+
+    /// Called when an accepted request starts.
+    fn on_start(&self) {}
+
+    /// Called when an accepted request ends, or is dropped, after `latency`.
+    fn on_end(&self, _latency: Duration) {}
+}
+
+impl<F> Signal for F
+where
+    F: Fn() -> f64 + Send + Sync + 'static,
+{
+    fn value(&self) -> f64 {
+        self()
+    }
+}
+
+/// The number of the requests in flight.
+#[derive(Debug, Default)]
+pub struct InFlight(AtomicUsize);
+
+impl Signal for InFlight {
+    fn value(&self) -> f64 {
+        self.0.load(Ordering::Relaxed) as f64
+    }
+
+    fn on_start(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_end(&self, _latency: Duration) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The exponentially weighted moving average of the latencies in seconds.
+#[derive(Debug)]
+pub struct EwmaLatency {
+    alpha: f64,
+    value: Mutex<f64>,
+}
+
+impl EwmaLatency {
+    /// Creates a signal weighting each new latency by `alpha`, between 0 and 1.
+    pub fn new(alpha: f64) -> Self {
+        Self {
+            alpha: alpha.clamp(0.0, 1.0),
+            value: Mutex::new(0.0),
+        }
+    }
+}
+
+impl Default for EwmaLatency {
+    fn default() -> Self {
+        Self::new(0.1)
+    }
+}
+
+impl Signal for EwmaLatency {
+    fn value(&self) -> f64 {
+        *self.value.lock().unwrap()
+    }
+
+    fn on_end(&self, latency: Duration) {
+        let mut value = self.value.lock().unwrap();
+        *value += (latency.as_secs_f64() - *value) * self.alpha;
+    }
+}
+
+struct Shedder<G> {
+    signal: G,
+    low: f64,
+    high: f64,
+}
+
+impl<G: Signal> Shedder<G> {
+    /// Returns the probability to reject a request of the priority.
+    fn probability(&self, priority: Priority) -> f64 {
+        let value = self.signal.value();
+        let p = if value <= self.low {
+            0.0
+        } else if value >= self.high {
+            MAX_PROBABILITY
+        } else {
+            (value - self.low) / (self.high - self.low) * MAX_PROBABILITY
+        };
+        match priority {
+            Priority::Low => (p * 2.0).min(MAX_PROBABILITY),
+            Priority::Normal => p,
+            Priority::High => 0.0,
+        }
+    }
+}
+
+/// Reports the end of a request to the signal when dropped.
+struct Started<'a, G: Signal> {
+    signal: &'a G,
+    start: Instant,
+}
+
+impl<G: Signal> Drop for Started<'_, G> {
+    fn drop(&mut self) {
+        self.signal.on_end(self.start.elapsed());
+    }
+}
+
+/// A [`Service`] that rejects a part of the requests when overloaded.
+pub struct LoadShed<S, G> {
+    inner: S,
+    shedder: Arc<Shedder<G>>,
+}
+
+impl<S: Clone, G> Clone for LoadShed<S, G> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            shedder: self.shedder.clone(),
+        }
+    }
+}
+
+impl<Cx, Req, S, G> Service<Cx, Req> for LoadShed<S, G>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+    S::Error: From<Overloaded>,
+    G: Signal,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let shedder = self.shedder.clone();
+            let priority = cx
+                .extensions()
+                .get::<Priority>()
+                .copied()
+                .unwrap_or_default();
+            let p = shedder.probability(priority);
+            let shed = p > 0.0 && rand::thread_rng().gen::<f64>() < p;
+            if shed {
+                tracing::debug!("[VOLO] request shed by the load {}", shedder.signal.value());
+                return Err(Overloaded(()).into());
+            }
+            shedder.signal.on_start();
+            let _started = Started {
+                signal: &shedder.signal,
+                start: Instant::now(),
+            };
+            self.inner.call(cx, req).await
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`LoadShed`].
+///
+/// All the services made by the same layer share the signal.
+pub struct LoadShedLayer<G> {
+    shedder: Arc<Shedder<G>>,
+}
+
+impl<G> Clone for LoadShedLayer<G> {
+    fn clone(&self) -> Self {
+        Self {
+            shedder: self.shedder.clone(),
+        }
+    }
+}
+
+impl<G: Signal> LoadShedLayer<G> {
+    /// Creates a layer starting to reject the requests when the signal exceeds `low`, and
+    /// rejecting most of them when it reaches `high`.
+    pub fn new(signal: G, low: f64, high: f64) -> Self {
+        Self {
+            shedder: Arc::new(Shedder {
+                signal,
+                low,
+                high: high.max(low),
+            }),
+        }
+    }
+}
+
+impl<S, G> Layer<S> for LoadShedLayer<G> {
+    type Service = LoadShed<S, G>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        LoadShed {
+            inner,
+            shedder: self.shedder,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probability() {
+        let layer = LoadShedLayer::new(InFlight::default(), 2.0, 4.0);
+        let shedder = &layer.shedder;
+        assert_eq!(shedder.probability(Priority::Normal), 0.0);
+        for _ in 0..3 {
+            shedder.signal.on_start();
+        }
+        assert_eq!(shedder.probability(Priority::Normal), 0.5 * MAX_PROBABILITY);
+        assert_eq!(shedder.probability(Priority::Low), MAX_PROBABILITY);
+        assert_eq!(shedder.probability(Priority::High), 0.0);
+        for _ in 0..2 {
+            shedder.signal.on_start();
+        }
+        assert_eq!(shedder.probability(Priority::Normal), MAX_PROBABILITY);
+    }
+
+    #[test]
+    fn test_ewma_latency() {
+        let signal = EwmaLatency::new(0.5);
+        signal.on_end(Duration::from_secs(2));
+        assert_eq!(signal.value(), 1.0);
+        signal.on_end(Duration::from_secs(2));
+        assert_eq!(signal.value(), 1.5);
+    }
+}
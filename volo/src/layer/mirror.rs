@@ -0,0 +1,218 @@
+//! Mirrors a part of the requests to a shadow target, such as a new version of the service, so
+//! that it can be validated with the production traffic, for the clients of any protocol.
+//!
+//! The mirrored calls are spawned in background after the request is duplicated by the
+//! [`Mirror`], their responses and errors are ignored and never delay the original calls. The
+//! mirrored calls in flight are bounded, over which the requests are not mirrored, so that a slow
+//! shadow target doesn't pile the calls up in the client.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::layer::MirrorLayer;
+//!
+//! let shadow = ItemServiceClientBuilder::new("item-canary").build();
+//! // mirrors 10% of the requests
+//! let layer = MirrorLayer::new(
+//!     move |method: &str, req: &ItemServiceRequest| {
+//!         let (mut shadow, method, req) = (shadow.clone(), method.to_string(), req.clone());
+//!         async move {
+//!             let _ = shadow.call(method, req, false).await;
+//!         }
+//!     },
+//!     0.1,
+//! );
+//! ```
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use futures::{
+    future::{BoxFuture, FutureExt},
+    Future,
+};
+use motore::{layer::Layer, service::Service};
+use rand::Rng;
+
+use crate::{context::Context, rt::Runtime};
+
+const DEFAULT_MAX_IN_FLIGHT: usize = 100;
+
+/// Duplicates the requests to the shadow target.
+pub trait Mirror<Req>: Send + Sync + 'static {
+    /// Returns the call of the duplicated request to the shadow target.
+    fn mirror(&self, method: &str, req: &Req) -> BoxFuture<'static, ()>;
+}
+
+impl<Req, F, Fut> Mirror<Req> for F
+where
+    F: Fn(&str, &Req) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    fn mirror(&self, method: &str, req: &Req) -> BoxFuture<'static, ()> {
+        self(method, req).boxed()
+    }
+}
+
+struct Shared<M> {
+    mirror: Arc<M>,
+    ratio: f64,
+    max_in_flight: usize,
+    in_flight: Arc<AtomicUsize>,
+    runtime: Runtime,
+}
+
+impl<M> Shared<M> {
+    /// Returns whether to mirror a request, and counts it in flight if so.
+    fn sample(&self) -> bool {
+        if self.ratio <= 0.0 || (self.ratio < 1.0 && rand::thread_rng().gen::<f64>() >= self.ratio)
+        {
+            return false;
+        }
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.max_in_flight).then_some(n + 1)
+            })
+            .is_ok()
+    }
+}
+
+/// A [`Service`] that mirrors a part of the requests to the shadow target.
+pub struct MirrorService<S, M> {
+    inner: S,
+    shared: Arc<Shared<M>>,
+}
+
+impl<S: Clone, M> Clone for MirrorService<S, M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<Cx, Req, S, M> Service<Cx, Req> for MirrorService<S, M>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+    M: Mirror<Req>,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        if self.shared.sample() {
+            let method = cx.rpc_info().method().map_or("", |m| m.as_str());
+            let call = self.shared.mirror.mirror(method, &req);
+            let shared = self.shared.clone();
+            self.shared.runtime.spawn(async move {
+                call.await;
+                shared.in_flight.fetch_sub(1, Ordering::AcqRel);
+            });
+        }
+        self.inner.call(cx, req)
+    }
+}
+
+/// A [`Layer`] that applies [`MirrorService`].
+///
+/// All the services made by the same layer, or by its clones, share the bound of the mirrored
+/// calls.
+pub struct MirrorLayer<M> {
+    mirror: Arc<M>,
+    ratio: f64,
+    max_in_flight: usize,
+    in_flight: Arc<AtomicUsize>,
+    runtime: Runtime,
+}
+
+impl<M> Clone for MirrorLayer<M> {
+    fn clone(&self) -> Self {
+        Self {
+            mirror: self.mirror.clone(),
+            ratio: self.ratio,
+            max_in_flight: self.max_in_flight,
+            in_flight: self.in_flight.clone(),
+            runtime: self.runtime.clone(),
+        }
+    }
+}
+
+impl<M> MirrorLayer<M> {
+    /// Creates a layer mirroring the `ratio` of the requests, between 0 and 1, by `mirror`.
+    pub fn new(mirror: M, ratio: f64) -> Self {
+        Self {
+            mirror: Arc::new(mirror),
+            ratio,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            runtime: Runtime::default(),
+        }
+    }
+
+    /// Sets the max mirrored calls in flight, over which the requests are not mirrored.
+    ///
+    /// Defaults to 100.
+    pub fn max_in_flight(mut self, max: usize) -> Self {
+        self.max_in_flight = max;
+        self
+    }
+
+    /// Sets the runtime to spawn the mirrored calls.
+    ///
+    /// Defaults to the tokio runtime.
+    pub fn runtime(mut self, runtime: Runtime) -> Self {
+        self.runtime = runtime;
+        self
+    }
+}
+
+impl<S, M> Layer<S> for MirrorLayer<M> {
+    type Service = MirrorService<S, M>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        MirrorService {
+            inner,
+            shared: Arc::new(Shared {
+                mirror: self.mirror,
+                ratio: self.ratio,
+                max_in_flight: self.max_in_flight,
+                in_flight: self.in_flight,
+                runtime: self.runtime,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample() {
+        let layer = MirrorLayer::new(|_: &str, _: &()| async {}, 1.0);
+        // configured after cloned, the clones share the bound
+        let clone = layer.clone().max_in_flight(2);
+        let shared = layer.max_in_flight(2).layer(()).shared;
+        let other = clone.layer(()).shared;
+        assert!(shared.sample());
+        assert!(other.sample());
+        // bounded by the calls in flight
+        assert!(!shared.sample());
+        shared.in_flight.fetch_sub(1, Ordering::AcqRel);
+        assert!(other.sample());
+
+        let layer = MirrorLayer::new(|_: &str, _: &()| async {}, 0.0);
+        assert!(!layer.layer(()).shared.sample());
+    }
+}
@@ -0,0 +1,23 @@
+pub use motore::layer::*;
+
+pub mod canary;
+pub mod circuit_breaker;
+pub mod concurrency_limit;
+pub mod load_shed;
+pub mod mirror;
+pub mod priority;
+pub mod rate_limit;
+pub mod retry;
+pub mod span;
+pub mod timeout;
+
+pub use canary::{CanaryHandle, CanaryLayer, CanaryRules, CanaryService};
+pub use circuit_breaker::{CircuitBreaker, CircuitBreakerLayer};
+pub use concurrency_limit::{ConcurrencyLimit, ConcurrencyLimitLayer};
+pub use load_shed::{LoadShed, LoadShedLayer};
+pub use mirror::{Mirror, MirrorLayer, MirrorService};
+pub use priority::{PriorityLayer, PriorityService};
+pub use rate_limit::{RateLimit, RateLimitLayer};
+pub use retry::{Retry, RetryLayer, RetryPolicy};
+pub use span::{Span, SpanLayer};
+pub use timeout::{Timeout, TimeoutLayer};
@@ -0,0 +1,81 @@
+//! Classifies the requests by their [`Priority`], for the servers of any protocol.
+//!
+//! The priority returned by the classifier is set into the context extensions, so that the
+//! layers after this one, such as [`LoadShed`](super::LoadShed) and
+//! [`ConcurrencyLimit`](super::ConcurrencyLimit), reject the low priority requests first. The
+//! priority already in the extensions, such as the one sent by a thrift client, is kept when the
+//! classifier returns `None`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::{context::Priority, layer::PriorityLayer};
+//!
+//! let layer = PriorityLayer::new(|cx: &ServerContext, _req: &_| {
+//!     match cx.rpc_info.method() {
+//!         "Ping" => Some(Priority::High),
+//!         "Report" => Some(Priority::Low),
+//!         _ => None,
+//!     }
+//! });
+//! ```
+
+use futures::Future;
+use motore::{layer::Layer, service::Service};
+
+use crate::context::{Context, Priority};
+
+/// A [`Service`] that sets the [`Priority`] of the requests.
+#[derive(Clone)]
+pub struct PriorityService<S, F> {
+    inner: S,
+    classify: F,
+}
+
+impl<Cx, Req, S, F> Service<Cx, Req> for PriorityService<S, F>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+    F: Fn(&Cx, &Req) -> Option<Priority> + Send + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        if let Some(priority) = (self.classify)(cx, &req) {
+            cx.extensions_mut().insert(priority);
+        }
+        self.inner.call(cx, req)
+    }
+}
+
+/// A [`Layer`] that applies [`PriorityService`].
+#[derive(Clone)]
+pub struct PriorityLayer<F> {
+    classify: F,
+}
+
+impl<F> PriorityLayer<F> {
+    /// Creates a layer setting the priority returned by `classify`.
+    pub fn new(classify: F) -> Self {
+        Self { classify }
+    }
+}
+
+impl<S, F> Layer<S> for PriorityLayer<F> {
+    type Service = PriorityService<S, F>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        PriorityService {
+            inner,
+            classify: self.classify,
+        }
+    }
+}
@@ -0,0 +1,398 @@
+//! Throttles the requests by token buckets, for the clients and the servers of any protocol.
+//!
+//! Each bucket is refilled by `rate` tokens per second up to `burst`, and each request takes a
+//! token, failing by [`RateLimited`] converted into the error of the inner service when there is
+//! none. The requests share a single bucket by default, or can be limited per key, such as per
+//! method by [`ByMethod`] or per caller service by [`ByCaller`]. The buckets refilled up to the
+//! burst are the same as new ones, so they are dropped as the keys grow, and only the ones of the
+//! keys of the recent requests are kept, such as per peer. The gRPC servers respond
+//! `RESOURCE_EXHAUSTED` to the rejected requests, and the thrift servers an `InternalError`
+//! application exception.
+//!
+//! A method can have its own rate by [`RateLimitLayer::method`], keyed by the method of the rpc
+//! info, which is the path for gRPC. Its requests share the bucket of the method instead of the
+//! ones of the keys.
+//!
+//! The requests allowed and rejected are counted by the [`RateLimitStats`] of
+//! [`RateLimitLayer::stats`], such as to export them as metrics.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::layer::rate_limit::{ByCaller, RateLimitLayer};
+//!
+//! // 100 requests per second of each caller, with bursts of 20
+//! let layer = RateLimitLayer::new(100.0, 20)
+//!     .key_by(ByCaller)
+//!     .method("GetItem", 1000.0, 100);
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use futures::Future;
+use motore::{layer::Layer, service::Service};
+use tokio::time::Instant;
+
+use crate::context::Context;
+
+/// The error of the requests rejected by the rate limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimited {
+    key: String,
+}
+
+impl RateLimited {
+    /// Returns the key of the exhausted bucket, empty for the shared one.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.key.is_empty() {
+            f.write_str("rate limited")
+        } else {
+            write!(f, "rate limited for {}", self.key)
+        }
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// The counters of the requests of a [`RateLimitLayer`].
+#[derive(Debug, Default)]
+pub struct RateLimitStats {
+    allowed: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl RateLimitStats {
+    /// The number of requests that have been let through.
+    pub fn allowed(&self) -> u64 {
+        self.allowed.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests that have been rejected.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, allowed: bool) {
+        let counter = if allowed {
+            &self.allowed
+        } else {
+            &self.rejected
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the key of the bucket of a request, `None` if the request is not limited.
+pub trait KeyExtractor<Cx> {
+    fn key(&self, cx: &Cx) -> Option<String>;
+}
+
+/// All the requests share a single bucket.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Global;
+
+impl<Cx> KeyExtractor<Cx> for Global {
+    fn key(&self, _cx: &Cx) -> Option<String> {
+        Some(String::new())
+    }
+}
+
+/// The requests of each method share a bucket.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByMethod;
+
+impl<Cx: Context> KeyExtractor<Cx> for ByMethod {
+    fn key(&self, cx: &Cx) -> Option<String> {
+        cx.rpc_info().method.as_deref().map(Into::into)
+    }
+}
+
+/// The requests of each caller service share a bucket.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByCaller;
+
+impl<Cx: Context> KeyExtractor<Cx> for ByCaller {
+    fn key(&self, cx: &Cx) -> Option<String> {
+        cx.rpc_info()
+            .caller()
+            .map(|c| c.service_name_ref().to_string())
+    }
+}
+
+impl<Cx, F> KeyExtractor<Cx> for F
+where
+    F: Fn(&Cx) -> Option<String>,
+{
+    fn key(&self, cx: &Cx) -> Option<String> {
+        self(cx)
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn try_acquire(&mut self, rate: f64, burst: f64, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.refilled_at = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    fn is_full(&self, rate: f64, burst: f64, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens + elapsed * rate >= burst
+    }
+}
+
+/// The buckets under which the full ones are not dropped.
+const MIN_SWEEP_LEN: usize = 1024;
+
+struct Keyed {
+    buckets: HashMap<String, Bucket>,
+    // the full buckets are dropped when there are as many buckets
+    sweep_len: usize,
+}
+
+struct Buckets {
+    rate: f64,
+    burst: f64,
+    keyed: Mutex<Keyed>,
+}
+
+impl Buckets {
+    fn new(rate: f64, burst: u32) -> Self {
+        Self {
+            rate: rate.max(0.0),
+            burst: burst.max(1) as f64,
+            keyed: Mutex::new(Keyed {
+                buckets: HashMap::new(),
+                sweep_len: MIN_SWEEP_LEN,
+            }),
+        }
+    }
+
+    fn try_acquire(&self, key: &str, now: Instant) -> bool {
+        let mut keyed = self.keyed.lock().unwrap();
+        if let Some(bucket) = keyed.buckets.get_mut(key) {
+            return bucket.try_acquire(self.rate, self.burst, now);
+        }
+        if keyed.buckets.len() >= keyed.sweep_len {
+            let (rate, burst) = (self.rate, self.burst);
+            keyed
+                .buckets
+                .retain(|_, bucket| !bucket.is_full(rate, burst, now));
+            // amortized by sweeping again only after the buckets kept doubled
+            keyed.sweep_len = (keyed.buckets.len() * 2).max(MIN_SWEEP_LEN);
+        }
+        // a new bucket is full
+        let mut bucket = Bucket {
+            tokens: self.burst,
+            refilled_at: now,
+        };
+        let acquired = bucket.try_acquire(self.rate, self.burst, now);
+        keyed.buckets.insert(key.to_string(), bucket);
+        acquired
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.keyed.lock().unwrap().buckets.len()
+    }
+}
+
+/// A [`Service`] that rejects the requests exceeding the rate.
+#[derive(Clone)]
+pub struct RateLimit<S, K> {
+    inner: S,
+    key: K,
+    buckets: Arc<Buckets>,
+    methods: Arc<HashMap<String, Arc<Buckets>>>,
+    stats: Arc<RateLimitStats>,
+}
+
+impl<Cx, Req, S, K> Service<Cx, Req> for RateLimit<S, K>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+    S::Error: From<RateLimited>,
+    K: KeyExtractor<Cx> + Send + Sync + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let method = cx
+                .rpc_info()
+                .method
+                .as_deref()
+                .and_then(|m| Some((m, self.methods.get(m)?)));
+            let now = Instant::now();
+            let rejected = match method {
+                Some((method, buckets)) => {
+                    (!buckets.try_acquire("", now)).then(|| method.to_string())
+                }
+                None => self
+                    .key
+                    .key(cx)
+                    .filter(|key| !self.buckets.try_acquire(key, now)),
+            };
+            self.stats.record(rejected.is_none());
+            if let Some(key) = rejected {
+                tracing::debug!("[VOLO] rate limited for {:?}", key);
+                return Err(RateLimited { key }.into());
+            }
+            self.inner.call(cx, req).await
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`RateLimit`].
+///
+/// All the services made by the same layer share the buckets.
+#[derive(Clone)]
+pub struct RateLimitLayer<K = Global> {
+    key: K,
+    buckets: Arc<Buckets>,
+    methods: HashMap<String, Arc<Buckets>>,
+    stats: Arc<RateLimitStats>,
+}
+
+impl RateLimitLayer {
+    /// Creates a layer allowing `rate` requests per second with bursts of up to `burst` requests.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Self {
+            key: Global,
+            buckets: Arc::new(Buckets::new(rate, burst)),
+            methods: HashMap::new(),
+            stats: Default::default(),
+        }
+    }
+}
+
+impl<K> RateLimitLayer<K> {
+    /// Sets the key of the bucket of each request, each key has its own rate and burst.
+    ///
+    /// Defaults to [`Global`].
+    pub fn key_by<K2>(self, key: K2) -> RateLimitLayer<K2> {
+        RateLimitLayer {
+            key,
+            buckets: self.buckets,
+            methods: self.methods,
+            stats: self.stats,
+        }
+    }
+
+    /// Allows `rate` requests of `method` per second with bursts of up to `burst` requests,
+    /// which share a single bucket regardless of their keys.
+    pub fn method(mut self, method: impl Into<String>, rate: f64, burst: u32) -> Self {
+        self.methods
+            .insert(method.into(), Arc::new(Buckets::new(rate, burst)));
+        self
+    }
+
+    /// Returns the counters shared by all the services made by this layer.
+    pub fn stats(&self) -> Arc<RateLimitStats> {
+        self.stats.clone()
+    }
+}
+
+impl<S, K> Layer<S> for RateLimitLayer<K> {
+    type Service = RateLimit<S, K>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            key: self.key,
+            buckets: self.buckets,
+            methods: Arc::new(self.methods),
+            stats: self.stats,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        let now = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 2.0,
+            refilled_at: now,
+        };
+        assert!(bucket.try_acquire(10.0, 2.0, now));
+        assert!(bucket.try_acquire(10.0, 2.0, now));
+        assert!(!bucket.try_acquire(10.0, 2.0, now));
+
+        // refilled a token in 100ms
+        let now = now + Duration::from_millis(100);
+        assert!(bucket.try_acquire(10.0, 2.0, now));
+        assert!(!bucket.try_acquire(10.0, 2.0, now));
+
+        // capped by the burst
+        let now = now + Duration::from_secs(10);
+        assert!(bucket.try_acquire(10.0, 2.0, now));
+        assert!(bucket.try_acquire(10.0, 2.0, now));
+        assert!(!bucket.try_acquire(10.0, 2.0, now));
+    }
+
+    #[test]
+    fn test_sweep() {
+        let now = Instant::now();
+        let buckets = Buckets::new(10.0, 1);
+        for key in 0..MIN_SWEEP_LEN {
+            assert!(buckets.try_acquire(&key.to_string(), now));
+        }
+
+        // the buckets not refilled yet are kept
+        assert!(buckets.try_acquire("new", now));
+        assert_eq!(buckets.len(), MIN_SWEEP_LEN + 1);
+        assert!(!buckets.try_acquire("0", now));
+
+        let buckets = Buckets::new(10.0, 1);
+        for key in 0..MIN_SWEEP_LEN {
+            assert!(buckets.try_acquire(&key.to_string(), now));
+        }
+        // refilled in 100ms
+        let now = now + Duration::from_millis(100);
+        assert!(buckets.try_acquire("new", now));
+        assert_eq!(buckets.len(), 1);
+        assert!(buckets.try_acquire("0", now));
+    }
+}
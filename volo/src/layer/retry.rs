@@ -0,0 +1,410 @@
+//! Retries the failed requests, for the clients of any protocol.
+//!
+//! What to retry is decided by the [`RetryPolicy`], which classifies the errors, computes the
+//! backoff between the attempts and clones the request for the next attempt. The layer limits
+//! the attempts of each request by [`RetryLayer::max_retries`], and the retries of all the
+//! requests by an optional [`Budget`], so that the retries don't overload the servers already in
+//! trouble.
+//!
+//! Each retry picks an instance again, the ones tried by the previous attempts last, unless the
+//! request is to a fixed address, see [`TriedAddresses`]. The policies of each request, such as
+//! by the config of a call, retry by [`retry`] instead of the layer.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::layer::retry::{Backoff, Budget, RetryLayer, RetryPolicy};
+//!
+//! #[derive(Clone)]
+//! struct Policy(Backoff);
+//!
+//! impl<Cx, Req: Clone, Resp> RetryPolicy<Cx, Req, Resp, Status> for Policy {
+//!     fn retryable(&self, _cx: &Cx, err: &Status) -> bool {
+//!         err.code() == Code::Unavailable
+//!     }
+//!
+//!     fn backoff(&self, attempt: usize) -> Duration {
+//!         self.0.delay(attempt)
+//!     }
+//!
+//!     fn clone_request(&self, req: &Req) -> Option<Req> {
+//!         Some(req.clone())
+//!     }
+//! }
+//!
+//! let backoff = Backoff::new(Duration::from_millis(10), Duration::from_secs(1));
+//! let layer = RetryLayer::new(Policy(backoff)).budget(Budget::new(0.1, 10));
+//! ```
+
+use std::{
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::Future;
+use motore::{layer::Layer, service::Service};
+use rand::Rng;
+use tracing::debug;
+
+use super::timeout::Elapsed;
+use crate::{context::Context, loadbalance::TriedAddresses};
+
+const DEFAULT_MAX_RETRIES: usize = 2;
+
+// the tokens of a retry, so that the fractions of the ratio are kept
+const TOKENS_PER_RETRY: i64 = 1000;
+
+/// Decides whether and how to retry a failed request.
+pub trait RetryPolicy<Cx, Req, Resp, E> {
+    /// Returns whether the request failed by `err` can be retried.
+    fn retryable(&self, cx: &Cx, err: &E) -> bool;
+
+    /// Returns how long to wait before the `attempt`-th retry, starting from 1.
+    fn backoff(&self, _attempt: usize) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Clones the request for the next attempt, `None` if the request can't be retried, such as a
+    /// streaming one.
+    fn clone_request(&self, req: &Req) -> Option<Req>;
+
+    /// Returns whether the request cloned before the failed attempt can still be sent, such as
+    /// the one whose body is kept while being sent by the failed attempt.
+    ///
+    /// Defaults to true.
+    fn replayable(&self, _next: &Req) -> bool {
+        true
+    }
+
+    /// Returns the timeout of each attempt, the attempts timed out fail by [`Elapsed`] converted
+    /// into the error, and are always retried.
+    ///
+    /// Defaults to none.
+    fn attempt_timeout(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// The exponential backoff with the full jitter.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self { base, max }
+    }
+
+    /// Returns a random delay up to `base * 2^(attempt - 1)`, capped by `max`.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let exp = attempt.saturating_sub(1).min(31) as u32;
+        let limit = self.base.saturating_mul(1 << exp).min(self.max);
+        limit.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+/// Limits the retries to a ratio of the requests.
+///
+/// Each request deposits `ratio` retries and each retry withdraws one. The balance starts from
+/// and is capped by `reserve`, which allows the retries of the services with low traffic.
+#[derive(Debug)]
+pub struct Budget {
+    balance: AtomicI64,
+    deposit: i64,
+    max_balance: i64,
+}
+
+impl Budget {
+    pub fn new(ratio: f32, reserve: u32) -> Self {
+        let max_balance = (reserve.max(1) as i64) * TOKENS_PER_RETRY;
+        Self {
+            balance: AtomicI64::new(max_balance),
+            deposit: (ratio.max(0.0) * TOKENS_PER_RETRY as f32) as i64,
+            max_balance,
+        }
+    }
+
+    fn deposit(&self) {
+        let _ = self
+            .balance
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |b| {
+                Some((b + self.deposit).min(self.max_balance))
+            });
+    }
+
+    fn withdraw(&self) -> bool {
+        self.balance
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |b| {
+                (b >= TOKENS_PER_RETRY).then_some(b - TOKENS_PER_RETRY)
+            })
+            .is_ok()
+    }
+}
+
+/// A [`Service`] that retries the failed requests by the [`RetryPolicy`].
+#[derive(Clone)]
+pub struct Retry<S, P> {
+    inner: S,
+    policy: P,
+    max_retries: usize,
+    budget: Option<Arc<Budget>>,
+}
+
+/// Calls the request, retrying it by the policy up to `max_retries` times within the budget.
+///
+/// It's the retries of [`Retry`], for the policies of each request.
+pub async fn retry<Cx, Req, S, P>(
+    inner: &mut S,
+    policy: &P,
+    max_retries: usize,
+    budget: Option<&Budget>,
+    cx: &mut Cx,
+    mut req: Req,
+) -> Result<S::Response, S::Error>
+where
+    Cx: Context,
+    S: Service<Cx, Req>,
+    S::Error: From<Elapsed>,
+    P: RetryPolicy<Cx, Req, S::Response, S::Error>,
+{
+    if let Some(budget) = budget {
+        budget.deposit();
+    }
+    // the address of the request, otherwise picked by the load balance
+    let fixed = cx
+        .rpc_info()
+        .callee()
+        .map_or(false, |callee| callee.address.is_some());
+    let mut attempt = 0;
+    loop {
+        let next = if attempt < max_retries {
+            policy.clone_request(&req)
+        } else {
+            None
+        };
+        // the error is not kept across the backoff
+        req = {
+            let call = inner.call(cx, req);
+            let (result, timed_out) = match policy.attempt_timeout() {
+                Some(timeout) => match tokio::time::timeout(timeout, call).await {
+                    Ok(result) => (result, false),
+                    Err(_) => (Err(Elapsed::new().into()), true),
+                },
+                None => (call.await, false),
+            };
+            let err = match result {
+                Ok(resp) => return Ok(resp),
+                Err(err) => err,
+            };
+            let next = match next {
+                Some(next)
+                    if (timed_out || policy.retryable(cx, &err)) && policy.replayable(&next) =>
+                {
+                    next
+                }
+                _ => return Err(err),
+            };
+            if let Some(budget) = budget {
+                if !budget.withdraw() {
+                    debug!("[VOLO] retry budget exhausted");
+                    return Err(err);
+                }
+            }
+            next
+        };
+        attempt += 1;
+        if !fixed {
+            let tried = cx
+                .rpc_info_mut()
+                .callee_mut()
+                .and_then(|callee| callee.address.take());
+            if let Some(tried) = tried {
+                cx.extensions_mut()
+                    .entry::<TriedAddresses>()
+                    .or_insert_with(TriedAddresses::default)
+                    .0
+                    .push(tried);
+            }
+        }
+        debug!(
+            "[VOLO] retrying the call of {:?}, attempt {} failed",
+            cx.rpc_info().method(),
+            attempt
+        );
+        let backoff = policy.backoff(attempt);
+        if !backoff.is_zero() {
+            tokio::time::sleep(backoff).await;
+        }
+    }
+}
+
+impl<Cx, Req, S, P> Service<Cx, Req> for Retry<S, P>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+    S::Error: From<Elapsed>,
+    P: RetryPolicy<Cx, Req, S::Response, S::Error> + Send + Sync + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        retry(
+            &mut self.inner,
+            &self.policy,
+            self.max_retries,
+            self.budget.as_deref(),
+            cx,
+            req,
+        )
+    }
+}
+
+/// A [`Layer`] that applies [`Retry`].
+#[derive(Clone)]
+pub struct RetryLayer<P> {
+    policy: P,
+    max_retries: usize,
+    budget: Option<Arc<Budget>>,
+}
+
+impl<P> RetryLayer<P> {
+    pub fn new(policy: P) -> Self {
+        Self {
+            policy,
+            max_retries: DEFAULT_MAX_RETRIES,
+            budget: None,
+        }
+    }
+
+    /// Sets the max retries of each request.
+    ///
+    /// Defaults to 2.
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Limits the retries of all the requests through the layer by the budget.
+    ///
+    /// Defaults to no limit.
+    pub fn budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(Arc::new(budget));
+        self
+    }
+}
+
+impl<S, P> Layer<S> for RetryLayer<P> {
+    type Service = Retry<S, P>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        Retry {
+            inner,
+            policy: self.policy,
+            max_retries: self.max_retries,
+            budget: self.budget,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use motore::{service::service_fn, BoxError};
+
+    use super::*;
+    use crate::{
+        context::{Endpoint, Role, RpcCx, RpcInfo},
+        net::Address,
+    };
+
+    type TestContext = RpcCx<(), ()>;
+
+    struct Policy;
+
+    impl RetryPolicy<TestContext, usize, usize, BoxError> for Policy {
+        fn retryable(&self, _cx: &TestContext, err: &BoxError) -> bool {
+            err.to_string() == "unavailable"
+        }
+
+        fn clone_request(&self, req: &usize) -> Option<usize> {
+            Some(*req)
+        }
+
+        fn attempt_timeout(&self) -> Option<Duration> {
+            Some(Duration::from_millis(50))
+        }
+    }
+
+    // picks the port of the attempt, hangs on the first one and fails the second one
+    async fn handle(cx: &mut TestContext, req: usize) -> Result<usize, BoxError> {
+        let attempt = cx
+            .extensions()
+            .get::<TriedAddresses>()
+            .map_or(0, |tried| tried.0.len());
+        cx.rpc_info_mut()
+            .callee_mut()
+            .unwrap()
+            .set_address(Address::Ip(([127, 0, 0, 1], attempt as u16).into()));
+        match attempt {
+            0 => futures::future::pending().await,
+            1 => Err("unavailable".into()),
+            _ => Ok(req),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let mut cx = RpcCx::new(RpcInfo::with_role(Role::Client), ());
+        cx.rpc_info.callee = Some(Endpoint::new("item".into()));
+        let mut service = service_fn(handle);
+
+        let resp = retry(&mut service, &Policy, 2, None, &mut cx, 1).await;
+        assert_eq!(resp.unwrap(), 1);
+        let tried = cx.extensions().get::<TriedAddresses>().unwrap();
+        assert_eq!(tried.0.len(), 2);
+
+        // the attempt timed out is retried, but not beyond the max retries
+        cx.extensions_mut().remove::<TriedAddresses>();
+        cx.rpc_info.callee = Some(Endpoint::new("item".into()));
+        let err = retry(&mut service, &Policy, 1, None, &mut cx, 1)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "unavailable");
+    }
+
+    #[test]
+    fn test_budget() {
+        let budget = Budget::new(0.5, 1);
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+        budget.deposit();
+        assert!(!budget.withdraw());
+        budget.deposit();
+        assert!(budget.withdraw());
+        // capped by the reserve
+        for _ in 0..10 {
+            budget.deposit();
+        }
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(50));
+        assert!(backoff.delay(1) <= Duration::from_millis(10));
+        assert!(backoff.delay(100) <= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,133 @@
+//! Runs each request in a tracing span with the identity of the service, for the services of any
+//! protocol.
+//!
+//! The span is named `rpc` and has the fields `service` and `version` of the [`ServiceInfo`],
+//! `method` as named in the IDL, and `role` of the client or the server, so that the events of the
+//! inner layers and the handlers, and the metrics recorded from the spans, are labeled the same
+//! for all the services.
+//!
+//! The [`LogFields`] of the request are recorded into the span as well, `caller` is added on the
+//! server side from the caller of the [`RpcInfo`](crate::context::RpcInfo), and the fields added
+//! during the request are recorded as they're added.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::layer::span::SpanLayer;
+//!
+//! let server = ItemServiceServer::new(S).layer_front(SpanLayer::<ItemServiceServer<S>>::new());
+//! ```
+
+use std::marker::PhantomData;
+
+use futures::Future;
+use motore::{layer::Layer, service::Service};
+use tracing::Instrument;
+
+use crate::{
+    context::{Context, LogFields, Role},
+    service_info::{method_name, ServiceInfo},
+};
+
+/// A [`Service`] that runs the requests in the spans of the service `T`.
+pub struct Span<S, T> {
+    inner: S,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<S: Clone, T> Clone for Span<S, T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Cx, Req, S, T> Service<Cx, Req> for Span<S, T>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+    T: ServiceInfo + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        let span = tracing::info_span!(
+            "rpc",
+            service = T::NAME,
+            version = T::VERSION,
+            method = cx.rpc_info().method().map_or("", |m| method_name(m)),
+            role = ?cx.rpc_info().role(),
+            request_id = tracing::field::Empty,
+            caller = tracing::field::Empty,
+            fields = tracing::field::Empty,
+        );
+
+        let mut fields = cx
+            .extensions_mut()
+            .remove::<LogFields>()
+            .unwrap_or_default();
+        if cx.rpc_info().role() == Role::Server && fields.get("caller").is_none() {
+            let caller = cx.rpc_info().caller().and_then(|c| {
+                if !c.service_name.is_empty() {
+                    Some(c.service_name.to_string())
+                } else {
+                    c.address.as_ref().map(|a| a.to_string())
+                }
+            });
+            if let Some(caller) = caller {
+                fields.insert("caller", caller);
+            }
+        }
+        fields.bind(span.clone());
+        cx.extensions_mut().insert(fields);
+
+        self.inner.call(cx, req).instrument(span)
+    }
+}
+
+/// A [`Layer`] that applies [`Span`].
+pub struct SpanLayer<T> {
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for SpanLayer<T> {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Default for SpanLayer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> SpanLayer<T> {
+    /// Creates a layer of the spans of the service `T`, such as the generated client or server.
+    pub fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, T> Layer<S> for SpanLayer<T> {
+    type Service = Span<S, T>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        Span {
+            inner,
+            _marker: PhantomData,
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Bounds the time spent on each request, for the services of any protocol.
+//!
+//! The deadline of a request is the earlier one of the [`Deadline`] in the context extensions and
+//! the default timeout of the layer since the request gets here. When it's exceeded, the inner
+//! call is dropped and [`Elapsed`] is returned, converted into the error of the inner service, so
+//! the services returning `BoxError` or `volo_grpc::Status` can both be wrapped.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use std::time::Duration;
+//!
+//! use volo::layer::TimeoutLayer;
+//!
+//! let layer = TimeoutLayer::new().default_timeout(Duration::from_secs(1));
+//! ```
+
+use std::{fmt, time::Duration};
+
+use futures::Future;
+use motore::{layer::Layer, service::Service};
+
+use crate::context::{Context, Deadline};
+
+/// The error returned when the deadline of a request is exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed(());
+
+impl Elapsed {
+    pub(crate) fn new() -> Self {
+        Self(())
+    }
+}
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("deadline exceeded")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+/// A [`Service`] that fails the requests exceeding their deadlines.
+#[derive(Clone)]
+pub struct Timeout<S> {
+    inner: S,
+    default_timeout: Option<Duration>,
+}
+
+impl<S> Timeout<S> {
+    fn deadline<Cx: Context>(&self, cx: &Cx) -> Option<tokio::time::Instant> {
+        let deadline = cx
+            .extensions()
+            .get::<Deadline>()
+            .map(|Deadline(d)| tokio::time::Instant::from_std(*d));
+        let default = self
+            .default_timeout
+            .map(|t| tokio::time::Instant::now() + t);
+        match (deadline, default) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+impl<Cx, Req, S> Service<Cx, Req> for Timeout<S>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+    S::Error: From<Elapsed>,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let deadline = self.deadline(cx);
+            match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, self.inner.call(cx, req))
+                    .await
+                    .unwrap_or_else(|_| Err(Elapsed(()).into())),
+                None => self.inner.call(cx, req).await,
+            }
+        }
+    }
+}
+
+/// A [`Layer`] that applies [`Timeout`].
+#[derive(Clone, Copy, Default)]
+pub struct TimeoutLayer {
+    default_timeout: Option<Duration>,
+}
+
+impl TimeoutLayer {
+    /// Creates a layer bounding the requests by their [`Deadline`]s only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the timeout of the requests since they get to the layer, the requests with an earlier
+    /// [`Deadline`] are still bounded by it.
+    pub fn default_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = Some(timeout);
+        self
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = Timeout<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        Timeout {
+            inner,
+            default_timeout: self.default_timeout,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use motore::BoxError;
+
+    use super::*;
+    use crate::context::{Role, RpcCx, RpcInfo};
+
+    #[derive(Clone)]
+    struct Sleep(Duration);
+
+    impl<Cx: Send + 'static> Service<Cx, ()> for Sleep {
+        type Response = ();
+
+        type Error = BoxError;
+
+        type Future<'cx> = impl Future<Output = Result<(), BoxError>> + 'cx;
+
+        fn call<'cx, 's>(&'s mut self, _cx: &'cx mut Cx, _req: ()) -> Self::Future<'cx>
+        where
+            's: 'cx,
+        {
+            async move {
+                tokio::time::sleep(self.0).await;
+                Ok(())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let mut cx = RpcCx::new(RpcInfo::<()>::with_role(Role::Client), ());
+        let layer = TimeoutLayer::new().default_timeout(Duration::from_millis(50));
+
+        let mut svc = layer.layer(Sleep(Duration::from_millis(10)));
+        svc.call(&mut cx, ()).await.unwrap();
+
+        let mut svc = layer.layer(Sleep(Duration::from_secs(10)));
+        let err = svc.call(&mut cx, ()).await.unwrap_err();
+        assert!(err.downcast_ref::<Elapsed>().is_some());
+
+        // the deadline of the request is earlier
+        cx.extensions_mut()
+            .insert(Deadline(Instant::now() + Duration::from_millis(1)));
+        let mut svc = TimeoutLayer::new().layer(Sleep(Duration::from_secs(10)));
+        svc.call(&mut cx, ()).await.unwrap_err();
+    }
+}
@@ -1,19 +1,26 @@
 #![feature(generic_associated_types)]
 #![feature(type_alias_impl_trait)]
 #![feature(once_cell)]
+#![cfg_attr(feature = "forbid-unsafe", forbid(unsafe_code))]
 #![doc(
     html_logo_url = "https://github.com/cloudwego/volo/raw/main/.github/assets/logo.png?sanitize=true"
 )]
 #![cfg_attr(not(doctest), doc = include_str!("../README.md"))]
 
 pub use async_trait::async_trait;
-pub use motore::{layer, layer::Layer, service, Service};
+pub use motore::{layer::Layer, service, Service};
 pub use tokio::{main, spawn};
 
 pub mod context;
 pub mod discovery;
+pub mod layer;
 pub mod loadbalance;
 pub mod net;
+pub mod profile;
+pub mod registry;
+pub mod rt;
+pub mod service_info;
+pub mod tower_adapter;
 pub mod util;
 pub use hack::Unwrap;
 
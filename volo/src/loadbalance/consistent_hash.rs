@@ -0,0 +1,345 @@
+//! Consistent hashing of the requests, so that the requests of the same key, such as of the same
+//! user, are sent to the same instance, and only the keys of the changed instances move when the
+//! instances change.
+//!
+//! The key of a request is the [`RequestHash`] in the tags of the callee, set by the
+//! [`RequestHashLayer`] from the request, such as from a metadata of gRPC or a field of thrift,
+//! or by the tags of its callopt. The requests without it are spread randomly.
+//!
+//! Each instance has [`ConsistentHashBalance::virtual_nodes`] points on the ring for the average
+//! weight, and proportionally more or fewer for heavier or lighter weights. When the instance of
+//! a key fails, the next instances on the ring are tried.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::loadbalance::consistent_hash::{ConsistentHashBalance, RequestHash, RequestHashLayer};
+//!
+//! // thrift, hashing by a field of the request
+//! let client = ItemServiceClientBuilder::new("item")
+//!     .load_balance(ConsistentHashBalance::new())
+//!     .discover(discover)
+//!     .layer_outer(RequestHashLayer::new(|_cx: &ClientContext, req: &_| match req {
+//!         ItemServiceRequest::GetItem(req) => Some(RequestHash::of(&req.req.user_id)),
+//!         _ => None,
+//!     }))
+//!     .build();
+//!
+//! // gRPC, hashing by a metadata
+//! let client = ItemServiceClientBuilder::new("item")
+//!     .load_balance(ConsistentHashBalance::new())
+//!     .discover(discover)
+//!     .layer(RequestHashLayer::new(|_cx: &ClientContext, req: &Request<_>| {
+//!         req.metadata().get("x-user-id").map(|v| RequestHash::of(v.as_bytes()))
+//!     }))
+//!     .build();
+//! ```
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    future::Future,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use motore::{layer::Layer, service::Service};
+
+use super::LoadBalance;
+use crate::{
+    context::{Context, Endpoint},
+    discovery::{Change, Discover, Instance},
+    net::Address,
+};
+
+const DEFAULT_VIRTUAL_NODES: usize = 160;
+
+/// The hash of the key of a request, in the tags of the callee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestHash(pub u64);
+
+impl RequestHash {
+    /// Hashes the key.
+    pub fn of<K: Hash + ?Sized>(key: &K) -> Self {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        Self(hasher.finish())
+    }
+}
+
+/// Mixes the bits of the hash, so that the close keys, such as the small integers, are spread
+/// over the ring.
+fn mix(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^ (h >> 33)
+}
+
+/// The points of the instances of a key, sorted.
+#[derive(Debug)]
+struct Ring {
+    instances: Vec<Arc<Instance>>,
+    points: Vec<(u64, usize)>,
+}
+
+impl Ring {
+    fn new(instances: Vec<Arc<Instance>>, virtual_nodes: usize) -> Self {
+        let total: u64 = instances.iter().map(|i| i.weight.max(1) as u64).sum();
+        let average = (total as f64 / instances.len().max(1) as f64).max(1.0);
+        let mut points = Vec::new();
+        for (offset, instance) in instances.iter().enumerate() {
+            let replicas =
+                ((virtual_nodes as f64 * instance.weight.max(1) as f64 / average).round() as usize)
+                    .max(1);
+            for replica in 0..replicas {
+                let point = RequestHash::of(&(&instance.address, replica)).0;
+                points.push((mix(point), offset));
+            }
+        }
+        points.sort_unstable();
+        Self { instances, points }
+    }
+}
+
+/// Picks the instance of the request hash, then the next ones on the ring.
+#[derive(Debug)]
+pub struct ConsistentHashPicker {
+    ring: Arc<Ring>,
+    hash: u64,
+    // the next point to walk from, and the instances picked
+    cursor: Option<usize>,
+    picked: HashSet<usize>,
+}
+
+impl Iterator for ConsistentHashPicker {
+    type Item = Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let points = &self.ring.points;
+        if self.picked.len() >= self.ring.instances.len() || points.is_empty() {
+            return None;
+        }
+        let mut cursor = match self.cursor {
+            Some(cursor) => cursor,
+            None => points.partition_point(|(point, _)| *point < self.hash),
+        };
+        loop {
+            let (_, offset) = points[cursor % points.len()];
+            cursor += 1;
+            if self.picked.insert(offset) {
+                self.cursor = Some(cursor);
+                return Some(self.ring.instances[offset].address.clone());
+            }
+        }
+    }
+}
+
+/// The load balance picking the instances by the consistent hashing of the [`RequestHash`].
+#[derive(Debug, Clone)]
+pub struct ConsistentHashBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    router: DashMap<K, Arc<Ring>>,
+    virtual_nodes: usize,
+}
+
+impl<K> ConsistentHashBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    pub fn with_discover<D>(_: &D) -> Self
+    where
+        D: Discover<Key = K>,
+    {
+        Self::new()
+    }
+
+    pub fn new() -> Self {
+        Self {
+            router: DashMap::new(),
+            virtual_nodes: DEFAULT_VIRTUAL_NODES,
+        }
+    }
+
+    /// Sets the points on the ring of an instance of the average weight, the more the evener the
+    /// keys are spread.
+    ///
+    /// Defaults to 160.
+    pub fn virtual_nodes(mut self, virtual_nodes: usize) -> Self {
+        self.virtual_nodes = virtual_nodes.max(1);
+        self
+    }
+}
+
+impl<K> Default for ConsistentHashBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> LoadBalance<D> for ConsistentHashBalance<D::Key>
+where
+    D: Discover,
+{
+    type InstanceIter<'iter> = ConsistentHashPicker;
+    type Error = D::Error;
+    type GetFut<'future, 'iter> =
+        impl Future<Output = Result<Self::InstanceIter<'iter>, Self::Error>> + Send;
+
+    fn get_picker<'future, 'iter>(
+        &'iter self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Self::GetFut<'future, 'iter> {
+        async {
+            let key = discover.key(endpoint);
+            let ring = match self.router.entry(key) {
+                Entry::Occupied(e) => e.get().clone(),
+                Entry::Vacant(e) => {
+                    let instances = discover.discover(endpoint).await?;
+                    let ring = Arc::new(Ring::new(instances, self.virtual_nodes));
+                    e.insert(ring).value().clone()
+                }
+            };
+            let hash = match endpoint.get::<RequestHash>() {
+                Some(hash) => mix(hash.0),
+                None => rand::random(),
+            };
+            Ok(ConsistentHashPicker {
+                ring,
+                hash,
+                cursor: None,
+                picked: HashSet::new(),
+            })
+        }
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        if let Entry::Occupied(mut entry) = self.router.entry(changes.key.clone()) {
+            let mut instances = entry.get().instances.clone();
+            changes.apply(&mut instances);
+            entry.insert(Arc::new(Ring::new(instances, self.virtual_nodes)));
+        }
+    }
+
+    fn reset(&self) {
+        self.router.clear();
+    }
+}
+
+/// A [`Service`] setting the [`RequestHash`] of the requests in the tags of the callee.
+#[derive(Clone)]
+pub struct RequestHashService<S, F> {
+    inner: S,
+    f: F,
+}
+
+impl<Cx, Req, S, F> Service<Cx, Req> for RequestHashService<S, F>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + 'static,
+    F: Fn(&Cx, &Req) -> Option<RequestHash> + Send + Sync + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        // the hash set by the callopt is kept
+        let skip = cx
+            .rpc_info()
+            .callee()
+            .map_or(true, |callee| callee.contains::<RequestHash>());
+        if !skip {
+            if let Some(hash) = (self.f)(cx, &req) {
+                if let Some(callee) = cx.rpc_info_mut().callee_mut() {
+                    callee.insert(hash);
+                }
+            }
+        }
+        self.inner.call(cx, req)
+    }
+}
+
+/// A [`Layer`] that applies [`RequestHashService`], which must be outside the load balance.
+#[derive(Clone)]
+pub struct RequestHashLayer<F> {
+    f: F,
+}
+
+impl<F> RequestHashLayer<F> {
+    /// Creates a new [`RequestHashLayer`] hashing the requests by `f`, the requests it returns
+    /// `None` for are spread randomly.
+    pub fn new(f: F) -> Self {
+        Self { f }
+    }
+}
+
+impl<S, F> Layer<S> for RequestHashLayer<F> {
+    type Service = RequestHashService<S, F>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        RequestHashService { inner, f: self.f }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loadbalance::tests::instance;
+
+    fn pick(ring: &Arc<Ring>, key: &str) -> ConsistentHashPicker {
+        ConsistentHashPicker {
+            ring: ring.clone(),
+            hash: mix(RequestHash::of(key).0),
+            cursor: None,
+            picked: HashSet::new(),
+        }
+    }
+
+    #[test]
+    fn test_consistent_hash() {
+        let instances: Vec<_> = (1..=4).map(|port| instance(port, 10)).collect();
+        let ring = Arc::new(Ring::new(instances.clone(), 160));
+        assert_eq!(ring.points.len(), 640);
+
+        let keys: Vec<_> = (0..1000).map(|i| format!("user-{}", i)).collect();
+        let before: Vec<_> = keys
+            .iter()
+            .map(|k| pick(&ring, k).next().unwrap())
+            .collect();
+        // the same key to the same instance, and all the instances in the end
+        let all: Vec<_> = pick(&ring, &keys[0]).collect();
+        assert_eq!(all[0], before[0]);
+        assert_eq!(all.iter().collect::<HashSet<_>>().len(), 4);
+
+        // only the keys of the removed instance move
+        let removed = instances[3].address.clone();
+        let ring = Arc::new(Ring::new(instances[..3].to_vec(), 160));
+        for (key, before) in keys.iter().zip(before) {
+            if before != removed {
+                assert_eq!(pick(&ring, key).next().unwrap(), before);
+            }
+        }
+
+        // the heavier instances have more points
+        let ring = Ring::new(vec![instance(1, 30), instance(2, 10)], 100);
+        let heavy = ring
+            .points
+            .iter()
+            .filter(|(_, offset)| *offset == 0)
+            .count();
+        assert_eq!(heavy, 150);
+        assert_eq!(ring.points.len(), 200);
+    }
+}
@@ -2,9 +2,13 @@ use std::{fmt::Debug, future::Future, sync::Arc};
 
 use anyhow::{anyhow, Context as _};
 use motore::{BoxError, Service};
-use tracing::warn;
 
-use crate::{context::Context, discovery::Discover, loadbalance::LoadBalance, Layer};
+use crate::{
+    context::Context,
+    discovery::Discover,
+    loadbalance::{Evictions, LoadBalance, TriedAddresses},
+    Layer,
+};
 
 #[derive(Clone)]
 pub struct LoadBalanceService<D, LB, S> {
@@ -12,6 +16,7 @@ pub struct LoadBalanceService<D, LB, S> {
     load_balance: Arc<LB>,
     service: S,
     retry: usize,
+    evictions: Evictions,
 }
 
 impl<D, LB, S> LoadBalanceService<D, LB, S>
@@ -27,20 +32,18 @@ where
             load_balance: lb.clone(),
             service,
             retry,
+            evictions: Evictions::default(),
         };
 
-        if let Some(mut channel) = service.discover.watch() {
-            tokio::spawn(async move {
-                loop {
-                    match channel.recv().await {
-                        Ok(recv) => lb.rebalance(recv),
-                        Err(err) => warn!("[VOLO] discovering subscription error {:?}", err),
-                    }
-                }
-            });
-        }
+        super::watch_changes(&service.discover, lb);
         service
     }
+
+    /// Picks the instances evicted by `evictions` last.
+    pub fn with_evictions(mut self, evictions: Evictions) -> Self {
+        self.evictions = evictions;
+        self
+    }
 }
 
 impl<Cx, Req, D, LB, S> Service<Cx, Req> for LoadBalanceService<D, LB, S>
@@ -85,9 +88,16 @@ where
                             .map_err(Into::<BoxError>::into);
                     }
                 };
-                let mut call_count = 0;
-                for (addr, _) in picker.zip(0..self.retry + 1) {
-                    call_count += 1;
+                // the instances called by the previous attempts of the call, or evicted, are
+                // picked last
+                let picker = self.evictions.healthy_first(picker);
+                let tried = cx
+                    .extensions()
+                    .get::<TriedAddresses>()
+                    .cloned()
+                    .unwrap_or_default();
+                let mut last_err = None;
+                for (addr, _) in tried.untried_first(picker).zip(0..self.retry + 1) {
                     if let Some(callee) = cx.rpc_info_mut().callee_mut() {
                         callee.address = Some(addr.clone())
                     }
@@ -98,13 +108,18 @@ where
                         }
                         Err(err) => {
                             tracing::warn!("[VOLO] call endpoint: {:?} error: {:?}", addr, err);
+                            last_err = Some(err.into());
                         }
                     }
                 }
-                if call_count == 0 {
-                    tracing::warn!("[VOLO] zero call count, call info: {:?}", cx.rpc_info());
+                match last_err {
+                    // the error of the last attempt, so that the layers outside can tell it
+                    Some(err) => Err(err),
+                    None => {
+                        tracing::warn!("[VOLO] zero call count, call info: {:?}", cx.rpc_info());
+                        Err(anyhow!("load balance retry reaches end").into())
+                    }
                 }
-                Err(anyhow!("load balance retry reaches end").into())
             } else {
                 Err(anyhow!("load balance get empty endpoint").into())
             }
@@ -126,11 +141,14 @@ where
     }
 }
 
+/// The layer of [`LoadBalanceService`], the evictions of which are set by
+/// [`with_evictions`](Self::with_evictions).
 #[derive(Clone, Default, Copy)]
-pub struct LoadBalanceLayer<D, LB> {
+pub struct LoadBalanceLayer<D, LB, E = ()> {
     discover: D,
     load_balance: LB,
     retry_count: usize,
+    evictions: E,
 }
 
 impl<D, LB> LoadBalanceLayer<D, LB> {
@@ -139,6 +157,19 @@ impl<D, LB> LoadBalanceLayer<D, LB> {
             discover,
             load_balance,
             retry_count,
+            evictions: (),
+        }
+    }
+}
+
+impl<D, LB, E> LoadBalanceLayer<D, LB, E> {
+    /// Picks the instances evicted by `evictions` last.
+    pub fn with_evictions(self, evictions: Evictions) -> LoadBalanceLayer<D, LB, Evictions> {
+        LoadBalanceLayer {
+            discover: self.discover,
+            load_balance: self.load_balance,
+            retry_count: self.retry_count,
+            evictions,
         }
     }
 }
@@ -155,9 +186,22 @@ where
     }
 }
 
+impl<D, LB, S> Layer<S> for LoadBalanceLayer<D, LB, Evictions>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    type Service = LoadBalanceService<D, LB, S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        LoadBalanceService::new(self.discover, self.load_balance, inner, self.retry_count)
+            .with_evictions(self.evictions)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::convert::Infallible;
+    use std::{convert::Infallible, net::SocketAddr};
 
     use motore::service::service_fn;
 
@@ -174,7 +218,7 @@ mod tests {
 
     #[test]
     fn test_service() {
-        let discover = StaticDiscover::from(vec!["127.0.0.1:8000".parse().unwrap()]);
+        let discover = StaticDiscover::from(vec!["127.0.0.1:8000".parse::<SocketAddr>().unwrap()]);
         let lb = WeightedRandomBalance::with_discover(&discover);
         let service = service_fn(handle);
 
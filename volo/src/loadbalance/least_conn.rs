@@ -0,0 +1,212 @@
+//! Least connections by the power of two choices: of two instances picked at random, the one of
+//! fewer calls in flight for its weight is called, which avoids the herds of the clients on the
+//! single least loaded instance.
+//!
+//! The calls in flight are counted by this client only, from the pick of an instance until its
+//! picker is dropped, which is when the load balance service returns.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use rand::Rng;
+
+use super::LoadBalance;
+use crate::{
+    context::Endpoint,
+    discovery::{Change, Discover, Instance},
+    net::Address,
+};
+
+type InFlight = Arc<Mutex<HashMap<Address, usize>>>;
+
+/// Picks the less loaded one of two untried instances every time.
+#[derive(Debug)]
+pub struct LeastConnectionPicker {
+    instances: Arc<Vec<Arc<Instance>>>,
+    in_flight: InFlight,
+    // the offsets of the instances not picked yet
+    untried: Option<Vec<usize>>,
+    picked: Option<Address>,
+}
+
+impl LeastConnectionPicker {
+    fn release(&mut self) {
+        if let Some(address) = self.picked.take() {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            if let Some(count) = in_flight.get_mut(&address) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    in_flight.remove(&address);
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for LeastConnectionPicker {
+    type Item = Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // the previous pick is done when the next one is asked for
+        self.release();
+        let instances = &self.instances;
+        let untried = self
+            .untried
+            .get_or_insert_with(|| (0..instances.len()).collect());
+        if untried.is_empty() {
+            return None;
+        }
+
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let load = |offset: usize| {
+            let instance = &instances[offset];
+            let count = in_flight.get(&instance.address).copied().unwrap_or(0);
+            (count as f64 + 1.0) / instance.weight.max(1) as f64
+        };
+        let mut rng = rand::thread_rng();
+        let a = rng.gen_range(0..untried.len());
+        let chosen = if untried.len() == 1 {
+            a
+        } else {
+            // another one than a
+            let b = (a + rng.gen_range(1..untried.len())) % untried.len();
+            if load(untried[b]) < load(untried[a]) {
+                b
+            } else {
+                a
+            }
+        };
+        let address = instances[untried.swap_remove(chosen)].address.clone();
+        *in_flight.entry(address.clone()).or_default() += 1;
+        drop(in_flight);
+        self.picked = Some(address.clone());
+        Some(address)
+    }
+}
+
+impl Drop for LeastConnectionPicker {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// The load balance picking the instances of the fewest calls in flight for their weights.
+#[derive(Debug, Clone)]
+pub struct LeastConnectionBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    router: DashMap<K, Arc<Vec<Arc<Instance>>>>,
+    in_flight: InFlight,
+}
+
+impl<K> LeastConnectionBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    pub fn with_discover<D>(_: &D) -> Self
+    where
+        D: Discover<Key = K>,
+    {
+        Self::new()
+    }
+
+    pub fn new() -> Self {
+        Self {
+            router: DashMap::new(),
+            in_flight: Default::default(),
+        }
+    }
+}
+
+impl<K> Default for LeastConnectionBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> LoadBalance<D> for LeastConnectionBalance<D::Key>
+where
+    D: Discover,
+{
+    type InstanceIter<'iter> = LeastConnectionPicker;
+    type Error = D::Error;
+    type GetFut<'future, 'iter> =
+        impl Future<Output = Result<Self::InstanceIter<'iter>, Self::Error>> + Send;
+
+    fn get_picker<'future, 'iter>(
+        &'iter self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Self::GetFut<'future, 'iter> {
+        async {
+            let key = discover.key(endpoint);
+            let instances = match self.router.entry(key) {
+                Entry::Occupied(e) => e.get().clone(),
+                Entry::Vacant(e) => {
+                    let instances = Arc::new(discover.discover(endpoint).await?);
+                    e.insert(instances).value().clone()
+                }
+            };
+            Ok(LeastConnectionPicker {
+                instances,
+                in_flight: self.in_flight.clone(),
+                untried: None,
+                picked: None,
+            })
+        }
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        if let Entry::Occupied(mut entry) = self.router.entry(changes.key.clone()) {
+            changes.apply(Arc::make_mut(entry.get_mut()));
+        }
+    }
+
+    fn reset(&self) {
+        self.router.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loadbalance::tests::instance;
+
+    #[test]
+    fn test_least_connection() {
+        let instances = Arc::new(vec![instance(1, 1), instance(2, 1)]);
+        let in_flight = InFlight::default();
+        let picker = || LeastConnectionPicker {
+            instances: instances.clone(),
+            in_flight: in_flight.clone(),
+            untried: None,
+            picked: None,
+        };
+
+        let mut busy = picker();
+        let first = busy.next().unwrap();
+        // the other one is less loaded
+        for _ in 0..10 {
+            let mut p = picker();
+            assert_ne!(p.next().unwrap(), first);
+        }
+        // and tried after it
+        let mut p = picker();
+        p.next();
+        assert_eq!(p.next(), Some(first));
+        assert_eq!(p.next(), None);
+        drop(p);
+
+        drop(busy);
+        assert!(in_flight.lock().unwrap().is_empty());
+    }
+}
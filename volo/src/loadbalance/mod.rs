@@ -1,7 +1,19 @@
+pub mod consistent_hash;
 mod layer;
+pub mod least_conn;
+pub mod profile;
 pub mod random;
+pub mod round_robin;
 
-use std::future::Future;
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use async_broadcast::RecvError;
+use futures::stream::{BoxStream, StreamExt};
+use tokio::time::Instant;
 
 use self::layer::LoadBalanceLayer;
 use crate::{
@@ -30,20 +42,143 @@ where
         endpoint: &'future Endpoint,
         discover: &'future D,
     ) -> Self::GetFut<'future, 'iter>;
-    /// `reblance` is the callback method be used in service discovering subscription.
+    /// `reblance` is the callback method be used in service discovering subscription, which
+    /// applies the change to the cached instances.
     fn rebalance(&self, changes: Change<D::Key>);
+    /// `reset` is called when some changes are missed, such as when the subscription lags
+    /// behind, so that the cached instances are dropped and discovered again.
+    fn reset(&self) {}
+}
+
+/// Applies the changes of the discover to the load balance in background, until the discover
+/// stops sending them.
+pub fn watch_changes<D, LB>(discover: &D, load_balance: Arc<LB>)
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    if let Some(mut channel) = discover.watch() {
+        tokio::spawn(async move {
+            loop {
+                match channel.recv().await {
+                    Ok(recv) => load_balance.rebalance(recv),
+                    Err(RecvError::Overflowed(n)) => {
+                        tracing::warn!("[VOLO] discovering subscription missed {} changes", n);
+                        load_balance.reset();
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        });
+    }
+}
+
+/// The addresses called by the previous attempts of a call, in the extensions of the context,
+/// such as by the retry layers of the clients, so that the load balance services pick the other
+/// instances first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TriedAddresses(pub Vec<Address>);
+
+impl TriedAddresses {
+    /// Returns the addresses of the picker not tried yet first, then the tried ones.
+    pub fn untried_first<I>(&self, picker: I) -> UntriedFirst<I>
+    where
+        I: Iterator<Item = Address>,
+    {
+        UntriedFirst {
+            picker,
+            tried: self.0.clone(),
+            deferred: VecDeque::new(),
+        }
+    }
+}
+
+/// The instances evicted from the pickers for a while, such as by the circuit breakers of the
+/// endpoints, shared by the load balance services and the layers evicting them.
+///
+/// The evicted instances are picked last rather than never, so the calls still go somewhere when
+/// all the instances are evicted.
+#[derive(Debug, Clone, Default)]
+pub struct Evictions(Arc<Mutex<HashMap<Address, Instant>>>);
+
+impl Evictions {
+    /// Evicts the instance until `until`.
+    pub fn evict(&self, address: Address, until: Instant) {
+        self.0.lock().unwrap().insert(address, until);
+    }
+
+    /// Restores the instance before its eviction ends.
+    pub fn restore(&self, address: &Address) {
+        self.0.lock().unwrap().remove(address);
+    }
+
+    /// Returns whether the instance is evicted now.
+    pub fn is_evicted(&self, address: &Address) -> bool {
+        let now = Instant::now();
+        matches!(self.0.lock().unwrap().get(address), Some(until) if *until > now)
+    }
+
+    /// Returns the addresses of the picker not evicted first, then the evicted ones.
+    pub fn healthy_first<I>(&self, picker: I) -> UntriedFirst<I>
+    where
+        I: Iterator<Item = Address>,
+    {
+        let now = Instant::now();
+        let evicted = {
+            let mut evictions = self.0.lock().unwrap();
+            evictions.retain(|_, until| *until > now);
+            evictions.keys().cloned().collect()
+        };
+        UntriedFirst {
+            picker,
+            tried: evicted,
+            deferred: VecDeque::new(),
+        }
+    }
+}
+
+/// The iterator returned by [`TriedAddresses::untried_first`] and [`Evictions::healthy_first`].
+#[derive(Debug)]
+pub struct UntriedFirst<I> {
+    picker: I,
+    tried: Vec<Address>,
+    deferred: VecDeque<Address>,
+}
+
+impl<I> Iterator for UntriedFirst<I>
+where
+    I: Iterator<Item = Address>,
+{
+    type Item = Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for address in self.picker.by_ref() {
+            if !self.tried.contains(&address) {
+                return Some(address);
+            }
+            self.deferred.push_back(address);
+        }
+        self.deferred.pop_front()
+    }
 }
 
 pub trait MkLbLayer<S> {
     type Layer;
 
     fn make(self) -> Self::Layer;
+
+    /// Returns the addresses of the instances removed by the service discovery from now on, so
+    /// that the transport can close the connections to them instead of waiting for errors.
+    fn removed_instances(&self) -> Option<BoxStream<'static, Vec<Address>>> {
+        None
+    }
 }
 
 pub struct LbConfig<L, DISC> {
     load_balance: L,
     discover: DISC,
     retry_count: usize,
+    evictions: Evictions,
 }
 
 impl<L, DISC> LbConfig<L, DISC> {
@@ -52,6 +187,7 @@ impl<L, DISC> LbConfig<L, DISC> {
             load_balance,
             discover,
             retry_count: 0,
+            evictions: Evictions::default(),
         }
     }
 
@@ -60,6 +196,7 @@ impl<L, DISC> LbConfig<L, DISC> {
             load_balance,
             discover: self.discover,
             retry_count: self.retry_count,
+            evictions: self.evictions,
         }
     }
 
@@ -68,6 +205,7 @@ impl<L, DISC> LbConfig<L, DISC> {
             load_balance: self.load_balance,
             discover,
             retry_count: self.retry_count,
+            evictions: self.evictions,
         }
     }
 
@@ -76,15 +214,39 @@ impl<L, DISC> LbConfig<L, DISC> {
         self.retry_count = count;
         self
     }
+
+    /// Picks the instances evicted by `evictions` last, such as the ones evicted by the circuit
+    /// breakers.
+    pub fn evictions(mut self, evictions: Evictions) -> Self {
+        self.evictions = evictions;
+        self
+    }
 }
 
 pub struct CustomLayer<L>(pub L);
 
-impl<LB, DISC, S> MkLbLayer<S> for LbConfig<LB, DISC> {
-    type Layer = LoadBalanceLayer<DISC, LB>;
+impl<LB, DISC, S> MkLbLayer<S> for LbConfig<LB, DISC>
+where
+    DISC: Discover,
+{
+    type Layer = LoadBalanceLayer<DISC, LB, Evictions>;
 
     fn make(self) -> Self::Layer {
         LoadBalanceLayer::new(self.discover, self.load_balance, self.retry_count)
+            .with_evictions(self.evictions)
+    }
+
+    fn removed_instances(&self) -> Option<BoxStream<'static, Vec<Address>>> {
+        let channel = self.discover.watch()?;
+        Some(
+            channel
+                .filter_map(|change| async move {
+                    let removed: Vec<_> =
+                        change.removed.iter().map(|i| i.address.clone()).collect();
+                    (!removed.is_empty()).then_some(removed)
+                })
+                .boxed(),
+        )
     }
 }
 
@@ -95,3 +257,54 @@ impl<S, L> MkLbLayer<S> for CustomLayer<L> {
         self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::Instance;
+
+    /// Makes a local instance of the port, shared by the tests of the policies.
+    pub(super) fn instance(port: u16, weight: u32) -> Arc<Instance> {
+        Arc::new(Instance {
+            address: Address::Ip(([127, 0, 0, 1], port).into()),
+            weight,
+            tags: Default::default(),
+        })
+    }
+
+    #[test]
+    fn test_untried_first() {
+        let addrs: Vec<Address> = (1..=3)
+            .map(|port| Address::Ip(([127, 0, 0, 1], port).into()))
+            .collect();
+        let tried = TriedAddresses(vec![addrs[0].clone(), addrs[2].clone()]);
+        let picked: Vec<_> = tried.untried_first(addrs.clone().into_iter()).collect();
+        assert_eq!(
+            picked,
+            [addrs[1].clone(), addrs[0].clone(), addrs[2].clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_healthy_first() {
+        let addrs: Vec<Address> = (1..=3)
+            .map(|port| Address::Ip(([127, 0, 0, 1], port).into()))
+            .collect();
+        let evictions = Evictions::default();
+        let now = Instant::now();
+        evictions.evict(addrs[0].clone(), now + std::time::Duration::from_secs(10));
+        // the eviction has ended
+        evictions.evict(addrs[1].clone(), now);
+        assert!(evictions.is_evicted(&addrs[0]));
+        assert!(!evictions.is_evicted(&addrs[1]));
+
+        let picked: Vec<_> = evictions.healthy_first(addrs.clone().into_iter()).collect();
+        assert_eq!(
+            picked,
+            [addrs[1].clone(), addrs[2].clone(), addrs[0].clone()]
+        );
+
+        evictions.restore(&addrs[0]);
+        assert!(!evictions.is_evicted(&addrs[0]));
+    }
+}
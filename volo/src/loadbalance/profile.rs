@@ -0,0 +1,204 @@
+//! The load balance switched by the [`Profile::load_balance`] of the callee, such as reloaded by
+//! the [`FileWatcher`], so that the load balance of a client changes without rebuilding it.
+//!
+//! The callees without it are balanced randomly.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::{loadbalance::profile::ProfileBalance, profile::FileWatcher};
+//!
+//! let profiles = FileWatcher::new("profiles.json").watch()?;
+//! let client = ItemServiceClientBuilder::new("item")
+//!     .load_balance(ProfileBalance::new(profiles))
+//!     .discover(discover)
+//!     .build();
+//! ```
+//!
+//! [`Profile::load_balance`]: crate::profile::Profile::load_balance
+//! [`FileWatcher`]: crate::profile::FileWatcher
+
+use std::{future::Future, hash::Hash, sync::Arc};
+
+use tokio::sync::watch;
+
+use super::{
+    consistent_hash::{ConsistentHashBalance, ConsistentHashPicker},
+    least_conn::{LeastConnectionBalance, LeastConnectionPicker},
+    random::{InstancePicker, WeightedRandomBalance},
+    round_robin::{RoundRobinPicker, WeightedRoundRobinBalance},
+    LoadBalance,
+};
+use crate::{
+    context::Endpoint,
+    discovery::{Change, Discover},
+    net::Address,
+    profile::{LoadBalanceKind, Profiles},
+};
+
+/// The picker of the load balance of the profile.
+#[derive(Debug)]
+pub enum ProfilePicker {
+    Random(InstancePicker),
+    RoundRobin(RoundRobinPicker),
+    LeastConn(LeastConnectionPicker),
+    ConsistentHash(ConsistentHashPicker),
+}
+
+impl Iterator for ProfilePicker {
+    type Item = Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            ProfilePicker::Random(picker) => picker.next(),
+            ProfilePicker::RoundRobin(picker) => picker.next(),
+            ProfilePicker::LeastConn(picker) => picker.next(),
+            ProfilePicker::ConsistentHash(picker) => picker.next(),
+        }
+    }
+}
+
+/// The load balance picking the instances by the load balance of the profile of the callee.
+///
+/// All the load balances keep their caches of the instances up to date, so that switching to one
+/// doesn't discover the instances again.
+pub struct ProfileBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    profiles: watch::Receiver<Arc<Profiles>>,
+    random: WeightedRandomBalance<K>,
+    round_robin: WeightedRoundRobinBalance<K>,
+    least_conn: LeastConnectionBalance<K>,
+    consistent_hash: ConsistentHashBalance<K>,
+}
+
+impl<K> ProfileBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    pub fn new(profiles: watch::Receiver<Arc<Profiles>>) -> Self {
+        Self {
+            profiles,
+            random: WeightedRandomBalance::new(),
+            round_robin: WeightedRoundRobinBalance::new(),
+            least_conn: LeastConnectionBalance::new(),
+            consistent_hash: ConsistentHashBalance::new(),
+        }
+    }
+
+    /// Sets the virtual nodes of the consistent hashing, see
+    /// [`ConsistentHashBalance::virtual_nodes`].
+    pub fn virtual_nodes(mut self, virtual_nodes: usize) -> Self {
+        self.consistent_hash = self.consistent_hash.virtual_nodes(virtual_nodes);
+        self
+    }
+}
+
+impl<D> LoadBalance<D> for ProfileBalance<D::Key>
+where
+    D: Discover,
+{
+    type InstanceIter<'iter> = ProfilePicker;
+    type Error = D::Error;
+    type GetFut<'future, 'iter> =
+        impl Future<Output = Result<Self::InstanceIter<'iter>, Self::Error>> + Send;
+
+    fn get_picker<'future, 'iter>(
+        &'iter self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Self::GetFut<'future, 'iter> {
+        let kind = self
+            .profiles
+            .borrow()
+            .get(&endpoint.service_name)
+            .load_balance
+            .unwrap_or_default();
+        async move {
+            Ok(match kind {
+                LoadBalanceKind::Random => ProfilePicker::Random(
+                    LoadBalance::<D>::get_picker(&self.random, endpoint, discover).await?,
+                ),
+                LoadBalanceKind::RoundRobin => ProfilePicker::RoundRobin(
+                    LoadBalance::<D>::get_picker(&self.round_robin, endpoint, discover).await?,
+                ),
+                LoadBalanceKind::LeastConn => ProfilePicker::LeastConn(
+                    LoadBalance::<D>::get_picker(&self.least_conn, endpoint, discover).await?,
+                ),
+                LoadBalanceKind::ConsistentHash => ProfilePicker::ConsistentHash(
+                    LoadBalance::<D>::get_picker(&self.consistent_hash, endpoint, discover).await?,
+                ),
+            })
+        }
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        LoadBalance::<D>::rebalance(&self.random, changes.clone());
+        LoadBalance::<D>::rebalance(&self.round_robin, changes.clone());
+        LoadBalance::<D>::rebalance(&self.least_conn, changes.clone());
+        LoadBalance::<D>::rebalance(&self.consistent_hash, changes);
+    }
+
+    fn reset(&self) {
+        LoadBalance::<D>::reset(&self.random);
+        LoadBalance::<D>::reset(&self.round_robin);
+        LoadBalance::<D>::reset(&self.least_conn);
+        LoadBalance::<D>::reset(&self.consistent_hash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::{
+        discovery::StaticDiscover, loadbalance::consistent_hash::RequestHash, profile::Profile,
+    };
+
+    async fn first(
+        lb: &ProfileBalance<()>,
+        endpoint: &Endpoint,
+        discover: &StaticDiscover,
+    ) -> Address {
+        let mut picker = LoadBalance::<StaticDiscover>::get_picker(lb, endpoint, discover)
+            .await
+            .unwrap();
+        picker.next().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_switch() {
+        let discover = StaticDiscover::from(
+            (1..=4)
+                .map(|port| ([127, 0, 0, 1], port).into())
+                .collect::<Vec<SocketAddr>>(),
+        );
+        let profile = |kind| Profiles {
+            default: Profile {
+                load_balance: Some(kind),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (sender, receiver) = watch::channel(Arc::new(profile(LoadBalanceKind::RoundRobin)));
+        let lb = ProfileBalance::new(receiver);
+        let mut endpoint = Endpoint::new("item".into());
+
+        let picked = [
+            first(&lb, &endpoint, &discover).await,
+            first(&lb, &endpoint, &discover).await,
+        ];
+        assert_ne!(picked[0], picked[1]);
+
+        sender
+            .send(Arc::new(profile(LoadBalanceKind::ConsistentHash)))
+            .unwrap();
+        endpoint.insert(RequestHash::of("user"));
+        let picked = first(&lb, &endpoint, &discover).await;
+        for _ in 0..10 {
+            assert_eq!(first(&lb, &endpoint, &discover).await, picked);
+        }
+    }
+}
@@ -1,5 +1,11 @@
 use core::cell::OnceCell;
-use std::{future::Future, hash::Hash, sync::Arc};
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use dashmap::{mapref::entry::Entry, DashMap};
 use rand::Rng;
@@ -65,10 +71,49 @@ impl Iterator for InstancePicker {
     }
 }
 
+/// The effective weight of the new instances starts from this fraction of their weights.
+const SLOW_START_MIN_FRACTION: f64 = 0.1;
+
+/// A new instance warming up.
+#[derive(Debug, Clone)]
+struct Warming {
+    since: Instant,
+    // the full weight
+    weight: u32,
+}
+
+impl Warming {
+    /// Returns the effective weight, `None` if the instance is warmed up.
+    fn weight(&self, window: Duration, now: Instant) -> Option<u32> {
+        let elapsed = now.saturating_duration_since(self.since);
+        if elapsed >= window {
+            return None;
+        }
+        let fraction = SLOW_START_MIN_FRACTION
+            + (1.0 - SLOW_START_MIN_FRACTION) * elapsed.as_secs_f64() / window.as_secs_f64();
+        Some(((self.weight as f64 * fraction).ceil() as u32).max(1))
+    }
+}
+
+fn with_weight(instance: &Arc<Instance>, weight: u32) -> Arc<Instance> {
+    if instance.weight == weight {
+        return instance.clone();
+    }
+    Arc::new(Instance {
+        weight,
+        ..Instance::clone(instance)
+    })
+}
+
 #[derive(Debug, Clone)]
 struct WeightedInstances {
     sum_of_weights: isize,
     instances: Vec<Arc<Instance>>,
+    // the offsets of the instances by the address, to apply the changes in place
+    index: HashMap<Address, usize>,
+    // the new instances of the effective weights lower than their weights
+    warming: HashMap<Address, Warming>,
+    warmed_at: Option<Instant>,
 }
 
 impl From<Vec<Arc<Instance>>> for WeightedInstances {
@@ -76,11 +121,99 @@ impl From<Vec<Arc<Instance>>> for WeightedInstances {
         let sum_of_weights = instances
             .iter()
             .fold(0, |lhs, rhs| lhs + rhs.weight as isize);
+        let index = instances
+            .iter()
+            .enumerate()
+            .map(|(offset, instance)| (instance.address.clone(), offset))
+            .collect();
         Self {
             instances,
             sum_of_weights,
+            index,
+            warming: HashMap::new(),
+            warmed_at: None,
+        }
+    }
+}
+
+impl WeightedInstances {
+    /// Applies the change, taking the time of the changed instances only.
+    ///
+    /// The added instances start warming up if `slow_start` is set.
+    fn apply<K>(&mut self, change: &Change<K>, slow_start: Option<Duration>) {
+        for removed in &change.removed {
+            self.warming.remove(&removed.address);
+            let offset = match self.index.remove(&removed.address) {
+                Some(offset) => offset,
+                None => continue,
+            };
+            self.sum_of_weights -= self.instances.swap_remove(offset).weight as isize;
+            if let Some(moved) = self.instances.get(offset) {
+                self.index.insert(moved.address.clone(), offset);
+            }
+        }
+        let now = Instant::now();
+        for instance in change.updated.iter().chain(&change.added) {
+            let offset = self.index.get(&instance.address).copied();
+            if offset.is_none() && slow_start.is_some() {
+                self.warming.insert(
+                    instance.address.clone(),
+                    Warming {
+                        since: now,
+                        weight: instance.weight,
+                    },
+                );
+            } else if let Some(warming) = self.warming.get_mut(&instance.address) {
+                warming.weight = instance.weight;
+            }
+            let instance = match (self.warming.get(&instance.address), slow_start) {
+                (Some(warming), Some(window)) => with_weight(
+                    instance,
+                    warming.weight(window, now).unwrap_or(warming.weight),
+                ),
+                _ => instance.clone(),
+            };
+
+            self.sum_of_weights += instance.weight as isize;
+            match offset {
+                Some(offset) => {
+                    self.sum_of_weights -= self.instances[offset].weight as isize;
+                    self.instances[offset] = instance;
+                }
+                None => {
+                    self.index
+                        .insert(instance.address.clone(), self.instances.len());
+                    self.instances.push(instance);
+                }
+            }
         }
     }
+
+    /// Returns whether the effective weights should be raised, which is done every tenth of the
+    /// window.
+    fn should_warm_up(&self, window: Duration, now: Instant) -> bool {
+        !self.warming.is_empty()
+            && self
+                .warmed_at
+                .map_or(true, |at| now.saturating_duration_since(at) >= window / 10)
+    }
+
+    /// Raises the effective weights of the warming instances by the time elapsed.
+    fn warm_up(&mut self, window: Duration, now: Instant) {
+        let (instances, index) = (&mut self.instances, &self.index);
+        let sum_of_weights = &mut self.sum_of_weights;
+        self.warming.retain(|address, warming| {
+            let weight = warming.weight(window, now);
+            if let Some(&offset) = index.get(address) {
+                let instance = &mut instances[offset];
+                *sum_of_weights -= instance.weight as isize;
+                *instance = with_weight(instance, weight.unwrap_or(warming.weight));
+                *sum_of_weights += instance.weight as isize;
+            }
+            weight.is_some()
+        });
+        self.warmed_at = Some(now);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -89,6 +222,7 @@ where
     K: Hash + PartialEq + Eq + Send + Sync + 'static,
 {
     router: DashMap<K, Arc<WeightedInstances>>,
+    slow_start: Option<Duration>,
 }
 
 impl<K> WeightedRandomBalance<K>
@@ -99,16 +233,25 @@ where
     where
         D: Discover<Key = K>,
     {
-        Self {
-            router: DashMap::new(),
-        }
+        Self::new()
     }
 
     pub fn new() -> Self {
         Self {
             router: DashMap::new(),
+            slow_start: None,
         }
     }
+
+    /// Ramps the weights of the new instances of the service discovery from a tenth to the full
+    /// over the window, so that the instances just started, such as of cold caches, are not
+    /// overloaded at once.
+    ///
+    /// The instances of the first discovery are not ramped. Defaults to disabled.
+    pub fn slow_start(mut self, window: Duration) -> Self {
+        self.slow_start = (!window.is_zero()).then_some(window);
+        self
+    }
 }
 
 impl<D> LoadBalance<D> for WeightedRandomBalance<D::Key>
@@ -128,7 +271,15 @@ where
         async {
             let key = discover.key(endpoint);
             let weighted_list = match self.router.entry(key) {
-                Entry::Occupied(e) => e.get().clone(),
+                Entry::Occupied(mut e) => {
+                    if let Some(window) = self.slow_start {
+                        let now = Instant::now();
+                        if e.get().should_warm_up(window, now) {
+                            Arc::make_mut(e.get_mut()).warm_up(window, now);
+                        }
+                    }
+                    e.get().clone()
+                }
                 Entry::Vacant(e) => {
                     let instances =
                         Arc::new(WeightedInstances::from(discover.discover(endpoint).await?));
@@ -146,16 +297,31 @@ where
     }
 
     fn rebalance(&self, changes: Change<D::Key>) {
-        if let Entry::Occupied(entry) = self.router.entry(changes.key.clone()) {
-            entry.replace_entry(Arc::new(WeightedInstances::from(changes.all)));
+        if let Entry::Occupied(mut entry) = self.router.entry(changes.key.clone()) {
+            // copies the instances only if some pickers are still using them
+            Arc::make_mut(entry.get_mut()).apply(&changes, self.slow_start);
         }
     }
+
+    fn reset(&self) {
+        self.router.clear();
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{LoadBalance, WeightedRandomBalance};
-    use crate::{context::Endpoint, discovery::StaticDiscover};
+    use std::{
+        net::SocketAddr,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::{LoadBalance, WeightedInstances, WeightedRandomBalance};
+    use crate::{
+        context::Endpoint,
+        discovery::{diff_instances, Instance, StaticDiscover},
+        net::Address,
+    };
 
     #[tokio::test]
     async fn test_weighted_random() {
@@ -165,7 +331,7 @@ mod tests {
             tags: Default::default(),
         };
         let discover = StaticDiscover::from(vec![
-            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.1:8000".parse::<SocketAddr>().unwrap(),
             "127.0.0.2:9000".parse().unwrap(),
         ]);
         let lb = WeightedRandomBalance::with_discover(&discover);
@@ -174,4 +340,53 @@ mod tests {
         assert!(all.len() == 2);
         assert!(all[0] != all[1]);
     }
+
+    #[test]
+    fn test_apply_change() {
+        let instance = |port: u16, weight| {
+            Arc::new(Instance {
+                address: Address::Ip(([127, 0, 0, 1], port).into()),
+                weight,
+                tags: Default::default(),
+            })
+        };
+        let prev = vec![instance(1, 1), instance(2, 1), instance(3, 1)];
+        let next = vec![instance(3, 1), instance(2, 5), instance(4, 2)];
+        let mut weighted = WeightedInstances::from(prev.clone());
+        weighted.apply(&diff_instances((), prev, next.clone()), None);
+
+        let expected = WeightedInstances::from(next);
+        assert_eq!(weighted.sum_of_weights, expected.sum_of_weights);
+        assert_eq!(weighted.instances.len(), 3);
+        for (offset, instance) in weighted.instances.iter().enumerate() {
+            assert_eq!(weighted.index[&instance.address], offset);
+            assert!(expected.instances.contains(instance));
+        }
+    }
+
+    #[test]
+    fn test_slow_start() {
+        let instance = |port: u16| {
+            Arc::new(Instance {
+                address: Address::Ip(([127, 0, 0, 1], port).into()),
+                weight: 10,
+                tags: Default::default(),
+            })
+        };
+        let window = Duration::from_secs(10);
+        let mut weighted = WeightedInstances::from(vec![instance(1)]);
+        weighted.apply(
+            &diff_instances((), vec![instance(1)], vec![instance(1), instance(2)]),
+            Some(window),
+        );
+        assert_eq!(weighted.sum_of_weights, 11);
+
+        let now = Instant::now();
+        assert!(weighted.should_warm_up(window, now));
+        weighted.warm_up(window, now + window / 2);
+        assert_eq!(weighted.sum_of_weights, 16);
+        weighted.warm_up(window, now + window);
+        assert_eq!(weighted.sum_of_weights, 20);
+        assert!(!weighted.should_warm_up(window, now + window));
+    }
 }
@@ -0,0 +1,217 @@
+//! Smooth weighted round-robin, which spreads the picks of the heavier instances evenly among
+//! the others instead of in bursts, like nginx.
+
+use std::{
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+
+use super::LoadBalance;
+use crate::{
+    context::Endpoint,
+    discovery::{Change, Discover, Instance},
+    net::Address,
+};
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// The instances of a key and the order of picking them.
+#[derive(Debug)]
+struct Schedule {
+    instances: Vec<Arc<Instance>>,
+    // the offsets of the instances, in the order of one round
+    sequence: Vec<usize>,
+    next: AtomicUsize,
+}
+
+impl Schedule {
+    fn new(instances: Vec<Arc<Instance>>) -> Self {
+        // the weights are divided by their gcd, keeping the rounds short
+        let divisor = instances
+            .iter()
+            .fold(0, |d, i| gcd(d, i.weight.max(1)))
+            .max(1);
+        let weights: Vec<i64> = instances
+            .iter()
+            .map(|i| (i.weight.max(1) / divisor) as i64)
+            .collect();
+        let total: i64 = weights.iter().sum();
+
+        let mut current = vec![0; weights.len()];
+        let mut sequence = Vec::with_capacity(total as usize);
+        for _ in 0..total {
+            let mut best = 0;
+            for (offset, weight) in weights.iter().enumerate() {
+                current[offset] += weight;
+                if current[offset] > current[best] {
+                    best = offset;
+                }
+            }
+            current[best] -= total;
+            sequence.push(best);
+        }
+        Self {
+            instances,
+            sequence,
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Picks the next instance of the round first, then the rest of the instances in order.
+#[derive(Debug)]
+pub struct RoundRobinPicker {
+    schedule: Arc<Schedule>,
+    first: Option<usize>,
+    picked: usize,
+}
+
+impl Iterator for RoundRobinPicker {
+    type Item = Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let instances = &self.schedule.instances;
+        if self.picked >= instances.len() {
+            return None;
+        }
+        let offset = match self.first {
+            None => {
+                let sequence = &self.schedule.sequence;
+                let next = self.schedule.next.fetch_add(1, Ordering::Relaxed);
+                let first = sequence[next % sequence.len()];
+                self.first = Some(first);
+                first
+            }
+            Some(first) => (first + self.picked) % instances.len(),
+        };
+        self.picked += 1;
+        Some(instances[offset].address.clone())
+    }
+}
+
+/// The load balance picking the instances by smooth weighted round-robin.
+#[derive(Debug, Clone)]
+pub struct WeightedRoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    router: DashMap<K, Arc<Schedule>>,
+}
+
+impl<K> WeightedRoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    pub fn with_discover<D>(_: &D) -> Self
+    where
+        D: Discover<Key = K>,
+    {
+        Self::new()
+    }
+
+    pub fn new() -> Self {
+        Self {
+            router: DashMap::new(),
+        }
+    }
+}
+
+impl<K> Default for WeightedRoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> LoadBalance<D> for WeightedRoundRobinBalance<D::Key>
+where
+    D: Discover,
+{
+    type InstanceIter<'iter> = RoundRobinPicker;
+    type Error = D::Error;
+    type GetFut<'future, 'iter> =
+        impl Future<Output = Result<Self::InstanceIter<'iter>, Self::Error>> + Send;
+
+    fn get_picker<'future, 'iter>(
+        &'iter self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Self::GetFut<'future, 'iter> {
+        async {
+            let key = discover.key(endpoint);
+            let schedule = match self.router.entry(key) {
+                Entry::Occupied(e) => e.get().clone(),
+                Entry::Vacant(e) => {
+                    let schedule = Arc::new(Schedule::new(discover.discover(endpoint).await?));
+                    e.insert(schedule).value().clone()
+                }
+            };
+            Ok(RoundRobinPicker {
+                schedule,
+                first: None,
+                picked: 0,
+            })
+        }
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        if let Entry::Occupied(mut entry) = self.router.entry(changes.key.clone()) {
+            let mut instances = entry.get().instances.clone();
+            changes.apply(&mut instances);
+            entry.insert(Arc::new(Schedule::new(instances)));
+        }
+    }
+
+    fn reset(&self) {
+        self.router.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loadbalance::tests::instance;
+
+    #[test]
+    fn test_schedule() {
+        let schedule = Schedule::new(vec![instance(1, 50), instance(2, 10), instance(3, 10)]);
+        assert_eq!(schedule.sequence, [0, 0, 1, 0, 2, 0, 0]);
+
+        let schedule = Arc::new(schedule);
+        let picker = RoundRobinPicker {
+            schedule: schedule.clone(),
+            first: None,
+            picked: 0,
+        };
+        let picked: Vec<_> = picker.collect();
+        assert_eq!(picked.len(), 3);
+        assert_eq!(picked[0], instance(1, 1).address);
+        // the next round goes on
+        let picked: Vec<_> = (0..6)
+            .map(|_| {
+                let mut picker = RoundRobinPicker {
+                    schedule: schedule.clone(),
+                    first: None,
+                    picked: 0,
+                };
+                picker.next().unwrap()
+            })
+            .collect();
+        assert_eq!(picked[1], instance(2, 1).address);
+        assert_eq!(picked[3], instance(3, 1).address);
+    }
+}
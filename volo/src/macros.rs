@@ -1,10 +1,10 @@
 #[macro_export]
 macro_rules! volo_unreachable {
     () => {
-        #[cfg(not(feature = "unsafe_unchecked"))]
+        #[cfg(any(not(feature = "unsafe_unchecked"), feature = "forbid-unsafe"))]
         unreachable!();
 
-        #[cfg(feature = "unsafe_unchecked")]
+        #[cfg(all(feature = "unsafe_unchecked", not(feature = "forbid-unsafe")))]
         unsafe {
             std::hint::unreachable_unchecked();
         }
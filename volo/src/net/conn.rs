@@ -1,5 +1,6 @@
 use std::{
     io,
+    net::IpAddr,
     pin::Pin,
     task::{Context, Poll},
 };
@@ -12,25 +13,73 @@ use tokio::{
 
 use super::Address;
 
-#[derive(Clone)]
+#[derive(Debug, Default, Clone)]
 pub struct ConnInfo {
     pub peer_addr: Option<Address>,
+    pub local_addr: Option<Address>,
+    /// The parameters negotiated by the TLS handshake, `None` if the connection isn't over TLS.
+    pub tls: Option<TlsInfo>,
+}
+
+/// The parameters negotiated by a TLS handshake.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// The protocol negotiated by ALPN, such as `h2`.
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// The server name sent by the client by SNI, only known on the server side.
+    pub server_name: Option<String>,
+    /// The TLS version, such as `TLSv1_3`.
+    pub protocol_version: Option<String>,
+    /// The cipher suite, such as `TLS13_AES_128_GCM_SHA256`.
+    pub cipher_suite: Option<String>,
+    /// The DER certificate chain verified of the peer, the leaf first, empty if the peer doesn't
+    /// send one, such as the clients without mTLS.
+    pub peer_certificates: Vec<Vec<u8>>,
+    /// The identity in the leaf certificate of the peer.
+    pub peer_identity: Option<PeerIdentity>,
+}
+
+/// The identity in the certificate of the peer verified by the TLS handshake, such as of the
+/// client by mTLS, in the context extensions of the server for the handlers and the authorization
+/// layers.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PeerIdentity {
+    /// The subject, formatted as RFC 4514 does, such as `CN=client,O=example`.
+    pub subject: String,
+    /// The common name of the subject.
+    pub common_name: Option<String>,
+    /// The DNS names of the subject alternative names.
+    pub dns_sans: Vec<String>,
+    /// The uris of the subject alternative names, such as the SPIFFE id.
+    pub uri_sans: Vec<String>,
+    /// The emails of the subject alternative names.
+    pub email_sans: Vec<String>,
+    /// The IPs of the subject alternative names.
+    pub ip_sans: Vec<IpAddr>,
 }
 
 pub trait DynStream: AsyncRead + AsyncWrite + Send + 'static {}
 
 impl<T> DynStream for T where T: AsyncRead + AsyncWrite + Send + 'static {}
 
+#[allow(clippy::large_enum_variant)]
 #[pin_project(project = IoStreamProj)]
 pub enum ConnStream {
     Tcp(#[pin] TcpStream),
     Unix(#[pin] UnixStream),
+    #[cfg(feature = "rustls")]
+    Tls(#[pin] tokio_rustls::TlsStream<TcpStream>),
+    /// Any other stream, such as the one made by a custom [`Dialer`](super::dial::Dialer).
+    Dyn(#[pin] Pin<Box<dyn DynStream>>),
 }
 
 #[pin_project(project = OwnedWriteHalfProj)]
 pub enum OwnedWriteHalf {
     Tcp(#[pin] tcp::OwnedWriteHalf),
     Unix(#[pin] unix::OwnedWriteHalf),
+    #[cfg(feature = "rustls")]
+    Tls(#[pin] tokio::io::WriteHalf<tokio_rustls::TlsStream<TcpStream>>),
+    Dyn(#[pin] tokio::io::WriteHalf<Pin<Box<dyn DynStream>>>),
 }
 
 impl AsyncWrite for OwnedWriteHalf {
@@ -42,6 +91,9 @@ impl AsyncWrite for OwnedWriteHalf {
         match self.project() {
             OwnedWriteHalfProj::Tcp(half) => half.poll_write(cx, buf),
             OwnedWriteHalfProj::Unix(half) => half.poll_write(cx, buf),
+            #[cfg(feature = "rustls")]
+            OwnedWriteHalfProj::Tls(half) => half.poll_write(cx, buf),
+            OwnedWriteHalfProj::Dyn(half) => half.poll_write(cx, buf),
         }
     }
 
@@ -49,6 +101,9 @@ impl AsyncWrite for OwnedWriteHalf {
         match self.project() {
             OwnedWriteHalfProj::Tcp(half) => half.poll_flush(cx),
             OwnedWriteHalfProj::Unix(half) => half.poll_flush(cx),
+            #[cfg(feature = "rustls")]
+            OwnedWriteHalfProj::Tls(half) => half.poll_flush(cx),
+            OwnedWriteHalfProj::Dyn(half) => half.poll_flush(cx),
         }
     }
 
@@ -56,6 +111,9 @@ impl AsyncWrite for OwnedWriteHalf {
         match self.project() {
             OwnedWriteHalfProj::Tcp(half) => half.poll_shutdown(cx),
             OwnedWriteHalfProj::Unix(half) => half.poll_shutdown(cx),
+            #[cfg(feature = "rustls")]
+            OwnedWriteHalfProj::Tls(half) => half.poll_shutdown(cx),
+            OwnedWriteHalfProj::Dyn(half) => half.poll_shutdown(cx),
         }
     }
 }
@@ -64,6 +122,9 @@ impl AsyncWrite for OwnedWriteHalf {
 pub enum OwnedReadHalf {
     Tcp(#[pin] tcp::OwnedReadHalf),
     Unix(#[pin] unix::OwnedReadHalf),
+    #[cfg(feature = "rustls")]
+    Tls(#[pin] tokio::io::ReadHalf<tokio_rustls::TlsStream<TcpStream>>),
+    Dyn(#[pin] tokio::io::ReadHalf<Pin<Box<dyn DynStream>>>),
 }
 
 impl AsyncRead for OwnedReadHalf {
@@ -75,6 +136,9 @@ impl AsyncRead for OwnedReadHalf {
         match self.project() {
             OwnedReadHalfProj::Tcp(half) => half.poll_read(cx, buf),
             OwnedReadHalfProj::Unix(half) => half.poll_read(cx, buf),
+            #[cfg(feature = "rustls")]
+            OwnedReadHalfProj::Tls(half) => half.poll_read(cx, buf),
+            OwnedReadHalfProj::Dyn(half) => half.poll_read(cx, buf),
         }
     }
 }
@@ -91,6 +155,15 @@ impl ConnStream {
                 let (rh, wh) = stream.into_split();
                 (OwnedReadHalf::Unix(rh), OwnedWriteHalf::Unix(wh))
             }
+            #[cfg(feature = "rustls")]
+            ConnStream::Tls(stream) => {
+                let (rh, wh) = tokio::io::split(stream);
+                (OwnedReadHalf::Tls(rh), OwnedWriteHalf::Tls(wh))
+            }
+            ConnStream::Dyn(stream) => {
+                let (rh, wh) = tokio::io::split(stream);
+                (OwnedReadHalf::Dyn(rh), OwnedWriteHalf::Dyn(wh))
+            }
         }
     }
 }
@@ -120,6 +193,9 @@ impl AsyncRead for ConnStream {
         match self.project() {
             IoStreamProj::Tcp(s) => s.poll_read(cx, buf),
             IoStreamProj::Unix(s) => s.poll_read(cx, buf),
+            #[cfg(feature = "rustls")]
+            IoStreamProj::Tls(s) => s.poll_read(cx, buf),
+            IoStreamProj::Dyn(s) => s.poll_read(cx, buf),
         }
     }
 }
@@ -134,6 +210,9 @@ impl AsyncWrite for ConnStream {
         match self.project() {
             IoStreamProj::Tcp(s) => s.poll_write(cx, buf),
             IoStreamProj::Unix(s) => s.poll_write(cx, buf),
+            #[cfg(feature = "rustls")]
+            IoStreamProj::Tls(s) => s.poll_write(cx, buf),
+            IoStreamProj::Dyn(s) => s.poll_write(cx, buf),
         }
     }
 
@@ -142,6 +221,9 @@ impl AsyncWrite for ConnStream {
         match self.project() {
             IoStreamProj::Tcp(s) => s.poll_flush(cx),
             IoStreamProj::Unix(s) => s.poll_flush(cx),
+            #[cfg(feature = "rustls")]
+            IoStreamProj::Tls(s) => s.poll_flush(cx),
+            IoStreamProj::Dyn(s) => s.poll_flush(cx),
         }
     }
 
@@ -150,16 +232,38 @@ impl AsyncWrite for ConnStream {
         match self.project() {
             IoStreamProj::Tcp(s) => s.poll_shutdown(cx),
             IoStreamProj::Unix(s) => s.poll_shutdown(cx),
+            #[cfg(feature = "rustls")]
+            IoStreamProj::Tls(s) => s.poll_shutdown(cx),
+            IoStreamProj::Dyn(s) => s.poll_shutdown(cx),
         }
     }
 }
 
 impl ConnStream {
+    /// Wraps any stream, whose peer address is unknown.
+    pub fn new_dyn(stream: impl DynStream) -> Self {
+        ConnStream::Dyn(Box::pin(stream))
+    }
+
     #[inline]
     pub fn peer_addr(&self) -> Option<Address> {
         match self {
             ConnStream::Tcp(s) => s.peer_addr().map(Address::from).ok(),
             ConnStream::Unix(s) => s.peer_addr().ok().and_then(|s| Address::try_from(s).ok()),
+            #[cfg(feature = "rustls")]
+            ConnStream::Tls(s) => s.get_ref().0.peer_addr().map(Address::from).ok(),
+            ConnStream::Dyn(_) => None,
+        }
+    }
+
+    #[inline]
+    pub fn local_addr(&self) -> Option<Address> {
+        match self {
+            ConnStream::Tcp(s) => s.local_addr().map(Address::from).ok(),
+            ConnStream::Unix(s) => s.local_addr().ok().and_then(|s| Address::try_from(s).ok()),
+            #[cfg(feature = "rustls")]
+            ConnStream::Tls(s) => s.get_ref().0.local_addr().map(Address::from).ok(),
+            ConnStream::Dyn(_) => None,
         }
     }
 }
@@ -182,8 +286,12 @@ where
     #[inline]
     fn from(i: T) -> Self {
         let i = i.into();
-        let peer_addr = i.peer_addr();
-        Conn::new(i, ConnInfo { peer_addr })
+        let info = ConnInfo {
+            peer_addr: i.peer_addr(),
+            local_addr: i.local_addr(),
+            tls: None,
+        };
+        Conn::new(i, info)
     }
 }
 
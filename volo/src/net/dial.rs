@@ -1,15 +1,105 @@
-use std::{io, net::TcpStream as StdTcpStream};
+use std::{fmt, future::Future, io, net::TcpStream as StdTcpStream, sync::Arc};
 
+use futures::future::{BoxFuture, FutureExt};
 use tokio::{
     net::{TcpStream, UnixStream},
     time::{timeout, Duration},
 };
 
-use super::{conn::Conn, Address};
+use super::{
+    conn::{Conn, ConnInfo, ConnStream, DynStream},
+    Address,
+};
+
+/// Makes the connections of the clients, such as through a tunnel, by a custom TLS stack, or to
+/// an in-memory stream in the tests, instead of dialing the addresses by tcp or unix socket.
+///
+/// The streams other than tcp and unix socket can be wrapped by
+/// [`ConnStream::new_dyn`](super::conn::ConnStream::new_dyn).
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use volo::net::{conn::{Conn, ConnInfo, ConnStream}, dial::MakeConnection, Address};
+///
+/// let make_connection = MakeConnection::new(None).with_dialer(|addr: Address| async move {
+///     let stream = my_tunnel::open(addr).await?;
+///     Ok(Conn::new(ConnStream::new_dyn(stream), ConnInfo::default()))
+/// });
+/// ```
+pub trait Dialer: Send + Sync + 'static {
+    fn dial(&self, addr: Address) -> BoxFuture<'static, io::Result<Conn>>;
+}
+
+impl<F, Fut> Dialer for F
+where
+    F: Fn(Address) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = io::Result<Conn>> + Send + 'static,
+{
+    fn dial(&self, addr: Address) -> BoxFuture<'static, io::Result<Conn>> {
+        self(addr).boxed()
+    }
+}
+
+/// A [`Dialer`] of a closure making the streams of any `AsyncRead + AsyncWrite`, such as of an
+/// in-memory pipe or of a custom tunnel, created by [`connector`].
+#[derive(Clone)]
+pub struct Connector<F>(F);
 
-#[derive(Default, Debug, Clone)]
+/// Creates a [`Connector`] of the closure, the streams it makes are wrapped as the connections to
+/// the address dialed.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use volo::net::dial::connector;
+///
+/// let dialer = connector(|addr: Address| async move { my_tunnel::open(addr).await });
+/// ```
+pub fn connector<F, Fut, IO>(f: F) -> Connector<F>
+where
+    F: Fn(Address) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = io::Result<IO>> + Send + 'static,
+    IO: DynStream,
+{
+    Connector(f)
+}
+
+impl<F, Fut, IO> Dialer for Connector<F>
+where
+    F: Fn(Address) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = io::Result<IO>> + Send + 'static,
+    IO: DynStream,
+{
+    fn dial(&self, addr: Address) -> BoxFuture<'static, io::Result<Conn>> {
+        let stream = (self.0)(addr.clone());
+        async move {
+            let info = ConnInfo {
+                peer_addr: Some(addr),
+                ..Default::default()
+            };
+            Ok(Conn::new(ConnStream::new_dyn(stream.await?), info))
+        }
+        .boxed()
+    }
+}
+
+#[derive(Default, Clone)]
 pub struct MakeConnection {
     cfg: Option<Config>,
+    #[cfg(feature = "rustls")]
+    tls: Option<super::tls::TlsConnector>,
+    dialer: Option<Arc<dyn Dialer>>,
+}
+
+impl fmt::Debug for MakeConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("MakeConnection");
+        d.field("cfg", &self.cfg);
+        #[cfg(feature = "rustls")]
+        d.field("tls", &self.tls);
+        d.field("dialer", &self.dialer.is_some()).finish()
+    }
 }
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -29,12 +119,39 @@ impl Config {
 
 impl MakeConnection {
     pub fn new(cfg: Option<Config>) -> Self {
-        Self { cfg }
+        Self {
+            cfg,
+            #[cfg(feature = "rustls")]
+            tls: None,
+            dialer: None,
+        }
+    }
+
+    /// Establishes TLS sessions over the tcp connections made.
+    #[cfg(feature = "rustls")]
+    pub fn with_tls(mut self, tls: super::tls::TlsConnector) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Makes the connections by the dialer, the config and the TLS are left to the dialer.
+    pub fn with_dialer(mut self, dialer: impl Dialer) -> Self {
+        self.dialer = Some(Arc::new(dialer));
+        self
+    }
+
+    /// Makes the connections by the shared dialer, see [`MakeConnection::with_dialer`].
+    pub fn with_shared_dialer(mut self, dialer: Arc<dyn Dialer>) -> Self {
+        self.dialer = Some(dialer);
+        self
     }
 }
 
 impl MakeConnection {
     pub async fn make_connection(&self, addr: Address) -> Result<Conn, io::Error> {
+        if let Some(dialer) = &self.dialer {
+            return dialer.dial(addr).await;
+        }
         match addr {
             Address::Ip(addr) => {
                 let stream = if let Some(cfg) = self.cfg {
@@ -55,9 +172,20 @@ impl MakeConnection {
                     TcpStream::connect(addr).await?
                 };
                 stream.set_nodelay(true)?;
+                #[cfg(feature = "rustls")]
+                if let Some(tls) = &self.tls {
+                    return tls.connect(Conn::from(stream)).await;
+                }
                 Ok(Conn::from(stream))
             }
             Address::Unix(addr) => UnixStream::connect(addr).await.map(Conn::from),
         }
     }
 }
+
+impl Dialer for MakeConnection {
+    fn dial(&self, addr: Address) -> BoxFuture<'static, io::Result<Conn>> {
+        let this = self.clone();
+        async move { this.make_connection(addr).await }.boxed()
+    }
+}
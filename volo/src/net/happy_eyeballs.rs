@@ -0,0 +1,105 @@
+//! Dials the addresses of both the IP families of a host as [RFC 8305] (Happy Eyeballs), so that
+//! an unreachable family, such as a broken IPv6 route, only delays the connection a little
+//! instead of failing or hanging it.
+//!
+//! The addresses are interleaved by the families, starting with the family of the first, and
+//! each attempt starts when the previous one fails or after the delay, whichever comes first.
+//! The first connection made wins, and the other attempts are canceled.
+//!
+//! [RFC 8305]: https://www.rfc-editor.org/rfc/rfc8305
+
+use std::{io, net::SocketAddr, time::Duration};
+
+use futures::{
+    future::{select, Either},
+    stream::FuturesUnordered,
+    StreamExt,
+};
+use tokio::net::TcpStream;
+
+/// The delay before starting the next attempt, recommended by the RFC.
+pub const DEFAULT_DELAY: Duration = Duration::from_millis(250);
+
+/// Interleaves the addresses by the families, starting with the family of the first.
+pub(crate) fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = match addrs.first() {
+        Some(addr) => addr.is_ipv6(),
+        None => return addrs,
+    };
+    let mut interleaved = Vec::with_capacity(addrs.len());
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_v6);
+    let (mut preferred, mut other) = (preferred.into_iter(), other.into_iter());
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => break,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+    interleaved
+}
+
+/// Connects to the first reachable of the addresses.
+pub async fn connect(addrs: Vec<SocketAddr>, delay: Duration) -> io::Result<TcpStream> {
+    let mut pending = interleave(addrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    match pending.next() {
+        Some(addr) => attempts.push(TcpStream::connect(addr)),
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no address to connect",
+            ))
+        }
+    }
+    let mut last_err = None;
+    loop {
+        let done = if !pending.as_slice().is_empty() {
+            let sleep = tokio::time::sleep(delay);
+            futures::pin_mut!(sleep);
+            let fired = select(attempts.next(), sleep).await;
+            match fired {
+                Either::Left((done, _)) => done,
+                // starts the next attempt without waiting for the previous ones
+                Either::Right(_) => None,
+            }
+        } else {
+            match attempts.next().await {
+                Some(done) => Some(done),
+                None => break,
+            }
+        };
+        match done {
+            Some(Ok(stream)) => return Ok(stream),
+            Some(Err(err)) => last_err = Some(err),
+            None => {}
+        }
+        if let Some(addr) = pending.next() {
+            attempts.push(TcpStream::connect(addr));
+        } else if attempts.is_empty() {
+            break;
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::Other, "no attempt made")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interleave() {
+        let v4 = |i: u8| SocketAddr::from(([10, 0, 0, i], 80));
+        let v6 = |i: u16| SocketAddr::from(([0xfd00, 0, 0, 0, 0, 0, 0, i], 80));
+        assert_eq!(
+            interleave(vec![v6(1), v6(2), v6(3), v4(1)]),
+            vec![v6(1), v4(1), v6(2), v6(3)]
+        );
+        assert_eq!(
+            interleave(vec![v4(1), v6(1), v4(2), v6(2)]),
+            vec![v4(1), v6(1), v4(2), v6(2)]
+        );
+        assert_eq!(interleave(vec![]), vec![]);
+    }
+}
@@ -1,9 +1,24 @@
 pub mod conn;
 pub mod dial;
+pub mod happy_eyeballs;
 pub mod incoming;
 mod probe;
+pub mod proxy;
+#[cfg(all(feature = "spiffe", unix))]
+pub mod spiffe;
+pub mod throttle;
+#[cfg(feature = "rustls")]
+pub mod tls;
+#[cfg(feature = "rustls")]
+mod x509;
 
-use std::{borrow::Cow, fmt, net::Ipv6Addr, path::Path};
+use std::{
+    borrow::Cow,
+    fmt, io,
+    net::{Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 pub use incoming::{Incoming, MakeIncoming};
 
@@ -37,11 +52,35 @@ impl fmt::Display for Address {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Address::Ip(addr) => write!(f, "{}", addr),
-            Address::Unix(_) => write!(f, "-"),
+            Address::Unix(path) => write!(f, "{}", path.display()),
         }
     }
 }
 
+/// Parses the `ip:port` addresses, and the unix socket paths of the `unix:` scheme, such as
+/// `unix:///run/proxy.sock` or `unix:relative.sock`.
+impl FromStr for Address {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s
+            .strip_prefix("unix://")
+            .or_else(|| s.strip_prefix("unix:"))
+        {
+            if path.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unix socket without a path: {}", s),
+                ));
+            }
+            return Ok(PathBuf::from(path).into());
+        }
+        s.parse::<SocketAddr>()
+            .map(Address::from)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", e, s)))
+    }
+}
+
 impl From<std::net::SocketAddr> for Address {
     fn from(addr: std::net::SocketAddr) -> Self {
         Address::Ip(addr)
@@ -54,6 +93,18 @@ impl From<Cow<'static, Path>> for Address {
     }
 }
 
+impl From<PathBuf> for Address {
+    fn from(path: PathBuf) -> Self {
+        Address::Unix(Cow::Owned(path))
+    }
+}
+
+impl From<&'static Path> for Address {
+    fn from(path: &'static Path) -> Self {
+        Address::Unix(Cow::Borrowed(path))
+    }
+}
+
 impl TryFrom<tokio::net::unix::SocketAddr> for Address {
     type Error = std::io::Error;
 
@@ -71,3 +122,33 @@ impl TryFrom<tokio::net::unix::SocketAddr> for Address {
         )))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            "127.0.0.1:8080".parse::<Address>().unwrap(),
+            Address::from(SocketAddr::from(([127, 0, 0, 1], 8080)))
+        );
+        assert_eq!(
+            "unix:///run/proxy.sock".parse::<Address>().unwrap(),
+            Address::from(PathBuf::from("/run/proxy.sock"))
+        );
+        assert_eq!(
+            "unix:proxy.sock".parse::<Address>().unwrap(),
+            Address::from(PathBuf::from("proxy.sock"))
+        );
+        // displayed as the path, so that it parses back
+        let address = "unix:///run/proxy.sock".parse::<Address>().unwrap();
+        assert_eq!(address.to_string(), "/run/proxy.sock");
+        assert_eq!(
+            format!("unix://{}", address).parse::<Address>().unwrap(),
+            address
+        );
+        "unix://".parse::<Address>().unwrap_err();
+        "localhost".parse::<Address>().unwrap_err();
+    }
+}
@@ -0,0 +1,559 @@
+//! The workload identity of [SPIFFE] for mTLS, by the X.509 SVIDs of the Workload API.
+//!
+//! [`X509Source`] streams the SVID of the workload and the trust bundle from the Workload API
+//! socket of the agent, such as SPIRE, and makes the [`TlsAcceptor`]s and the [`TlsConnector`]s
+//! presenting the SVID and verifying the peers by the bundle. The configs are reloaded when the
+//! agent rotates the SVID or the bundle, the established connections are kept.
+//!
+//! The peers are authorized by their SPIFFE ids, the uri SANs of their certificates, with the
+//! [`Authorizer`].
+//!
+//! This module requires the `spiffe` feature.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::net::spiffe::{Authorizer, X509Source};
+//!
+//! // from `SPIFFE_ENDPOINT_SOCKET`
+//! let source = X509Source::connect(None).await?;
+//! let acceptor = source.acceptor(Authorizer::exact(["spiffe://example.org/frontend"]), vec![])?;
+//! let connector = source.connector(
+//!     "item.example.org",
+//!     Authorizer::trust_domain("example.org"),
+//!     vec![],
+//! )?;
+//! ```
+//!
+//! [SPIFFE]: https://spiffe.io
+
+use std::{
+    io,
+    sync::{Arc, Mutex, RwLock, Weak},
+    time::{Duration, SystemTime},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use prost::Message;
+use tokio_rustls::rustls::{
+    self,
+    client::{ServerCertVerified, ServerCertVerifier},
+    server::{AllowAnyAuthenticatedClient, ClientCertVerified, ClientCertVerifier},
+    Certificate, DistinguishedNames, PrivateKey, RootCertStore, ServerName,
+};
+
+use super::{
+    tls::{TlsAcceptor, TlsConnector},
+    x509,
+};
+
+const ENDPOINT_ENV: &str = "SPIFFE_ENDPOINT_SOCKET";
+const FETCH_X509_SVID_PATH: &str = "/SpiffeWorkloadAPI/FetchX509SVID";
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Clone, PartialEq, Message)]
+struct X509SvidRequest {}
+
+#[derive(Clone, PartialEq, Message)]
+struct X509SvidResponse {
+    #[prost(message, repeated, tag = "1")]
+    svids: Vec<X509Svid>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct X509Svid {
+    #[prost(string, tag = "1")]
+    spiffe_id: String,
+    #[prost(bytes = "vec", tag = "2")]
+    x509_svid: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    x509_svid_key: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    bundle: Vec<u8>,
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn h2_error(e: h2::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// Decides whether a peer of the SPIFFE id is authorized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Authorizer {
+    /// Any peer verified by the bundle.
+    Any,
+    /// The peers of the ids.
+    Exact(Vec<String>),
+    /// The peers in the trust domain, such as `example.org`.
+    TrustDomain(String),
+}
+
+impl Authorizer {
+    pub fn exact<I: IntoIterator<Item = T>, T: Into<String>>(ids: I) -> Self {
+        Authorizer::Exact(ids.into_iter().map(Into::into).collect())
+    }
+
+    pub fn trust_domain(trust_domain: impl Into<String>) -> Self {
+        Authorizer::TrustDomain(trust_domain.into())
+    }
+
+    /// Returns whether the id is authorized.
+    pub fn authorize(&self, id: &str) -> bool {
+        match self {
+            Authorizer::Any => true,
+            Authorizer::Exact(ids) => ids.iter().any(|i| i == id),
+            Authorizer::TrustDomain(td) => id
+                .strip_prefix("spiffe://")
+                .and_then(|rest| rest.split('/').next())
+                .map_or(false, |domain| domain == td),
+        }
+    }
+
+    fn verify(&self, cert: &Certificate) -> Result<(), rustls::Error> {
+        let ids = x509::uri_sans(&cert.0);
+        // an SVID has exactly one SPIFFE id
+        match ids.iter().find(|id| id.starts_with("spiffe://")) {
+            Some(id) if self.authorize(id) => Ok(()),
+            Some(id) => Err(rustls::Error::General(format!(
+                "the peer {} is not authorized",
+                id
+            ))),
+            None => Err(rustls::Error::InvalidCertificateData(
+                "no SPIFFE id in the certificate".into(),
+            )),
+        }
+    }
+}
+
+/// The SVID of the workload, and the bundle to verify the peers.
+struct Svid {
+    spiffe_id: String,
+    chain: Vec<Certificate>,
+    key: PrivateKey,
+    bundle: Vec<Certificate>,
+}
+
+impl Svid {
+    fn parse(svid: X509Svid) -> io::Result<Self> {
+        let split = |der: &[u8]| {
+            x509::split_certs(der)
+                .filter(|certs| !certs.is_empty())
+                .map(|certs| certs.into_iter().map(Certificate).collect::<Vec<_>>())
+                .ok_or_else(|| invalid_data(format!("invalid certificates of {}", svid.spiffe_id)))
+        };
+        Ok(Self {
+            chain: split(&svid.x509_svid)?,
+            bundle: split(&svid.bundle)?,
+            key: PrivateKey(svid.x509_svid_key),
+            spiffe_id: svid.spiffe_id,
+        })
+    }
+
+    fn roots(&self) -> io::Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        for cert in &self.bundle {
+            roots
+                .add(cert)
+                .map_err(|e| invalid_data(format!("invalid bundle: {}", e)))?;
+        }
+        Ok(roots)
+    }
+
+    fn server_config(
+        &self,
+        authorizer: &Authorizer,
+        alpn_protocols: &[Vec<u8>],
+    ) -> io::Result<rustls::ServerConfig> {
+        let verifier = Arc::new(ClientVerifier {
+            inner: AllowAnyAuthenticatedClient::new(self.roots()?),
+            authorizer: authorizer.clone(),
+        });
+        let mut config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(self.chain.clone(), self.key.clone())
+            .map_err(|e| invalid_data(e.to_string()))?;
+        config.alpn_protocols = alpn_protocols.to_vec();
+        Ok(config)
+    }
+
+    fn client_config(
+        &self,
+        authorizer: &Authorizer,
+        alpn_protocols: &[Vec<u8>],
+    ) -> io::Result<rustls::ClientConfig> {
+        let verifier = Arc::new(ServerVerifier {
+            roots: self.bundle.clone(),
+            authorizer: authorizer.clone(),
+        });
+        let mut config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(verifier)
+            .with_single_cert(self.chain.clone(), self.key.clone())
+            .map_err(|e| invalid_data(e.to_string()))?;
+        config.alpn_protocols = alpn_protocols.to_vec();
+        Ok(config)
+    }
+}
+
+/// Verifies the clients by the bundle and the authorizer.
+struct ClientVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+    authorizer: Authorizer,
+}
+
+impl ClientCertVerifier for ClientVerifier {
+    fn client_auth_root_subjects(&self) -> Option<DistinguishedNames> {
+        self.inner.client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.inner
+            .verify_client_cert(end_entity, intermediates, now)?;
+        self.authorizer.verify(end_entity)?;
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+static SIGNATURE_ALGORITHMS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// Verifies the servers by the bundle and the authorizer, instead of the server name, as the
+/// SVIDs don't have the DNS names.
+struct ServerVerifier {
+    roots: Vec<Certificate>,
+    authorizer: Authorizer,
+}
+
+impl ServerCertVerifier for ServerVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let invalid = |e: webpki::Error| rustls::Error::InvalidCertificateData(e.to_string());
+        let cert = webpki::EndEntityCert::try_from(end_entity.0.as_ref()).map_err(invalid)?;
+        let anchors = self
+            .roots
+            .iter()
+            .map(|root| webpki::TrustAnchor::try_from_cert_der(&root.0))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(invalid)?;
+        let intermediates = intermediates
+            .iter()
+            .map(|c| c.0.as_ref())
+            .collect::<Vec<_>>();
+        let now = webpki::Time::try_from(now).map_err(|_| rustls::Error::FailedToGetCurrentTime)?;
+        cert.verify_is_valid_tls_server_cert(
+            SIGNATURE_ALGORITHMS,
+            &webpki::TlsServerTrustAnchors(&anchors),
+            &intermediates,
+            now,
+        )
+        .map_err(invalid)?;
+        self.authorizer.verify(end_entity)?;
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Streams the responses of `FetchX509SVID` over the unix socket.
+struct Fetcher {
+    body: h2::RecvStream,
+    buf: BytesMut,
+}
+
+impl Fetcher {
+    async fn connect(path: &str) -> io::Result<Self> {
+        let stream = tokio::net::UnixStream::connect(path).await?;
+        let (client, conn) = h2::client::handshake(stream).await.map_err(h2_error)?;
+        tokio::spawn(async move {
+            if let Err(e) = conn.await {
+                tracing::debug!("[VOLO] workload api connection closed: {}", e);
+            }
+        });
+        let mut client = client.ready().await.map_err(h2_error)?;
+
+        let req = http::Request::post(format!("http://localhost{}", FETCH_X509_SVID_PATH))
+            .header("content-type", "application/grpc")
+            .header("te", "trailers")
+            // required by the Workload API to tell the calls of the workloads
+            .header("workload.spiffe.io", "true")
+            .body(())
+            .map_err(|e| invalid_data(e.to_string()))?;
+        let (resp, mut send) = client.send_request(req, false).map_err(h2_error)?;
+        let mut frame = BytesMut::new();
+        frame.put_u8(0);
+        frame.put_u32(X509SvidRequest {}.encoded_len() as u32);
+        X509SvidRequest {}
+            .encode(&mut frame)
+            .map_err(|e| invalid_data(e.to_string()))?;
+        send.send_data(frame.freeze(), true).map_err(h2_error)?;
+
+        let resp = resp.await.map_err(h2_error)?;
+        // a trailers-only response of the failure
+        if let Some(status) = resp.headers().get("grpc-status").filter(|s| *s != "0") {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("workload api failed with grpc-status {:?}", status),
+            ));
+        }
+        Ok(Self {
+            body: resp.into_body(),
+            buf: BytesMut::new(),
+        })
+    }
+
+    /// Returns the next response, `None` if the stream ends.
+    async fn next(&mut self) -> io::Result<Option<X509SvidResponse>> {
+        loop {
+            if let Some(msg) = self.decode()? {
+                return Ok(Some(msg));
+            }
+            match self.body.data().await {
+                Some(data) => {
+                    let data = data.map_err(h2_error)?;
+                    let _ = self.body.flow_control().release_capacity(data.len());
+                    self.buf.extend_from_slice(&data);
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn decode(&mut self) -> io::Result<Option<X509SvidResponse>> {
+        if self.buf.len() < 5 {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes(self.buf[1..5].try_into().unwrap()) as usize;
+        if self.buf.len() < 5 + len {
+            return Ok(None);
+        }
+        if self.buf[0] != 0 {
+            return Err(invalid_data("compressed responses are not supported"));
+        }
+        self.buf.advance(5);
+        let msg: Bytes = self.buf.split_to(len).freeze();
+        X509SvidResponse::decode(msg)
+            .map(Some)
+            .map_err(|e| invalid_data(e.to_string()))
+    }
+}
+
+enum Target {
+    Acceptor(TlsAcceptor),
+    Connector(TlsConnector),
+}
+
+/// The acceptors and the connectors with their authorizers and ALPN protocols.
+type Targets = Vec<(Target, Authorizer, Vec<Vec<u8>>)>;
+
+struct Shared {
+    svid: RwLock<Arc<Svid>>,
+    // reloaded on every update of the SVID
+    targets: Mutex<Targets>,
+}
+
+impl Shared {
+    fn update(&self, svid: Svid) {
+        let svid = Arc::new(svid);
+        *self.svid.write().unwrap() = svid.clone();
+        let targets = self.targets.lock().unwrap();
+        for (target, authorizer, alpn) in &*targets {
+            let result = match target {
+                Target::Acceptor(acceptor) => svid
+                    .server_config(authorizer, alpn)
+                    .map(|config| acceptor.reload(config)),
+                Target::Connector(connector) => svid
+                    .client_config(authorizer, alpn)
+                    .map(|config| connector.reload(config)),
+            };
+            if let Err(e) = result {
+                tracing::warn!("[VOLO] failed to reload the SVID: {}", e);
+            }
+        }
+        tracing::info!("[VOLO] SVID of {} updated", svid.spiffe_id);
+    }
+}
+
+/// The source of the X.509 SVID of the workload, kept updated from the Workload API until
+/// dropped.
+#[derive(Clone)]
+pub struct X509Source {
+    shared: Arc<Shared>,
+}
+
+impl X509Source {
+    /// Connects to the Workload API at `endpoint` or `SPIFFE_ENDPOINT_SOCKET`, such as
+    /// `unix:///run/spire/sockets/agent.sock`, and waits for the first SVID.
+    ///
+    /// The first SVID of the response is used if there are many.
+    pub async fn connect(endpoint: Option<&str>) -> io::Result<Self> {
+        let endpoint = match endpoint {
+            Some(endpoint) => endpoint.to_string(),
+            None => std::env::var(ENDPOINT_ENV).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} is not set", ENDPOINT_ENV),
+                )
+            })?,
+        };
+        let path = endpoint
+            .strip_prefix("unix://")
+            .or_else(|| endpoint.strip_prefix("unix:"))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("only the unix endpoints are supported: {}", endpoint),
+                )
+            })?
+            .to_string();
+
+        let mut fetcher = Fetcher::connect(&path).await?;
+        let svid = Self::next_svid(&mut fetcher)
+            .await?
+            .ok_or_else(|| invalid_data("the workload api closed without any SVID"))?;
+        let shared = Arc::new(Shared {
+            svid: RwLock::new(Arc::new(svid)),
+            targets: Default::default(),
+        });
+        tokio::spawn(Self::watch(Arc::downgrade(&shared), path, fetcher));
+        Ok(Self { shared })
+    }
+
+    async fn next_svid(fetcher: &mut Fetcher) -> io::Result<Option<Svid>> {
+        match fetcher.next().await? {
+            Some(resp) => match resp.svids.into_iter().next() {
+                Some(svid) => Svid::parse(svid).map(Some),
+                None => Err(invalid_data("no SVID in the response")),
+            },
+            None => Ok(None),
+        }
+    }
+
+    async fn watch(shared: Weak<Shared>, path: String, mut fetcher: Fetcher) {
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            match Self::next_svid(&mut fetcher).await {
+                Ok(Some(svid)) => {
+                    let shared = match shared.upgrade() {
+                        Some(shared) => shared,
+                        None => return,
+                    };
+                    shared.update(svid);
+                    backoff = Duration::from_secs(1);
+                    continue;
+                }
+                Ok(None) => tracing::warn!("[VOLO] workload api stream ended"),
+                Err(e) => tracing::warn!("[VOLO] workload api stream failed: {}", e),
+            }
+            // reconnects until the source is dropped, the current SVID is kept meanwhile
+            loop {
+                if shared.strong_count() == 0 {
+                    return;
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                match Fetcher::connect(&path).await {
+                    Ok(f) => {
+                        fetcher = f;
+                        break;
+                    }
+                    Err(e) => tracing::warn!("[VOLO] failed to connect to workload api: {}", e),
+                }
+            }
+        }
+    }
+
+    /// Returns the SPIFFE id of the workload.
+    pub fn spiffe_id(&self) -> String {
+        self.shared.svid.read().unwrap().spiffe_id.clone()
+    }
+
+    /// Returns the acceptor presenting the SVID and authorizing the clients, kept updated with
+    /// the SVID.
+    pub fn acceptor(
+        &self,
+        authorizer: Authorizer,
+        alpn_protocols: Vec<Vec<u8>>,
+    ) -> io::Result<TlsAcceptor> {
+        let config = self
+            .shared
+            .svid
+            .read()
+            .unwrap()
+            .server_config(&authorizer, &alpn_protocols)?;
+        let acceptor = TlsAcceptor::new(config);
+        self.shared.targets.lock().unwrap().push((
+            Target::Acceptor(acceptor.clone()),
+            authorizer,
+            alpn_protocols,
+        ));
+        Ok(acceptor)
+    }
+
+    /// Returns the connector presenting the SVID and authorizing the servers, kept updated with
+    /// the SVID.
+    ///
+    /// The `server_name` is only sent by SNI, the servers are verified by their SPIFFE ids.
+    pub fn connector(
+        &self,
+        server_name: &str,
+        authorizer: Authorizer,
+        alpn_protocols: Vec<Vec<u8>>,
+    ) -> io::Result<TlsConnector> {
+        let config = self
+            .shared
+            .svid
+            .read()
+            .unwrap()
+            .client_config(&authorizer, &alpn_protocols)?;
+        let connector = TlsConnector::new(config, server_name)?;
+        self.shared.targets.lock().unwrap().push((
+            Target::Connector(connector.clone()),
+            authorizer,
+            alpn_protocols,
+        ));
+        Ok(connector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorizer() {
+        assert!(Authorizer::Any.authorize("spiffe://example.org/a"));
+        let exact = Authorizer::exact(["spiffe://example.org/a"]);
+        assert!(exact.authorize("spiffe://example.org/a"));
+        assert!(!exact.authorize("spiffe://example.org/b"));
+        let td = Authorizer::trust_domain("example.org");
+        assert!(td.authorize("spiffe://example.org/b"));
+        assert!(!td.authorize("spiffe://example.org.evil/b"));
+        assert!(!td.authorize("https://example.org/b"));
+    }
+}
@@ -0,0 +1,330 @@
+//! Limits the throughput and delays the data of the connections, to reproduce the slow networks
+//! in the tests, such as the streaming RPCs over a congested link.
+//!
+//! The data read or written through a [`Throttled`] stream arrives at the other end as if sent
+//! over a link of the [`Throttle::rate`], and then after the [`Throttle::delay`] plus a random
+//! [`Throttle::jitter`], keeping the order of the data. The written data arriving later is sent
+//! to the inner stream on flushing.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use std::time::Duration;
+//!
+//! use volo::net::{dial::MakeConnection, throttle::Throttle};
+//!
+//! let throttle = Throttle::new()
+//!     .rate(64 * 1024)
+//!     .delay(Duration::from_millis(100))
+//!     .jitter(Duration::from_millis(20));
+//! let client = ItemServiceClientBuilder::new("item")
+//!     .dialer(throttle.dialer(MakeConnection::default()))
+//!     .build();
+//! ```
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{
+    future::{BoxFuture, FutureExt},
+    ready,
+};
+use rand::Rng;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{Instant, Sleep},
+};
+
+use super::{
+    conn::{Conn, ConnStream},
+    dial::Dialer,
+    Address,
+};
+
+/// The max size of a chunk, so that a large write at a low rate arrives gradually.
+const MAX_CHUNK: usize = 4 * 1024;
+
+/// The max data held in each direction, over which the stream stops reading or writing.
+const MAX_QUEUED: usize = 64 * 1024;
+
+/// The shape of the throttled link.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Throttle {
+    rate: Option<u64>,
+    delay: Duration,
+    jitter: Duration,
+}
+
+impl Throttle {
+    /// Creates a throttle of no limit and no delay.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the bytes per second of each direction.
+    ///
+    /// Defaults to no limit.
+    pub fn rate(mut self, bytes_per_sec: u64) -> Self {
+        self.rate = (bytes_per_sec > 0).then_some(bytes_per_sec);
+        self
+    }
+
+    /// Sets the latency added to the data of each direction.
+    ///
+    /// Defaults to zero.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Sets the max random latency added on top of [`Throttle::delay`].
+    ///
+    /// Defaults to zero.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Returns a dialer throttling the connections made by `dialer`.
+    pub fn dialer<D: Dialer>(self, dialer: D) -> ThrottleDialer<D> {
+        ThrottleDialer {
+            inner: dialer,
+            throttle: self,
+        }
+    }
+}
+
+/// The data in flight of one direction.
+struct Link {
+    throttle: Throttle,
+    chunks: VecDeque<(Instant, Vec<u8>)>,
+    queued: usize,
+    // when the link finishes sending the queued data
+    busy_until: Instant,
+    // when the last chunk arrives, the later ones never arrive before it
+    last_arrival: Instant,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl Link {
+    fn new(throttle: Throttle) -> Self {
+        let now = Instant::now();
+        Self {
+            throttle,
+            chunks: VecDeque::new(),
+            queued: 0,
+            busy_until: now,
+            last_arrival: now,
+            sleep: None,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        for chunk in data.chunks(MAX_CHUNK) {
+            let now = Instant::now();
+            let mut sent = now.max(self.busy_until);
+            if let Some(rate) = self.throttle.rate {
+                sent += Duration::from_secs_f64(chunk.len() as f64 / rate as f64);
+            }
+            self.busy_until = sent;
+            let mut arrival = sent + self.throttle.delay;
+            if !self.throttle.jitter.is_zero() {
+                arrival += self.throttle.jitter.mul_f64(rand::thread_rng().gen());
+            }
+            self.last_arrival = arrival.max(self.last_arrival);
+            self.chunks.push_back((self.last_arrival, chunk.to_vec()));
+            self.queued += chunk.len();
+        }
+    }
+
+    /// Polls until the first chunk arrives, ready at once if there is none.
+    fn poll_arrival(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let arrival = match self.chunks.front() {
+            Some((arrival, _)) if *arrival > Instant::now() => *arrival,
+            _ => return Poll::Ready(()),
+        };
+        let sleep = self
+            .sleep
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(arrival)));
+        sleep.as_mut().reset(arrival);
+        sleep.as_mut().poll(cx)
+    }
+
+    /// Takes `n` bytes of the first chunk.
+    fn consume(&mut self, n: usize) {
+        let (_, data) = self.chunks.front_mut().expect("consumed an empty link");
+        data.drain(..n);
+        if data.is_empty() {
+            self.chunks.pop_front();
+        }
+        self.queued -= n;
+    }
+}
+
+/// A stream throttled by a [`Throttle`] in both the directions.
+pub struct Throttled<S> {
+    inner: S,
+    read: Link,
+    write: Link,
+    read_eof: bool,
+}
+
+impl<S> Throttled<S> {
+    pub fn new(inner: S, throttle: Throttle) -> Self {
+        Self {
+            inner,
+            read: Link::new(throttle),
+            write: Link::new(throttle),
+            read_eof: false,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Throttled<S> {
+    /// Writes all the queued data to the inner stream as it arrives.
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            ready!(self.write.poll_arrival(cx));
+            let data = match self.write.chunks.front() {
+                Some((_, data)) => data,
+                None => return Poll::Ready(Ok(())),
+            };
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, data))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write.consume(n);
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // reads ahead, so that the data is in flight while the reader waits
+        let mut chunk = [0u8; MAX_CHUNK];
+        while !this.read_eof && this.read.queued < MAX_QUEUED {
+            let mut read_buf = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut this.inner).poll_read(cx, &mut read_buf) {
+                Poll::Ready(Ok(())) if read_buf.filled().is_empty() => this.read_eof = true,
+                Poll::Ready(Ok(())) => this.read.push(read_buf.filled()),
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => break,
+            }
+        }
+        ready!(this.read.poll_arrival(cx));
+        match this.read.chunks.front() {
+            Some((_, data)) => {
+                let n = data.len().min(buf.remaining());
+                buf.put_slice(&data[..n]);
+                this.read.consume(n);
+                Poll::Ready(Ok(()))
+            }
+            // the data read so far has arrived
+            None if this.read_eof => Poll::Ready(Ok(())),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.write.queued >= MAX_QUEUED {
+            ready!(this.poll_send(cx))?;
+        }
+        let n = buf.len().min(MAX_QUEUED - this.write.queued);
+        this.write.push(&buf[..n]);
+        // sends the arrived data, the rest is sent on flushing
+        if let Poll::Ready(Err(err)) = this.poll_send(cx) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// A [`Dialer`] throttling the connections made by the inner dialer.
+#[derive(Clone)]
+pub struct ThrottleDialer<D> {
+    inner: D,
+    throttle: Throttle,
+}
+
+impl<D: Dialer> Dialer for ThrottleDialer<D> {
+    fn dial(&self, addr: Address) -> BoxFuture<'static, io::Result<Conn>> {
+        let (dial, throttle) = (self.inner.dial(addr), self.throttle);
+        async move {
+            let conn = dial.await?;
+            Ok(Conn::new(
+                ConnStream::new_dyn(Throttled::new(conn.stream, throttle)),
+                conn.info,
+            ))
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_throttled() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let throttle = Throttle::new()
+            .rate(100 * 1024)
+            .delay(Duration::from_millis(50));
+        let mut client = Throttled::new(client, throttle);
+        let mut server = Throttled::new(server, Throttle::new());
+
+        let start = Instant::now();
+        // 10KiB at 100KiB/s takes 100ms, plus the delay
+        client.write_all(&[1u8; 10 * 1024]).await.unwrap();
+        client.flush().await.unwrap();
+        let mut received = vec![0u8; 10 * 1024];
+        server.read_exact(&mut received).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert!(received.iter().all(|b| *b == 1));
+
+        // the delay applies to the read side too
+        let start = Instant::now();
+        server.write_all(b"pong").await.unwrap();
+        server.flush().await.unwrap();
+        let mut pong = [0u8; 4];
+        client.read_exact(&mut pong).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(&pong, b"pong");
+    }
+}
@@ -0,0 +1,350 @@
+//! TLS support based on [`rustls`].
+//!
+//! Both sides are configured with the [`rustls`] configs, so that mTLS, ALPN, cipher suites and
+//! so on can be set up as needed. For mTLS, the server config should verify client certificates
+//! with [`rustls::server::AllowAnyAuthenticatedClient`], and the client config should carry the
+//! client certificate by `with_single_cert`.
+//!
+//! The configs can be replaced at runtime by `reload`, such as for the short-lived certificates,
+//! which applies to the new connections only, the established ones are kept. [`CertWatcher`]
+//! reloads them when the certificate files change, or on `SIGHUP`:
+//!
+//! ```rust,ignore
+//! use volo::net::tls::{load_certs, load_private_key, rustls, CertWatcher, TlsAcceptor};
+//!
+//! let load = || {
+//!     rustls::ServerConfig::builder()
+//!         .with_safe_defaults()
+//!         .with_no_client_auth()
+//!         .with_single_cert(load_certs("cert.pem")?, load_private_key("key.pem")?)
+//!         .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+//! };
+//! let acceptor = TlsAcceptor::new(load()?);
+//! acceptor.watch(CertWatcher::new(["cert.pem", "key.pem"]), load);
+//! ```
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use futures::future::Either;
+use tokio::{sync::Notify, task::JoinHandle};
+pub use tokio_rustls::rustls;
+use tokio_rustls::TlsStream;
+
+use super::conn::{Conn, ConnStream, TlsInfo};
+
+fn tls_info(session: &rustls::CommonState, server_name: Option<&str>) -> TlsInfo {
+    let peer_certificates: Vec<Vec<u8>> = session
+        .peer_certificates()
+        .map(|certs| certs.iter().map(|c| c.0.clone()).collect())
+        .unwrap_or_default();
+    TlsInfo {
+        alpn_protocol: session.alpn_protocol().map(|p| p.to_vec()),
+        server_name: server_name.map(|s| s.to_string()),
+        protocol_version: session.protocol_version().map(|v| format!("{:?}", v)),
+        cipher_suite: session
+            .negotiated_cipher_suite()
+            .map(|s| format!("{:?}", s.suite())),
+        peer_identity: peer_certificates
+            .first()
+            .and_then(|cert| super::x509::peer_identity(cert)),
+        peer_certificates,
+    }
+}
+
+/// Establishes TLS sessions on the client side.
+///
+/// The clones share the config, which is replaced for all of them by [`TlsConnector::reload`].
+#[derive(Clone)]
+pub struct TlsConnector {
+    config: Arc<RwLock<Arc<rustls::ClientConfig>>>,
+    server_name: rustls::ServerName,
+}
+
+impl TlsConnector {
+    /// Creates a new [`TlsConnector`], `server_name` is used to verify the certificate of the
+    /// server.
+    pub fn new(config: rustls::ClientConfig, server_name: &str) -> io::Result<Self> {
+        let server_name = rustls::ServerName::try_from(server_name)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(Self {
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            server_name,
+        })
+    }
+
+    /// Replaces the config for the new connections.
+    pub fn reload(&self, config: rustls::ClientConfig) {
+        *self.config.write().unwrap() = Arc::new(config);
+    }
+
+    /// Reloads the config by `load` when the watcher is triggered, until the returned task is
+    /// aborted.
+    pub fn watch<F>(&self, watcher: CertWatcher, load: F) -> JoinHandle<()>
+    where
+        F: Fn() -> io::Result<rustls::ClientConfig> + Send + 'static,
+    {
+        let this = self.clone();
+        watcher.spawn(move || load().map(|config| this.reload(config)))
+    }
+
+    /// Performs the TLS handshake on an established connection.
+    pub async fn connect(&self, conn: Conn) -> io::Result<Conn> {
+        match conn.stream {
+            ConnStream::Tcp(stream) => {
+                let connector =
+                    tokio_rustls::TlsConnector::from(self.config.read().unwrap().clone());
+                let stream = connector.connect(self.server_name.clone(), stream).await?;
+                let mut info = conn.info;
+                info.tls = Some(tls_info(stream.get_ref().1, None));
+                Ok(Conn::new(ConnStream::Tls(TlsStream::Client(stream)), info))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "tls is only supported over tcp",
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for TlsConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConnector")
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepts TLS sessions on the server side.
+///
+/// The clones share the config, which is replaced for all of them by [`TlsAcceptor::reload`].
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    config: Arc<RwLock<Arc<rustls::ServerConfig>>>,
+    handshake_timeout: Option<Duration>,
+}
+
+impl TlsAcceptor {
+    pub fn new(config: rustls::ServerConfig) -> Self {
+        Self {
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            handshake_timeout: Some(DEFAULT_HANDSHAKE_TIMEOUT),
+        }
+    }
+
+    /// Sets the timeout of the handshakes, so that the peers never finishing them, such as the
+    /// ones connecting without TLS, don't hold the connections, `None` for no timeout.
+    ///
+    /// Defaults to 10 seconds.
+    pub fn handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Replaces the config for the new connections.
+    pub fn reload(&self, config: rustls::ServerConfig) {
+        *self.config.write().unwrap() = Arc::new(config);
+    }
+
+    /// Reloads the config by `load` when the watcher is triggered, until the returned task is
+    /// aborted.
+    pub fn watch<F>(&self, watcher: CertWatcher, load: F) -> JoinHandle<()>
+    where
+        F: Fn() -> io::Result<rustls::ServerConfig> + Send + 'static,
+    {
+        let this = self.clone();
+        watcher.spawn(move || load().map(|config| this.reload(config)))
+    }
+
+    /// Performs the TLS handshake on an accepted connection.
+    pub async fn accept(&self, conn: Conn) -> io::Result<Conn> {
+        match conn.stream {
+            ConnStream::Tcp(stream) => {
+                let acceptor = tokio_rustls::TlsAcceptor::from(self.config.read().unwrap().clone());
+                let accept = acceptor.accept(stream);
+                let stream = match self.handshake_timeout {
+                    Some(timeout) => {
+                        tokio::time::timeout(timeout, accept).await.map_err(|_| {
+                            io::Error::new(io::ErrorKind::TimedOut, "tls handshake timed out")
+                        })??
+                    }
+                    None => accept.await?,
+                };
+                let session = stream.get_ref().1;
+                let mut info = conn.info;
+                info.tls = Some(tls_info(session, session.sni_hostname()));
+                Ok(Conn::new(ConnStream::Tls(TlsStream::Server(stream)), info))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "tls is only supported over tcp",
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for TlsAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsAcceptor")
+            .field("handshake_timeout", &self.handshake_timeout)
+            .finish()
+    }
+}
+
+/// Loads the certificate chain from the PEM file.
+pub fn load_certs(path: impl AsRef<Path>) -> io::Result<Vec<rustls::Certificate>> {
+    let mut reader = io::BufReader::new(std::fs::File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader)?;
+    Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+/// Loads the first private key of PKCS#8, PKCS#1 or SEC1 from the PEM file.
+pub fn load_private_key(path: impl AsRef<Path>) -> io::Result<rustls::PrivateKey> {
+    let mut reader = io::BufReader::new(std::fs::File::open(path)?);
+    loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(
+                rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::ECKey(key),
+            ) => return Ok(rustls::PrivateKey(key)),
+            Some(_) => continue,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "no private key found",
+                ))
+            }
+        }
+    }
+}
+
+const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Triggers the reloading of the configs when any of the files is modified, checked by the
+/// modification time every interval, or on `SIGHUP` on unix.
+#[derive(Debug, Clone)]
+pub struct CertWatcher {
+    paths: Vec<PathBuf>,
+    interval: Duration,
+    sighup: bool,
+}
+
+impl CertWatcher {
+    /// Creates a new [`CertWatcher`] of the files, checked every 10 seconds and on `SIGHUP`.
+    pub fn new<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> Self {
+        Self {
+            paths: paths
+                .into_iter()
+                .map(|p| p.as_ref().to_path_buf())
+                .collect(),
+            interval: DEFAULT_WATCH_INTERVAL,
+            sighup: true,
+        }
+    }
+
+    /// Sets the interval to check the files.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets whether to reload on `SIGHUP`, only on unix.
+    pub fn sighup(mut self, enable: bool) -> Self {
+        self.sighup = enable;
+        self
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        self.paths
+            .iter()
+            .map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    fn spawn<F>(self, reload: F) -> JoinHandle<()>
+    where
+        F: Fn() -> io::Result<()> + Send + 'static,
+    {
+        let signaled = Arc::new(Notify::new());
+        #[cfg(unix)]
+        if self.sighup {
+            let signaled = signaled.clone();
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                Ok(mut signal) => {
+                    tokio::spawn(async move {
+                        while signal.recv().await.is_some() {
+                            signaled.notify_one();
+                        }
+                    });
+                }
+                Err(e) => tracing::warn!("[VOLO] failed to listen to SIGHUP: {}", e),
+            }
+        }
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut modified = self.modified();
+            loop {
+                let tick = interval.tick();
+                let notified = signaled.notified();
+                futures::pin_mut!(tick, notified);
+                let changed = match futures::future::select(tick, notified).await {
+                    Either::Left(_) => {
+                        let now = self.modified();
+                        let changed = now != modified;
+                        modified = now;
+                        changed
+                    }
+                    Either::Right(_) => {
+                        modified = self.modified();
+                        true
+                    }
+                };
+                if !changed {
+                    continue;
+                }
+                // a half written file fails to load, and is retried when it changes again
+                match reload() {
+                    Ok(()) => tracing::info!("[VOLO] tls config reloaded"),
+                    Err(e) => tracing::warn!("[VOLO] failed to reload tls config: {}", e),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        // no certificate is needed before the client hello
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(rustls::server::ResolvesServerCertUsingSni::new()));
+        let acceptor = TlsAcceptor::new(config).handshake_timeout(Some(Duration::from_millis(50)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        // connects without sending the client hello
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        match acceptor.accept(Conn::from(stream)).await {
+            Ok(_) => panic!("the handshake is never finished"),
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::TimedOut),
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! A minimal reader of the DER certificates, for the identities in them without a full X.509
+//! parser.
+
+use std::net::IpAddr;
+
+use super::conn::PeerIdentity;
+
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_OID: u8 = 0x06;
+const TAG_OCTET_STRING: u8 = 0x04;
+// [3] EXPLICIT, the extensions of the tbsCertificate
+const TAG_EXTENSIONS: u8 = 0xa3;
+// [0] EXPLICIT, the version of the tbsCertificate
+const TAG_VERSION: u8 = 0xa0;
+const TAG_SET: u8 = 0x31;
+// the IMPLICIT tags of the GeneralName
+const TAG_EMAIL: u8 = 0x81;
+const TAG_DNS: u8 = 0x82;
+const TAG_URI: u8 = 0x86;
+const TAG_IP: u8 = 0x87;
+
+// 2.5.29.17
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+// 2.5.4.3
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+/// Reads a TLV, returns the tag, the value and the rest.
+fn read_tlv(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, mut input) = input.split_first()?;
+    let len = if first & 0x80 == 0 {
+        first as usize
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || input.len() < n {
+            return None;
+        }
+        let len = input[..n]
+            .iter()
+            .fold(0usize, |len, b| (len << 8) | *b as usize);
+        input = &input[n..];
+        len
+    };
+    if input.len() < len {
+        return None;
+    }
+    Some((tag, &input[..len], &input[len..]))
+}
+
+/// Splits the concatenated DER certificates.
+#[cfg(all(feature = "spiffe", unix))]
+pub(crate) fn split_certs(mut input: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut certs = Vec::new();
+    while !input.is_empty() {
+        let (tag, _, rest) = read_tlv(input)?;
+        if tag != TAG_SEQUENCE {
+            return None;
+        }
+        certs.push(input[..input.len() - rest.len()].to_vec());
+        input = rest;
+    }
+    Some(certs)
+}
+
+/// Returns the values of the subject alternative names of the tag, such as [`TAG_URI`].
+fn subject_alt_names(cert: &[u8], name_tag: u8) -> Option<Vec<&[u8]>> {
+    let (_, cert, _) = read_tlv(cert)?;
+    let (_, mut tbs, _) = read_tlv(cert)?;
+    let mut extensions = loop {
+        let (tag, value, rest) = read_tlv(tbs)?;
+        if tag == TAG_EXTENSIONS {
+            break read_tlv(value)?.1;
+        }
+        tbs = rest;
+    };
+    while !extensions.is_empty() {
+        let (_, extension, rest) = read_tlv(extensions)?;
+        extensions = rest;
+        let (tag, oid, mut fields) = read_tlv(extension)?;
+        if tag != TAG_OID || oid != OID_SUBJECT_ALT_NAME {
+            continue;
+        }
+        // skips the critical flag if any
+        let value = loop {
+            let (tag, value, rest) = read_tlv(fields)?;
+            if tag == TAG_OCTET_STRING {
+                break value;
+            }
+            fields = rest;
+        };
+        let (_, mut names, _) = read_tlv(value)?;
+        let mut values = Vec::new();
+        while !names.is_empty() {
+            let (tag, value, rest) = read_tlv(names)?;
+            if tag == name_tag {
+                values.push(value);
+            }
+            names = rest;
+        }
+        return Some(values);
+    }
+    Some(Vec::new())
+}
+
+fn string_sans(cert: &[u8], name_tag: u8) -> Vec<String> {
+    subject_alt_names(cert, name_tag)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|v| std::str::from_utf8(v).ok())
+        .map(|v| v.to_string())
+        .collect()
+}
+
+/// Returns the uris of the subject alternative names of the DER certificate.
+pub(crate) fn uri_sans(cert: &[u8]) -> Vec<String> {
+    string_sans(cert, TAG_URI)
+}
+
+fn ip_sans(cert: &[u8]) -> Vec<IpAddr> {
+    subject_alt_names(cert, TAG_IP)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|v| match v.len() {
+            4 => <[u8; 4]>::try_from(v).ok().map(IpAddr::from),
+            16 => <[u8; 16]>::try_from(v).ok().map(IpAddr::from),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the short name of the attribute type, or its dotted OID if unknown.
+fn attribute_name(oid: &[u8]) -> String {
+    match oid {
+        OID_COMMON_NAME => "CN".to_string(),
+        [0x55, 0x04, 0x06] => "C".to_string(),
+        [0x55, 0x04, 0x07] => "L".to_string(),
+        [0x55, 0x04, 0x08] => "ST".to_string(),
+        [0x55, 0x04, 0x0a] => "O".to_string(),
+        [0x55, 0x04, 0x0b] => "OU".to_string(),
+        _ => {
+            let mut arcs = Vec::new();
+            let mut arc = 0u64;
+            for b in oid {
+                arc = (arc << 7) | (b & 0x7f) as u64;
+                if b & 0x80 != 0 {
+                    continue;
+                }
+                if arcs.is_empty() {
+                    // the first one encodes the first two arcs
+                    let first = (arc / 40).min(2);
+                    arcs.push(first);
+                    arcs.push(arc - first * 40);
+                } else {
+                    arcs.push(arc);
+                }
+                arc = 0;
+            }
+            arcs.iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+                .join(".")
+        }
+    }
+}
+
+/// Returns the subject of the DER certificate, formatted as RFC 4514 does, such as
+/// `CN=client,O=example`, with the values not of the string types left out, and its common name.
+fn subject(cert: &[u8]) -> Option<(String, Option<String>)> {
+    let (_, cert, _) = read_tlv(cert)?;
+    let (_, mut tbs, _) = read_tlv(cert)?;
+    if tbs.first() == Some(&TAG_VERSION) {
+        tbs = read_tlv(tbs)?.2;
+    }
+    // skips the serial number, the signature algorithm, the issuer and the validity
+    for _ in 0..4 {
+        tbs = read_tlv(tbs)?.2;
+    }
+    let (tag, mut rdns, _) = read_tlv(tbs)?;
+    if tag != TAG_SEQUENCE {
+        return None;
+    }
+    let mut names = Vec::new();
+    let mut common_name = None;
+    while !rdns.is_empty() {
+        let (tag, mut attributes, rest) = read_tlv(rdns)?;
+        rdns = rest;
+        if tag != TAG_SET {
+            return None;
+        }
+        let mut rdn = Vec::new();
+        while !attributes.is_empty() {
+            let (_, attribute, rest) = read_tlv(attributes)?;
+            attributes = rest;
+            let (_, oid, value) = read_tlv(attribute)?;
+            let (tag, value, _) = read_tlv(value)?;
+            let value = match tag {
+                // UTF8String, PrintableString, IA5String and T61String
+                0x0c | 0x13 | 0x16 | 0x14 => String::from_utf8_lossy(value).into_owned(),
+                // BMPString
+                0x1e => {
+                    let units: Vec<u16> = value
+                        .chunks_exact(2)
+                        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                        .collect();
+                    String::from_utf16_lossy(&units)
+                }
+                _ => continue,
+            };
+            rdn.push(format!("{}={}", attribute_name(oid), escape(&value)));
+            if oid == OID_COMMON_NAME {
+                // the most specific one, which is the last
+                common_name = Some(value);
+            }
+        }
+        if !rdn.is_empty() {
+            names.push(rdn.join("+"));
+        }
+    }
+    // the most specific one comes first
+    names.reverse();
+    Some((names.join(","), common_name))
+}
+
+fn escape(value: &str) -> String {
+    let count = value.chars().count();
+    let mut out = String::with_capacity(value.len());
+    for (i, c) in value.chars().enumerate() {
+        let leading = i == 0 && (c == ' ' || c == '#');
+        let trailing = i + 1 == count && c == ' ';
+        if leading || trailing || matches!(c, '"' | '+' | ',' | ';' | '<' | '>' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Returns the identity in the DER certificate of the peer.
+pub(crate) fn peer_identity(cert: &[u8]) -> Option<PeerIdentity> {
+    let (subject, common_name) = subject(cert)?;
+    Some(PeerIdentity {
+        subject,
+        common_name,
+        dns_sans: string_sans(cert, TAG_DNS),
+        uri_sans: uri_sans(cert),
+        email_sans: string_sans(cert, TAG_EMAIL),
+        ip_sans: ip_sans(cert),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        if value.len() < 0x80 {
+            out.push(value.len() as u8);
+        } else {
+            out.extend_from_slice(&[0x82, (value.len() >> 8) as u8, value.len() as u8]);
+        }
+        out.extend_from_slice(value);
+        out
+    }
+
+    fn attribute(oid: &[u8], tag: u8, value: &str) -> Vec<u8> {
+        tlv(
+            TAG_SET,
+            &tlv(
+                TAG_SEQUENCE,
+                &[tlv(TAG_OID, oid), tlv(tag, value.as_bytes())].concat(),
+            ),
+        )
+    }
+
+    // the fields not read are left empty
+    fn cert_of(subject: &[Vec<u8>], names: &[Vec<u8>]) -> Vec<u8> {
+        let names = tlv(TAG_SEQUENCE, &names.concat());
+        let san = [
+            tlv(TAG_OID, OID_SUBJECT_ALT_NAME),
+            tlv(0x01, &[0xff]),
+            tlv(TAG_OCTET_STRING, &names),
+        ]
+        .concat();
+        let extensions = tlv(TAG_EXTENSIONS, &tlv(TAG_SEQUENCE, &tlv(TAG_SEQUENCE, &san)));
+        let tbs = [
+            tlv(TAG_VERSION, &tlv(0x02, &[0x02])),
+            tlv(0x02, &[0x01]),
+            tlv(TAG_SEQUENCE, &[0; 10]),
+            tlv(TAG_SEQUENCE, &[0; 20]),
+            tlv(TAG_SEQUENCE, &[0; 30]),
+            tlv(TAG_SEQUENCE, &subject.concat()),
+            tlv(TAG_SEQUENCE, &[0; 200]),
+            extensions,
+        ]
+        .concat();
+        tlv(TAG_SEQUENCE, &tlv(TAG_SEQUENCE, &tbs))
+    }
+
+    #[cfg(all(feature = "spiffe", unix))]
+    fn cert(uri: &str) -> Vec<u8> {
+        cert_of(&[], &[tlv(TAG_URI, uri.as_bytes())])
+    }
+
+    #[cfg(all(feature = "spiffe", unix))]
+    #[test]
+    fn test_uri_sans() {
+        let a = cert("spiffe://example.org/a");
+        let b = cert("spiffe://example.org/b");
+        assert_eq!(uri_sans(&a), ["spiffe://example.org/a"]);
+
+        let certs = split_certs(&[a.clone(), b].concat()).unwrap();
+        assert_eq!(certs.len(), 2);
+        assert_eq!(certs[0], a);
+        assert_eq!(uri_sans(&certs[1]), ["spiffe://example.org/b"]);
+        assert!(split_certs(&a[..a.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_peer_identity() {
+        let subject = [
+            attribute(&[0x55, 0x04, 0x06], 0x13, "CN"),
+            attribute(&[0x55, 0x04, 0x0a], 0x0c, "Example, Inc."),
+            attribute(&[0x55, 0x04, 0x03], 0x0c, "client"),
+            // 2.5.4.5, the serial number
+            attribute(&[0x55, 0x04, 0x05], 0x13, "42"),
+        ];
+        let names = [
+            tlv(TAG_DNS, b"client.example.org"),
+            tlv(TAG_URI, b"spiffe://example.org/client"),
+            tlv(TAG_EMAIL, b"client@example.org"),
+            tlv(TAG_IP, &[10, 0, 0, 1]),
+        ];
+        let identity = peer_identity(&cert_of(&subject, &names)).unwrap();
+        assert_eq!(
+            identity.subject,
+            "2.5.4.5=42,CN=client,O=Example\\, Inc.,C=CN"
+        );
+        assert_eq!(identity.common_name.as_deref(), Some("client"));
+        assert_eq!(identity.dns_sans, ["client.example.org"]);
+        assert_eq!(identity.uri_sans, ["spiffe://example.org/client"]);
+        assert_eq!(identity.email_sans, ["client@example.org"]);
+        assert_eq!(identity.ip_sans, [IpAddr::from([10, 0, 0, 1])]);
+    }
+}
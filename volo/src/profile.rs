@@ -0,0 +1,465 @@
+//! Per-callee settings of the clients, such as the timeouts, the retries and the pool sizes, so
+//! that the clients of the services of different needs are configured from one place, such as a
+//! config file, instead of one setting on every builder.
+//!
+//! A [`Profiles`] holds the default [`Profile`] and the profiles of the callees by their service
+//! names, the fields set in the profile of a callee override the default ones, and the fields set
+//! in neither keep the setting of the builder. The profiles are applied by the `profiles` method
+//! of the client builders of thrift and gRPC, each of which applies the fields it supports.
+//!
+//! With the `json-profile` feature, the profiles can be loaded from JSON, like:
+//!
+//! ```json
+//! {
+//!     "default": { "rpc_timeout_ms": 1000, "retry_count": 1 },
+//!     "callees": {
+//!         "report": { "rpc_timeout_ms": 5000, "max_conns": 16 }
+//!     }
+//! }
+//! ```
+//!
+//! And from YAML with the `yaml-profile` feature, or from TOML with the `toml-profile` feature,
+//! the files of which are told apart by their extensions by [`Profiles::from_file`], like:
+//!
+//! ```toml
+//! [default]
+//! rpc_timeout_ms = 1000
+//! retry_count = 1
+//!
+//! [callees.report]
+//! rpc_timeout_ms = 5000
+//! max_conns = 16
+//! ```
+//!
+//! The profiles of a file can also be reloaded when the file changes by a [`FileWatcher`], and
+//! the client builders subscribe to them by `watch_profiles`, so that the timeouts and the retries
+//! are changed without redeploying. The reloaded profiles apply to the calls made after the
+//! change, as the defaults overridden by the `CallOpt` of the calls, while the pool sizes are
+//! kept as the clients were built.
+//!
+//! The load balance and the cluster of the callee are switched by the reloaded profiles too, when
+//! the clients are built with the [`ProfileBalance`] and the [`ProfileDiscover`] of the same
+//! receiver, like:
+//!
+//! ```json
+//! {
+//!     "callees": {
+//!         "item": { "load_balance": "consistent_hash", "cluster": "canary" }
+//!     }
+//! }
+//! ```
+//!
+//! ```rust,ignore
+//! use volo::{
+//!     discovery::profile::ProfileDiscover, loadbalance::profile::ProfileBalance,
+//!     profile::FileWatcher,
+//! };
+//!
+//! let profiles = FileWatcher::new("profiles.json").watch()?;
+//! let client = ItemServiceClientBuilder::new("item")
+//!     .profiles(&profiles.borrow())
+//!     .load_balance(ProfileBalance::new(profiles.clone()))
+//!     .discover(ProfileDiscover::new(discover, profiles.clone()))
+//!     .watch_profiles(profiles)
+//!     .build();
+//! ```
+//!
+//! [`ProfileBalance`]: crate::loadbalance::profile::ProfileBalance
+//! [`ProfileDiscover`]: crate::discovery::profile::ProfileDiscover
+
+use std::{collections::HashMap, time::Duration};
+#[cfg(any(
+    feature = "json-profile",
+    feature = "yaml-profile",
+    feature = "toml-profile"
+))]
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+#[cfg(any(
+    feature = "json-profile",
+    feature = "yaml-profile",
+    feature = "toml-profile"
+))]
+use tokio::sync::watch;
+
+#[cfg(any(
+    feature = "json-profile",
+    feature = "yaml-profile",
+    feature = "toml-profile"
+))]
+use crate::rt::Runtime;
+
+/// The settings of the clients of a callee, every field is optional.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+pub struct Profile {
+    /// The timeout of each call, 0 to disable it.
+    pub rpc_timeout_ms: Option<u64>,
+    /// The timeout of making a connection.
+    pub connect_timeout_ms: Option<u64>,
+    /// The timeout of each read and write of the connections.
+    pub read_write_timeout_ms: Option<u64>,
+    /// The times to retry a failed call.
+    pub retry_count: Option<usize>,
+    /// The max idle connections kept to each instance.
+    pub max_idle_conns: Option<usize>,
+    /// The min idle connections kept to each instance.
+    pub min_idle_conns: Option<usize>,
+    /// The max connections to each instance.
+    pub max_conns: Option<usize>,
+    /// The load balance of the calls, applied by the
+    /// [`ProfileBalance`](crate::loadbalance::profile::ProfileBalance).
+    pub load_balance: Option<LoadBalanceKind>,
+    /// The cluster of the callee to call, the instances of the other [`labels::CLUSTER`]s are
+    /// left out by the [`ProfileDiscover`](crate::discovery::profile::ProfileDiscover).
+    ///
+    /// [`labels::CLUSTER`]: crate::discovery::labels::CLUSTER
+    pub cluster: Option<String>,
+}
+
+/// The load balances to switch among by the profiles.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum LoadBalanceKind {
+    /// See [`WeightedRandomBalance`](crate::loadbalance::random::WeightedRandomBalance).
+    #[default]
+    Random,
+    /// See [`WeightedRoundRobinBalance`](crate::loadbalance::round_robin::WeightedRoundRobinBalance).
+    RoundRobin,
+    /// See [`LeastConnectionBalance`](crate::loadbalance::least_conn::LeastConnectionBalance).
+    LeastConn,
+    /// See [`ConsistentHashBalance`](crate::loadbalance::consistent_hash::ConsistentHashBalance).
+    ConsistentHash,
+}
+
+fn millis(ms: Option<u64>) -> Option<Duration> {
+    ms.map(Duration::from_millis)
+}
+
+impl Profile {
+    /// Returns the profile with the fields set in `other` overriding the ones of `self`.
+    pub fn merge(&self, other: &Profile) -> Profile {
+        Profile {
+            rpc_timeout_ms: other.rpc_timeout_ms.or(self.rpc_timeout_ms),
+            connect_timeout_ms: other.connect_timeout_ms.or(self.connect_timeout_ms),
+            read_write_timeout_ms: other.read_write_timeout_ms.or(self.read_write_timeout_ms),
+            retry_count: other.retry_count.or(self.retry_count),
+            max_idle_conns: other.max_idle_conns.or(self.max_idle_conns),
+            min_idle_conns: other.min_idle_conns.or(self.min_idle_conns),
+            max_conns: other.max_conns.or(self.max_conns),
+            load_balance: other.load_balance.or(self.load_balance),
+            cluster: other.cluster.clone().or_else(|| self.cluster.clone()),
+        }
+    }
+
+    /// Returns the rpc timeout if set, `Some(None)` if it's disabled.
+    pub fn rpc_timeout(&self) -> Option<Option<Duration>> {
+        self.rpc_timeout_ms
+            .map(|ms| (ms > 0).then_some(Duration::from_millis(ms)))
+    }
+
+    /// Returns the connect timeout if set.
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        millis(self.connect_timeout_ms)
+    }
+
+    /// Returns the read write timeout if set.
+    pub fn read_write_timeout(&self) -> Option<Duration> {
+        millis(self.read_write_timeout_ms)
+    }
+}
+
+/// The default profile and the profiles of the callees.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+pub struct Profiles {
+    /// The profile of all the callees.
+    pub default: Profile,
+    /// The profiles of the callees by their service names.
+    pub callees: HashMap<String, Profile>,
+}
+
+impl Profiles {
+    /// Returns the profile of the callee, merged with the default one.
+    pub fn get(&self, callee: &str) -> Profile {
+        match self.callees.get(callee) {
+            Some(profile) => self.default.merge(profile),
+            None => self.default.clone(),
+        }
+    }
+
+    /// Parses the profiles from JSON.
+    #[cfg(feature = "json-profile")]
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Loads the profiles from the JSON file.
+    #[cfg(feature = "json-profile")]
+    pub fn from_json_file(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        Self::from_json(&json).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Parses the profiles from YAML.
+    #[cfg(feature = "yaml-profile")]
+    pub fn from_yaml(yaml: &str) -> Result<Self, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    /// Parses the profiles from TOML.
+    #[cfg(feature = "toml-profile")]
+    pub fn from_toml(toml: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(toml)
+    }
+
+    /// Loads the profiles from the file of the format by its extension, which is `json`, `yaml`
+    /// or `yml`, or `toml`, and of which the feature is enabled.
+    #[cfg(any(
+        feature = "json-profile",
+        feature = "yaml-profile",
+        feature = "toml-profile"
+    ))]
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            #[cfg(feature = "json-profile")]
+            Some("json") => Self::from_json_file(path),
+            #[cfg(feature = "yaml-profile")]
+            Some("yaml" | "yml") => Self::from_yaml(&std::fs::read_to_string(path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            #[cfg(feature = "toml-profile")]
+            Some("toml") => Self::from_toml(&std::fs::read_to_string(path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the format of the profiles of {:?} is not supported", path),
+            )),
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "json-profile",
+    feature = "yaml-profile",
+    feature = "toml-profile"
+))]
+const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Reloads the profiles from a file when it changes, and sends them to the receivers, such as of
+/// the `watch_profiles` of the client builders.
+///
+/// The file is loaded by [`Profiles::from_file`], and checked by its modified time every
+/// interval. The file failing to load keeps the last profiles, and the error is logged.
+#[cfg(any(
+    feature = "json-profile",
+    feature = "yaml-profile",
+    feature = "toml-profile"
+))]
+pub struct FileWatcher {
+    path: PathBuf,
+    interval: Duration,
+    runtime: Runtime,
+}
+
+#[cfg(any(
+    feature = "json-profile",
+    feature = "yaml-profile",
+    feature = "toml-profile"
+))]
+impl FileWatcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            interval: DEFAULT_WATCH_INTERVAL,
+            runtime: Default::default(),
+        }
+    }
+
+    /// Sets how often the file is checked.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the runtime to spawn the task checking the file.
+    ///
+    /// Defaults to the tokio runtime.
+    pub fn runtime(mut self, runtime: Runtime) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Loads the profiles from the file, and reloads them in the background until all the
+    /// receivers are dropped.
+    pub fn watch(self) -> io::Result<watch::Receiver<Arc<Profiles>>> {
+        let mut last_modified = modified(&self.path);
+        let (sender, receiver) = watch::channel(Arc::new(Profiles::from_file(&self.path)?));
+        let runtime = self.runtime.clone();
+        runtime.spawn(async move {
+            loop {
+                self.runtime.sleep(self.interval).await;
+                if sender.is_closed() {
+                    return;
+                }
+                let m = modified(&self.path);
+                if m == last_modified {
+                    continue;
+                }
+                last_modified = m;
+                match Profiles::from_file(&self.path) {
+                    Ok(profiles) => {
+                        if **sender.borrow() != profiles {
+                            tracing::info!("[VOLO] reloaded the profiles from {:?}", self.path);
+                            let _ = sender.send(Arc::new(profiles));
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            "[VOLO] failed to reload the profiles from {:?}: {}",
+                            self.path,
+                            e
+                        );
+                    }
+                }
+            }
+        });
+        Ok(receiver)
+    }
+}
+
+#[cfg(any(
+    feature = "json-profile",
+    feature = "yaml-profile",
+    feature = "toml-profile"
+))]
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get() {
+        let mut profiles = Profiles {
+            default: Profile {
+                rpc_timeout_ms: Some(1000),
+                retry_count: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        profiles.callees.insert(
+            "report".to_string(),
+            Profile {
+                rpc_timeout_ms: Some(0),
+                max_conns: Some(16),
+                ..Default::default()
+            },
+        );
+
+        let profile = profiles.get("report");
+        assert_eq!(profile.rpc_timeout(), Some(None));
+        assert_eq!(profile.retry_count, Some(1));
+        assert_eq!(profile.max_conns, Some(16));
+
+        let profile = profiles.get("item");
+        assert_eq!(profile.rpc_timeout(), Some(Some(Duration::from_secs(1))));
+        assert_eq!(profile.max_conns, None);
+    }
+
+    #[cfg(feature = "json-profile")]
+    #[test]
+    fn test_from_json() {
+        let profiles = Profiles::from_json(
+            r#"{
+                "default": { "load_balance": "round_robin" },
+                "callees": { "item": { "load_balance": "consistent_hash", "cluster": "canary" } }
+            }"#,
+        )
+        .unwrap();
+        let profile = profiles.get("item");
+        assert_eq!(profile.load_balance, Some(LoadBalanceKind::ConsistentHash));
+        assert_eq!(profile.cluster.as_deref(), Some("canary"));
+        let profile = profiles.get("report");
+        assert_eq!(profile.load_balance, Some(LoadBalanceKind::RoundRobin));
+        assert_eq!(profile.cluster, None);
+    }
+
+    #[cfg(feature = "yaml-profile")]
+    #[test]
+    fn test_from_yaml() {
+        let path = std::env::temp_dir().join(format!("volo-profiles-{}.yml", std::process::id()));
+        std::fs::write(
+            &path,
+            "default:\n  rpc_timeout_ms: 1000\ncallees:\n  report:\n    max_conns: 16\n",
+        )
+        .unwrap();
+        let profiles = Profiles::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let profile = profiles.get("report");
+        assert_eq!(profile.rpc_timeout_ms, Some(1000));
+        assert_eq!(profile.max_conns, Some(16));
+
+        Profiles::from_yaml("default: [1]").unwrap_err();
+    }
+
+    #[cfg(feature = "toml-profile")]
+    #[test]
+    fn test_from_toml() {
+        let path = std::env::temp_dir().join(format!("volo-profiles-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[default]\nload_balance = \"round_robin\"\n\n[callees.item]\ncluster = \"canary\"\n",
+        )
+        .unwrap();
+        let profiles = Profiles::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let profile = profiles.get("item");
+        assert_eq!(profile.load_balance, Some(LoadBalanceKind::RoundRobin));
+        assert_eq!(profile.cluster.as_deref(), Some("canary"));
+
+        // told apart by the extension
+        let err = Profiles::from_file("profiles.ini").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(feature = "json-profile")]
+    #[tokio::test]
+    async fn test_file_watcher() {
+        let path = std::env::temp_dir().join(format!("volo-profiles-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"default": {"rpc_timeout_ms": 1000}}"#).unwrap();
+
+        let mut profiles = FileWatcher::new(&path)
+            .interval(Duration::from_millis(10))
+            .watch()
+            .unwrap();
+        assert_eq!(profiles.borrow().default.rpc_timeout_ms, Some(1000));
+
+        std::fs::write(
+            &path,
+            r#"{"default": {"rpc_timeout_ms": 2000}, "callees": {"report": {"retry_count": 2}}}"#,
+        )
+        .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), profiles.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(profiles.borrow().get("report").rpc_timeout_ms, Some(2000));
+        assert_eq!(profiles.borrow().get("report").retry_count, Some(2));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
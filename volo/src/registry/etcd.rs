@@ -0,0 +1,184 @@
+//! [`Registry`] by the keys in etcd, discovered by
+//! [`EtcdDiscover`](crate::discovery::etcd::EtcdDiscover).
+//!
+//! Each instance is put with a lease, which is kept alive in background until the instance is
+//! deregistered, so the crashed instances are removed after the TTL of the lease. When the lease
+//! is lost, such as after a long partition, the instance is registered again with a new lease.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::registry::{etcd::EtcdRegistry, RegistryInfo};
+//!
+//! let client = etcd_client::Client::connect(["127.0.0.1:2379"], None).await?;
+//! let registry = EtcdRegistry::new(client);
+//! registry.register(&RegistryInfo::new("echo", addr)).await?;
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use etcd_client::{Client, PutOptions};
+use motore::BoxError;
+use tokio::task::JoinHandle;
+
+use super::{Registry, RegistryInfo};
+use crate::discovery::etcd::{EtcdError, Record, DEFAULT_PREFIX};
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A registered instance, with the lease changed when registered again.
+struct Registered {
+    lease: Arc<AtomicI64>,
+    keep_alive: JoinHandle<()>,
+}
+
+/// A [`Registry`] putting the instances into etcd.
+pub struct EtcdRegistry {
+    client: Client,
+    prefix: String,
+    ttl: i64,
+    registered: Mutex<HashMap<String, Registered>>,
+}
+
+impl EtcdRegistry {
+    /// Creates a registry by the connected client.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            prefix: DEFAULT_PREFIX.to_string(),
+            ttl: 10,
+            registered: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the prefix of the keys, which should be the same as the one of the discover.
+    ///
+    /// Defaults to `volo/services`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Sets the TTL of the leases, the instances are removed this long after the server is
+    /// gone without deregistering.
+    ///
+    /// Defaults to 10 seconds.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = (ttl.as_secs() as i64).max(1);
+        self
+    }
+}
+
+/// Puts the instance with a new lease, returns the lease.
+async fn put(client: &mut Client, key: &str, value: &str, ttl: i64) -> Result<i64, EtcdError> {
+    let lease = client.lease_grant(ttl, None).await?.id();
+    client
+        .put(key, value, Some(PutOptions::new().with_lease(lease)))
+        .await?;
+    Ok(lease)
+}
+
+/// Keeps the lease alive, returns when the lease is lost or the keep alive fails.
+async fn keep_alive_once(client: &mut Client, lease: i64, ttl: i64) -> Result<(), EtcdError> {
+    let (mut keeper, mut stream) = client.lease_keep_alive(lease).await?;
+    loop {
+        keeper.keep_alive().await?;
+        match stream.message().await? {
+            Some(resp) if resp.ttl() > 0 => {}
+            _ => return Ok(()),
+        }
+        tokio::time::sleep(Duration::from_secs((ttl as u64 / 3).max(1))).await;
+    }
+}
+
+async fn keep_alive(
+    mut client: Client,
+    key: String,
+    value: String,
+    ttl: i64,
+    lease: Arc<AtomicI64>,
+) {
+    loop {
+        if let Err(err) = keep_alive_once(&mut client, lease.load(Ordering::Acquire), ttl).await {
+            tracing::warn!(
+                "[VOLO] failed to keep the etcd lease of {} alive: {}",
+                key,
+                err
+            );
+        }
+        // the lease may be lost, registers again
+        loop {
+            tokio::time::sleep(RETRY_INTERVAL).await;
+            match put(&mut client, &key, &value, ttl).await {
+                Ok(id) => {
+                    lease.store(id, Ordering::Release);
+                    break;
+                }
+                Err(err) => tracing::warn!("[VOLO] failed to register {} to etcd: {}", key, err),
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Registry for EtcdRegistry {
+    async fn register(&self, info: &RegistryInfo) -> Result<(), BoxError> {
+        let key = Record::key(&self.prefix, info);
+        let value = serde_json::to_string(&Record::from(info))?;
+        let mut client = self.client.clone();
+        let lease = Arc::new(AtomicI64::new(
+            put(&mut client, &key, &value, self.ttl).await?,
+        ));
+        let keep_alive = tokio::spawn(keep_alive(
+            client,
+            key.clone(),
+            value,
+            self.ttl,
+            lease.clone(),
+        ));
+        let prev = self
+            .registered
+            .lock()
+            .unwrap()
+            .insert(key, Registered { lease, keep_alive });
+        if let Some(prev) = prev {
+            prev.keep_alive.abort();
+        }
+        Ok(())
+    }
+
+    async fn deregister(&self, info: &RegistryInfo) -> Result<(), BoxError> {
+        let key = Record::key(&self.prefix, info);
+        let registered = self.registered.lock().unwrap().remove(&key);
+        let mut client = self.client.clone();
+        match registered {
+            Some(registered) => {
+                registered.keep_alive.abort();
+                // the key is deleted with the lease
+                client
+                    .lease_revoke(registered.lease.load(Ordering::Acquire))
+                    .await?;
+            }
+            None => {
+                client.delete(key, None).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EtcdRegistry {
+    fn drop(&mut self) {
+        // the instances not deregistered expire with their leases
+        for (_, registered) in self.registered.get_mut().unwrap().drain() {
+            registered.keep_alive.abort();
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! This module contains the abstraction for the servers to register themselves, so that the
+//! clients can find them by the [`Discover`](crate::discovery::Discover) of the same service
+//! discovery.
+#[cfg(feature = "etcd")]
+pub mod etcd;
+
+use std::{borrow::Cow, collections::HashMap};
+
+use motore::BoxError;
+use smol_str::SmolStr;
+
+use crate::{net::Address, service_info::ServiceInfo};
+
+/// The tag of the version of the service registered by [`RegistryInfo::of`].
+pub const VERSION_TAG: &str = "version";
+
+/// [`RegistryInfo`] describes the instance to register.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryInfo {
+    pub service_name: SmolStr,
+    /// The address for the clients to connect, rather than the one listened, such as
+    /// `0.0.0.0:8080`.
+    pub address: Address,
+    pub weight: u32,
+    pub tags: HashMap<Cow<'static, str>, Cow<'static, str>>,
+}
+
+impl RegistryInfo {
+    /// Creates the info of an instance weighted 1 without tags.
+    pub fn new(service_name: impl Into<SmolStr>, address: impl Into<Address>) -> Self {
+        Self {
+            service_name: service_name.into(),
+            address: address.into(),
+            weight: 1,
+            tags: Default::default(),
+        }
+    }
+
+    /// Creates the info of an instance of the service `S` named by [`ServiceInfo::NAME`], tagged
+    /// by the `version` if any.
+    pub fn of<S: ServiceInfo>(address: impl Into<Address>) -> Self {
+        let mut info = Self::new(S::NAME, address);
+        if !S::VERSION.is_empty() {
+            info.tags.insert(VERSION_TAG.into(), S::VERSION.into());
+        }
+        info
+    }
+}
+
+/// [`Registry`] registers the instances of a server to a service discovery.
+///
+/// The registered instance should be kept alive by the registry until it's deregistered.
+#[async_trait::async_trait]
+pub trait Registry: Send + Sync + 'static {
+    async fn register(&self, info: &RegistryInfo) -> Result<(), BoxError>;
+
+    async fn deregister(&self, info: &RegistryInfo) -> Result<(), BoxError>;
+}
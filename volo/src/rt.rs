@@ -0,0 +1,97 @@
+//! The runtime to spawn the background tasks and to sleep, so that the servers and the clients
+//! can be embedded into the applications running their own executors.
+//!
+//! The [`Runtime`] runs on the current tokio runtime by default. The IO and the timeouts of the
+//! transports are still driven by tokio, so a tokio runtime, such as a current thread one, should
+//! be entered when using a custom runtime.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::rt::{Executor, Runtime, TokioTimer};
+//!
+//! struct MyExecutor;
+//!
+//! impl Executor for MyExecutor {
+//!     fn spawn(&self, fut: futures::future::BoxFuture<'static, ()>) {
+//!         my_runtime::spawn(fut);
+//!     }
+//! }
+//!
+//! let runtime = Runtime::new(MyExecutor, TokioTimer);
+//! ```
+
+use std::{fmt, future::Future, sync::Arc, time::Duration};
+
+use futures::future::{BoxFuture, FutureExt};
+
+/// Spawns the background tasks.
+pub trait Executor: Send + Sync + 'static {
+    fn spawn(&self, fut: BoxFuture<'static, ()>);
+}
+
+/// Sleeps for a while.
+pub trait Timer: Send + Sync + 'static {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// Spawns the tasks by [`tokio::spawn`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioExecutor;
+
+impl Executor for TokioExecutor {
+    fn spawn(&self, fut: BoxFuture<'static, ()>) {
+        tokio::spawn(fut);
+    }
+}
+
+/// Sleeps by [`tokio::time::sleep`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioTimer;
+
+impl Timer for TokioTimer {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        tokio::time::sleep(duration).boxed()
+    }
+}
+
+/// The [`Executor`] and the [`Timer`] shared by the components of a server or a client.
+#[derive(Clone)]
+pub struct Runtime {
+    executor: Arc<dyn Executor>,
+    timer: Arc<dyn Timer>,
+}
+
+impl Runtime {
+    pub fn new(executor: impl Executor, timer: impl Timer) -> Self {
+        Self {
+            executor: Arc::new(executor),
+            timer: Arc::new(timer),
+        }
+    }
+
+    /// Spawns a task running in background.
+    pub fn spawn<F>(&self, fut: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.executor.spawn(fut.boxed());
+    }
+
+    /// Sleeps for `duration`.
+    pub fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        self.timer.sleep(duration)
+    }
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new(TokioExecutor, TokioTimer)
+    }
+}
+
+impl fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Runtime").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,67 @@
+//! The identity of the services of the IDLs, implemented by the generated servers and clients of
+//! both thrift and gRPC.
+//!
+//! The registration, the tracing spans and the metrics take the name, the methods and the version
+//! of a service from [`ServiceInfo`], instead of the strings repeated in every project, such as
+//! [`RegistryInfo::of`] and [`SpanLayer`].
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::{layer::span::SpanLayer, registry::RegistryInfo, service_info::ServiceInfo};
+//!
+//! let info = RegistryInfo::of::<ItemServiceServer<S>>(addr);
+//! let client = ItemServiceClientBuilder::new(ItemServiceClient::NAME)
+//!     .layer_outer(SpanLayer::<ItemServiceClient>::new())
+//!     .build();
+//! ```
+//!
+//! [`RegistryInfo::of`]: crate::registry::RegistryInfo::of
+//! [`SpanLayer`]: crate::layer::span::SpanLayer
+
+/// The identity of a service of the IDLs.
+pub trait ServiceInfo {
+    /// The full name of the service, with the package of the IDL if any, such as
+    /// `volo.example.ItemService`.
+    const NAME: &'static str;
+
+    /// The names of the methods as in the IDL.
+    const METHODS: &'static [&'static str];
+
+    /// The version of the crate the code is generated in, empty if unknown.
+    const VERSION: &'static str;
+
+    /// Returns whether the service has the method named in the IDL.
+    fn has_method(method: &str) -> bool {
+        Self::METHODS.contains(&method)
+    }
+}
+
+/// Returns the name of the method in the IDL of the method of the [`RpcInfo`], which is the path
+/// of the HTTP request for gRPC, such as `/volo.example.ItemService/GetItem`.
+///
+/// [`RpcInfo`]: crate::context::RpcInfo
+pub fn method_name(method: &str) -> &str {
+    method.rsplit('/').next().unwrap_or(method)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct ItemService;
+
+    impl ServiceInfo for ItemService {
+        const NAME: &'static str = "volo.example.ItemService";
+        const METHODS: &'static [&'static str] = &["GetItem"];
+        const VERSION: &'static str = "0.1.0";
+    }
+
+    #[test]
+    fn test_service_info() {
+        assert!(ItemService::has_method("GetItem"));
+        assert!(!ItemService::has_method("SetItem"));
+        assert_eq!(method_name("/volo.example.ItemService/GetItem"), "GetItem");
+        assert_eq!(method_name("GetItem"), "GetItem");
+    }
+}
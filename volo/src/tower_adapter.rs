@@ -0,0 +1,227 @@
+//! Adapters between the volo services and the [`tower`] services, so that the tower middlewares,
+//! such as the ones of `tower-http`, can be reused in the volo stacks, and the other way around.
+//!
+//! The services of volo take the context and the request separately, while the tower services
+//! only take the request. So each adapter takes a function to convert between them:
+//!
+//! - [`TowerAdapter`] makes a volo service from a tower service, with a function making the tower
+//!   request from the context and the volo request.
+//! - [`MotoreAdapter`] makes a tower service from a volo service, with a function making the
+//!   context and the volo request from the tower request.
+//!
+//! The layer adapters combine both, as the layer wraps a service of the other side. The functions
+//! can move the context into the tower request to pass it across the tower middlewares, such as
+//! by the extensions of `http::Request`, while the changes made by the inner services to the
+//! context moved are not seen by the outer ones.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use volo::tower_adapter::TowerLayerAdapter;
+//!
+//! let layer = TowerLayerAdapter::new(
+//!     tower::limit::ConcurrencyLimitLayer::new(100),
+//!     |cx: &mut ServerContext, req| (std::mem::take(cx), req),
+//!     |(cx, req)| (cx, req),
+//! );
+//! ```
+
+use std::task::Poll;
+
+use futures::Future;
+use motore::{layer::Layer, service::Service};
+
+/// A volo [`Service`] calling the inner tower service.
+///
+/// The tower service is polled to be ready before each call.
+#[derive(Clone)]
+pub struct TowerAdapter<S, F> {
+    inner: S,
+    f: F,
+}
+
+impl<S, F> TowerAdapter<S, F> {
+    /// Creates a service calling `inner` by the request returned by `f`.
+    pub fn new(inner: S, f: F) -> Self {
+        Self { inner, f }
+    }
+}
+
+impl<Cx, Req, S, F, TowerReq> Service<Cx, Req> for TowerAdapter<S, F>
+where
+    Cx: Send + 'static,
+    Req: Send + 'static,
+    S: tower::Service<TowerReq> + Send + 'static,
+    S::Future: Send,
+    F: FnMut(&mut Cx, Req) -> TowerReq + Send + 'static,
+    TowerReq: 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<S::Response, S::Error>> + 'cx;
+
+    fn call<'cx, 's>(&'s mut self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            futures::future::poll_fn(|task_cx| {
+                tower::Service::poll_ready(&mut self.inner, task_cx)
+            })
+            .await?;
+            let resp = tower::Service::call(&mut self.inner, (self.f)(cx, req));
+            resp.await
+        }
+    }
+}
+
+/// A [`tower::Service`] calling the inner volo service.
+///
+/// The volo services are always ready, and the inner service is cloned for each call, as the
+/// tower futures can't borrow the service.
+#[derive(Clone)]
+pub struct MotoreAdapter<S, F> {
+    inner: S,
+    f: F,
+}
+
+impl<S, F> MotoreAdapter<S, F> {
+    /// Creates a service calling `inner` by the context and the request returned by `f`.
+    pub fn new(inner: S, f: F) -> Self {
+        Self { inner, f }
+    }
+}
+
+impl<TowerReq, S, F, Cx, Req> tower::Service<TowerReq> for MotoreAdapter<S, F>
+where
+    S: Service<Cx, Req> + Clone,
+    F: FnMut(TowerReq) -> (Cx, Req),
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future = impl Future<Output = Result<S::Response, S::Error>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: TowerReq) -> Self::Future {
+        let (mut cx, req) = (self.f)(req);
+        let mut inner = self.inner.clone();
+        async move { inner.call(&mut cx, req).await }
+    }
+}
+
+/// A volo [`Layer`] applying the inner tower layer.
+///
+/// The tower layer wraps the inner volo service by [`MotoreAdapter`] with `into_volo`, and the
+/// service it returns is wrapped by [`TowerAdapter`] with `into_tower`.
+#[derive(Clone)]
+pub struct TowerLayerAdapter<L, F1, F2> {
+    layer: L,
+    into_tower: F1,
+    into_volo: F2,
+}
+
+impl<L, F1, F2> TowerLayerAdapter<L, F1, F2> {
+    pub fn new(layer: L, into_tower: F1, into_volo: F2) -> Self {
+        Self {
+            layer,
+            into_tower,
+            into_volo,
+        }
+    }
+}
+
+impl<S, L, F1, F2> Layer<S> for TowerLayerAdapter<L, F1, F2>
+where
+    L: tower::Layer<MotoreAdapter<S, F2>>,
+{
+    type Service = TowerAdapter<L::Service, F1>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        let inner = self.layer.layer(MotoreAdapter::new(inner, self.into_volo));
+        TowerAdapter::new(inner, self.into_tower)
+    }
+}
+
+/// A [`tower::Layer`] applying the inner volo layer.
+///
+/// The volo layer wraps the inner tower service by [`TowerAdapter`] with `into_tower`, and the
+/// service it returns is wrapped by [`MotoreAdapter`] with `into_volo`.
+#[derive(Clone)]
+pub struct MotoreLayerAdapter<L, F1, F2> {
+    layer: L,
+    into_tower: F1,
+    into_volo: F2,
+}
+
+impl<L, F1, F2> MotoreLayerAdapter<L, F1, F2> {
+    pub fn new(layer: L, into_tower: F1, into_volo: F2) -> Self {
+        Self {
+            layer,
+            into_tower,
+            into_volo,
+        }
+    }
+}
+
+impl<S, L, F1, F2> tower::Layer<S> for MotoreLayerAdapter<L, F1, F2>
+where
+    L: Layer<TowerAdapter<S, F1>> + Clone,
+    F1: Clone,
+    F2: Clone,
+{
+    type Service = MotoreAdapter<L::Service, F2>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        let inner = self
+            .layer
+            .clone()
+            .layer(TowerAdapter::new(inner, self.into_tower.clone()));
+        MotoreAdapter::new(inner, self.into_volo.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use futures::future::{ready, Ready};
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct Double;
+
+    impl tower::Service<u32> for Double {
+        type Response = u32;
+        type Error = Infallible;
+        type Future = Ready<Result<u32, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: u32) -> Self::Future {
+            ready(Ok(req * 2))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        // the context is the offset added to the request
+        let mut svc = TowerAdapter::new(Double, |cx: &mut u32, req: u32| req + *cx);
+        assert_eq!(svc.call(&mut 1, 2).await, Ok(6));
+
+        let mut svc = MotoreAdapter::new(svc, |req: u32| (1u32, req));
+        futures::future::poll_fn(|cx| tower::Service::poll_ready(&mut svc, cx))
+            .await
+            .unwrap();
+        assert_eq!(tower::Service::call(&mut svc, 2).await, Ok(6));
+    }
+}
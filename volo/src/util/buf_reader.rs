@@ -25,7 +25,7 @@ macro_rules! ready {
 
 impl<R: AsyncRead + Unpin> BufReader<R> {
     pub async fn fill_buf_at_least(&mut self, len: usize) -> io::Result<&[u8]> {
-        if self.len >= len {
+        if self.len - self.pos >= len {
             return Ok(&self.buf[self.pos..self.len]);
         }
 
@@ -40,7 +40,7 @@ impl<R: AsyncRead + Unpin> BufReader<R> {
             self.len = size;
             self.pos = 0
         } else if self.len < self.cap {
-            while self.len < len {
+            while self.len - self.pos < len {
                 let buf = &mut self.buf[self.len..self.cap];
                 let size = self.inner.read(buf).await?;
                 if size == 0 {
@@ -144,12 +144,7 @@ impl<R: AsyncRead> BufReader<R> {
         }
 
         let len = self.len - self.pos;
-        let dst = self.buf.as_mut_ptr();
-        let src = unsafe { dst.add(self.pos) };
-
-        unsafe {
-            std::ptr::copy(src, dst, len);
-        }
+        self.buf.copy_within(self.pos..self.len, 0);
 
         self.pos = 0;
         self.len = len;
@@ -294,4 +289,14 @@ mod tests {
         assert_eq!(buf.len, 5);
         assert_eq!(buf.buf[buf.pos..buf.len], [6, 7, 8, 9, 10]);
     }
+
+    #[tokio::test]
+    async fn test_fill_buf_at_least() {
+        let data: Vec<u8> = (0..12).collect();
+        let mut buf = BufReader::with_capacity(8, &data[..]);
+        assert_eq!(buf.fill_buf_at_least(4).await.unwrap(), &data[..8]);
+        std::pin::Pin::new(&mut buf).consume(6);
+        // the bytes consumed are not counted
+        assert_eq!(buf.fill_buf_at_least(4).await.unwrap(), &data[6..12]);
+    }
 }